@@ -120,9 +120,21 @@ impl BoxGame {
     pub fn handle_requests(&mut self, requests: Vec<GGRSRequest>) {
         for request in requests {
             match request {
-                GGRSRequest::LoadGameState { cell } => self.load_game_state(cell),
+                GGRSRequest::LoadGameState { cell, .. } => self.load_game_state(cell),
                 GGRSRequest::SaveGameState { cell, frame } => self.save_game_state(cell, frame),
-                GGRSRequest::AdvanceFrame { inputs } => self.advance_frame(inputs),
+                GGRSRequest::AdvanceFrame { inputs, .. } => self.advance_frame(inputs),
+                GGRSRequest::Resimulate {
+                    from_frame,
+                    inputs_per_frame,
+                    cells,
+                } => {
+                    for (i, (inputs, cell)) in inputs_per_frame.into_iter().zip(cells).enumerate() {
+                        self.advance_frame(inputs);
+                        if let Some(cell) = cell {
+                            self.save_game_state(cell, from_frame + i as i32 + 1);
+                        }
+                    }
+                }
             }
         }
     }
@@ -151,9 +163,9 @@ impl BoxGame {
         // it is very inefficient to serialize the gamestate here just for the checksum
         let buffer = bincode::serialize(&self.game_state).unwrap();
         let checksum = fletcher16(&buffer) as u64;
-        self.last_checksum = (self.game_state.frame, checksum);
+        self.last_checksum = (Frame::from(self.game_state.frame), checksum);
         if self.game_state.frame % CHECKSUM_PERIOD == 0 {
-            self.periodic_checksum = (self.game_state.frame, checksum);
+            self.periodic_checksum = (Frame::from(self.game_state.frame), checksum);
         }
     }
 
@@ -1,6 +1,6 @@
 extern crate freetype as ft;
 
-use ggrs::{GGRSEvent, PlayerType, SessionState};
+use ggrs::{GGRSEvent, PlayerHandle, PlayerType, SessionState};
 use glutin_window::GlutinWindow as Window;
 use opengl_graphics::{GlGraphics, OpenGL};
 use piston::event_loop::{EventSettings, Events};
@@ -31,7 +31,7 @@ struct Opt {
 fn main() -> Result<(), Box<dyn std::error::Error>> {
     // read cmd line arguments
     let opt = Opt::from_args();
-    let mut local_handle = 0;
+    let mut local_handle = PlayerHandle(0);
     let num_players = opt.players.len();
     assert!(num_players > 0);
 
@@ -45,18 +45,21 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
     for (i, player_addr) in opt.players.iter().enumerate() {
         // local player
         if player_addr == "localhost" {
-            sess.add_player(PlayerType::Local, i)?;
-            local_handle = i;
+            sess.add_player(PlayerType::Local, PlayerHandle(i))?;
+            local_handle = PlayerHandle(i);
         } else {
             // remote players
             let remote_addr: SocketAddr = player_addr.parse()?;
-            sess.add_player(PlayerType::Remote(remote_addr), i)?;
+            sess.add_player(PlayerType::Remote(remote_addr), PlayerHandle(i))?;
         }
     }
 
     // optionally, add spectators
     for (i, spec_addr) in opt.spectators.iter().enumerate() {
-        sess.add_player(PlayerType::Spectator(*spec_addr), num_players + i)?;
+        sess.add_player(
+            PlayerType::Spectator(*spec_addr),
+            PlayerHandle(num_players + i),
+        )?;
     }
 
     // set input delay for the local player
@@ -109,7 +112,7 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
             // if the session is running, tell GGRS it is time to advance the frame and handle the requests
             if sess.current_state() == SessionState::Running {
                 // always get WASD inputs
-                let local_input = game.local_input(0);
+                let local_input = game.local_input(PlayerHandle(0));
 
                 match sess.advance_frame(local_handle, &local_input) {
                     Ok(requests) => game.handle_requests(requests),
@@ -122,7 +125,7 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
                 //regularily print networks stats
                 if game.current_frame() % 120 == 0 {
                     for i in 0..num_players {
-                        if let Ok(stats) = sess.network_stats(i) {
+                        if let Ok(stats) = sess.network_stats(PlayerHandle(i)) {
                             println!("NetworkStats to player {}: {:?}", i, stats);
                         }
                     }
@@ -1,5 +1,6 @@
 extern crate freetype as ft;
 
+use ggrs::PlayerHandle;
 use glutin_window::GlutinWindow as Window;
 use opengl_graphics::{GlGraphics, OpenGL};
 use piston::event_loop::{EventSettings, Events};
@@ -34,7 +35,7 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
 
     // set input delay for any player you want
     for i in 0..opt.num_players {
-        sess.set_frame_delay(2, i)?;
+        sess.set_frame_delay(2, PlayerHandle(i))?;
     }
 
     // Change this to OpenGL::V2_1 if not working
@@ -70,7 +71,7 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
             // create inputs for all players
             let mut all_inputs = Vec::new();
             for i in 0..opt.num_players {
-                all_inputs.push(game.local_input(i));
+                all_inputs.push(game.local_input(PlayerHandle(i)));
             }
             // tell GGRS it is time to advance the frame and handle the requests
             let requests = sess.advance_frame(&all_inputs)?;
@@ -124,9 +124,21 @@ impl RapierGame {
     pub fn handle_requests(&mut self, requests: Vec<GGRSRequest>) {
         for request in requests {
             match request {
-                GGRSRequest::LoadGameState { cell } => self.load_game_state(cell),
+                GGRSRequest::LoadGameState { cell, .. } => self.load_game_state(cell),
                 GGRSRequest::SaveGameState { cell, frame } => self.save_game_state(cell, frame),
-                GGRSRequest::AdvanceFrame { inputs } => self.advance_frame(inputs),
+                GGRSRequest::AdvanceFrame { inputs, .. } => self.advance_frame(inputs),
+                GGRSRequest::Resimulate {
+                    from_frame,
+                    inputs_per_frame,
+                    cells,
+                } => {
+                    for (i, (inputs, cell)) in inputs_per_frame.into_iter().zip(cells).enumerate() {
+                        self.advance_frame(inputs);
+                        if let Some(cell) = cell {
+                            self.save_game_state(cell, from_frame + i as i32 + 1);
+                        }
+                    }
+                }
             }
         }
     }
@@ -139,9 +151,9 @@ impl RapierGame {
         let checksum = fletcher16(&buffer) as u64;
 
         // remember checksum to render it later
-        self.last_checksum = (self.state.frame, checksum);
+        self.last_checksum = (Frame::from(self.state.frame), checksum);
         if self.state.frame % CHECKSUM_PERIOD == 0 {
-            self.periodic_checksum = (self.state.frame, checksum);
+            self.periodic_checksum = (Frame::from(self.state.frame), checksum);
         }
 
         cell.save(GameState::new(frame, Some(buffer), Some(checksum)));
@@ -0,0 +1,24 @@
+use ggrs::WebSocketRelayServer;
+use std::time::Duration;
+use structopt::StructOpt;
+
+#[derive(StructOpt)]
+struct Opt {
+    /// The address the relay listens on.
+    #[structopt(short, long, default_value = "0.0.0.0:8080")]
+    address: String,
+}
+
+fn main() -> Result<(), Box<dyn std::error::Error>> {
+    // read cmd line arguments
+    let opt = Opt::from_args();
+
+    let mut relay = WebSocketRelayServer::bind(&opt.address)?;
+    println!("Relaying on {}", relay.local_addr()?);
+
+    // forward messages until the process is stopped
+    loop {
+        relay.poll()?;
+        std::thread::sleep(Duration::from_millis(1));
+    }
+}
@@ -1,6 +1,7 @@
-use std::error::Error;
-use std::fmt;
-use std::fmt::Display;
+use alloc::string::String;
+use alloc::vec::Vec;
+use core::fmt;
+use core::fmt::Display;
 
 use crate::Frame;
 
@@ -13,7 +14,10 @@ pub enum GGRSError {
     PredictionThreshold,
     /// You made an invalid request, usually by using wrong parameters for function calls or starting a session that is already started.
     InvalidRequest { info: String },
+    /// When starting a session, the configuration of the session has been found invalid. `errors` contains a description of every problem found.
+    InvalidConfiguration { errors: Vec<String> },
     /// In a `SyncTestSession`, this error is returned if checksums of resimulated frames do not match up with the original checksum.
+    /// A `SimulationHarness` returns it if the confirmed gamestates of two players differ.
     MismatchedChecksum { frame: Frame },
     /// A problem occured during creation of the UDP socket.
     SocketCreationFailed,
@@ -23,6 +27,56 @@ pub enum GGRSError {
     PlayerDisconnected,
     /// The spectator got so far behind the host that catching up is impossible.
     SpectatorTooFarBehind,
+    /// The session has reached the frame of a synchronized pause. It will not advance until any peer requests to resume.
+    Paused,
+    /// The session is further ahead of a remote player than the limit set with `set_max_frames_ahead()`. It will not advance until the remote player has caught up.
+    TooFarAhead,
+    /// The session has reached a barrier frame and waits for the inputs of the other peers. It will not advance until they have arrived.
+    WaitingAtBarrier,
+    /// The session is turn-based and waits for the turn inputs of the other players. It will not advance until they have arrived.
+    WaitingForTurn,
+}
+
+/// The coarse category of a `GGRSError`, telling your game loop how to react without matching on every variant.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub enum GGRSErrorKind {
+    /// The session can not proceed right now, but will be able to later. Keep polling and try again next frame.
+    Retryable,
+    /// The request was invalid, e.g. because of a wrong player handle or parameter. The session is unaffected, but retrying the same request will fail again.
+    InvalidUsage,
+    /// The session can not continue, e.g. because the players desynced or the spectator fell too far behind. Tear down the session.
+    Fatal,
+}
+
+impl GGRSError {
+    /// Returns the category of this error.
+    pub const fn kind(&self) -> GGRSErrorKind {
+        match self {
+            GGRSError::PredictionThreshold
+            | GGRSError::NotSynchronized
+            | GGRSError::Paused
+            | GGRSError::TooFarAhead
+            | GGRSError::WaitingAtBarrier
+            | GGRSError::WaitingForTurn => GGRSErrorKind::Retryable,
+            GGRSError::InvalidHandle
+            | GGRSError::InvalidRequest { .. }
+            | GGRSError::InvalidConfiguration { .. }
+            | GGRSError::PlayerDisconnected => GGRSErrorKind::InvalidUsage,
+            GGRSError::MismatchedChecksum { .. }
+            | GGRSError::SocketCreationFailed
+            | GGRSError::SpectatorTooFarBehind => GGRSErrorKind::Fatal,
+        }
+    }
+
+    /// Returns true if the request may succeed when you try again next frame.
+    pub const fn is_retryable(&self) -> bool {
+        matches!(self.kind(), GGRSErrorKind::Retryable)
+    }
+
+    /// Returns true if the session can not continue and should be torn down.
+    pub const fn is_fatal(&self) -> bool {
+        matches!(self.kind(), GGRSErrorKind::Fatal)
+    }
 }
 
 impl Display for GGRSError {
@@ -40,6 +94,9 @@ impl Display for GGRSError {
             GGRSError::InvalidRequest { info } => {
                 write!(f, "Invalid Request: {}", info)
             }
+            GGRSError::InvalidConfiguration { errors } => {
+                write!(f, "Invalid Configuration: {}", errors.join(" "))
+            }
             GGRSError::NotSynchronized => {
                 write!(
                     f,
@@ -68,8 +125,48 @@ impl Display for GGRSError {
                     "The spectator got so far behind the host that catching up is impossible."
                 )
             }
+            GGRSError::Paused => {
+                write!(f, "The session is paused.")
+            }
+            GGRSError::TooFarAhead => {
+                write!(f, "The session is too far ahead of a remote player.")
+            }
+            GGRSError::WaitingAtBarrier => {
+                write!(
+                    f,
+                    "The session waits for the other peers at a barrier frame."
+                )
+            }
+            GGRSError::WaitingForTurn => {
+                write!(f, "The session waits for the turns of the other players.")
+            }
         }
     }
 }
 
-impl Error for GGRSError {}
+#[cfg(feature = "std")]
+impl std::error::Error for GGRSError {}
+
+// #########
+// # TESTS #
+// #########
+
+#[cfg(test)]
+mod error_tests {
+
+    use super::*;
+
+    #[test]
+    fn test_kind() {
+        assert!(GGRSError::PredictionThreshold.is_retryable());
+        assert!(GGRSError::Paused.is_retryable());
+        assert!(GGRSError::TooFarAhead.is_retryable());
+        assert!(GGRSError::WaitingAtBarrier.is_retryable());
+        assert!(GGRSError::MismatchedChecksum { frame: Frame(3) }.is_fatal());
+        let invalid = GGRSError::InvalidRequest {
+            info: String::new(),
+        };
+        assert_eq!(invalid.kind(), GGRSErrorKind::InvalidUsage);
+        assert!(!invalid.is_retryable() && !invalid.is_fatal());
+    }
+}
@@ -0,0 +1,143 @@
+use std::time::{Duration, Instant};
+
+use crate::GGRSError;
+
+/// The maximum amount of frames a single update returns, so a long hitch does not make the game simulate hundreds of frames at once.
+const MAX_FRAMES_PER_UPDATE: u32 = 8;
+/// While waiting, every frame takes `1 / STRETCH_DIVISOR` longer than usual.
+const STRETCH_DIVISOR: u32 = 10;
+
+/// A `FrameAccumulator` decouples the simulation from the render loop. It tracks real time and tells you how many frames to simulate at the configured FPS,
+/// which can be zero, one or multiple frames per rendered frame. Call `update()` once per rendered frame and call `advance_frame()` on your session that many times.
+///
+/// When you receive a `GGRSEvent::WaitRecommendation`, pass the skipped frames to `wait()`. Instead of pausing the game, the accumulator stretches the following frames slightly
+/// until the recommended time has been waited, so the game slows down smoothly.
+#[derive(Debug, Clone)]
+pub struct FrameAccumulator {
+    frame_duration: Duration,
+    accumulator: Duration,
+    last_update: Option<Instant>,
+    remaining_wait: Duration,
+}
+
+impl FrameAccumulator {
+    /// Creates a new `FrameAccumulator` for the given FPS.
+    /// # Errors
+    /// - Returns `InvalidRequest` if the FPS is 0.
+    pub fn new(fps: u32) -> Result<Self, GGRSError> {
+        if fps == 0 {
+            return Err(GGRSError::InvalidRequest {
+                info: "FPS should be higher than 0.".to_owned(),
+            });
+        }
+
+        Ok(Self {
+            frame_duration: Duration::from_secs(1) / fps,
+            accumulator: Duration::ZERO,
+            last_update: None,
+            remaining_wait: Duration::ZERO,
+        })
+    }
+
+    /// Measures the time since the last call and returns the number of frames you should simulate now. The first call only starts the clock and returns 0.
+    pub fn update(&mut self) -> u32 {
+        let now = Instant::now();
+        let elapsed = match self.last_update {
+            Some(last_update) => now - last_update,
+            None => Duration::ZERO,
+        };
+        self.last_update = Some(now);
+        self.advance(elapsed)
+    }
+
+    /// Adds the given elapsed time and returns the number of frames you should simulate now. Use this instead of `update()` if you measure time yourself.
+    pub fn advance(&mut self, elapsed: Duration) -> u32 {
+        self.accumulator += elapsed;
+
+        let mut frames = 0;
+        while frames < MAX_FRAMES_PER_UPDATE {
+            // while waiting, frames take a little longer
+            let stretch = if self.remaining_wait > Duration::ZERO {
+                std::cmp::min(self.frame_duration / STRETCH_DIVISOR, self.remaining_wait)
+            } else {
+                Duration::ZERO
+            };
+            let duration = self.frame_duration + stretch;
+            if self.accumulator < duration {
+                break;
+            }
+
+            self.accumulator -= duration;
+            self.remaining_wait -= stretch;
+            frames += 1;
+        }
+
+        // we could not keep up, so we drop the time we are behind instead of trying to catch up forever
+        if frames == MAX_FRAMES_PER_UPDATE {
+            self.accumulator = std::cmp::min(self.accumulator, self.frame_duration);
+        }
+
+        frames
+    }
+
+    /// Slows down the following frames until the time of the given amount of frames has been waited. Pass the `skip_frames` of a `GGRSEvent::WaitRecommendation` here.
+    pub fn wait(&mut self, frames: u32) {
+        self.remaining_wait += self.frame_duration * frames;
+    }
+
+    /// Returns true if the accumulator is still slowing down frames due to a call to `wait()`.
+    pub fn is_waiting(&self) -> bool {
+        self.remaining_wait > Duration::ZERO
+    }
+
+    /// Returns how far the accumulated time has progressed towards the next frame, between 0.0 and 1.0. Useful to interpolate between the last two gamestates when rendering.
+    pub fn interpolation_alpha(&self) -> f64 {
+        (self.accumulator.as_secs_f64() / self.frame_duration.as_secs_f64()).min(1.0)
+    }
+}
+
+// #########
+// # TESTS #
+// #########
+
+#[cfg(test)]
+mod frame_accumulator_tests {
+
+    use super::*;
+
+    #[test]
+    fn test_invalid_fps() {
+        assert!(FrameAccumulator::new(0).is_err());
+    }
+
+    #[test]
+    fn test_frames_per_update() {
+        let mut accumulator = FrameAccumulator::new(50).unwrap();
+        assert_eq!(accumulator.advance(Duration::from_millis(10)), 0);
+        assert_eq!(accumulator.advance(Duration::from_millis(10)), 1);
+        assert_eq!(accumulator.advance(Duration::from_millis(60)), 3);
+        assert!((accumulator.interpolation_alpha() - 0.0).abs() < f64::EPSILON);
+
+        // a long hitch does not lead to a spiral of ever more frames
+        assert_eq!(
+            accumulator.advance(Duration::from_secs(10)),
+            MAX_FRAMES_PER_UPDATE
+        );
+        assert!(accumulator.advance(Duration::ZERO) <= 1);
+    }
+
+    #[test]
+    fn test_wait_stretches_frames() {
+        let mut accumulator = FrameAccumulator::new(50).unwrap();
+        accumulator.wait(2);
+        assert!(accumulator.is_waiting());
+
+        // at 50 FPS, a second has 50 frames. Waiting 2 frames leaves 48 frames in that second
+        let mut frames = 0;
+        for _ in 0..100 {
+            frames += accumulator.advance(Duration::from_millis(10));
+        }
+        assert_eq!(frames, 48);
+        assert!(!accumulator.is_waiting());
+    }
+}
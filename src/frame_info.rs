@@ -1,3 +1,6 @@
+use alloc::vec::Vec;
+use serde::{Deserialize, Serialize};
+
 use crate::{Frame, MAX_INPUT_BYTES, MAX_PLAYERS, NULL_FRAME};
 
 /// The input buffer used to save the bytes from a player input. It is bigger than necessary by a factor `MAX_PLAYERS` to allow the same type of buffers to be used to transmit
@@ -65,7 +68,7 @@ impl GameState {
 
 /// Represents a serialized input for a single player in a single frame. This struct holds a `buffer` where the first `size` bytes represent the encoded input of a single player.
 /// The associated frame is denoted with `frame`. You do not need to create this struct, but the sessions will provide a `Vec<GameInput>` for you during `advance_frame()`.
-#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Serialize, Deserialize)]
 pub struct GameInput {
     /// The frame to which this info belongs to. -1/`NULL_FRAME` represents an invalid frame
     pub frame: Frame,
@@ -132,9 +135,9 @@ mod game_input_tests {
         let fake_inputs: u32 = 5;
         let input_size = std::mem::size_of::<u32>();
         let serialized_inputs = bincode::serialize(&fake_inputs).unwrap();
-        let mut input1 = GameInput::new(0, input_size);
+        let mut input1 = GameInput::new(Frame(0), input_size);
         input1.copy_input(&serialized_inputs);
-        let mut input2 = GameInput::new(5, input_size);
+        let mut input2 = GameInput::new(Frame(5), input_size);
         input2.copy_input(&serialized_inputs);
         assert!(input1.equal(&input2, true)); // different frames, but does not matter
     }
@@ -145,12 +148,12 @@ mod game_input_tests {
 
         let fake_inputs: u32 = 5;
         let serialized_inputs = bincode::serialize(&fake_inputs).unwrap();
-        let mut input1 = GameInput::new(0, input_size);
+        let mut input1 = GameInput::new(Frame(0), input_size);
         input1.copy_input(&serialized_inputs);
 
         let fake_inputs: u32 = 7;
         let serialized_inputs = bincode::serialize(&fake_inputs).unwrap();
-        let mut input2 = GameInput::new(0, input_size);
+        let mut input2 = GameInput::new(Frame(0), input_size);
         input2.copy_input(&serialized_inputs);
 
         assert!(!input1.equal(&input2, false)); // different bits
@@ -0,0 +1,77 @@
+use alloc::collections::VecDeque;
+use alloc::vec;
+use alloc::vec::Vec;
+
+/// Holds back the local inputs of every player for a number of frames, to simulate the latency of a slower input device or connection.
+/// Unlike the frame delay, the held back inputs are not known to the session at all, so they are neither sent to other peers nor predicted.
+#[derive(Debug)]
+pub(crate) struct InputLatency {
+    frames: usize,
+    /// The inputs given by the user that have not been released yet, for every player.
+    queues: Vec<VecDeque<Vec<u8>>>,
+    /// The last released input of every player, repeated while the queue of that player fills up.
+    released: Vec<Option<Vec<u8>>>,
+}
+
+impl InputLatency {
+    pub(crate) fn new(num_players: usize) -> Self {
+        Self {
+            frames: 0,
+            queues: vec![VecDeque::new(); num_players],
+            released: vec![None; num_players],
+        }
+    }
+
+    /// Changes the latency. After raising it, the last released input is repeated until the queue has filled up.
+    /// After lowering it, the inputs that would have been released in the meantime are skipped.
+    pub(crate) fn set_frames(&mut self, frames: u32) {
+        self.frames = frames as usize;
+    }
+
+    /// Queues the input of a player given in the current frame and returns the input to use instead, which is the input given `frames` frames ago.
+    /// Until then, the last released input is repeated, or a zeroed input is returned if there is none.
+    pub(crate) fn delay(&mut self, player: usize, input: &[u8]) -> Vec<u8> {
+        let queue = &mut self.queues[player];
+        queue.push_back(input.to_vec());
+        while queue.len() > self.frames {
+            self.released[player] = queue.pop_front();
+        }
+        match &self.released[player] {
+            Some(released) => released.clone(),
+            None => vec![0; input.len()],
+        }
+    }
+}
+
+// #########
+// # TESTS #
+// #########
+
+#[cfg(test)]
+mod input_latency_tests {
+    use super::*;
+
+    #[test]
+    fn test_inputs_are_held_back() {
+        let mut latency = InputLatency::new(1);
+        assert_eq!(latency.delay(0, &[1]), vec![1]);
+
+        latency.set_frames(2);
+        assert_eq!(latency.delay(0, &[2]), vec![1]);
+        assert_eq!(latency.delay(0, &[3]), vec![1]);
+        assert_eq!(latency.delay(0, &[4]), vec![2]);
+
+        // lowering the latency skips the held back inputs
+        latency.set_frames(0);
+        assert_eq!(latency.delay(0, &[5]), vec![5]);
+    }
+
+    #[test]
+    fn test_zeroed_input_before_first_release() {
+        let mut latency = InputLatency::new(2);
+        latency.set_frames(1);
+        assert_eq!(latency.delay(1, &[7, 7]), vec![0, 0]);
+        assert_eq!(latency.delay(1, &[8, 8]), vec![7, 7]);
+        assert_eq!(latency.delay(0, &[9]), vec![0]);
+    }
+}
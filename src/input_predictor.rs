@@ -0,0 +1,133 @@
+use alloc::vec::Vec;
+
+use crate::{Frame, GameInput, PlayerHandle};
+
+/// An `InputPredictor` decides which input GGRS assumes for a remote player while their actual input has not arrived yet.
+/// Every misprediction causes a rollback, so a predictor tailored to your game (e.g. decaying held buttons or dead-reckoning analog sticks)
+/// can reduce the number and depth of rollbacks considerably. Predictions must be deterministic: for the same arguments, always return the same prediction.
+pub trait InputPredictor: core::fmt::Debug {
+    /// Predicts the serialized input of a player `frames_ahead` frames after the last input received from them.
+    /// `prediction` initially contains that last received input and should be modified in place. `frames_ahead` is always at least 1.
+    fn predict(&self, prediction: &mut [u8], frames_ahead: u32);
+}
+
+/// The default `InputPredictor`, which predicts that players keep doing exactly what they did in their last received input.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct RepeatLastInput;
+
+impl InputPredictor for RepeatLastInput {
+    fn predict(&self, _prediction: &mut [u8], _frames_ahead: u32) {}
+}
+
+/// A `DisconnectedInput` decides which input disconnected players have for all frames after they disconnected. By default, their input is blank.
+/// Substituted inputs still have `NULL_FRAME` as their frame, so your game can tell that the player is disconnected, e.g. to let an AI take over.
+/// All peers have to substitute the same way, so substitutions must be deterministic: for the same arguments, always return the same input.
+pub trait DisconnectedInput: core::fmt::Debug {
+    /// Substitutes the serialized input of a player `frames_disconnected` frames after their last confirmed input.
+    /// `input` initially contains that last confirmed input, or zeroes if there is none, and should be modified in place. `frames_disconnected` is always at least 1.
+    fn substitute(&self, input: &mut [u8], frames_disconnected: u32);
+}
+
+/// Disconnected players keep doing exactly what they did in their last confirmed input.
+impl DisconnectedInput for RepeatLastInput {
+    fn substitute(&self, _input: &mut [u8], _frames_disconnected: u32) {}
+}
+
+/// A `DisconnectedInput` that gives disconnected players the same fixed input on every frame, e.g. a neutral stance.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct FixedInput {
+    input: Vec<u8>,
+}
+
+impl FixedInput {
+    /// Creates a new `FixedInput` with the given serialized input. Bytes beyond the input size of the session are ignored, missing bytes are zeroed.
+    pub fn new(input: Vec<u8>) -> Self {
+        Self { input }
+    }
+}
+
+impl DisconnectedInput for FixedInput {
+    fn substitute(&self, input: &mut [u8], _frames_disconnected: u32) {
+        for (i, byte) in input.iter_mut().enumerate() {
+            *byte = self.input.get(i).copied().unwrap_or(0);
+        }
+    }
+}
+
+/// An `InputBot` plays for disconnected players, so the match can go on with a CPU substitute instead of a frozen character. Unlike a `DisconnectedInput`,
+/// a bot sees the inputs all players had in the frame before. All peers run the bot for the same frames with the same arguments, so bots must be deterministic:
+/// for the same arguments, always return the same input. Inputs played by the bot still have `NULL_FRAME` as their frame.
+pub trait InputBot: core::fmt::Debug {
+    /// Plays the serialized input of the disconnected player `player_handle` for `frame`. `input` initially contains the last confirmed input of the player,
+    /// or zeroes if there is none, and should be modified in place. `previous_inputs` are the inputs of all players in the frame before, including the inputs
+    /// played by the bot. They are empty for the first frame of the session and for the first frame after restoring a session.
+    fn play(
+        &self,
+        frame: Frame,
+        player_handle: PlayerHandle,
+        previous_inputs: &[GameInput],
+        input: &mut [u8],
+    );
+}
+
+/// An `InputTolerance` decides if a received input is close enough to the input that has been predicted for it to not trigger a rollback.
+/// This is useful for analog inputs, where tiny stick drift would otherwise cause a misprediction on almost every frame.
+/// Be aware that frames simulated with a prediction that was within tolerance are not resimulated with the exact input.
+/// The game states of the peers will therefore slightly diverge, unless your simulation is insensitive to the tolerated differences.
+pub trait InputTolerance: core::fmt::Debug {
+    /// Returns `true` if the serialized `actual` input is close enough to the serialized `predicted` input to be treated as equal.
+    fn within_tolerance(&self, predicted: &[u8], actual: &[u8]) -> bool;
+}
+
+/// An `InputTolerance` that treats every byte of the serialized input as an unsigned value with its own tolerance.
+/// Inputs are within tolerance if the absolute difference of every byte is smaller or equal to the tolerance given for that byte.
+/// Bytes without a given tolerance have to match exactly.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct ByteTolerance {
+    tolerances: Vec<u8>,
+}
+
+impl ByteTolerance {
+    /// Creates a new `ByteTolerance` with the given tolerance for every byte of the input.
+    pub fn new(tolerances: Vec<u8>) -> Self {
+        Self { tolerances }
+    }
+}
+
+impl InputTolerance for ByteTolerance {
+    fn within_tolerance(&self, predicted: &[u8], actual: &[u8]) -> bool {
+        predicted.len() == actual.len()
+            && predicted.iter().zip(actual).enumerate().all(|(i, (p, a))| {
+                let tolerance = self.tolerances.get(i).copied().unwrap_or(0);
+                (*p as i16 - *a as i16).abs() <= tolerance as i16
+            })
+    }
+}
+
+// #########
+// # TESTS #
+// #########
+
+#[cfg(test)]
+mod input_predictor_tests {
+    use super::*;
+
+    #[test]
+    fn test_byte_tolerance() {
+        let tolerance = ByteTolerance::new(vec![2, 0]);
+        assert!(tolerance.within_tolerance(&[10, 5, 1], &[12, 5, 1]));
+        assert!(tolerance.within_tolerance(&[10, 5, 1], &[8, 5, 1]));
+        assert!(!tolerance.within_tolerance(&[10, 5, 1], &[13, 5, 1]));
+        assert!(!tolerance.within_tolerance(&[10, 5, 1], &[10, 6, 1]));
+        assert!(!tolerance.within_tolerance(&[10, 5, 1], &[10, 5, 2]));
+    }
+
+    #[test]
+    fn test_disconnected_inputs() {
+        let mut input = [1, 2, 3];
+        RepeatLastInput.substitute(&mut input, 1);
+        assert_eq!(input, [1, 2, 3]);
+        FixedInput::new(vec![7, 8]).substitute(&mut input, 5);
+        assert_eq!(input, [7, 8, 0]);
+    }
+}
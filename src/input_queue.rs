@@ -1,9 +1,39 @@
 use crate::frame_info::GameInput;
+use crate::input_predictor::{InputPredictor, InputTolerance, RepeatLastInput};
 use crate::{Frame, PlayerHandle, NULL_FRAME};
-use std::cmp;
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use core::cmp;
 
 /// The length of the input queue. This describes the number of inputs GGRS can hold at the same time per player.
-const INPUT_QUEUE_LENGTH: usize = 128;
+pub(crate) const INPUT_QUEUE_LENGTH: usize = 128;
+
+/// `InputQueueInfo` is a read-only view of the inputs GGRS holds for a single player. Frames between `first_confirmed_frame` and `last_confirmed_frame` have confirmed inputs,
+/// while all frames after `last_confirmed_frame` up to the current frame of the session are simulated with predicted inputs.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct InputQueueInfo {
+    /// The handle of the player the inputs belong to.
+    pub player_handle: PlayerHandle,
+    /// The input delay of the player.
+    pub frame_delay: u32,
+    /// The oldest frame with a confirmed input that is still kept, or `NULL_FRAME` if there is none.
+    pub first_confirmed_frame: Frame,
+    /// The newest frame with a confirmed input, or `NULL_FRAME` if there is none.
+    pub last_confirmed_frame: Frame,
+    /// The first frame that has been predicted incorrectly, or `NULL_FRAME` if all predictions were correct so far.
+    /// The session rolls back to this frame during the next call to `advance_frame()`.
+    pub first_incorrect_frame: Frame,
+}
+
+impl InputQueueInfo {
+    /// Returns true if the input for the given frame is confirmed and still kept.
+    pub fn is_confirmed(&self, frame: Frame) -> bool {
+        frame != NULL_FRAME
+            && self.first_confirmed_frame != NULL_FRAME
+            && frame >= self.first_confirmed_frame
+            && frame <= self.last_confirmed_frame
+    }
+}
 
 /// `InputQueue` handles inputs for a single player and saves them in a circular array. Valid Inputs are between `head` and `tail`.
 #[derive(Debug, Clone)]
@@ -33,6 +63,12 @@ pub(crate) struct InputQueue {
     inputs: [GameInput; INPUT_QUEUE_LENGTH],
     /// A pre-allocated prediction we are going to use to return predictions from.
     prediction: GameInput,
+    /// The frame of the input the current prediction is based on. `NULL_FRAME` if there was no input to base the prediction on.
+    prediction_base_frame: Frame,
+    /// Computes the predicted inputs for each frame from the last received input.
+    predictor: Arc<dyn InputPredictor + Send + Sync>,
+    /// If set, received inputs that are within tolerance of the prediction do not count as mispredictions.
+    tolerance: Option<Arc<dyn InputTolerance + Send + Sync>>,
 }
 
 impl InputQueue {
@@ -49,6 +85,9 @@ impl InputQueue {
             last_requested_frame: NULL_FRAME,
 
             prediction: GameInput::new(NULL_FRAME, input_size),
+            prediction_base_frame: NULL_FRAME,
+            predictor: Arc::new(RepeatLastInput),
+            tolerance: None,
             inputs: [GameInput::new(NULL_FRAME, input_size); INPUT_QUEUE_LENGTH],
         }
     }
@@ -61,16 +100,97 @@ impl InputQueue {
         self.frame_delay = delay;
     }
 
+    pub(crate) fn set_input_predictor(&mut self, predictor: Arc<dyn InputPredictor + Send + Sync>) {
+        self.predictor = predictor;
+    }
+
+    #[cfg(feature = "std")]
+    pub(crate) fn set_input_tolerance(
+        &mut self,
+        tolerance: Option<Arc<dyn InputTolerance + Send + Sync>>,
+    ) {
+        self.tolerance = tolerance;
+    }
+
     pub(crate) fn reset_prediction(&mut self) {
         self.prediction.frame = NULL_FRAME;
         self.first_incorrect_frame = NULL_FRAME;
         self.last_requested_frame = NULL_FRAME;
     }
 
+    #[cfg(feature = "std")]
+    pub(crate) const fn last_added_frame(&self) -> Frame {
+        self.last_added_frame
+    }
+
+    pub(crate) const fn frame_delay(&self) -> u32 {
+        self.frame_delay
+    }
+
+    /// Returns `true` if the queue cannot take another input until confirmed frames are discarded.
+    #[cfg(feature = "std")]
+    pub(crate) const fn is_full(&self) -> bool {
+        self.length >= INPUT_QUEUE_LENGTH
+    }
+
+    pub(crate) fn info(&self) -> InputQueueInfo {
+        // the inputs in the queue are consecutive and end with the last added frame
+        let first_confirmed_frame = if self.length > 0 {
+            self.last_added_frame - self.length as i32 + 1
+        } else {
+            NULL_FRAME
+        };
+        InputQueueInfo {
+            player_handle: self.id,
+            frame_delay: self.frame_delay,
+            first_confirmed_frame,
+            last_confirmed_frame: self.last_added_frame,
+            first_incorrect_frame: self.first_incorrect_frame,
+        }
+    }
+
+    /// Returns all inputs in the queue from the given frame on. Predictions are not included.
+    pub(crate) fn inputs_since(&self, frame: Frame) -> Vec<GameInput> {
+        let mut inputs = Vec::new();
+        if self.last_added_frame == NULL_FRAME {
+            return inputs;
+        }
+        for requested_frame in cmp::max(frame.0, 0)..=self.last_added_frame.0 {
+            let input = self.inputs[requested_frame as usize % INPUT_QUEUE_LENGTH];
+            if input.frame.0 == requested_frame {
+                inputs.push(input);
+            }
+        }
+        inputs
+    }
+
+    /// Replaces the content of the queue with the given consecutive inputs, for example when restoring a session.
+    pub(crate) fn restore(&mut self, inputs: &[GameInput], frame_delay: u32) {
+        *self = Self {
+            predictor: self.predictor.clone(),
+            tolerance: self.tolerance.clone(),
+            ..Self::new(self.id, self.prediction.size)
+        };
+        self.frame_delay = frame_delay;
+
+        for (i, input) in inputs.iter().enumerate() {
+            assert!(i == 0 || inputs[i - 1].frame + 1 == input.frame);
+            assert!(input.size == self.prediction.size);
+            self.inputs[input.frame.0 as usize % INPUT_QUEUE_LENGTH] = *input;
+            self.length += 1;
+            self.first_frame = false;
+            self.last_added_frame = input.frame;
+        }
+        if let Some(first) = inputs.first() {
+            self.tail = first.frame.0 as usize % INPUT_QUEUE_LENGTH;
+            self.head = (self.tail + self.length) % INPUT_QUEUE_LENGTH;
+        }
+    }
+
     /// Returns a `GameInput`, but only if the input for the requested frame is confirmed.
     /// In contrast to `input()`, this will not return a prediction if there is no confirmed input for the frame, but panic instead.
     pub(crate) fn confirmed_input(&self, requested_frame: Frame) -> GameInput {
-        let offset = requested_frame as usize % INPUT_QUEUE_LENGTH;
+        let offset = requested_frame.0 as usize % INPUT_QUEUE_LENGTH;
 
         if self.inputs[offset].frame == requested_frame {
             return self.inputs[offset];
@@ -80,6 +200,20 @@ impl InputQueue {
         panic!("SyncLayer::confirmed_input(): There is no confirmed input for the requested frame");
     }
 
+    /// Returns the input of the given frame, if the queue still holds it. Unlike `confirmed_input()`, this works for discarded frames
+    /// until their input has been overwritten.
+    pub(crate) fn stored_input(&self, frame: Frame) -> Option<GameInput> {
+        if frame.is_null() {
+            return None;
+        }
+        let input = self.inputs[frame.0 as usize % INPUT_QUEUE_LENGTH];
+        if input.frame == frame {
+            Some(input)
+        } else {
+            None
+        }
+    }
+
     /// Discards confirmed frames up to given `frame` from the queue. All confirmed frames are guaranteed to be synchronized between players, so there is no need to save the inputs anymore.
     pub(crate) fn discard_confirmed_frames(&mut self, mut frame: Frame) {
         // we only drop frames until the last frame that was requested, otherwise we might delete data still needed
@@ -110,8 +244,15 @@ impl InputQueue {
         // Remember the last requested frame number for later. We'll need this in add_input() to drop out of prediction mode.
         self.last_requested_frame = requested_frame;
 
-        // assert that we request a frame that still exists
-        assert!(requested_frame >= self.inputs[self.tail].frame);
+        // a frame that has already been discarded can only be requested after inconsistent data from remote clients, which must not crash the session
+        if requested_frame < self.inputs[self.tail].frame {
+            ggrs_warn!(
+                "Input of player {} for frame {} has already been discarded",
+                self.id,
+                requested_frame
+            );
+            return GameInput::new(requested_frame, self.prediction.size);
+        }
 
         // We currently don't have a prediction frame
         if self.prediction.frame < 0 {
@@ -124,10 +265,11 @@ impl InputQueue {
                 return self.inputs[offset]; // GameInput has copy semantics
             }
 
-            // The requested frame isn't in the queue. This means we need to return a prediction frame. Predict that the user will do the same thing they did last time.
+            // The requested frame isn't in the queue. This means we need to return a prediction frame, which the predictor derives from what the user did last time.
             if requested_frame == 0 || self.last_added_frame == NULL_FRAME {
                 // basing new prediction frame from nothing, since we are on frame 0 or we have no frames yet
                 self.prediction.erase_bits();
+                self.prediction_base_frame = NULL_FRAME;
             } else {
                 // basing new prediction frame from previously added frame
                 let previous_position: usize;
@@ -136,16 +278,38 @@ impl InputQueue {
                     _ => previous_position = self.head - 1,
                 }
                 self.prediction = self.inputs[previous_position];
+                self.prediction_base_frame = self.prediction.frame;
             }
             // update the prediction's frame
             self.prediction.frame += 1;
         }
 
-        // We must be predicting, so we return the prediction frame contents. We are adjusting the prediction to have the requested frame.
+        // We must be predicting, so we return the prediction for the requested frame.
         assert!(self.prediction.frame != NULL_FRAME);
-        let mut prediction_to_return = self.prediction; // GameInput has copy semantics
-        prediction_to_return.frame = requested_frame;
-        prediction_to_return
+        self.predicted_input(requested_frame)
+    }
+
+    /// Returns the prediction for the given frame, computed by the predictor from the input the current prediction is based on.
+    fn predicted_input(&self, frame: Frame) -> GameInput {
+        let mut prediction = self.prediction; // GameInput has copy semantics
+        prediction.frame = frame;
+        if self.prediction_base_frame != NULL_FRAME && frame > self.prediction_base_frame {
+            let frames_ahead = (frame - self.prediction_base_frame) as u32;
+            self.predictor
+                .predict(&mut prediction.buffer[..prediction.size], frames_ahead);
+        }
+        prediction
+    }
+
+    /// Returns `true` if the received input matches the prediction, or is within the configured tolerance of it.
+    fn matches_prediction(&self, prediction: &GameInput, input: &GameInput) -> bool {
+        if prediction.equal(input, true) {
+            return true;
+        }
+        match &self.tolerance {
+            Some(tolerance) => tolerance.within_tolerance(prediction.input(), input.input()),
+            None => false,
+        }
     }
 
     /// Adds an input frame to the queue. Will consider the set frame delay.
@@ -192,7 +356,9 @@ impl InputQueue {
             assert!(frame_number == self.prediction.frame);
 
             // Remember the first input which was incorrect so we can report it
-            if self.first_incorrect_frame == NULL_FRAME && !self.prediction.equal(&input, true) {
+            if self.first_incorrect_frame == NULL_FRAME
+                && !self.matches_prediction(&self.predicted_input(frame_number), &input)
+            {
                 self.first_incorrect_frame = frame_number;
             }
 
@@ -217,7 +383,7 @@ impl InputQueue {
         }
 
         let mut expected_frame = if self.first_frame {
-            0
+            Frame(0)
         } else {
             self.inputs[previous_position].frame + 1
         };
@@ -253,68 +419,156 @@ impl InputQueue {
 mod input_queue_tests {
 
     use super::*;
+    use crate::input_predictor::ByteTolerance;
 
     #[test]
     #[should_panic]
     fn test_add_input_wrong_frame() {
-        let mut queue = InputQueue::new(0, std::mem::size_of::<u32>());
-        let input = GameInput::new(0, std::mem::size_of::<u32>());
+        let mut queue = InputQueue::new(PlayerHandle(0), std::mem::size_of::<u32>());
+        let input = GameInput::new(Frame(0), std::mem::size_of::<u32>());
         queue.add_input(input); // fine
-        let input_wrong_frame = GameInput::new(3, std::mem::size_of::<u32>());
+        let input_wrong_frame = GameInput::new(Frame(3), std::mem::size_of::<u32>());
         queue.add_input(input_wrong_frame); // not fine
     }
 
     #[test]
     #[should_panic]
     fn test_add_input_twice() {
-        let mut queue = InputQueue::new(0, std::mem::size_of::<u32>());
-        let input = GameInput::new(0, std::mem::size_of::<u32>());
+        let mut queue = InputQueue::new(PlayerHandle(0), std::mem::size_of::<u32>());
+        let input = GameInput::new(Frame(0), std::mem::size_of::<u32>());
         queue.add_input(input); // fine
         queue.add_input(input); // not fine
     }
 
     #[test]
     fn test_add_input_sequentially() {
-        let mut queue = InputQueue::new(0, std::mem::size_of::<u32>());
+        let mut queue = InputQueue::new(PlayerHandle(0), std::mem::size_of::<u32>());
         for i in 0..10 {
-            let input = GameInput::new(i, std::mem::size_of::<u32>());
+            let input = GameInput::new(Frame(i), std::mem::size_of::<u32>());
             queue.add_input(input);
-            assert_eq!(queue.last_added_frame, i);
+            assert_eq!(queue.last_added_frame, Frame(i));
             assert_eq!(queue.length, (i + 1) as usize);
         }
     }
 
     #[test]
     fn test_input_sequentially() {
-        let mut queue = InputQueue::new(0, std::mem::size_of::<u32>());
+        let mut queue = InputQueue::new(PlayerHandle(0), std::mem::size_of::<u32>());
         for i in 0..10 {
-            let mut input = GameInput::new(i, std::mem::size_of::<u32>());
+            let mut input = GameInput::new(Frame(i), std::mem::size_of::<u32>());
             let fake_inputs: u32 = i as u32;
             let serialized_inputs = bincode::serialize(&fake_inputs).unwrap();
             input.copy_input(&serialized_inputs);
             queue.add_input(input);
-            assert_eq!(queue.last_added_frame, i);
+            assert_eq!(queue.last_added_frame, Frame(i));
             assert_eq!(queue.length, (i + 1) as usize);
-            let input_in_queue = queue.input(i);
+            let input_in_queue = queue.input(Frame(i));
             assert!(input_in_queue.equal(&input, false));
         }
     }
 
     #[test]
     fn test_delayed_inputs() {
-        let mut queue = InputQueue::new(0, std::mem::size_of::<u32>());
+        let mut queue = InputQueue::new(PlayerHandle(0), std::mem::size_of::<u32>());
         let delay: i32 = 2;
         queue.set_frame_delay(delay as u32);
         for i in 0..10 {
-            let mut input = GameInput::new(i, std::mem::size_of::<u32>());
+            let mut input = GameInput::new(Frame(i), std::mem::size_of::<u32>());
             let fake_inputs: u32 = i as u32;
             let serialized_inputs = bincode::serialize(&fake_inputs).unwrap();
             input.copy_input(&serialized_inputs);
             queue.add_input(input);
-            assert_eq!(queue.last_added_frame, i + delay);
-            assert_eq!(queue.length, (i + delay + 1) as usize);
-            let input_in_queue = queue.input(i + delay);
+            assert_eq!(queue.last_added_frame, Frame(i) + delay);
+            assert_eq!(queue.length, i as usize + delay as usize + 1);
+            let input_in_queue = queue.input(Frame(i) + delay);
             assert!(input_in_queue.equal(&input, true));
         }
     }
+
+    #[derive(Debug)]
+    struct CountingPredictor;
+
+    impl InputPredictor for CountingPredictor {
+        fn predict(&self, prediction: &mut [u8], frames_ahead: u32) {
+            let last: u32 = bincode::deserialize(prediction).unwrap();
+            let predicted = bincode::serialize(&(last + frames_ahead)).unwrap();
+            prediction.copy_from_slice(&predicted);
+        }
+    }
+
+    #[test]
+    fn test_custom_predictor() {
+        let mut queue = InputQueue::new(PlayerHandle(0), std::mem::size_of::<u32>());
+        queue.set_input_predictor(Arc::new(CountingPredictor));
+        let mut input = GameInput::new(Frame(0), std::mem::size_of::<u32>());
+        input.copy_input(&bincode::serialize(&0u32).unwrap());
+        queue.add_input(input);
+
+        // predictions count upwards from the last received input
+        for i in 1..5 {
+            let prediction = queue.input(Frame(i));
+            let predicted: u32 = bincode::deserialize(prediction.input()).unwrap();
+            assert_eq!(predicted, i as u32);
+        }
+
+        // inputs matching the predictions do not count as mispredictions
+        for i in 1..4 {
+            let mut input = GameInput::new(Frame(i), std::mem::size_of::<u32>());
+            input.copy_input(&bincode::serialize(&(i as u32)).unwrap());
+            queue.add_input(input);
+        }
+        assert_eq!(queue.first_incorrect_frame(), NULL_FRAME);
+
+        // but everything else does
+        let mut input = GameInput::new(Frame(4), std::mem::size_of::<u32>());
+        input.copy_input(&bincode::serialize(&0u32).unwrap());
+        queue.add_input(input);
+        assert_eq!(queue.first_incorrect_frame(), 4);
+    }
+
+    #[test]
+    fn test_input_tolerance() {
+        let mut queue = InputQueue::new(PlayerHandle(0), 2);
+        queue.set_input_tolerance(Some(Arc::new(ByteTolerance::new(vec![3, 0]))));
+        let mut input = GameInput::new(Frame(0), 2);
+        input.copy_input(&[100, 1]);
+        queue.add_input(input);
+        queue.input(Frame(1));
+        queue.input(Frame(2));
+
+        // drift within tolerance is not a misprediction
+        let mut input = GameInput::new(Frame(1), 2);
+        input.copy_input(&[102, 1]);
+        queue.add_input(input);
+        assert_eq!(queue.first_incorrect_frame(), NULL_FRAME);
+
+        // the exact received input is kept
+        assert_eq!(queue.confirmed_input(Frame(1)).input(), &[102, 1]);
+
+        // a different button is
+        let mut input = GameInput::new(Frame(2), 2);
+        input.copy_input(&[100, 0]);
+        queue.add_input(input);
+        assert_eq!(queue.first_incorrect_frame(), 2);
+    }
+
+    #[test]
+    fn test_info() {
+        let mut queue = InputQueue::new(PlayerHandle(3), std::mem::size_of::<u32>());
+        let info = queue.info();
+        assert_eq!(info.player_handle, 3);
+        assert_eq!(info.last_confirmed_frame, NULL_FRAME);
+        assert!(!info.is_confirmed(Frame(0)));
+
+        for i in 0..5 {
+            queue.add_input(GameInput::new(Frame(i), std::mem::size_of::<u32>()));
+        }
+        queue.discard_confirmed_frames(Frame(2));
+        let info = queue.info();
+        assert_eq!(info.first_confirmed_frame, 2);
+        assert_eq!(info.last_confirmed_frame, 4);
+        assert!(info.is_confirmed(Frame(2)));
+        assert!(!info.is_confirmed(Frame(1)));
+        assert!(!info.is_confirmed(Frame(5)));
+    }
 }
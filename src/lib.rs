@@ -2,37 +2,153 @@
 //! GGRS (good game rollback system) is a reimagination of the GGPO network SDK written in 100% safe Rust 🦀.
 //! The callback-style API from the original library has been replaced with a much saner, simpler control flow.
 //! Instead of registering callback functions, GGRS returns a list of requests for the user to fulfill.
+//!
+//! Enable the `log` feature to have GGRS report handshakes, rollbacks, dropped packets and disconnects through the `log` crate.
+//! Enable the `test-utils` feature to get a `SimulationHarness` that runs the sessions of all players in a single process to test your game for determinism.
+//! Enable the `hole-punching` feature to get a `HolePuncher` that connects players behind NATs through a `RendezvousServer` before the session starts.
+//! Enable the `tracing` feature to get the same reports as `tracing` events, together with spans around advancing a frame, rollbacks and resimulations.
+//!
+//! The `std` feature is enabled by default. Without it, GGRS only needs `core` and `alloc`, so the rollback core can run on embedded targets and
+//! homebrew consoles: the sync layer, the input queues and the input compression are available through `SyncTestSession` and `OfflineSession`,
+//! while the networked sessions and sockets, which need a clock and the network stack of `std`, are left out.
 
+#![cfg_attr(not(feature = "std"), no_std)]
+// without `std`, the parts of the core that only the networked sessions use are never called
 #![forbid(unsafe_code)] // let us try
 
 //#![warn(clippy::all, clippy::pedantic, clippy::nursery, clippy::cargo)]
 
-use std::net::SocketAddr;
+extern crate alloc;
 
-pub use error::GGRSError;
+use alloc::borrow::ToOwned;
+use alloc::vec::Vec;
+#[cfg(feature = "std")]
+use std::net::{SocketAddr, ToSocketAddrs};
+#[cfg(feature = "std")]
+use std::time::Duration;
+
+pub use error::{GGRSError, GGRSErrorKind};
+#[cfg(feature = "std")]
+pub use frame_accumulator::FrameAccumulator;
 pub use frame_info::{GameInput, GameState};
-pub use network::network_stats::NetworkStats;
-pub use sessions::p2p_session::P2PSession;
+pub use input_predictor::{
+    ByteTolerance, DisconnectedInput, FixedInput, InputBot, InputPredictor, InputTolerance,
+    RepeatLastInput,
+};
+pub use input_queue::InputQueueInfo;
+#[cfg(feature = "std")]
+pub use network::capture::{CaptureDirection, CaptureEntry, CaptureSocket, ReplaySocket};
+#[cfg(feature = "channel-socket")]
+pub use network::channel_socket::{ChannelSocket, ChannelSocketHandle};
+#[cfg(feature = "std")]
+pub use network::codec::{BincodeCodec, MessageCodec, WireFormatCodec};
+#[cfg(feature = "hole-punching")]
+pub use network::hole_punch::{HolePuncher, PunchedHoles, RendezvousServer};
+#[cfg(feature = "std")]
+pub use network::input_stats::InputStats;
+#[cfg(feature = "std")]
+pub use network::network_stats::{FrameAdvantage, NetworkStats, SpectatorStats};
+#[cfg(feature = "std")]
+pub use network::socket_mux::{MultiplexedSocket, SocketMultiplexer};
+#[cfg(feature = "std")]
+pub use network::udp_msg::UdpMessage;
+#[cfg(feature = "std")]
+pub use network::udp_protocol::PendingOutputPolicy;
+#[cfg(feature = "std")]
+pub use network::udp_socket::{NonBlockingSocket, SocketError, SocketStats, UdpNonBlockingSocket};
+#[cfg(feature = "websocket")]
+pub use network::websocket::{WebSocketRelayServer, WebSocketRelaySocket};
+#[cfg(feature = "std")]
+pub use network::wire_format::WIRE_FORMAT_VERSION;
+#[cfg(feature = "std")]
+pub use replay::{Replay, ReplayMetadata, ReplayWriter, REPLAY_FORMAT_VERSION};
+#[cfg(feature = "std")]
+pub use sessions::event_queue::EventHandler;
+pub use sessions::offline_session::OfflineSession;
+#[cfg(feature = "std")]
+pub use sessions::p2p_relay_session::P2PRelaySession;
+#[cfg(feature = "std")]
+pub use sessions::p2p_session::{DesyncPolicy, NetworkProfile, P2PSession, SessionSnapshot};
+#[cfg(feature = "std")]
 pub use sessions::p2p_spectator_session::P2PSpectatorSession;
+#[cfg(feature = "std")]
+pub use sessions::session::Session;
 pub use sessions::sync_test_session::SyncTestSession;
+pub use side_effects::SideEffectQueue;
 pub use sync_layer::GameStateCell;
+pub use telemetry::SessionTelemetry;
+#[cfg(feature = "test-utils")]
+pub use test_utils::{InMemoryNetwork, InMemorySocket, SimulationGame, SimulationHarness};
+#[cfg(feature = "std")]
+pub use time_source::{ManualTimeSource, SystemTimeSource, TimeSource};
+pub use types::{Frame, PlayerHandle};
+
+#[macro_use]
+mod logging;
 
 pub(crate) mod error;
+#[cfg(feature = "std")]
+pub(crate) mod frame_accumulator;
 pub(crate) mod frame_info;
+pub(crate) mod input_latency;
+pub(crate) mod input_predictor;
 pub(crate) mod input_queue;
+#[cfg(feature = "std")]
+pub(crate) mod replay;
+pub(crate) mod side_effects;
 pub(crate) mod sync_layer;
+pub(crate) mod telemetry;
+#[cfg(feature = "test-utils")]
+pub(crate) mod test_utils;
+#[cfg(feature = "std")]
+pub(crate) mod time_source;
+#[cfg(feature = "std")]
 pub(crate) mod time_sync;
+pub(crate) mod types;
 pub(crate) mod sessions {
+    #[cfg(feature = "std")]
+    pub(crate) mod event_queue;
+    pub(crate) mod offline_session;
+    #[cfg(feature = "std")]
+    pub(crate) mod p2p_relay_session;
+    #[cfg(feature = "std")]
     pub(crate) mod p2p_session;
+    #[cfg(feature = "std")]
     pub(crate) mod p2p_spectator_session;
+    #[cfg(feature = "std")]
+    pub(crate) mod session;
     pub(crate) mod sync_test_session;
 }
 pub(crate) mod network {
+    #[cfg(feature = "std")]
+    pub(crate) mod bandwidth;
+    #[cfg(feature = "std")]
+    pub(crate) mod capture;
+    #[cfg(feature = "channel-socket")]
+    pub(crate) mod channel_socket;
+    #[cfg(feature = "std")]
+    pub(crate) mod codec;
+    #[cfg(feature = "std")]
     pub(crate) mod compression;
+    #[cfg(feature = "std")]
+    pub(crate) mod connection_token;
+    #[cfg(feature = "hole-punching")]
+    pub(crate) mod hole_punch;
+    #[cfg(feature = "std")]
+    pub(crate) mod input_stats;
+    #[cfg(feature = "std")]
     pub(crate) mod network_stats;
+    #[cfg(feature = "std")]
+    pub(crate) mod socket_mux;
     pub(crate) mod udp_msg;
+    #[cfg(feature = "std")]
     pub(crate) mod udp_protocol;
+    #[cfg(feature = "std")]
     pub(crate) mod udp_socket;
+    #[cfg(feature = "websocket")]
+    pub(crate) mod websocket;
+    #[cfg(feature = "std")]
+    pub(crate) mod wire_format;
 }
 
 // #############
@@ -46,11 +162,8 @@ pub const MAX_PREDICTION_FRAMES: u32 = 8;
 /// The maximum number of bytes the input of a single player can consist of. This corresponds to the size of `usize`.
 /// Higher values should be possible, but are not tested.
 pub const MAX_INPUT_BYTES: usize = 8;
-/// Internally, -1 represents no frame / invalid frame.
-pub const NULL_FRAME: i32 = -1;
-
-pub type Frame = i32;
-pub type PlayerHandle = usize;
+/// Represents no frame / invalid frame. The same as `Frame::NULL`.
+pub const NULL_FRAME: Frame = Frame::NULL;
 
 // #############
 // #   ENUMS   #
@@ -61,6 +174,7 @@ pub type PlayerHandle = usize;
 /// - remote players, who play on other devices and
 /// - spectators, who are remote players that do not contribute to the game input.
 /// Both `Remote` and `Spectator` have a socket address associated with them.
+#[cfg(feature = "std")]
 #[derive(Debug, Copy, Clone, Hash, PartialEq, Eq)]
 pub enum PlayerType {
     /// This player plays on the local device.
@@ -71,6 +185,36 @@ pub enum PlayerType {
     Spectator(std::net::SocketAddr),
 }
 
+#[cfg(feature = "std")]
+impl PlayerType {
+    /// Creates a `Remote` player from an address that may need to be resolved first, e.g. `"example.com:7777"`.
+    /// If the host name resolves to multiple addresses, the first one is used.
+    /// # Errors
+    /// - Will return an `std::io::Error` if the address could not be resolved.
+    pub fn remote(addr: impl ToSocketAddrs) -> Result<Self, std::io::Error> {
+        Ok(Self::Remote(resolve(addr)?))
+    }
+
+    /// Creates a `Spectator` from an address that may need to be resolved first, e.g. `"example.com:7777"`.
+    /// If the host name resolves to multiple addresses, the first one is used.
+    /// # Errors
+    /// - Will return an `std::io::Error` if the address could not be resolved.
+    pub fn spectator(addr: impl ToSocketAddrs) -> Result<Self, std::io::Error> {
+        Ok(Self::Spectator(resolve(addr)?))
+    }
+}
+
+#[cfg(feature = "std")]
+fn resolve(addr: impl ToSocketAddrs) -> Result<SocketAddr, std::io::Error> {
+    addr.to_socket_addrs()?.next().ok_or_else(|| {
+        std::io::Error::new(
+            std::io::ErrorKind::InvalidInput,
+            "the address did not resolve to any socket address",
+        )
+    })
+}
+
+#[cfg(feature = "std")]
 impl Default for PlayerType {
     fn default() -> Self {
         Self::Local
@@ -84,11 +228,15 @@ pub enum SessionState {
     Initializing,
     /// When synchronizing, the session attempts to establish a connection to the remote clients.
     Synchronizing,
+    /// When in the waiting room, the session has synchronized with all remote clients, but does not advance frames until you call `start_match()`.
+    /// Sessions only enter the waiting room if it has been enabled.
+    WaitingRoom,
     /// When running, the session has synchronized and is ready to take and transmit player input.
     Running,
 }
 
 /// Notifications that you can receive from the session. Handling them is up to the user.
+#[cfg(feature = "std")]
 #[derive(Debug, Copy, Clone, PartialEq, Eq)]
 pub enum GGRSEvent {
     /// The session made progress in synchronizing. After `total` roundtrips, the session are synchronized.
@@ -99,6 +247,9 @@ pub enum GGRSEvent {
     },
     /// The session is now synchronized with the remote client.
     Synchronized { player_handle: PlayerHandle },
+    /// The remote client did not answer the sync requests before the retries ran out or the sync timeout passed. A `P2PSession` disconnects the player,
+    /// so the other players can still start the match. See `P2PSession::set_sync_retries()` and `P2PSession::set_sync_timeout()`.
+    SyncFailed { player_handle: PlayerHandle },
     /// The remote client has disconnected.
     Disconnected { player_handle: PlayerHandle },
     /// The session has not received packets from the remote client for some time and will disconnect the remote in `disconnect_timeout` ms.
@@ -108,8 +259,102 @@ pub enum GGRSEvent {
     },
     /// Sent only after a `NetworkInterrupted` event, if communication with that player has resumed.
     NetworkResumed { player_handle: PlayerHandle },
-    /// Sent out if GGRS recommends skipping a few frames to let clients catch up. If you receive this, consider waiting `skip_frames` number of frames.
+    /// The remote client configured a different FPS than this session. Peers running at different FPS would drift apart, so the session does not synchronize with it.
+    FpsMismatch {
+        player_handle: PlayerHandle,
+        local_fps: u32,
+        remote_fps: u32,
+    },
+    /// The remote client sent a message that is malformed or violates the protocol. The message has been dropped.
+    InvalidMessage { player_handle: PlayerHandle },
+    /// The remote client sent more messages or bytes than allowed by the rate limit. Further messages are dropped until the current second is over.
+    /// The event is sent at most once per second.
+    RateLimitExceeded { player_handle: PlayerHandle },
+    /// The remote client did not acknowledge inputs in time, so the oldest `count` pending inputs to it are not resent anymore.
+    /// Only sent with `PendingOutputPolicy::DropOldest`.
+    PendingInputsDropped {
+        player_handle: PlayerHandle,
+        count: u32,
+    },
+    /// The remote client continues the match from the new address `addr`. Only sent if address migration is enabled.
+    PeerAddressChanged {
+        player_handle: PlayerHandle,
+        addr: SocketAddr,
+    },
+    /// Sending or receiving on the socket of the session failed. Unlike `NetworkInterrupted`, this indicates a problem with the local socket,
+    /// e.g. a full send buffer or an unreachable network.
+    SocketError { error: SocketError },
+    /// Sent out if GGRS recommends skipping a few frames to let clients catch up. If you receive this, consider waiting `skip_frames` number of frames. Not sent if the session follows its recommendations by itself, see `P2PSession::set_auto_wait()`.
     WaitRecommendation { skip_frames: u32 },
+    /// Sent out by a spectator that fell behind the host by more than its jump-to-live threshold. The spectator fast-forwards from frame `from` to frame `to`
+    /// within a single `advance_frame()` call. You should not render the frames in between, so the viewer jumps back to the live game.
+    SkippedFrames { from: Frame, to: Frame },
+    /// Sent every frame while the session catches up on a correction that exceeded the maximum rollback depth. The gamestate lags `remaining_frames` frames
+    /// behind the local inputs; the session resimulates up to the maximum rollback depth of frames per `advance_frame()` call until it has caught up.
+    /// See `P2PSession::set_max_rollback_depth()`.
+    CorrectionDeferred { remaining_frames: u32 },
+    /// Sent with the requests of a rollback. `first_incorrect_frame` is the first frame that was simulated with mispredicted or changed inputs.
+    /// The session may load an earlier state than that, e.g. with sparse saving, but everything your game spawned before `first_incorrect_frame`,
+    /// like sounds or particles, is spawned again by the resimulation exactly as before. Only effects spawned from `first_incorrect_frame` on may differ.
+    Misprediction { first_incorrect_frame: Frame },
+    /// The session decided that the inputs of the disconnected player are `NULL_FRAME` for all frames after `frame`.
+    /// This is sent again if peers later agree on an earlier frame, for example after a host overrode the disconnect frame.
+    DisconnectFrameDecided {
+        player_handle: PlayerHandle,
+        frame: Frame,
+    },
+    /// Sent by a spectator when the host reports that a player has disconnected. The inputs of that player are `NULL_FRAME` for all frames after `frame`.
+    /// This is sent again if the host later reports an earlier frame.
+    PlayerDisconnected {
+        player_handle: PlayerHandle,
+        frame: Frame,
+    },
+    /// A synchronized pause has been requested by a peer. All peers will stop advancing once they reach `frame`.
+    PauseScheduled { frame: Frame },
+    /// A peer requested to resume from the synchronized pause at `frame`.
+    Resumed { frame: Frame },
+    /// A peer requested to change the frame rate of the match to `fps`. All sessions switch their time synchronization to the new frame rate
+    /// once they reach `frame`, so you should run your game at the new frame rate from that frame on. If a timescale is in effect, `fps` is already scaled by it.
+    FpsChangeScheduled { frame: Frame, fps: u32 },
+    /// A peer requested to change the speed of the match to `percent` percent of its regular speed, e.g. 50 for slow motion. This is always sent together with
+    /// a `FpsChangeScheduled` event for the scaled frame rate: you should keep advancing your gamestate by the time of a regular frame,
+    /// but run the game at the scaled frame rate from `frame` on. See `P2PSession::request_timescale_change()`.
+    TimescaleChangeScheduled { frame: Frame, percent: u16 },
+    /// The session waits at the barrier `frame` and has received the inputs up to the barrier of `confirmed` out of `total` connected remote players.
+    /// See `P2PSession::add_barrier_frame()`.
+    BarrierProgress {
+        frame: Frame,
+        confirmed: u32,
+        total: u32,
+    },
+    /// The inputs of all connected peers up to the barrier `frame` have arrived, the session continues advancing.
+    BarrierPassed { frame: Frame },
+    /// The input validator rejected the input of a remote player for the given frame.
+    InvalidInput {
+        player_handle: PlayerHandle,
+        frame: Frame,
+    },
+    /// The remote player has a different checksum for the confirmed gamestate of `frame` than the local session, so the gamestates of the players have diverged.
+    /// Only sent if the checksums are exchanged, see `P2PSession::set_checksum_interval()` and `P2PSession::set_desync_policy()`.
+    DesyncDetected {
+        player_handle: PlayerHandle,
+        frame: Frame,
+        local_checksum: u64,
+        remote_checksum: u64,
+    },
+}
+
+/// A `GGRSEvent` together with the frame the session was in and the moment the event occurred. If you query events only after a long frame,
+/// this lets you correlate disconnects and interruptions with the frames of your simulation, e.g. for logs and replays.
+#[cfg(feature = "std")]
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct TimedEvent {
+    /// The event itself.
+    pub event: GGRSEvent,
+    /// The current frame of the session when the event occurred, as returned by `current_frame()`. For a `P2PRelaySession`, this is the last frame received from the host.
+    pub frame: Frame,
+    /// The moment the event occurred, as returned by the `TimeSource` of the session.
+    pub timestamp: Duration,
 }
 
 /// Requests that you can receive from the session. Handling them is mandatory.
@@ -117,11 +362,23 @@ pub enum GGRSEvent {
 pub enum GGRSRequest {
     /// You should save the current gamestate in the `cell` provided to you. The given `frame` is a sanity check: The gamestate you save should be from that frame.
     SaveGameState { cell: GameStateCell, frame: Frame },
-    /// You should load the gamestate in the `cell` provided to you.
-    LoadGameState { cell: GameStateCell },
+    /// You should load the gamestate in the `cell` provided to you. The given `frame` is the frame of that gamestate.
+    LoadGameState { cell: GameStateCell, frame: Frame },
     /// You should advance the gamestate with the `inputs` provided to you.
     /// Disconnected players are indicated by having `NULL_FRAME` instead of the correct current frame in their input.
-    AdvanceFrame { inputs: Vec<GameInput> },
+    /// The given `frame` is the frame the inputs belong to: your gamestate should be of that frame before advancing and of frame `frame + 1` afterwards.
+    AdvanceFrame {
+        frame: Frame,
+        inputs: Vec<GameInput>,
+    },
+    /// You should advance the gamestate once for every entry of `inputs_per_frame`, starting from the gamestate of `from_frame`. Only sent if batched resimulation
+    /// is enabled, see `P2PSession::set_batched_resimulation()`. If `cells[i]` is set, you should save the gamestate after advancing with `inputs_per_frame[i]`,
+    /// which is the gamestate of frame `from_frame + i + 1`, in that cell. The inputs follow the same rules as the inputs of `AdvanceFrame`.
+    Resimulate {
+        from_frame: Frame,
+        inputs_per_frame: Vec<Vec<GameInput>>,
+        cells: Vec<Option<GameStateCell>>,
+    },
 }
 
 // #############
@@ -177,6 +434,40 @@ pub fn start_synctest_session(
     ))
 }
 
+/// Used to create a new `OfflineSession` for games where all players are local, e.g. single player, couch multiplayer or training modes.
+/// The session hands out the same requests as a `P2PSession`, so you can use the same code to fulfill them.
+/// # Example
+///
+/// ```
+/// # use ggrs::GGRSError;
+/// # fn main() -> Result<(), GGRSError> {
+/// let num_players : u32 = 2;
+/// let input_size : usize = std::mem::size_of::<u32>();
+/// let mut sess = ggrs::start_offline_session(num_players, input_size)?;
+/// # Ok(())
+/// # }
+/// ```
+///
+/// # Errors
+/// - Will return a `InvalidRequestError` if the number of players is higher than the allowed maximum (see `MAX_PLAYERS`).
+/// - Will return a `InvalidRequestError` if `input_size` is higher than the allowed maximum (see `MAX_INPUT_BYTES`).
+pub fn start_offline_session(
+    num_players: u32,
+    input_size: usize,
+) -> Result<OfflineSession, GGRSError> {
+    if num_players > MAX_PLAYERS {
+        return Err(GGRSError::InvalidRequest {
+            info: "Too many players.".to_owned(),
+        });
+    }
+    if input_size > MAX_INPUT_BYTES {
+        return Err(GGRSError::InvalidRequest {
+            info: "Input size too big.".to_owned(),
+        });
+    }
+    Ok(OfflineSession::new(num_players, input_size))
+}
+
 /// Used to create a new `P2PSession` for players who participate on the game input. After creating the session, add local and remote players,
 /// set input delay for local players and then start the session.
 /// # Example
@@ -196,6 +487,7 @@ pub fn start_synctest_session(
 /// - Will return a `InvalidRequest` if the number of players is higher than the allowed maximum (see `MAX_PLAYERS`).
 /// - Will return a `InvalidRequest` if `input_size` is higher than the allowed maximum (see `MAX_INPUT_BYTES`).
 /// - Will return `SocketCreationFailed` if the UPD socket could not be created.
+#[cfg(feature = "std")]
 pub fn start_p2p_session(
     num_players: u32,
     input_size: usize,
@@ -211,8 +503,46 @@ pub fn start_p2p_session(
             info: "Input size too big.".to_owned(),
         });
     }
-    P2PSession::new(num_players, input_size, local_port)
-        .map_err(|_| GGRSError::SocketCreationFailed)
+    let socket = UdpNonBlockingSocket::bind_to_port(local_port)
+        .map_err(|_| GGRSError::SocketCreationFailed)?;
+    start_p2p_session_with_socket(num_players, input_size, socket)
+}
+
+/// Used to create a new `P2PSession` that uses the given socket to communicate with remote clients, instead of binding a UDP socket itself.
+/// This allows you to use your own transport or to wrap a socket, for example with a `CaptureSocket`.
+/// # Example
+///
+/// ```
+/// # use ggrs::{GGRSError, UdpNonBlockingSocket};
+/// # fn main() -> Result<(), Box<dyn std::error::Error>> {
+/// let socket = UdpNonBlockingSocket::bind_to_port(7777)?;
+/// let num_players : u32 = 2;
+/// let input_size : usize = std::mem::size_of::<u32>();
+/// let mut sess = ggrs::start_p2p_session_with_socket(num_players, input_size, socket)?;
+/// # Ok(())
+/// # }
+/// ```
+///
+/// # Errors
+/// - Will return a `InvalidRequest` if the number of players is higher than the allowed maximum (see `MAX_PLAYERS`).
+/// - Will return a `InvalidRequest` if `input_size` is higher than the allowed maximum (see `MAX_INPUT_BYTES`).
+#[cfg(feature = "std")]
+pub fn start_p2p_session_with_socket(
+    num_players: u32,
+    input_size: usize,
+    socket: impl NonBlockingSocket + 'static,
+) -> Result<P2PSession, GGRSError> {
+    if num_players > MAX_PLAYERS {
+        return Err(GGRSError::InvalidRequest {
+            info: "Too many players.".to_owned(),
+        });
+    }
+    if input_size > MAX_INPUT_BYTES {
+        return Err(GGRSError::InvalidRequest {
+            info: "Input size too big.".to_owned(),
+        });
+    }
+    Ok(P2PSession::new(num_players, input_size, Box::new(socket)))
 }
 
 /// Used to create a new `P2PSpectatorSession` for a spectator.
@@ -235,6 +565,7 @@ pub fn start_p2p_session(
 /// - Will return a `InvalidRequest` if the number of players is higher than the allowed maximum (see `MAX_PLAYERS`).
 /// - Will return a `InvalidRequest` if `input_size` is higher than the allowed maximum (see `MAX_INPUT_BYTES`).
 /// - Will return `SocketCreationFailed` if the UPD socket could not be created.
+#[cfg(feature = "std")]
 pub fn start_p2p_spectator_session(
     num_players: u32,
     input_size: usize,
@@ -251,6 +582,122 @@ pub fn start_p2p_spectator_session(
             info: "Input size too big.".to_owned(),
         });
     }
-    P2PSpectatorSession::new(num_players, input_size, local_port, host_addr)
-        .map_err(|_| GGRSError::SocketCreationFailed)
+    let socket = UdpNonBlockingSocket::bind_to_port(local_port)
+        .map_err(|_| GGRSError::SocketCreationFailed)?;
+    start_p2p_spectator_session_with_socket(num_players, input_size, host_addr, socket)
+}
+
+/// Used to create a new `P2PSpectatorSession` that uses the given socket to communicate with the host, instead of binding a UDP socket itself.
+/// # Example
+///
+/// ```
+/// # use std::net::SocketAddr;
+/// # use ggrs::UdpNonBlockingSocket;
+/// # fn main() -> Result<(), Box<dyn std::error::Error>> {
+/// let socket = UdpNonBlockingSocket::bind_to_port(7777)?;
+/// let num_players : u32 = 2;
+/// let input_size : usize = std::mem::size_of::<u32>();
+/// let host_addr: SocketAddr = "127.0.0.1:8888".parse()?;
+/// let mut sess = ggrs::start_p2p_spectator_session_with_socket(num_players, input_size, host_addr, socket)?;
+/// # Ok(())
+/// # }
+/// ```
+///
+/// # Errors
+/// - Will return a `InvalidRequest` if the number of players is higher than the allowed maximum (see `MAX_PLAYERS`).
+/// - Will return a `InvalidRequest` if `input_size` is higher than the allowed maximum (see `MAX_INPUT_BYTES`).
+#[cfg(feature = "std")]
+pub fn start_p2p_spectator_session_with_socket(
+    num_players: u32,
+    input_size: usize,
+    host_addr: SocketAddr,
+    socket: impl NonBlockingSocket + 'static,
+) -> Result<P2PSpectatorSession, GGRSError> {
+    if num_players > MAX_PLAYERS {
+        return Err(GGRSError::InvalidRequest {
+            info: "Too many players.".to_owned(),
+        });
+    }
+    if input_size > MAX_INPUT_BYTES {
+        return Err(GGRSError::InvalidRequest {
+            info: "Input size too big.".to_owned(),
+        });
+    }
+    Ok(P2PSpectatorSession::new(
+        num_players,
+        input_size,
+        host_addr,
+        Box::new(socket),
+    ))
+}
+
+/// Used to create a new `P2PRelaySession` for a headless forwarding server.
+/// The host sees the relay as a regular spectator. The relay rebroadcasts all inputs it receives to its own spectators.
+/// # Example
+///
+/// ```
+/// # use std::net::SocketAddr;
+/// # fn main() -> Result<(), Box<dyn std::error::Error>> {
+/// let local_port: u16 = 7777;
+/// let num_players : u32 = 2;
+/// let input_size : usize = std::mem::size_of::<u32>();
+/// let host_addr: SocketAddr = "127.0.0.1:8888".parse()?;
+/// let mut sess = ggrs::start_p2p_relay_session(num_players, input_size, local_port, host_addr)?;
+/// # Ok(())
+/// # }
+/// ```
+///
+/// # Errors
+/// - Will return a `InvalidRequest` if the number of players is higher than the allowed maximum (see `MAX_PLAYERS`).
+/// - Will return a `InvalidRequest` if `input_size` is higher than the allowed maximum (see `MAX_INPUT_BYTES`).
+/// - Will return `SocketCreationFailed` if the UPD socket could not be created.
+#[cfg(feature = "std")]
+pub fn start_p2p_relay_session(
+    num_players: u32,
+    input_size: usize,
+    local_port: u16,
+    host_addr: SocketAddr,
+) -> Result<P2PRelaySession, GGRSError> {
+    if num_players > MAX_PLAYERS {
+        return Err(GGRSError::InvalidRequest {
+            info: "Too many players.".to_owned(),
+        });
+    }
+    if input_size > MAX_INPUT_BYTES {
+        return Err(GGRSError::InvalidRequest {
+            info: "Input size too big.".to_owned(),
+        });
+    }
+    let socket = UdpNonBlockingSocket::bind_to_port(local_port)
+        .map_err(|_| GGRSError::SocketCreationFailed)?;
+    start_p2p_relay_session_with_socket(num_players, input_size, host_addr, socket)
+}
+
+/// Used to create a new `P2PRelaySession` that uses the given socket to communicate with the host and the spectators, instead of binding a UDP socket itself.
+/// # Errors
+/// - Will return a `InvalidRequest` if the number of players is higher than the allowed maximum (see `MAX_PLAYERS`).
+/// - Will return a `InvalidRequest` if `input_size` is higher than the allowed maximum (see `MAX_INPUT_BYTES`).
+#[cfg(feature = "std")]
+pub fn start_p2p_relay_session_with_socket(
+    num_players: u32,
+    input_size: usize,
+    host_addr: SocketAddr,
+    socket: impl NonBlockingSocket + 'static,
+) -> Result<P2PRelaySession, GGRSError> {
+    if num_players > MAX_PLAYERS {
+        return Err(GGRSError::InvalidRequest {
+            info: "Too many players.".to_owned(),
+        });
+    }
+    if input_size > MAX_INPUT_BYTES {
+        return Err(GGRSError::InvalidRequest {
+            info: "Input size too big.".to_owned(),
+        });
+    }
+    Ok(P2PRelaySession::new(
+        num_players,
+        input_size,
+        host_addr,
+        Box::new(socket),
+    ))
 }
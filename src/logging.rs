@@ -0,0 +1,50 @@
+//! Internal logging macros. With the `log` feature enabled, these forward to the `log` crate, so you can inspect handshakes,
+//! rollbacks, dropped packets and disconnects with any logger of your choice. With the `tracing` feature enabled, they emit `tracing` events as well,
+//! and `ggrs_span!` wraps advancing a frame, rollbacks and resimulations in spans, so your existing subscribers see where the time of a frame goes.
+//! Without either feature, they expand to nothing.
+
+#[cfg(feature = "std")]
+macro_rules! ggrs_trace {
+    ($($arg:tt)+) => {
+        #[cfg(feature = "log")]
+        log::trace!($($arg)+);
+        #[cfg(feature = "tracing")]
+        tracing::trace!($($arg)+);
+    };
+}
+
+macro_rules! ggrs_debug {
+    ($($arg:tt)+) => {
+        #[cfg(feature = "log")]
+        log::debug!($($arg)+);
+        #[cfg(feature = "tracing")]
+        tracing::debug!($($arg)+);
+    };
+}
+
+#[cfg(feature = "std")]
+macro_rules! ggrs_info {
+    ($($arg:tt)+) => {
+        #[cfg(feature = "log")]
+        log::info!($($arg)+);
+        #[cfg(feature = "tracing")]
+        tracing::info!($($arg)+);
+    };
+}
+
+macro_rules! ggrs_warn {
+    ($($arg:tt)+) => {
+        #[cfg(feature = "log")]
+        log::warn!($($arg)+);
+        #[cfg(feature = "tracing")]
+        tracing::warn!($($arg)+);
+    };
+}
+
+/// Enters a `tracing` span with the given name and fields until the end of the enclosing block.
+macro_rules! ggrs_span {
+    ($name:literal $(, $($fields:tt)+)?) => {
+        #[cfg(feature = "tracing")]
+        let _span = tracing::debug_span!($name $(, $($fields)+)?).entered();
+    };
+}
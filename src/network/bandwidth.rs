@@ -0,0 +1,161 @@
+use std::net::SocketAddr;
+use std::time::Duration;
+
+use crate::network::udp_msg::{MessageBody, UdpMessage};
+
+/// Size of IP + UDP headers, which count against the budget as well.
+const UDP_HEADER_SIZE: usize = 28;
+/// The budget can be saved up to this many bytes, so a single message of maximum size always fits eventually.
+const MIN_BURST: usize = 1500;
+
+/// How important an outgoing message is. Messages with a lower priority are only sent if the budget allows after all more important messages.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord)]
+pub(crate) enum MessagePriority {
+    /// Inputs, input acks and handshakes with players, which the match can not progress without.
+    Gameplay,
+    /// Quality reports, keep alives and reliable messages for players.
+    Maintenance,
+    /// Everything sent to spectators.
+    Spectator,
+}
+
+impl MessagePriority {
+    pub(crate) fn of(msg: &UdpMessage, to_spectator: bool) -> Self {
+        if to_spectator {
+            return MessagePriority::Spectator;
+        }
+        match msg.body {
+            MessageBody::QualityReport(_)
+            | MessageBody::QualityReply(_)
+            | MessageBody::KeepAlive
+            | MessageBody::Reliable(_)
+            | MessageBody::ReliableAck(_)
+            | MessageBody::StateChecksum(_) => MessagePriority::Maintenance,
+            _ => MessagePriority::Gameplay,
+        }
+    }
+}
+
+/// A token bucket limiting the outgoing bytes per second of a session. Every poll, all queued messages are scheduled at once:
+/// the most important messages are sent as long as the budget allows and the rest are dropped. GGRS resends all unacknowledged inputs
+/// and regularly sends the other messages again, so dropped messages only delay the connection instead of breaking it.
+#[derive(Debug)]
+pub(crate) struct BandwidthBudget {
+    bytes_per_second: usize,
+    available: usize,
+    last_refill: Duration,
+}
+
+impl BandwidthBudget {
+    pub(crate) fn new(bytes_per_second: usize, now: Duration) -> Self {
+        Self {
+            bytes_per_second,
+            available: Self::burst(bytes_per_second),
+            last_refill: now,
+        }
+    }
+
+    /// Restarts refilling the budget at `now`, e.g. after switching to another time source.
+    pub(crate) fn restart(&mut self, now: Duration) {
+        self.last_refill = now;
+    }
+
+    fn burst(bytes_per_second: usize) -> usize {
+        std::cmp::max(bytes_per_second / 10, MIN_BURST)
+    }
+
+    /// Returns the messages that fit into the budget, most important first. Messages of the same priority keep their order.
+    pub(crate) fn schedule(
+        &mut self,
+        now: Duration,
+        mut messages: Vec<(MessagePriority, SocketAddr, UdpMessage)>,
+    ) -> Vec<(SocketAddr, UdpMessage)> {
+        let elapsed = now.saturating_sub(self.last_refill);
+        let refill = elapsed.as_micros() * self.bytes_per_second as u128 / 1_000_000;
+        if refill > 0 {
+            self.available = std::cmp::min(
+                self.available.saturating_add(refill as usize),
+                Self::burst(self.bytes_per_second),
+            );
+            self.last_refill = now;
+        }
+
+        messages.sort_by_key(|(priority, _, _)| *priority);
+        let mut scheduled = Vec::with_capacity(messages.len());
+        for (_, addr, msg) in messages {
+            let size = msg.encoded_len() + UDP_HEADER_SIZE;
+            if size <= self.available {
+                self.available -= size;
+                scheduled.push((addr, msg));
+            } else {
+                ggrs_debug!("Dropping message to {}, bandwidth budget exhausted", addr);
+            }
+        }
+        scheduled
+    }
+}
+
+// #########
+// # TESTS #
+// #########
+
+#[cfg(test)]
+mod bandwidth_tests {
+
+    use super::*;
+    use crate::network::udp_msg::{InputAck, MessageHeader};
+    use crate::Frame;
+
+    fn message(body: MessageBody) -> UdpMessage {
+        UdpMessage {
+            header: MessageHeader {
+                magic: 1,
+                session_id: 0,
+                sequence_number: 0,
+            },
+            body,
+        }
+    }
+
+    #[test]
+    fn test_priorities() {
+        let addr = "127.0.0.1:7777".parse().unwrap();
+        let keep_alive = message(MessageBody::KeepAlive);
+        let ack = message(MessageBody::InputAck(InputAck {
+            ack_frame: Frame(0),
+        }));
+        let size = keep_alive.encoded_len() + UDP_HEADER_SIZE;
+        // the burst only allows for a few messages
+        let count = MIN_BURST / size + 5;
+
+        let mut budget = BandwidthBudget::new(100, Duration::ZERO);
+        let mut messages = Vec::new();
+        for _ in 0..count {
+            messages.push((
+                MessagePriority::of(&keep_alive, true),
+                addr,
+                keep_alive.clone(),
+            ));
+        }
+        messages.push((MessagePriority::of(&ack, false), addr, ack.clone()));
+        let scheduled = budget.schedule(Duration::ZERO, messages);
+        assert!(scheduled.len() < count);
+        assert_eq!(scheduled[0].1, ack);
+    }
+
+    #[test]
+    fn test_refill() {
+        let addr = "127.0.0.1:7777".parse().unwrap();
+        let msg = message(MessageBody::KeepAlive);
+        let start = Duration::from_secs(3);
+        let mut budget = BandwidthBudget::new(10_000, start);
+        let batch = || vec![(MessagePriority::Maintenance, addr, msg.clone()); 100];
+
+        // the first poll uses up the burst, the next one only gets what accumulated since
+        let first = budget.schedule(start, batch()).len();
+        assert_eq!(budget.schedule(start, batch()).len(), 0);
+        let later = start + Duration::from_millis(50);
+        let second = budget.schedule(later, batch()).len();
+        assert!(second > 0 && second < first);
+    }
+}
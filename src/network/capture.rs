@@ -0,0 +1,348 @@
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::fs::File;
+use std::io::{BufReader, BufWriter, ErrorKind, Write};
+use std::net::SocketAddr;
+use std::path::Path;
+use std::time::Instant;
+
+use serde::{Deserialize, Serialize};
+
+use crate::network::udp_msg::{MessageBody, UdpMessage};
+use crate::network::udp_socket::{NonBlockingSocket, SocketError, SocketStats};
+
+/// Indicates whether a captured message was sent or received by the capturing session.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum CaptureDirection {
+    /// The message was sent by the capturing session to `addr`.
+    Sent,
+    /// The message was received by the capturing session from `addr`.
+    Received,
+}
+
+/// A single message recorded by a `CaptureSocket`.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct CaptureEntry {
+    /// Milliseconds since the capture was started.
+    pub timestamp: u128,
+    /// If this message was sent or received.
+    pub direction: CaptureDirection,
+    /// The address of the remote end of this message.
+    pub addr: SocketAddr,
+    /// The captured message.
+    pub msg: UdpMessage,
+}
+
+/// A `CaptureSocket` wraps another `NonBlockingSocket` and records every sent and received message with a timestamp to a file.
+/// The resulting capture can be inspected with `ReplaySocket::read_capture()` or fed back into a session with a `ReplaySocket`,
+/// making protocol problems reproducible offline. If writing the capture fails, e.g. because the disk is full, the socket keeps working,
+/// but stops recording. `flush()` reports the error.
+#[derive(Debug)]
+pub struct CaptureSocket<S: NonBlockingSocket> {
+    inner: S,
+    writer: BufWriter<File>,
+    start_time: Instant,
+    /// True once writing the capture failed. Nothing is recorded afterwards.
+    failed: bool,
+    /// The error that stopped the recording, until `flush()` reports it.
+    error: Option<std::io::Error>,
+}
+
+impl<S: NonBlockingSocket> CaptureSocket<S> {
+    /// Creates a new `CaptureSocket` wrapping `inner`, writing the capture to the file at `path`. An existing file will be overwritten.
+    /// # Errors
+    /// - Will return an `std::io::Error` if the file could not be created.
+    pub fn new(inner: S, path: impl AsRef<Path>) -> Result<Self, std::io::Error> {
+        Ok(Self {
+            inner,
+            writer: BufWriter::new(File::create(path)?),
+            start_time: Instant::now(),
+            failed: false,
+            error: None,
+        })
+    }
+
+    /// Writes all buffered entries to the capture file.
+    /// # Errors
+    /// - Will return an `std::io::Error` if writing to the file failed, now or while recording an earlier message. The recording stops after the first error.
+    pub fn flush(&mut self) -> Result<(), std::io::Error> {
+        if self.failed {
+            return Err(self.error.take().unwrap_or_else(|| {
+                std::io::Error::other("The capture stopped after an earlier error")
+            }));
+        }
+        let result = self.writer.flush();
+        self.failed = result.is_err();
+        result
+    }
+
+    fn record(&mut self, direction: CaptureDirection, addr: SocketAddr, msg: &UdpMessage) {
+        if self.failed {
+            return;
+        }
+        let entry = CaptureEntry {
+            timestamp: self.start_time.elapsed().as_millis(),
+            direction,
+            addr,
+            msg: msg.clone(),
+        };
+        if let Err(error) = bincode::serialize_into(&mut self.writer, &entry) {
+            // never crash the game because of the recording, report the error on the next flush instead
+            self.failed = true;
+            self.error = Some(match *error {
+                bincode::ErrorKind::Io(error) => error,
+                error => std::io::Error::other(error),
+            });
+        }
+    }
+}
+
+impl<S: NonBlockingSocket> NonBlockingSocket for CaptureSocket<S> {
+    fn send_to(&mut self, msg: &UdpMessage, addr: SocketAddr) {
+        self.record(CaptureDirection::Sent, addr, msg);
+        self.inner.send_to(msg, addr);
+    }
+
+    fn receive_all_messages(&mut self) -> Vec<(SocketAddr, UdpMessage)> {
+        let received = self.inner.receive_all_messages();
+        for (addr, msg) in &received {
+            self.record(CaptureDirection::Received, *addr, msg);
+        }
+        received
+    }
+
+    fn take_errors(&mut self) -> Vec<SocketError> {
+        self.inner.take_errors()
+    }
+
+    fn stats(&self) -> Option<SocketStats> {
+        self.inner.stats()
+    }
+}
+
+/// A `ReplaySocket` plays back the received messages of a capture written by a `CaptureSocket`. Messages are handed out once
+/// as much time has passed since the first call to `receive_all_messages()` as during the original capture. Sent messages are discarded.
+///
+/// The replaying session picks new random data for its sync requests, so the captured sync replies are handed out in lockstep with the
+/// sync requests of the session and answer their random data. This way, the session synchronizes and receives the same inputs as during the capture.
+/// Some things still differ from the capture: the session picks its own contribution to `P2PSession::shared_seed()`, so games that seed
+/// their random number generator with it should store the seed next to the capture, and sessions with a connection token cannot be replayed,
+/// as the captured proofs of the peers answer the random data of the original session.
+#[derive(Debug)]
+pub struct ReplaySocket {
+    pending: VecDeque<CaptureEntry>,
+    start_time: Option<Instant>,
+    /// The random data of the latest unanswered sync request the replaying session sent to each address.
+    sync_requests: HashMap<SocketAddr, u32>,
+    /// The addresses the replaying session is synchronized with, recognized by the session sending messages other than sync requests.
+    synchronized: HashSet<SocketAddr>,
+}
+
+impl ReplaySocket {
+    /// Creates a new `ReplaySocket` from the received messages in the given capture entries.
+    pub fn new(entries: impl IntoIterator<Item = CaptureEntry>) -> Self {
+        Self {
+            pending: entries
+                .into_iter()
+                .filter(|entry| entry.direction == CaptureDirection::Received)
+                .collect(),
+            start_time: None,
+            sync_requests: HashMap::new(),
+            synchronized: HashSet::new(),
+        }
+    }
+
+    /// Creates a new `ReplaySocket` from a capture file.
+    /// # Errors
+    /// - Will return an `std::io::Error` if the file could not be read or does not contain a valid capture.
+    pub fn from_file(path: impl AsRef<Path>) -> Result<Self, std::io::Error> {
+        let entries = Self::read_capture(path)?;
+        Ok(Self::new(entries))
+    }
+
+    /// Reads all entries of a capture file written by a `CaptureSocket`.
+    /// # Errors
+    /// - Will return an `std::io::Error` if the file could not be read or does not contain a valid capture.
+    pub fn read_capture(path: impl AsRef<Path>) -> Result<Vec<CaptureEntry>, std::io::Error> {
+        let mut reader = BufReader::new(File::open(path)?);
+        let mut entries = Vec::new();
+        loop {
+            match bincode::deserialize_from(&mut reader) {
+                Ok(entry) => entries.push(entry),
+                Err(err) => match *err {
+                    // we reached the end of the capture
+                    bincode::ErrorKind::Io(ref io_err)
+                        if io_err.kind() == ErrorKind::UnexpectedEof =>
+                    {
+                        return Ok(entries)
+                    }
+                    _ => return Err(std::io::Error::new(ErrorKind::InvalidData, err)),
+                },
+            }
+        }
+    }
+
+    /// Returns `true` if all captured messages have been replayed.
+    pub fn is_finished(&self) -> bool {
+        self.pending.is_empty()
+    }
+}
+
+impl NonBlockingSocket for ReplaySocket {
+    fn send_to(&mut self, msg: &UdpMessage, addr: SocketAddr) {
+        match &msg.body {
+            MessageBody::SyncRequest(body) => {
+                self.sync_requests.insert(addr, body.random_request);
+            }
+            MessageBody::Input(_) | MessageBody::InputAck(_) | MessageBody::QualityReport(_) => {
+                self.synchronized.insert(addr);
+            }
+            _ => (),
+        }
+    }
+
+    fn receive_all_messages(&mut self) -> Vec<(SocketAddr, UdpMessage)> {
+        let elapsed = self.start_time.get_or_insert_with(Instant::now).elapsed();
+        let mut received = Vec::new();
+        while let Some(entry) = self.pending.front_mut() {
+            if entry.timestamp > elapsed.as_millis() {
+                break;
+            }
+            // a sync reply answers the latest sync request. While synchronizing, the replay waits until the session has sent the next one
+            if let MessageBody::SyncReply(body) = &mut entry.msg.body {
+                match self.sync_requests.remove(&entry.addr) {
+                    Some(random_request) => body.random_reply = random_request,
+                    None if !self.synchronized.contains(&entry.addr) => break,
+                    None => (),
+                }
+            }
+            let entry = self.pending.pop_front().expect("Entry vanished");
+            received.push((entry.addr, entry.msg));
+        }
+        received
+    }
+}
+
+// #########
+// # TESTS #
+// #########
+
+#[cfg(test)]
+mod capture_tests {
+    use super::*;
+    use crate::network::udp_msg::{MessageHeader, SyncReply, SyncRequest};
+
+    #[derive(Debug, Default)]
+    struct LoopbackSocket {
+        queue: Vec<(SocketAddr, UdpMessage)>,
+    }
+
+    impl NonBlockingSocket for LoopbackSocket {
+        fn send_to(&mut self, msg: &UdpMessage, addr: SocketAddr) {
+            self.queue.push((addr, msg.clone()));
+        }
+
+        fn receive_all_messages(&mut self) -> Vec<(SocketAddr, UdpMessage)> {
+            self.queue.drain(..).collect()
+        }
+    }
+
+    #[test]
+    fn test_capture_and_replay() {
+        let path = std::env::temp_dir().join("ggrs_test_capture_and_replay.cap");
+        let addr: SocketAddr = "127.0.0.1:7777".parse().unwrap();
+        let msg = UdpMessage {
+            header: MessageHeader {
+                magic: 42,
+                session_id: 0,
+                sequence_number: 0,
+            },
+            body: MessageBody::KeepAlive,
+        };
+
+        let mut socket = CaptureSocket::new(LoopbackSocket::default(), &path).unwrap();
+        socket.send_to(&msg, addr);
+        assert_eq!(socket.receive_all_messages().len(), 1);
+        socket.flush().unwrap();
+
+        let entries = ReplaySocket::read_capture(&path).unwrap();
+        assert_eq!(entries.len(), 2);
+        assert_eq!(entries[0].direction, CaptureDirection::Sent);
+        assert_eq!(entries[1].direction, CaptureDirection::Received);
+        assert_eq!(entries[1].msg, msg);
+
+        let mut replay = ReplaySocket::from_file(&path).unwrap();
+        assert_eq!(replay.receive_all_messages(), vec![(addr, msg)]);
+        assert!(replay.is_finished());
+
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn test_replay_answers_sync_requests() {
+        let addr: SocketAddr = "127.0.0.1:7777".parse().unwrap();
+        let header = MessageHeader {
+            magic: 42,
+            session_id: 0,
+            sequence_number: 0,
+        };
+        let sync_reply = CaptureEntry {
+            timestamp: 0,
+            direction: CaptureDirection::Received,
+            addr,
+            msg: UdpMessage {
+                header,
+                body: MessageBody::SyncReply(SyncReply {
+                    random_reply: 1,
+                    fps: 60,
+                    token_proof: 0,
+                }),
+            },
+        };
+        let mut replay = ReplaySocket::new(vec![sync_reply.clone(), sync_reply]);
+
+        // the replies wait for the sync requests of the replaying session
+        assert!(replay.receive_all_messages().is_empty());
+        for random_request in [7, 8] {
+            let request = UdpMessage {
+                header,
+                body: MessageBody::SyncRequest(SyncRequest {
+                    random_request,
+                    fps: 60,
+                    seed: 0,
+                }),
+            };
+            replay.send_to(&request, addr);
+            let received = replay.receive_all_messages();
+            assert_eq!(received.len(), 1);
+            assert!(matches!(
+                received[0].1.body,
+                MessageBody::SyncReply(SyncReply { random_reply, .. }) if random_reply == random_request
+            ));
+        }
+        assert!(replay.is_finished());
+    }
+
+    #[test]
+    #[cfg(target_os = "linux")]
+    fn test_write_errors_stop_recording() {
+        let addr: SocketAddr = "127.0.0.1:7777".parse().unwrap();
+        let msg = UdpMessage {
+            header: MessageHeader {
+                magic: 42,
+                session_id: 0,
+                sequence_number: 0,
+            },
+            body: MessageBody::KeepAlive,
+        };
+
+        // every write to /dev/full fails, which the socket notices once its buffer is full
+        let mut socket = CaptureSocket::new(LoopbackSocket::default(), "/dev/full").unwrap();
+        for _ in 0..1000 {
+            socket.send_to(&msg, addr);
+            assert_eq!(socket.receive_all_messages().len(), 1);
+        }
+        assert!(socket.failed);
+        assert!(socket.flush().is_err());
+        assert!(socket.flush().is_err());
+    }
+}
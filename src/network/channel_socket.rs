@@ -0,0 +1,150 @@
+use parking_lot::Mutex;
+use std::collections::VecDeque;
+use std::net::SocketAddr;
+use std::sync::Arc;
+
+use crate::network::codec::{MessageCodec, WireFormatCodec};
+use crate::network::udp_msg::UdpMessage;
+use crate::network::udp_socket::{NonBlockingSocket, SocketStats};
+
+#[derive(Debug, Default)]
+struct Channels {
+    incoming: VecDeque<(SocketAddr, Vec<u8>)>,
+    outgoing: VecDeque<(SocketAddr, Vec<u8>)>,
+    stats: SocketStats,
+}
+
+/// A `NonBlockingSocket` that does not talk to the network itself, but hands all packets to your own transport through a `ChannelSocketHandle`.
+/// Use it where UDP sockets are not available, e.g. in browsers: forward the packets of `ChannelSocketHandle::take_outgoing()` over a WebSocket
+/// or WebRTC data channel and feed everything you receive into `ChannelSocketHandle::push_incoming()`.
+/// Peers are identified by a `SocketAddr`, which does not need to be a real address. Any address works, as long as you map it to the same peer consistently.
+/// Available with the `channel-socket` feature.
+#[derive(Debug)]
+pub struct ChannelSocket {
+    channels: Arc<Mutex<Channels>>,
+    codec: Box<dyn MessageCodec>,
+}
+
+impl Default for ChannelSocket {
+    fn default() -> Self {
+        Self {
+            channels: Arc::default(),
+            codec: Box::new(WireFormatCodec),
+        }
+    }
+}
+
+impl ChannelSocket {
+    /// Creates a new `ChannelSocket` without any queued packets.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Sets the codec that encodes sent and decodes received messages. All peers have to use the same codec.
+    pub fn set_codec(&mut self, codec: impl MessageCodec + 'static) {
+        self.codec = Box::new(codec);
+    }
+
+    /// Returns a handle to the packet queues of this socket. Keep the handle around after handing the socket to a session.
+    pub fn handle(&self) -> ChannelSocketHandle {
+        ChannelSocketHandle {
+            channels: self.channels.clone(),
+        }
+    }
+}
+
+impl NonBlockingSocket for ChannelSocket {
+    fn send_to(&mut self, msg: &UdpMessage, addr: SocketAddr) {
+        let buf = self.codec.encode(msg);
+        let mut channels = self.channels.lock();
+        channels.stats.packets_sent += 1;
+        channels.stats.bytes_sent += buf.len() as u64;
+        channels.outgoing.push_back((addr, buf));
+    }
+
+    fn receive_all_messages(&mut self) -> Vec<(SocketAddr, UdpMessage)> {
+        let mut channels = self.channels.lock();
+        let mut received_messages = Vec::new();
+        while let Some((addr, buf)) = channels.incoming.pop_front() {
+            channels.stats.packets_received += 1;
+            channels.stats.bytes_received += buf.len() as u64;
+            // packets that are not valid GGRS messages are dropped
+            if let Some(msg) = self.codec.decode(&buf) {
+                received_messages.push((addr, msg));
+            } else {
+                ggrs_warn!("Dropping invalid packet from {}", addr);
+                channels.stats.invalid_packets += 1;
+            }
+        }
+        received_messages
+    }
+
+    fn stats(&self) -> Option<SocketStats> {
+        Some(self.channels.lock().stats)
+    }
+}
+
+/// A handle to the packet queues of a `ChannelSocket`. Cloning the handle gives you another handle to the same queues, which you can also move to the thread of your transport.
+#[derive(Debug, Clone)]
+pub struct ChannelSocketHandle {
+    channels: Arc<Mutex<Channels>>,
+}
+
+impl ChannelSocketHandle {
+    /// Queues a packet received by your transport from the peer identified by `addr`. The session picks it up the next time it polls.
+    pub fn push_incoming(&self, addr: SocketAddr, packet: &[u8]) {
+        self.channels
+            .lock()
+            .incoming
+            .push_back((addr, packet.to_vec()));
+    }
+
+    /// Returns all packets the session sent since the last call, together with the peer they are addressed to. Each packet has to be delivered as a whole;
+    /// packets may be lost, duplicated or reordered, just like UDP datagrams.
+    pub fn take_outgoing(&self) -> Vec<(SocketAddr, Vec<u8>)> {
+        self.channels.lock().outgoing.drain(..).collect()
+    }
+}
+
+// #########
+// # TESTS #
+// #########
+
+#[cfg(test)]
+mod channel_socket_tests {
+
+    use super::*;
+    use crate::network::udp_msg::{MessageBody, MessageHeader};
+
+    #[test]
+    fn test_packets_pass_through_handles() {
+        let addr1: SocketAddr = "10.0.0.1:1".parse().unwrap();
+        let addr2: SocketAddr = "10.0.0.2:1".parse().unwrap();
+        let mut socket1 = ChannelSocket::new();
+        let mut socket2 = ChannelSocket::new();
+        let (handle1, handle2) = (socket1.handle(), socket2.handle());
+        let msg = UdpMessage {
+            header: MessageHeader {
+                magic: 1,
+                session_id: 0,
+                sequence_number: 0,
+            },
+            body: MessageBody::KeepAlive,
+        };
+
+        // the transport delivers everything socket 1 sends to socket 2
+        socket1.send_to(&msg, addr2);
+        for (to, packet) in handle1.take_outgoing() {
+            assert_eq!(to, addr2);
+            handle2.push_incoming(addr1, &packet);
+        }
+        handle2.push_incoming(addr1, &[1, 2, 3]);
+
+        assert_eq!(socket2.receive_all_messages(), vec![(addr1, msg)]);
+        assert!(handle1.take_outgoing().is_empty());
+        let stats = socket2.stats().unwrap();
+        assert_eq!(stats.packets_received, 2);
+        assert_eq!(stats.invalid_packets, 1);
+        assert_eq!(socket1.stats().unwrap().packets_sent, 1);
+    }
+}
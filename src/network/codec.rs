@@ -0,0 +1,75 @@
+use crate::network::udp_msg::UdpMessage;
+
+/// Encodes `UdpMessage`s to the bytes that are sent over the network and decodes received bytes back into messages.
+/// The sockets of GGRS use the `WireFormatCodec` by default, but you can swap in your own codec with `set_codec()`, e.g. to compress or encrypt
+/// the packets, to wrap them in the framing of an existing backend or to use any `serde` format. All peers of a session have to use the same codec.
+pub trait MessageCodec: std::fmt::Debug + Send {
+    /// Encodes the message to the bytes of a single packet.
+    fn encode(&self, msg: &UdpMessage) -> Vec<u8>;
+
+    /// Decodes the bytes of a single packet. Returns `None` if the bytes are not a valid message, in which case the packet is dropped.
+    fn decode(&self, bytes: &[u8]) -> Option<UdpMessage>;
+}
+
+/// Encodes messages in the stable, documented GGRS wire format with `UdpMessage::to_bytes()` and `UdpMessage::from_bytes()`. This is the default codec.
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq)]
+pub struct WireFormatCodec;
+
+impl MessageCodec for WireFormatCodec {
+    fn encode(&self, msg: &UdpMessage) -> Vec<u8> {
+        msg.to_bytes()
+    }
+
+    fn decode(&self, bytes: &[u8]) -> Option<UdpMessage> {
+        UdpMessage::from_bytes(bytes)
+    }
+}
+
+/// Encodes messages with `bincode`, which is how GGRS encoded its messages before it had a wire format of its own.
+/// The encoding follows the internal layout of the messages, so it may change between versions of GGRS.
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq)]
+pub struct BincodeCodec;
+
+impl MessageCodec for BincodeCodec {
+    fn encode(&self, msg: &UdpMessage) -> Vec<u8> {
+        bincode::serialize(msg).expect("serializing a message should never fail")
+    }
+
+    fn decode(&self, bytes: &[u8]) -> Option<UdpMessage> {
+        bincode::deserialize(bytes).ok()
+    }
+}
+
+// #########
+// # TESTS #
+// #########
+
+#[cfg(test)]
+mod codec_tests {
+
+    use super::*;
+    use crate::network::udp_msg::{InputAck, MessageBody, MessageHeader};
+    use crate::Frame;
+
+    #[test]
+    fn test_codecs_roundtrip() {
+        let msg = UdpMessage {
+            header: MessageHeader {
+                magic: 7,
+                session_id: 3,
+                sequence_number: 12,
+            },
+            body: MessageBody::InputAck(InputAck {
+                ack_frame: Frame(42),
+            }),
+        };
+        let codecs: Vec<Box<dyn MessageCodec>> =
+            vec![Box::new(WireFormatCodec), Box::new(BincodeCodec)];
+        for codec in &codecs {
+            let bytes = codec.encode(&msg);
+            assert_eq!(codec.decode(&bytes), Some(msg.clone()));
+            assert_eq!(codec.decode(&bytes[..bytes.len() - 1]), None);
+        }
+        assert_ne!(WireFormatCodec.encode(&msg), BincodeCodec.encode(&msg));
+    }
+}
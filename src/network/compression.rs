@@ -1,27 +1,57 @@
+use alloc::vec::Vec;
+
 use crate::{Frame, GameInput, NULL_FRAME};
 
+/// The maximum length of a varint in the RLE encoding we accept. Everything longer would not fit into an `u64`.
+const MAX_VARINT_BYTES: u32 = 9;
+
+/// What a sequence of inputs is delta-encoded against.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub(crate) enum Reference<'a> {
+    /// The inputs are encoded against zeroes, so they can be decoded without knowing any previous input.
+    Keyframe { input_size: usize },
+    /// The inputs continue right after this input and are encoded against it.
+    Input(&'a GameInput),
+}
+
+impl Reference<'_> {
+    fn input(self) -> GameInput {
+        match self {
+            Reference::Keyframe { input_size } => GameInput::new(NULL_FRAME, input_size),
+            Reference::Input(input) => *input,
+        }
+    }
+}
+
 pub(crate) fn encode<'a>(
-    reference: &GameInput,
+    reference: Reference,
     pending_input: impl Iterator<Item = &'a GameInput>,
 ) -> Vec<u8> {
     // first, do a XOR encoding to the reference input (will probably lead to a lot of same bits in sequence)
     let buf = delta_encode(reference, pending_input);
     // then, RLE encode the buffer (making use of the property mentioned above)
-    bitfield_rle::encode(buf)
+    rle_encode(&buf)
 }
 
 pub(crate) fn delta_encode<'a>(
-    reference: &GameInput,
+    reference: Reference,
     pending_input: impl Iterator<Item = &'a GameInput>,
 ) -> Vec<u8> {
-    let ref_bytes = reference.input();
+    let reference_input = reference.input();
+    let ref_bytes = reference_input.input();
     let (lower, upper) = pending_input.size_hint();
-    let capacity = upper.unwrap_or(lower) * reference.size;
+    let capacity = upper.unwrap_or(lower) * reference_input.size;
     let mut bytes = Vec::with_capacity(capacity);
 
-    for (i, input) in pending_input.enumerate() {
-        assert_eq!(input.size, reference.size);
-        assert!(reference.frame == NULL_FRAME || input.frame == reference.frame + i as i32 + 1);
+    // the inputs have to be consecutive, and continue right after a reference input
+    let mut next_frame = match reference {
+        Reference::Keyframe { .. } => None,
+        Reference::Input(input) => Some(input.frame + 1),
+    };
+    for input in pending_input {
+        assert_eq!(input.size, reference_input.size);
+        assert!(next_frame.is_none() || next_frame == Some(input.frame));
+        next_frame = Some(input.frame + 1);
         let input_bytes = input.input();
         for (b1, b2) in ref_bytes.iter().zip(input_bytes.iter()) {
             bytes.push(b1 ^ b2);
@@ -30,23 +60,201 @@ pub(crate) fn delta_encode<'a>(
     bytes
 }
 
+/// Merges consecutive equal inputs into a single input and returns the merged inputs together with the number of frames each of them stands for.
+/// The merged inputs get consecutive frames from the frame of the first input on, so they can be encoded like any other inputs.
+pub(crate) fn collapse_runs<'a>(
+    inputs: impl Iterator<Item = &'a GameInput>,
+) -> (Vec<GameInput>, Vec<u16>) {
+    let mut merged: Vec<GameInput> = Vec::new();
+    let mut runs: Vec<u16> = Vec::new();
+    for input in inputs {
+        match (merged.last(), runs.last_mut()) {
+            (Some(last), Some(run)) if last.input() == input.input() && *run < u16::MAX => {
+                *run += 1;
+            }
+            _ => {
+                let mut input = *input;
+                if let Some(first) = merged.first() {
+                    input.frame = first.frame + merged.len() as i32;
+                }
+                merged.push(input);
+                runs.push(1);
+            }
+        }
+    }
+    (merged, runs)
+}
+
+/// Reverts `collapse_runs()`. The runs come from the network, so `None` is returned if they do not match the inputs or stand for more than `max_frames` frames.
+pub(crate) fn expand_runs(
+    inputs: &[GameInput],
+    runs: &[u16],
+    max_frames: usize,
+) -> Option<Vec<GameInput>> {
+    if runs.len() != inputs.len()
+        || runs.contains(&0)
+        || runs.iter().map(|run| *run as usize).sum::<usize>() > max_frames
+    {
+        return None;
+    }
+    let mut expanded = Vec::new();
+    for (input, run) in inputs.iter().zip(runs) {
+        for _ in 0..*run {
+            let mut input = *input;
+            input.frame = inputs[0].frame + expanded.len() as i32;
+            expanded.push(input);
+        }
+    }
+    Some(expanded)
+}
+
+/// Decodes inputs encoded with `encode()`. The data comes from the network, so malformed data or data decoding to more than `max_len` bytes is rejected
+/// before anything is allocated.
 pub(crate) fn decode(
-    reference: &GameInput,
+    reference: Reference,
     start_frame: Frame,
     data: impl AsRef<[u8]>,
-) -> Result<Vec<GameInput>, Box<dyn std::error::Error>> {
+    max_len: usize,
+) -> Result<Vec<GameInput>, &'static str> {
+    let data = data.as_ref();
+    let len = decoded_len(data, max_len).ok_or("malformed or oversized input data")?;
+    if len % reference.input().size != 0 {
+        return Err("input data does not match the input size");
+    }
+
     // decode the RLE encoding first
-    let buf = bitfield_rle::decode(data)?;
+    let buf = rle_decode(data, len);
 
     // decode the delta-encoding
     Ok(delta_decode(reference, start_frame, &buf))
 }
 
+/// RLE encodes the buffer in the format of the `bitfield-rle` crate: runs of `0x00` or `0xFF` bytes are stored as their length,
+/// everything in between is stored as literal bytes. Each run starts with a varint, which is `(len << 2) + 1` for a run of `0x00`,
+/// `(len << 2) + 3` for a run of `0xFF` and `len << 1` for `len` literal bytes.
+fn rle_encode(buf: &[u8]) -> Vec<u8> {
+    let mut enc = Vec::new();
+    let mut literals: Vec<u8> = Vec::new();
+    let mut run: Option<(u8, u64)> = None;
+
+    for byte in buf {
+        match &mut run {
+            Some((bits, len)) if bits == byte => {
+                *len += 1;
+                continue;
+            }
+            Some((bits, len)) => write_varint(&mut enc, contiguous_varint(*bits, *len)),
+            None => (),
+        }
+        run = None;
+        if *byte == 0 || *byte == 255 {
+            if !literals.is_empty() {
+                write_literals(&mut enc, &mut literals);
+            }
+            run = Some((*byte, 1));
+        } else {
+            literals.push(*byte);
+        }
+    }
+
+    match run {
+        Some((bits, len)) => write_varint(&mut enc, contiguous_varint(bits, len)),
+        // like `bitfield-rle`, this also writes an empty literal run for an empty buffer
+        None => write_literals(&mut enc, &mut literals),
+    }
+    enc
+}
+
+const fn contiguous_varint(bits: u8, len: u64) -> u64 {
+    if bits == 255 {
+        (len << 2) + 3
+    } else {
+        (len << 2) + 1
+    }
+}
+
+fn write_literals(enc: &mut Vec<u8>, literals: &mut Vec<u8>) {
+    write_varint(enc, (literals.len() as u64) << 1);
+    enc.append(literals);
+}
+
+fn write_varint(enc: &mut Vec<u8>, mut value: u64) {
+    while value >= 128 {
+        enc.push((value & 127) as u8 | 128);
+        value >>= 7;
+    }
+    enc.push(value as u8);
+}
+
+/// Reads a varint at `offset` and moves the offset past it. Returns `None` if the data ends or the varint is too long.
+fn read_varint(data: &[u8], offset: &mut usize) -> Option<u64> {
+    let mut value: u64 = 0;
+    let mut num_bytes = 0;
+    loop {
+        let byte = *data.get(*offset)?;
+        *offset += 1;
+        value |= ((byte & 127) as u64) << (7 * num_bytes);
+        num_bytes += 1;
+        if byte & 128 == 0 {
+            return Some(value);
+        }
+        // longer varints could overflow while decoding
+        if num_bytes == MAX_VARINT_BYTES {
+            return None;
+        }
+    }
+}
+
+/// Returns the length of the RLE encoded data once decoded, or `None` if the data is malformed or longer than `max_len`.
+/// The data comes from the network, so we walk the encoding once before allocating anything.
+fn decoded_len(data: &[u8], max_len: usize) -> Option<usize> {
+    let mut offset = 0;
+    let mut len: usize = 0;
+
+    while offset < data.len() {
+        let next = read_varint(data, &mut offset)?;
+        let repeat = next & 1 > 0;
+        let slice = if repeat { next >> 2 } else { next >> 1 } as usize;
+        len = len.checked_add(slice)?;
+        if len > max_len {
+            return None;
+        }
+        // literal bytes have to be part of the data
+        if !repeat {
+            offset = offset.checked_add(slice)?;
+            if offset > data.len() {
+                return None;
+            }
+        }
+    }
+    Some(len)
+}
+
+/// Reverts `rle_encode()`. The data has to be validated with `decoded_len()` first, which returned `len`.
+fn rle_decode(data: &[u8], len: usize) -> Vec<u8> {
+    let mut buf = Vec::with_capacity(len);
+    let mut offset = 0;
+
+    while offset < data.len() {
+        let next = read_varint(data, &mut offset).expect("data has been validated");
+        if next & 1 > 0 {
+            let bits = if next & 2 > 0 { 255 } else { 0 };
+            buf.resize(buf.len() + (next >> 2) as usize, bits);
+        } else {
+            let slice = (next >> 1) as usize;
+            buf.extend_from_slice(&data[offset..offset + slice]);
+            offset += slice;
+        }
+    }
+    buf
+}
+
 pub(crate) fn delta_decode(
-    reference: &GameInput,
+    reference: Reference,
     start_frame: Frame,
     data: &[u8],
 ) -> Vec<GameInput> {
+    let reference = reference.input();
     assert!(data.len() % reference.size == 0);
     let out_size = data.len() / reference.size;
     let mut output = Vec::with_capacity(out_size);
@@ -73,19 +281,116 @@ mod compression_tests {
     #[test]
     fn test_encode_decode() {
         let size = 4;
-        let mut ref_input = GameInput::new(5, size);
+        let mut ref_input = GameInput::new(Frame(5), size);
         ref_input.buffer[3] = 1;
-        let inp0 = GameInput::new(6, size);
-        let inp1 = GameInput::new(7, size);
-        let inp2 = GameInput::new(8, size);
-        let inp3 = GameInput::new(9, size);
-        let inp4 = GameInput::new(10, size);
+        let inp0 = GameInput::new(Frame(6), size);
+        let inp1 = GameInput::new(Frame(7), size);
+        let inp2 = GameInput::new(Frame(8), size);
+        let inp3 = GameInput::new(Frame(9), size);
+        let inp4 = GameInput::new(Frame(10), size);
 
         let pend_inp = vec![inp0, inp1, inp2, inp3, inp4];
 
-        let encoded = encode(&ref_input, pend_inp.iter());
-        let decoded = decode(&ref_input, 6, encoded).unwrap();
+        let encoded = encode(Reference::Input(&ref_input), pend_inp.iter());
+        let decoded = decode(Reference::Input(&ref_input), Frame(6), encoded, 1024).unwrap();
 
         assert!(pend_inp == decoded);
     }
+
+    #[test]
+    fn test_keyframe_ignores_previous_inputs() {
+        let size = 2;
+        let mut inputs = [
+            GameInput::new(Frame(6), size),
+            GameInput::new(Frame(7), size),
+        ];
+        inputs[0].copy_input(&[1, 2]);
+        inputs[1].copy_input(&[1, 3]);
+        let keyframe = Reference::Keyframe { input_size: size };
+
+        // a keyframe is encoded against zeroes and does not need to continue any previous input
+        let encoded = encode(keyframe, inputs.iter());
+        assert_eq!(delta_encode(keyframe, inputs.iter()), vec![1, 2, 1, 3]);
+        assert_eq!(decode(keyframe, Frame(6), &encoded, 1024).unwrap(), inputs);
+        let mut reference = GameInput::new(Frame(5), size);
+        reference.copy_input(&[1, 2]);
+        assert_ne!(encode(Reference::Input(&reference), inputs.iter()), encoded);
+    }
+
+    #[test]
+    #[should_panic]
+    fn test_encode_requires_consecutive_frames() {
+        let size = 1;
+        let inputs = [
+            GameInput::new(Frame(6), size),
+            GameInput::new(Frame(8), size),
+        ];
+        encode(Reference::Keyframe { input_size: size }, inputs.iter());
+    }
+
+    #[test]
+    fn test_decode_malformed() {
+        let size = 4;
+        let ref_input = GameInput::new(Frame(5), size);
+        let pend_inp = [
+            GameInput::new(Frame(6), size),
+            GameInput::new(Frame(7), size),
+        ];
+        let reference = Reference::Input(&ref_input);
+        let encoded = encode(reference, pend_inp.iter());
+
+        // more than we are willing to decode
+        assert!(decode(reference, Frame(6), &encoded, size).is_err());
+        // a run claiming to be gigantic
+        assert!(decode(reference, Frame(6), [0xFF, 0xFF, 0xFF, 0xFF, 0x0F], 1024).is_err());
+        // a varint that never ends
+        assert!(decode(reference, Frame(6), [0xFF; 12], usize::MAX).is_err());
+        // literal bytes missing
+        assert!(decode(reference, Frame(6), [8, 1], 1024).is_err());
+        // not a multiple of the input size
+        assert!(decode(reference, Frame(6), [7], 1024).is_err());
+    }
+
+    #[test]
+    fn test_rle_matches_bitfield_rle() {
+        let buffers: [&[u8]; 7] = [
+            &[],
+            &[0],
+            &[1, 2, 3],
+            &[0, 0, 255, 255, 255, 7, 0, 9, 9],
+            &[5, 0, 0, 0, 6, 255],
+            &[0; 200],
+            &[3; 200],
+        ];
+        for buf in &buffers {
+            let encoded = rle_encode(buf);
+            assert_eq!(encoded, bitfield_rle::encode(buf));
+            let len = decoded_len(&encoded, usize::MAX).unwrap();
+            assert_eq!(rle_decode(&encoded, len), *buf);
+        }
+    }
+
+    #[test]
+    fn test_collapse_expand_runs() {
+        let size = 1;
+        let mut inputs = Vec::new();
+        for (i, byte) in [1u8, 1, 1, 2, 1, 1].iter().enumerate() {
+            let mut input = GameInput::new(Frame(10 + i as i64), size);
+            input.copy_input(&[*byte]);
+            inputs.push(input);
+        }
+
+        let (merged, runs) = collapse_runs(inputs.iter());
+        assert_eq!(runs, vec![3, 1, 2]);
+        assert_eq!(
+            merged.iter().map(|input| input.frame).collect::<Vec<_>>(),
+            vec![Frame(10), Frame(11), Frame(12)]
+        );
+        assert_eq!(expand_runs(&merged, &runs, 6), Some(inputs));
+
+        // runs that do not match the inputs
+        assert!(expand_runs(&merged, &runs, 5).is_none());
+        assert!(expand_runs(&merged, &[3, 1], 6).is_none());
+        assert!(expand_runs(&merged, &[3, 0, 2], 6).is_none());
+    }
 }
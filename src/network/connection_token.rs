@@ -0,0 +1,118 @@
+/// A secret shared by all peers of a match. During synchronization, every peer proves that it knows the token by answering the random request of the other peer
+/// with a SipHash-2-4 MAC keyed with the token, so strangers who only know the address of a peer never pass synchronization.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct ConnectionToken {
+    k0: u64,
+    k1: u64,
+}
+
+impl ConnectionToken {
+    /// Derives the key from a token of any length.
+    pub(crate) fn new(token: &[u8]) -> Self {
+        let mut first = token.to_vec();
+        first.push(0);
+        let mut second = token.to_vec();
+        second.push(1);
+        Self {
+            k0: siphash(0, 0, &first),
+            k1: siphash(0, 0, &second),
+        }
+    }
+
+    /// Returns the proof for the given random request, answered by the endpoint with the given magic.
+    pub(crate) fn proof(&self, random_request: u32, magic: u16) -> u64 {
+        let mut msg = random_request.to_le_bytes().to_vec();
+        msg.extend_from_slice(&magic.to_le_bytes());
+        siphash(self.k0, self.k1, &msg)
+    }
+}
+
+/// SipHash-2-4 as specified by Aumasson and Bernstein.
+pub(crate) fn siphash(k0: u64, k1: u64, msg: &[u8]) -> u64 {
+    let mut v = [
+        k0 ^ 0x736f_6d65_7073_6575,
+        k1 ^ 0x646f_7261_6e64_6f6d,
+        k0 ^ 0x6c79_6765_6e65_7261,
+        k1 ^ 0x7465_6462_7974_6573,
+    ];
+
+    let chunks = msg.chunks_exact(8);
+    let remainder = chunks.remainder();
+    for chunk in chunks {
+        let mut word = [0; 8];
+        word.copy_from_slice(chunk);
+        compress(&mut v, u64::from_le_bytes(word), 2);
+    }
+
+    // the last word contains the remaining bytes and the message length
+    let mut last = [0; 8];
+    last[..remainder.len()].copy_from_slice(remainder);
+    last[7] = msg.len() as u8;
+    compress(&mut v, u64::from_le_bytes(last), 2);
+
+    v[2] ^= 0xff;
+    for _ in 0..4 {
+        round(&mut v);
+    }
+    v[0] ^ v[1] ^ v[2] ^ v[3]
+}
+
+fn compress(v: &mut [u64; 4], word: u64, rounds: usize) {
+    v[3] ^= word;
+    for _ in 0..rounds {
+        round(v);
+    }
+    v[0] ^= word;
+}
+
+fn round(v: &mut [u64; 4]) {
+    v[0] = v[0].wrapping_add(v[1]);
+    v[1] = v[1].rotate_left(13);
+    v[1] ^= v[0];
+    v[0] = v[0].rotate_left(32);
+    v[2] = v[2].wrapping_add(v[3]);
+    v[3] = v[3].rotate_left(16);
+    v[3] ^= v[2];
+    v[0] = v[0].wrapping_add(v[3]);
+    v[3] = v[3].rotate_left(21);
+    v[3] ^= v[0];
+    v[2] = v[2].wrapping_add(v[1]);
+    v[1] = v[1].rotate_left(17);
+    v[1] ^= v[2];
+    v[2] = v[2].rotate_left(32);
+}
+
+// #########
+// # TESTS #
+// #########
+
+#[cfg(test)]
+mod connection_token_tests {
+
+    use super::*;
+
+    #[test]
+    fn test_siphash_reference_vectors() {
+        // key 00 01 02 ... 0f, messages 00 01 02 ... from the reference implementation
+        let k0 = u64::from_le_bytes([0, 1, 2, 3, 4, 5, 6, 7]);
+        let k1 = u64::from_le_bytes([8, 9, 10, 11, 12, 13, 14, 15]);
+        let msg: Vec<u8> = (0..15).collect();
+        assert_eq!(siphash(k0, k1, &[]), 0x726f_db47_dd0e_0e31);
+        assert_eq!(siphash(k0, k1, &msg), 0xa129_ca61_49be_45e5);
+    }
+
+    #[test]
+    fn test_proof() {
+        let token = ConnectionToken::new(b"secret");
+        assert_eq!(
+            token.proof(42, 7),
+            ConnectionToken::new(b"secret").proof(42, 7)
+        );
+        assert_ne!(
+            token.proof(42, 7),
+            ConnectionToken::new(b"secreT").proof(42, 7)
+        );
+        assert_ne!(token.proof(42, 7), token.proof(43, 7));
+        assert_ne!(token.proof(42, 7), token.proof(42, 8));
+    }
+}
@@ -0,0 +1,498 @@
+//! NAT traversal through a rendezvous server. Before the session starts, every player registers at the server with a match id.
+//! Once all players of the match have registered, the server tells every player the public addresses of all other players,
+//! and the players send punch packets to each other until they have heard from every peer. Since both sides send at the same time,
+//! the NATs of both sides expect the incoming packets, so the direct connection works even if both players are behind a NAT.
+//!
+//! The packets start with the magic `GGHP` and a type byte, followed by the fields in little-endian:
+//!
+//! - `0` register (player to server): `match_id: u64`, `player_handle: u32`, `num_players: u32`
+//! - `1` peers (server to player): `match_id: u64`, `count: u8`, then for every player `player_handle: u32` and its address
+//! - `2` punch (player to player): `match_id: u64`, `player_handle: u32`, `heard: bool`, whether the sender has heard from the receiver
+//!
+//! Addresses are encoded as `4`, four octets and `port: u16` or as `6`, sixteen octets and `port: u16`.
+
+use std::collections::HashMap;
+use std::convert::TryInto;
+use std::io::{Error, ErrorKind};
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr, ToSocketAddrs, UdpSocket};
+use std::time::{Duration, Instant};
+
+use crate::network::wire_format::Reader;
+use crate::{GGRSError, P2PSession, PlayerHandle, PlayerType, UdpNonBlockingSocket};
+
+const MAGIC: [u8; 4] = *b"GGHP";
+const RECV_BUFFER_SIZE: usize = 1024;
+const DEFAULT_TIMEOUT: Duration = Duration::from_secs(10);
+const DEFAULT_RESEND_INTERVAL: Duration = Duration::from_millis(100);
+const POLL_INTERVAL: Duration = Duration::from_millis(1);
+/// After a player has heard from all peers, it sends this many final punches, so the peers still waiting for an answer receive one.
+const FINAL_PUNCHES: usize = 3;
+/// The rendezvous server forgets matches that had no registrations for this long.
+const MATCH_LIFETIME: Duration = Duration::from_secs(60);
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Packet {
+    Register {
+        match_id: u64,
+        player_handle: PlayerHandle,
+        num_players: u32,
+    },
+    Peers {
+        match_id: u64,
+        peers: Vec<(PlayerHandle, SocketAddr)>,
+    },
+    Punch {
+        match_id: u64,
+        player_handle: PlayerHandle,
+        heard: bool,
+    },
+}
+
+impl Packet {
+    fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = MAGIC.to_vec();
+        match self {
+            Packet::Register {
+                match_id,
+                player_handle,
+                num_players,
+            } => {
+                bytes.push(0);
+                bytes.extend_from_slice(&match_id.to_le_bytes());
+                bytes.extend_from_slice(&(player_handle.0 as u32).to_le_bytes());
+                bytes.extend_from_slice(&num_players.to_le_bytes());
+            }
+            Packet::Peers { match_id, peers } => {
+                bytes.push(1);
+                bytes.extend_from_slice(&match_id.to_le_bytes());
+                bytes.push(peers.len() as u8);
+                for (handle, addr) in peers {
+                    bytes.extend_from_slice(&(handle.0 as u32).to_le_bytes());
+                    match addr.ip() {
+                        IpAddr::V4(ip) => {
+                            bytes.push(4);
+                            bytes.extend_from_slice(&ip.octets());
+                        }
+                        IpAddr::V6(ip) => {
+                            bytes.push(6);
+                            bytes.extend_from_slice(&ip.octets());
+                        }
+                    }
+                    bytes.extend_from_slice(&addr.port().to_le_bytes());
+                }
+            }
+            Packet::Punch {
+                match_id,
+                player_handle,
+                heard,
+            } => {
+                bytes.push(2);
+                bytes.extend_from_slice(&match_id.to_le_bytes());
+                bytes.extend_from_slice(&(player_handle.0 as u32).to_le_bytes());
+                bytes.push(*heard as u8);
+            }
+        }
+        bytes
+    }
+
+    fn from_bytes(bytes: &[u8]) -> Option<Self> {
+        let mut reader = Reader { bytes };
+        if reader.take(MAGIC.len())? != MAGIC {
+            return None;
+        }
+        let packet = match reader.u8()? {
+            0 => Packet::Register {
+                match_id: reader.u64()?,
+                player_handle: PlayerHandle(reader.u32()? as usize),
+                num_players: reader.u32()?,
+            },
+            1 => {
+                let match_id = reader.u64()?;
+                let count = reader.u8()?;
+                let mut peers = Vec::with_capacity(count as usize);
+                for _ in 0..count {
+                    let handle = PlayerHandle(reader.u32()? as usize);
+                    let ip = match reader.u8()? {
+                        4 => {
+                            let octets: [u8; 4] = reader.take(4)?.try_into().ok()?;
+                            IpAddr::V4(Ipv4Addr::from(octets))
+                        }
+                        6 => {
+                            let octets: [u8; 16] = reader.take(16)?.try_into().ok()?;
+                            IpAddr::V6(Ipv6Addr::from(octets))
+                        }
+                        _ => return None,
+                    };
+                    peers.push((handle, SocketAddr::new(ip, reader.u16()?)));
+                }
+                Packet::Peers { match_id, peers }
+            }
+            2 => Packet::Punch {
+                match_id: reader.u64()?,
+                player_handle: PlayerHandle(reader.u32()? as usize),
+                heard: reader.bool()?,
+            },
+            _ => return None,
+        };
+        // trailing bytes are invalid
+        if !reader.bytes.is_empty() {
+            return None;
+        }
+        Some(packet)
+    }
+}
+
+/// Performs the hole punching for a single player. Bind it to the port you want to use for the session, configure it and call `punch()`.
+#[derive(Debug)]
+pub struct HolePuncher {
+    socket: UdpSocket,
+    server_addr: SocketAddr,
+    match_id: u64,
+    local_handle: PlayerHandle,
+    num_players: u32,
+    timeout: Duration,
+    resend_interval: Duration,
+}
+
+impl HolePuncher {
+    /// Creates a hole puncher for the local player `local_handle` of a match with `num_players` players. All players of the match need to use
+    /// the same `match_id` and rendezvous server. The socket is bound to the given port on all IPv4 interfaces.
+    /// # Errors
+    /// - Will return an `std::io::Error` if the socket could not be bound or the server address could not be resolved.
+    pub fn bind_to_port(
+        port: u16,
+        server_addr: impl ToSocketAddrs,
+        match_id: u64,
+        local_handle: PlayerHandle,
+        num_players: u32,
+    ) -> Result<Self, Error> {
+        let server_addr = server_addr
+            .to_socket_addrs()?
+            .next()
+            .ok_or_else(|| Error::new(ErrorKind::InvalidInput, "no rendezvous server address"))?;
+        let addr = SocketAddr::new(IpAddr::V4(Ipv4Addr::new(0, 0, 0, 0)), port);
+        let socket = UdpSocket::bind(addr)?;
+        socket.set_nonblocking(true)?;
+        Ok(Self {
+            socket,
+            server_addr,
+            match_id,
+            local_handle,
+            num_players,
+            timeout: DEFAULT_TIMEOUT,
+            resend_interval: DEFAULT_RESEND_INTERVAL,
+        })
+    }
+
+    /// Sets the time after which `punch()` gives up. The default is 10 seconds.
+    pub fn set_timeout(&mut self, timeout: Duration) {
+        self.timeout = timeout;
+    }
+
+    /// Sets the interval in which registrations and punches are resent. The default is 100 ms.
+    pub fn set_resend_interval(&mut self, interval: Duration) {
+        self.resend_interval = interval;
+    }
+
+    /// Registers at the rendezvous server and punches holes to all other players of the match. Blocks until a direct connection to every peer
+    /// has been established in both directions or the timeout has passed.
+    /// # Errors
+    /// - Will return an `std::io::Error` of kind `TimedOut` if not all peers could be reached in time.
+    /// - Will return an `std::io::Error` if sending or receiving failed.
+    pub fn punch(self) -> Result<PunchedHoles, Error> {
+        let deadline = Instant::now() + self.timeout;
+        let mut next_send = Instant::now();
+        let mut buffer = [0; RECV_BUFFER_SIZE];
+        // the addresses announced by the server and the addresses we actually received punches from
+        let mut announced: Option<HashMap<PlayerHandle, SocketAddr>> = None;
+        let mut heard_from: HashMap<PlayerHandle, SocketAddr> = HashMap::new();
+        let mut heard_by: HashMap<PlayerHandle, bool> = HashMap::new();
+
+        loop {
+            if let Some(announced) = &announced {
+                let done = announced.keys().all(|handle| {
+                    heard_from.contains_key(handle) && heard_by.get(handle) == Some(&true)
+                });
+                if done {
+                    for _ in 0..FINAL_PUNCHES {
+                        self.send_punches(announced, &heard_from)?;
+                    }
+                    ggrs_info!("Punched holes to all peers: {:?}", heard_from);
+                    return Ok(PunchedHoles {
+                        socket: UdpNonBlockingSocket::from_socket(self.socket)?,
+                        local_handle: self.local_handle,
+                        num_players: self.num_players,
+                        peers: heard_from,
+                    });
+                }
+            }
+
+            let now = Instant::now();
+            if now >= deadline {
+                return Err(Error::new(
+                    ErrorKind::TimedOut,
+                    "could not reach all peers in time",
+                ));
+            }
+            if now >= next_send {
+                if let Some(announced) = &announced {
+                    self.send_punches(announced, &heard_from)?;
+                } else {
+                    self.send(
+                        &Packet::Register {
+                            match_id: self.match_id,
+                            player_handle: self.local_handle,
+                            num_players: self.num_players,
+                        },
+                        self.server_addr,
+                    )?;
+                }
+                next_send = now + self.resend_interval;
+            }
+
+            loop {
+                let (len, from) = match self.socket.recv_from(&mut buffer) {
+                    Ok(received) => received,
+                    Err(ref err) if err.kind() == ErrorKind::WouldBlock => break,
+                    // some platforms report unreachable peers this way
+                    Err(ref err) if err.kind() == ErrorKind::ConnectionReset => break,
+                    Err(err) => return Err(err),
+                };
+                match Packet::from_bytes(&buffer[..len]) {
+                    Some(Packet::Peers { match_id, peers })
+                        if match_id == self.match_id && from == self.server_addr =>
+                    {
+                        let peers: HashMap<_, _> = peers
+                            .into_iter()
+                            .filter(|(handle, _)| *handle != self.local_handle)
+                            .collect();
+                        if peers.len() + 1 == self.num_players as usize {
+                            announced = Some(peers);
+                        }
+                    }
+                    Some(Packet::Punch {
+                        match_id,
+                        player_handle,
+                        heard,
+                    }) if match_id == self.match_id
+                        && player_handle != self.local_handle
+                        && player_handle < self.num_players as usize =>
+                    {
+                        heard_from.insert(player_handle, from);
+                        *heard_by.entry(player_handle).or_insert(false) |= heard;
+                    }
+                    _ => {
+                        ggrs_warn!("Ignoring unexpected packet from {}", from);
+                    }
+                }
+            }
+
+            std::thread::sleep(POLL_INTERVAL);
+        }
+    }
+
+    fn send_punches(
+        &self,
+        announced: &HashMap<PlayerHandle, SocketAddr>,
+        heard_from: &HashMap<PlayerHandle, SocketAddr>,
+    ) -> Result<(), Error> {
+        for (handle, addr) in announced {
+            let punch = Packet::Punch {
+                match_id: self.match_id,
+                player_handle: self.local_handle,
+                heard: heard_from.contains_key(handle),
+            };
+            self.send(&punch, *addr)?;
+            // if the punches of the peer arrive from a different address than announced, answer there as well
+            if let Some(heard_addr) = heard_from.get(handle) {
+                if heard_addr != addr {
+                    self.send(&punch, *heard_addr)?;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn send(&self, packet: &Packet, addr: SocketAddr) -> Result<(), Error> {
+        match self.socket.send_to(&packet.to_bytes(), addr) {
+            Ok(_) => Ok(()),
+            // a full send buffer only loses this packet, it is resent later anyway
+            Err(ref err) if err.kind() == ErrorKind::WouldBlock => Ok(()),
+            Err(err) => Err(err),
+        }
+    }
+}
+
+/// The result of successful hole punching: the socket that has been used for it and the address of every other player.
+/// The session has to use this socket, since the holes are only open for its port.
+#[derive(Debug)]
+pub struct PunchedHoles {
+    /// The socket the holes have been punched for.
+    pub socket: UdpNonBlockingSocket,
+    /// The handle of the local player.
+    pub local_handle: PlayerHandle,
+    /// The number of players in the match.
+    pub num_players: u32,
+    /// The address of every other player, as seen from this player.
+    pub peers: HashMap<PlayerHandle, SocketAddr>,
+}
+
+impl PunchedHoles {
+    /// Creates a `P2PSession` on the punched socket and adds the local player and all remote players. You can configure the session further
+    /// and then start it with `start_session()`.
+    /// # Errors
+    /// - Will return a `InvalidRequest` if the number of players or `input_size` are too high, see `start_p2p_session_with_socket()`.
+    pub fn start_p2p_session(self, input_size: usize) -> Result<P2PSession, GGRSError> {
+        let mut sess =
+            crate::start_p2p_session_with_socket(self.num_players, input_size, self.socket)?;
+        sess.add_player(PlayerType::Local, self.local_handle)?;
+        for (handle, addr) in self.peers {
+            sess.add_player(PlayerType::Remote(addr), handle)?;
+        }
+        Ok(sess)
+    }
+}
+
+#[derive(Debug)]
+struct Rendezvous {
+    num_players: u32,
+    players: HashMap<PlayerHandle, SocketAddr>,
+    last_registration: Instant,
+}
+
+/// A minimal rendezvous server for `HolePuncher`. It needs to be reachable by all players, so it usually runs on a public server.
+/// Call `poll()` regularly to answer registrations.
+#[derive(Debug)]
+pub struct RendezvousServer {
+    socket: UdpSocket,
+    matches: HashMap<u64, Rendezvous>,
+    buffer: [u8; RECV_BUFFER_SIZE],
+}
+
+impl RendezvousServer {
+    /// Binds the server to the given port on all IPv4 interfaces.
+    /// # Errors
+    /// - Will return an `std::io::Error` if the socket could not be bound.
+    pub fn bind_to_port(port: u16) -> Result<Self, Error> {
+        let addr = SocketAddr::new(IpAddr::V4(Ipv4Addr::new(0, 0, 0, 0)), port);
+        let socket = UdpSocket::bind(addr)?;
+        socket.set_nonblocking(true)?;
+        Ok(Self {
+            socket,
+            matches: HashMap::new(),
+            buffer: [0; RECV_BUFFER_SIZE],
+        })
+    }
+
+    /// Handles all received registrations. Once all players of a match have registered, every registration is answered with the addresses
+    /// of all players. Matches without registrations for a minute are forgotten.
+    /// # Errors
+    /// - Will return an `std::io::Error` if sending or receiving failed.
+    pub fn poll(&mut self) -> Result<(), Error> {
+        let now = Instant::now();
+        self.matches
+            .retain(|_, rendezvous| now - rendezvous.last_registration < MATCH_LIFETIME);
+
+        loop {
+            let (len, from) = match self.socket.recv_from(&mut self.buffer) {
+                Ok(received) => received,
+                Err(ref err) if err.kind() == ErrorKind::WouldBlock => return Ok(()),
+                Err(ref err) if err.kind() == ErrorKind::ConnectionReset => continue,
+                Err(err) => return Err(err),
+            };
+            let (match_id, player_handle, num_players) =
+                match Packet::from_bytes(&self.buffer[..len]) {
+                    Some(Packet::Register {
+                        match_id,
+                        player_handle,
+                        num_players,
+                    }) => (match_id, player_handle, num_players),
+                    _ => {
+                        ggrs_warn!("Ignoring unexpected packet from {}", from);
+                        continue;
+                    }
+                };
+
+            let rendezvous = self.matches.entry(match_id).or_insert_with(|| Rendezvous {
+                num_players,
+                players: HashMap::new(),
+                last_registration: now,
+            });
+            // players of the same match have to agree on the number of players
+            if rendezvous.num_players != num_players || player_handle >= num_players as usize {
+                continue;
+            }
+            rendezvous.players.insert(player_handle, from);
+            rendezvous.last_registration = now;
+
+            if rendezvous.players.len() == num_players as usize {
+                let mut peers: Vec<_> = rendezvous
+                    .players
+                    .iter()
+                    .map(|(handle, addr)| (*handle, *addr))
+                    .collect();
+                peers.sort_unstable_by_key(|(handle, _)| *handle);
+                let reply = Packet::Peers { match_id, peers }.to_bytes();
+                match self.socket.send_to(&reply, from) {
+                    Ok(_) => (),
+                    Err(ref err) if err.kind() == ErrorKind::WouldBlock => (),
+                    Err(err) => return Err(err),
+                }
+            }
+        }
+    }
+}
+
+// #########
+// # TESTS #
+// #########
+
+#[cfg(test)]
+mod hole_punch_tests {
+
+    use super::*;
+
+    #[test]
+    fn test_packet_roundtrip() {
+        let packets = [
+            Packet::Register {
+                match_id: 42,
+                player_handle: PlayerHandle(1),
+                num_players: 2,
+            },
+            Packet::Peers {
+                match_id: 42,
+                peers: vec![
+                    (PlayerHandle(0), "127.0.0.1:7777".parse().unwrap()),
+                    (PlayerHandle(1), "[::1]:8888".parse().unwrap()),
+                ],
+            },
+            Packet::Punch {
+                match_id: 42,
+                player_handle: PlayerHandle(0),
+                heard: true,
+            },
+        ];
+        for packet in packets.iter() {
+            assert_eq!(
+                Packet::from_bytes(&packet.to_bytes()).as_ref(),
+                Some(packet)
+            );
+        }
+    }
+
+    #[test]
+    fn test_invalid_packets() {
+        let mut bytes = Packet::Punch {
+            match_id: 42,
+            player_handle: PlayerHandle(0),
+            heard: true,
+        }
+        .to_bytes();
+        assert!(Packet::from_bytes(&bytes[..bytes.len() - 1]).is_none());
+        bytes.push(0);
+        assert!(Packet::from_bytes(&bytes).is_none());
+        assert!(Packet::from_bytes(b"GGHP\x07").is_none());
+        assert!(Packet::from_bytes(b"ABCD\x00").is_none());
+    }
+}
@@ -0,0 +1,136 @@
+use crate::GameInput;
+
+/// The `InputStats` struct tells you how well the inputs of your game compress. GGRS XORs every input with the last acknowledged input
+/// and run-length encodes the result, so inputs where few bytes change from frame to frame compress best. Use `byte_changes` to find the bytes of your
+/// input struct that change most often, e.g. to move analog values next to each other or to pack rarely changing buttons into the same bytes.
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct InputStats {
+    /// The number of input packets sent since the session was started.
+    pub packets_sent: usize,
+    /// The average number of bytes the encoded inputs of an input packet take up.
+    pub average_encoded_size: f32,
+    /// The size of the encoded inputs divided by the size of the raw inputs they contain. Lower is better.
+    pub compression_ratio: f32,
+    /// The number of local inputs analyzed since the analysis has been enabled.
+    pub inputs_analyzed: usize,
+    /// For every byte of the input, the number of analyzed local inputs in which that byte differs from the previous input.
+    pub byte_changes: Vec<usize>,
+}
+
+impl InputStats {
+    /// Returns the indices of all input bytes, ordered from the most to the least often changed byte.
+    pub fn most_changed_bytes(&self) -> Vec<usize> {
+        let mut bytes: Vec<usize> = (0..self.byte_changes.len()).collect();
+        bytes.sort_by_key(|&byte| std::cmp::Reverse(self.byte_changes[byte]));
+        bytes
+    }
+}
+
+/// Counts the input packets of an endpoint and the bytes their inputs take up before and after encoding.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct InputEncodingStats {
+    pub packets: usize,
+    pub raw_bytes: usize,
+    pub encoded_bytes: usize,
+}
+
+impl InputEncodingStats {
+    pub(crate) fn record_packet(&mut self, raw_bytes: usize, encoded_bytes: usize) {
+        self.packets += 1;
+        self.raw_bytes += raw_bytes;
+        self.encoded_bytes += encoded_bytes;
+    }
+
+    pub(crate) fn merge(&mut self, other: Self) {
+        self.packets += other.packets;
+        self.raw_bytes += other.raw_bytes;
+        self.encoded_bytes += other.encoded_bytes;
+    }
+}
+
+/// Records which bytes of the local inputs change from frame to frame.
+#[derive(Debug)]
+pub(crate) struct InputAnalyzer {
+    last_input: Option<GameInput>,
+    inputs_analyzed: usize,
+    byte_changes: Vec<usize>,
+}
+
+impl InputAnalyzer {
+    pub(crate) fn new(input_size: usize) -> Self {
+        Self {
+            last_input: None,
+            inputs_analyzed: 0,
+            byte_changes: vec![0; input_size],
+        }
+    }
+
+    pub(crate) fn record_input(&mut self, input: &GameInput) {
+        if let Some(last_input) = &self.last_input {
+            for (count, (b1, b2)) in self
+                .byte_changes
+                .iter_mut()
+                .zip(last_input.input().iter().zip(input.input()))
+            {
+                if b1 != b2 {
+                    *count += 1;
+                }
+            }
+        }
+        self.inputs_analyzed += 1;
+        self.last_input = Some(*input);
+    }
+
+    pub(crate) fn stats(&self, encoding: InputEncodingStats) -> InputStats {
+        let ratio = |numerator: usize, denominator: usize| {
+            if denominator == 0 {
+                0.0
+            } else {
+                numerator as f32 / denominator as f32
+            }
+        };
+        InputStats {
+            packets_sent: encoding.packets,
+            average_encoded_size: ratio(encoding.encoded_bytes, encoding.packets),
+            compression_ratio: ratio(encoding.encoded_bytes, encoding.raw_bytes),
+            inputs_analyzed: self.inputs_analyzed,
+            byte_changes: self.byte_changes.clone(),
+        }
+    }
+}
+
+// #########
+// # TESTS #
+// #########
+
+#[cfg(test)]
+mod input_stats_tests {
+    use super::*;
+    use crate::Frame;
+
+    #[test]
+    fn test_analyze_inputs() {
+        let mut analyzer = InputAnalyzer::new(2);
+        for (frame, bytes) in [[0u8, 1], [0, 2], [0, 3], [1, 3]].iter().enumerate() {
+            let mut input = GameInput::new(Frame(frame as i64), 2);
+            input.copy_input(bytes);
+            analyzer.record_input(&input);
+        }
+
+        let mut encoding = InputEncodingStats::default();
+        encoding.record_packet(4, 2);
+        encoding.merge(InputEncodingStats {
+            packets: 1,
+            raw_bytes: 4,
+            encoded_bytes: 4,
+        });
+
+        let stats = analyzer.stats(encoding);
+        assert_eq!(stats.packets_sent, 2);
+        assert_eq!(stats.average_encoded_size, 3.0);
+        assert_eq!(stats.compression_ratio, 0.75);
+        assert_eq!(stats.inputs_analyzed, 4);
+        assert_eq!(stats.byte_changes, vec![1, 2]);
+        assert_eq!(stats.most_changed_bytes(), vec![1, 0]);
+    }
+}
@@ -1,3 +1,5 @@
+use crate::PlayerHandle;
+
 /// The `NetworkStats` struct contains statistics about the current session.
 #[derive(Debug, Default, Clone, Copy)]
 pub struct NetworkStats {
@@ -5,8 +7,13 @@ pub struct NetworkStats {
     /// The length of the send queue is a rough indication of the quality of the connection. The longer the send queue, the higher the round-trip time between the
     /// clients. The send queue will also be longer than usual during high packet loss situations.
     pub send_queue_len: usize,
-    /// The roundtrip packet transmission time as calcuated by GGRS.
+    /// The roundtrip packet transmission time as calcuated by GGRS, in milliseconds. The measurements are smoothed as described in RFC 6298,
+    /// so the value does not jump with every measurement.
     pub ping: u128,
+    /// The most recently measured roundtrip packet transmission time, in milliseconds.
+    pub raw_ping: u128,
+    /// The variance of the roundtrip packet transmission time, in milliseconds. High values indicate jitter.
+    pub ping_variance: u128,
     /// The estimated bandwidth used between the two clients, in kilobits per second.
     pub kbps_sent: usize,
 
@@ -16,6 +23,22 @@ pub struct NetworkStats {
     pub local_frames_behind: i32,
     /// The same as `local_frames_behind`, but calculated from the perspective of the remote player.
     pub remote_frames_behind: i32,
+    /// The FPS the remote client reported during synchronization, or 0 if it has not reported any yet.
+    pub remote_fps: u32,
+
+    /// The number of packets sent to the remote client.
+    pub packets_sent: usize,
+    /// The number of packets received from the remote client.
+    pub packets_received: usize,
+    /// The estimated fraction of packets from the remote client that got lost, between 0 and 1. GGRS numbers every packet it sends,
+    /// so lost packets show up as gaps in the sequence numbers of the received packets.
+    pub packet_loss: f32,
+    /// The number of packets from the remote client that arrived after a packet that was sent later.
+    pub packets_out_of_order: usize,
+    /// The number of packets from the remote client that arrived more than once. Duplicates are dropped without being processed.
+    pub packets_duplicated: usize,
+    /// The number of packets from the remote client that arrived so late that GGRS could not tell whether they are duplicates. These are dropped as well.
+    pub packets_stale: usize,
 }
 
 impl NetworkStats {
@@ -24,3 +47,33 @@ impl NetworkStats {
         Self::default()
     }
 }
+
+/// The `SpectatorStats` struct contains information about a single spectator of a `P2PSession`.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct SpectatorStats {
+    /// The handle of the spectator.
+    pub player_handle: PlayerHandle,
+    /// The number of confirmed frames the spectator has not acknowledged yet, not counting the frames held back by the spectator delay.
+    /// Spectators that joined late are behind until they have caught up.
+    pub frames_behind: u32,
+    /// The roundtrip packet transmission time as calcuated by GGRS.
+    pub ping: u128,
+    /// The estimated bandwidth used to send inputs to the spectator, in kilobits per second.
+    pub kbps_sent: usize,
+}
+
+/// The `FrameAdvantage` struct contains the estimated frame advantage of the local client over a single remote player.
+/// These are the same values the session uses to decide on a `GGRSEvent::WaitRecommendation`, averaged over the time sync window.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct FrameAdvantage {
+    /// The handle of the remote player.
+    pub player_handle: PlayerHandle,
+    /// The average number of frames the local client is behind the remote client.
+    pub local_frames_behind: f32,
+    /// The average number of frames the remote client is behind the local client, as reported by the remote client.
+    pub remote_frames_behind: f32,
+    /// The estimated number of frames the local client is ahead of the remote client. Negative values mean the local client is behind.
+    pub frames_ahead: f32,
+    /// The number of frames the session would currently recommend to wait because of this player.
+    pub recommended_wait: u32,
+}
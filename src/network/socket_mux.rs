@@ -0,0 +1,228 @@
+use parking_lot::Mutex;
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::sync::Arc;
+
+use crate::network::udp_msg::{MessageBody, UdpMessage};
+use crate::network::udp_socket::{NonBlockingSocket, SocketError, SocketStats};
+
+#[derive(Debug, Default)]
+struct Route {
+    received: Vec<(SocketAddr, UdpMessage)>,
+    errors: Vec<SocketError>,
+}
+
+#[derive(Debug)]
+struct Multiplexer {
+    socket: Box<dyn NonBlockingSocket + Send>,
+    routes: Vec<Route>,
+    /// The sockets that have sent messages to a remote address. Messages from that address are routed to them.
+    claims: HashMap<SocketAddr, Vec<usize>>,
+    /// The socket a sync request with the given random number has been sent from.
+    sync_requests: HashMap<(SocketAddr, u32), usize>,
+    /// The socket whose session is synchronized with the remote session of the given address and magic.
+    magics: HashMap<(SocketAddr, u16), usize>,
+}
+
+impl Multiplexer {
+    fn send_to(&mut self, id: usize, msg: &UdpMessage, addr: SocketAddr) {
+        let claimants = self.claims.entry(addr).or_default();
+        if !claimants.contains(&id) {
+            claimants.push(id);
+        }
+        if let MessageBody::SyncRequest(body) = &msg.body {
+            self.sync_requests.insert((addr, body.random_request), id);
+        }
+        self.socket.send_to(msg, addr);
+    }
+
+    /// Receives all messages of the shared socket and routes them to the sockets of the sessions.
+    fn receive(&mut self) {
+        for (addr, msg) in self.socket.receive_all_messages() {
+            // a sync reply tells us which session the remote session is talking to
+            if let MessageBody::SyncReply(body) = &msg.body {
+                if let Some(id) = self.sync_requests.remove(&(addr, body.random_reply)) {
+                    self.magics.insert((addr, msg.header.magic), id);
+                    // older unanswered sync requests of that session are not needed anymore
+                    self.sync_requests
+                        .retain(|(to, _), from| *to != addr || *from != id);
+                }
+            }
+
+            if let Some(id) = self.magics.get(&(addr, msg.header.magic)) {
+                self.routes[*id].received.push((addr, msg));
+            } else if let Some(claimants) = self.claims.get(&addr) {
+                // until the sessions are synchronized, all sessions talking to the address get the message and filter it themselves
+                for id in claimants {
+                    self.routes[*id].received.push((addr, msg.clone()));
+                }
+            } else {
+                ggrs_trace!("Dropping message from {} that no session talks to", addr);
+            }
+        }
+
+        // socket errors concern all sessions
+        for error in self.socket.take_errors() {
+            for route in &mut self.routes {
+                route.errors.push(error);
+            }
+        }
+    }
+}
+
+/// Shares a single `NonBlockingSocket` between several sessions, e.g. to host many matches on a relay or observer server with a single port.
+/// Create a `MultiplexedSocket` for every session with `socket()`. Messages are routed to the sessions by the remote address and the magic
+/// number of the remote session: a session receives the messages of all addresses it has sent messages to. If several sessions talk to the same address,
+/// each message goes to the session that synchronized with the sending session.
+#[derive(Debug)]
+pub struct SocketMultiplexer {
+    multiplexer: Arc<Mutex<Multiplexer>>,
+}
+
+impl SocketMultiplexer {
+    /// Creates a new `SocketMultiplexer` that shares the given socket.
+    pub fn new(socket: impl NonBlockingSocket + 'static) -> Self {
+        Self {
+            multiplexer: Arc::new(Mutex::new(Multiplexer {
+                socket: Box::new(socket),
+                routes: Vec::new(),
+                claims: HashMap::new(),
+                sync_requests: HashMap::new(),
+                magics: HashMap::new(),
+            })),
+        }
+    }
+
+    /// Returns a new socket for a session, which sends and receives through the shared socket.
+    pub fn socket(&self) -> MultiplexedSocket {
+        let mut multiplexer = self.multiplexer.lock();
+        multiplexer.routes.push(Route::default());
+        MultiplexedSocket {
+            id: multiplexer.routes.len() - 1,
+            multiplexer: self.multiplexer.clone(),
+        }
+    }
+}
+
+/// A socket of a single session that shares its underlying socket with other sessions through a `SocketMultiplexer`.
+#[derive(Debug)]
+pub struct MultiplexedSocket {
+    id: usize,
+    multiplexer: Arc<Mutex<Multiplexer>>,
+}
+
+impl NonBlockingSocket for MultiplexedSocket {
+    fn send_to(&mut self, msg: &UdpMessage, addr: SocketAddr) {
+        self.multiplexer.lock().send_to(self.id, msg, addr);
+    }
+
+    fn receive_all_messages(&mut self) -> Vec<(SocketAddr, UdpMessage)> {
+        let mut multiplexer = self.multiplexer.lock();
+        multiplexer.receive();
+        std::mem::take(&mut multiplexer.routes[self.id].received)
+    }
+
+    fn take_errors(&mut self) -> Vec<SocketError> {
+        std::mem::take(&mut self.multiplexer.lock().routes[self.id].errors)
+    }
+
+    /// Returns the statistics of the shared socket, which include the traffic of all sessions.
+    fn stats(&self) -> Option<SocketStats> {
+        self.multiplexer.lock().socket.stats()
+    }
+}
+
+// #########
+// # TESTS #
+// #########
+
+#[cfg(test)]
+mod socket_mux_tests {
+
+    use super::*;
+    use crate::network::udp_msg::{MessageHeader, SyncReply, SyncRequest};
+
+    /// A socket that receives the messages queued in `incoming` and counts the sent messages.
+    #[derive(Debug, Default)]
+    struct QueueSocket {
+        incoming: Arc<Mutex<Vec<(SocketAddr, UdpMessage)>>>,
+        sent: Arc<Mutex<usize>>,
+    }
+
+    impl NonBlockingSocket for QueueSocket {
+        fn send_to(&mut self, _msg: &UdpMessage, _addr: SocketAddr) {
+            *self.sent.lock() += 1;
+        }
+
+        fn receive_all_messages(&mut self) -> Vec<(SocketAddr, UdpMessage)> {
+            std::mem::take(&mut *self.incoming.lock())
+        }
+    }
+
+    fn message(magic: u16, body: MessageBody) -> UdpMessage {
+        UdpMessage {
+            header: MessageHeader {
+                magic,
+                session_id: 0,
+                sequence_number: 0,
+            },
+            body,
+        }
+    }
+
+    #[test]
+    fn test_messages_are_routed_to_sessions() {
+        let addr1: SocketAddr = "10.0.0.1:1".parse().unwrap();
+        let addr2: SocketAddr = "10.0.0.2:1".parse().unwrap();
+        let shared = QueueSocket::default();
+        let (incoming, sent) = (shared.incoming.clone(), shared.sent.clone());
+        let mux = SocketMultiplexer::new(shared);
+        let (mut socket1, mut socket2) = (mux.socket(), mux.socket());
+
+        // both sessions talk to the first address, only the second session to the second address
+        let request = |random_request| {
+            MessageBody::SyncRequest(SyncRequest {
+                random_request,
+                ..SyncRequest::default()
+            })
+        };
+        socket1.send_to(&message(1, request(11)), addr1);
+        socket2.send_to(&message(2, request(22)), addr1);
+        socket2.send_to(&message(2, request(33)), addr2);
+        assert_eq!(*sent.lock(), 3);
+
+        let reply = message(
+            7,
+            MessageBody::SyncReply(SyncReply {
+                random_reply: 22,
+                ..SyncReply::default()
+            }),
+        );
+        let keep_alive = message(7, MessageBody::KeepAlive);
+        let unknown = message(8, MessageBody::KeepAlive);
+        incoming.lock().extend(vec![
+            (addr1, reply.clone()),
+            (addr1, keep_alive.clone()),
+            (addr1, unknown.clone()),
+            (addr2, keep_alive.clone()),
+            ("10.0.0.3:1".parse().unwrap(), keep_alive.clone()),
+        ]);
+
+        // the reply synchronizes the remote session at the first address with the second session
+        assert_eq!(
+            socket1.receive_all_messages(),
+            vec![(addr1, unknown.clone())]
+        );
+        assert_eq!(
+            socket2.receive_all_messages(),
+            vec![
+                (addr1, reply),
+                (addr1, keep_alive.clone()),
+                (addr1, unknown),
+                (addr2, keep_alive)
+            ]
+        );
+        assert!(socket1.receive_all_messages().is_empty());
+        assert!(socket2.stats().is_none());
+    }
+}
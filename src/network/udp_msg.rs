@@ -1,5 +1,8 @@
 use serde::{Deserialize, Serialize};
 
+#[cfg(feature = "std")]
+use alloc::vec::Vec;
+
 use crate::{Frame, NULL_FRAME};
 
 #[derive(Copy, Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
@@ -17,42 +20,156 @@ impl Default for ConnectionStatus {
     }
 }
 
+/// The latest pause request a peer knows of. Pause frames only ever increase, so the newest status is always the largest one,
+/// even if messages arrive out of order.
+#[cfg(feature = "std")]
+#[derive(Copy, Clone, Debug, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+pub(crate) struct PauseStatus {
+    pub pause_frame: Frame,
+    pub resumed: bool,
+}
+
+#[cfg(feature = "std")]
+impl Default for PauseStatus {
+    fn default() -> Self {
+        Self {
+            pause_frame: NULL_FRAME,
+            resumed: true,
+        }
+    }
+}
+
+/// The highest frame rate sessions can change to.
+#[cfg(feature = "std")]
+pub(crate) const MAX_FPS: u32 = 1000;
+/// The highest timescale in percent, ten times the regular speed.
+#[cfg(feature = "std")]
+pub(crate) const MAX_TIMESCALE: u16 = 1000;
+
+/// The latest change of the frame rate a peer knows of. Like pause frames, change frames only ever increase,
+/// so the newest change is always the largest one, even if messages arrive out of order.
+#[cfg(feature = "std")]
+#[derive(Copy, Clone, Debug, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+pub(crate) struct FpsChange {
+    pub frame: Frame,
+    pub fps: u32,
+    /// The speed of the game in percent of its regular speed. The sessions run at `fps` scaled by the timescale.
+    pub timescale: u16,
+}
+
+#[cfg(feature = "std")]
+impl FpsChange {
+    /// Returns the frame rate with the timescale applied, which is what the sessions synchronize their time with.
+    pub(crate) fn scaled_fps(&self) -> u32 {
+        // the frame rate comes from the peers, so it is scaled without overflowing
+        let scaled = self.fps as u64 * self.timescale as u64 / 100;
+        core::cmp::max(1, core::cmp::min(scaled, u32::MAX as u64) as u32)
+    }
+}
+
+#[cfg(feature = "std")]
+impl Default for FpsChange {
+    fn default() -> Self {
+        Self {
+            frame: NULL_FRAME,
+            fps: 0,
+            timescale: 100,
+        }
+    }
+}
+
+#[cfg(feature = "std")]
 #[derive(Copy, Clone, Debug, PartialEq, Eq, Serialize, Deserialize, Default)]
 pub(crate) struct SyncRequest {
     pub random_request: u32, // please reply back with this random data
+    pub fps: u32,
+    pub seed: u32, // the contribution of the sending session to the shared seed
 }
 
+#[cfg(feature = "std")]
 #[derive(Copy, Clone, Debug, PartialEq, Eq, Serialize, Deserialize, Default)]
 pub(crate) struct SyncReply {
     pub random_reply: u32, // here's your random data back
+    pub fps: u32,
+    pub token_proof: u64, // proves that we know the connection token, 0 without a token
 }
 
+#[cfg(feature = "std")]
 #[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
 pub(crate) struct Input {
     pub peer_connect_status: Vec<ConnectionStatus>,
     pub disconnect_requested: bool,
+    pub pause_status: PauseStatus,
+    pub fps_change: FpsChange,
     pub start_frame: Frame,
+    /// If set, the bytes are delta-encoded against a blank input and can be decoded without any previous input. `reference_frame` is `NULL_FRAME` then.
+    pub keyframe: bool,
+    /// The frame of the input the bytes are delta-encoded against, unless this is a keyframe.
+    pub reference_frame: Frame,
     pub ack_frame: Frame,
     pub bytes: Vec<u8>,
+    /// The number of consecutive frames each of the encoded inputs stands for. If empty, every encoded input stands for a single frame.
+    pub frame_runs: Vec<u16>,
 }
 
+#[cfg(feature = "std")]
 impl Default for Input {
     fn default() -> Self {
         Self {
             peer_connect_status: Vec::new(),
             disconnect_requested: false,
+            pause_status: PauseStatus::default(),
+            fps_change: FpsChange::default(),
             start_frame: NULL_FRAME,
+            keyframe: false,
+            reference_frame: NULL_FRAME,
             ack_frame: NULL_FRAME,
             bytes: Vec::new(),
+            frame_runs: Vec::new(),
         }
     }
 }
 
+/// A part of a chunk of the input history, sent to spectators that are far behind. The parts of a chunk are concatenated and decoded against a blank input.
+#[cfg(feature = "std")]
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub(crate) struct InputHistory {
+    pub start_frame: Frame,
+    pub part: u8,
+    pub num_parts: u8,
+    pub bytes: Vec<u8>,
+}
+
+/// A message of the reliable channel. The messages of an endpoint are numbered consecutively and delivered in that order, each exactly once.
+#[cfg(feature = "std")]
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub(crate) struct Reliable {
+    pub sequence: u32,
+    pub bytes: Vec<u8>,
+}
+
+/// Acknowledges all reliable messages before `next_sequence`.
+#[cfg(feature = "std")]
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Serialize, Deserialize, Default)]
+pub(crate) struct ReliableAck {
+    pub next_sequence: u32,
+}
+
+/// The checksum of the confirmed gamestate of `frame`, exchanged between players to detect desyncs.
+#[cfg(feature = "std")]
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub(crate) struct StateChecksum {
+    pub frame: Frame,
+    pub checksum: u64,
+}
+
+#[cfg(feature = "std")]
 #[derive(Copy, Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
 pub(crate) struct InputAck {
     pub ack_frame: Frame,
 }
 
+#[cfg(feature = "std")]
 impl Default for InputAck {
     fn default() -> Self {
         Self {
@@ -61,22 +178,28 @@ impl Default for InputAck {
     }
 }
 
+#[cfg(feature = "std")]
 #[derive(Copy, Clone, Debug, PartialEq, Eq, Serialize, Deserialize, Default)]
 pub(crate) struct QualityReport {
-    pub frame_advantage: i8, // frame advantage of other player
+    pub frame_advantage: i32, // frame advantage of other player, in 1/256 frames
     pub ping: u128,
 }
 
+#[cfg(feature = "std")]
 #[derive(Copy, Clone, Debug, PartialEq, Eq, Serialize, Deserialize, Default)]
 pub(crate) struct QualityReply {
     pub pong: u128,
 }
 
+#[cfg(feature = "std")]
 #[derive(Copy, Clone, Debug, PartialEq, Eq, Serialize, Deserialize, Default)]
 pub(crate) struct MessageHeader {
     pub magic: u16,
+    pub session_id: u32,
+    pub sequence_number: u16,
 }
 
+#[cfg(feature = "std")]
 #[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
 pub(crate) enum MessageBody {
     SyncRequest(SyncRequest),
@@ -86,10 +209,41 @@ pub(crate) enum MessageBody {
     QualityReport(QualityReport),
     QualityReply(QualityReply),
     KeepAlive,
+    InputHistory(InputHistory),
+    Reliable(Reliable),
+    ReliableAck(ReliableAck),
+    StateChecksum(StateChecksum),
 }
 
+/// A single message exchanged between GGRS sessions. The contents are internal to GGRS, but the message can be encoded in the stable, documented GGRS wire format
+/// with `to_bytes()` and `from_bytes()`, so custom `NonBlockingSocket` implementations can transmit it and peers written in other languages can talk to GGRS.
+/// The message can also be (de)serialized with `serde`, but that encoding depends on the serializer and is not meant for the wire.
+#[cfg(feature = "std")]
 #[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
-pub(crate) struct UdpMessage {
-    pub header: MessageHeader,
-    pub body: MessageBody,
+pub struct UdpMessage {
+    pub(crate) header: MessageHeader,
+    pub(crate) body: MessageBody,
+}
+
+// #########
+// # TESTS #
+// #########
+
+#[cfg(test)]
+mod udp_msg_tests {
+
+    use super::*;
+
+    #[test]
+    fn test_scaled_fps() {
+        let change = |fps, timescale| FpsChange {
+            frame: Frame(0),
+            fps,
+            timescale,
+        };
+        assert_eq!(change(60, 50).scaled_fps(), 30);
+        assert_eq!(change(1, 50).scaled_fps(), 1);
+        assert_eq!(change(u32::MAX, 100).scaled_fps(), u32::MAX);
+        assert_eq!(change(u32::MAX, MAX_TIMESCALE).scaled_fps(), u32::MAX);
+    }
 }
@@ -1,41 +1,54 @@
 use crate::frame_info::{GameInput, BLANK_INPUT};
-use crate::network::compression::{decode, encode};
+use crate::network::compression::{collapse_runs, decode, encode, expand_runs, Reference};
+use crate::network::connection_token::ConnectionToken;
+use crate::network::input_stats::InputEncodingStats;
 use crate::network::udp_msg::{
-    ConnectionStatus, Input, InputAck, MessageBody, MessageHeader, QualityReply, QualityReport,
-    SyncReply, SyncRequest, UdpMessage,
+    ConnectionStatus, FpsChange, Input, InputAck, InputHistory, MessageBody, MessageHeader,
+    PauseStatus, QualityReply, QualityReport, Reliable, ReliableAck, StateChecksum, SyncReply,
+    SyncRequest, UdpMessage, MAX_FPS, MAX_TIMESCALE,
 };
 use crate::network::udp_socket::NonBlockingSocket;
 use crate::sessions::p2p_session::{
     Event, DEFAULT_DISCONNECT_NOTIFY_START, DEFAULT_DISCONNECT_TIMEOUT, DEFAULT_FPS,
 };
+use crate::time_source::{SharedTimeSource, SystemTimeSource};
 use crate::time_sync::TimeSync;
 use crate::{Frame, PlayerHandle, NULL_FRAME};
 
+use serde::{Deserialize, Serialize};
 use std::collections::vec_deque::Drain;
 use std::collections::VecDeque;
-use std::convert::TryFrom;
+use std::convert::TryInto;
 use std::net::SocketAddr;
 use std::ops::Add;
-use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use std::sync::Arc;
+use std::time::Duration;
 
 use super::network_stats::NetworkStats;
 
 const UDP_HEADER_SIZE: usize = 28; // Size of IP + UDP headers
+/// Roundtrip times above this are treated as this value when estimating the frame of the remote client.
+const MAX_ESTIMATED_PING: u128 = 60_000;
+/// The weight of a new roundtrip time sample in the smoothed roundtrip time, as recommended by RFC 6298.
+const RTT_ALPHA: f32 = 1.0 / 8.0;
+/// The weight of a new roundtrip time sample in the roundtrip time variance, as recommended by RFC 6298.
+const RTT_BETA: f32 = 1.0 / 4.0;
+/// Quality reports carry the frame advantage as a fixed-point number in units of 1/256 frames.
+const FRAME_ADVANTAGE_SCALE: f32 = 256.0;
 const NUM_SYNC_PACKETS: u32 = 5;
 const UDP_SHUTDOWN_TIMER: u64 = 5000;
-const PENDING_OUTPUT_SIZE: usize = 128;
-const SYNC_RETRY_INTERVAL: Duration = Duration::from_millis(200);
+pub(crate) const PENDING_OUTPUT_SIZE: usize = 128;
+pub(crate) const SYNC_RETRY_INTERVAL: Duration = Duration::from_millis(200);
 const RUNNING_RETRY_INTERVAL: Duration = Duration::from_millis(200);
-const KEEP_ALIVE_INTERVAL: Duration = Duration::from_millis(200);
+pub(crate) const DEFAULT_KEEP_ALIVE_INTERVAL: Duration = Duration::from_millis(200);
 const QUALITY_REPORT_INTERVAL: Duration = Duration::from_millis(200);
-const MAX_PAYLOAD: usize = 467; // 512 is max safe UDP payload, minus 45 bytes for the rest of the packet
-
-fn millis_since_epoch() -> u128 {
-    SystemTime::now()
-        .duration_since(UNIX_EPOCH)
-        .expect("Time went backwards")
-        .as_millis()
-}
+pub(crate) const MAX_PAYLOAD: usize = 467; // 512 is max safe UDP payload, minus 45 bytes for the rest of the packet
+/// The maximum number of raw input bytes in a chunk of the input history. The encoded chunk is split into parts of up to `MAX_PAYLOAD` bytes.
+pub(crate) const HISTORY_CHUNK_BYTES: usize = 32 * 1024;
+/// The number of sequence numbers before the newest received one that are remembered to detect duplicated messages.
+const SEQUENCE_WINDOW: u16 = 64;
+/// Sequence numbers further away from the newest received one than this are taken as a restarted peer instead of a duplicated or stale message.
+const MAX_SEQUENCE_JUMP: u16 = 1024;
 
 #[derive(Debug, PartialEq, Eq)]
 enum ProtocolState {
@@ -46,6 +59,48 @@ enum ProtocolState {
     Shutdown,
 }
 
+/// Everything a `UdpProtocol` needs to resume the communication with a peer after a restart, without synchronizing again.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub(crate) struct EndpointSnapshot {
+    handle: PlayerHandle,
+    peer_addr: SocketAddr,
+    input_size: usize,
+    magic: u16,
+    remote_magic: u16,
+    disconnected: bool,
+    peer_connect_status: Vec<ConnectionStatus>,
+    pending_output: Vec<GameInput>,
+    last_received_input: GameInput,
+    last_acked_input: GameInput,
+    local_pause_status: PauseStatus,
+    peer_pause_status: PauseStatus,
+    local_fps_change: FpsChange,
+    peer_fps_change: FpsChange,
+    next_sequence_number: u16,
+}
+
+/// What happens when a peer does not acknowledge our inputs and more inputs are pending than allowed. See `P2PSession::set_pending_output_limit()`.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum PendingOutputPolicy {
+    /// The peer is disconnected, as if it timed out. This is the default.
+    Disconnect,
+    /// The oldest pending inputs are not resent anymore, as if the peer had acknowledged them, and `GGRSEvent::PendingInputsDropped` is sent.
+    /// All inputs are sent at least once before they are dropped, so this only keeps memory and packet sizes bounded while acknowledgements get lost.
+    /// A peer that really misses the dropped inputs can not continue.
+    DropOldest,
+    /// The session does not advance anymore: `P2PSession::advance_frame()` returns `GGRSError::PredictionThreshold` until the peer acknowledges
+    /// enough inputs to get below the limit.
+    BlockAdvance,
+}
+
+/// A chunk of the input history that has been sent, but not acknowledged yet.
+#[derive(Debug)]
+struct PendingHistory {
+    parts: Vec<InputHistory>,
+    last_input: GameInput,
+    last_sent: Duration,
+}
+
 #[derive(Debug)]
 pub(crate) struct UdpProtocol {
     handle: PlayerHandle,
@@ -57,40 +112,103 @@ pub(crate) struct UdpProtocol {
     state: ProtocolState,
     sync_remaining_roundtrips: u32,
     sync_random_request: u32,
-    running_last_quality_report: Instant,
-    running_last_input_recv: Instant,
+    running_last_quality_report: Duration,
+    running_last_input_recv: Duration,
     disconnect_notify_sent: bool,
     disconnect_event_sent: bool,
+    disconnect_request_pending: bool,
+    local_pause_status: PauseStatus,
+    peer_pause_status: PauseStatus,
+    pause_status_pending: bool,
+    local_fps_change: FpsChange,
+    peer_fps_change: FpsChange,
+    fps_change_pending: bool,
 
     // constants
+    sync_retry_interval: Duration,
+    max_sync_retries: Option<u32>,
+    sync_timeout: Option<Duration>,
+    sync_start_time: Duration,
+    sync_retries: u32,
     disconnect_timeout: Duration,
     disconnect_notify_start: Duration,
-    shutdown_timeout: Instant,
+    keep_alive_interval: Duration,
+    input_send_interval: u32,
+    sparse_input_interval: u32,
+    unsent_inputs: u32,
+    previous_input: GameInput,
+    shutdown_timeout: Duration,
     fps: u32,
+    remote_fps: u32,
+    fps_mismatch_reported: bool,
+    connection_token: Option<ConnectionToken>,
+    /// Identifies the match. Messages of other matches are dropped.
+    session_id: u32,
 
     // the other client
     peer_addr: SocketAddr,
     remote_magic: u16,
     peer_connect_status: Vec<ConnectionStatus>,
+    /// Our contribution to the shared seed and the contribution of the peer, once a sync request of the peer has arrived.
+    local_seed: u32,
+    remote_seed: Option<u32>,
 
     // input compression
     pending_output: VecDeque<GameInput>,
     last_received_input: GameInput,
     last_acked_input: GameInput,
     input_size: usize,
+    input_encoding_stats: InputEncodingStats,
+    pending_output_limit: usize,
+    pending_output_policy: PendingOutputPolicy,
+
+    // input history
+    accept_input_history: bool,
+    pending_history: Option<PendingHistory>,
+    received_history: Vec<Option<Vec<u8>>>,
+    received_history_start: Frame,
+
+    // reliable channel
+    reliable_outgoing: VecDeque<Reliable>,
+    next_reliable_sequence: u32,
+    expected_reliable_sequence: u32,
+    received_reliable: Vec<Vec<u8>>,
+    reliable_last_sent: Duration,
 
     // time sync
     time_sync_layer: TimeSync,
-    local_frame_advantage: i32,
-    remote_frame_advantage: i32,
+    local_frame_advantage: f32,
+    remote_frame_advantage: f32,
 
     // network
     stats_start_time: u128,
     packets_sent: usize,
+    packets_received: usize,
     bytes_sent: usize,
+    next_sequence_number: u16,
+    last_recv_sequence_number: Option<u16>,
+    /// Bit `i` is set if the message with sequence number `last_recv_sequence_number - i` has been received.
+    recent_sequence_numbers: u64,
+    packets_lost: usize,
+    packets_out_of_order: usize,
+    packets_duplicated: usize,
+    packets_stale: usize,
     round_trip_time: u128,
-    last_send_time: Instant,
-    last_recv_time: Instant,
+    smoothed_round_trip_time: Option<f32>,
+    round_trip_time_variance: f32,
+    last_send_time: Duration,
+    last_recv_time: Duration,
+
+    // rate limiting
+    max_messages_per_second: u32,
+    max_bytes_per_second: usize,
+    rate_window_start: Duration,
+    messages_in_window: u32,
+    bytes_in_window: usize,
+    rate_limit_reported: bool,
+
+    // all timestamps above are read from this time source
+    time_source: SharedTimeSource,
 }
 
 impl PartialEq for UdpProtocol {
@@ -107,6 +225,9 @@ impl UdpProtocol {
         num_players: u32,
         input_size: usize,
     ) -> Self {
+        // the timers start at zero, which is the first time read from a new time source
+        let time_source: SharedTimeSource = Arc::new(SystemTimeSource::new());
+        let now = Duration::ZERO;
         let mut magic = rand::random::<u16>();
         while magic == 0 {
             magic = rand::random::<u16>();
@@ -132,20 +253,43 @@ impl UdpProtocol {
             state: ProtocolState::Initializing,
             sync_remaining_roundtrips: NUM_SYNC_PACKETS,
             sync_random_request: rand::random::<u32>(),
-            running_last_quality_report: Instant::now(),
-            running_last_input_recv: Instant::now(),
+            running_last_quality_report: now,
+            running_last_input_recv: now,
             disconnect_notify_sent: false,
             disconnect_event_sent: false,
+            disconnect_request_pending: false,
+            local_pause_status: PauseStatus::default(),
+            peer_pause_status: PauseStatus::default(),
+            pause_status_pending: false,
+            local_fps_change: FpsChange::default(),
+            peer_fps_change: FpsChange::default(),
+            fps_change_pending: false,
 
             // constants
+            sync_retry_interval: SYNC_RETRY_INTERVAL,
+            max_sync_retries: None,
+            sync_timeout: None,
+            sync_start_time: now,
+            sync_retries: 0,
             disconnect_timeout: DEFAULT_DISCONNECT_TIMEOUT,
             disconnect_notify_start: DEFAULT_DISCONNECT_NOTIFY_START,
-            shutdown_timeout: Instant::now(),
+            keep_alive_interval: DEFAULT_KEEP_ALIVE_INTERVAL,
+            input_send_interval: 1,
+            sparse_input_interval: 0,
+            unsent_inputs: 0,
+            previous_input: blank_input,
+            shutdown_timeout: now,
             fps: DEFAULT_FPS,
+            remote_fps: 0,
+            fps_mismatch_reported: false,
+            connection_token: None,
+            session_id: 0,
 
             // the other client
             peer_addr,
             remote_magic: 0,
+            local_seed: 0,
+            remote_seed: None,
             peer_connect_status,
 
             // input compression
@@ -153,22 +297,117 @@ impl UdpProtocol {
             last_received_input: blank_input,
             last_acked_input: blank_input,
             input_size,
+            input_encoding_stats: InputEncodingStats::default(),
+            pending_output_limit: PENDING_OUTPUT_SIZE,
+            pending_output_policy: PendingOutputPolicy::Disconnect,
+
+            // input history
+            accept_input_history: false,
+            pending_history: None,
+            received_history: Vec::new(),
+            received_history_start: NULL_FRAME,
+
+            // reliable channel
+            reliable_outgoing: VecDeque::new(),
+            next_reliable_sequence: 0,
+            expected_reliable_sequence: 0,
+            received_reliable: Vec::new(),
+            reliable_last_sent: now,
 
             // time sync
             time_sync_layer: TimeSync::new(),
-            local_frame_advantage: 0,
-            remote_frame_advantage: 0,
+            local_frame_advantage: 0.0,
+            remote_frame_advantage: 0.0,
 
             // network
             stats_start_time: 0,
             packets_sent: 0,
+            packets_received: 0,
             bytes_sent: 0,
+            next_sequence_number: 0,
+            last_recv_sequence_number: None,
+            recent_sequence_numbers: 0,
+            packets_lost: 0,
+            packets_out_of_order: 0,
+            packets_duplicated: 0,
+            packets_stale: 0,
             round_trip_time: 0,
-            last_send_time: Instant::now(),
-            last_recv_time: Instant::now(),
+            smoothed_round_trip_time: None,
+            round_trip_time_variance: 0.0,
+            last_send_time: now,
+            last_recv_time: now,
+
+            // rate limiting
+            max_messages_per_second: 0,
+            max_bytes_per_second: 0,
+            rate_window_start: now,
+            messages_in_window: 0,
+            bytes_in_window: 0,
+            rate_limit_reported: false,
+
+            time_source,
         }
     }
 
+    /// Creates a running endpoint from a snapshot, so the peer accepts our messages as if we never left.
+    pub(crate) fn restore(snapshot: EndpointSnapshot) -> Self {
+        let mut endpoint = Self::new(
+            snapshot.handle,
+            snapshot.peer_addr,
+            snapshot.peer_connect_status.len() as u32,
+            snapshot.input_size,
+        );
+        endpoint.magic = snapshot.magic;
+        endpoint.remote_magic = snapshot.remote_magic;
+        endpoint.peer_connect_status = snapshot.peer_connect_status;
+        endpoint.pending_output = snapshot.pending_output.into_iter().collect();
+        endpoint.last_received_input = snapshot.last_received_input;
+        endpoint.last_acked_input = snapshot.last_acked_input;
+        endpoint.local_pause_status = snapshot.local_pause_status;
+        endpoint.peer_pause_status = snapshot.peer_pause_status;
+        endpoint.local_fps_change = snapshot.local_fps_change;
+        endpoint.peer_fps_change = snapshot.peer_fps_change;
+        endpoint.stats_start_time = endpoint.time_source.now().as_millis();
+        // the peer still remembers the sequence numbers the endpoint sent before the restore, including those sent after the snapshot was taken.
+        // Continue half the sequence space away, so the peer resets its window instead of dropping the messages as duplicates or stale
+        endpoint.next_sequence_number =
+            snapshot.next_sequence_number.wrapping_add(u16::MAX / 2 + 1);
+        if snapshot.disconnected {
+            endpoint.state = ProtocolState::Disconnected;
+            endpoint.shutdown_timeout = endpoint
+                .time_source
+                .now()
+                .add(Duration::from_millis(UDP_SHUTDOWN_TIMER));
+        } else {
+            endpoint.state = ProtocolState::Running;
+        }
+        endpoint
+    }
+
+    /// Returns `None` if the endpoint has not synchronized yet or has been shut down.
+    pub(crate) fn snapshot(&self) -> Option<EndpointSnapshot> {
+        if self.state != ProtocolState::Running && self.state != ProtocolState::Disconnected {
+            return None;
+        }
+        Some(EndpointSnapshot {
+            handle: self.handle,
+            peer_addr: self.peer_addr,
+            input_size: self.input_size,
+            magic: self.magic,
+            remote_magic: self.remote_magic,
+            disconnected: self.state == ProtocolState::Disconnected,
+            peer_connect_status: self.peer_connect_status.clone(),
+            pending_output: self.pending_output.iter().copied().collect(),
+            last_received_input: self.last_received_input,
+            last_acked_input: self.last_acked_input,
+            local_pause_status: self.local_pause_status,
+            peer_pause_status: self.peer_pause_status,
+            local_fps_change: self.local_fps_change,
+            peer_fps_change: self.peer_fps_change,
+            next_sequence_number: self.next_sequence_number,
+        })
+    }
+
     pub(crate) const fn player_handle(&self) -> PlayerHandle {
         self.handle
     }
@@ -181,20 +420,117 @@ impl UdpProtocol {
             return;
         }
         // Estimate which frame the other client is on by looking at the last frame they gave us plus some delta for the packet roundtrip time.
-        let ping = i32::try_from(self.round_trip_time).expect("Ping is higher than i32::MAX");
-        let remote_frame = self.last_received_input.frame + ((ping * self.fps as i32) / 1000);
+        // The roundtrip time depends on the pongs of the remote client, so it is capped to keep the estimate in range.
+        // The estimate keeps fractions of frames, the time a frame takes is short compared to the roundtrip time for high FPS.
+        let ping = std::cmp::min(self.smoothed_ping(), MAX_ESTIMATED_PING) as f32;
+        let remote_frame =
+            self.last_received_input.frame.0 as f32 + ping * self.fps as f32 / 1000.0;
         // Our frame "advantage" is how many frames behind the remote client we are. (It's an advantage because they will have to predict more often)
-        self.local_frame_advantage = remote_frame - local_frame;
+        self.local_frame_advantage = remote_frame - local_frame.0 as f32;
+    }
+
+    /// Returns how many whole frames the local client is estimated to be ahead of the remote client.
+    pub(crate) fn frames_ahead(&self) -> i32 {
+        (-self.local_frame_advantage).round() as i32
+    }
+
+    /// Sync requests are resent every `interval` until the peer answers. After `max_retries` resent requests without an answer, synchronization fails.
+    pub(crate) fn set_sync_retries(&mut self, interval: Duration, max_retries: Option<u32>) {
+        self.sync_retry_interval = interval;
+        self.max_sync_retries = max_retries;
+    }
+
+    /// Synchronization fails if it has not completed `timeout` after it started, no matter how many sync requests have been sent.
+    pub(crate) fn set_sync_timeout(&mut self, timeout: Option<Duration>) {
+        self.sync_timeout = timeout;
     }
 
     pub(crate) fn set_disconnect_timeout(&mut self, timeout: Duration) {
         self.disconnect_timeout = timeout;
     }
 
+    /// If we did not send a message for this long, we send a keep alive.
+    pub(crate) fn set_keep_alive_interval(&mut self, interval: Duration) {
+        self.keep_alive_interval = interval;
+    }
+
+    /// Reads the time from the given time source from now on. All timers restart at the current time of the new time source.
+    pub(crate) fn set_time_source(&mut self, time_source: SharedTimeSource) {
+        let now = time_source.now();
+        self.running_last_quality_report = now;
+        self.running_last_input_recv = now;
+        self.sync_start_time = now;
+        self.reliable_last_sent = now;
+        self.last_send_time = now;
+        self.last_recv_time = now;
+        self.rate_window_start = now;
+        self.stats_start_time = now.as_millis();
+        if self.state == ProtocolState::Disconnected {
+            self.shutdown_timeout = now.add(Duration::from_millis(UDP_SHUTDOWN_TIMER));
+        }
+        if let Some(history) = &mut self.pending_history {
+            history.last_sent = now;
+        }
+        self.time_source = time_source;
+    }
+
+    /// Inputs are only sent every `interval` inputs, so multiple inputs are coalesced into a single packet.
+    pub(crate) fn set_input_send_interval(&mut self, interval: u32) {
+        self.input_send_interval = interval;
+    }
+
+    /// With sparse inputs, inputs are sent right away when they change, but unchanged inputs only every `interval` inputs. Unchanged inputs are sent
+    /// only once per message, together with the number of frames they stand for. An interval of 0 disables sparse inputs.
+    pub(crate) fn set_sparse_input_interval(&mut self, interval: u32) {
+        self.sparse_input_interval = interval;
+    }
+
+    /// Limits how many messages and bytes per second we accept from the peer. A limit of 0 disables it.
+    pub(crate) fn set_rate_limit(&mut self, messages_per_second: u32, bytes_per_second: usize) {
+        self.max_messages_per_second = messages_per_second;
+        self.max_bytes_per_second = bytes_per_second;
+    }
+
+    /// With a connection token, the peer has to prove that it knows the token during synchronization.
+    pub(crate) fn set_connection_token(&mut self, token: Option<ConnectionToken>) {
+        self.connection_token = token;
+    }
+
+    /// Messages with a different session id are dropped, so messages of an earlier match on the same address are never mistaken for ours.
+    pub(crate) fn set_session_id(&mut self, session_id: u32) {
+        self.session_id = session_id;
+    }
+
+    pub(crate) fn set_time_sync_window(&mut self, window_size: usize) {
+        self.time_sync_layer.set_window_size(window_size);
+    }
+
+    pub(crate) fn set_wait_recommendation_limits(&mut self, min: u32, max: u32) {
+        self.time_sync_layer.set_frame_advantage_limits(min, max);
+    }
+
     pub(crate) fn set_disconnect_notify_start(&mut self, notify_start: Duration) {
         self.disconnect_notify_start = notify_start;
     }
 
+    /// Limits the number of unacknowledged inputs we keep for the peer. The policy decides what happens once the peer exceeds the limit.
+    pub(crate) fn set_pending_output_limit(&mut self, limit: usize, policy: PendingOutputPolicy) {
+        assert!(limit > 0 && limit <= PENDING_OUTPUT_SIZE);
+        self.pending_output_limit = limit;
+        self.pending_output_policy = policy;
+    }
+
+    /// Allocates room for as many pending inputs as the limit allows, and for the input that exceeds the limit before the peer is disconnected.
+    pub(crate) fn preallocate(&mut self) {
+        let additional = (self.pending_output_limit + 1).saturating_sub(self.pending_output.len());
+        self.pending_output.reserve_exact(additional);
+    }
+
+    /// Only endpoints talking to a host accept chunks of the input history.
+    pub(crate) fn set_accept_input_history(&mut self, accept: bool) {
+        self.accept_input_history = accept;
+    }
+
     pub(crate) fn set_fps(&mut self, fps: u32) {
         assert!(fps > 0);
         self.fps = fps;
@@ -205,21 +541,113 @@ impl UdpProtocol {
             return None;
         }
 
-        let now = millis_since_epoch();
+        let now = self.time_source.now().as_millis();
         let total_bytes_sent = self.bytes_sent + (self.packets_sent * UDP_HEADER_SIZE);
-        let seconds = (now - self.stats_start_time) / 1000;
+        let seconds = std::cmp::max(1, (now - self.stats_start_time) / 1000);
         let bps = total_bytes_sent / seconds as usize;
         //let upd_overhead = (self.packets_sent * UDP_HEADER_SIZE) / self.bytes_sent;
+        let packets_expected = self.packets_received + self.packets_lost;
+        let packet_loss = if packets_expected == 0 {
+            0.0
+        } else {
+            self.packets_lost as f32 / packets_expected as f32
+        };
 
         Some(NetworkStats {
-            ping: self.round_trip_time,
+            ping: self.smoothed_ping(),
+            raw_ping: self.round_trip_time,
+            ping_variance: self.round_trip_time_variance.round() as u128,
             send_queue_len: self.pending_output.len(),
             kbps_sent: bps / 1024,
-            local_frames_behind: self.local_frame_advantage,
-            remote_frames_behind: self.remote_frame_advantage,
+            local_frames_behind: self.local_frame_advantage.round() as i32,
+            remote_frames_behind: self.remote_frame_advantage.round() as i32,
+            remote_fps: self.remote_fps,
+            packets_sent: self.packets_sent,
+            packets_received: self.packets_received,
+            packet_loss,
+            packets_out_of_order: self.packets_out_of_order,
+            packets_duplicated: self.packets_duplicated,
+            packets_stale: self.packets_stale,
         })
     }
 
+    /// Returns the smoothed roundtrip time in milliseconds, which does not jump with every measurement like the raw roundtrip time.
+    fn smoothed_ping(&self) -> u128 {
+        self.smoothed_round_trip_time
+            .map_or(self.round_trip_time, |srtt| srtt.round() as u128)
+    }
+
+    /// Returns how long a reply to a message can take in the worst case, going by the roundtrip times so far. This is the retransmission timeout of RFC 6298.
+    fn expected_reply_time(&self) -> Duration {
+        let millis = self
+            .smoothed_round_trip_time
+            .map_or(0.0, |srtt| srtt + 4.0 * self.round_trip_time_variance);
+        Duration::from_millis(millis as u64)
+    }
+
+    pub(crate) const fn input_encoding_stats(&self) -> InputEncodingStats {
+        self.input_encoding_stats
+    }
+
+    pub(crate) const fn packets_sent(&self) -> usize {
+        self.packets_sent
+    }
+
+    pub(crate) const fn packets_received(&self) -> usize {
+        self.packets_received
+    }
+
+    pub(crate) const fn last_acked_frame(&self) -> Frame {
+        self.last_acked_input.frame
+    }
+
+    pub(crate) fn pending_output_len(&self) -> usize {
+        self.pending_output.len()
+    }
+
+    /// Sets the contribution of our session to the shared seed, which is sent to the peer with every sync request.
+    pub(crate) fn set_local_seed(&mut self, seed: u32) {
+        self.local_seed = seed;
+    }
+
+    /// Returns the contribution of the peer to the shared seed, if a sync request of the peer has arrived.
+    pub(crate) const fn remote_seed(&self) -> Option<u32> {
+        self.remote_seed
+    }
+
+    /// Returns true while a chunk of the input history has not been acknowledged.
+    pub(crate) fn is_sending_history(&self) -> bool {
+        self.pending_history.is_some()
+    }
+
+    /// Sends a message over the reliable channel. The message is resent until the peer acknowledges it.
+    pub(crate) fn send_reliable(&mut self, bytes: &[u8]) {
+        let msg = Reliable {
+            sequence: self.next_reliable_sequence,
+            bytes: bytes.to_vec(),
+        };
+        self.next_reliable_sequence = self.next_reliable_sequence.wrapping_add(1);
+        self.queue_message(MessageBody::Reliable(msg.clone()));
+        self.reliable_outgoing.push_back(msg);
+        self.reliable_last_sent = self.time_source.now();
+    }
+
+    /// Returns all messages received over the reliable channel since the last call, in the order they have been sent.
+    pub(crate) fn take_reliable_messages(&mut self) -> Vec<Vec<u8>> {
+        std::mem::take(&mut self.received_reliable)
+    }
+
+    /// Sends the checksum of the confirmed gamestate of `frame`. Checksums are not resent, so a lost checksum is simply not compared.
+    pub(crate) fn send_state_checksum(&mut self, frame: Frame, checksum: u64) {
+        if self.state != ProtocolState::Running {
+            return;
+        }
+        self.queue_message(MessageBody::StateChecksum(StateChecksum {
+            frame,
+            checksum,
+        }));
+    }
+
     pub(crate) fn is_synchronized(&self) -> bool {
         self.state == ProtocolState::Running
             || self.state == ProtocolState::Disconnected
@@ -230,12 +658,74 @@ impl UdpProtocol {
         self.state == ProtocolState::Running
     }
 
+    pub(crate) fn is_shut_down(&self) -> bool {
+        self.state == ProtocolState::Shutdown
+    }
+
     pub(crate) fn is_handling_message(&self, addr: &SocketAddr) -> bool {
         self.peer_addr == *addr
     }
 
+    /// Returns true if the message could have been sent by our peer from a new address. The peer proves its identity with the magic established
+    /// during synchronization and by only acknowledging inputs we actually sent.
+    pub(crate) fn is_migrating(&self, msg: &UdpMessage) -> bool {
+        if self.state != ProtocolState::Running
+            || self.remote_magic == 0
+            || msg.header.magic != self.remote_magic
+            || msg.header.session_id != self.session_id
+        {
+            return false;
+        }
+
+        let ack_frame = match &msg.body {
+            MessageBody::Input(body) => body.ack_frame,
+            MessageBody::InputAck(body) => body.ack_frame,
+            _ => return false,
+        };
+        let newest_sent_frame = match (self.pending_output.back(), &self.pending_history) {
+            (Some(input), _) => input.frame,
+            (None, Some(history)) => history.last_input.frame,
+            (None, None) => self.last_acked_input.frame,
+        };
+        ack_frame >= self.last_acked_input.frame && ack_frame <= newest_sent_frame
+    }
+
+    /// Sends all further messages to the given address and accepts messages from there.
+    pub(crate) const fn peer_addr(&self) -> SocketAddr {
+        self.peer_addr
+    }
+
+    pub(crate) fn set_peer_addr(&mut self, addr: SocketAddr) {
+        ggrs_info!("{} moved to {}", self.peer_addr, addr);
+        self.peer_addr = addr;
+    }
+
     pub(crate) fn peer_connect_status(&self, handle: PlayerHandle) -> ConnectionStatus {
-        self.peer_connect_status[handle]
+        self.peer_connect_status[handle.0]
+    }
+
+    pub(crate) const fn peer_pause_status(&self) -> PauseStatus {
+        self.peer_pause_status
+    }
+
+    /// Sets the pause status that is sent to the peer. A changed status is sent with the next poll, even if there are no new inputs.
+    pub(crate) fn set_pause_status(&mut self, pause_status: PauseStatus) {
+        if self.local_pause_status != pause_status {
+            self.local_pause_status = pause_status;
+            self.pause_status_pending = true;
+        }
+    }
+
+    pub(crate) const fn peer_fps_change(&self) -> FpsChange {
+        self.peer_fps_change
+    }
+
+    /// Sets the change of the frame rate that is sent to the peer. A changed value is sent with the next poll, even if there are no new inputs.
+    pub(crate) fn set_fps_change(&mut self, fps_change: FpsChange) {
+        if self.local_fps_change != fps_change {
+            self.local_fps_change = fps_change;
+            self.fps_change_pending = true;
+        }
     }
 
     pub(crate) fn disconnect(&mut self) {
@@ -243,38 +733,100 @@ impl UdpProtocol {
             return;
         }
 
+        ggrs_info!(
+            "Disconnecting endpoint {} at {}",
+            self.handle,
+            self.peer_addr
+        );
+        // if we were running, tell the peer right away that we disconnected them
+        self.disconnect_request_pending = self.state == ProtocolState::Running;
         self.state = ProtocolState::Disconnected;
         // schedule the timeout which will lead to shutdown
-        self.shutdown_timeout = Instant::now().add(Duration::from_millis(UDP_SHUTDOWN_TIMER))
+        self.shutdown_timeout = self
+            .time_source
+            .now()
+            .add(Duration::from_millis(UDP_SHUTDOWN_TIMER))
     }
 
     pub(crate) fn synchronize(&mut self) {
         assert_eq!(self.state, ProtocolState::Initializing);
+        ggrs_debug!("Starting synchronization with {}", self.peer_addr);
         self.state = ProtocolState::Synchronizing;
         self.sync_remaining_roundtrips = NUM_SYNC_PACKETS;
-        self.stats_start_time = millis_since_epoch();
+        self.sync_start_time = self.time_source.now();
+        self.sync_retries = 0;
+        self.stats_start_time = self.time_source.now().as_millis();
         self.send_sync_request();
     }
 
+    /// Runs the sync handshake again, with the same or a new address of the peer. The received inputs are kept, so the peer can continue
+    /// sending inputs where it left off.
+    pub(crate) fn resynchronize(&mut self, peer_addr: SocketAddr) {
+        self.peer_addr = peer_addr;
+        // the peer may have restarted, so accept messages with any magic and sequence numbers until we are synchronized again
+        self.remote_magic = 0;
+        self.remote_seed = None;
+        self.last_recv_sequence_number = None;
+        self.last_recv_time = self.time_source.now();
+        self.disconnect_notify_sent = false;
+        self.disconnect_event_sent = false;
+        self.disconnect_request_pending = false;
+        self.state = ProtocolState::Initializing;
+        self.synchronize();
+    }
+
     pub(crate) fn recommend_frame_delay(&self, require_idle_input: bool) -> u32 {
         self.time_sync_layer
             .recommend_frame_delay(require_idle_input)
     }
 
+    pub(crate) fn average_frame_advantage(&self) -> (f32, f32) {
+        self.time_sync_layer.average_frame_advantage()
+    }
+
     pub(crate) fn poll(&mut self, connect_status: &[ConnectionStatus]) -> Drain<Event> {
-        let now = Instant::now();
+        let now = self.time_source.now();
         match self.state {
             ProtocolState::Synchronizing => {
-                // some time has passed, let us send another sync request
-                if self.last_send_time + SYNC_RETRY_INTERVAL < now {
+                // give up if the peer did not answer in time
+                let retry_due = self.last_send_time + self.sync_retry_interval < now;
+                let retries_exhausted = matches!(self.max_sync_retries, Some(max_retries) if self.sync_retries >= max_retries);
+                let timed_out = matches!(self.sync_timeout, Some(timeout) if self.sync_start_time + timeout < now);
+                if timed_out || (retry_due && retries_exhausted) {
+                    ggrs_warn!("Synchronization with {} failed", self.peer_addr);
+                    self.disconnect();
+                    self.event_queue.push_back(Event::SyncFailed);
+                } else if retry_due {
+                    // some time has passed, let us send another sync request
+                    self.sync_retries += 1;
                     self.send_sync_request();
                 }
             }
             ProtocolState::Running => {
                 // resend pending inputs, if some time has passed without sending or receiving inputs
-                if self.running_last_input_recv + RUNNING_RETRY_INTERVAL < now {
+                if self.pause_status_pending
+                    || self.fps_change_pending
+                    || self.running_last_input_recv + RUNNING_RETRY_INTERVAL < now
+                {
                     self.send_pending_output(connect_status);
-                    self.running_last_input_recv = Instant::now();
+                    self.running_last_input_recv = self.time_source.now();
+                }
+
+                // resend the whole chunk of the input history, if it has not been acknowledged for some time
+                if let Some(history) = &self.pending_history {
+                    if history.last_sent + RUNNING_RETRY_INTERVAL < now {
+                        self.send_history_parts();
+                    }
+                }
+
+                // resend all unacknowledged reliable messages, if they have not been acknowledged for some time
+                if !self.reliable_outgoing.is_empty()
+                    && self.reliable_last_sent + RUNNING_RETRY_INTERVAL < now
+                {
+                    for msg in self.reliable_outgoing.clone() {
+                        self.queue_message(MessageBody::Reliable(msg));
+                    }
+                    self.reliable_last_sent = now;
                 }
 
                 // periodically send a quality report
@@ -283,15 +835,23 @@ impl UdpProtocol {
                 }
 
                 // send keep alive packet if we didn't send a packet for some time
-                if self.last_send_time + KEEP_ALIVE_INTERVAL < now {
+                if self.last_send_time + self.keep_alive_interval < now {
                     self.send_keep_alive();
                 }
 
-                // trigger a NetworkInterrupted event if we didn't receive a packet for some time
-                if !self.disconnect_notify_sent
-                    && self.last_recv_time + self.disconnect_notify_start < now
-                {
-                    let duration: Duration = self.disconnect_timeout - self.disconnect_notify_start;
+                // trigger a NetworkInterrupted event if we didn't receive a packet for some time.
+                // On slow and jittery connections, replies regularly take longer, so we wait at least as long as a reply can be expected to take
+                let notify_start = std::cmp::min(
+                    std::cmp::max(self.disconnect_notify_start, self.expected_reply_time()),
+                    self.disconnect_timeout,
+                );
+                if !self.disconnect_notify_sent && self.last_recv_time + notify_start < now {
+                    let duration: Duration = self.disconnect_timeout - notify_start;
+                    ggrs_warn!(
+                        "Connection to {} interrupted, disconnecting in {} ms",
+                        self.peer_addr,
+                        duration.as_millis()
+                    );
                     self.event_queue.push_back(Event::NetworkInterrupted {
                         disconnect_timeout: Duration::as_millis(&duration),
                     });
@@ -302,12 +862,21 @@ impl UdpProtocol {
                 if !self.disconnect_event_sent
                     && self.last_recv_time + self.disconnect_timeout < now
                 {
+                    ggrs_warn!("Connection to {} timed out", self.peer_addr);
                     self.event_queue.push_back(Event::Disconnected);
                     self.disconnect_event_sent = true;
                 }
             }
             ProtocolState::Disconnected => {
-                if self.shutdown_timeout < Instant::now() {
+                // let the peer know that we disconnected them until we shut down, in case the message gets lost
+                if self.disconnect_request_pending
+                    || self.last_send_time + RUNNING_RETRY_INTERVAL < now
+                {
+                    self.send_pending_output(connect_status);
+                    self.disconnect_request_pending = false;
+                }
+
+                if self.shutdown_timeout < self.time_source.now() {
                     self.state = ProtocolState::Shutdown;
                 }
             }
@@ -317,6 +886,12 @@ impl UdpProtocol {
     }
 
     fn pop_pending_output(&mut self, ack_frame: Frame) {
+        if let Some(history) = &self.pending_history {
+            if history.last_input.frame <= ack_frame {
+                self.last_acked_input = history.last_input;
+                self.pending_history = None;
+            }
+        }
         while let Some(input) = self.pending_output.front() {
             if input.frame <= ack_frame {
                 self.last_acked_input = *input;
@@ -331,15 +906,19 @@ impl UdpProtocol {
      *  SENDING MESSAGES
      */
 
-    pub(crate) fn send_all_messages(&mut self, socket: &NonBlockingSocket) {
-        if self.state == ProtocolState::Shutdown {
-            self.send_queue.drain(..);
-            return;
+    pub(crate) fn send_all_messages(&mut self, socket: &mut dyn NonBlockingSocket) {
+        for msg in self.take_messages() {
+            socket.send_to(&msg, self.peer_addr);
         }
+    }
 
-        for msg in self.send_queue.drain(..) {
-            socket.send_to(&msg, self.peer_addr);
+    /// Empties the send queue and returns the messages, so the caller can send them itself.
+    pub(crate) fn take_messages(&mut self) -> Vec<UdpMessage> {
+        if self.state == ProtocolState::Shutdown {
+            self.send_queue.clear();
+            return Vec::new();
         }
+        self.send_queue.drain(..).collect()
     }
 
     pub(crate) fn send_input(&mut self, input: GameInput, connect_status: &[ConnectionStatus]) {
@@ -354,21 +933,168 @@ impl UdpProtocol {
             self.remote_frame_advantage,
         );
 
+        self.queue_input(input);
+
+        // coalesce inputs, if configured. Unsent inputs are sent with the next packet or resent after some time anyway
+        self.unsent_inputs += 1;
+        let send_now = if self.sparse_input_interval > 0 {
+            input.input() != self.previous_input.input()
+                || self.unsent_inputs >= self.sparse_input_interval
+        } else {
+            self.unsent_inputs >= self.input_send_interval
+        };
+        self.previous_input = input;
+        if send_now {
+            self.send_pending_output(connect_status);
+        }
+    }
+
+    /// Adds the input to the pending inputs without sending it. The input is sent with the next input message or by `send_encoded_inputs()`.
+    pub(crate) fn queue_input(&mut self, input: GameInput) {
+        if self.state != ProtocolState::Running {
+            return;
+        }
+
         self.pending_output.push_back(input);
-        if self.pending_output.len() > PENDING_OUTPUT_SIZE {
-            if self.handle >= 1000 {
-                // if this is a spectator that didn't ack our input, we just disconnect them
-                self.event_queue.push_back(Event::Disconnected);
-            } else {
-                // we should never have so much pending input for a remote player (if they didn't ack, we should stop at MAX_PREDICTION_THRESHOLD)
-                assert!(self.pending_output.len() <= PENDING_OUTPUT_SIZE);
+        if self.pending_output.len() <= self.pending_output_limit {
+            return;
+        }
+        match self.pending_output_policy {
+            PendingOutputPolicy::Disconnect => {
+                if !self.disconnect_event_sent {
+                    ggrs_warn!("{} did not acknowledge inputs in time", self.peer_addr);
+                    self.event_queue.push_back(Event::Disconnected);
+                    self.disconnect_event_sent = true;
+                }
             }
+            PendingOutputPolicy::DropOldest => {
+                // the dropped inputs become the reference for the delta encoding, just like acknowledged inputs
+                let mut count = 0;
+                while self.pending_output.len() > self.pending_output_limit {
+                    if let Some(dropped) = self.pending_output.pop_front() {
+                        self.last_acked_input = dropped;
+                        count += 1;
+                    }
+                }
+                ggrs_warn!(
+                    "{} did not acknowledge inputs in time, dropping {} pending inputs",
+                    self.peer_addr,
+                    count
+                );
+                self.event_queue
+                    .push_back(Event::PendingInputsDropped { count });
+            }
+            // the session stops advancing at the limit, so inputs queued anyway are kept
+            PendingOutputPolicy::BlockAdvance => (),
+        }
+    }
+
+    /// Returns true if the session should not advance, because the peer has not acknowledged as many inputs as allowed.
+    pub(crate) fn is_pending_output_full(&self) -> bool {
+        self.state == ProtocolState::Running
+            && self.pending_output_policy == PendingOutputPolicy::BlockAdvance
+            && self.pending_output.len() >= self.pending_output_limit
+    }
+
+    /// Sends pending inputs that have already been encoded, starting at `start_frame` and delta-encoded against the input of `reference_frame`,
+    /// or against a blank input if `reference_frame` is `None`. This way, the same encoded inputs can be sent to several peers,
+    /// e.g. batches of inputs broadcast to spectators.
+    pub(crate) fn send_encoded_inputs(
+        &mut self,
+        start_frame: Frame,
+        reference_frame: Option<Frame>,
+        bytes: &[u8],
+        connect_status: &[ConnectionStatus],
+    ) {
+        if self.state != ProtocolState::Running {
+            return;
+        }
+        assert!(bytes.len() <= MAX_PAYLOAD);
+
+        let frames = self
+            .pending_output
+            .iter()
+            .filter(|input| input.frame >= start_frame)
+            .count();
+        self.input_encoding_stats
+            .record_packet(frames * self.input_size, bytes.len());
+
+        let body = Input {
+            start_frame,
+            keyframe: reference_frame.is_none(),
+            reference_frame: reference_frame.unwrap_or(NULL_FRAME),
+            bytes: bytes.to_vec(),
+            ..Input::default()
+        };
+        self.send_input_message(body, connect_status);
+    }
+
+    /// Sends a chunk of the input history, which has to continue right after the last acknowledged input. The inputs are encoded against a blank input
+    /// and split into as many parts as needed. Until the peer acknowledges the chunk, no other inputs can be sent and the chunk is resent periodically.
+    pub(crate) fn send_input_history(&mut self, inputs: &[GameInput]) {
+        if self.state != ProtocolState::Running {
+            return;
+        }
+        assert!(self.pending_output.is_empty() && self.pending_history.is_none());
+        let (first, last) = match (inputs.first(), inputs.last()) {
+            (Some(first), Some(last)) => (first, last),
+            _ => return,
+        };
+        assert!(first.frame == self.last_acked_input.frame + 1);
+
+        let keyframe = Reference::Keyframe {
+            input_size: self.input_size,
+        };
+        let bytes = encode(keyframe, inputs.iter());
+        self.input_encoding_stats
+            .record_packet(inputs.len() * self.input_size, bytes.len());
+        let num_parts: u8 = bytes
+            .chunks(MAX_PAYLOAD)
+            .len()
+            .try_into()
+            .expect("input history chunk too large");
+        let parts = bytes
+            .chunks(MAX_PAYLOAD)
+            .enumerate()
+            .map(|(part, bytes)| InputHistory {
+                start_frame: first.frame,
+                part: part as u8,
+                num_parts,
+                bytes: bytes.to_vec(),
+            })
+            .collect();
+
+        ggrs_debug!(
+            "Sending the inputs of frames {} to {} to {} in {} parts",
+            first.frame,
+            last.frame,
+            self.peer_addr,
+            num_parts
+        );
+        self.pending_history = Some(PendingHistory {
+            parts,
+            last_input: *last,
+            last_sent: self.time_source.now(),
+        });
+        self.send_history_parts();
+    }
+
+    fn send_history_parts(&mut self) {
+        let parts = match &mut self.pending_history {
+            Some(history) => {
+                history.last_sent = self.time_source.now();
+                history.parts.clone()
+            }
+            None => return,
+        };
+        for part in parts {
+            self.queue_message(MessageBody::InputHistory(part));
         }
-        self.send_pending_output(connect_status);
     }
 
     fn send_pending_output(&mut self, connect_status: &[ConnectionStatus]) {
         let mut body = Input::default();
+        self.unsent_inputs = 0;
 
         if let Some(input) = self.pending_output.front() {
             assert!(
@@ -377,17 +1103,48 @@ impl UdpProtocol {
             );
             body.start_frame = input.frame;
         } else {
-            body.start_frame = 0;
+            // without inputs, the message still starts where the next input would, right after the reference
+            body.start_frame = std::cmp::max(self.last_acked_input.frame + 1, Frame(0));
         }
 
-        // encode all pending inputs to a byte buffer
-        body.bytes = encode(&self.last_acked_input, self.pending_output.iter());
+        // until the peer acknowledged an input, we send keyframes, which the peer can decode without knowing any previous input
+        let reference = if self.last_acked_input.frame == NULL_FRAME {
+            body.keyframe = true;
+            Reference::Keyframe {
+                input_size: self.input_size,
+            }
+        } else {
+            Reference::Input(&self.last_acked_input)
+        };
+        body.reference_frame = self.last_acked_input.frame;
+
+        // encode all pending inputs to a byte buffer. With sparse inputs, unchanged inputs are encoded only once
+        if self.sparse_input_interval > 0 {
+            let (inputs, runs) = collapse_runs(self.pending_output.iter());
+            body.bytes = encode(reference, inputs.iter());
+            body.frame_runs = runs;
+        } else {
+            body.bytes = encode(reference, self.pending_output.iter());
+        }
 
         // the byte buffer should not exceed a certain size to guarantee a maximum UDP packet size
         assert!(body.bytes.len() <= MAX_PAYLOAD);
+        self.input_encoding_stats.record_packet(
+            self.pending_output.len() * self.input_size,
+            body.bytes.len(),
+        );
+
+        self.send_input_message(body, connect_status);
+    }
 
+    /// Adds our acknowledgement and the statuses we have to tell our peer about to the input message and queues it.
+    fn send_input_message(&mut self, mut body: Input, connect_status: &[ConnectionStatus]) {
         body.ack_frame = self.last_received_input.frame;
         body.disconnect_requested = self.state == ProtocolState::Disconnected;
+        body.pause_status = self.local_pause_status;
+        self.pause_status_pending = false;
+        body.fps_change = self.local_fps_change;
+        self.fps_change_pending = false;
         body.peer_connect_status = connect_status.to_owned();
 
         self.queue_message(MessageBody::Input(body));
@@ -409,16 +1166,18 @@ impl UdpProtocol {
         self.sync_random_request = rand::random::<u32>();
         let body = SyncRequest {
             random_request: self.sync_random_request,
+            fps: self.fps,
+            seed: self.local_seed,
         };
         self.queue_message(MessageBody::SyncRequest(body));
     }
 
     fn send_quality_report(&mut self) {
-        self.running_last_quality_report = Instant::now();
+        self.running_last_quality_report = self.time_source.now();
         let body = QualityReport {
-            frame_advantage: i8::try_from(self.local_frame_advantage)
-                .expect("local_frame_advantage bigger than i8::MAX"),
-            ping: millis_since_epoch(),
+            // float to integer casts saturate, so a remote client far ahead or behind does not overflow the report
+            frame_advantage: (self.local_frame_advantage * FRAME_ADVANTAGE_SCALE).round() as i32,
+            ping: self.time_source.now().as_millis(),
         };
 
         self.queue_message(MessageBody::QualityReport(body));
@@ -426,11 +1185,16 @@ impl UdpProtocol {
 
     fn queue_message(&mut self, body: MessageBody) {
         // set the header
-        let header = MessageHeader { magic: self.magic };
+        let header = MessageHeader {
+            magic: self.magic,
+            session_id: self.session_id,
+            sequence_number: self.next_sequence_number,
+        };
         let msg = UdpMessage { header, body };
+        self.next_sequence_number = self.next_sequence_number.wrapping_add(1);
 
         self.packets_sent += 1;
-        self.last_send_time = Instant::now();
+        self.last_send_time = self.time_source.now();
         self.bytes_sent += std::mem::size_of_val(&msg);
 
         // add the packet to the back of the send queue
@@ -447,17 +1211,40 @@ impl UdpProtocol {
             return;
         }
 
+        // drop everything above the rate limit, so a flood of messages can not keep us busy
+        if !self.check_rate_limit(msg) {
+            return;
+        }
+
+        // filter packets of other matches
+        if msg.header.session_id != self.session_id {
+            ggrs_trace!(
+                "Dropping message from {} of another session",
+                self.peer_addr
+            );
+            return;
+        }
+
         // filter packets that don't match the magic if we have set it already
         if self.remote_magic != 0 && msg.header.magic != self.remote_magic {
+            ggrs_trace!("Dropping message from {} with wrong magic", self.peer_addr);
+            return;
+        }
+
+        // duplicated and stale messages are dropped before they can touch any state. Before synchronizing, the messages could still be from
+        // an earlier run of the peer that started over with the same sequence numbers, and the sync messages do not mind duplicates anyway
+        if !self.track_sequence_number(msg.header.sequence_number) && self.remote_magic != 0 {
             return;
         }
 
         // update time when we last received packages
-        self.last_recv_time = Instant::now();
+        self.last_recv_time = self.time_source.now();
+        self.packets_received += 1;
 
         // if the connection has been marked as interrupted, send an event to signal we are receiving again
         if self.disconnect_notify_sent && self.state == ProtocolState::Running {
             self.disconnect_notify_sent = false;
+            ggrs_info!("Connection to {} resumed", self.peer_addr);
             self.event_queue.push_back(Event::NetworkResumed);
         }
 
@@ -470,17 +1257,141 @@ impl UdpProtocol {
             MessageBody::QualityReport(body) => self.on_quality_report(body),
             MessageBody::QualityReply(body) => self.on_quality_reply(body),
             MessageBody::KeepAlive => (),
+            MessageBody::InputHistory(body) => self.on_input_history(body),
+            MessageBody::Reliable(body) => self.on_reliable(body),
+            MessageBody::ReliableAck(body) => self.on_reliable_ack(*body),
+            MessageBody::StateChecksum(body) => self.on_state_checksum(*body),
+        }
+    }
+
+    /// Counts the gaps in the sequence numbers of received messages as lost messages. A message arriving after a later message has been counted as lost
+    /// when the later message arrived, so it is counted as out of order instead. Returns false if the message is a duplicate of an already received message
+    /// or too old to tell, in which case it should be dropped.
+    fn track_sequence_number(&mut self, sequence_number: u16) -> bool {
+        let last = match self.last_recv_sequence_number {
+            Some(last) => last,
+            None => {
+                self.reset_sequence_window(sequence_number);
+                return true;
+            }
+        };
+
+        // sequence numbers wrap around, so only the numbers a little ahead of the newest one are newer
+        let distance = sequence_number.wrapping_sub(last);
+        let behind = last.wrapping_sub(sequence_number);
+        if distance == 0 {
+            self.packets_duplicated += 1;
+            false
+        } else if distance <= MAX_SEQUENCE_JUMP {
+            self.packets_lost += distance as usize - 1;
+            self.recent_sequence_numbers = if distance < SEQUENCE_WINDOW {
+                self.recent_sequence_numbers << distance | 1
+            } else {
+                1
+            };
+            self.last_recv_sequence_number = Some(sequence_number);
+            true
+        } else if behind < SEQUENCE_WINDOW {
+            let bit = 1 << behind;
+            if self.recent_sequence_numbers & bit != 0 {
+                self.packets_duplicated += 1;
+                return false;
+            }
+            self.recent_sequence_numbers |= bit;
+            self.packets_out_of_order += 1;
+            self.packets_lost = self.packets_lost.saturating_sub(1);
+            true
+        } else if behind <= MAX_SEQUENCE_JUMP {
+            self.packets_stale += 1;
+            false
+        } else {
+            // the peer has started sending with different sequence numbers, e.g. after restoring its session
+            ggrs_debug!(
+                "Sequence numbers of {} jumped from {} to {}",
+                self.peer_addr,
+                last,
+                sequence_number
+            );
+            self.reset_sequence_window(sequence_number);
+            true
         }
     }
 
+    fn reset_sequence_window(&mut self, sequence_number: u16) {
+        self.last_recv_sequence_number = Some(sequence_number);
+        self.recent_sequence_numbers = 1;
+    }
+
+    /// Counts the message towards the rate limit of the current second and returns false if the limit is exceeded. Exceeding the limit is reported once per second.
+    fn check_rate_limit(&mut self, msg: &UdpMessage) -> bool {
+        if self.max_messages_per_second == 0 && self.max_bytes_per_second == 0 {
+            return true;
+        }
+
+        let now = self.time_source.now();
+        if now - self.rate_window_start >= Duration::from_secs(1) {
+            self.rate_window_start = now;
+            self.messages_in_window = 0;
+            self.bytes_in_window = 0;
+            self.rate_limit_reported = false;
+        }
+
+        self.messages_in_window += 1;
+        self.bytes_in_window += msg.encoded_len();
+        let exceeded = (self.max_messages_per_second > 0
+            && self.messages_in_window > self.max_messages_per_second)
+            || (self.max_bytes_per_second > 0 && self.bytes_in_window > self.max_bytes_per_second);
+
+        if exceeded && !self.rate_limit_reported {
+            ggrs_warn!(
+                "{} exceeded the rate limit, dropping messages",
+                self.peer_addr
+            );
+            self.event_queue.push_back(Event::RateLimitExceeded);
+            self.rate_limit_reported = true;
+        }
+        !exceeded
+    }
+
     /// Upon receiving a `SyncRequest`, answer with a `SyncReply` with the proper data
     fn on_sync_request(&mut self, body: SyncRequest) {
+        // peers running at different FPS would drift apart, so we refuse to synchronize with them
+        if !self.check_remote_fps(body.fps) {
+            return;
+        }
+        self.remote_seed = Some(body.seed);
+        let token_proof = match &self.connection_token {
+            Some(token) => token.proof(body.random_request, self.magic),
+            None => 0,
+        };
         let reply_body = SyncReply {
             random_reply: body.random_request,
+            fps: self.fps,
+            token_proof,
         };
         self.queue_message(MessageBody::SyncReply(reply_body));
     }
 
+    /// Remembers the FPS of the remote client and returns false if it differs from our own. The mismatch is reported only once.
+    fn check_remote_fps(&mut self, remote_fps: u32) -> bool {
+        self.remote_fps = remote_fps;
+        if remote_fps == self.fps {
+            return true;
+        }
+        if !self.fps_mismatch_reported {
+            ggrs_warn!(
+                "{} runs at {} FPS, but we run at {} FPS",
+                self.peer_addr,
+                remote_fps,
+                self.fps
+            );
+            self.event_queue
+                .push_back(Event::FpsMismatch { remote_fps });
+            self.fps_mismatch_reported = true;
+        }
+        false
+    }
+
     /// Upon receiving a `SyncReply`, check validity and either continue the synchronization process or conclude synchronization.
     fn on_sync_reply(&mut self, header: MessageHeader, body: SyncReply) {
         // ignore sync replies when not syncing
@@ -491,9 +1402,24 @@ impl UdpProtocol {
         if self.sync_random_request != body.random_reply {
             return;
         }
+        // peers that do not know the connection token never pass synchronization
+        if let Some(token) = &self.connection_token {
+            if body.token_proof != token.proof(body.random_reply, header.magic) {
+                ggrs_warn!("{} does not know the connection token", self.peer_addr);
+                return;
+            }
+        }
+        if !self.check_remote_fps(body.fps) {
+            return;
+        }
         // the sync reply is good, so we send a sync request again until we have finished the required roundtrips. Then, we can conclude the syncing process.
         self.sync_remaining_roundtrips -= 1;
         if self.sync_remaining_roundtrips > 0 {
+            ggrs_trace!(
+                "Synchronizing with {}: {} roundtrips remaining",
+                self.peer_addr,
+                self.sync_remaining_roundtrips
+            );
             // register an event
             let evt = Event::Synchronizing {
                 total: NUM_SYNC_PACKETS,
@@ -504,15 +1430,32 @@ impl UdpProtocol {
             self.send_sync_request();
         } else {
             // switch to running state
+            ggrs_info!("Synchronized with {}", self.peer_addr);
             self.state = ProtocolState::Running;
             // register an event
             self.event_queue.push_back(Event::Synchronized);
             // the remote endpoint is now "authorized"
             self.remote_magic = header.magic;
+            // only count the messages of the synchronized endpoint
+            self.reset_sequence_window(header.sequence_number);
+            self.packets_lost = 0;
+            self.packets_out_of_order = 0;
+            self.packets_duplicated = 0;
+            self.packets_stale = 0;
         }
     }
 
     fn on_input(&mut self, body: &Input) {
+        // the message comes from the network, so we check it before touching any state
+        let recv_inputs = match self.validate_input(body) {
+            Some(recv_inputs) => recv_inputs,
+            None => {
+                ggrs_warn!("Dropping malformed input message from {}", self.peer_addr);
+                self.event_queue.push_back(Event::InvalidMessage);
+                return;
+            }
+        };
+
         // drop pending outputs until the ack frame
         self.pop_pending_output(body.ack_frame);
 
@@ -535,24 +1478,24 @@ impl UdpProtocol {
             }
         }
 
-        // this input has not been encoded with what we expect, so we drop the whole thing
-        // TODO: this could be made so much more efficient if we kept more received input history
-        // so we can properly decode with the right reference
-        assert!(
-            self.last_received_input.frame == NULL_FRAME
-                || self.last_received_input.frame + 1 >= body.start_frame
-        );
-        if self.last_received_input.frame != NULL_FRAME
-            && self.last_received_input.frame + 1 != body.start_frame
-        {
+        // the newest pause status always wins, regardless of the order messages arrive in
+        self.peer_pause_status = std::cmp::max(self.peer_pause_status, body.pause_status);
+        self.peer_fps_change = std::cmp::max(self.peer_fps_change, body.fps_change);
+
+        // this input has not been encoded with a reference we know, so we drop the whole thing.
+        // Keyframes can always be decoded, even if they start before the next frame we expect
+        if !body.keyframe && body.reference_frame != self.last_received_input.frame {
+            ggrs_debug!(
+                "Dropping input from {} starting at frame {} encoded against frame {}, expected frame {}",
+                self.peer_addr,
+                body.start_frame,
+                body.reference_frame,
+                self.last_received_input.frame + 1
+            );
             return;
         }
 
-        self.running_last_input_recv = Instant::now();
-
-        // we know everything is correct, so we decode
-        let recv_inputs = decode(&self.last_received_input, body.start_frame, &body.bytes)
-            .expect("decoding failed");
+        self.running_last_input_recv = self.time_source.now();
 
         for game_input in &recv_inputs {
             // skip inputs that we don't need
@@ -568,6 +1511,166 @@ impl UdpProtocol {
         self.send_input_ack();
     }
 
+    /// Checks an input message against everything a well-behaved peer would send and decodes the inputs. Returns `None` if the message is malformed.
+    fn validate_input(&self, body: &Input) -> Option<Vec<GameInput>> {
+        if body.peer_connect_status.len() != self.peer_connect_status.len() || body.start_frame < 0
+        {
+            return None;
+        }
+
+        // a new change of the frame rate is sent with the first inputs for frames before the change, so it cannot be scheduled for a frame we already have inputs for
+        let fps_change = body.fps_change;
+        if fps_change > self.peer_fps_change
+            && (fps_change.frame <= self.last_received_input.frame
+                || fps_change.frame < 0
+                || fps_change.fps == 0
+                || fps_change.fps > MAX_FPS
+                || fps_change.timescale == 0
+                || fps_change.timescale > MAX_TIMESCALE)
+        {
+            return None;
+        }
+
+        // keyframes have no reference, and all other inputs continue right after their reference
+        if body.keyframe != (body.reference_frame == NULL_FRAME)
+            || (!body.keyframe && body.reference_frame + 1 != body.start_frame)
+        {
+            return None;
+        }
+
+        // a peer sends all inputs we have not acknowledged, so the inputs can never start after the next frame we expect
+        let last_received_frame = self.last_received_input.frame;
+        if last_received_frame != NULL_FRAME && body.start_frame > last_received_frame + 1 {
+            return None;
+        }
+
+        // a peer never has more pending inputs than we are willing to decode
+        let max_len = PENDING_OUTPUT_SIZE * self.input_size;
+        let reference = if body.keyframe {
+            Reference::Keyframe {
+                input_size: self.input_size,
+            }
+        } else {
+            Reference::Input(&self.last_received_input)
+        };
+        let mut recv_inputs = decode(reference, body.start_frame, &body.bytes, max_len).ok()?;
+        if !body.frame_runs.is_empty() {
+            recv_inputs = expand_runs(&recv_inputs, &body.frame_runs, PENDING_OUTPUT_SIZE)?;
+        }
+
+        // the inputs have to fit into the input queues, even for the very first input
+        if let Some(newest) = recv_inputs.last() {
+            if newest.frame > last_received_frame + PENDING_OUTPUT_SIZE as i32 {
+                return None;
+            }
+        }
+        Some(recv_inputs)
+    }
+
+    /// Delivers the next reliable message in order. Duplicates and messages that arrive before their predecessors are dropped, the sender resends them.
+    /// Every reliable message is acknowledged, so the sender learns about lost acknowledgements as well.
+    fn on_reliable(&mut self, body: &Reliable) {
+        if body.sequence == self.expected_reliable_sequence {
+            self.received_reliable.push(body.bytes.clone());
+            self.expected_reliable_sequence = self.expected_reliable_sequence.wrapping_add(1);
+        }
+        self.queue_message(MessageBody::ReliableAck(ReliableAck {
+            next_sequence: self.expected_reliable_sequence,
+        }));
+    }
+
+    fn on_reliable_ack(&mut self, body: ReliableAck) {
+        // sequence numbers wrap around, so the acknowledged messages are the ones before `next_sequence`
+        while let Some(msg) = self.reliable_outgoing.front() {
+            if (msg.sequence.wrapping_sub(body.next_sequence) as i32) >= 0 {
+                break;
+            }
+            self.reliable_outgoing.pop_front();
+        }
+    }
+
+    fn on_state_checksum(&mut self, body: StateChecksum) {
+        if self.state != ProtocolState::Running {
+            return;
+        }
+        if body.frame < 0 {
+            ggrs_warn!("Dropping malformed state checksum from {}", self.peer_addr);
+            self.event_queue.push_back(Event::InvalidMessage);
+            return;
+        }
+        self.event_queue.push_back(Event::StateChecksum {
+            frame: body.frame,
+            checksum: body.checksum,
+        });
+    }
+
+    /// Upon receiving a part of the input history, store it until all parts of the chunk have arrived. Then, hand all inputs of the chunk to the session.
+    fn on_input_history(&mut self, body: &InputHistory) {
+        if !self.accept_input_history
+            || body.num_parts == 0
+            || body.part >= body.num_parts
+            || body.start_frame < 0
+            || body.bytes.len() > MAX_PAYLOAD
+        {
+            ggrs_warn!("Dropping malformed input history from {}", self.peer_addr);
+            self.event_queue.push_back(Event::InvalidMessage);
+            return;
+        }
+
+        // we already have these inputs, but the peer has not received our acknowledgement
+        let last_received_frame = self.last_received_input.frame;
+        if body.start_frame <= last_received_frame {
+            self.send_input_ack();
+            return;
+        }
+        if body.start_frame != last_received_frame + 1 {
+            ggrs_debug!(
+                "Dropping input history from {} starting at frame {}, expected frame {}",
+                self.peer_addr,
+                body.start_frame,
+                last_received_frame + 1
+            );
+            return;
+        }
+
+        // a new chunk replaces the parts of a chunk we never completed
+        if self.received_history_start != body.start_frame
+            || self.received_history.len() != body.num_parts as usize
+        {
+            self.received_history_start = body.start_frame;
+            self.received_history = vec![None; body.num_parts as usize];
+        }
+        self.received_history[body.part as usize] = Some(body.bytes.clone());
+        if self.received_history.iter().any(Option::is_none) {
+            return;
+        }
+
+        let bytes: Vec<u8> = std::mem::take(&mut self.received_history)
+            .into_iter()
+            .flatten()
+            .flatten()
+            .collect();
+        let keyframe = Reference::Keyframe {
+            input_size: self.input_size,
+        };
+        let max_len = std::cmp::max(HISTORY_CHUNK_BYTES, self.input_size);
+        let recv_inputs = match decode(keyframe, body.start_frame, bytes, max_len) {
+            Ok(recv_inputs) => recv_inputs,
+            Err(_) => {
+                ggrs_warn!("Dropping malformed input history from {}", self.peer_addr);
+                self.event_queue.push_back(Event::InvalidMessage);
+                return;
+            }
+        };
+
+        self.running_last_input_recv = self.time_source.now();
+        for game_input in recv_inputs {
+            self.last_received_input = game_input;
+            self.event_queue.push_back(Event::Input(game_input));
+        }
+        self.send_input_ack();
+    }
+
     /// Upon receiving a `InputAck`, discard the oldest buffered input including the acked input.
     fn on_input_ack(&mut self, body: InputAck) {
         self.pop_pending_output(body.ack_frame);
@@ -575,15 +1678,34 @@ impl UdpProtocol {
 
     /// Upon receiving a `QualityReport`, update network stats and reply with a `QualityReply`.
     fn on_quality_report(&mut self, body: &QualityReport) {
-        self.remote_frame_advantage = body.frame_advantage as i32;
+        self.remote_frame_advantage = body.frame_advantage as f32 / FRAME_ADVANTAGE_SCALE;
         let reply_body = QualityReply { pong: body.ping };
         self.queue_message(MessageBody::QualityReply(reply_body));
     }
 
     /// Upon receiving a `QualityReply`, update network stats.
     fn on_quality_reply(&mut self, body: &QualityReply) {
-        let millis = millis_since_epoch();
-        assert!(millis >= body.pong);
+        let millis = self.time_source.now().as_millis();
+        // a pong from the future has not been sent by us
+        if body.pong > millis {
+            ggrs_warn!("Dropping malformed quality reply from {}", self.peer_addr);
+            self.event_queue.push_back(Event::InvalidMessage);
+            return;
+        }
         self.round_trip_time = millis - body.pong;
+
+        // smooth the roundtrip time as described in RFC 6298, so single slow replies do not make the frame advantage estimate flap
+        let sample = self.round_trip_time as f32;
+        match self.smoothed_round_trip_time {
+            None => {
+                self.smoothed_round_trip_time = Some(sample);
+                self.round_trip_time_variance = sample / 2.0;
+            }
+            Some(srtt) => {
+                self.round_trip_time_variance = (1.0 - RTT_BETA) * self.round_trip_time_variance
+                    + RTT_BETA * (srtt - sample).abs();
+                self.smoothed_round_trip_time = Some((1.0 - RTT_ALPHA) * srtt + RTT_ALPHA * sample);
+            }
+        }
     }
 }
@@ -1,38 +1,189 @@
+use crate::network::codec::{MessageCodec, WireFormatCodec};
 use crate::network::udp_msg::UdpMessage;
-use std::io::ErrorKind;
-use std::net::{SocketAddr, ToSocketAddrs, UdpSocket};
+use std::io::{Error, ErrorKind};
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr, ToSocketAddrs, UdpSocket};
 
 const RECV_BUFFER_SIZE: usize = 4096;
 
+/// This trait is used to send and receive packets between sessions. GGRS provides a `UdpNonBlockingSocket`, but you can implement
+/// this trait for your own transport (or wrap another socket, e.g. to record traffic) and hand it to the `start_*_with_socket()` functions.
+/// Sockets have to be `Send`, so sessions can be moved to another thread.
+pub trait NonBlockingSocket: Send + std::fmt::Debug {
+    /// Takes a `UdpMessage` and sends it to the given address.
+    fn send_to(&mut self, msg: &UdpMessage, addr: SocketAddr);
+
+    /// This method should return all messages received since the last time this method was called.
+    /// The pairs `(SocketAddr, UdpMessage)` indicate from which address each packet was received.
+    fn receive_all_messages(&mut self) -> Vec<(SocketAddr, UdpMessage)>;
+
+    /// Returns all errors that occured since the last time this method was called. Sessions call this after receiving messages and
+    /// report the errors with `GGRSEvent::SocketError`. The default implementation reports no errors.
+    fn take_errors(&mut self) -> Vec<SocketError> {
+        Vec::new()
+    }
+
+    /// Returns statistics about the traffic on this socket, if the socket keeps track of them. The default implementation returns `None`.
+    fn stats(&self) -> Option<SocketStats> {
+        None
+    }
+}
+
+/// An error reported by the operating system while sending or receiving. Since these errors are not tied to a single player,
+/// they tell you whether your own socket is broken rather than a peer being silent.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct SocketError {
+    /// The kind of the error, e.g. `WouldBlock` if the send buffer was full.
+    pub kind: ErrorKind,
+    /// The platform-specific error code, e.g. `EHOSTUNREACH`, if there is one.
+    pub raw_os_error: Option<i32>,
+    /// The address a message was sent to when the error occured. `None` for errors while receiving.
+    pub addr: Option<SocketAddr>,
+}
+
+impl SocketError {
+    fn new(err: &Error, addr: Option<SocketAddr>) -> Self {
+        Self {
+            kind: err.kind(),
+            raw_os_error: err.raw_os_error(),
+            addr,
+        }
+    }
+}
+
+/// Statistics about all traffic on a socket, counted since the socket has been created.
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq)]
+pub struct SocketStats {
+    /// The number of datagrams sent successfully.
+    pub packets_sent: u64,
+    /// The number of bytes sent successfully.
+    pub bytes_sent: u64,
+    /// The number of datagrams received, including the dropped ones.
+    pub packets_received: u64,
+    /// The number of bytes received, including the dropped datagrams.
+    pub bytes_received: u64,
+    /// The number of datagrams that could not be sent, e.g. because the send buffer was full or the destination was unreachable.
+    pub send_errors: u64,
+    /// The number of errors while receiving.
+    pub receive_errors: u64,
+    /// The number of received datagrams that were no valid GGRS messages.
+    pub invalid_packets: u64,
+    /// The number of received datagrams that were too large for the receive buffer.
+    pub oversized_packets: u64,
+}
+
+/// A simple non-blocking UDP socket to use with GGRS sessions. Messages are encoded in the GGRS wire format, see `UdpMessage::to_bytes()`,
+/// unless you set another codec with `set_codec()`.
 #[derive(Debug)]
-pub(crate) struct NonBlockingSocket {
+pub struct UdpNonBlockingSocket {
     socket: UdpSocket,
     buffer: [u8; RECV_BUFFER_SIZE],
+    stats: SocketStats,
+    errors: Vec<SocketError>,
+    codec: Box<dyn MessageCodec>,
+    /// IPv6 sockets send to IPv4 peers through IPv4-mapped addresses.
+    ipv6: bool,
 }
 
-impl NonBlockingSocket {
-    pub(crate) fn new<A: ToSocketAddrs>(addr: A) -> Result<Self, std::io::Error> {
-        let socket = UdpSocket::bind(addr)?;
+impl UdpNonBlockingSocket {
+    /// Binds a non-blocking UDP socket to the given port on all IPv4 interfaces.
+    /// # Errors
+    /// - Will return an `std::io::Error` if the socket could not be bound.
+    pub fn bind_to_port(port: u16) -> Result<Self, std::io::Error> {
+        let addr = SocketAddr::new(IpAddr::V4(Ipv4Addr::UNSPECIFIED), port);
+        Self::bind(addr)
+    }
+
+    /// Binds a non-blocking UDP socket to the given port on all IPv6 interfaces. Whether the socket also reaches IPv4 peers depends on the platform:
+    /// Linux and macOS create dual-stack sockets by default, Windows does not. For a dual-stack socket, you can use IPv4 and IPv6 addresses for remote players alike.
+    /// # Errors
+    /// - Will return an `std::io::Error` if the socket could not be bound.
+    pub fn bind_to_port_v6(port: u16) -> Result<Self, std::io::Error> {
+        let addr = SocketAddr::new(IpAddr::V6(Ipv6Addr::UNSPECIFIED), port);
+        Self::bind(addr)
+    }
+
+    /// Binds a non-blocking UDP socket to the given local address, e.g. `"192.168.0.2:7777"` to only use a single interface or `"[::]:7777"` for IPv6.
+    /// If the address resolves to multiple addresses, the first one that can be bound is used.
+    /// # Errors
+    /// - Will return an `std::io::Error` if the address could not be resolved or the socket could not be bound.
+    pub fn bind<A: ToSocketAddrs>(addr: A) -> Result<Self, std::io::Error> {
+        Self::from_socket(UdpSocket::bind(addr)?)
+    }
+
+    /// Uses an already bound UDP socket, for example one that has been used for NAT traversal. The socket is set to non-blocking mode.
+    /// # Errors
+    /// - Will return an `std::io::Error` if the socket could not be set to non-blocking mode.
+    pub fn from_socket(socket: UdpSocket) -> Result<Self, std::io::Error> {
         socket.set_nonblocking(true)?;
+        let ipv6 = socket.local_addr()?.is_ipv6();
         Ok(Self {
             socket,
             buffer: [0; RECV_BUFFER_SIZE],
+            stats: SocketStats::default(),
+            errors: Vec::new(),
+            codec: Box::new(WireFormatCodec),
+            ipv6,
         })
     }
 
-    pub(crate) fn send_to<A: ToSocketAddrs>(&self, msg: &UdpMessage, addr: A) {
-        let buf = bincode::serialize(&msg).unwrap();
-        self.socket.send_to(&buf, addr).unwrap();
+    /// Returns the local address the socket is bound to.
+    /// # Errors
+    /// - Will return an `std::io::Error` if the address could not be queried.
+    pub fn local_addr(&self) -> Result<SocketAddr, std::io::Error> {
+        self.socket.local_addr()
+    }
+
+    /// Sets the codec that encodes sent and decodes received messages. All peers have to use the same codec.
+    pub fn set_codec(&mut self, codec: impl MessageCodec + 'static) {
+        self.codec = Box::new(codec);
+    }
+}
+
+impl NonBlockingSocket for UdpNonBlockingSocket {
+    fn send_to(&mut self, msg: &UdpMessage, addr: SocketAddr) {
+        let buf = self.codec.encode(msg);
+        let target = match addr {
+            SocketAddr::V4(v4) if self.ipv6 => {
+                SocketAddr::new(IpAddr::V6(v4.ip().to_ipv6_mapped()), v4.port())
+            }
+            _ => addr,
+        };
+        match self.socket.send_to(&buf, target) {
+            Ok(number_of_bytes) => {
+                self.stats.packets_sent += 1;
+                self.stats.bytes_sent += number_of_bytes as u64;
+            }
+            // the message is lost, but GGRS resends everything that matters
+            Err(err) => {
+                ggrs_warn!("Sending to {} failed: {}", addr, err);
+                self.stats.send_errors += 1;
+                self.errors.push(SocketError::new(&err, Some(addr)));
+            }
+        }
     }
 
-    pub(crate) fn receive_all_messages(&mut self) -> Vec<(SocketAddr, UdpMessage)> {
+    fn receive_all_messages(&mut self) -> Vec<(SocketAddr, UdpMessage)> {
         let mut received_messages = Vec::new();
         loop {
             match self.socket.recv_from(&mut self.buffer) {
                 Ok((number_of_bytes, src_addr)) => {
-                    assert!(number_of_bytes <= RECV_BUFFER_SIZE);
-                    let msg = bincode::deserialize(&self.buffer[0..number_of_bytes]).unwrap();
-                    received_messages.push((src_addr, msg));
+                    // IPv4 peers of a dual-stack socket are identified by their IPv4 address
+                    let src_addr = SocketAddr::new(src_addr.ip().to_canonical(), src_addr.port());
+                    self.stats.packets_received += 1;
+                    self.stats.bytes_received += number_of_bytes as u64;
+                    // datagrams larger than the buffer are truncated
+                    if number_of_bytes >= RECV_BUFFER_SIZE {
+                        ggrs_warn!("Dropping oversized packet from {}", src_addr);
+                        self.stats.oversized_packets += 1;
+                        continue;
+                    }
+                    // packets that are not valid GGRS messages are dropped
+                    if let Some(msg) = self.codec.decode(&self.buffer[0..number_of_bytes]) {
+                        received_messages.push((src_addr, msg));
+                    } else {
+                        ggrs_warn!("Dropping invalid packet from {}", src_addr);
+                        self.stats.invalid_packets += 1;
+                    }
                 }
                 // there are no more messages
                 Err(ref err) if err.kind() == ErrorKind::WouldBlock => return received_messages,
@@ -40,9 +191,96 @@ impl NonBlockingSocket {
                 Err(ref err) if err.kind() == ErrorKind::ConnectionReset => {
                     return received_messages
                 }
-                // all other errors cause a panic
-                Err(err) => panic!("{:?}: {} on {:?}", err.kind(), err, &self.socket),
+                // all other errors are reported, the messages received so far are still handed out
+                Err(err) => {
+                    ggrs_warn!("Receiving failed: {}", err);
+                    self.stats.receive_errors += 1;
+                    self.errors.push(SocketError::new(&err, None));
+                    return received_messages;
+                }
             }
         }
     }
+
+    fn take_errors(&mut self) -> Vec<SocketError> {
+        std::mem::take(&mut self.errors)
+    }
+
+    fn stats(&self) -> Option<SocketStats> {
+        Some(self.stats)
+    }
+}
+
+// #########
+// # TESTS #
+// #########
+
+#[cfg(test)]
+mod udp_socket_tests {
+
+    use super::*;
+    use crate::network::udp_msg::{MessageBody, MessageHeader};
+
+    #[test]
+    fn test_stats() {
+        let mut socket = UdpNonBlockingSocket::bind_to_port(0).unwrap();
+        let addr = SocketAddr::new(
+            IpAddr::V4(Ipv4Addr::LOCALHOST),
+            socket.socket.local_addr().unwrap().port(),
+        );
+        let msg = UdpMessage {
+            header: MessageHeader {
+                magic: 1,
+                session_id: 0,
+                sequence_number: 0,
+            },
+            body: MessageBody::KeepAlive,
+        };
+        let sender = UdpSocket::bind("127.0.0.1:0").unwrap();
+        sender.send_to(&[1, 2, 3], addr).unwrap();
+        sender.send_to(&[0; RECV_BUFFER_SIZE + 1], addr).unwrap();
+        socket.send_to(&msg, addr);
+        std::thread::sleep(std::time::Duration::from_millis(10));
+
+        assert_eq!(socket.receive_all_messages(), vec![(addr, msg)]);
+        let stats = socket.stats().unwrap();
+        assert_eq!(stats.packets_sent, 1);
+        assert_eq!(stats.packets_received, 3);
+        assert_eq!(stats.invalid_packets, 1);
+        assert_eq!(stats.oversized_packets, 1);
+        assert!(socket.take_errors().is_empty());
+    }
+
+    #[test]
+    fn test_dual_stack() {
+        // not every platform supports dual-stack sockets
+        let mut socket = match UdpNonBlockingSocket::bind_to_port_v6(0) {
+            Ok(socket) => socket,
+            Err(_) => return,
+        };
+        let port = socket.local_addr().unwrap().port();
+        let mut other = UdpNonBlockingSocket::bind("127.0.0.1:0").unwrap();
+        let other_addr = other.local_addr().unwrap();
+        let msg = UdpMessage {
+            header: MessageHeader {
+                magic: 1,
+                session_id: 0,
+                sequence_number: 0,
+            },
+            body: MessageBody::KeepAlive,
+        };
+        socket.send_to(&msg, other_addr);
+        if !socket.take_errors().is_empty() {
+            return;
+        }
+        std::thread::sleep(std::time::Duration::from_millis(10));
+        let received = other.receive_all_messages();
+        assert_eq!(received.len(), 1);
+        assert_eq!(received[0].0.port(), port);
+
+        // the answer arrives from the plain IPv4 address
+        other.send_to(&msg, received[0].0);
+        std::thread::sleep(std::time::Duration::from_millis(10));
+        assert_eq!(socket.receive_all_messages(), vec![(other_addr, msg)]);
+    }
 }
@@ -0,0 +1,780 @@
+use std::io::{Error, ErrorKind, Read, Write};
+use std::net::{SocketAddr, TcpListener, TcpStream, ToSocketAddrs};
+use std::time::{Duration, Instant};
+
+use rand::Rng;
+
+use crate::network::codec::{MessageCodec, WireFormatCodec};
+use crate::network::udp_msg::UdpMessage;
+use crate::network::udp_socket::{NonBlockingSocket, SocketError, SocketStats};
+
+/// Appended to the key of the client to compute the accept key of the server, as defined by RFC 6455.
+const WEBSOCKET_GUID: &str = "258EAFA5-E914-47DA-95CA-C5AB0DC85B11";
+/// The time the client waits for the handshake response of the relay.
+const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(5);
+/// Connections sending larger handshakes or messages are closed.
+const MAX_MESSAGE_SIZE: usize = 64 * 1024;
+/// If more bytes than this are waiting to be written to a connection, further packets are dropped like on a congested UDP link.
+const MAX_PENDING_BYTES: usize = 256 * 1024;
+const READ_BUFFER_SIZE: usize = 4096;
+
+const OPCODE_CONTINUATION: u8 = 0x0;
+const OPCODE_BINARY: u8 = 0x2;
+const OPCODE_CLOSE: u8 = 0x8;
+const OPCODE_PING: u8 = 0x9;
+const OPCODE_PONG: u8 = 0xA;
+
+/// Relay packet announcing the address under which the sender wants to be reachable.
+const RELAY_REGISTER: u8 = 0;
+/// Relay packet carrying a GGRS message. Clients put the destination address in it, the relay replaces it with the source address.
+const RELAY_DATA: u8 = 1;
+
+// ###########
+// # HELPERS #
+// ###########
+
+/// Computes the SHA-1 digest, which is only used for the accept key of the WebSocket handshake.
+fn sha1(data: &[u8]) -> [u8; 20] {
+    let mut h: [u32; 5] = [0x67452301, 0xEFCDAB89, 0x98BADCFE, 0x10325476, 0xC3D2E1F0];
+
+    let mut message = data.to_vec();
+    message.push(0x80);
+    while message.len() % 64 != 56 {
+        message.push(0);
+    }
+    message.extend_from_slice(&(data.len() as u64 * 8).to_be_bytes());
+
+    for chunk in message.chunks(64) {
+        let mut w = [0u32; 80];
+        for i in 0..16 {
+            w[i] = u32::from_be_bytes([
+                chunk[4 * i],
+                chunk[4 * i + 1],
+                chunk[4 * i + 2],
+                chunk[4 * i + 3],
+            ]);
+        }
+        for i in 16..80 {
+            w[i] = (w[i - 3] ^ w[i - 8] ^ w[i - 14] ^ w[i - 16]).rotate_left(1);
+        }
+
+        let [mut a, mut b, mut c, mut d, mut e] = h;
+        for (i, word) in w.iter().enumerate() {
+            let (f, k) = match i {
+                0..=19 => ((b & c) | (!b & d), 0x5A827999),
+                20..=39 => (b ^ c ^ d, 0x6ED9EBA1),
+                40..=59 => ((b & c) | (b & d) | (c & d), 0x8F1BBCDC),
+                _ => (b ^ c ^ d, 0xCA62C1D6),
+            };
+            let temp = a
+                .rotate_left(5)
+                .wrapping_add(f)
+                .wrapping_add(e)
+                .wrapping_add(k)
+                .wrapping_add(*word);
+            e = d;
+            d = c;
+            c = b.rotate_left(30);
+            b = a;
+            a = temp;
+        }
+        for (state, value) in h.iter_mut().zip([a, b, c, d, e]) {
+            *state = state.wrapping_add(value);
+        }
+    }
+
+    let mut digest = [0; 20];
+    for (i, state) in h.iter().enumerate() {
+        digest[4 * i..4 * i + 4].copy_from_slice(&state.to_be_bytes());
+    }
+    digest
+}
+
+fn base64(data: &[u8]) -> String {
+    const ALPHABET: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+    let mut encoded = String::new();
+    for chunk in data.chunks(3) {
+        let bytes = [
+            chunk[0],
+            *chunk.get(1).unwrap_or(&0),
+            *chunk.get(2).unwrap_or(&0),
+        ];
+        let triple = (bytes[0] as u32) << 16 | (bytes[1] as u32) << 8 | bytes[2] as u32;
+        for i in 0..4 {
+            if i <= chunk.len() {
+                encoded.push(ALPHABET[(triple >> (18 - 6 * i) & 0x3F) as usize] as char);
+            } else {
+                encoded.push('=');
+            }
+        }
+    }
+    encoded
+}
+
+/// Computes the `Sec-WebSocket-Accept` header value for the given `Sec-WebSocket-Key`.
+fn accept_key(key: &str) -> String {
+    base64(&sha1(format!("{}{}", key, WEBSOCKET_GUID).as_bytes()))
+}
+
+/// Returns the value of the given header of an HTTP request or response, if present.
+fn header<'a>(head: &'a str, name: &str) -> Option<&'a str> {
+    head.lines().skip(1).find_map(|line| {
+        let (key, value) = line.split_once(':')?;
+        if key.trim().eq_ignore_ascii_case(name) {
+            Some(value.trim())
+        } else {
+            None
+        }
+    })
+}
+
+/// Returns the length of the HTTP head including the empty line, if the buffer contains all of it.
+fn head_len(buf: &[u8]) -> Option<usize> {
+    buf.windows(4)
+        .position(|window| window == b"\r\n\r\n")
+        .map(|pos| pos + 4)
+}
+
+/// Encodes a single unfragmented WebSocket frame. Frames sent by clients have to be masked.
+fn encode_frame(opcode: u8, payload: &[u8], mask: Option<[u8; 4]>) -> Vec<u8> {
+    let mut frame = vec![0x80 | opcode];
+    let mask_bit = if mask.is_some() { 0x80 } else { 0 };
+    if payload.len() < 126 {
+        frame.push(mask_bit | payload.len() as u8);
+    } else if payload.len() <= u16::MAX as usize {
+        frame.push(mask_bit | 126);
+        frame.extend_from_slice(&(payload.len() as u16).to_be_bytes());
+    } else {
+        frame.push(mask_bit | 127);
+        frame.extend_from_slice(&(payload.len() as u64).to_be_bytes());
+    }
+    match mask {
+        Some(mask) => {
+            frame.extend_from_slice(&mask);
+            frame.extend(payload.iter().enumerate().map(|(i, b)| b ^ mask[i % 4]));
+        }
+        None => frame.extend_from_slice(payload),
+    }
+    frame
+}
+
+/// Decodes the first WebSocket frame in the buffer. Returns the opcode, the unmasked payload and the number of bytes consumed,
+/// or `None` if the frame is incomplete.
+/// # Errors
+/// - Returns an `InvalidData` error for fragmented or oversized frames.
+fn decode_frame(buf: &[u8]) -> Result<Option<(u8, Vec<u8>, usize)>, Error> {
+    if buf.len() < 2 {
+        return Ok(None);
+    }
+    let fin = buf[0] & 0x80 != 0;
+    let opcode = buf[0] & 0x0F;
+    if !fin || opcode == OPCODE_CONTINUATION {
+        return Err(Error::new(
+            ErrorKind::InvalidData,
+            "fragmented messages are not supported",
+        ));
+    }
+
+    let masked = buf[1] & 0x80 != 0;
+    let (len, mut pos) = match buf[1] & 0x7F {
+        126 if buf.len() >= 4 => (u16::from_be_bytes([buf[2], buf[3]]) as u64, 4),
+        127 if buf.len() >= 10 => {
+            let mut bytes = [0; 8];
+            bytes.copy_from_slice(&buf[2..10]);
+            (u64::from_be_bytes(bytes), 10)
+        }
+        126 | 127 => return Ok(None),
+        len => (len as u64, 2),
+    };
+    if len > MAX_MESSAGE_SIZE as u64 {
+        return Err(Error::new(ErrorKind::InvalidData, "message too large"));
+    }
+
+    let mask = if masked {
+        if buf.len() < pos + 4 {
+            return Ok(None);
+        }
+        let mask = [buf[pos], buf[pos + 1], buf[pos + 2], buf[pos + 3]];
+        pos += 4;
+        Some(mask)
+    } else {
+        None
+    };
+
+    let end = pos + len as usize;
+    if buf.len() < end {
+        return Ok(None);
+    }
+    let payload = match mask {
+        Some(mask) => buf[pos..end]
+            .iter()
+            .enumerate()
+            .map(|(i, b)| b ^ mask[i % 4])
+            .collect(),
+        None => buf[pos..end].to_vec(),
+    };
+    Ok(Some((opcode, payload, end)))
+}
+
+fn encode_relay_packet(kind: u8, addr: SocketAddr, payload: &[u8]) -> Vec<u8> {
+    let addr = addr.to_string();
+    let mut packet = vec![kind];
+    if kind == RELAY_DATA {
+        packet.push(addr.len() as u8);
+    }
+    packet.extend_from_slice(addr.as_bytes());
+    packet.extend_from_slice(payload);
+    packet
+}
+
+/// Decodes a relay packet into its kind, address and payload.
+fn decode_relay_packet(packet: &[u8]) -> Option<(u8, SocketAddr, &[u8])> {
+    let (&kind, rest) = packet.split_first()?;
+    let (addr, payload) = match kind {
+        RELAY_REGISTER => (rest, &rest[rest.len()..]),
+        RELAY_DATA => {
+            let (&len, rest) = rest.split_first()?;
+            if rest.len() < len as usize {
+                return None;
+            }
+            rest.split_at(len as usize)
+        }
+        _ => return None,
+    };
+    let addr = std::str::from_utf8(addr).ok()?.parse().ok()?;
+    Some((kind, addr, payload))
+}
+
+// ##############
+// # CONNECTION #
+// ##############
+
+/// A non-blocking WebSocket connection after the handshake. Outgoing frames are buffered until the stream accepts them.
+#[derive(Debug)]
+struct Connection {
+    stream: TcpStream,
+    read_buf: Vec<u8>,
+    write_buf: Vec<u8>,
+    /// Clients mask their frames, servers do not.
+    mask: bool,
+    closed: bool,
+}
+
+impl Connection {
+    fn new(stream: TcpStream, read_buf: Vec<u8>, mask: bool) -> Result<Self, Error> {
+        stream.set_nonblocking(true)?;
+        stream.set_nodelay(true)?;
+        Ok(Self {
+            stream,
+            read_buf,
+            write_buf: Vec::new(),
+            mask,
+            closed: false,
+        })
+    }
+
+    /// Queues a binary message. Returns `false` if the message has been dropped because too much data is waiting to be written.
+    fn send(&mut self, opcode: u8, payload: &[u8]) -> bool {
+        if self.closed || self.write_buf.len() > MAX_PENDING_BYTES {
+            return false;
+        }
+        let mask = if self.mask {
+            Some(rand::thread_rng().gen())
+        } else {
+            None
+        };
+        self.write_buf
+            .extend_from_slice(&encode_frame(opcode, payload, mask));
+        true
+    }
+
+    /// Writes as much of the buffered data as the stream accepts without blocking.
+    fn flush(&mut self) -> Result<(), Error> {
+        while !self.write_buf.is_empty() {
+            match self.stream.write(&self.write_buf) {
+                Ok(0) => return Err(Error::from(ErrorKind::WriteZero)),
+                Ok(written) => {
+                    self.write_buf.drain(..written);
+                }
+                Err(ref err) if err.kind() == ErrorKind::WouldBlock => return Ok(()),
+                Err(ref err) if err.kind() == ErrorKind::Interrupted => (),
+                Err(err) => return Err(err),
+            }
+        }
+        Ok(())
+    }
+
+    /// Reads everything available and returns the payloads of all complete binary messages. Pings are answered and a close frame closes the connection.
+    fn receive(&mut self) -> Result<Vec<Vec<u8>>, Error> {
+        let mut buf = [0; READ_BUFFER_SIZE];
+        loop {
+            match self.stream.read(&mut buf) {
+                Ok(0) => {
+                    self.closed = true;
+                    break;
+                }
+                Ok(len) => self.read_buf.extend_from_slice(&buf[..len]),
+                Err(ref err) if err.kind() == ErrorKind::WouldBlock => break,
+                Err(ref err) if err.kind() == ErrorKind::Interrupted => (),
+                Err(err) => return Err(err),
+            }
+        }
+
+        let mut messages = Vec::new();
+        while let Some((opcode, payload, len)) = decode_frame(&self.read_buf)? {
+            self.read_buf.drain(..len);
+            match opcode {
+                OPCODE_BINARY => messages.push(payload),
+                OPCODE_PING => {
+                    self.send(OPCODE_PONG, &payload);
+                }
+                OPCODE_CLOSE => {
+                    self.send(OPCODE_CLOSE, &[]);
+                    self.closed = true;
+                }
+                // text messages and pongs are ignored
+                _ => (),
+            }
+        }
+        Ok(messages)
+    }
+}
+
+// ##########
+// # CLIENT #
+// ##########
+
+/// A `NonBlockingSocket` that tunnels all messages over a WebSocket connection to a `WebSocketRelayServer`, which forwards them to the other peers.
+/// Use it for players behind symmetric NATs, where direct UDP connections fail. Every peer registers with a `SocketAddr` under which the others reach it;
+/// the address only identifies the peer on the relay and does not need to be the real address of the peer.
+///
+/// WebSocket connections are reliable and ordered, unlike UDP. The socket keeps that from stalling the session: if the connection is congested,
+/// further messages are dropped instead of piling up, and GGRS resends what matters. Available with the `websocket` feature.
+#[derive(Debug)]
+pub struct WebSocketRelaySocket {
+    connection: Connection,
+    stats: SocketStats,
+    errors: Vec<SocketError>,
+    reported_close: bool,
+    codec: Box<dyn MessageCodec>,
+}
+
+impl WebSocketRelaySocket {
+    /// Connects to the relay at `relay_addr` and registers under `local_addr`. Blocks until the WebSocket handshake is done.
+    /// # Errors
+    /// - Will return an `std::io::Error` if the connection could not be established or the relay did not accept the handshake.
+    pub fn connect<A: ToSocketAddrs>(relay_addr: A, local_addr: SocketAddr) -> Result<Self, Error> {
+        let mut stream = TcpStream::connect(relay_addr)?;
+        let host = stream.peer_addr()?;
+        stream.set_read_timeout(Some(HANDSHAKE_TIMEOUT))?;
+
+        let key = base64(&rand::thread_rng().gen::<[u8; 16]>());
+        let request = format!(
+            "GET / HTTP/1.1\r\nHost: {}\r\nUpgrade: websocket\r\nConnection: Upgrade\r\nSec-WebSocket-Key: {}\r\nSec-WebSocket-Version: 13\r\n\r\n",
+            host, key
+        );
+        stream.write_all(request.as_bytes())?;
+
+        // read the response head, the relay might already send frames right after it
+        let mut response = Vec::new();
+        let mut buf = [0; READ_BUFFER_SIZE];
+        let len = loop {
+            if let Some(len) = head_len(&response) {
+                break len;
+            }
+            if response.len() > MAX_MESSAGE_SIZE {
+                return Err(Error::new(ErrorKind::InvalidData, "handshake too large"));
+            }
+            match stream.read(&mut buf)? {
+                0 => return Err(Error::from(ErrorKind::UnexpectedEof)),
+                read => response.extend_from_slice(&buf[..read]),
+            }
+        };
+        let head = String::from_utf8_lossy(&response[..len]).into_owned();
+        let switching = head
+            .lines()
+            .next()
+            .and_then(|status| status.split(' ').nth(1))
+            == Some("101");
+        if !switching || header(&head, "Sec-WebSocket-Accept") != Some(&accept_key(&key)) {
+            return Err(Error::new(
+                ErrorKind::InvalidData,
+                "the relay did not accept the handshake",
+            ));
+        }
+
+        stream.set_read_timeout(None)?;
+        let mut connection = Connection::new(stream, response[len..].to_vec(), true)?;
+        connection.send(
+            OPCODE_BINARY,
+            &encode_relay_packet(RELAY_REGISTER, local_addr, &[]),
+        );
+        connection.flush()?;
+        Ok(Self {
+            connection,
+            stats: SocketStats::default(),
+            errors: Vec::new(),
+            reported_close: false,
+            codec: Box::new(WireFormatCodec),
+        })
+    }
+
+    /// Returns `true` if the connection to the relay has been closed. A closed socket drops all messages.
+    pub fn is_closed(&self) -> bool {
+        self.connection.closed
+    }
+
+    /// Sets the codec that encodes sent and decodes received messages. All peers have to use the same codec, the relay does not care.
+    pub fn set_codec(&mut self, codec: impl MessageCodec + 'static) {
+        self.codec = Box::new(codec);
+    }
+
+    fn record_error(&mut self, err: &Error, addr: Option<SocketAddr>) {
+        self.errors.push(SocketError {
+            kind: err.kind(),
+            raw_os_error: err.raw_os_error(),
+            addr,
+        });
+    }
+
+    fn flush(&mut self) {
+        if let Err(err) = self.connection.flush() {
+            ggrs_warn!("Sending to the relay failed: {}", err);
+            self.connection.closed = true;
+            self.record_error(&err, None);
+        }
+    }
+
+    fn report_close(&mut self) {
+        if self.connection.closed && !self.reported_close {
+            self.reported_close = true;
+            self.record_error(&Error::from(ErrorKind::ConnectionAborted), None);
+        }
+    }
+}
+
+impl NonBlockingSocket for WebSocketRelaySocket {
+    fn send_to(&mut self, msg: &UdpMessage, addr: SocketAddr) {
+        let packet = encode_relay_packet(RELAY_DATA, addr, &self.codec.encode(msg));
+        if self.connection.send(OPCODE_BINARY, &packet) {
+            self.stats.packets_sent += 1;
+            self.stats.bytes_sent += packet.len() as u64;
+        } else {
+            // the message is lost, just like on a congested UDP link
+            self.stats.send_errors += 1;
+            self.record_error(&Error::from(ErrorKind::WouldBlock), Some(addr));
+        }
+        self.flush();
+    }
+
+    fn receive_all_messages(&mut self) -> Vec<(SocketAddr, UdpMessage)> {
+        let mut received_messages = Vec::new();
+        match self.connection.receive() {
+            Ok(packets) => {
+                for packet in packets {
+                    self.stats.packets_received += 1;
+                    self.stats.bytes_received += packet.len() as u64;
+                    match decode_relay_packet(&packet) {
+                        Some((RELAY_DATA, addr, payload)) => match self.codec.decode(payload) {
+                            Some(msg) => received_messages.push((addr, msg)),
+                            None => self.stats.invalid_packets += 1,
+                        },
+                        _ => self.stats.invalid_packets += 1,
+                    }
+                }
+            }
+            Err(err) => {
+                ggrs_warn!("Receiving from the relay failed: {}", err);
+                self.stats.receive_errors += 1;
+                self.connection.closed = true;
+                self.record_error(&err, None);
+            }
+        }
+        // answers to pings are sent right away
+        self.flush();
+        self.report_close();
+        received_messages
+    }
+
+    fn take_errors(&mut self) -> Vec<SocketError> {
+        std::mem::take(&mut self.errors)
+    }
+
+    fn stats(&self) -> Option<SocketStats> {
+        Some(self.stats)
+    }
+}
+
+// ##########
+// # SERVER #
+// ##########
+
+#[derive(Debug)]
+struct PendingHandshake {
+    stream: TcpStream,
+    buf: Vec<u8>,
+    since: Instant,
+}
+
+#[derive(Debug)]
+struct Client {
+    connection: Connection,
+    addr: Option<SocketAddr>,
+}
+
+/// A minimal relay for `WebSocketRelaySocket`s and browser clients speaking the same protocol. It needs to be reachable by all players,
+/// so it usually runs on a public server. Call `poll()` regularly to forward messages.
+///
+/// Every binary WebSocket message is a relay packet. `[0, addr...]` registers the sender under `addr`, given as UTF-8 text like `10.0.0.1:7000`.
+/// `[1, len, addr..., payload...]` carries a GGRS message: sent by a peer, `addr` is the destination; forwarded by the relay, it is the source.
+/// Messages to unknown addresses are dropped.
+#[derive(Debug)]
+pub struct WebSocketRelayServer {
+    listener: TcpListener,
+    pending: Vec<PendingHandshake>,
+    clients: Vec<Client>,
+}
+
+impl WebSocketRelayServer {
+    /// Binds the relay to the given address, e.g. `0.0.0.0:8080`.
+    /// # Errors
+    /// - Will return an `std::io::Error` if the listener could not be bound.
+    pub fn bind<A: ToSocketAddrs>(addr: A) -> Result<Self, Error> {
+        let listener = TcpListener::bind(addr)?;
+        listener.set_nonblocking(true)?;
+        Ok(Self {
+            listener,
+            pending: Vec::new(),
+            clients: Vec::new(),
+        })
+    }
+
+    /// Returns the address the relay is bound to.
+    /// # Errors
+    /// - Will return an `std::io::Error` if the address could not be queried.
+    pub fn local_addr(&self) -> Result<SocketAddr, Error> {
+        self.listener.local_addr()
+    }
+
+    /// Returns the number of connected clients that completed the handshake.
+    pub fn num_clients(&self) -> usize {
+        self.clients.len()
+    }
+
+    /// Accepts new connections, completes their handshakes and forwards all received messages to their destinations.
+    /// Connections that fail or break the protocol are dropped.
+    /// # Errors
+    /// - Will return an `std::io::Error` if accepting connections failed.
+    pub fn poll(&mut self) -> Result<(), Error> {
+        loop {
+            match self.listener.accept() {
+                Ok((stream, _)) => {
+                    stream.set_nonblocking(true)?;
+                    self.pending.push(PendingHandshake {
+                        stream,
+                        buf: Vec::new(),
+                        since: Instant::now(),
+                    });
+                }
+                Err(ref err) if err.kind() == ErrorKind::WouldBlock => break,
+                Err(err) => return Err(err),
+            }
+        }
+
+        self.handle_handshakes();
+
+        // collect all packets first, so they can be forwarded to any client
+        let mut forwards = Vec::new();
+        for client in &mut self.clients {
+            let packets = match client.connection.receive() {
+                Ok(packets) => packets,
+                Err(_) => {
+                    client.connection.closed = true;
+                    continue;
+                }
+            };
+            for packet in packets {
+                match decode_relay_packet(&packet) {
+                    Some((RELAY_REGISTER, addr, _)) => client.addr = Some(addr),
+                    Some((RELAY_DATA, to, payload)) => {
+                        if let Some(from) = client.addr {
+                            forwards.push((to, encode_relay_packet(RELAY_DATA, from, payload)));
+                        }
+                    }
+                    // invalid packets are dropped
+                    _ => (),
+                }
+            }
+        }
+
+        for (to, packet) in forwards {
+            // the latest registration for an address wins
+            if let Some(client) = self
+                .clients
+                .iter_mut()
+                .rev()
+                .find(|client| client.addr == Some(to))
+            {
+                client.connection.send(OPCODE_BINARY, &packet);
+            }
+        }
+
+        for client in &mut self.clients {
+            if client.connection.flush().is_err() {
+                client.connection.closed = true;
+            }
+        }
+        self.clients.retain(|client| !client.connection.closed);
+        Ok(())
+    }
+
+    fn handle_handshakes(&mut self) {
+        let mut buf = [0; READ_BUFFER_SIZE];
+        let mut completed = Vec::new();
+        let mut index = 0;
+        while index < self.pending.len() {
+            let pending = &mut self.pending[index];
+            let done = loop {
+                match pending.stream.read(&mut buf) {
+                    Ok(0) => break Some(false),
+                    Ok(len) => pending.buf.extend_from_slice(&buf[..len]),
+                    Err(ref err) if err.kind() == ErrorKind::WouldBlock => break None,
+                    Err(ref err) if err.kind() == ErrorKind::Interrupted => (),
+                    Err(_) => break Some(false),
+                }
+            };
+            let done = match done {
+                Some(false) => Some(false),
+                _ if head_len(&pending.buf).is_some() => Some(true),
+                _ if pending.buf.len() > MAX_MESSAGE_SIZE
+                    || pending.since.elapsed() > HANDSHAKE_TIMEOUT =>
+                {
+                    Some(false)
+                }
+                _ => None,
+            };
+            match done {
+                Some(success) => {
+                    let pending = self.pending.swap_remove(index);
+                    if success {
+                        completed.push(pending);
+                    }
+                }
+                None => index += 1,
+            }
+        }
+
+        for pending in completed {
+            if let Some(client) = Self::accept_handshake(pending) {
+                self.clients.push(client);
+            }
+        }
+    }
+
+    /// Answers the handshake of a client. Returns `None` if the request is not a valid WebSocket handshake.
+    fn accept_handshake(pending: PendingHandshake) -> Option<Client> {
+        let len = head_len(&pending.buf)?;
+        let head = String::from_utf8_lossy(&pending.buf[..len]).into_owned();
+        let key = header(&head, "Sec-WebSocket-Key")?;
+        let response = format!(
+            "HTTP/1.1 101 Switching Protocols\r\nUpgrade: websocket\r\nConnection: Upgrade\r\nSec-WebSocket-Accept: {}\r\n\r\n",
+            accept_key(key)
+        );
+        let mut connection =
+            Connection::new(pending.stream, pending.buf[len..].to_vec(), false).ok()?;
+        connection.write_buf.extend_from_slice(response.as_bytes());
+        connection.flush().ok()?;
+        Some(Client {
+            connection,
+            addr: None,
+        })
+    }
+}
+
+// #########
+// # TESTS #
+// #########
+
+#[cfg(test)]
+mod websocket_tests {
+
+    use super::*;
+    use crate::network::udp_msg::{MessageBody, MessageHeader};
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use std::sync::Arc;
+
+    #[test]
+    fn test_accept_key() {
+        // the example of RFC 6455
+        assert_eq!(
+            accept_key("dGhlIHNhbXBsZSBub25jZQ=="),
+            "s3pPLMBiTxaQ9kYGzzhZRbK+xOo="
+        );
+        assert_eq!(base64(b"ab"), "YWI=");
+        assert_eq!(base64(b"a"), "YQ==");
+    }
+
+    #[test]
+    fn test_frames() {
+        let long_payload = vec![7; 300];
+        for payload in [&b"hello"[..], &long_payload[..]] {
+            for mask in [None, Some([1, 2, 3, 4])] {
+                let mut frame = encode_frame(OPCODE_BINARY, payload, mask);
+                frame.extend_from_slice(&[0x82]);
+                let (opcode, decoded, len) = decode_frame(&frame).unwrap().unwrap();
+                assert_eq!(opcode, OPCODE_BINARY);
+                assert_eq!(decoded, payload);
+                assert_eq!(len, frame.len() - 1);
+                // incomplete frames are left for later
+                assert!(decode_frame(&frame[..len - 1]).unwrap().is_none());
+            }
+        }
+    }
+
+    #[test]
+    fn test_relay_forwards_messages() {
+        let mut server = WebSocketRelayServer::bind("127.0.0.1:0").unwrap();
+        let relay_addr = server.local_addr().unwrap();
+        let addr1: SocketAddr = "10.0.0.1:7000".parse().unwrap();
+        let addr2: SocketAddr = "10.0.0.2:7000".parse().unwrap();
+
+        // the clients block on the handshake, so the relay runs on its own thread
+        let stop = Arc::new(AtomicBool::new(false));
+        let stop_server = stop.clone();
+        let relay = std::thread::spawn(move || {
+            while !stop_server.load(Ordering::SeqCst) {
+                server.poll().unwrap();
+                std::thread::sleep(Duration::from_millis(1));
+            }
+            server.num_clients()
+        });
+
+        let mut socket1 = WebSocketRelaySocket::connect(relay_addr, addr1).unwrap();
+        let mut socket2 = WebSocketRelaySocket::connect(relay_addr, addr2).unwrap();
+        let msg = UdpMessage {
+            header: MessageHeader {
+                magic: 1,
+                session_id: 0,
+                sequence_number: 0,
+            },
+            body: MessageBody::KeepAlive,
+        };
+
+        // the relay might not have seen the registration of the second socket yet, so keep sending
+        let mut received = Vec::new();
+        for _ in 0..500 {
+            socket1.send_to(&msg, addr2);
+            std::thread::sleep(Duration::from_millis(2));
+            received = socket2.receive_all_messages();
+            if !received.is_empty() {
+                break;
+            }
+        }
+        stop.store(true, Ordering::SeqCst);
+
+        assert_eq!(received[0], (addr1, msg));
+        assert!(socket1.take_errors().is_empty());
+        assert!(!socket2.is_closed());
+        assert_eq!(relay.join().unwrap(), 2);
+    }
+}
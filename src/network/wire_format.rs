@@ -0,0 +1,628 @@
+//! The GGRS wire format. Every message is encoded explicitly, field by field, so peers written in other languages can interoperate with GGRS.
+//! All integers are little-endian and have a fixed width, except for frames. Booleans are a single byte, either 0 or 1.
+//! Frames are 64-bit signed integers, encoded as zigzag LEB128 varints: the frame `n` is mapped to `2n` if it is positive and to `-2n - 1` if it is negative,
+//! then written in groups of seven bits, starting with the lowest, with the highest bit of every byte set except for the last one.
+//! Frames below 64 take up a single byte, frames below 8192 two bytes, and so on. Varints with more bytes than necessary are invalid.
+//!
+//! Every message starts with a header of ten bytes:
+//!
+//! | Field           | Type | Description                                                          |
+//! |-----------------|------|----------------------------------------------------------------------|
+//! | version         | u8   | The wire format version, currently 2                                 |
+//! | magic           | u16  | Identifies the sending endpoint                                      |
+//! | session_id      | u32  | Identifies the match, 0 if no session id has been set                |
+//! | sequence_number | u16  | Counts the messages of the sending endpoint, wrapping around at 2^16 |
+//! | type            | u8   | The type of the body, determining the layout below                   |
+//!
+//! The body depends on the type:
+//!
+//! - `0` sync request: `random_request: u32`, `fps: u32`, `seed: u32`, the random contribution of the sending session to the shared seed
+//! - `1` sync reply: `random_reply: u32`, `fps: u32`, `token_proof: u64`
+//! - `2` input: `num_players: u8`, then for every player `disconnected: bool` and `last_frame: frame`, followed by `disconnect_requested: bool`,
+//!   `pause_frame: frame`, `resumed: bool`, `fps_change_frame: frame`, `fps: u32`, `timescale: u16` in percent between 1 and 1000, `start_frame: frame`, `reference_frame: frame`, `ack_frame: frame`,
+//!   `num_bytes: u16` and `num_bytes` bytes of encoded inputs. `fps_change_frame` is -1, `fps` is 0 and `timescale` is 100 if the frame rate has never been changed.
+//!   The inputs are XORed with the input of `reference_frame` and then run-length encoded. If the second highest bit of `num_bytes` is set, the inputs are
+//!   a keyframe: they are XORed with zeroes instead, so they can be decoded without any previous input, and `reference_frame` is -1.
+//!   If the highest bit of `num_bytes` is set, the bytes are followed by `num_runs: u8` and `num_runs` times `run: u16`, and the n-th input stands for
+//!   the n-th `run` of consecutive frames, so unchanged inputs are only sent once. Otherwise, every input stands for a single frame
+//! - `3` input ack: `ack_frame: frame`
+//! - `4` quality report: `frame_advantage: i32` in 1/256 frames, `ping: u128`
+//! - `5` quality reply: `pong: u128`
+//! - `6` keep alive: no body
+//! - `7` input history: `start_frame: frame`, `part: u8`, `num_parts: u8`, `num_bytes: u16` and `num_bytes` bytes. The bytes of all parts of a chunk, in order,
+//!   are the inputs starting at `start_frame`, XORed with zeroes and run-length encoded like the inputs of an input message
+//! - `8` reliable: `sequence: u32`, `num_bytes: u16` and `num_bytes` bytes of a user message. The messages of an endpoint are numbered consecutively from 0 on
+//! - `9` reliable ack: `next_sequence: u32`, the sequence number of the next reliable message the endpoint expects
+//! - `10` state checksum: `frame: frame`, `checksum: u64`, the checksum of the confirmed gamestate of `frame`
+//!
+//! Messages with a different version, an unknown type, missing bytes or trailing bytes are invalid.
+//! Input messages are also invalid if `num_players` exceeds `MAX_PLAYERS`, and all messages with a `num_bytes` field are invalid if it exceeds 467, the maximum payload GGRS ever sends.
+
+use std::convert::TryInto;
+
+use crate::network::udp_msg::{
+    ConnectionStatus, FpsChange, Input, InputAck, InputHistory, MessageBody, MessageHeader,
+    PauseStatus, QualityReply, QualityReport, Reliable, ReliableAck, StateChecksum, SyncReply,
+    SyncRequest, UdpMessage, MAX_TIMESCALE,
+};
+use crate::network::udp_protocol::MAX_PAYLOAD;
+use crate::{Frame, MAX_PLAYERS};
+
+/// The version of the wire format. Peers only accept messages of the same version.
+pub const WIRE_FORMAT_VERSION: u8 = 2;
+
+/// The length of the header in bytes.
+const HEADER_LEN: usize = 10;
+
+const SYNC_REQUEST: u8 = 0;
+const SYNC_REPLY: u8 = 1;
+const INPUT: u8 = 2;
+const INPUT_ACK: u8 = 3;
+const QUALITY_REPORT: u8 = 4;
+const QUALITY_REPLY: u8 = 5;
+const KEEP_ALIVE: u8 = 6;
+const INPUT_HISTORY: u8 = 7;
+const RELIABLE: u8 = 8;
+const RELIABLE_ACK: u8 = 9;
+const STATE_CHECKSUM: u8 = 10;
+
+/// Set in `num_bytes` of an input message if the encoded inputs are followed by frame runs.
+const FRAME_RUNS_FLAG: u16 = 0x8000;
+/// Set in `num_bytes` of an input message if the encoded inputs are a keyframe.
+const KEYFRAME_FLAG: u16 = 0x4000;
+
+impl UdpMessage {
+    /// Encodes the message in the GGRS wire format.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = vec![WIRE_FORMAT_VERSION];
+        bytes.extend_from_slice(&self.header.magic.to_le_bytes());
+        bytes.extend_from_slice(&self.header.session_id.to_le_bytes());
+        bytes.extend_from_slice(&self.header.sequence_number.to_le_bytes());
+
+        match &self.body {
+            MessageBody::SyncRequest(body) => {
+                bytes.push(SYNC_REQUEST);
+                bytes.extend_from_slice(&body.random_request.to_le_bytes());
+                bytes.extend_from_slice(&body.fps.to_le_bytes());
+                bytes.extend_from_slice(&body.seed.to_le_bytes());
+            }
+            MessageBody::SyncReply(body) => {
+                bytes.push(SYNC_REPLY);
+                bytes.extend_from_slice(&body.random_reply.to_le_bytes());
+                bytes.extend_from_slice(&body.fps.to_le_bytes());
+                bytes.extend_from_slice(&body.token_proof.to_le_bytes());
+            }
+            MessageBody::Input(body) => {
+                bytes.push(INPUT);
+                let num_players: u8 = body
+                    .peer_connect_status
+                    .len()
+                    .try_into()
+                    .expect("too many players");
+                bytes.push(num_players);
+                for status in &body.peer_connect_status {
+                    bytes.push(status.disconnected as u8);
+                    write_frame(&mut bytes, status.last_frame);
+                }
+                bytes.push(body.disconnect_requested as u8);
+                write_frame(&mut bytes, body.pause_status.pause_frame);
+                bytes.push(body.pause_status.resumed as u8);
+                write_frame(&mut bytes, body.fps_change.frame);
+                bytes.extend_from_slice(&body.fps_change.fps.to_le_bytes());
+                bytes.extend_from_slice(&body.fps_change.timescale.to_le_bytes());
+                write_frame(&mut bytes, body.start_frame);
+                write_frame(&mut bytes, body.reference_frame);
+                write_frame(&mut bytes, body.ack_frame);
+                let mut num_bytes: u16 = body.bytes.len().try_into().expect("input too large");
+                if !body.frame_runs.is_empty() {
+                    num_bytes |= FRAME_RUNS_FLAG;
+                }
+                if body.keyframe {
+                    num_bytes |= KEYFRAME_FLAG;
+                }
+                bytes.extend_from_slice(&num_bytes.to_le_bytes());
+                bytes.extend_from_slice(&body.bytes);
+                if !body.frame_runs.is_empty() {
+                    let num_runs: u8 = body.frame_runs.len().try_into().expect("too many runs");
+                    bytes.push(num_runs);
+                    for run in &body.frame_runs {
+                        bytes.extend_from_slice(&run.to_le_bytes());
+                    }
+                }
+            }
+            MessageBody::InputAck(body) => {
+                bytes.push(INPUT_ACK);
+                write_frame(&mut bytes, body.ack_frame);
+            }
+            MessageBody::QualityReport(body) => {
+                bytes.push(QUALITY_REPORT);
+                bytes.extend_from_slice(&body.frame_advantage.to_le_bytes());
+                bytes.extend_from_slice(&body.ping.to_le_bytes());
+            }
+            MessageBody::QualityReply(body) => {
+                bytes.push(QUALITY_REPLY);
+                bytes.extend_from_slice(&body.pong.to_le_bytes());
+            }
+            MessageBody::KeepAlive => bytes.push(KEEP_ALIVE),
+            MessageBody::InputHistory(body) => {
+                bytes.push(INPUT_HISTORY);
+                write_frame(&mut bytes, body.start_frame);
+                bytes.push(body.part);
+                bytes.push(body.num_parts);
+                let num_bytes: u16 = body.bytes.len().try_into().expect("input too large");
+                bytes.extend_from_slice(&num_bytes.to_le_bytes());
+                bytes.extend_from_slice(&body.bytes);
+            }
+            MessageBody::Reliable(body) => {
+                bytes.push(RELIABLE);
+                bytes.extend_from_slice(&body.sequence.to_le_bytes());
+                let num_bytes: u16 = body.bytes.len().try_into().expect("message too large");
+                bytes.extend_from_slice(&num_bytes.to_le_bytes());
+                bytes.extend_from_slice(&body.bytes);
+            }
+            MessageBody::ReliableAck(body) => {
+                bytes.push(RELIABLE_ACK);
+                bytes.extend_from_slice(&body.next_sequence.to_le_bytes());
+            }
+            MessageBody::StateChecksum(body) => {
+                bytes.push(STATE_CHECKSUM);
+                write_frame(&mut bytes, body.frame);
+                bytes.extend_from_slice(&body.checksum.to_le_bytes());
+            }
+        }
+        bytes
+    }
+
+    /// Returns the number of bytes of the message in the GGRS wire format, without encoding it.
+    pub(crate) fn encoded_len(&self) -> usize {
+        let body_len = match &self.body {
+            MessageBody::SyncRequest(_) => 12,
+            MessageBody::SyncReply(_) => 16,
+            MessageBody::Input(body) => {
+                let runs_len = match body.frame_runs.len() {
+                    0 => 0,
+                    num_runs => 1 + 2 * num_runs,
+                };
+                let frames_len: usize = body
+                    .peer_connect_status
+                    .iter()
+                    .map(|status| 1 + frame_len(status.last_frame))
+                    .sum::<usize>()
+                    + frame_len(body.pause_status.pause_frame)
+                    + frame_len(body.fps_change.frame)
+                    + frame_len(body.start_frame)
+                    + frame_len(body.reference_frame)
+                    + frame_len(body.ack_frame);
+                11 + frames_len + body.bytes.len() + runs_len
+            }
+            MessageBody::InputAck(body) => frame_len(body.ack_frame),
+            MessageBody::QualityReport(_) => 20,
+            MessageBody::QualityReply(_) => 16,
+            MessageBody::KeepAlive => 0,
+            MessageBody::InputHistory(body) => 4 + frame_len(body.start_frame) + body.bytes.len(),
+            MessageBody::Reliable(body) => 6 + body.bytes.len(),
+            MessageBody::ReliableAck(_) => 4,
+            MessageBody::StateChecksum(body) => frame_len(body.frame) + 8,
+        };
+        HEADER_LEN + body_len
+    }
+
+    /// Decodes a message in the GGRS wire format. Returns `None` if the bytes are not a valid message.
+    pub fn from_bytes(bytes: &[u8]) -> Option<Self> {
+        let mut reader = Reader { bytes };
+
+        if reader.u8()? != WIRE_FORMAT_VERSION {
+            return None;
+        }
+        let header = MessageHeader {
+            magic: reader.u16()?,
+            session_id: reader.u32()?,
+            sequence_number: reader.u16()?,
+        };
+
+        let body = match reader.u8()? {
+            SYNC_REQUEST => MessageBody::SyncRequest(SyncRequest {
+                random_request: reader.u32()?,
+                fps: reader.u32()?,
+                seed: reader.u32()?,
+            }),
+            SYNC_REPLY => MessageBody::SyncReply(SyncReply {
+                random_reply: reader.u32()?,
+                fps: reader.u32()?,
+                token_proof: reader.u64()?,
+            }),
+            INPUT => {
+                let num_players = reader.u8()?;
+                if num_players as u32 > MAX_PLAYERS {
+                    return None;
+                }
+                let mut peer_connect_status = Vec::new();
+                for _ in 0..num_players {
+                    peer_connect_status.push(ConnectionStatus {
+                        disconnected: reader.bool()?,
+                        last_frame: reader.frame()?,
+                    });
+                }
+                let disconnect_requested = reader.bool()?;
+                let pause_status = PauseStatus {
+                    pause_frame: reader.frame()?,
+                    resumed: reader.bool()?,
+                };
+                let fps_change = FpsChange {
+                    frame: reader.frame()?,
+                    fps: reader.u32()?,
+                    timescale: reader.u16()?,
+                };
+                if fps_change.timescale == 0 || fps_change.timescale > MAX_TIMESCALE {
+                    return None;
+                }
+                let start_frame = reader.frame()?;
+                let reference_frame = reader.frame()?;
+                let ack_frame = reader.frame()?;
+                let num_bytes = reader.u16()?;
+                let has_runs = num_bytes & FRAME_RUNS_FLAG != 0;
+                let keyframe = num_bytes & KEYFRAME_FLAG != 0;
+                let num_bytes = (num_bytes & !(FRAME_RUNS_FLAG | KEYFRAME_FLAG)) as usize;
+                if num_bytes > MAX_PAYLOAD {
+                    return None;
+                }
+                let bytes = reader.take(num_bytes)?.to_vec();
+                let mut frame_runs = Vec::new();
+                if has_runs {
+                    for _ in 0..reader.u8()? {
+                        frame_runs.push(reader.u16()?);
+                    }
+                }
+                MessageBody::Input(Input {
+                    peer_connect_status,
+                    disconnect_requested,
+                    pause_status,
+                    fps_change,
+                    start_frame,
+                    keyframe,
+                    reference_frame,
+                    ack_frame,
+                    bytes,
+                    frame_runs,
+                })
+            }
+            INPUT_ACK => MessageBody::InputAck(InputAck {
+                ack_frame: reader.frame()?,
+            }),
+            QUALITY_REPORT => MessageBody::QualityReport(QualityReport {
+                frame_advantage: reader.i32()?,
+                ping: reader.u128()?,
+            }),
+            QUALITY_REPLY => MessageBody::QualityReply(QualityReply {
+                pong: reader.u128()?,
+            }),
+            KEEP_ALIVE => MessageBody::KeepAlive,
+            INPUT_HISTORY => {
+                let start_frame = reader.frame()?;
+                let part = reader.u8()?;
+                let num_parts = reader.u8()?;
+                let num_bytes = reader.u16()? as usize;
+                if num_bytes > MAX_PAYLOAD {
+                    return None;
+                }
+                MessageBody::InputHistory(InputHistory {
+                    start_frame,
+                    part,
+                    num_parts,
+                    bytes: reader.take(num_bytes)?.to_vec(),
+                })
+            }
+            RELIABLE => {
+                let sequence = reader.u32()?;
+                let num_bytes = reader.u16()? as usize;
+                if num_bytes > MAX_PAYLOAD {
+                    return None;
+                }
+                MessageBody::Reliable(Reliable {
+                    sequence,
+                    bytes: reader.take(num_bytes)?.to_vec(),
+                })
+            }
+            RELIABLE_ACK => MessageBody::ReliableAck(ReliableAck {
+                next_sequence: reader.u32()?,
+            }),
+            STATE_CHECKSUM => MessageBody::StateChecksum(StateChecksum {
+                frame: reader.frame()?,
+                checksum: reader.u64()?,
+            }),
+            _ => return None,
+        };
+
+        // trailing bytes mean the message has not been encoded the way we expect
+        if !reader.bytes.is_empty() {
+            return None;
+        }
+        Some(Self { header, body })
+    }
+}
+
+/// Maps a frame to an unsigned integer, so frames close to 0 have few significant bits, no matter if they are positive or negative.
+fn zigzag(frame: Frame) -> u64 {
+    ((frame.0 << 1) ^ (frame.0 >> 63)) as u64
+}
+
+/// Writes a frame as a zigzag LEB128 varint.
+pub(crate) fn write_frame(bytes: &mut Vec<u8>, frame: Frame) {
+    let mut value = zigzag(frame);
+    while value >= 0x80 {
+        bytes.push(value as u8 | 0x80);
+        value >>= 7;
+    }
+    bytes.push(value as u8);
+}
+
+/// Returns the number of bytes `write_frame()` writes for the frame.
+fn frame_len(frame: Frame) -> usize {
+    let bits = 64 - zigzag(frame).leading_zeros() as usize;
+    std::cmp::max(1, bits.div_ceil(7))
+}
+
+/// Reads fixed-width little-endian values and varint frames from a byte slice. Every read returns `None` if there are not enough bytes left.
+pub(crate) struct Reader<'a> {
+    pub(crate) bytes: &'a [u8],
+}
+
+impl<'a> Reader<'a> {
+    pub(crate) fn take(&mut self, len: usize) -> Option<&'a [u8]> {
+        if self.bytes.len() < len {
+            return None;
+        }
+        let (taken, rest) = self.bytes.split_at(len);
+        self.bytes = rest;
+        Some(taken)
+    }
+
+    pub(crate) fn u8(&mut self) -> Option<u8> {
+        Some(self.take(1)?[0])
+    }
+
+    pub(crate) fn bool(&mut self) -> Option<bool> {
+        match self.u8()? {
+            0 => Some(false),
+            1 => Some(true),
+            _ => None,
+        }
+    }
+
+    pub(crate) fn u16(&mut self) -> Option<u16> {
+        Some(u16::from_le_bytes(self.take(2)?.try_into().ok()?))
+    }
+
+    pub(crate) fn u32(&mut self) -> Option<u32> {
+        Some(u32::from_le_bytes(self.take(4)?.try_into().ok()?))
+    }
+
+    pub(crate) fn i32(&mut self) -> Option<i32> {
+        Some(i32::from_le_bytes(self.take(4)?.try_into().ok()?))
+    }
+
+    /// Reads a zigzag LEB128 varint frame. Returns `None` if the varint does not fit into 64 bits or has more bytes than necessary.
+    pub(crate) fn frame(&mut self) -> Option<Frame> {
+        let mut value: u64 = 0;
+        for shift in (0..64).step_by(7) {
+            let byte = self.u8()?;
+            // the tenth byte may only hold the highest bit
+            if shift == 63 && byte > 1 {
+                return None;
+            }
+            value |= u64::from(byte & 0x7F) << shift;
+            if byte & 0x80 == 0 {
+                // a zero byte at the end could have been left out
+                if byte == 0 && shift > 0 {
+                    return None;
+                }
+                return Some(Frame((value >> 1) as i64 ^ -((value & 1) as i64)));
+            }
+        }
+        None
+    }
+
+    pub(crate) fn u64(&mut self) -> Option<u64> {
+        Some(u64::from_le_bytes(self.take(8)?.try_into().ok()?))
+    }
+
+    pub(crate) fn u128(&mut self) -> Option<u128> {
+        Some(u128::from_le_bytes(self.take(16)?.try_into().ok()?))
+    }
+}
+
+// #########
+// # TESTS #
+// #########
+
+#[cfg(test)]
+mod wire_format_tests {
+
+    use super::*;
+
+    fn message(body: MessageBody) -> UdpMessage {
+        UdpMessage {
+            header: MessageHeader {
+                magic: 0xBEEF,
+                session_id: 7,
+                sequence_number: 0x1234,
+            },
+            body,
+        }
+    }
+
+    #[test]
+    fn test_roundtrip() {
+        let input = Input {
+            peer_connect_status: vec![
+                ConnectionStatus {
+                    disconnected: false,
+                    last_frame: Frame(i64::MAX),
+                },
+                ConnectionStatus {
+                    disconnected: true,
+                    last_frame: Frame(-1),
+                },
+            ],
+            disconnect_requested: false,
+            pause_status: PauseStatus {
+                pause_frame: Frame(20),
+                resumed: false,
+            },
+            fps_change: FpsChange {
+                frame: Frame(30),
+                fps: 30,
+                timescale: 50,
+            },
+            start_frame: Frame(10),
+            keyframe: false,
+            reference_frame: Frame(9),
+            ack_frame: Frame(9),
+            bytes: vec![1, 2, 3, 4, 5],
+            frame_runs: vec![3, 1],
+        };
+        let keyframe = Input {
+            keyframe: true,
+            reference_frame: Frame(-1),
+            frame_runs: Vec::new(),
+            ..input.clone()
+        };
+        let messages = vec![
+            message(MessageBody::SyncRequest(SyncRequest {
+                random_request: 42,
+                fps: 60,
+                seed: 7,
+            })),
+            message(MessageBody::SyncReply(SyncReply {
+                random_reply: 42,
+                fps: 60,
+                token_proof: 0xDEAD_BEEF,
+            })),
+            message(MessageBody::Input(input)),
+            message(MessageBody::Input(keyframe)),
+            message(MessageBody::InputAck(InputAck {
+                ack_frame: Frame(7),
+            })),
+            message(MessageBody::QualityReport(QualityReport {
+                frame_advantage: -3 * 256 - 128,
+                ping: 1234567,
+            })),
+            message(MessageBody::QualityReply(QualityReply { pong: 1234567 })),
+            message(MessageBody::KeepAlive),
+            message(MessageBody::InputHistory(InputHistory {
+                start_frame: Frame(5_000_000_000),
+                part: 1,
+                num_parts: 3,
+                bytes: vec![6, 7, 8],
+            })),
+            message(MessageBody::Reliable(Reliable {
+                sequence: 5,
+                bytes: vec![9, 10],
+            })),
+            message(MessageBody::ReliableAck(ReliableAck { next_sequence: 6 })),
+            message(MessageBody::StateChecksum(StateChecksum {
+                frame: Frame(600),
+                checksum: 0xDEAD_BEEF_CAFE,
+            })),
+        ];
+
+        for msg in messages {
+            assert_eq!(msg.encoded_len(), msg.to_bytes().len());
+            assert_eq!(UdpMessage::from_bytes(&msg.to_bytes()), Some(msg));
+        }
+    }
+
+    #[test]
+    fn test_layout() {
+        let msg = message(MessageBody::InputAck(InputAck {
+            ack_frame: Frame(1),
+        }));
+        assert_eq!(
+            msg.to_bytes(),
+            vec![2, 0xEF, 0xBE, 7, 0, 0, 0, 0x34, 0x12, 3, 2]
+        );
+    }
+
+    #[test]
+    fn test_frame_varints() {
+        let cases: Vec<(i64, Vec<u8>)> = vec![
+            (0, vec![0]),
+            (-1, vec![1]),
+            (63, vec![126]),
+            (-64, vec![127]),
+            (64, vec![0x80, 1]),
+            (8191, vec![0xFE, 0x7F]),
+            (8192, vec![0x80, 0x80, 1]),
+            (
+                i64::MAX,
+                vec![0xFE, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 1],
+            ),
+            (
+                i64::MIN,
+                vec![0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 1],
+            ),
+        ];
+        for (frame, encoded) in cases {
+            let mut bytes = Vec::new();
+            write_frame(&mut bytes, Frame(frame));
+            assert_eq!(bytes, encoded);
+            assert_eq!(frame_len(Frame(frame)), encoded.len());
+            assert_eq!(Reader { bytes: &encoded }.frame(), Some(Frame(frame)));
+        }
+
+        // too long, more bytes than necessary and truncated
+        assert_eq!(Reader { bytes: &[0xFF; 10] }.frame(), None);
+        assert_eq!(Reader { bytes: &[0x80, 0] }.frame(), None);
+        assert_eq!(Reader { bytes: &[0x80] }.frame(), None);
+    }
+
+    #[test]
+    fn test_invalid_messages() {
+        let bytes = message(MessageBody::SyncRequest(SyncRequest {
+            random_request: 42,
+            fps: 60,
+            seed: 7,
+        }))
+        .to_bytes();
+
+        // truncated
+        assert_eq!(UdpMessage::from_bytes(&bytes[..bytes.len() - 1]), None);
+        assert_eq!(UdpMessage::from_bytes(&[]), None);
+        // trailing bytes
+        let mut trailing = bytes.clone();
+        trailing.push(0);
+        assert_eq!(UdpMessage::from_bytes(&trailing), None);
+        // other version
+        let mut other_version = bytes.clone();
+        other_version[0] = WIRE_FORMAT_VERSION + 1;
+        assert_eq!(UdpMessage::from_bytes(&other_version), None);
+        // unknown type
+        let mut unknown_type = bytes;
+        unknown_type[9] = 200;
+        assert_eq!(UdpMessage::from_bytes(&unknown_type), None);
+
+        // too many players
+        let mut too_many_players = vec![WIRE_FORMAT_VERSION, 0, 0, 0, 0, 0, 0, 0, 0, INPUT, 200];
+        too_many_players.extend_from_slice(&[0; 200 * 5 + 31]);
+        assert_eq!(UdpMessage::from_bytes(&too_many_players), None);
+        // too many input bytes
+        let mut too_many_bytes = vec![WIRE_FORMAT_VERSION, 0, 0, 0, 0, 0, 0, 0, 0, INPUT, 0];
+        too_many_bytes.extend_from_slice(&[0; 13]);
+        too_many_bytes.extend_from_slice(&1000u16.to_le_bytes());
+        too_many_bytes.extend_from_slice(&[0; 1000]);
+        assert_eq!(UdpMessage::from_bytes(&too_many_bytes), None);
+        // timescale out of range
+        for timescale in [0, MAX_TIMESCALE + 1] {
+            let input = Input {
+                fps_change: FpsChange {
+                    frame: Frame(30),
+                    fps: 60,
+                    timescale,
+                },
+                ..Input::default()
+            };
+            let bytes = message(MessageBody::Input(input)).to_bytes();
+            assert_eq!(UdpMessage::from_bytes(&bytes), None);
+        }
+    }
+}
@@ -0,0 +1,516 @@
+//! The GGRS replay format. A replay holds everything needed to play a match back: metadata about the game and the players, the confirmed inputs
+//! of every frame, checksums of the gamestate to verify the playback and the result of the match. Like the wire format, every field is encoded explicitly,
+//! so replays can be read by other versions of GGRS and by tools written in other languages. Integers are little-endian and frames are zigzag LEB128 varints,
+//! see the wire format. Booleans are a single byte, either 0 or 1.
+//!
+//! A replay starts with the magic bytes `GGRSRPLY` and the version of the replay format, a `u8`, currently 1. A sequence of records follows:
+//!
+//! | Field     | Type | Description                                                                                         |
+//! |-----------|------|-----------------------------------------------------------------------------------------------------|
+//! | type      | u8   | The type of the record, determining the layout of the payload below                                 |
+//! | num_bytes | u32  | The length of the payload                                                                           |
+//! | payload   |      | `num_bytes` bytes                                                                                   |
+//! | hash      | u64  | SipHash-2-4 of `type`, `num_bytes` and `payload`, keyed with the hash of the previous record and 0 |
+//!
+//! The key of the first record is 0 and 0. Since every hash depends on all previous records, corrupted, reordered or removed records are detected on load.
+//! The payload depends on the type:
+//!
+//! - `0` metadata: `fps: u32`, `input_size: u16`, `num_players: u8`, then for every player `name_len: u16` and `name_len` bytes of the UTF-8 name,
+//!   followed by `fingerprint_len: u16` and `fingerprint_len` bytes identifying the game. This is always the first record and appears only once
+//! - `1` inputs: `start_frame: frame`, `num_frames: u16`, then for every frame and every player `disconnected: bool` and `input_size` bytes of input.
+//!   The inputs records contain all frames from frame 0 on, in order and without gaps
+//! - `2` checksum: `frame: frame`, `checksum: u64`, the checksum of the gamestate of `frame`
+//! - `3` result: the bytes of the result of the match. This is always the last record. Replays without a result are incomplete, e.g. because the game
+//!   stopped recording before the match was over
+//!
+//! Replays with a different magic or version, unknown record types, records that are cut off, wrong hashes or payloads that do not match their layout are invalid.
+
+use std::convert::TryInto;
+use std::fs::File;
+use std::io::{BufReader, BufWriter, Error, ErrorKind, Read, Write};
+use std::path::Path;
+
+use crate::network::connection_token::siphash;
+use crate::network::wire_format::{write_frame, Reader};
+use crate::{Frame, GameInput, MAX_INPUT_BYTES, MAX_PLAYERS, NULL_FRAME};
+
+/// The version of the replay format. Replays of other versions are rejected.
+pub const REPLAY_FORMAT_VERSION: u8 = 1;
+
+/// The magic bytes at the start of every replay.
+const MAGIC: &[u8; 8] = b"GGRSRPLY";
+
+const METADATA: u8 = 0;
+const INPUTS: u8 = 1;
+const CHECKSUM: u8 = 2;
+const RESULT: u8 = 3;
+
+/// The number of frames the writer collects into a single inputs record.
+const FRAMES_PER_RECORD: usize = 64;
+/// The largest payload of a record. Larger records are rejected without reading them.
+const MAX_RECORD_BYTES: usize = 1 << 20;
+
+/// Describes the match of a replay. Check the `game_fingerprint` before playing a replay back, since replays of other versions of your game
+/// will most likely not play back the same way.
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct ReplayMetadata {
+    /// Identifies the game and the version of its simulation, e.g. a hash of the game logic and data.
+    pub game_fingerprint: Vec<u8>,
+    /// The names of all players, ordered by handle.
+    pub players: Vec<String>,
+    /// The frame rate of the match.
+    pub fps: u32,
+    /// The size of the input of a single player in bytes.
+    pub input_size: usize,
+}
+
+/// Writes a replay in the GGRS replay format, one frame at a time. Write the confirmed inputs as the session hands them out,
+/// e.g. from `P2PSession::confirmed_inputs()`, add checksums of gamestates along the way and call `finish()` with the result once the match is over.
+/// The inputs are written in records of several frames; call `flush()` to write the frames collected so far.
+#[derive(Debug)]
+pub struct ReplayWriter<W: Write> {
+    writer: W,
+    num_players: usize,
+    input_size: usize,
+    /// The hash of the last written record, which keys the hash of the next record.
+    hash: u64,
+    /// The frame the next added inputs have to belong to.
+    next_frame: Frame,
+    /// The encoded frames that have not been written yet, starting at `next_frame - pending_frames`.
+    pending: Vec<u8>,
+    pending_frames: usize,
+}
+
+impl ReplayWriter<BufWriter<File>> {
+    /// Creates the file at `path` and writes the start of a replay to it. An existing file will be overwritten.
+    /// # Errors
+    /// - Will return an `std::io::Error` if the file could not be created or written.
+    /// - Will return an `std::io::Error` of kind `InvalidInput` if the metadata is invalid, see `new()`.
+    pub fn create(path: impl AsRef<Path>, metadata: &ReplayMetadata) -> Result<Self, Error> {
+        Self::new(BufWriter::new(File::create(path)?), metadata)
+    }
+}
+
+impl<W: Write> ReplayWriter<W> {
+    /// Writes the start of a replay with the given metadata to `writer`.
+    /// # Errors
+    /// - Will return an `std::io::Error` if writing failed.
+    /// - Will return an `std::io::Error` of kind `InvalidInput` if there are no or more than `MAX_PLAYERS` players, if `input_size` is 0
+    ///   or higher than `MAX_INPUT_BYTES`, or if a name or the fingerprint is longer than 65535 bytes.
+    pub fn new(mut writer: W, metadata: &ReplayMetadata) -> Result<Self, Error> {
+        if metadata.players.is_empty() || metadata.players.len() > MAX_PLAYERS as usize {
+            return Err(invalid_input("invalid number of players"));
+        }
+        if metadata.input_size == 0 || metadata.input_size > MAX_INPUT_BYTES {
+            return Err(invalid_input("invalid input size"));
+        }
+
+        let mut payload = metadata.fps.to_le_bytes().to_vec();
+        payload.extend_from_slice(&(metadata.input_size as u16).to_le_bytes());
+        payload.push(metadata.players.len() as u8);
+        for name in &metadata.players {
+            write_bytes(&mut payload, name.as_bytes())?;
+        }
+        write_bytes(&mut payload, &metadata.game_fingerprint)?;
+
+        writer.write_all(MAGIC)?;
+        writer.write_all(&[REPLAY_FORMAT_VERSION])?;
+        let mut replay = Self {
+            writer,
+            num_players: metadata.players.len(),
+            input_size: metadata.input_size,
+            hash: 0,
+            next_frame: Frame(0),
+            pending: Vec::new(),
+            pending_frames: 0,
+        };
+        replay.write_record(METADATA, &payload)?;
+        Ok(replay)
+    }
+
+    /// Adds the inputs of all players for the given frame. Frames have to be added in order, starting at frame 0.
+    /// Inputs with `NULL_FRAME` as their frame are recorded as inputs of disconnected players.
+    /// # Errors
+    /// - Will return an `std::io::Error` if writing failed.
+    /// - Will return an `std::io::Error` of kind `InvalidInput` if `frame` is not the next frame or the inputs do not match the metadata.
+    pub fn add_frame(&mut self, frame: Frame, inputs: &[GameInput]) -> Result<(), Error> {
+        if frame != self.next_frame {
+            return Err(invalid_input("frames have to be added in order"));
+        }
+        if inputs.len() != self.num_players
+            || inputs.iter().any(|input| input.size != self.input_size)
+        {
+            return Err(invalid_input("inputs do not match the metadata"));
+        }
+
+        for input in inputs {
+            self.pending.push((input.frame == NULL_FRAME) as u8);
+            self.pending.extend_from_slice(input.input());
+        }
+        self.pending_frames += 1;
+        self.next_frame += 1;
+        if self.pending_frames == FRAMES_PER_RECORD {
+            self.write_inputs()?;
+        }
+        Ok(())
+    }
+
+    /// Adds the checksum of the gamestate of the given frame, which lets `Replay::checksum()` verify the playback.
+    /// # Errors
+    /// - Will return an `std::io::Error` if writing failed.
+    pub fn add_checksum(&mut self, frame: Frame, checksum: u64) -> Result<(), Error> {
+        // the frames before the checksum come first, so a replay cut off after the checksum contains the frames it refers to
+        self.write_inputs()?;
+        let mut payload = Vec::new();
+        write_frame(&mut payload, frame);
+        payload.extend_from_slice(&checksum.to_le_bytes());
+        self.write_record(CHECKSUM, &payload)
+    }
+
+    /// Writes all frames added so far and flushes the underlying writer.
+    /// # Errors
+    /// - Will return an `std::io::Error` if writing failed.
+    pub fn flush(&mut self) -> Result<(), Error> {
+        self.write_inputs()?;
+        self.writer.flush()
+    }
+
+    /// Writes the remaining frames and the result of the match, which completes the replay. Returns the underlying writer.
+    /// # Errors
+    /// - Will return an `std::io::Error` if writing failed.
+    /// - Will return an `std::io::Error` of kind `InvalidInput` if the result is larger than 1 MiB.
+    pub fn finish(mut self, result: &[u8]) -> Result<W, Error> {
+        if result.len() > MAX_RECORD_BYTES {
+            return Err(invalid_input("result too large"));
+        }
+        self.write_inputs()?;
+        self.write_record(RESULT, result)?;
+        self.writer.flush()?;
+        Ok(self.writer)
+    }
+
+    fn write_inputs(&mut self) -> Result<(), Error> {
+        if self.pending_frames == 0 {
+            return Ok(());
+        }
+        let mut payload = Vec::new();
+        write_frame(&mut payload, self.next_frame - self.pending_frames as i32);
+        payload.extend_from_slice(&(self.pending_frames as u16).to_le_bytes());
+        payload.append(&mut self.pending);
+        self.pending_frames = 0;
+        self.write_record(INPUTS, &payload)
+    }
+
+    fn write_record(&mut self, record_type: u8, payload: &[u8]) -> Result<(), Error> {
+        let mut record = vec![record_type];
+        record.extend_from_slice(&(payload.len() as u32).to_le_bytes());
+        record.extend_from_slice(payload);
+        self.hash = siphash(self.hash, 0, &record);
+        record.extend_from_slice(&self.hash.to_le_bytes());
+        self.writer.write_all(&record)
+    }
+}
+
+/// A replay read from the GGRS replay format, with the integrity of all records verified.
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct Replay {
+    /// Describes the match of the replay.
+    pub metadata: ReplayMetadata,
+    /// The inputs of all players for every frame, starting at frame 0. Disconnected players are indicated by having `NULL_FRAME`
+    /// instead of the correct frame in their input, just like in the inputs handed out by the sessions.
+    pub inputs: Vec<Vec<GameInput>>,
+    /// The recorded checksums of gamestates, in the order they have been added.
+    pub checksums: Vec<(Frame, u64)>,
+    /// The result of the match, or `None` if the replay is incomplete.
+    pub result: Option<Vec<u8>>,
+}
+
+impl Replay {
+    /// Reads a replay from a file.
+    /// # Errors
+    /// - Will return an `std::io::Error` if the file could not be read.
+    /// - Will return an `std::io::Error` of kind `InvalidData` if the file does not contain a valid replay.
+    pub fn from_file(path: impl AsRef<Path>) -> Result<Self, Error> {
+        Self::read(BufReader::new(File::open(path)?))
+    }
+
+    /// Reads a replay and verifies the hashes of all records.
+    /// # Errors
+    /// - Will return an `std::io::Error` if reading failed.
+    /// - Will return an `std::io::Error` of kind `InvalidData` if the bytes are not a valid replay.
+    pub fn read(mut reader: impl Read) -> Result<Self, Error> {
+        let mut start = [0; 9];
+        read_exact(&mut reader, &mut start)?;
+        if &start[..8] != MAGIC {
+            return Err(invalid_data("not a replay"));
+        }
+        if start[8] != REPLAY_FORMAT_VERSION {
+            return Err(invalid_data("unsupported replay version"));
+        }
+
+        let mut replay: Option<Self> = None;
+        let mut hash = 0;
+        while let Some((record_type, payload)) = read_record(&mut reader, &mut hash)? {
+            let mut payload = Reader { bytes: &payload };
+            match (record_type, &mut replay) {
+                (METADATA, None) => {
+                    replay = Some(Self {
+                        metadata: read_metadata(&mut payload).ok_or_else(malformed)?,
+                        ..Self::default()
+                    })
+                }
+                (INPUTS, Some(replay)) => {
+                    replay.read_inputs(&mut payload).ok_or_else(malformed)?;
+                }
+                (CHECKSUM, Some(replay)) => {
+                    let frame = payload.frame().ok_or_else(malformed)?;
+                    let checksum = payload.u64().ok_or_else(malformed)?;
+                    replay.checksums.push((frame, checksum));
+                }
+                (RESULT, Some(replay)) => {
+                    replay.result = Some(payload.bytes.to_vec());
+                    payload.bytes = &[];
+                }
+                _ => return Err(invalid_data("unexpected record")),
+            }
+            if !payload.bytes.is_empty() {
+                return Err(malformed());
+            }
+            if matches!(&replay, Some(replay) if replay.result.is_some()) {
+                break;
+            }
+        }
+
+        // nothing may follow the result
+        let replay = replay.ok_or_else(|| invalid_data("missing metadata"))?;
+        if replay.result.is_some() && reader.read(&mut [0])? != 0 {
+            return Err(invalid_data("trailing bytes after the result"));
+        }
+        Ok(replay)
+    }
+
+    /// Returns the number of frames of the replay.
+    pub fn num_frames(&self) -> usize {
+        self.inputs.len()
+    }
+
+    /// Returns the recorded checksum of the gamestate of the given frame, if there is one. Compare it with the checksum of your gamestate during the playback
+    /// to detect replays that do not play back the same way, e.g. because your game logic changed.
+    pub fn checksum(&self, frame: Frame) -> Option<u64> {
+        self.checksums
+            .iter()
+            .find(|(checksum_frame, _)| *checksum_frame == frame)
+            .map(|(_, checksum)| *checksum)
+    }
+
+    fn read_inputs(&mut self, payload: &mut Reader) -> Option<()> {
+        let start_frame = payload.frame()?;
+        if start_frame != Frame(self.inputs.len() as i64) {
+            return None;
+        }
+        let input_size = self.metadata.input_size;
+        for frame in start_frame.0..start_frame.0 + i64::from(payload.u16()?) {
+            let mut inputs = Vec::with_capacity(self.metadata.players.len());
+            for _ in 0..self.metadata.players.len() {
+                let frame = if payload.bool()? {
+                    NULL_FRAME
+                } else {
+                    Frame(frame)
+                };
+                let mut input = GameInput::new(frame, input_size);
+                input.copy_input(payload.take(input_size)?);
+                inputs.push(input);
+            }
+            self.inputs.push(inputs);
+        }
+        Some(())
+    }
+}
+
+fn read_metadata(payload: &mut Reader) -> Option<ReplayMetadata> {
+    let fps = payload.u32()?;
+    let input_size = payload.u16()? as usize;
+    let num_players = payload.u8()?;
+    if input_size == 0 || input_size > MAX_INPUT_BYTES || u32::from(num_players) > MAX_PLAYERS {
+        return None;
+    }
+    let mut players = Vec::new();
+    for _ in 0..num_players {
+        let len = payload.u16()? as usize;
+        players.push(String::from_utf8(payload.take(len)?.to_vec()).ok()?);
+    }
+    let len = payload.u16()? as usize;
+    Some(ReplayMetadata {
+        game_fingerprint: payload.take(len)?.to_vec(),
+        players,
+        fps,
+        input_size,
+    })
+}
+
+/// Reads the next record and verifies its hash. Returns `None` at the end of the replay.
+fn read_record(reader: &mut impl Read, hash: &mut u64) -> Result<Option<(u8, Vec<u8>)>, Error> {
+    let mut record = vec![0; 5];
+    // the replay may only end between two records
+    if reader.read(&mut record[..1])? == 0 {
+        return Ok(None);
+    }
+    read_exact(reader, &mut record[1..])?;
+    let num_bytes = u32::from_le_bytes(record[1..5].try_into().expect("four bytes")) as usize;
+    if num_bytes > MAX_RECORD_BYTES {
+        return Err(invalid_data("record too large"));
+    }
+    record.resize(5 + num_bytes, 0);
+    read_exact(reader, &mut record[5..])?;
+
+    let mut record_hash = [0; 8];
+    read_exact(reader, &mut record_hash)?;
+    *hash = siphash(*hash, 0, &record);
+    if u64::from_le_bytes(record_hash) != *hash {
+        return Err(invalid_data("wrong record hash"));
+    }
+    Ok(Some((record[0], record.split_off(5))))
+}
+
+/// Like `Read::read_exact()`, but reports a replay that ends too early as invalid data.
+fn read_exact(reader: &mut impl Read, buf: &mut [u8]) -> Result<(), Error> {
+    reader.read_exact(buf).map_err(|err| match err.kind() {
+        ErrorKind::UnexpectedEof => invalid_data("replay cut off"),
+        _ => err,
+    })
+}
+
+fn write_bytes(payload: &mut Vec<u8>, bytes: &[u8]) -> Result<(), Error> {
+    let len: u16 = bytes
+        .len()
+        .try_into()
+        .map_err(|_| invalid_input("metadata too long"))?;
+    payload.extend_from_slice(&len.to_le_bytes());
+    payload.extend_from_slice(bytes);
+    Ok(())
+}
+
+fn invalid_input(msg: &str) -> Error {
+    Error::new(ErrorKind::InvalidInput, msg)
+}
+
+fn invalid_data(msg: &str) -> Error {
+    Error::new(ErrorKind::InvalidData, msg)
+}
+
+fn malformed() -> Error {
+    invalid_data("malformed record")
+}
+
+// #########
+// # TESTS #
+// #########
+
+#[cfg(test)]
+mod replay_tests {
+    use super::*;
+
+    fn metadata() -> ReplayMetadata {
+        ReplayMetadata {
+            game_fingerprint: vec![0xAB; 4],
+            players: vec!["alice".to_owned(), "bob".to_owned()],
+            fps: 60,
+            input_size: 2,
+        }
+    }
+
+    fn inputs(frame: i64) -> Vec<GameInput> {
+        (0..2u8)
+            .map(|player| {
+                // the second player disconnects after frame 99
+                let input_frame = if player == 1 && frame >= 100 {
+                    NULL_FRAME
+                } else {
+                    Frame(frame)
+                };
+                let mut input = GameInput::new(input_frame, 2);
+                input.copy_input(&[frame as u8, player]);
+                input
+            })
+            .collect()
+    }
+
+    fn record_replay(num_frames: i64, result: Option<&[u8]>) -> Vec<u8> {
+        let mut writer = ReplayWriter::new(Vec::new(), &metadata()).unwrap();
+        for frame in 0..num_frames {
+            writer.add_frame(Frame(frame), &inputs(frame)).unwrap();
+            if frame % 50 == 0 {
+                writer.add_checksum(Frame(frame), frame as u64 * 7).unwrap();
+            }
+        }
+        match result {
+            Some(result) => writer.finish(result).unwrap(),
+            None => {
+                writer.flush().unwrap();
+                writer.writer
+            }
+        }
+    }
+
+    #[test]
+    fn test_roundtrip() {
+        let bytes = record_replay(150, Some(b"alice wins"));
+        assert_eq!(&bytes[..9], b"GGRSRPLY\x01");
+
+        let replay = Replay::read(&bytes[..]).unwrap();
+        assert_eq!(replay.metadata, metadata());
+        assert_eq!(replay.num_frames(), 150);
+        for (frame, frame_inputs) in replay.inputs.iter().enumerate() {
+            assert_eq!(*frame_inputs, inputs(frame as i64));
+        }
+        assert_eq!(replay.checksums.len(), 3);
+        assert_eq!(replay.checksum(Frame(100)), Some(700));
+        assert_eq!(replay.checksum(Frame(101)), None);
+        assert_eq!(replay.result, Some(b"alice wins".to_vec()));
+    }
+
+    #[test]
+    fn test_incomplete_replay() {
+        let replay = Replay::read(&record_replay(70, None)[..]).unwrap();
+        assert_eq!(replay.num_frames(), 70);
+        assert_eq!(replay.result, None);
+    }
+
+    #[test]
+    fn test_invalid_replays() {
+        let bytes = record_replay(150, Some(b"draw"));
+
+        // every corrupted byte after the magic is detected
+        for i in 9..bytes.len() {
+            let mut corrupted = bytes.clone();
+            corrupted[i] ^= 0x10;
+            assert!(Replay::read(&corrupted[..]).is_err());
+        }
+        // cut off in the middle of a record or with trailing bytes
+        assert!(Replay::read(&bytes[..bytes.len() - 1]).is_err());
+        let mut trailing = bytes.clone();
+        trailing.push(0);
+        assert!(Replay::read(&trailing[..]).is_err());
+        // other version
+        let mut other_version = bytes;
+        other_version[8] = REPLAY_FORMAT_VERSION + 1;
+        assert_eq!(
+            Replay::read(&other_version[..]).unwrap_err().kind(),
+            ErrorKind::InvalidData
+        );
+    }
+
+    #[test]
+    fn test_writer_rejects_invalid_frames() {
+        let mut writer = ReplayWriter::new(Vec::new(), &metadata()).unwrap();
+        assert!(writer.add_frame(Frame(1), &inputs(1)).is_err());
+        assert!(writer.add_frame(Frame(0), &inputs(0)[..1]).is_err());
+        assert!(writer.add_frame(Frame(0), &inputs(0)).is_ok());
+
+        let no_players = ReplayMetadata {
+            players: Vec::new(),
+            ..metadata()
+        };
+        assert!(ReplayWriter::new(Vec::new(), &no_players).is_err());
+    }
+}
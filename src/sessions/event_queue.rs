@@ -0,0 +1,200 @@
+use std::collections::vec_deque::Drain;
+use std::collections::VecDeque;
+use std::sync::Arc;
+
+use crate::time_source::{SharedTimeSource, SystemTimeSource};
+use crate::{Frame, GGRSEvent, TimedEvent, NULL_FRAME};
+
+/// The number of events a session keeps by default before discarding the oldest ones.
+pub(crate) const DEFAULT_MAX_EVENT_QUEUE_SIZE: usize = 100;
+
+/// An `EventHandler` receives the events of a session as they occur, instead of you querying them with `events()`. The session calls the handler
+/// from within the call that caused the event, e.g. `poll_remote_clients()` or `advance_frame()`, so integrations with their own event bus can forward
+/// every event right away and never miss one. Any closure taking a `TimedEvent` is an `EventHandler`. Handlers have to be `Send`, so sessions
+/// can be moved to another thread. To collect events elsewhere, share them through an `Arc<Mutex<_>>` or hand them to a channel.
+pub trait EventHandler: Send {
+    /// Handles a single event of the session.
+    fn handle_event(&mut self, event: TimedEvent);
+}
+
+impl<F: FnMut(TimedEvent) + Send> EventHandler for F {
+    fn handle_event(&mut self, event: TimedEvent) {
+        self(event)
+    }
+}
+
+/// The handler set by the user, if any.
+struct Handler(Box<dyn EventHandler>);
+
+impl std::fmt::Debug for Handler {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("EventHandler")
+    }
+}
+
+/// The events of a session that have not been queried by the user yet. Every event is stamped with the moment it has been queued,
+/// read from the time source of the session, and the frame the session was in at that moment. Sessions update that frame via `set_frame()` whenever their frame changes.
+/// If the queue is bounded, the oldest events are discarded once it is full. With an `EventHandler`, events are handed to it instead of being queued.
+#[derive(Debug)]
+pub(crate) struct EventQueue {
+    events: VecDeque<TimedEvent>,
+    frame: Frame,
+    max_size: Option<usize>,
+    discarded: usize,
+    handler: Option<Handler>,
+    time_source: SharedTimeSource,
+}
+
+impl EventQueue {
+    pub(crate) fn new() -> Self {
+        Self {
+            events: VecDeque::new(),
+            frame: NULL_FRAME,
+            max_size: Some(DEFAULT_MAX_EVENT_QUEUE_SIZE),
+            discarded: 0,
+            handler: None,
+            time_source: Arc::new(SystemTimeSource::new()),
+        }
+    }
+
+    /// Sets the frame that is attributed to all events queued from now on.
+    pub(crate) fn set_frame(&mut self, frame: Frame) {
+        self.frame = frame;
+    }
+
+    pub(crate) fn push_back(&mut self, event: GGRSEvent) {
+        let timed = TimedEvent {
+            event,
+            frame: self.frame,
+            timestamp: self.time_source.now(),
+        };
+        match &mut self.handler {
+            Some(handler) => handler.0.handle_event(timed),
+            None => {
+                self.events.push_back(timed);
+                self.discard_excess();
+            }
+        }
+    }
+
+    /// Hands all events to the handler from now on, starting with the events queued so far.
+    pub(crate) fn set_handler(&mut self, mut handler: Box<dyn EventHandler>) {
+        for timed in self.events.drain(..) {
+            handler.handle_event(timed);
+        }
+        self.handler = Some(Handler(handler));
+    }
+
+    pub(crate) fn set_time_source(&mut self, time_source: SharedTimeSource) {
+        self.time_source = time_source;
+    }
+
+    /// Sets the maximum number of stored events, or `None` to store all events until they are queried.
+    pub(crate) fn set_max_size(&mut self, max_size: Option<usize>) {
+        self.max_size = max_size;
+        self.discard_excess();
+    }
+
+    pub(crate) const fn max_size(&self) -> Option<usize> {
+        self.max_size
+    }
+
+    /// Allocates room for the maximum number of stored events, and for the event that is pushed before the oldest one is discarded.
+    pub(crate) fn preallocate(&mut self) {
+        if let Some(max_size) = self.max_size {
+            self.events.reserve_exact(max_size + 1);
+        }
+    }
+
+    /// Returns the number of events that have been discarded because the queue was full.
+    pub(crate) const fn discarded(&self) -> usize {
+        self.discarded
+    }
+
+    fn discard_excess(&mut self) {
+        if let Some(max_size) = self.max_size {
+            while self.events.len() > max_size {
+                self.events.pop_front();
+                self.discarded += 1;
+            }
+        }
+    }
+
+    pub(crate) fn drain(&mut self) -> Drain<'_, TimedEvent> {
+        self.events.drain(..)
+    }
+}
+
+// #########
+// # TESTS #
+// #########
+
+#[cfg(test)]
+mod event_queue_tests {
+    use super::*;
+
+    #[test]
+    fn test_events_are_attributed_to_frames() {
+        let mut queue = EventQueue::new();
+        queue.push_back(GGRSEvent::WaitRecommendation { skip_frames: 1 });
+        queue.set_frame(Frame(5));
+        queue.push_back(GGRSEvent::WaitRecommendation { skip_frames: 2 });
+        assert_eq!(queue.events.len(), 2);
+
+        let events: Vec<TimedEvent> = queue.drain().collect();
+        assert_eq!(events[0].frame, NULL_FRAME);
+        assert_eq!(events[1].frame, 5);
+        assert_eq!(
+            events[1].event,
+            GGRSEvent::WaitRecommendation { skip_frames: 2 }
+        );
+        assert!(events[0].timestamp <= events[1].timestamp);
+        assert_eq!(queue.events.len(), 0);
+    }
+
+    #[test]
+    fn test_bounded_queue_discards_oldest() {
+        let mut queue = EventQueue::new();
+        queue.set_max_size(Some(2));
+        for skip_frames in 0..5 {
+            queue.push_back(GGRSEvent::WaitRecommendation { skip_frames });
+        }
+        assert_eq!(queue.events.len(), 2);
+        assert_eq!(queue.discarded(), 3);
+        assert_eq!(
+            queue.drain().next().unwrap().event,
+            GGRSEvent::WaitRecommendation { skip_frames: 3 }
+        );
+
+        // an unbounded queue keeps everything
+        queue.set_max_size(None);
+        for skip_frames in 0..2 * DEFAULT_MAX_EVENT_QUEUE_SIZE as u32 {
+            queue.push_back(GGRSEvent::WaitRecommendation { skip_frames });
+        }
+        assert_eq!(queue.events.len(), 2 * DEFAULT_MAX_EVENT_QUEUE_SIZE);
+        assert_eq!(queue.discarded(), 3);
+    }
+
+    #[test]
+    fn test_handler_receives_all_events() {
+        let handled = Arc::new(parking_lot::Mutex::new(Vec::new()));
+        let mut queue = EventQueue::new();
+        queue.push_back(GGRSEvent::WaitRecommendation { skip_frames: 1 });
+
+        let handled_clone = handled.clone();
+        queue.set_handler(Box::new(move |timed: TimedEvent| {
+            handled_clone.lock().push(timed.event)
+        }));
+        queue.push_back(GGRSEvent::WaitRecommendation { skip_frames: 2 });
+
+        // the queued event is handed to the handler first, and nothing is queued anymore
+        assert_eq!(
+            *handled.lock(),
+            vec![
+                GGRSEvent::WaitRecommendation { skip_frames: 1 },
+                GGRSEvent::WaitRecommendation { skip_frames: 2 }
+            ]
+        );
+        assert_eq!(queue.drain().count(), 0);
+    }
+}
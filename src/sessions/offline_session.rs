@@ -0,0 +1,253 @@
+use alloc::borrow::ToOwned;
+use alloc::collections::VecDeque;
+use alloc::vec;
+use alloc::vec::Vec;
+
+use crate::error::GGRSError;
+use crate::frame_info::GameInput;
+use crate::input_latency::InputLatency;
+use crate::input_queue::InputQueueInfo;
+use crate::network::udp_msg::ConnectionStatus;
+#[cfg(feature = "std")]
+use crate::sessions::session::{inputs_of_all_players, Session};
+use crate::sync_layer::{GameStateCell, SyncLayer};
+use crate::{Frame, GGRSRequest, PlayerHandle};
+#[cfg(feature = "std")]
+use crate::{GGRSEvent, NetworkStats, SessionState};
+
+/// An `OfflineSession` runs a game where all players are local, e.g. single player, couch multiplayer or training modes. It hands out the same
+/// `GGRSRequest`s as the other sessions, so your game can use a single request-handling code path for online and offline play.
+/// Since all inputs are known right away, it never rolls back on its own. It only requests saving gamestates if you enable rewinding
+/// with `set_rewind_frames()`, and only requests loading them when you `rewind()`.
+#[derive(Debug)]
+pub struct OfflineSession {
+    num_players: u32,
+    input_size: usize,
+    sync_layer: SyncLayer,
+    dummy_connect_status: Vec<ConnectionStatus>,
+    rewind_frames: usize,
+    rewind_points: VecDeque<RewindPoint>,
+    input_latency: InputLatency,
+}
+
+/// A saved gamestate together with the inputs that were queued at that frame because of frame delay.
+#[derive(Debug)]
+struct RewindPoint {
+    frame: Frame,
+    cell: GameStateCell,
+    inputs: Vec<(u32, Vec<GameInput>)>,
+}
+
+impl OfflineSession {
+    /// Creates a new `OfflineSession` instance with given values.
+    pub(crate) fn new(num_players: u32, input_size: usize) -> Self {
+        let mut dummy_connect_status = Vec::new();
+        for _ in 0..num_players {
+            dummy_connect_status.push(ConnectionStatus::default());
+        }
+        Self {
+            num_players,
+            input_size,
+            sync_layer: SyncLayer::new(num_players, input_size),
+            dummy_connect_status,
+            rewind_frames: 0,
+            rewind_points: VecDeque::new(),
+            input_latency: InputLatency::new(num_players as usize),
+        }
+    }
+
+    /// Advances the state by a single frame with the inputs of all players, ordered by player handle. Returns an order-sensitive `Vec<GGRSRequest>`.
+    /// You should fulfill all requests in the exact order they are provided.
+    ///
+    /// # Errors
+    /// - Returns `InvalidRequest` if the number of inputs does not match the number of players.
+    pub fn advance_frame(&mut self, all_inputs: &[Vec<u8>]) -> Result<Vec<GGRSRequest>, GGRSError> {
+        if all_inputs.len() != self.num_players as usize {
+            return Err(GGRSError::InvalidRequest {
+                info: "An OfflineSession takes the inputs of all players.".to_owned(),
+            });
+        }
+
+        let mut requests = Vec::new();
+        if self.rewind_frames > 0 {
+            requests.push(self.save_rewind_point());
+        }
+
+        // pass all inputs into the sync layer
+        for (handle, player_input) in all_inputs.iter().enumerate() {
+            let mut input = GameInput::new(self.sync_layer.current_frame(), self.input_size);
+            input.copy_input(&self.input_latency.delay(handle, player_input));
+            self.sync_layer
+                .add_local_input(PlayerHandle(handle), input)?;
+        }
+
+        // all inputs are local, so there are no predictions
+        let frame = self.sync_layer.current_frame();
+        let inputs = self
+            .sync_layer
+            .synchronized_inputs(&self.dummy_connect_status);
+        self.sync_layer.advance_frame();
+
+        // every advanced frame is confirmed right away, so older inputs can be discarded
+        let current_frame = self.sync_layer.current_frame();
+        self.sync_layer
+            .set_last_confirmed_frame(current_frame, false);
+        for con_stat in &mut self.dummy_connect_status {
+            con_stat.last_frame = current_frame;
+        }
+
+        requests.push(GGRSRequest::AdvanceFrame { frame, inputs });
+        Ok(requests)
+    }
+
+    /// Rewinds the session to an earlier frame, e.g. for instant rewind or input playback in training modes. Returns a `LoadGameState` request
+    /// for the gamestate of that frame. Afterwards, the session continues from that frame with the inputs you give to `advance_frame()`,
+    /// which may differ from the original ones. Inputs that were given before that frame and delayed past it are kept.
+    /// Only the frames kept according to `set_rewind_frames()` can be rewound to.
+    ///
+    /// # Errors
+    /// - Returns `InvalidRequest` if the gamestate of the given frame is not kept.
+    pub fn rewind(&mut self, frame: Frame) -> Result<Vec<GGRSRequest>, GGRSError> {
+        let point = if frame < self.current_frame() {
+            self.rewind_points.iter().find(|point| point.frame == frame)
+        } else {
+            None
+        };
+        let point = point.ok_or_else(|| GGRSError::InvalidRequest {
+            info: "The gamestate of this frame is not kept.".to_owned(),
+        })?;
+
+        self.sync_layer.restore(frame, frame - 1, &point.inputs);
+        for con_stat in &mut self.dummy_connect_status {
+            con_stat.last_frame = frame - 1;
+        }
+        let cell = point.cell.clone();
+
+        // later frames will be saved again once they are advanced
+        self.rewind_points.retain(|point| point.frame <= frame);
+        Ok(vec![GGRSRequest::LoadGameState { cell, frame }])
+    }
+
+    /// Sets the number of past frames whose gamestates are kept so you can `rewind()` to them. With a value above 0,
+    /// the session requests saving the gamestate every frame. By default, no gamestates are kept.
+    pub fn set_rewind_frames(&mut self, frames: usize) {
+        self.rewind_frames = frames;
+        while self.rewind_points.len() > frames {
+            self.rewind_points.pop_front();
+        }
+    }
+
+    /// Returns the frames that can currently be rewound to, from oldest to newest.
+    pub fn rewindable_frames(&self) -> Vec<Frame> {
+        self.rewind_points.iter().map(|point| point.frame).collect()
+    }
+
+    fn save_rewind_point(&mut self) -> GGRSRequest {
+        let frame = self.sync_layer.current_frame();
+        // after a rewind, the previously kept gamestate of this frame is replaced
+        self.rewind_points.retain(|point| point.frame < frame);
+        if self.rewind_points.len() >= self.rewind_frames {
+            self.rewind_points.pop_front();
+        }
+
+        let cell = GameStateCell::default();
+        cell.reset(frame);
+        self.rewind_points.push_back(RewindPoint {
+            frame,
+            cell: cell.clone(),
+            inputs: self.sync_layer.inputs_since(frame - 1),
+        });
+        GGRSRequest::SaveGameState { cell, frame }
+    }
+
+    /// Holds back the local inputs for `frames` frames before the session uses them, to simulate the input latency your players will experience,
+    /// e.g. to tune the frame delay against the amount of rollbacks. The latency adds to the frame delay, but is not part of the input queues:
+    /// held back inputs are simply not known yet. The default is 0.
+    pub fn set_simulated_latency(&mut self, frames: u32) {
+        self.input_latency.set_frames(frames);
+    }
+
+    /// Change the amount of frames GGRS will delay the inputs for a player, e.g. to give offline play the same feel as online play.
+    /// # Errors
+    /// Returns `InvalidHandle` if the provided player handle does not refer to a player of the session.
+    pub fn set_frame_delay(
+        &mut self,
+        frame_delay: u32,
+        player_handle: PlayerHandle,
+    ) -> Result<(), GGRSError> {
+        if player_handle >= self.num_players as usize {
+            return Err(GGRSError::InvalidHandle);
+        }
+        self.sync_layer.set_frame_delay(player_handle, frame_delay);
+        Ok(())
+    }
+
+    /// Returns an `InputQueueInfo` for every player, ordered by handle. It describes which frames have confirmed inputs.
+    pub fn input_queue_info(&self) -> Vec<InputQueueInfo> {
+        self.sync_layer.input_queue_info()
+    }
+
+    /// Returns the frame of the current gamestate, which is the frame the next `AdvanceFrame` request advances from.
+    pub const fn current_frame(&self) -> Frame {
+        self.sync_layer.current_frame()
+    }
+
+    /// Returns the last frame that has been advanced, or `NULL_FRAME` if there is none yet. All inputs of an offline session are given directly,
+    /// so this is always the frame before `current_frame()`.
+    pub fn confirmed_frame(&self) -> Frame {
+        self.sync_layer.current_frame() - 1
+    }
+
+    /// Returns the number of players this session was constructed with.
+    pub const fn num_players(&self) -> u32 {
+        self.num_players
+    }
+
+    /// Returns the input size this session was constructed with.
+    pub const fn input_size(&self) -> usize {
+        self.input_size
+    }
+}
+
+#[cfg(feature = "std")]
+impl Session for OfflineSession {
+    fn advance_frame(
+        &mut self,
+        local_inputs: &[(PlayerHandle, &[u8])],
+    ) -> Result<Vec<GGRSRequest>, GGRSError> {
+        let all_inputs = inputs_of_all_players(local_inputs, self.num_players)?;
+        self.advance_frame(&all_inputs)
+    }
+
+    fn poll_remote_clients(&mut self) {}
+
+    fn events(&mut self) -> Vec<GGRSEvent> {
+        Vec::new()
+    }
+
+    fn current_state(&self) -> SessionState {
+        SessionState::Running
+    }
+
+    fn network_stats(&self, _player_handle: PlayerHandle) -> Result<NetworkStats, GGRSError> {
+        Err(GGRSError::InvalidRequest {
+            info: "An OfflineSession has no network.".to_owned(),
+        })
+    }
+
+    fn current_frame(&self) -> Frame {
+        self.current_frame()
+    }
+
+    fn confirmed_frame(&self) -> Frame {
+        self.confirmed_frame()
+    }
+
+    fn num_players(&self) -> u32 {
+        self.num_players()
+    }
+
+    fn input_size(&self) -> usize {
+        self.input_size()
+    }
+}
@@ -0,0 +1,545 @@
+use std::{
+    collections::{vec_deque::Drain, HashMap, VecDeque},
+    net::SocketAddr,
+    sync::Arc,
+    time::Duration,
+};
+
+use crate::{
+    network::{
+        connection_token::ConnectionToken,
+        udp_msg::{ConnectionStatus, FpsChange},
+        udp_protocol::{UdpProtocol, MAX_PAYLOAD},
+        udp_socket::{NonBlockingSocket, SocketStats},
+    },
+    time_source::{SharedTimeSource, SystemTimeSource, TimeSource},
+    Frame, GGRSError, GGRSEvent, GameInput, NetworkStats, PlayerHandle, SessionState, TimedEvent,
+};
+
+use super::event_queue::{EventHandler, EventQueue};
+use super::p2p_session::{Event, DEFAULT_FPS};
+use super::session::poll_until;
+
+// Handles of downstream spectators start at this value, just like the spectator handles of a `P2PSession`
+const SPECTATOR_HANDLE_OFFSET: PlayerHandle = PlayerHandle(1000);
+
+/// A downstream spectator of the relay together with the next frame of inputs it should receive.
+#[derive(Debug)]
+struct RelayedSpectator {
+    endpoint: UdpProtocol,
+    next_frame: usize,
+}
+
+/// A `P2PRelaySession` connects to a host like a `P2PSpectatorSession`, but instead of running the game, it rebroadcasts all confirmed inputs
+/// to any number of downstream `P2PSpectatorSession`s. This allows running a headless forwarding server, so the host only has to send its inputs once.
+/// The relay keeps the full input history of the match, so every downstream spectator is synchronized and caught up individually,
+/// even if it connects after the match has started. The history is never shortened and costs about 48 bytes per frame, or about 10 MB per hour at 60 FPS.
+/// Spectators that disconnected are dropped once their endpoint has shut down.
+#[derive(Debug)]
+pub struct P2PRelaySession {
+    state: SessionState,
+    num_players: u32,
+    input_size: usize,
+    inputs: Vec<GameInput>,
+    host_connect_status: Vec<ConnectionStatus>,
+    socket: Box<dyn NonBlockingSocket + Send>,
+    host: UdpProtocol,
+    spectators: HashMap<PlayerHandle, RelayedSpectator>,
+    next_spectator_handle: PlayerHandle,
+    send_window: usize,
+    event_queue: EventQueue,
+    fps: u32,
+    connection_token: Option<ConnectionToken>,
+    session_id: u32,
+    fps_change: FpsChange,
+    fps_change_applied: bool,
+    time_source: SharedTimeSource,
+}
+
+impl P2PRelaySession {
+    pub(crate) fn new(
+        num_players: u32,
+        input_size: usize,
+        host_addr: SocketAddr,
+        socket: Box<dyn NonBlockingSocket + Send>,
+    ) -> Self {
+        // host connection status
+        let mut host_connect_status = Vec::new();
+        for _ in 0..num_players {
+            host_connect_status.push(ConnectionStatus::default());
+        }
+
+        // the number of unacknowledged frames we send to a single spectator, so the encoded inputs always fit into a packet
+        let merged_input_size = input_size * num_players as usize;
+        let send_window = std::cmp::max(1, MAX_PAYLOAD / (2 * merged_input_size.max(1)));
+
+        // the host streams the input history to us in large chunks if we are far behind
+        let mut host = UdpProtocol::new(PlayerHandle(0), host_addr, num_players, merged_input_size);
+        host.set_accept_input_history(true);
+
+        Self {
+            state: SessionState::Initializing,
+            num_players,
+            input_size,
+            inputs: Vec::new(),
+            host_connect_status,
+            socket,
+            host,
+            spectators: HashMap::new(),
+            next_spectator_handle: SPECTATOR_HANDLE_OFFSET,
+            send_window,
+            event_queue: EventQueue::new(),
+            fps: DEFAULT_FPS,
+            connection_token: None,
+            session_id: 0,
+            fps_change: FpsChange::default(),
+            fps_change_applied: false,
+            time_source: Arc::new(SystemTimeSource::new()),
+        }
+    }
+
+    /// Returns the current `SessionState` of a session.
+    pub const fn current_state(&self) -> SessionState {
+        self.state
+    }
+
+    /// Adds a downstream spectator at the given address and returns the handle used in events concerning this spectator.
+    /// Spectators can be added at any time. If the session is already started, the synchronization with the spectator begins immediately.
+    pub fn add_spectator(&mut self, addr: SocketAddr) -> PlayerHandle {
+        let handle = self.next_spectator_handle;
+        self.next_spectator_handle.0 += 1;
+
+        let mut endpoint = UdpProtocol::new(
+            handle,
+            addr,
+            self.num_players,
+            self.input_size * self.num_players as usize,
+        );
+        endpoint.set_fps(self.fps);
+        endpoint.set_connection_token(self.connection_token);
+        endpoint.set_session_id(self.session_id);
+        endpoint.set_time_source(self.time_source.clone());
+        if self.state != SessionState::Initializing {
+            endpoint.synchronize();
+        }
+
+        self.spectators.insert(
+            handle,
+            RelayedSpectator {
+                endpoint,
+                next_frame: 0,
+            },
+        );
+        handle
+    }
+
+    /// Returns the number of downstream spectators that are currently connected or synchronizing.
+    pub fn num_spectators(&self) -> usize {
+        self.spectators
+            .values()
+            .filter(|spectator| {
+                !spectator.endpoint.is_synchronized() || spectator.endpoint.is_running()
+            })
+            .count()
+    }
+
+    /// Returns the last frame of which the relay has received inputs from the host or `NULL_FRAME` if it has not received any inputs yet.
+    pub fn last_received_frame(&self) -> Frame {
+        Frame(self.inputs.len() as i64) - 1
+    }
+
+    /// Used to fetch some statistics about the quality of the network connection to the host.
+    /// # Errors
+    /// - Returns `NotSynchronized` if the session is not connected to the host yet.
+    pub fn network_stats(&self) -> Result<NetworkStats, GGRSError> {
+        match self.host.network_stats() {
+            Some(stats) => Ok(stats),
+            None => Err(GGRSError::NotSynchronized),
+        }
+    }
+
+    /// Used to fetch some statistics about the quality of the network connection to a downstream spectator.
+    /// # Errors
+    /// - Returns `InvalidRequest` if the handle does not refer to a spectator of this session, e.g. because the spectator has disconnected.
+    /// - Returns `NotSynchronized` if the session is not connected to the spectator yet.
+    pub fn spectator_network_stats(
+        &self,
+        spectator_handle: PlayerHandle,
+    ) -> Result<NetworkStats, GGRSError> {
+        let spectator =
+            self.spectators
+                .get(&spectator_handle)
+                .ok_or_else(|| GGRSError::InvalidRequest {
+                    info: "No spectator with this handle.".to_owned(),
+                })?;
+        match spectator.endpoint.network_stats() {
+            Some(stats) => Ok(stats),
+            None => Err(GGRSError::NotSynchronized),
+        }
+    }
+
+    /// Returns all events that happened since last queried for events. If the number of stored events exceeds the maximum set with `set_max_event_queue_size()`, the oldest events will be discarded.
+    /// Events concerning the host carry the player handle 0, events concerning downstream spectators carry the handle returned by `add_spectator()`.
+    pub fn events(
+        &mut self,
+    ) -> impl DoubleEndedIterator<Item = GGRSEvent> + ExactSizeIterator + '_ {
+        self.event_queue.drain().map(|timed| timed.event)
+    }
+
+    /// Sets how many events the session stores until you query them. Once the limit is reached, the oldest events are discarded, which you can detect
+    /// with `discarded_events()`. With `None`, the session stores all events, no matter how long you do not query them. The default is 100 events.
+    pub fn set_max_event_queue_size(&mut self, max_size: Option<usize>) {
+        self.event_queue.set_max_size(max_size);
+    }
+
+    /// Sets an `EventHandler` that receives all events of the session as they occur, starting with the events that have not been queried yet.
+    /// Events are not stored for `events()` anymore once a handler is set.
+    pub fn set_event_handler(&mut self, handler: impl EventHandler + 'static) {
+        self.event_queue.set_handler(Box::new(handler));
+    }
+
+    /// Returns the number of events that have been discarded since the session has been created, because they were not queried before the event queue was full.
+    pub const fn discarded_events(&self) -> usize {
+        self.event_queue.discarded()
+    }
+
+    /// Like `events()`, but every event comes with the frame of the session and the moment it occurred.
+    pub fn timed_events(&mut self) -> Drain<'_, TimedEvent> {
+        self.event_queue.drain()
+    }
+
+    /// Starts the session. The relay begins to synchronize with the host and all spectators added so far.
+    /// # Errors
+    /// - Returns `InvalidRequest` if the session has already been started.
+    /// - Returns `InvalidConfiguration` listing all problems found if the session has been configured with invalid values.
+    pub fn start_session(&mut self) -> Result<(), GGRSError> {
+        // if we are not in the initialization state, we already started the session at some point
+        if self.state != SessionState::Initializing {
+            return Err(GGRSError::InvalidRequest {
+                info: "Session already started.".to_owned(),
+            });
+        }
+
+        // check the whole configuration and report all problems at once
+        let errors = self.validate_config();
+        if !errors.is_empty() {
+            return Err(GGRSError::InvalidConfiguration { errors });
+        }
+
+        // start the synchronisation
+        self.state = SessionState::Synchronizing;
+        self.host.set_fps(self.fps);
+        self.host.synchronize();
+        for spectator in self.spectators.values_mut() {
+            spectator.endpoint.set_fps(self.fps);
+            spectator.endpoint.synchronize();
+        }
+
+        Ok(())
+    }
+
+    /// Polls the host and all spectators like `poll_remote_clients()` and then keeps polling about every millisecond until `max_duration` has passed.
+    /// This lets a headless relay run its main loop without busy waiting. With a duration of zero, this polls exactly once.
+    pub fn idle(&mut self, max_duration: Duration) {
+        poll_until(max_duration, || self.poll_remote_clients());
+    }
+
+    /// Receive UDP packages, distribute them to corresponding UDP endpoints, handle all occurring events, forward received inputs
+    /// to the spectators and send all outgoing UDP packages. Since the relay does not run the game itself, this is the only method you need to call periodically.
+    pub fn poll_remote_clients(&mut self) {
+        self.event_queue
+            .set_frame(Frame(self.inputs.len() as i64) - 1);
+        // Get all udp packets and distribute them to associated endpoints.
+        // The endpoints will handle their packets, which will trigger both events and UPD replies.
+        for (from, msg) in &self.socket.receive_all_messages() {
+            if self.host.is_handling_message(from) {
+                self.host.handle_message(msg);
+                continue;
+            }
+            for spectator in self.spectators.values_mut() {
+                if spectator.endpoint.is_handling_message(from) {
+                    spectator.endpoint.handle_message(msg);
+                    break;
+                }
+            }
+        }
+
+        // run host poll and get events. This will trigger additional UDP packets to be sent.
+        let mut events = VecDeque::new();
+        for event in self.host.poll(&self.host_connect_status) {
+            events.push_back((event, PlayerHandle(0)));
+        }
+        for (handle, spectator) in self.spectators.iter_mut() {
+            for event in spectator.endpoint.poll(&self.host_connect_status) {
+                events.push_back((event, *handle));
+            }
+        }
+
+        // handle all events locally
+        for (event, handle) in events.drain(..) {
+            self.handle_event(event, handle);
+        }
+
+        // forward changes of the frame rate scheduled by the host and switch to the new frame rate once the host reached it
+        let fps_change = self.host.peer_fps_change();
+        if fps_change > self.fps_change {
+            self.event_queue.push_back(GGRSEvent::FpsChangeScheduled {
+                frame: fps_change.frame,
+                fps: fps_change.scaled_fps(),
+            });
+            if fps_change.timescale != self.fps_change.timescale {
+                self.event_queue
+                    .push_back(GGRSEvent::TimescaleChangeScheduled {
+                        frame: fps_change.frame,
+                        percent: fps_change.timescale,
+                    });
+            }
+            self.fps_change = fps_change;
+            self.fps_change_applied = false;
+        }
+        for spectator in self.spectators.values_mut() {
+            spectator.endpoint.set_fps_change(self.fps_change);
+        }
+        if !self.fps_change_applied
+            && self.fps_change.fps != 0
+            && Frame(self.inputs.len() as i64) > self.fps_change.frame
+        {
+            self.fps_change_applied = true;
+            self.update_fps(self.fps_change.scaled_fps());
+        }
+
+        // rebroadcast the inputs to every spectator, starting from the inputs each of them still needs
+        self.send_inputs_to_spectators();
+
+        // send out all pending UDP messages
+        self.host.send_all_messages(&mut *self.socket);
+        for spectator in self.spectators.values_mut() {
+            spectator.endpoint.send_all_messages(&mut *self.socket);
+        }
+        // the disconnect has been sent to spectators that shut down, so they can be forgotten
+        self.spectators
+            .retain(|_, spectator| !spectator.endpoint.is_shut_down());
+
+        // report errors of the socket itself, which are not tied to a single player
+        for error in self.socket.take_errors() {
+            self.event_queue.push_back(GGRSEvent::SocketError { error });
+        }
+    }
+
+    /// Returns statistics about the traffic on the socket of this session, if the socket keeps track of them.
+    pub fn socket_stats(&self) -> Option<SocketStats> {
+        self.socket.stats()
+    }
+
+    /// Returns the number of players this session was constructed with.
+    pub const fn num_players(&self) -> u32 {
+        self.num_players
+    }
+
+    /// Returns the input size this session was constructed with.
+    pub const fn input_size(&self) -> usize {
+        self.input_size
+    }
+
+    /// Sets the FPS the host and spectators are running with. This influences ping estimates.
+    /// Before the session has been started, the value is validated when calling `start_session()`.
+    /// # Errors
+    /// - Returns `InvalidRequest` if the session has already been started and `fps` is 0.
+    pub fn set_fps(&mut self, fps: u32) -> Result<(), GGRSError> {
+        if self.state == SessionState::Initializing {
+            self.fps = fps;
+            return Ok(());
+        }
+
+        if fps == 0 {
+            return Err(GGRSError::InvalidRequest {
+                info: "FPS should be higher than 0.".to_owned(),
+            });
+        }
+
+        self.update_fps(fps);
+        Ok(())
+    }
+
+    /// Runs the host endpoint and all spectators with the given frame rate.
+    fn update_fps(&mut self, fps: u32) {
+        self.fps = fps;
+        self.host.set_fps(fps);
+        for spectator in self.spectators.values_mut() {
+            spectator.endpoint.set_fps(fps);
+        }
+    }
+
+    /// Sets the secret shared by all peers of the match. The relay proves to the host that it knows the token and requires the same from its own spectators.
+    /// The token has to be set before starting the session and adding spectators.
+    pub fn set_connection_token(&mut self, token: &[u8]) {
+        self.connection_token = Some(ConnectionToken::new(token));
+        self.host.set_connection_token(self.connection_token);
+        for spectator in self.spectators.values_mut() {
+            spectator
+                .endpoint
+                .set_connection_token(self.connection_token);
+        }
+    }
+
+    /// Sets the identifier of the match. It has to be the same session id the host has set with `P2PSession::set_session_id()`,
+    /// and the spectators of the relay have to use it as well.
+    pub fn set_session_id(&mut self, session_id: u32) {
+        self.session_id = session_id;
+        self.host.set_session_id(session_id);
+        for spectator in self.spectators.values_mut() {
+            spectator.endpoint.set_session_id(session_id);
+        }
+    }
+
+    /// Sets the clock the relay reads the current time from, e.g. to detect disconnects and stamp events. By default, the relay uses a `SystemTimeSource`.
+    /// See `P2PSession::set_time_source()`.
+    /// # Errors
+    /// - Returns `InvalidRequest` if the session has already been started.
+    pub fn set_time_source(
+        &mut self,
+        time_source: impl TimeSource + Send + Sync + 'static,
+    ) -> Result<(), GGRSError> {
+        if self.state != SessionState::Initializing {
+            return Err(GGRSError::InvalidRequest {
+                info: "Session already started. You can only set the time source before starting the session."
+                    .to_owned(),
+            });
+        }
+
+        self.time_source = Arc::new(time_source);
+        self.event_queue.set_time_source(self.time_source.clone());
+        self.host.set_time_source(self.time_source.clone());
+        for spectator in self.spectators.values_mut() {
+            spectator.endpoint.set_time_source(self.time_source.clone());
+        }
+        Ok(())
+    }
+
+    /// Checks the configuration of the session and returns a description of every problem found.
+    fn validate_config(&self) -> Vec<String> {
+        let mut errors = Vec::new();
+
+        if self.fps == 0 {
+            errors.push("FPS should be higher than 0.".to_owned());
+        }
+
+        errors
+    }
+
+    fn send_inputs_to_spectators(&mut self) {
+        for spectator in self.spectators.values_mut() {
+            if !spectator.endpoint.is_running() {
+                continue;
+            }
+            // only keep a limited amount of unacknowledged inputs in flight, the rest is sent once the spectator catches up
+            while spectator.next_frame < self.inputs.len()
+                && spectator.endpoint.pending_output_len() < self.send_window
+            {
+                spectator
+                    .endpoint
+                    .send_input(self.inputs[spectator.next_frame], &self.host_connect_status);
+                spectator.next_frame += 1;
+            }
+        }
+    }
+
+    fn handle_event(&mut self, event: Event, player_handle: PlayerHandle) {
+        match event {
+            // forward to user
+            Event::Synchronizing { total, count } => {
+                self.event_queue.push_back(GGRSEvent::Synchronizing {
+                    player_handle,
+                    total,
+                    count,
+                });
+            }
+            // forward to user
+            Event::NetworkInterrupted { disconnect_timeout } => {
+                self.event_queue.push_back(GGRSEvent::NetworkInterrupted {
+                    player_handle,
+                    disconnect_timeout,
+                });
+            }
+            // forward to user
+            Event::NetworkResumed => {
+                self.event_queue
+                    .push_back(GGRSEvent::NetworkResumed { player_handle });
+            }
+            // forward to user
+            Event::FpsMismatch { remote_fps } => {
+                self.event_queue.push_back(GGRSEvent::FpsMismatch {
+                    player_handle,
+                    local_fps: self.fps,
+                    remote_fps,
+                });
+            }
+            // forward to user
+            Event::InvalidMessage => {
+                self.event_queue
+                    .push_back(GGRSEvent::InvalidMessage { player_handle });
+            }
+            // forward to user
+            Event::RateLimitExceeded => {
+                self.event_queue
+                    .push_back(GGRSEvent::RateLimitExceeded { player_handle });
+            }
+            // forward to user
+            Event::PendingInputsDropped { count } => {
+                self.event_queue.push_back(GGRSEvent::PendingInputsDropped {
+                    player_handle,
+                    count,
+                });
+            }
+            // synced with the host or a spectator, then forward to user
+            Event::Synchronized => {
+                if player_handle < SPECTATOR_HANDLE_OFFSET {
+                    self.state = SessionState::Running;
+                }
+                self.event_queue
+                    .push_back(GGRSEvent::Synchronized { player_handle });
+            }
+            // forward to user
+            Event::SyncFailed => {
+                self.event_queue
+                    .push_back(GGRSEvent::SyncFailed { player_handle });
+            }
+            // checksums are only exchanged between players
+            Event::StateChecksum { .. } => (),
+            // disconnect the spectator, then forward to user
+            Event::Disconnected => {
+                if let Some(spectator) = self.spectators.get_mut(&player_handle) {
+                    spectator.endpoint.disconnect();
+                }
+                self.event_queue
+                    .push_back(GGRSEvent::Disconnected { player_handle });
+            }
+            // store the input from the host, spectators do not send any inputs
+            Event::Input(input) => {
+                if player_handle >= SPECTATOR_HANDLE_OFFSET {
+                    return;
+                }
+                // the host has to send every frame exactly once and in order
+                if input.frame != Frame(self.inputs.len() as i64) {
+                    ggrs_warn!(
+                        "Host sent input for frame {}, expected frame {}",
+                        input.frame,
+                        self.inputs.len()
+                    );
+                    self.event_queue
+                        .push_back(GGRSEvent::InvalidMessage { player_handle });
+                    return;
+                }
+                self.inputs.push(input);
+
+                // update the frame advantage
+                self.host.update_local_frame_advantage(input.frame);
+
+                // update the host connection status
+                for i in 0..self.num_players as usize {
+                    self.host_connect_status[i] = self.host.peer_connect_status(PlayerHandle(i));
+                }
+            }
+        }
+    }
+}
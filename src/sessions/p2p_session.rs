@@ -1,24 +1,50 @@
 use crate::error::GGRSError;
 use crate::frame_info::GameInput;
-use crate::network::network_stats::NetworkStats;
-use crate::network::udp_msg::ConnectionStatus;
-use crate::network::udp_protocol::UdpProtocol;
-use crate::network::udp_socket::NonBlockingSocket;
+use crate::input_latency::InputLatency;
+use crate::input_predictor::{DisconnectedInput, InputBot, InputPredictor, InputTolerance};
+use crate::input_queue::{InputQueueInfo, INPUT_QUEUE_LENGTH};
+use crate::network::bandwidth::{BandwidthBudget, MessagePriority};
+use crate::network::compression::{encode, Reference};
+use crate::network::connection_token::{siphash, ConnectionToken};
+use crate::network::input_stats::{InputAnalyzer, InputEncodingStats, InputStats};
+use crate::network::network_stats::{FrameAdvantage, NetworkStats, SpectatorStats};
+use crate::network::udp_msg::{ConnectionStatus, FpsChange, PauseStatus, MAX_FPS, MAX_TIMESCALE};
+use crate::network::udp_protocol::{
+    EndpointSnapshot, PendingOutputPolicy, UdpProtocol, DEFAULT_KEEP_ALIVE_INTERVAL,
+    HISTORY_CHUNK_BYTES, MAX_PAYLOAD, PENDING_OUTPUT_SIZE, SYNC_RETRY_INTERVAL,
+};
+use crate::network::udp_socket::{NonBlockingSocket, SocketStats};
+use crate::sessions::event_queue::{EventHandler, EventQueue};
+use crate::sessions::session::{poll_until, Session};
 use crate::sync_layer::SyncLayer;
+use crate::telemetry::SessionTelemetry;
+use crate::time_source::{SharedTimeSource, SystemTimeSource, TimeSource};
+use crate::time_sync::{
+    DEFAULT_FRAME_WINDOW_SIZE, DEFAULT_MAX_FRAME_ADVANTAGE, DEFAULT_MIN_FRAME_ADVANTAGE,
+};
 use crate::{
-    Frame, GGRSEvent, GGRSRequest, PlayerHandle, PlayerType, SessionState, MAX_PREDICTION_FRAMES,
-    NULL_FRAME,
+    Frame, GGRSEvent, GGRSRequest, PlayerHandle, PlayerType, SessionState, TimedEvent,
+    MAX_PREDICTION_FRAMES, NULL_FRAME,
 };
 
+use serde::{Deserialize, Serialize};
 use std::collections::vec_deque::Drain;
+use std::collections::BTreeMap;
+use std::collections::BTreeSet;
 use std::collections::HashMap;
 use std::collections::VecDeque;
-use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+use std::net::SocketAddr;
+use std::sync::Arc;
 use std::time::Duration;
 
 /// The minimum amounts of frames between sleeps to compensate being ahead of other players
-const RECOMMENDATION_INTERVAL: Frame = 40;
-const MAX_EVENT_QUEUE_SIZE: usize = 100;
+const RECOMMENDATION_INTERVAL: i32 = 40;
+/// The number of frames the session keeps exchanged state checksums for, waiting for the checksums of all players to arrive.
+const CHECKSUM_HISTORY_LENGTH: usize = 16;
+/// Spectator handles are the handle provided by the user plus this offset.
+const SPECTATOR_HANDLE_OFFSET: usize = 1000;
+/// Spectators more than this amount of frames behind receive the input history in large chunks instead of single inputs.
+const SPECTATOR_HISTORY_THRESHOLD: usize = 128;
 const DEFAULT_SAVE_MODE: bool = false;
 pub(crate) const DEFAULT_DISCONNECT_TIMEOUT: Duration = Duration::from_millis(2000);
 pub(crate) const DEFAULT_DISCONNECT_NOTIFY_START: Duration = Duration::from_millis(500);
@@ -32,7 +58,6 @@ enum Player {
 }
 
 impl Player {
-    #[allow(dead_code)]
     const fn as_endpoint(&self) -> Option<&UdpProtocol> {
         match self {
             Player::Remote(endpoint) => Some(endpoint),
@@ -63,17 +88,12 @@ impl Player {
         }
     }
 
-    const fn spectator_as_endpoint(&self) -> Option<&UdpProtocol> {
-        match self {
-            Player::Spectator(endpoint) => Some(endpoint),
-            Player::Remote(_) | Player::Local => None,
-        }
-    }
-
-    fn spectator_as_endpoint_mut(&mut self) -> Option<&mut UdpProtocol> {
+    /// Remote players and spectators are given with their current address, which differs from the added one if the peer migrated.
+    const fn player_type(&self) -> PlayerType {
         match self {
-            Player::Spectator(endpoint) => Some(endpoint),
-            Player::Remote(_) | Player::Local => None,
+            Player::Local => PlayerType::Local,
+            Player::Remote(endpoint) => PlayerType::Remote(endpoint.peer_addr()),
+            Player::Spectator(endpoint) => PlayerType::Spectator(endpoint.peer_addr()),
         }
     }
 }
@@ -84,6 +104,8 @@ pub(crate) enum Event {
     Synchronizing { total: u32, count: u32 },
     /// The session is now synchronized with the remote client.
     Synchronized,
+    /// The remote client did not answer the sync requests in time, so the session gave up synchronizing with it.
+    SyncFailed,
     /// The session has received an input from the remote client. This event will not be forwarded to the user.
     Input(GameInput),
     /// The remote client has disconnected.
@@ -92,6 +114,231 @@ pub(crate) enum Event {
     NetworkInterrupted { disconnect_timeout: u128 },
     /// Sent only after a `NetworkInterrupted` event, if communication has resumed.
     NetworkResumed,
+    /// The remote client runs at a different FPS, so the session refuses to synchronize.
+    FpsMismatch { remote_fps: u32 },
+    /// The remote client sent a malformed message, which has been dropped.
+    InvalidMessage,
+    /// The remote client exceeded the rate limit, so its messages are dropped for the rest of the second.
+    RateLimitExceeded,
+    /// The remote client did not acknowledge inputs in time, so the oldest `count` pending inputs have been dropped.
+    PendingInputsDropped { count: u32 },
+    /// The remote client sent the checksum of its confirmed gamestate of `frame`. This event will not be forwarded to the user.
+    StateChecksum { frame: Frame, checksum: u64 },
+}
+
+type ValidatorFn = dyn Fn(PlayerHandle, Frame, &[u8]) -> bool + Send;
+
+/// A user-provided closure that checks the inputs of remote players before they are accepted.
+struct InputValidator(Box<ValidatorFn>);
+
+impl std::fmt::Debug for InputValidator {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("InputValidator")
+    }
+}
+
+/// The checksums of the confirmed gamestate of a frame, of the local session and of every remote player that has sent one.
+#[derive(Debug, Default)]
+struct FrameChecksums {
+    local: Option<u64>,
+    remote: BTreeMap<PlayerHandle, u64>,
+}
+
+/// The encoded inputs of consecutive frames, broadcast to all spectators.
+#[derive(Debug)]
+struct SpectatorBatch {
+    start_frame: Frame,
+    /// The frame of the input the batch is delta-encoded against, or `None` for keyframes.
+    reference_frame: Option<Frame>,
+    bytes: Vec<u8>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+enum PlayerSnapshot {
+    Local,
+    Remote(EndpointSnapshot),
+    Spectator(EndpointSnapshot),
+}
+
+/// Presets for `P2PSession::set_network_profile()`, giving coherent settings for common kinds of networks.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum NetworkProfile {
+    /// Peers in the same local network with pings of a few milliseconds. No frame delay, a disconnect timeout of 1 second with a notification after 300 milliseconds,
+    /// keep alives every 100 milliseconds, inputs sent every frame, a time sync window of 30 frames, wait recommendations between 2 and 6 frames
+    /// and at most 4 frames ahead of the other peers.
+    Lan,
+    /// Peers connected over the internet with a wired or stable wireless connection. A frame delay of 2, a disconnect timeout of 2 seconds with a notification
+    /// after 500 milliseconds, keep alives every 200 milliseconds, inputs sent every frame, a time sync window of 30 frames,
+    /// wait recommendations between 3 and 10 frames and at most 6 frames ahead of the other peers.
+    Broadband,
+    /// Peers on mobile networks with high and unstable latency. A frame delay of 3, a disconnect timeout of 5 seconds with a notification after 1.5 seconds,
+    /// keep alives every 500 milliseconds, inputs sent every second frame, a time sync window of 60 frames, wait recommendations between 4 and 10 frames
+    /// and no limit for the frames ahead of the other peers, since spikes of latency are common.
+    Mobile,
+}
+
+/// The settings a `NetworkProfile` stands for.
+struct ProfileSettings {
+    frame_delay: u32,
+    disconnect_timeout: Duration,
+    disconnect_notify_delay: Duration,
+    keep_alive_interval: Duration,
+    input_send_interval: u32,
+    time_sync_window: usize,
+    min_wait_recommendation: u32,
+    max_wait_recommendation: u32,
+    max_frames_ahead: u32,
+}
+
+impl NetworkProfile {
+    const fn settings(self) -> ProfileSettings {
+        match self {
+            NetworkProfile::Lan => ProfileSettings {
+                frame_delay: 0,
+                disconnect_timeout: Duration::from_millis(1000),
+                disconnect_notify_delay: Duration::from_millis(300),
+                keep_alive_interval: Duration::from_millis(100),
+                input_send_interval: 1,
+                time_sync_window: 30,
+                min_wait_recommendation: 2,
+                max_wait_recommendation: 6,
+                max_frames_ahead: 4,
+            },
+            NetworkProfile::Broadband => ProfileSettings {
+                frame_delay: 2,
+                disconnect_timeout: Duration::from_millis(2000),
+                disconnect_notify_delay: Duration::from_millis(500),
+                keep_alive_interval: Duration::from_millis(200),
+                input_send_interval: 1,
+                time_sync_window: 30,
+                min_wait_recommendation: 3,
+                max_wait_recommendation: 10,
+                max_frames_ahead: 6,
+            },
+            NetworkProfile::Mobile => ProfileSettings {
+                frame_delay: 3,
+                disconnect_timeout: Duration::from_millis(5000),
+                disconnect_notify_delay: Duration::from_millis(1500),
+                keep_alive_interval: Duration::from_millis(500),
+                input_send_interval: 2,
+                time_sync_window: 60,
+                min_wait_recommendation: 4,
+                max_wait_recommendation: 10,
+                max_frames_ahead: 0,
+            },
+        }
+    }
+}
+
+/// What a `P2PSession` does when the checksum of a confirmed gamestate of a remote player differs from its own. See `P2PSession::set_desync_policy()`.
+/// In any case, a `GGRSEvent::DesyncDetected` is sent for every player with a different checksum.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum DesyncPolicy {
+    /// Only the events are sent. This is the default.
+    Notify,
+    /// The session requests a synchronized pause, as if `P2PSession::request_pause()` had been called, so all peers stop before the desync spreads any further.
+    Pause,
+    /// Once the checksums of all connected players for the frame have arrived, the players vote: if more than half of all checksums agree,
+    /// the session disconnects every remote player with a different checksum. If the local checksum is the one that deviates, or no checksum
+    /// has a majority, e.g. with only two players, nobody is disconnected.
+    DisconnectDeviating,
+}
+
+/// A `SessionSnapshot` holds everything a `P2PSession` needs to resume a running match after the process has been restarted,
+/// without synchronizing with the peers again. It can be serialized with `serde`, e.g. to write it to disk.
+/// Get one with `P2PSession::save_session()` and restore it with `P2PSession::restore_session()`.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SessionSnapshot {
+    num_players: u32,
+    input_size: usize,
+    frame: Frame,
+    last_confirmed_frame: Frame,
+    inputs: Vec<(u32, Vec<GameInput>)>,
+    players: HashMap<PlayerHandle, PlayerSnapshot>,
+    local_connect_status: Vec<ConnectionStatus>,
+    spectator_inputs: Vec<GameInput>,
+    spectator_frames: HashMap<PlayerHandle, usize>,
+    next_confirmed_frame: Frame,
+    pause_status: PauseStatus,
+    fps_change: FpsChange,
+    barrier_frames: BTreeSet<Frame>,
+    shared_seed: Option<u64>,
+}
+
+impl SessionSnapshot {
+    /// The frame the session will continue from after restoring it. You need to load the gamestate you saved for this frame alongside the snapshot.
+    pub const fn frame(&self) -> Frame {
+        self.frame
+    }
+
+    /// Returns what is wrong with the snapshot if it could not have been taken by `P2PSession::save_session()`, e.g. because it has been tampered with on disk.
+    fn inconsistency(&self) -> Option<&'static str> {
+        let num_players = self.num_players as usize;
+        if self.frame < 0
+            || self.last_confirmed_frame < NULL_FRAME
+            || self.last_confirmed_frame > self.frame
+        {
+            return Some("The frames of the snapshot are invalid.");
+        }
+        if self.next_confirmed_frame < self.frame {
+            return Some("The next confirmed frame of the snapshot has already been discarded.");
+        }
+        if self.inputs.len() != num_players || self.local_connect_status.len() != num_players {
+            return Some(
+                "The snapshot does not contain the inputs and connection status of every player.",
+            );
+        }
+
+        // the input queues expect consecutive inputs that begin at the frame of the snapshot
+        for (_, inputs) in &self.inputs {
+            if inputs.len() > INPUT_QUEUE_LENGTH {
+                return Some("The snapshot contains more inputs than an input queue can hold.");
+            }
+            if matches!(inputs.first(), Some(first) if first.frame != self.frame)
+                || inputs
+                    .windows(2)
+                    .any(|pair| pair[0].frame + 1 != pair[1].frame)
+                || inputs.iter().any(|input| input.size != self.input_size)
+            {
+                return Some("The snapshot contains invalid inputs.");
+            }
+        }
+
+        // the spectator inputs hold the confirmed inputs of all players for every frame of the match
+        if self.spectator_inputs.iter().enumerate().any(|(i, input)| {
+            input.frame.0 as usize != i || input.size != self.input_size * num_players
+        }) || self
+            .spectator_frames
+            .values()
+            .any(|frame| *frame > self.spectator_inputs.len())
+        {
+            return Some("The snapshot contains invalid spectator inputs.");
+        }
+
+        let mut player_handles = 0;
+        for (handle, player) in &self.players {
+            match player {
+                PlayerSnapshot::Local | PlayerSnapshot::Remote(_) if handle.0 < num_players => {
+                    player_handles += 1
+                }
+                PlayerSnapshot::Spectator(_) if handle.0 >= SPECTATOR_HANDLE_OFFSET => (),
+                _ => return Some("The snapshot contains an invalid player handle."),
+            }
+        }
+        if player_handles != num_players {
+            return Some("The snapshot does not contain every player.");
+        }
+
+        // without any change, the frame rate is 0 and the session runs at the frame rate it has been configured with
+        let fps_changed = !self.fps_change.frame.is_null();
+        if (fps_changed && (self.fps_change.fps == 0 || self.fps_change.fps > MAX_FPS))
+            || self.fps_change.timescale == 0
+            || self.fps_change.timescale > MAX_TIMESCALE
+        {
+            return Some("The frame rate of the snapshot is invalid.");
+        }
+        None
+    }
 }
 
 /// A `P2PSession` provides a UDP protocol to connect to remote clients in a peer-to-peer fashion.
@@ -107,87 +354,268 @@ pub struct P2PSession {
     fps: u32,
     /// With sparse saving, the session will only request to save the minimum confirmed frame.
     sparse_saving: bool,
+    /// The number of inputs the session coalesces into a single packet to remote players.
+    input_send_interval: u32,
+    /// If not 0, unchanged inputs are only sent to remote players every this many frames.
+    sparse_input_interval: u32,
+    /// The number of frames over which frame advantages are averaged for wait recommendations.
+    time_sync_window: usize,
+    /// The smallest rift in frames for which the session recommends to wait.
+    min_wait_recommendation: u32,
+    /// The largest amount of frames a single wait recommendation asks for.
+    max_wait_recommendation: u32,
+    /// The session refuses to advance if it is more than this many frames ahead of a remote player, or 0 if disabled.
+    max_frames_ahead: u32,
+    /// How many frames the session resimulates every frame to check for desyncs, or 0 if disabled.
+    determinism_check_distance: u32,
+    /// The checksums of confirmed frames, used to detect nondeterministic resimulations.
+    checksum_history: HashMap<Frame, u64>,
+    /// The session exchanges the checksum of every confirmed frame that is a multiple of this with the remote players, or never if 0.
+    checksum_interval: u32,
+    /// The next frame of which the checksum will be sent.
+    next_checksum_frame: Frame,
+    /// The exchanged checksums of recent frames that have not been compared with all players yet.
+    state_checksums: BTreeMap<Frame, FrameChecksums>,
+    /// What the session does when a remote player has a different checksum.
+    desync_policy: DesyncPolicy,
+    /// The most frames the session resimulates within a single `advance_frame()` call, or 0 if unlimited.
+    max_rollback_depth: u32,
+    /// The number of frames the gamestate lags behind the local inputs because a correction has been deferred.
+    deferred_frames: i32,
+    /// If true, rollbacks hand out all resimulated frames in a single `GGRSRequest::Resimulate`.
+    batched_resimulation: bool,
 
     /// The time until a remote player gets disconnected.
     disconnect_timeout: Duration,
     /// The time until the client will get a notification that a remote player is about to be disconnected.
     disconnect_notify_start: Duration,
+    /// The time without sending after which the session sends a keep alive to a remote player.
+    keep_alive_interval: Duration,
+    /// The interval between sync requests and the number of resent requests after which synchronizing with a remote player fails, if limited.
+    sync_retry_interval: Duration,
+    max_sync_retries: Option<u32>,
+    /// The time after which synchronizing with a remote player fails, if limited.
+    sync_timeout: Option<Duration>,
+    /// The frame delay of the local player when it is added.
+    default_frame_delay: u32,
+    /// The maximum number of messages per second accepted from each remote player and spectator, or 0 for no limit.
+    max_messages_per_second: u32,
+    /// The maximum number of bytes per second accepted from each remote player and spectator, or 0 for no limit.
+    max_bytes_per_second: usize,
+    /// If enabled, peers that continue the match from a new address are accepted.
+    address_migration: bool,
+    /// If set, peers have to prove that they know this token while synchronizing.
+    connection_token: Option<ConnectionToken>,
+    /// Identifies the match in the header of every message.
+    session_id: u32,
+    /// If set, limits the outgoing bytes per second of the whole session.
+    bandwidth_budget: Option<BandwidthBudget>,
     /// If we receive a disconnect from another client, we have to rollback from that frame on in order to prevent wrong predictions
     disconnect_frame: Frame,
+    /// The inputs of all players are confirmed up to and including this frame.
+    min_confirmed_frame: Frame,
 
     /// Internal State of the Session.
     state: SessionState,
+    /// The moment the session has been started, as read from the time source. Used to compute telemetry rates.
+    start_time: Option<Duration>,
+    /// The clock of the session and all its endpoints.
+    time_source: SharedTimeSource,
 
     /// The `P2PSession` uses this UDP socket to send and receive all messages for remote players.
-    socket: NonBlockingSocket,
+    socket: Box<dyn NonBlockingSocket + Send>,
     /// A map of player handle to a player struct that handles receiving and sending messages for remote players, remote spectators and register local players.
     players: HashMap<PlayerHandle, Player>,
     /// This struct contains information about remote players, like connection status and the frame of last received input.
     local_connect_status: Vec<ConnectionStatus>,
 
-    /// notes which inputs have already been sent to the spectators
-    next_spectator_frame: Frame,
+    /// All confirmed inputs of all players since the start of the match, so spectators joining late can catch up.
+    spectator_inputs: Vec<GameInput>,
+    /// If enabled, spectators can be added after the session has been started, so the session keeps the inputs for them even without any spectators.
+    late_spectators: bool,
+    /// The next frame of `spectator_inputs` each spectator should receive.
+    spectator_frames: HashMap<PlayerHandle, usize>,
+    /// The amount of unacknowledged frames we send to a single spectator, so the encoded inputs always fit into a packet.
+    spectator_send_window: usize,
+    /// The number of frames sent to spectators in a single batch, or 0 if the inputs are sent to every spectator separately.
+    spectator_batch_size: usize,
+    /// Every this many batches, a batch is encoded against a blank input instead of the previous batch.
+    spectator_keyframe_interval: usize,
+    /// The encoded batches of `spectator_inputs`, shared by all spectators.
+    spectator_batches: Vec<SpectatorBatch>,
+    /// The number of confirmed frames the inputs are held back before they are sent to spectators.
+    spectator_delay: u32,
+    /// Holds back the local inputs to simulate input latency.
+    input_latency: InputLatency,
+    /// The number of unacknowledged inputs kept for each peer and what happens when a peer exceeds it.
+    pending_output_limit: usize,
+    pending_output_policy: PendingOutputPolicy,
+    /// If enabled, the session waits in `SessionState::WaitingRoom` after synchronizing until the user starts the match.
+    waiting_room: bool,
+    /// If enabled, frames only advance once the inputs of all players have arrived, without any prediction.
+    turn_based: bool,
+    /// If set, the buffers of the session are preallocated when it starts, with this many bytes for every saved gamestate.
+    bounded_memory: Option<usize>,
+    /// If enabled, the session keeps all confirmed inputs until the user drains them.
+    record_confirmed_inputs: bool,
+    /// notes which inputs have already been added to the confirmed inputs
+    next_confirmed_frame: Frame,
+    /// Contains all confirmed inputs that have not been drained by the user yet.
+    confirmed_inputs: VecDeque<(Frame, Vec<GameInput>)>,
+    /// The number of frames of confirmed inputs kept in the input history.
+    input_history_length: usize,
+    /// The most recent confirmed inputs, kept for instant replays.
+    input_history: VecDeque<(Frame, Vec<GameInput>)>,
+    /// Contains all messages received over the reliable channel that have not been drained by the user yet.
+    reliable_messages: VecDeque<(PlayerHandle, Vec<u8>)>,
+    /// The random contribution of this session to the shared seed.
+    local_seed: u32,
+    /// The seed derived from the contributions of all players, once they are known.
+    shared_seed: Option<u64>,
+    /// If enabled, analyzes which bytes of the local inputs change.
+    input_analyzer: Option<InputAnalyzer>,
     /// The soonest frame on which the session can send a `GGRSEvent::WaitRecommendation` again.
     next_recommended_sleep: Frame,
+    /// If enabled, the session follows its own wait recommendations instead of emitting them as events.
+    auto_wait: bool,
+    /// The number of calls to `advance_frame()` the session still skips to follow a wait recommendation.
+    frames_to_skip: u32,
+    /// The newest synchronized pause this session knows of.
+    pause_status: PauseStatus,
+    /// The newest synchronized change of the frame rate this session knows of.
+    fps_change: FpsChange,
+    /// True once the session has reached the frame of `fps_change` and switched to its frame rate.
+    fps_change_applied: bool,
+    /// The barrier frames the session has not passed yet.
+    barrier_frames: BTreeSet<Frame>,
+    /// The number of remote players confirmed at the current barrier frame that has last been reported to the user.
+    barrier_progress: Option<u32>,
+    /// If set, every input of a remote player is checked by this validator.
+    input_validator: Option<InputValidator>,
+    /// If enabled, remote players are disconnected when sending an invalid input.
+    disconnect_on_invalid_input: bool,
 
     ///Contains all events to be forwarded to the user.
-    event_queue: VecDeque<GGRSEvent>,
+    event_queue: EventQueue,
 }
 
 impl P2PSession {
     pub(crate) fn new(
         num_players: u32,
         input_size: usize,
-        port: u16,
-    ) -> Result<Self, std::io::Error> {
+        socket: Box<dyn NonBlockingSocket + Send>,
+    ) -> Self {
         // local connection status
         let mut local_connect_status = Vec::new();
         for _ in 0..num_players {
             local_connect_status.push(ConnectionStatus::default());
         }
 
-        // udp nonblocking socket creation
-        let addr = SocketAddr::new(IpAddr::V4(Ipv4Addr::new(0, 0, 0, 0)), port); //TODO: IpV6?
-        let socket = NonBlockingSocket::new(addr)?;
+        let merged_input_size = input_size * num_players as usize;
+        let spectator_send_window = std::cmp::max(1, MAX_PAYLOAD / (2 * merged_input_size.max(1)));
 
-        Ok(Self {
+        Self {
             state: SessionState::Initializing,
+            start_time: None,
             num_players,
             input_size,
             fps: DEFAULT_FPS,
             sparse_saving: DEFAULT_SAVE_MODE,
+            input_send_interval: 1,
+            sparse_input_interval: 0,
+            time_sync_window: DEFAULT_FRAME_WINDOW_SIZE,
+            min_wait_recommendation: DEFAULT_MIN_FRAME_ADVANTAGE,
+            max_wait_recommendation: DEFAULT_MAX_FRAME_ADVANTAGE,
+            max_frames_ahead: 0,
+            determinism_check_distance: 0,
+            checksum_history: HashMap::new(),
+            checksum_interval: 0,
+            next_checksum_frame: NULL_FRAME,
+            state_checksums: BTreeMap::new(),
+            desync_policy: DesyncPolicy::Notify,
+            max_rollback_depth: 0,
+            deferred_frames: 0,
+            batched_resimulation: false,
             socket,
             local_connect_status,
-            next_recommended_sleep: 0,
-            next_spectator_frame: 0,
+            next_recommended_sleep: Frame(0),
+            auto_wait: false,
+            frames_to_skip: 0,
+            pause_status: PauseStatus::default(),
+            fps_change: FpsChange::default(),
+            fps_change_applied: false,
+            barrier_frames: BTreeSet::new(),
+            barrier_progress: None,
+            input_validator: None,
+            disconnect_on_invalid_input: false,
+            spectator_inputs: Vec::new(),
+            late_spectators: false,
+            spectator_frames: HashMap::new(),
+            spectator_send_window,
+            spectator_batch_size: 0,
+            spectator_keyframe_interval: 1,
+            spectator_batches: Vec::new(),
+            spectator_delay: 0,
+            input_latency: InputLatency::new(num_players as usize),
+            pending_output_limit: PENDING_OUTPUT_SIZE,
+            pending_output_policy: PendingOutputPolicy::Disconnect,
+            waiting_room: false,
+            turn_based: false,
+            bounded_memory: None,
+            record_confirmed_inputs: false,
+            next_confirmed_frame: Frame(0),
+            confirmed_inputs: VecDeque::new(),
+            input_history_length: 0,
+            input_analyzer: None,
+            input_history: VecDeque::new(),
+            reliable_messages: VecDeque::new(),
+            local_seed: rand::random::<u32>(),
+            shared_seed: None,
             sync_layer: SyncLayer::new(num_players, input_size),
             disconnect_timeout: DEFAULT_DISCONNECT_TIMEOUT,
             disconnect_notify_start: DEFAULT_DISCONNECT_NOTIFY_START,
+            keep_alive_interval: DEFAULT_KEEP_ALIVE_INTERVAL,
+            sync_retry_interval: SYNC_RETRY_INTERVAL,
+            max_sync_retries: None,
+            sync_timeout: None,
+            default_frame_delay: 0,
+            max_messages_per_second: 0,
+            max_bytes_per_second: 0,
+            address_migration: false,
+            connection_token: None,
+            session_id: 0,
+            bandwidth_budget: None,
             disconnect_frame: NULL_FRAME,
+            min_confirmed_frame: NULL_FRAME,
             players: HashMap::new(),
-            event_queue: VecDeque::new(),
-        })
+            event_queue: EventQueue::new(),
+            time_source: Arc::new(SystemTimeSource::new()),
+        }
     }
 
     /// Must be called for each player in the session (e.g. in a 3 player session, must be called 3 times) before starting the session. Returns the player handle
     /// used by GGRS to represent that player internally. The player handle will be the same you provided for players, but `player_handle + 1000` for spectators.
     /// You will need the player handle to add input, change parameters or disconnect the player or spectator.
+    /// With `set_late_spectators_enabled()`, spectators can also be added after the session has been started. They synchronize right away and are sent
+    /// all inputs since the start of the match, a limited amount of frames at a time, until they have caught up.
     ///
     /// # Errors
     /// - Returns `InvalidHandle` when the provided player handle is too big for the number of players
     /// - Returns `InvalidRequest` if a player with that handle has been added before
-    /// - Returns `InvalidRequest` if the session has already been started
+    /// - Returns `InvalidRequest` if the session has already been started and the player is not a spectator, or late spectators are disabled
     /// - Returns `InvalidRequest` when adding more than one local player
     pub fn add_player(
         &mut self,
         player_type: PlayerType,
         player_handle: PlayerHandle,
     ) -> Result<PlayerHandle, GGRSError> {
-        // currently, you can only add players in the init phase
-        if self.state != SessionState::Initializing {
+        // you can only add players in the init phase, and spectators later on if late spectators are enabled
+        if self.state != SessionState::Initializing
+            && !matches!(player_type, PlayerType::Spectator(_))
+        {
             return Err(GGRSError::InvalidRequest {
                 info:
-                    "Session already started. You can only add players before starting the session."
+                    "Session already started. You can only add spectators after starting the session."
                         .to_owned(),
             });
         }
@@ -196,13 +624,15 @@ impl P2PSession {
         match player_type {
             PlayerType::Local => self.add_local_player(player_handle),
             PlayerType::Remote(addr) => self.add_remote_player(player_handle, addr),
-            PlayerType::Spectator(addr) => self.add_spectator(player_handle, addr),
+            PlayerType::Spectator(addr) => self.add_remote_spectator(player_handle, addr),
         }
     }
 
     /// After you are done defining and adding all players, you should start the session. Then, the synchronization process will begin.
+    /// Before starting, the whole configuration of the session is validated.
     /// # Errors
-    /// - Returns `InvalidRequest` if the session has already been started or if insufficient players have been registered.
+    /// - Returns `InvalidRequest` if the session has already been started.
+    /// - Returns `InvalidConfiguration` listing all problems found if insufficient players have been registered or the session has been configured with invalid values.
     pub fn start_session(&mut self) -> Result<(), GGRSError> {
         // if we are not in the initialization state, we already started the session at some point
         if self.state != SessionState::Initializing {
@@ -211,30 +641,175 @@ impl P2PSession {
             });
         }
 
-        // check if all players are added
-        for player_handle in 0..self.num_players as PlayerHandle {
-            if self.players.get(&player_handle).is_none() {
-                return Err(GGRSError::InvalidRequest{
-                    info: "Not enough players have been added. Keep registering players up to the defined player number.".to_owned(),
-                });
-            }
+        // check the whole configuration and report all problems at once
+        let errors = self.validate_config();
+        if !errors.is_empty() {
+            return Err(GGRSError::InvalidConfiguration { errors });
         }
 
+        self.preallocate();
+
         // start the synchronisation
         self.state = SessionState::Synchronizing;
+        self.start_time = Some(self.time_source.now());
         for endpoint in self
             .players
             .values_mut()
             .filter_map(Player::as_endpoint_mut)
         {
+            endpoint.set_fps(self.fps);
             endpoint.synchronize();
         }
+        for endpoint in self
+            .players
+            .values_mut()
+            .filter_map(Player::remote_as_endpoint_mut)
+        {
+            endpoint.set_input_send_interval(self.input_send_interval);
+            endpoint.set_sparse_input_interval(self.sparse_input_interval);
+            endpoint.set_time_sync_window(self.time_sync_window);
+            endpoint.set_wait_recommendation_limits(
+                self.min_wait_recommendation,
+                self.max_wait_recommendation,
+            );
+        }
+
+        // without any remote endpoints, there is nothing to synchronize with
+        self.check_initial_sync();
+        Ok(())
+    }
+
+    /// Returns a snapshot of the session that allows you to resume the match after a crash or restart with `restore_session()`.
+    /// The snapshot begins at the last confirmed frame, so alongside the snapshot you need to persist the gamestate you saved for `SessionSnapshot::frame()`.
+    /// The snapshot is outdated as soon as the session exchanges new messages, so you should take one after every call to `advance_frame()` or `poll_remote_clients()`.
+    /// # Errors
+    /// - Returns `NotSynchronized` if the session is not running yet.
+    pub fn save_session(&self) -> Result<SessionSnapshot, GGRSError> {
+        if self.state != SessionState::Running {
+            return Err(GGRSError::NotSynchronized);
+        }
+
+        let mut players = HashMap::new();
+        for (handle, player) in &self.players {
+            let player_snapshot = match player {
+                Player::Local => PlayerSnapshot::Local,
+                Player::Remote(endpoint) => {
+                    PlayerSnapshot::Remote(endpoint.snapshot().ok_or(GGRSError::NotSynchronized)?)
+                }
+                Player::Spectator(endpoint) => PlayerSnapshot::Spectator(
+                    endpoint.snapshot().ok_or(GGRSError::NotSynchronized)?,
+                ),
+            };
+            players.insert(*handle, player_snapshot);
+        }
+
+        let frame = std::cmp::max(self.sync_layer.last_confirmed_frame(), Frame(0));
+        Ok(SessionSnapshot {
+            num_players: self.num_players,
+            input_size: self.input_size,
+            frame,
+            last_confirmed_frame: self.sync_layer.last_confirmed_frame(),
+            inputs: self.sync_layer.inputs_since(frame),
+            players,
+            local_connect_status: self.local_connect_status.clone(),
+            spectator_inputs: self.spectator_inputs.clone(),
+            spectator_frames: self.spectator_frames.clone(),
+            next_confirmed_frame: self.next_confirmed_frame,
+            pause_status: self.pause_status,
+            fps_change: self.fps_change,
+            barrier_frames: self.barrier_frames.clone(),
+            shared_seed: self.shared_seed,
+        })
+    }
+
+    /// Restores a session from a snapshot taken with `save_session()`, for example after the process has been restarted. Instead of adding players and starting the session,
+    /// the session continues running with the players of the snapshot, so the peers do not need to synchronize again. Before advancing, load the gamestate you saved for `SessionSnapshot::frame()`.
+    /// Inputs you already sent to your peers before the restart are replayed; the input you provide to `advance_frame()` for those frames is ignored.
+    /// Configure the session (e.g. FPS and disconnect timeout) before restoring it. Your peers must not have disconnected you in the meantime, so you might need a long enough disconnect timeout.
+    /// # Errors
+    /// - Returns `InvalidRequest` if the session has already been started or players have been added.
+    /// - Returns `InvalidRequest` if the snapshot has been taken from a session with a different number of players or input size.
+    /// - Returns `InvalidRequest` if the snapshot is inconsistent, e.g. because it has been corrupted on disk.
+    /// - Returns `InvalidConfiguration` listing all problems found if the session has been configured with invalid values.
+    pub fn restore_session(&mut self, snapshot: SessionSnapshot) -> Result<(), GGRSError> {
+        if self.state != SessionState::Initializing || !self.players.is_empty() {
+            return Err(GGRSError::InvalidRequest {
+                info:
+                    "A session can only be restored before adding players and starting the session."
+                        .to_owned(),
+            });
+        }
+        if snapshot.num_players != self.num_players || snapshot.input_size != self.input_size {
+            return Err(GGRSError::InvalidRequest {
+                info: "The snapshot does not match the number of players or the input size of this session."
+                    .to_owned(),
+            });
+        }
+        if let Some(info) = snapshot.inconsistency() {
+            return Err(GGRSError::InvalidRequest {
+                info: info.to_owned(),
+            });
+        }
+
+        // recreate the players and their endpoints, which continue from where they left off
+        for (handle, player_snapshot) in snapshot.players {
+            let player = match player_snapshot {
+                PlayerSnapshot::Local => Player::Local,
+                PlayerSnapshot::Remote(endpoint) => {
+                    let mut endpoint = self.restore_endpoint(endpoint);
+                    endpoint.set_input_send_interval(self.input_send_interval);
+                    endpoint.set_sparse_input_interval(self.sparse_input_interval);
+                    endpoint.set_time_sync_window(self.time_sync_window);
+                    endpoint.set_wait_recommendation_limits(
+                        self.min_wait_recommendation,
+                        self.max_wait_recommendation,
+                    );
+                    Player::Remote(Box::new(endpoint))
+                }
+                PlayerSnapshot::Spectator(endpoint) => {
+                    Player::Spectator(Box::new(self.restore_endpoint(endpoint)))
+                }
+            };
+            self.players.insert(handle, player);
+        }
+
+        // check the whole configuration and report all problems at once
+        let errors = self.validate_config();
+        if !errors.is_empty() {
+            self.players.clear();
+            return Err(GGRSError::InvalidConfiguration { errors });
+        }
+
+        self.sync_layer.restore(
+            snapshot.frame,
+            snapshot.last_confirmed_frame,
+            &snapshot.inputs,
+        );
+        self.local_connect_status = snapshot.local_connect_status;
+        self.spectator_inputs = snapshot.spectator_inputs;
+        self.spectator_frames = snapshot.spectator_frames;
+        self.next_confirmed_frame = snapshot.next_confirmed_frame;
+        self.next_recommended_sleep = snapshot.frame;
+        self.pause_status = snapshot.pause_status;
+        self.fps_change = snapshot.fps_change;
+        self.fps_change_applied = false;
+        self.barrier_frames = snapshot.barrier_frames;
+        self.shared_seed = snapshot.shared_seed;
+        self.barrier_progress = None;
+        self.disconnect_frame = NULL_FRAME;
+        self.min_confirmed_frame = snapshot.last_confirmed_frame;
+        self.preallocate();
+
+        self.state = SessionState::Running;
+        self.start_time = Some(self.time_source.now());
         Ok(())
     }
 
-    /// Disconnects a remote player from a game.  
+    /// Disconnects a remote player from a game, e.g. to kick an abusive or AFK player. The disconnect is propagated to all other peers
+    /// through the connection status, so they disconnect the player at the same frame. A `GGRSEvent::Disconnected` is emitted on all peers.
     /// # Errors
-    /// - Returns `InvalidRequest` if you try to disconnect a player who has already been disconnected or if you try to disconnect a local player.
+    /// - Returns `InvalidRequest` if you try to disconnect a local player.
+    /// - Returns `PlayerDisconnected` if you try to disconnect a player who has already been disconnected.
     pub fn disconnect_player(&mut self, player_handle: PlayerHandle) -> Result<(), GGRSError> {
         match self.players.get_mut(&player_handle) {
             // the local player cannot be disconnected
@@ -243,9 +818,11 @@ impl P2PSession {
             }),
             // a remote player can only be disconnected if not already disconnected, since there is some additional logic attached
             Some(Player::Remote(_)) => {
-                if !self.local_connect_status[player_handle].disconnected {
-                    let last_frame = self.local_connect_status[player_handle].last_frame;
+                if !self.local_connect_status[player_handle.0].disconnected {
+                    let last_frame = self.local_connect_status[player_handle.0].last_frame;
                     self.disconnect_player_at_frame(player_handle, last_frame);
+                    self.event_queue
+                        .push_back(GGRSEvent::Disconnected { player_handle });
                     return Ok(());
                 }
                 Err(GGRSError::PlayerDisconnected)
@@ -253,29 +830,138 @@ impl P2PSession {
             // disconnecting spectators is simpler
             Some(Player::Spectator(_)) => {
                 self.disconnect_player_at_frame(player_handle, NULL_FRAME);
+                self.event_queue
+                    .push_back(GGRSEvent::Disconnected { player_handle });
                 Ok(())
             }
         }
     }
 
+    /// Adds a spectator at the given address and returns its handle, which is the lowest free spectator handle. Unlike `add_player()`, you don't need to pick a handle yourself,
+    /// which is convenient for viewers that come and go during a long match. If the session has been started, the spectator synchronizes right away and catches up on all inputs since the start of the match,
+    /// which requires `set_late_spectators_enabled()`. As long as the session has a spectator or accepts late spectators, it keeps the confirmed inputs of all players for every frame of the match,
+    /// which costs about 48 bytes per frame, or about 10 MB per hour at 60 FPS.
+    /// # Errors
+    /// - Returns `InvalidRequest` if a spectator with that address has been added before.
+    /// - Returns `InvalidRequest` if the session has already been started and late spectators are disabled.
+    pub fn add_spectator(&mut self, addr: SocketAddr) -> Result<PlayerHandle, GGRSError> {
+        if self.spectator_handle(addr).is_some() {
+            return Err(GGRSError::InvalidRequest {
+                info: "A spectator with this address already exists.".to_owned(),
+            });
+        }
+
+        let spectator_handle = (SPECTATOR_HANDLE_OFFSET..)
+            .find(|handle| !self.players.contains_key(&PlayerHandle(*handle)))
+            .expect("no free spectator handle");
+        self.add_remote_spectator(
+            PlayerHandle(spectator_handle - SPECTATOR_HANDLE_OFFSET),
+            addr,
+        )
+    }
+
+    /// Removes the spectator at the given address from the session. The spectator is notified that it has been disconnected and its handle becomes free again.
+    /// # Errors
+    /// - Returns `InvalidRequest` if there is no spectator with that address.
+    pub fn remove_spectator(&mut self, addr: SocketAddr) -> Result<(), GGRSError> {
+        let spectator_handle =
+            self.spectator_handle(addr)
+                .ok_or_else(|| GGRSError::InvalidRequest {
+                    info: "No spectator with this address.".to_owned(),
+                })?;
+
+        // tell the spectator right away, since the endpoint is gone afterwards
+        if let Some(Player::Spectator(mut endpoint)) = self.players.remove(&spectator_handle) {
+            endpoint.disconnect();
+            endpoint.poll(&self.local_connect_status).for_each(drop);
+            endpoint.send_all_messages(&mut *self.socket);
+        }
+        self.spectator_frames.remove(&spectator_handle);
+        Ok(())
+    }
+
+    /// Overrides the frame after which the inputs of a disconnected remote player are considered `NULL_FRAME`.
+    /// The disconnect frame can only be moved back, since later inputs of the player might not have reached all peers.
+    /// All peers adopt the earliest disconnect frame they learn of, so if a host overrides the frame, all peers will agree on it.
+    /// The session will roll back and resimulate if necessary.
+    /// # Errors
+    /// - Returns `InvalidRequest` if the handle does not refer to a remote player.
+    /// - Returns `InvalidRequest` if the player is not disconnected yet.
+    /// - Returns `InvalidRequest` if the given frame is later than the current disconnect frame or earlier than the last confirmed frame.
+    pub fn override_disconnect_frame(
+        &mut self,
+        player_handle: PlayerHandle,
+        frame: Frame,
+    ) -> Result<(), GGRSError> {
+        match self.players.get(&player_handle) {
+            Some(Player::Remote(_)) => (),
+            _ => {
+                return Err(GGRSError::InvalidRequest {
+                    info: "The disconnect frame can only be overridden for remote players."
+                        .to_owned(),
+                })
+            }
+        }
+
+        let con_stat = self.local_connect_status[player_handle.0];
+        if !con_stat.disconnected {
+            return Err(GGRSError::InvalidRequest {
+                info: "Player is not disconnected.".to_owned(),
+            });
+        }
+        if frame > con_stat.last_frame || frame < self.sync_layer.last_confirmed_frame() {
+            return Err(GGRSError::InvalidRequest {
+                info: "Disconnect frame can only be moved back, but not before the last confirmed frame."
+                    .to_owned(),
+            });
+        }
+
+        if frame < con_stat.last_frame {
+            self.disconnect_player_at_frame(player_handle, frame);
+        }
+        Ok(())
+    }
+
     /// You should call this to notify GGRS that you are ready to advance your gamestate by a single frame.
     /// Returns an order-sensitive `Vec<GGRSRequest>`. You should fulfill all requests in the exact order they are provided.
     /// Failure to do so will cause panics later.
     ///
+    /// If this returns an error, the local input has not been added and no requests have been lost, so you can simply try again next frame without
+    /// submitting an input twice or skipping one. The only exception are turn-based sessions, which keep the input of a turn while `WaitingForTurn`.
+    ///
     /// # Errors
     /// - Returns `InvalidHandle` if the provided player handle is higher than the number of players.
     /// - Returns `InvalidRequest` if the provided player handle refers to a remote player.
-    /// - Returns `NotSynchronized` if the session is not yet ready to accept input. In this case, you either need to start the session or wait for synchronization between clients.
+    /// - Returns `NotSynchronized` if the session is not yet ready to accept input. In this case, you either need to start the session, wait for synchronization between clients or start the match in the waiting room.
+    /// - Returns `Paused` if the session reached the frame of a synchronized pause. See `request_pause()`.
+    /// - Returns `WaitingAtBarrier` if the session reached a barrier frame and waits for the inputs of other peers. See `add_barrier_frame()`.
+    /// - Returns `TooFarAhead` if the session is further ahead of a remote player than allowed. See `set_max_frames_ahead()`.
+    /// - Returns `PredictionThreshold` if the session cannot predict any further frames until inputs of the remote players arrive.
+    /// - Returns `PredictionThreshold` if a peer has not acknowledged as many inputs as allowed with `PendingOutputPolicy::BlockAdvance`. See `set_pending_output_limit()`.
+    /// - Returns `WaitingForTurn` in a turn-based session until the turn inputs of all players have arrived. See `set_turn_based()`.
     pub fn advance_frame(
         &mut self,
         local_player_handle: PlayerHandle,
         local_input: &[u8],
+    ) -> Result<Vec<GGRSRequest>, GGRSError> {
+        ggrs_span!("advance_frame", frame = self.sync_layer.current_frame().0);
+        let start = self.time_source.now();
+        let result = self.advance_frame_untimed(local_player_handle, local_input);
+        self.sync_layer
+            .record_advance_frame(self.time_source.now().saturating_sub(start));
+        result
+    }
+
+    fn advance_frame_untimed(
+        &mut self,
+        local_player_handle: PlayerHandle,
+        local_input: &[u8],
     ) -> Result<Vec<GGRSRequest>, GGRSError> {
         // receive info from remote players, trigger events and send messages
         self.poll_remote_clients();
 
         // player handle is invalid
-        if local_player_handle > self.num_players as PlayerHandle {
+        if local_player_handle > self.num_players as usize {
             return Err(GGRSError::InvalidHandle);
         }
 
@@ -290,23 +976,108 @@ impl P2PSession {
             return Err(GGRSError::NotSynchronized);
         }
 
+        // all peers wait at the pause frame until someone resumes
+        if self.is_paused() {
+            return Err(GGRSError::Paused);
+        }
+
+        // wait at barrier frames until the inputs of all connected peers up to the barrier have arrived
+        if self.wait_at_barrier() {
+            return Err(GGRSError::WaitingAtBarrier);
+        }
+
+        // turns are neither predicted nor paced, they end once all players have submitted their input
+        if self.turn_based {
+            return self.advance_turn(local_player_handle, local_input);
+        }
+
+        // follow the own wait recommendation by not advancing the gamestate
+        if self.frames_to_skip > 0 {
+            self.frames_to_skip -= 1;
+            return Ok(Vec::new());
+        }
+
+        // switch the frame rate at the frame all peers agreed on
+        self.apply_fps_change();
+
+        // refuse to run away from remote players, even if wait recommendations are ignored
+        if self.max_frames_ahead > 0 && self.frames_ahead() > self.max_frames_ahead as i32 {
+            return Err(GGRSError::TooFarAhead);
+        }
+
+        // wait for peers that have not acknowledged as many inputs as allowed
+        if self
+            .players
+            .values()
+            .filter_map(Player::as_endpoint)
+            .any(UdpProtocol::is_pending_output_full)
+        {
+            return Err(GGRSError::PredictionThreshold);
+        }
+
+        // compare the checksums of resimulated frames with their originals
+        if self.determinism_check_distance > 0 {
+            self.check_determinism()?;
+        }
+
+        // compare the checksums of confirmed frames with the remote players
+        if self.checksum_interval > 0 {
+            self.exchange_checksums();
+        }
+
+        ggrs_trace!("Advancing frame {}", self.sync_layer.current_frame());
+
+        // find the total minimum confirmed frame and propagate disconnects. Without any connected player, nothing new is confirmed
+        if let Some(min_confirmed) = self.min_confirmed_frame() {
+            self.min_confirmed_frame = min_confirmed;
+        }
+        let min_confirmed = self.min_confirmed_frame;
+
+        // refuse to predict too far before saving or rolling back, so the session is unchanged and the local input can be given again next frame
+        SyncLayer::check_prediction_threshold(
+            self.sync_layer.current_frame() + self.deferred_frames,
+            min_confirmed,
+        )?;
+
         // This list of requests will be returned to the user
         let mut requests = Vec::new();
 
-        // if we are in the first frame, we have to save the state
-        if self.sync_layer.current_frame() == 0 {
+        // if we are in the first frame (or the first frame after restoring the session), we have to save the state
+        if self.sync_layer.last_saved_frame() == NULL_FRAME {
             requests.push(self.sync_layer.save_current_state());
         }
 
-        // find the total minimum confirmed frame and propagate disconnects
-        let min_confirmed = self.min_confirmed_frame();
-
         // check game consistency and rollback, if necessary.
         // The disconnect frame indicates if a rollback is necessary due to a previously disconnected player
-        let first_incorrect = self
+        let mut first_incorrect = self
             .sync_layer
             .check_simulation_consistency(self.disconnect_frame);
-        if first_incorrect != NULL_FRAME {
+        let mispredicted_frame = first_incorrect;
+
+        // in paranoid mode, also resimulate the last few frames in order to compare their checksums next frame
+        if self.determinism_check_distance > 0 {
+            let frame_to = self.sync_layer.current_frame() - self.determinism_check_distance as i32;
+            if frame_to >= 0
+                && (first_incorrect == NULL_FRAME || frame_to < first_incorrect)
+                && self.sync_layer.saved_state_by_frame(frame_to).is_some()
+            {
+                first_incorrect = frame_to;
+            }
+        }
+
+        if first_incorrect != NULL_FRAME || self.deferred_frames > 0 {
+            // only frames the gamestate has already reached are rolled back, frames of a deferred correction have not been simulated yet
+            if mispredicted_frame != NULL_FRAME
+                && mispredicted_frame < self.sync_layer.current_frame()
+            {
+                ggrs_debug!(
+                    "Rolling back, the first mispredicted frame is {}",
+                    mispredicted_frame
+                );
+                self.event_queue.push_back(GGRSEvent::Misprediction {
+                    first_incorrect_frame: mispredicted_frame,
+                });
+            }
             self.adjust_gamestate(first_incorrect, min_confirmed, &mut requests);
             self.disconnect_frame = NULL_FRAME;
         }
@@ -314,7 +1085,7 @@ impl P2PSession {
         // in sparse saving mode, we need to make sure not to lose the last saved frame
         let last_saved = self.sync_layer.last_saved_frame();
         if self.sparse_saving
-            && self.sync_layer.current_frame() - last_saved >= MAX_PREDICTION_FRAMES as i32
+            && self.sync_layer.current_frame() - last_saved >= MAX_PREDICTION_FRAMES as i64
         {
             // check if the current frame is confirmed, otherwise we need to roll back
             if min_confirmed >= self.sync_layer.current_frame() {
@@ -335,6 +1106,9 @@ impl P2PSession {
         // send confirmed inputs to remotes
         self.send_confirmed_inputs_to_spectators(min_confirmed);
 
+        // keep confirmed inputs for the user, if requested
+        self.record_confirmed_inputs(min_confirmed);
+
         // set the last confirmed frame and discard all saved inputs before that frame
         self.sync_layer
             .set_last_confirmed_frame(min_confirmed, self.sparse_saving);
@@ -343,28 +1117,44 @@ impl P2PSession {
         if self.sync_layer.current_frame() > self.next_recommended_sleep {
             let skip_frames = self.max_delay_recommendation(true);
             if skip_frames > 0 {
+                ggrs_debug!("Recommending to wait {} frames", skip_frames);
                 self.next_recommended_sleep =
                     self.sync_layer.current_frame() + RECOMMENDATION_INTERVAL;
-                self.event_queue
-                    .push_back(GGRSEvent::WaitRecommendation { skip_frames });
+                if self.auto_wait {
+                    self.frames_to_skip = skip_frames;
+                } else {
+                    self.event_queue
+                        .push_back(GGRSEvent::WaitRecommendation { skip_frames });
+                }
             }
         }
 
-        //create an input struct for current frame
-        let mut game_input: GameInput =
-            GameInput::new(self.sync_layer.current_frame(), self.input_size);
-        game_input.copy_input(local_input);
+        //create an input struct for current frame, which is ahead of the gamestate while a correction is deferred
+        let mut game_input: GameInput = GameInput::new(
+            self.sync_layer.current_frame() + self.deferred_frames,
+            self.input_size,
+        );
+        game_input.copy_input(&self.input_latency.delay(local_player_handle.0, local_input));
 
-        // send the input into the sync layer
-        let actual_frame = self
+        // send the input into the sync layer. After restoring a session, the inputs we sent before the restart are replayed instead
+        let actual_frame = if self
             .sync_layer
-            .add_local_input(local_player_handle, game_input)?;
+            .is_local_input_added(local_player_handle, game_input.frame)
+        {
+            NULL_FRAME
+        } else {
+            self.sync_layer
+                .add_local_input_unchecked(local_player_handle, game_input)
+        };
 
         // if the actual frame is the null frame, the frame has been dropped by the input queues (for example due to changed input delay)
         if actual_frame != NULL_FRAME {
             // if not dropped, send the input to all other clients, but with the correct frame (influenced by input delay)
             game_input.frame = actual_frame;
-            self.local_connect_status[local_player_handle].last_frame = actual_frame;
+            self.local_connect_status[local_player_handle.0].last_frame = actual_frame;
+            if let Some(analyzer) = &mut self.input_analyzer {
+                analyzer.record_input(&game_input);
+            }
 
             for endpoint in self
                 .players
@@ -373,8 +1163,8 @@ impl P2PSession {
             {
                 // send the input directly
                 endpoint.send_input(game_input, &self.local_connect_status);
-                endpoint.send_all_messages(&self.socket);
             }
+            self.send_all_messages();
         }
 
         // without sparse saving, always save the current frame
@@ -392,18 +1182,31 @@ impl P2PSession {
         }
 
         // advance the frame
+        requests.push(GGRSRequest::AdvanceFrame {
+            frame: self.sync_layer.current_frame(),
+            inputs,
+        });
         self.sync_layer.advance_frame();
-        requests.push(GGRSRequest::AdvanceFrame { inputs });
 
         Ok(requests)
     }
 
+    /// Gives GGRS time to do its internal work while your game is not advancing frames, e.g. while `advance_frame()` returns `PredictionThreshold`,
+    /// during a synchronized pause or while waiting because of a `GGRSEvent::WaitRecommendation`. This polls the remote clients like `poll_remote_clients()`,
+    /// which also keeps the time synchronization going, and then keeps polling about every millisecond until `max_duration` has passed.
+    /// With a duration of zero, this polls exactly once and returns right away. `advance_frame()` polls by itself, so you do not need to call this before it.
+    pub fn idle(&mut self, max_duration: Duration) {
+        poll_until(max_duration, || self.poll_remote_clients());
+    }
+
     /// Should be called periodically by your application to give GGRS a chance to do internal work.
     /// GGRS will receive UDP packets, distribute them to corresponding endpoints, handle all occurring events and send all outgoing UDP packets.
     pub fn poll_remote_clients(&mut self) {
+        self.event_queue.set_frame(self.sync_layer.current_frame());
         // Get all udp packets and distribute them to associated endpoints.
         // The endpoints will handle their packets, which will trigger both events and UPD replies.
         for (from, msg) in &self.socket.receive_all_messages() {
+            let mut handled = false;
             for endpoint in self
                 .players
                 .values_mut()
@@ -411,9 +1214,27 @@ impl P2PSession {
             {
                 if endpoint.is_handling_message(from) {
                     endpoint.handle_message(msg);
+                    handled = true;
                     break;
                 }
             }
+
+            // a peer whose NAT assigned it a new address keeps talking to us from there
+            if !handled && self.address_migration {
+                if let Some(endpoint) = self
+                    .players
+                    .values_mut()
+                    .filter_map(Player::as_endpoint_mut)
+                    .find(|endpoint| endpoint.is_migrating(msg))
+                {
+                    endpoint.set_peer_addr(*from);
+                    endpoint.handle_message(msg);
+                    self.event_queue.push_back(GGRSEvent::PeerAddressChanged {
+                        player_handle: endpoint.player_handle(),
+                        addr: *from,
+                    });
+                }
+            }
         }
 
         // update frame information between remote players
@@ -445,23 +1266,69 @@ impl P2PSession {
             self.handle_event(event, handle);
         }
 
-        // send all queued UDP packets
+        // derive the shared seed once the contributions of all players are known
+        if self.shared_seed.is_none() {
+            self.shared_seed = self.derive_shared_seed();
+        }
+
+        // collect the messages of the reliable channel
         for endpoint in self
             .players
             .values_mut()
             .filter_map(Player::as_endpoint_mut)
         {
-            endpoint.send_all_messages(&self.socket);
+            let player_handle = endpoint.player_handle();
+            for msg in endpoint.take_reliable_messages() {
+                self.reliable_messages.push_back((player_handle, msg));
+            }
+        }
+
+        // adopt pause requests of remote players
+        let newest_pause_status = self
+            .players
+            .values()
+            .filter_map(Player::remote_as_endpoint)
+            .filter(|endpoint| endpoint.is_running())
+            .map(UdpProtocol::peer_pause_status)
+            .max()
+            .unwrap_or_default();
+        self.update_pause_status(newest_pause_status);
+
+        // adopt changes of the frame rate requested by remote players
+        let newest_fps_change = self
+            .players
+            .values()
+            .filter_map(Player::remote_as_endpoint)
+            .filter(|endpoint| endpoint.is_running())
+            .map(UdpProtocol::peer_fps_change)
+            .max()
+            .unwrap_or_default();
+        self.update_fps_change(newest_fps_change);
+
+        // continue sending inputs to spectators that are still catching up
+        self.send_inputs_to_spectators();
+
+        // send all queued UDP packets
+        self.send_all_messages();
+
+        // report errors of the socket itself, which are not tied to a single player
+        for error in self.socket.take_errors() {
+            self.event_queue.push_back(GGRSEvent::SocketError { error });
         }
     }
 
+    /// Returns statistics about the traffic on the socket of this session, if the socket keeps track of them.
+    pub fn socket_stats(&self) -> Option<SocketStats> {
+        self.socket.stats()
+    }
+
     /// Returns a `NetworkStats` struct that gives information about the quality of the network connection.
     /// # Errors
     /// - Returns `InvalidHandle` if the provided player handle does not refer to an existing remote player.
     /// - Returns `NotSynchronized` if the session is not connected to other clients yet.
     pub fn network_stats(&self, player_handle: PlayerHandle) -> Result<NetworkStats, GGRSError> {
         // player handle is invalid
-        if player_handle > self.num_players as PlayerHandle {
+        if player_handle > self.num_players as usize {
             return Err(GGRSError::InvalidHandle);
         }
 
@@ -482,97 +1349,1097 @@ impl P2PSession {
         }
     }
 
-    /// Change the amount of frames GGRS will delay the inputs for a player. You should only set the frame delay for local players.
+    /// Returns a `NetworkStats` struct that gives information about the quality of the network connection to the spectator at the given address.
+    /// Use this to monitor the connections of your spectators and to remove spectators with `remove_spectator()` that are lagging behind or flooding the host.
     /// # Errors
-    /// - Returns `InvalidHandle` if the provided player handle is invalid.
-    /// - Returns `InvalidRequest` if the provided player handle does not refer to a local player.
-    pub fn set_frame_delay(
-        &mut self,
-        frame_delay: u32,
-        player_handle: PlayerHandle,
-    ) -> Result<(), GGRSError> {
-        // player handle is invalid
-        if player_handle > self.num_players as PlayerHandle {
-            return Err(GGRSError::InvalidHandle);
-        }
-
-        match self
-            .players
-            .get(&player_handle)
-            .ok_or(GGRSError::InvalidHandle)?
-        {
-            Player::Remote(_) | Player::Spectator(_) => Err(GGRSError::InvalidRequest {
-                info: "Frame delay can only be set for the local player.".to_owned(),
-            }),
-            Player::Local => {
-                self.sync_layer.set_frame_delay(player_handle, frame_delay);
-                Ok(())
+    /// - Returns `InvalidRequest` if there is no spectator with that address.
+    /// - Returns `NotSynchronized` if the spectator is not connected.
+    pub fn network_stats_for_spectator(&self, addr: SocketAddr) -> Result<NetworkStats, GGRSError> {
+        let spectator_handle =
+            self.spectator_handle(addr)
+                .ok_or_else(|| GGRSError::InvalidRequest {
+                    info: "No spectator with this address.".to_owned(),
+                })?;
+        match self.players.get(&spectator_handle) {
+            Some(Player::Spectator(endpoint)) => {
+                endpoint.network_stats().ok_or(GGRSError::NotSynchronized)
             }
+            _ => Err(GGRSError::NotSynchronized),
         }
     }
 
-    /// Sets the disconnect timeout. The session will automatically disconnect from a remote peer if it has not received a packet in the timeout window.
-    pub fn set_disconnect_timeout(&mut self, timeout: Duration) {
-        self.disconnect_timeout = timeout;
-        for endpoint in self
+    /// Returns a `FrameAdvantage` for every connected remote player, ordered by handle. Use this to show players whether they are ahead or behind,
+    /// or to implement your own pacing instead of following `GGRSEvent::WaitRecommendation`.
+    pub fn frame_advantages(&self) -> Vec<FrameAdvantage> {
+        let mut advantages: Vec<FrameAdvantage> = self
             .players
-            .values_mut()
+            .iter()
+            .filter_map(|(handle, player)| match player {
+                Player::Remote(endpoint) if !self.local_connect_status[handle.0].disconnected => {
+                    let (local_avg, remote_avg) = endpoint.average_frame_advantage();
+                    Some(FrameAdvantage {
+                        player_handle: *handle,
+                        local_frames_behind: local_avg,
+                        remote_frames_behind: remote_avg,
+                        frames_ahead: (remote_avg - local_avg) / 2.0,
+                        recommended_wait: endpoint.recommend_frame_delay(true),
+                    })
+                }
+                Player::Local | Player::Remote(_) | Player::Spectator(_) => None,
+            })
+            .collect();
+        advantages.sort_by_key(|advantage| advantage.player_handle);
+        advantages
+    }
+
+    /// Returns an `InputQueueInfo` for every player, ordered by handle. It describes which frames have confirmed inputs, which frames are predicted
+    /// and from which frame on the session will roll back. Useful for rollback debugging overlays.
+    pub fn input_queue_info(&self) -> Vec<InputQueueInfo> {
+        self.sync_layer.input_queue_info()
+    }
+
+    /// Returns the number of spectators that are currently connected or synchronizing.
+    pub fn num_spectators(&self) -> usize {
+        self.players
+            .values()
+            .filter(|player| match player {
+                Player::Spectator(endpoint) => !endpoint.is_synchronized() || endpoint.is_running(),
+                Player::Local | Player::Remote(_) => false,
+            })
+            .count()
+    }
+
+    /// Returns a `SpectatorStats` for every spectator that is connected, ordered by handle. Use this to show how many viewers are watching or to cap the number of spectators.
+    pub fn spectator_stats(&self) -> Vec<SpectatorStats> {
+        let mut stats: Vec<SpectatorStats> = self
+            .players
+            .iter()
+            .filter_map(|(handle, player)| match player {
+                Player::Spectator(endpoint) => endpoint.network_stats().map(|network_stats| {
+                    let acked_frames = (endpoint.last_acked_frame().0 + 1) as usize;
+                    SpectatorStats {
+                        player_handle: *handle,
+                        frames_behind: self
+                            .spectator_frames_available()
+                            .saturating_sub(acked_frames)
+                            as u32,
+                        ping: network_stats.ping,
+                        kbps_sent: network_stats.kbps_sent,
+                    }
+                }),
+                Player::Local | Player::Remote(_) => None,
+            })
+            .collect();
+        stats.sort_by_key(|spectator| spectator.player_handle);
+        stats
+    }
+
+    /// Returns a `SessionTelemetry` struct that gives information about the rollbacks performed, the packets transmitted and the time spent in `advance_frame()` since the session was started.
+    pub fn telemetry(&self) -> SessionTelemetry {
+        let mut telemetry = self.sync_layer.telemetry();
+
+        let seconds = match self.start_time {
+            Some(start_time) => self
+                .time_source
+                .now()
+                .saturating_sub(start_time)
+                .as_secs_f32(),
+            None => return telemetry,
+        };
+
+        if seconds > 0.0 {
+            let (mut sent, mut received) = (0, 0);
+            for endpoint in self.players.values().filter_map(Player::as_endpoint) {
+                sent += endpoint.packets_sent();
+                received += endpoint.packets_received();
+            }
+            telemetry.packets_sent_per_second = sent as f32 / seconds;
+            telemetry.packets_received_per_second = received as f32 / seconds;
+        }
+
+        telemetry
+    }
+
+    /// Enables or disables the input analysis. While enabled, the session records which bytes of the local inputs change from frame to frame.
+    /// Disabling the analysis discards everything recorded so far.
+    pub fn set_input_analysis(&mut self, enabled: bool) {
+        if !enabled {
+            self.input_analyzer = None;
+        } else if self.input_analyzer.is_none() {
+            self.input_analyzer = Some(InputAnalyzer::new(self.input_size));
+        }
+    }
+
+    /// Returns an `InputStats` struct that tells you how well the inputs sent by this session compress and which bytes of your input change most often.
+    /// Returns `None` if the input analysis has not been enabled with `set_input_analysis()`.
+    pub fn input_stats(&self) -> Option<InputStats> {
+        let analyzer = self.input_analyzer.as_ref()?;
+        let mut encoding = InputEncodingStats::default();
+        for endpoint in self.players.values().filter_map(Player::as_endpoint) {
+            encoding.merge(endpoint.input_encoding_stats());
+        }
+        Some(analyzer.stats(encoding))
+    }
+
+    /// Holds back the local inputs for `frames` frames before the session uses them, to simulate the input latency your players will experience,
+    /// e.g. to tune the frame delay against the amount of rollbacks. The latency adds to the frame delay, but is not part of the input queues:
+    /// held back inputs are not sent to the other peers yet, so they see the latency as well. The default is 0.
+    pub fn set_simulated_latency(&mut self, frames: u32) {
+        self.input_latency.set_frames(frames);
+    }
+
+    /// Change the amount of frames GGRS will delay the inputs for a player. You should only set the frame delay for local players.
+    /// # Errors
+    /// - Returns `InvalidHandle` if the provided player handle is invalid.
+    /// - Returns `InvalidRequest` if the provided player handle does not refer to a local player.
+    pub fn set_frame_delay(
+        &mut self,
+        frame_delay: u32,
+        player_handle: PlayerHandle,
+    ) -> Result<(), GGRSError> {
+        // player handle is invalid
+        if player_handle > self.num_players as usize {
+            return Err(GGRSError::InvalidHandle);
+        }
+
+        match self
+            .players
+            .get(&player_handle)
+            .ok_or(GGRSError::InvalidHandle)?
+        {
+            Player::Remote(_) | Player::Spectator(_) => Err(GGRSError::InvalidRequest {
+                info: "Frame delay can only be set for the local player.".to_owned(),
+            }),
+            Player::Local => {
+                self.sync_layer.set_frame_delay(player_handle, frame_delay);
+                Ok(())
+            }
+        }
+    }
+
+    /// Sets the disconnect timeout. The session will automatically disconnect from a remote peer if it has not received a packet in the timeout window.
+    pub fn set_disconnect_timeout(&mut self, timeout: Duration) {
+        self.disconnect_timeout = timeout;
+        for endpoint in self
+            .players
+            .values_mut()
             .filter_map(Player::as_endpoint_mut)
         {
             endpoint.set_disconnect_timeout(timeout);
         }
     }
 
-    /// Sets the time before the first notification will be sent in case of a prolonged period of no received packages.
-    pub fn set_disconnect_notify_delay(&mut self, notify_delay: Duration) {
-        self.disconnect_notify_start = notify_delay;
-        for endpoint in self
-            .players
-            .values_mut()
-            .filter_map(Player::as_endpoint_mut)
-        {
-            endpoint.set_disconnect_notify_start(notify_delay);
+    /// Sets the clock the session reads the current time from, e.g. to resend messages, send keep alives, detect disconnects and stamp events.
+    /// By default, the session uses a `SystemTimeSource`. With a `ManualTimeSource`, you advance the time of the session yourself, e.g. to test
+    /// disconnect timeouts without waiting for them.
+    /// # Errors
+    /// - Returns `InvalidRequest` if the session has already been started.
+    pub fn set_time_source(
+        &mut self,
+        time_source: impl TimeSource + Send + Sync + 'static,
+    ) -> Result<(), GGRSError> {
+        if self.state != SessionState::Initializing {
+            return Err(GGRSError::InvalidRequest {
+                info: "Session already started. You can only set the time source before starting the session."
+                    .to_owned(),
+            });
+        }
+
+        self.time_source = Arc::new(time_source);
+        self.event_queue.set_time_source(self.time_source.clone());
+        if let Some(budget) = &mut self.bandwidth_budget {
+            budget.restart(self.time_source.now());
+        }
+        for endpoint in self
+            .players
+            .values_mut()
+            .filter_map(Player::as_endpoint_mut)
+        {
+            endpoint.set_time_source(self.time_source.clone());
+        }
+        Ok(())
+    }
+
+    /// Sets how long the session waits without sending anything to a remote player or spectator before it sends a keep alive.
+    /// Shorter intervals detect broken connections sooner, longer intervals save traffic. The default is 200 milliseconds.
+    pub fn set_keep_alive_interval(&mut self, interval: Duration) {
+        self.keep_alive_interval = interval;
+        for endpoint in self
+            .players
+            .values_mut()
+            .filter_map(Player::as_endpoint_mut)
+        {
+            endpoint.set_keep_alive_interval(interval);
+        }
+    }
+
+    /// Sets how often the session resends sync requests to remote players and spectators that have not answered yet, and how many requests it resends
+    /// before it gives up with a `GGRSEvent::SyncFailed`. With `None`, the session keeps trying until the sync timeout passes, if there is one.
+    /// The default is a sync request every 200 milliseconds without a limit.
+    pub fn set_sync_retries(&mut self, interval: Duration, max_retries: Option<u32>) {
+        self.sync_retry_interval = interval;
+        self.max_sync_retries = max_retries;
+        for endpoint in self
+            .players
+            .values_mut()
+            .filter_map(Player::as_endpoint_mut)
+        {
+            endpoint.set_sync_retries(interval, max_retries);
+        }
+    }
+
+    /// Sets how long the session tries to synchronize with a remote player or spectator before it gives up with a `GGRSEvent::SyncFailed`.
+    /// A player that failed to synchronize is disconnected, so the session can start without it. By default, there is no timeout.
+    pub fn set_sync_timeout(&mut self, timeout: Option<Duration>) {
+        self.sync_timeout = timeout;
+        for endpoint in self
+            .players
+            .values_mut()
+            .filter_map(Player::as_endpoint_mut)
+        {
+            endpoint.set_sync_timeout(timeout);
+        }
+    }
+
+    /// Configures the session with coherent settings for the given kind of network, so you do not need to tune every setting yourself.
+    /// See `NetworkProfile` for the values. The profile sets the frame delay of the local player, the disconnect timeout and notify delay,
+    /// the keep alive interval, the input send interval, the time sync window, the wait recommendation limits and the maximum frames ahead.
+    /// All of them can be changed afterwards with their own setters.
+    pub fn set_network_profile(&mut self, profile: NetworkProfile) {
+        let settings = profile.settings();
+        self.default_frame_delay = settings.frame_delay;
+        if let Some(handle) = self.local_player_handle() {
+            self.sync_layer
+                .set_frame_delay(handle, settings.frame_delay);
+        }
+        self.set_disconnect_timeout(settings.disconnect_timeout);
+        self.set_disconnect_notify_delay(settings.disconnect_notify_delay);
+        self.set_keep_alive_interval(settings.keep_alive_interval);
+        self.update_input_send_interval(settings.input_send_interval);
+        self.set_time_sync_window(settings.time_sync_window);
+        self.set_wait_recommendation_limits(
+            settings.min_wait_recommendation,
+            settings.max_wait_recommendation,
+        );
+        self.set_max_frames_ahead(settings.max_frames_ahead);
+    }
+
+    /// Limits how many messages and bytes per second the session accepts from each remote player and spectator. Messages above the limit are dropped
+    /// and reported with `GGRSEvent::RateLimitExceeded`. This protects sessions with public addresses against floods of packets. A limit of 0 disables it.
+    /// By default, there are no limits. Keep in mind that peers send several messages per frame, so the limits should allow for a few times your FPS.
+    pub fn set_rate_limit(&mut self, messages_per_second: u32, bytes_per_second: usize) {
+        self.max_messages_per_second = messages_per_second;
+        self.max_bytes_per_second = bytes_per_second;
+        for endpoint in self
+            .players
+            .values_mut()
+            .filter_map(Player::as_endpoint_mut)
+        {
+            endpoint.set_rate_limit(messages_per_second, bytes_per_second);
+        }
+    }
+
+    /// Limits the outgoing traffic of the whole session to the given number of bytes per second, including IP and UDP headers. When the budget is tight,
+    /// inputs and input acks for remote players are sent first, followed by quality reports and keep alives, and messages to spectators last.
+    /// Messages that do not fit into the budget are dropped; unacknowledged inputs are resent later anyway. A limit of 0 disables it, which is the default.
+    /// Keep in mind that a budget that is too small for the inputs of all players delays the inputs and causes long rollbacks or disconnects.
+    pub fn set_bandwidth_limit(&mut self, bytes_per_second: usize) {
+        self.bandwidth_budget = match bytes_per_second {
+            0 => None,
+            _ => Some(BandwidthBudget::new(
+                bytes_per_second,
+                self.time_source.now(),
+            )),
+        };
+    }
+
+    /// Sets a secret shared by all peers of the match, for example handed out by your matchmaking. Remote players and spectators have to prove that they know
+    /// the same token while synchronizing, so strangers who learn the address of this session can not join the match. The token has to be set before the
+    /// peers synchronize. Spectators use `P2PSpectatorSession::set_connection_token()`.
+    pub fn set_connection_token(&mut self, token: &[u8]) {
+        self.connection_token = Some(ConnectionToken::new(token));
+        for endpoint in self
+            .players
+            .values_mut()
+            .filter_map(Player::as_endpoint_mut)
+        {
+            endpoint.set_connection_token(self.connection_token);
+        }
+    }
+
+    /// Sets an identifier of the match, for example handed out by your matchmaking, which is sent in the header of every message.
+    /// Messages with a different session id are dropped, so packets of a previous match or of another match on the same port can not interfere,
+    /// e.g. during a quick rematch that reuses the socket. All peers and spectators of the match have to use the same session id. Defaults to 0.
+    /// Spectators use `P2PSpectatorSession::set_session_id()`.
+    pub fn set_session_id(&mut self, session_id: u32) {
+        self.session_id = session_id;
+        for endpoint in self
+            .players
+            .values_mut()
+            .filter_map(Player::as_endpoint_mut)
+        {
+            endpoint.set_session_id(session_id);
+        }
+    }
+
+    /// Enables address migration. If the NAT of a peer assigns it a new address during the match, for example when switching from Wi-Fi to a mobile network,
+    /// the session accepts the new address once the peer proves its identity with the data established during synchronization, and emits `GGRSEvent::PeerAddressChanged`.
+    /// Without address migration, messages from the new address are ignored and the peer eventually times out. Disabled by default.
+    pub fn set_address_migration_enabled(&mut self, enabled: bool) {
+        self.address_migration = enabled;
+    }
+
+    /// Sets the time before the first notification will be sent in case of a prolonged period of no received packages.
+    /// On connections with high or jittery roundtrip times, the session waits longer, as long as a reply can be expected to take.
+    pub fn set_disconnect_notify_delay(&mut self, notify_delay: Duration) {
+        self.disconnect_notify_start = notify_delay;
+        for endpoint in self
+            .players
+            .values_mut()
+            .filter_map(Player::as_endpoint_mut)
+        {
+            endpoint.set_disconnect_notify_start(notify_delay);
+        }
+    }
+
+    /// Sets the FPS this session is used with. This influences estimations for frame synchronization between sessions.
+    /// Before the session has been started, the value is validated when calling `start_session()`.
+    /// # Errors
+    /// - Returns `InvalidRequest` if the session has already been started and `fps` is 0.
+    pub fn set_fps(&mut self, fps: u32) -> Result<(), GGRSError> {
+        if self.state == SessionState::Initializing {
+            self.fps = fps;
+            return Ok(());
+        }
+
+        if fps == 0 {
+            return Err(GGRSError::InvalidRequest {
+                info: "FPS should be higher than 0.".to_owned(),
+            });
+        }
+
+        self.update_fps(fps);
+        Ok(())
+    }
+
+    /// Runs the session and all endpoints with the given frame rate.
+    fn update_fps(&mut self, fps: u32) {
+        self.fps = fps;
+        for endpoint in self
+            .players
+            .values_mut()
+            .filter_map(Player::as_endpoint_mut)
+        {
+            endpoint.set_fps(fps);
+        }
+    }
+
+    /// Sets after how many inputs the session sends a packet to remote players. With an interval of 2, the inputs of two frames are coalesced into a single packet,
+    /// which halves the packet rate at the cost of a frame of additional latency. This can be useful for mobile connections or games running at high FPS. The default is 1.
+    /// Before the session has been started, the value is validated when calling `start_session()`.
+    /// # Errors
+    /// - Returns `InvalidRequest` if the session has already been started and `interval` is 0 or larger than `MAX_PREDICTION_FRAMES`.
+    pub fn set_input_send_interval(&mut self, interval: u32) -> Result<(), GGRSError> {
+        if self.state != SessionState::Initializing
+            && (interval == 0 || interval > MAX_PREDICTION_FRAMES)
+        {
+            return Err(GGRSError::InvalidRequest {
+                info: format!(
+                    "Input send interval should be between 1 and {}.",
+                    MAX_PREDICTION_FRAMES
+                ),
+            });
+        }
+        self.update_input_send_interval(interval);
+        Ok(())
+    }
+
+    /// Coalesces the given number of inputs into a packet to every remote player.
+    fn update_input_send_interval(&mut self, interval: u32) {
+        self.input_send_interval = interval;
+        for endpoint in self
+            .players
+            .values_mut()
+            .filter_map(Player::remote_as_endpoint_mut)
+        {
+            endpoint.set_input_send_interval(interval);
+        }
+    }
+
+    /// Enables sparse inputs for games where inputs rarely change, like turn-based or slow-paced games. A changed input is sent to remote players right away,
+    /// but unchanged inputs are only sent every `interval` frames, so remote players can still confirm them before they reach the prediction threshold.
+    /// Each message contains every unchanged input only once, together with the number of frames it stands for. While inputs do not change,
+    /// this cuts the packet rate to a packet every `interval` frames. Sparse inputs replace the input send interval. The default is 0, which disables sparse inputs.
+    /// The value is validated when calling `start_session()`. After the session has been started, invalid values are ignored.
+    pub fn set_sparse_input_interval(&mut self, interval: u32) {
+        if self.state != SessionState::Initializing {
+            if interval > MAX_PREDICTION_FRAMES {
+                return;
+            }
+            for endpoint in self
+                .players
+                .values_mut()
+                .filter_map(Player::remote_as_endpoint_mut)
+            {
+                endpoint.set_sparse_input_interval(interval);
+            }
+        }
+        self.sparse_input_interval = interval;
+    }
+
+    /// Sets the number of frames over which the frame advantages of both peers are averaged before recommending to wait. A shorter window reacts faster to changing
+    /// network conditions, while a longer window leads to steadier recommendations. The default is 30 frames. Changing the window after the session has been started discards the collected frame advantages.
+    /// The value is validated when calling `start_session()`. After the session has been started, invalid values are ignored.
+    pub fn set_time_sync_window(&mut self, frames: usize) {
+        if self.state != SessionState::Initializing {
+            if frames == 0 {
+                return;
+            }
+            for endpoint in self
+                .players
+                .values_mut()
+                .filter_map(Player::remote_as_endpoint_mut)
+            {
+                endpoint.set_time_sync_window(frames);
+            }
+        }
+        self.time_sync_window = frames;
+    }
+
+    /// Sets how aggressively the session recommends to wait with `GGRSEvent::WaitRecommendation`. The session only recommends to wait if it is at least `min_frames` ahead of a remote peer
+    /// and never recommends waiting more than `max_frames` at once. Lower values keep peers more closely in sync at the cost of more frequent slowdowns. The defaults are 3 and 10.
+    /// The values are validated when calling `start_session()`. After the session has been started, invalid values are ignored.
+    pub fn set_wait_recommendation_limits(&mut self, min_frames: u32, max_frames: u32) {
+        if self.state != SessionState::Initializing {
+            if min_frames > max_frames {
+                return;
+            }
+            for endpoint in self
+                .players
+                .values_mut()
+                .filter_map(Player::remote_as_endpoint_mut)
+            {
+                endpoint.set_wait_recommendation_limits(min_frames, max_frames);
+            }
+        }
+        self.min_wait_recommendation = min_frames;
+        self.max_wait_recommendation = max_frames;
+    }
+
+    /// If enabled, the session follows its own wait recommendations: instead of emitting a `GGRSEvent::WaitRecommendation`, the next calls to `advance_frame()`
+    /// return an empty list of requests for the recommended number of frames. The local input of those calls is discarded, so games that simply fulfill
+    /// the returned requests every frame stay in sync with their peers without handling the event. Disabled by default.
+    pub fn set_auto_wait(&mut self, enabled: bool) {
+        self.auto_wait = enabled;
+        if !enabled {
+            self.frames_to_skip = 0;
+        }
+    }
+
+    /// Limits how many frames the session may run ahead of any connected remote player. While the session is further ahead, `advance_frame()` returns
+    /// `GGRSError::TooFarAhead` instead of advancing, so the game can not speed away from its peers even if it ignores `GGRSEvent::WaitRecommendation`.
+    /// The limit should be well below the prediction window of 8 frames to have an effect. A limit of 0 disables it, which is the default.
+    pub fn set_max_frames_ahead(&mut self, frames: u32) {
+        self.max_frames_ahead = frames;
+    }
+
+    /// Enables paranoid mode, in which the session works like a `SyncTestSession` while playing online: every frame, it additionally rolls back
+    /// `check_distance` frames and resimulates them. The checksums of confirmed frames are compared with the checksums from previous simulations of the same frames,
+    /// so desyncs caused by nondeterministic game code are detected right away. You need to provide checksums when saving your gamestate.
+    /// The resimulations show up in the telemetry as rollbacks. A distance of 0 disables paranoid mode, which is the default.
+    /// The value is validated when calling `start_session()`. After the session has been started, invalid values are ignored.
+    pub fn set_determinism_check_distance(&mut self, check_distance: u32) {
+        if self.state != SessionState::Initializing
+            && (check_distance >= MAX_PREDICTION_FRAMES
+                || (check_distance > 0 && self.sparse_saving))
+        {
+            return;
+        }
+        self.determinism_check_distance = check_distance;
+        self.checksum_history.clear();
+        self.sync_layer.set_retained_frames(check_distance);
+    }
+
+    /// Exchanges the checksums of confirmed gamestates with the remote players every `interval` frames, so desyncs between the players are detected.
+    /// The checksums of all frames that are a multiple of `interval` are compared, so all players have to use the same interval. Frames without a saved state,
+    /// e.g. with sparse saving, are skipped. Checksums are sent unreliably and lost checksums are not resent. An interval of 0 disables the exchange, which is the default.
+    /// If a remote player has a different checksum, the session sends a `GGRSEvent::DesyncDetected` and reacts as set with `set_desync_policy()`.
+    pub fn set_checksum_interval(&mut self, interval: u32) {
+        self.checksum_interval = interval;
+        self.state_checksums.clear();
+        // the first frame after the current one that all players compare
+        self.next_checksum_frame = match interval {
+            0 => NULL_FRAME,
+            interval => {
+                let interval = i64::from(interval);
+                Frame(
+                    (std::cmp::max(self.sync_layer.current_frame().0, 0) / interval + 1) * interval,
+                )
+            }
+        };
+    }
+
+    /// Sets what the session does when a remote player has a different checksum. See `DesyncPolicy` and `set_checksum_interval()`. Defaults to `DesyncPolicy::Notify`.
+    pub fn set_desync_policy(&mut self, policy: DesyncPolicy) {
+        self.desync_policy = policy;
+    }
+
+    /// Limits how many frames the session resimulates within a single `advance_frame()` call, so a long rollback does not cause a visible hitch on slow hardware.
+    /// If a correction needs more frames, the session resimulates as many as allowed and defers the rest: the following calls to `advance_frame()` resimulate up to
+    /// `frames` additional frames each until the gamestate has caught up with the inputs again. Meanwhile, `current_frame()` lags behind the frame of the local inputs
+    /// and the session sends a `GGRSEvent::CorrectionDeferred` every frame. A depth of 0 removes the limit, which is the default.
+    /// The value is validated when calling `start_session()`. After the session has been started, invalid values are ignored.
+    pub fn set_max_rollback_depth(&mut self, frames: u32) {
+        if self.state != SessionState::Initializing && frames > 0 && self.sparse_saving {
+            return;
+        }
+        self.max_rollback_depth = frames;
+    }
+
+    /// If enabled, rollbacks resimulate all frames with a single `GGRSRequest::Resimulate` instead of an `AdvanceFrame` and `SaveGameState` request per frame,
+    /// so engines that simulate several frames more efficiently in one go, e.g. with a job system, get all inputs of the rollback at once.
+    /// The newest frame of every `advance_frame()` call is still advanced with a regular `AdvanceFrame` request. Disabled by default.
+    pub fn set_batched_resimulation(&mut self, enabled: bool) {
+        self.batched_resimulation = enabled;
+    }
+
+    /// Sets the sparse saving mode. With sparse saving turned on, only the minimum confirmed frame (for which all inputs from all players are confirmed correct) will be saved.
+    /// This leads to much less save requests at the cost of potentially longer rollbacks and thus more advance frame requests. Recommended, if saving your gamestate
+    /// takes much more time than advancing the game state.
+    pub fn set_sparse_saving(&mut self, sparse_saving: bool) -> Result<(), GGRSError> {
+        // you can only switch the saving mode in the init phase
+        if self.state != SessionState::Initializing {
+            return Err(GGRSError::InvalidRequest {
+                info:
+                    "Session already started. You can only change the saving mode before starting the session."
+                        .to_owned(),
+            });
+        }
+
+        self.sparse_saving = sparse_saving;
+        Ok(())
+    }
+
+    /// Broadcasts the inputs to spectators in batches of `batch_size` frames. Each batch contains the inputs of all players and is encoded only once,
+    /// against the last input of the previous batch, and then sent to every spectator that needs it. Every `keyframe_interval`-th batch is a keyframe,
+    /// encoded against a blank input, so it can be decoded by spectators that are missing the previous batch.
+    /// Batching cuts the upload of the host for large spectator counts at the cost of up to `batch_size - 1` frames of additional delay for spectators.
+    /// The batch size is capped so every batch fits into a single packet. A batch size of 0 disables batching, which is the default.
+    pub fn set_spectator_batching(
+        &mut self,
+        batch_size: usize,
+        keyframe_interval: usize,
+    ) -> Result<(), GGRSError> {
+        // you can only switch to batches in the init phase
+        if self.state != SessionState::Initializing {
+            return Err(GGRSError::InvalidRequest {
+                info:
+                    "Session already started. You can only change spectator batching before starting the session."
+                        .to_owned(),
+            });
+        }
+
+        self.spectator_batch_size = std::cmp::min(batch_size, self.spectator_send_window);
+        self.spectator_keyframe_interval = std::cmp::max(1, keyframe_interval);
+        Ok(())
+    }
+
+    /// Delays the inputs sent to spectators by `frames` confirmed frames, e.g. to keep players from watching the broadcast of the match to learn about their opponents.
+    /// Spectators only receive the inputs of a frame once the match is `frames` frames past it, so they watch the match that many frames behind the players.
+    /// The default is 0, which sends inputs as soon as they are confirmed.
+    pub fn set_spectator_delay(&mut self, frames: u32) -> Result<(), GGRSError> {
+        // you can only change the delay in the init phase
+        if self.state != SessionState::Initializing {
+            return Err(GGRSError::InvalidRequest {
+                info:
+                    "Session already started. You can only change the spectator delay before starting the session."
+                        .to_owned(),
+            });
+        }
+
+        self.spectator_delay = frames;
+        Ok(())
+    }
+
+    /// Limits the number of inputs the session keeps for a remote player or spectator that has not acknowledged them yet, and sets what happens
+    /// when a peer exceeds the limit. The default is a limit of 128 inputs, after which the peer is disconnected.
+    /// A lower limit keeps memory and packet sizes smaller for unresponsive peers, but should stay well above the number of frames an acknowledgement takes.
+    /// Spectators are never sent more unacknowledged inputs than the limit, so the policy only applies to spectators that stop acknowledging entirely.
+    /// # Errors
+    /// - Returns `InvalidRequest` if the limit is 0 or higher than 128.
+    /// - Returns `InvalidRequest` if the session has already been started.
+    pub fn set_pending_output_limit(
+        &mut self,
+        limit: usize,
+        policy: PendingOutputPolicy,
+    ) -> Result<(), GGRSError> {
+        // you can only change the limit in the init phase
+        if self.state != SessionState::Initializing {
+            return Err(GGRSError::InvalidRequest {
+                info:
+                    "Session already started. You can only change the pending output limit before starting the session."
+                        .to_owned(),
+            });
+        }
+        if limit == 0 || limit > PENDING_OUTPUT_SIZE {
+            return Err(GGRSError::InvalidRequest {
+                info: format!(
+                    "The pending output limit has to be between 1 and {}.",
+                    PENDING_OUTPUT_SIZE
+                ),
+            });
+        }
+
+        self.pending_output_limit = limit;
+        self.pending_output_policy = policy;
+        for endpoint in self
+            .players
+            .values_mut()
+            .filter_map(Player::as_endpoint_mut)
+        {
+            endpoint.set_pending_output_limit(limit, policy);
+        }
+        // spectators must never have more inputs in flight than the limit allows
+        self.spectator_send_window = std::cmp::min(self.spectator_send_window, limit);
+        self.spectator_batch_size =
+            std::cmp::min(self.spectator_batch_size, self.spectator_send_window);
+        Ok(())
+    }
+
+    /// Enables the waiting room. With the waiting room enabled, the session does not start running once all peers are synchronized,
+    /// but enters `SessionState::WaitingRoom` instead. In the waiting room, the session keeps exchanging messages with all peers, so you can display their pings,
+    /// but frames can only be advanced after you call `start_match()`.
+    pub fn set_waiting_room_enabled(&mut self, enabled: bool) -> Result<(), GGRSError> {
+        // you can only enable the waiting room in the init phase
+        if self.state != SessionState::Initializing {
+            return Err(GGRSError::InvalidRequest {
+                info:
+                    "Session already started. You can only enable the waiting room before starting the session."
+                        .to_owned(),
+            });
+        }
+
+        self.waiting_room = enabled;
+        Ok(())
+    }
+
+    /// Allows adding spectators after the session has been started. Since late spectators catch up on all inputs since the start of the match,
+    /// the session then keeps these inputs even while there are no spectators, see `add_spectator()` for the memory this takes.
+    /// Without late spectators, a session without spectators keeps no inputs for them. The default is disabled.
+    pub fn set_late_spectators_enabled(&mut self, enabled: bool) -> Result<(), GGRSError> {
+        // you can only allow late spectators in the init phase, since they need the inputs from the first frame on
+        if self.state != SessionState::Initializing {
+            return Err(GGRSError::InvalidRequest {
+                info:
+                    "Session already started. You can only allow late spectators before starting the session."
+                        .to_owned(),
+            });
+        }
+
+        self.late_spectators = enabled;
+        Ok(())
+    }
+
+    /// Turns the session into a turn-based session, for asynchronous or turn-based games. In a turn-based session, a frame is a turn: it only advances
+    /// once all connected players have submitted their input for it, so remote inputs are never predicted and the session never rolls back or asks to save
+    /// or load the gamestate. Frames are not paced either, so there are no wait recommendations and a turn may take as long as the players need.
+    /// `advance_frame()` sends the local input of the turn on the first call and returns `GGRSError::WaitingForTurn` until the inputs of all other players
+    /// have arrived. Inputs given in these later calls are ignored. Turn-based sessions can not have a frame delay.
+    pub fn set_turn_based(&mut self, enabled: bool) -> Result<(), GGRSError> {
+        // you can only switch to turns in the init phase
+        if self.state != SessionState::Initializing {
+            return Err(GGRSError::InvalidRequest {
+                info:
+                    "Session already started. You can only change the turn-based mode before starting the session."
+                        .to_owned(),
+            });
+        }
+
+        self.turn_based = enabled;
+        Ok(())
+    }
+
+    /// Preallocates the buffers of the session when it starts, for platforms with a fixed memory budget. The input queues and the ring of saved gamestates
+    /// have a fixed length anyway. With bounded memory, every cell of that ring also gets a buffer of `state_capacity` bytes up front, which
+    /// `GameStateCell::save_bytes()` copies your gamestate into. The pending outputs of every peer are allocated for the pending output limit and the event queue
+    /// for its maximum size, see `set_pending_output_limit()` and `set_max_event_queue_size()`. Instead of growing these buffers, the session returns errors
+    /// or discards the oldest events. Spectators keep the inputs of the whole match, so they can not be added to a session with bounded memory.
+    /// The bound is partial: only the buffers above are preallocated. The requests and network messages of every frame are still allocated and freed again right away,
+    /// and the exchanged checksums, the recorded confirmed inputs and the input history allocate while the session runs if you enable them.
+    /// # Errors
+    /// - Returns `InvalidRequest` if the session has already been started.
+    /// - Returns `InvalidRequest` if `state_capacity` is 0.
+    pub fn set_bounded_memory(&mut self, state_capacity: usize) -> Result<(), GGRSError> {
+        // you can only bound the memory in the init phase
+        if self.state != SessionState::Initializing {
+            return Err(GGRSError::InvalidRequest {
+                info:
+                    "Session already started. You can only bound the memory before starting the session."
+                        .to_owned(),
+            });
+        }
+        if state_capacity == 0 {
+            return Err(GGRSError::InvalidRequest {
+                info: "The capacity of saved gamestates should be higher than 0.".to_owned(),
+            });
+        }
+
+        self.bounded_memory = Some(state_capacity);
+        Ok(())
+    }
+
+    /// Leaves the waiting room and starts the match, so frames can be advanced.
+    /// # Errors
+    /// - Returns `NotSynchronized` if the session has not synchronized with all peers yet.
+    /// - Returns `InvalidRequest` if the match has already been started.
+    pub fn start_match(&mut self) -> Result<(), GGRSError> {
+        match self.state {
+            SessionState::WaitingRoom => {
+                self.state = SessionState::Running;
+                Ok(())
+            }
+            SessionState::Running => Err(GGRSError::InvalidRequest {
+                info: "Match already started.".to_owned(),
+            }),
+            SessionState::Initializing | SessionState::Synchronizing => {
+                Err(GGRSError::NotSynchronized)
+            }
+        }
+    }
+
+    /// Sets a function that computes the checksums of saved gamestates from their buffers, which are used by the determinism checks of `set_determinism_check_distance()`.
+    /// With a checksum function, you do not need to provide checksums when saving your gamestate and checksums provided in `GameState` are ignored.
+    /// By default, the checksums of `GameState::new()` are used, which are computed with fletcher16 if you do not provide any.
+    pub fn set_checksum_function(&mut self, checksum_function: fn(&[u8]) -> u64) {
+        self.sync_layer.set_checksum_function(checksum_function);
+        self.checksum_history.clear();
+    }
+
+    /// Sets the `InputPredictor` used to predict the inputs of remote players. By default, GGRS predicts that players repeat their last input (`RepeatLastInput`).
+    /// # Errors
+    /// - Returns `InvalidRequest` if the session has already been started.
+    pub fn set_input_predictor(
+        &mut self,
+        predictor: impl InputPredictor + Send + Sync + 'static,
+    ) -> Result<(), GGRSError> {
+        // all peers need to predict the same way for the whole session, so you can only switch the predictor in the init phase
+        if self.state != SessionState::Initializing {
+            return Err(GGRSError::InvalidRequest {
+                info:
+                    "Session already started. You can only change the input predictor before starting the session."
+                        .to_owned(),
+            });
+        }
+
+        self.sync_layer.set_input_predictor(Arc::new(predictor));
+        Ok(())
+    }
+
+    /// Sets the `DisconnectedInput` that substitutes the inputs of disconnected players, e.g. with their last input (`RepeatLastInput`)
+    /// or a neutral stance (`FixedInput`). By default, disconnected players have blank inputs. Either way, their inputs have `NULL_FRAME` as their frame.
+    /// All peers have to use the same substitution. Spectators receive the substituted inputs from the host.
+    /// # Errors
+    /// - Returns `InvalidRequest` if the session has already been started.
+    pub fn set_disconnected_input(
+        &mut self,
+        disconnected_input: impl DisconnectedInput + Send + Sync + 'static,
+    ) -> Result<(), GGRSError> {
+        // all peers need to substitute the same inputs for the whole session, so you can only switch the substitution in the init phase
+        if self.state != SessionState::Initializing {
+            return Err(GGRSError::InvalidRequest {
+                info:
+                    "Session already started. You can only change the disconnected input before starting the session."
+                        .to_owned(),
+            });
+        }
+
+        self.sync_layer
+            .set_disconnected_input(Arc::new(disconnected_input));
+        Ok(())
+    }
+
+    /// Sets the `InputBot` that plays for disconnected players, so the match can go on with a CPU substitute. The bot takes precedence over
+    /// the `DisconnectedInput`. Inputs played by the bot have `NULL_FRAME` as their frame. All peers have to use the same bot.
+    /// # Errors
+    /// - Returns `InvalidRequest` if the session has already been started.
+    pub fn set_input_bot(
+        &mut self,
+        input_bot: impl InputBot + Send + Sync + 'static,
+    ) -> Result<(), GGRSError> {
+        // all peers need to run the same bot for the whole session, so you can only set the bot in the init phase
+        if self.state != SessionState::Initializing {
+            return Err(GGRSError::InvalidRequest {
+                info: "Session already started. You can only set the input bot before starting the session."
+                    .to_owned(),
+            });
+        }
+
+        self.sync_layer.set_input_bot(Arc::new(input_bot));
+        Ok(())
+    }
+
+    /// Requests a synchronized pause. All peers will stop advancing at the same frame, which is returned.
+    /// Once a session reaches that frame, `advance_frame()` returns `GGRSError::Paused` until any peer calls `request_resume()`.
+    /// All peers are notified with a `GGRSEvent::PauseScheduled`.
+    /// # Errors
+    /// - Returns `NotSynchronized` if the session is not running yet.
+    /// - Returns `InvalidRequest` if a pause has already been requested and not been resumed yet.
+    pub fn request_pause(&mut self) -> Result<Frame, GGRSError> {
+        if self.state != SessionState::Running {
+            return Err(GGRSError::NotSynchronized);
+        }
+        if !self.pause_status.resumed {
+            return Err(GGRSError::InvalidRequest {
+                info: "A pause has already been requested.".to_owned(),
+            });
         }
+
+        // pause frames have to increase, so the newest pause always wins
+        let pause_frame = std::cmp::max(
+            self.first_frame_no_peer_passed(),
+            self.pause_status.pause_frame + 1,
+        );
+
+        self.update_pause_status(PauseStatus {
+            pause_frame,
+            resumed: false,
+        });
+        Ok(pause_frame)
     }
 
-    /// Sets the FPS this session is used with. This influences estimations for frame synchronization between sessions.
-    pub fn set_fps(&mut self, fps: u32) -> Result<(), GGRSError> {
-        if fps == 0 {
+    /// Resumes from a synchronized pause. All peers are notified with a `GGRSEvent::Resumed` and continue advancing.
+    /// # Errors
+    /// - Returns `InvalidRequest` if no pause has been requested.
+    pub fn request_resume(&mut self) -> Result<(), GGRSError> {
+        if self.pause_status.resumed {
             return Err(GGRSError::InvalidRequest {
-                info: "FPS should be higher than 0.".to_owned(),
+                info: "No pause has been requested.".to_owned(),
             });
         }
 
-        self.fps = fps;
+        self.update_pause_status(PauseStatus {
+            pause_frame: self.pause_status.pause_frame,
+            resumed: true,
+        });
+        Ok(())
+    }
 
-        for endpoint in self
-            .players
-            .values_mut()
-            .filter_map(Player::as_endpoint_mut)
-        {
-            endpoint.set_fps(fps);
+    /// Requests a synchronized change of the frame rate, e.g. when the game switches between a 30 and a 60 FPS mode. All peers and spectators
+    /// switch to the new frame rate at the same frame, which is returned, so their time synchronization stays correct. All peers are notified with
+    /// a `GGRSEvent::FpsChangeScheduled`. Run your game at the new frame rate from the returned frame on.
+    /// # Errors
+    /// - Returns `NotSynchronized` if the session is not running yet.
+    /// - Returns `InvalidRequest` if `fps` is 0 or higher than 1000.
+    pub fn request_fps_change(&mut self, fps: u32) -> Result<Frame, GGRSError> {
+        if self.state != SessionState::Running {
+            return Err(GGRSError::NotSynchronized);
+        }
+        if fps == 0 || fps > MAX_FPS {
+            return Err(GGRSError::InvalidRequest {
+                info: format!("FPS must be between 1 and {}.", MAX_FPS),
+            });
+        }
+
+        // change frames have to increase, so the newest change always wins
+        let frame = std::cmp::max(self.first_frame_no_peer_passed(), self.fps_change.frame + 1);
+        self.update_fps_change(FpsChange {
+            frame,
+            fps,
+            timescale: self.fps_change.timescale,
+        });
+        Ok(frame)
+    }
+
+    /// Requests a synchronized change of the speed of the match to `percent` percent of its regular speed, e.g. 50 for slow motion on a knockout or 200 to fast-forward.
+    /// The timescale is applied like a change of the frame rate: all peers and spectators switch their time synchronization to the scaled frame rate at the same frame, which is returned,
+    /// so time synchronization does not misfire while the game runs slower or faster. All peers are notified with a `GGRSEvent::TimescaleChangeScheduled` and a `GGRSEvent::FpsChangeScheduled`
+    /// for the scaled frame rate. A timescale of 100 returns to the regular speed. Changes of the frame rate requested later keep the timescale.
+    /// # Errors
+    /// - Returns `NotSynchronized` if the session is not running yet.
+    /// - Returns `InvalidRequest` if `percent` is 0 or higher than 1000.
+    pub fn request_timescale_change(&mut self, percent: u16) -> Result<Frame, GGRSError> {
+        if self.state != SessionState::Running {
+            return Err(GGRSError::NotSynchronized);
+        }
+        if percent == 0 || percent > MAX_TIMESCALE {
+            return Err(GGRSError::InvalidRequest {
+                info: format!("Timescale must be between 1 and {} percent.", MAX_TIMESCALE),
+            });
+        }
+
+        // without an earlier change, the session still runs at the frame rate it was started with
+        let fps = match self.fps_change.fps {
+            0 => self.fps,
+            fps => fps,
+        };
+        let frame = std::cmp::max(self.first_frame_no_peer_passed(), self.fps_change.frame + 1);
+        self.update_fps_change(FpsChange {
+            frame,
+            fps,
+            timescale: percent,
+        });
+        Ok(frame)
+    }
+
+    /// Registers a barrier frame, e.g. the end of a round or the frame the next stage is loaded. The session does not advance past that frame until it has received
+    /// the inputs of all connected remote players up to the barrier, so the gamestate of the barrier frame is final and will not be rolled back. Until then, `advance_frame()`
+    /// returns `GGRSError::WaitingAtBarrier`. Barriers are not sent to the peers, all peers should register the same barrier frames.
+    /// While the session waits, a `GGRSEvent::BarrierProgress` is sent whenever another peer has been confirmed, followed by a `GGRSEvent::BarrierPassed` once all are.
+    /// # Errors
+    /// - Returns `InvalidRequest` if the session has already advanced past the frame.
+    pub fn add_barrier_frame(&mut self, frame: Frame) -> Result<(), GGRSError> {
+        if frame < self.sync_layer.current_frame() {
+            return Err(GGRSError::InvalidRequest {
+                info: "The session has already advanced past this frame.".to_owned(),
+            });
         }
 
+        self.barrier_frames.insert(frame);
         Ok(())
     }
 
-    /// Sets the sparse saving mode. With sparse saving turned on, only the minimum confirmed frame (for which all inputs from all players are confirmed correct) will be saved.
-    /// This leads to much less save requests at the cost of potentially longer rollbacks and thus more advance frame requests. Recommended, if saving your gamestate
-    /// takes much more time than advancing the game state.
-    pub fn set_sparse_saving(&mut self, sparse_saving: bool) -> Result<(), GGRSError> {
-        // you can only switch the saving mode in the init phase
+    /// Returns `true` if the session has reached the frame of a synchronized pause and waits for a peer to resume.
+    pub fn is_paused(&self) -> bool {
+        !self.pause_status.resumed
+            && self.sync_layer.current_frame() >= self.pause_status.pause_frame
+    }
+
+    /// Registers a validator that checks every input received from remote players, e.g. for impossible button combinations or out-of-range axes.
+    /// The validator is called with the handle of the player, the frame and the serialized input and should return `false` for invalid inputs.
+    /// Rejected inputs trigger a `GGRSEvent::InvalidInput`. See `set_disconnect_on_invalid_input()` for what happens to the input afterwards.
+    pub fn set_input_validator(
+        &mut self,
+        validator: impl Fn(PlayerHandle, Frame, &[u8]) -> bool + Send + 'static,
+    ) {
+        self.input_validator = Some(InputValidator(Box::new(validator)));
+    }
+
+    /// If enabled, a remote player sending an input that is rejected by the input validator is disconnected right before that input,
+    /// so the invalid input is never used. If disabled (the default), invalid inputs are only reported and still accepted,
+    /// since replacing them would make the session diverge from the other peers.
+    pub fn set_disconnect_on_invalid_input(&mut self, disconnect: bool) {
+        self.disconnect_on_invalid_input = disconnect;
+    }
+
+    /// Sets an `InputTolerance` for received inputs of remote players. Inputs within tolerance of their prediction will not trigger a rollback,
+    /// which avoids constant rollbacks for analog inputs. The exact inputs are still handed to you for all frames simulated afterwards.
+    /// By default, inputs have to match their predictions exactly. See `clear_input_tolerance()` to go back to that.
+    /// # Errors
+    /// - Returns `InvalidRequest` if the session has already been started.
+    pub fn set_input_tolerance(
+        &mut self,
+        tolerance: impl InputTolerance + Send + Sync + 'static,
+    ) -> Result<(), GGRSError> {
+        // you can only switch the tolerance in the init phase
         if self.state != SessionState::Initializing {
             return Err(GGRSError::InvalidRequest {
                 info:
-                    "Session already started. You can only change the saving mode before starting the session."
+                    "Session already started. You can only change the input tolerance before starting the session."
                         .to_owned(),
             });
         }
 
-        self.sparse_saving = sparse_saving;
+        self.sync_layer
+            .set_input_tolerance(Some(Arc::new(tolerance)));
+        Ok(())
+    }
+
+    /// Removes the `InputTolerance` set with `set_input_tolerance()`, so received inputs have to match their predictions exactly again.
+    /// # Errors
+    /// - Returns `InvalidRequest` if the session has already been started.
+    pub fn clear_input_tolerance(&mut self) -> Result<(), GGRSError> {
+        // you can only switch the tolerance in the init phase
+        if self.state != SessionState::Initializing {
+            return Err(GGRSError::InvalidRequest {
+                info:
+                    "Session already started. You can only change the input tolerance before starting the session."
+                        .to_owned(),
+            });
+        }
+
+        self.sync_layer.set_input_tolerance(None);
+        Ok(())
+    }
+
+    /// Enables or disables recording of confirmed inputs. If enabled, the session will keep every frame of inputs that has been confirmed
+    /// by all players until you drain them via `confirmed_inputs()`. This allows you to persist the authoritative input log of a session.
+    pub fn set_confirmed_inputs_enabled(&mut self, enabled: bool) {
+        self.record_confirmed_inputs = enabled;
+    }
+
+    /// Returns all inputs that have been confirmed since last queried, each frame exactly once and in order.
+    /// Disconnected players are indicated by having `NULL_FRAME` instead of the correct frame in their input.
+    /// Only contains inputs if recording has been enabled with `set_confirmed_inputs_enabled()`.
+    pub fn confirmed_inputs(&mut self) -> Drain<'_, (Frame, Vec<GameInput>)> {
+        self.confirmed_inputs.drain(..)
+    }
+
+    /// Sends a message over the reliable channel to a remote player or spectator, e.g. a chat message or a decision the peer must not miss.
+    /// Unlike inputs, reliable messages are not tied to frames: they are resent until the peer acknowledges them and the peer receives them exactly once,
+    /// in the order they have been sent, through `reliable_messages()`. They share the socket and the bandwidth with the rest of the session,
+    /// so they are meant for low-frequency messages. Messages that have not arrived when the session is dropped are lost.
+    /// # Errors
+    /// - Returns `InvalidHandle` if the handle does not belong to a remote player or spectator.
+    /// - Returns `NotSynchronized` if the session is not synchronized with the peer.
+    /// - Returns `InvalidRequest` if the message is larger than 467 bytes, the maximum payload of a single packet.
+    pub fn send_reliable_message(
+        &mut self,
+        player_handle: PlayerHandle,
+        msg: &[u8],
+    ) -> Result<(), GGRSError> {
+        let endpoint = self
+            .players
+            .get_mut(&player_handle)
+            .and_then(Player::as_endpoint_mut)
+            .ok_or(GGRSError::InvalidHandle)?;
+        if !endpoint.is_running() {
+            return Err(GGRSError::NotSynchronized);
+        }
+        if msg.len() > MAX_PAYLOAD {
+            return Err(GGRSError::InvalidRequest {
+                info: format!("Reliable messages can be at most {} bytes.", MAX_PAYLOAD),
+            });
+        }
+
+        endpoint.send_reliable(msg);
+        self.send_all_messages();
         Ok(())
     }
 
+    /// Returns a random seed that is the same on all peers, e.g. to seed the random number generator of your game.
+    /// During synchronization, every player contributes a random number, and the seed is derived from the contributions of all players.
+    /// Returns `None` until the session has received the contributions of all remote players, which usually happens while synchronizing.
+    /// Spectators do not contribute to the seed and do not learn it.
+    pub fn shared_seed(&self) -> Option<u64> {
+        self.shared_seed
+    }
+
+    /// Returns all messages received over the reliable channel that have not been drained yet, together with the handle of the sender.
+    /// The messages of every peer are in the order the peer sent them. See `send_reliable_message()`.
+    pub fn reliable_messages(&mut self) -> Drain<'_, (PlayerHandle, Vec<u8>)> {
+        self.reliable_messages.drain(..)
+    }
+
+    /// Sets the number of frames of confirmed inputs the session keeps in its input history, e.g. `10 * fps` to keep the last ten seconds.
+    /// Unlike `confirmed_inputs()`, the history is not drained, so you can replay the recent past whenever you like,
+    /// e.g. for a kill cam or an instant replay. By default, no history is kept.
+    pub fn set_input_history_length(&mut self, frames: usize) {
+        self.input_history_length = frames;
+        while self.input_history.len() > frames {
+            self.input_history.pop_front();
+        }
+    }
+
+    /// Returns the confirmed inputs of the input history, from oldest to newest frame. To replay them, load a gamestate you saved
+    /// at one of these frames and advance it with the inputs of that frame and all following ones.
+    /// Disconnected players are indicated by having `NULL_FRAME` instead of the correct frame in their input.
+    /// Only contains inputs if the history has been enabled with `set_input_history_length()`.
+    pub fn input_history(
+        &self,
+    ) -> impl DoubleEndedIterator<Item = &(Frame, Vec<GameInput>)> + ExactSizeIterator {
+        self.input_history.iter()
+    }
+
     /// Returns the handle of the local player, if the player is already added
     pub fn local_player_handle(&self) -> Option<PlayerHandle> {
         self.players
@@ -581,14 +2448,82 @@ impl P2PSession {
             .map(|(&k, _)| k)
     }
 
+    /// Returns the handles of all added players and spectators together with their `PlayerType`, ordered by handle.
+    /// Remote players and spectators are given with their current address, which differs from the added one if the peer migrated.
+    pub fn players(&self) -> impl Iterator<Item = (PlayerHandle, PlayerType)> {
+        let mut players: Vec<(PlayerHandle, PlayerType)> = self
+            .players
+            .iter()
+            .map(|(&handle, player)| (handle, player.player_type()))
+            .collect();
+        players.sort_by_key(|&(handle, _)| handle);
+        players.into_iter()
+    }
+
+    /// Returns the current address of the remote player or spectator with the given handle, or `None` for the local player and unknown handles.
+    pub fn address_for_handle(&self, player_handle: PlayerHandle) -> Option<SocketAddr> {
+        self.players
+            .get(&player_handle)
+            .and_then(Player::as_endpoint)
+            .map(UdpProtocol::peer_addr)
+    }
+
+    /// Returns the handle of the remote player or spectator with the given current address, or `None` if no peer of the session has that address.
+    pub fn handle_for_address(&self, addr: SocketAddr) -> Option<PlayerHandle> {
+        self.players
+            .iter()
+            .filter(|(_, player)| player.as_endpoint().map(UdpProtocol::peer_addr) == Some(addr))
+            .map(|(&handle, _)| handle)
+            .min()
+    }
+
     /// Returns the current `SessionState` of a session.
     pub const fn current_state(&self) -> SessionState {
         self.state
     }
 
-    /// Returns all events that happened since last queried for events. If the number of stored events exceeds `MAX_EVENT_QUEUE_SIZE`, the oldest events will be discarded.
-    pub fn events(&mut self) -> Drain<GGRSEvent> {
-        self.event_queue.drain(..)
+    /// Returns the frame of the current gamestate, which is the frame the next `AdvanceFrame` request advances from.
+    /// While a correction is deferred, this lags behind the frame of the local inputs. See `set_max_rollback_depth()`.
+    pub const fn current_frame(&self) -> Frame {
+        self.sync_layer.current_frame()
+    }
+
+    /// Returns the last frame that has been advanced with confirmed inputs of all players, or `NULL_FRAME` if there is none yet.
+    /// All frames up to and including this frame will never be rolled back, so you can run systems that must only see confirmed gamestates for them.
+    pub fn confirmed_frame(&self) -> Frame {
+        std::cmp::min(
+            self.min_confirmed_frame,
+            self.sync_layer.current_frame() - 1,
+        )
+    }
+
+    /// Returns all events that happened since last queried for events. If the number of stored events exceeds the maximum set with `set_max_event_queue_size()`, the oldest events will be discarded.
+    pub fn events(
+        &mut self,
+    ) -> impl DoubleEndedIterator<Item = GGRSEvent> + ExactSizeIterator + '_ {
+        self.event_queue.drain().map(|timed| timed.event)
+    }
+
+    /// Sets how many events the session stores until you query them. Once the limit is reached, the oldest events are discarded, which you can detect
+    /// with `discarded_events()`. With `None`, the session stores all events, no matter how long you do not query them. The default is 100 events.
+    pub fn set_max_event_queue_size(&mut self, max_size: Option<usize>) {
+        self.event_queue.set_max_size(max_size);
+    }
+
+    /// Sets an `EventHandler` that receives all events of the session as they occur, starting with the events that have not been queried yet.
+    /// Events are not stored for `events()` anymore once a handler is set.
+    pub fn set_event_handler(&mut self, handler: impl EventHandler + 'static) {
+        self.event_queue.set_handler(Box::new(handler));
+    }
+
+    /// Returns the number of events that have been discarded since the session has been created, because they were not queried before the event queue was full.
+    pub const fn discarded_events(&self) -> usize {
+        self.event_queue.discarded()
+    }
+
+    /// Like `events()`, but every event comes with the frame of the session and the moment it occurred.
+    pub fn timed_events(&mut self) -> Drain<'_, TimedEvent> {
+        self.event_queue.drain()
     }
 
     /// Returns the number of players this session was constructed with.
@@ -601,9 +2536,117 @@ impl P2PSession {
         self.input_size
     }
 
+    /// Checks the configuration of the session and returns a description of every problem found.
+    fn validate_config(&self) -> Vec<String> {
+        let mut errors = Vec::new();
+
+        if self.fps == 0 {
+            errors.push("FPS should be higher than 0.".to_owned());
+        }
+
+        if self.input_send_interval == 0 || self.input_send_interval > MAX_PREDICTION_FRAMES {
+            errors.push(format!(
+                "Input send interval should be between 1 and {}.",
+                MAX_PREDICTION_FRAMES
+            ));
+        }
+
+        if self.sparse_input_interval > MAX_PREDICTION_FRAMES {
+            errors.push(format!(
+                "Sparse input interval should be at most {}.",
+                MAX_PREDICTION_FRAMES
+            ));
+        }
+
+        if self.time_sync_window == 0 {
+            errors.push("Time sync window should be at least 1 frame.".to_owned());
+        }
+
+        if self.min_wait_recommendation > self.max_wait_recommendation {
+            errors.push(
+                "Minimum wait recommendation should not be higher than the maximum wait recommendation."
+                    .to_owned(),
+            );
+        }
+
+        if self.determinism_check_distance >= MAX_PREDICTION_FRAMES {
+            errors.push(format!(
+                "Determinism check distance should be lower than {}.",
+                MAX_PREDICTION_FRAMES
+            ));
+        }
+
+        if self.determinism_check_distance > 0 && self.sparse_saving {
+            errors.push("Determinism checks can not be combined with sparse saving.".to_owned());
+        }
+
+        if self.max_rollback_depth > 0 && self.sparse_saving {
+            errors.push(
+                "A maximum rollback depth can not be combined with sparse saving.".to_owned(),
+            );
+        }
+
+        if self.disconnect_notify_start > self.disconnect_timeout {
+            errors.push(
+                "Disconnect notify delay should not be longer than the disconnect timeout."
+                    .to_owned(),
+            );
+        }
+
+        if self.turn_based
+            && self
+                .sync_layer
+                .input_queue_info()
+                .iter()
+                .any(|info| info.frame_delay > 0)
+        {
+            errors.push("Turn-based sessions can not have a frame delay.".to_owned());
+        }
+
+        if self.bounded_memory.is_some() {
+            if self.event_queue.max_size().is_none() {
+                errors.push(
+                    "Sessions with bounded memory need a maximum event queue size.".to_owned(),
+                );
+            }
+            if self.num_spectators() > 0 {
+                errors.push("Sessions with bounded memory can not have spectators.".to_owned());
+            }
+            if self.late_spectators {
+                errors
+                    .push("Sessions with bounded memory can not allow late spectators.".to_owned());
+            }
+        }
+
+        for player_handle in (0..self.num_players as usize).map(PlayerHandle) {
+            if !self.players.contains_key(&player_handle) {
+                errors.push(format!("Player {} has not been added.", player_handle));
+            }
+        }
+
+        errors
+    }
+
+    /// Allocates the buffers of a session with bounded memory up front.
+    fn preallocate(&mut self) {
+        let state_capacity = match self.bounded_memory {
+            Some(state_capacity) => state_capacity,
+            None => return,
+        };
+        self.sync_layer.preallocate_states(state_capacity);
+        self.event_queue.preallocate();
+        for endpoint in self
+            .players
+            .values_mut()
+            .filter_map(Player::as_endpoint_mut)
+        {
+            endpoint.preallocate();
+        }
+    }
+
     fn add_local_player(&mut self, player_handle: PlayerHandle) -> Result<PlayerHandle, GGRSError> {
         // check if valid player
-        if player_handle >= self.num_players as PlayerHandle {
+        if player_handle >= self.num_players as usize {
             return Err(GGRSError::InvalidHandle);
         }
 
@@ -621,6 +2664,8 @@ impl P2PSession {
 
         // finally add the local player
         self.players.insert(player_handle, Player::Local);
+        self.sync_layer
+            .set_frame_delay(player_handle, self.default_frame_delay);
         Ok(player_handle)
     }
 
@@ -630,7 +2675,7 @@ impl P2PSession {
         addr: SocketAddr,
     ) -> Result<PlayerHandle, GGRSError> {
         // check if valid player
-        if player_handle >= self.num_players as PlayerHandle {
+        if player_handle >= self.num_players as usize {
             return Err(GGRSError::InvalidHandle);
         }
 
@@ -645,6 +2690,15 @@ impl P2PSession {
         let mut endpoint = UdpProtocol::new(player_handle, addr, self.num_players, self.input_size);
         endpoint.set_disconnect_notify_start(self.disconnect_notify_start);
         endpoint.set_disconnect_timeout(self.disconnect_timeout);
+        endpoint.set_rate_limit(self.max_messages_per_second, self.max_bytes_per_second);
+        endpoint.set_connection_token(self.connection_token);
+        endpoint.set_session_id(self.session_id);
+        endpoint.set_keep_alive_interval(self.keep_alive_interval);
+        endpoint.set_time_source(self.time_source.clone());
+        endpoint.set_sync_retries(self.sync_retry_interval, self.max_sync_retries);
+        endpoint.set_sync_timeout(self.sync_timeout);
+        endpoint.set_pending_output_limit(self.pending_output_limit, self.pending_output_policy);
+        endpoint.set_local_seed(self.local_seed);
 
         // if the input delay has been set previously, erase it (remote players handle input delay at their end)
         self.sync_layer.set_frame_delay(player_handle, 0);
@@ -655,12 +2709,27 @@ impl P2PSession {
         Ok(player_handle)
     }
 
-    fn add_spectator(
+    fn add_remote_spectator(
         &mut self,
         player_handle: PlayerHandle,
         addr: SocketAddr,
     ) -> Result<PlayerHandle, GGRSError> {
-        let spectator_handle = player_handle + 1000;
+        let spectator_handle = PlayerHandle(player_handle.0 + SPECTATOR_HANDLE_OFFSET);
+
+        // spectators keep the inputs of the whole match
+        if self.bounded_memory.is_some() {
+            return Err(GGRSError::InvalidRequest {
+                info: "Spectators can not be added to a session with bounded memory.".to_owned(),
+            });
+        }
+
+        // without late spectators, the session does not keep the inputs a spectator joining later would need
+        if self.state != SessionState::Initializing && !self.late_spectators {
+            return Err(GGRSError::InvalidRequest {
+                info: "Session already started. Enable late spectators to add spectators after starting the session."
+                    .to_owned(),
+            });
+        }
 
         // check if player handle already exists
         if self.players.contains_key(&spectator_handle) {
@@ -678,42 +2747,219 @@ impl P2PSession {
         );
         endpoint.set_disconnect_notify_start(self.disconnect_notify_start);
         endpoint.set_disconnect_timeout(self.disconnect_timeout);
+        endpoint.set_rate_limit(self.max_messages_per_second, self.max_bytes_per_second);
+        endpoint.set_connection_token(self.connection_token);
+        endpoint.set_session_id(self.session_id);
+        endpoint.set_keep_alive_interval(self.keep_alive_interval);
+        endpoint.set_time_source(self.time_source.clone());
+        endpoint.set_sync_retries(self.sync_retry_interval, self.max_sync_retries);
+        endpoint.set_sync_timeout(self.sync_timeout);
+        endpoint.set_pending_output_limit(self.pending_output_limit, self.pending_output_policy);
+
+        // a spectator joining a started session synchronizes right away
+        if self.state != SessionState::Initializing {
+            endpoint.set_fps(self.fps);
+            endpoint.synchronize();
+        }
 
-        // add the spectator
+        // add the spectator, it will receive all inputs from the first frame on
+        self.spectator_frames.insert(spectator_handle, 0);
         self.players
             .insert(spectator_handle, Player::Spectator(Box::new(endpoint)));
         Ok(spectator_handle)
     }
 
-    fn disconnect_player_at_frame(&mut self, player_handle: PlayerHandle, last_frame: Frame) {
-        // disconnect the remote player
-        match self
+    fn spectator_handle(&self, addr: SocketAddr) -> Option<PlayerHandle> {
+        self.players
+            .iter()
+            .find(|(_, player)| match player {
+                Player::Spectator(endpoint) => endpoint.is_handling_message(&addr),
+                Player::Local | Player::Remote(_) => false,
+            })
+            .map(|(handle, _)| *handle)
+    }
+
+    fn restore_endpoint(&self, snapshot: EndpointSnapshot) -> UdpProtocol {
+        let mut endpoint = UdpProtocol::restore(snapshot);
+        endpoint.set_fps(self.fps);
+        endpoint.set_disconnect_notify_start(self.disconnect_notify_start);
+        endpoint.set_disconnect_timeout(self.disconnect_timeout);
+        endpoint.set_rate_limit(self.max_messages_per_second, self.max_bytes_per_second);
+        endpoint.set_connection_token(self.connection_token);
+        endpoint.set_session_id(self.session_id);
+        endpoint.set_keep_alive_interval(self.keep_alive_interval);
+        endpoint.set_time_source(self.time_source.clone());
+        endpoint.set_sync_retries(self.sync_retry_interval, self.max_sync_retries);
+        endpoint.set_sync_timeout(self.sync_timeout);
+        endpoint.set_pending_output_limit(self.pending_output_limit, self.pending_output_policy);
+        endpoint
+    }
+
+    fn disconnect_player_at_frame(&mut self, player_handle: PlayerHandle, last_frame: Frame) {
+        // disconnect the remote player
+        match self
+            .players
+            .get_mut(&player_handle)
+            .expect("Invalid player handle")
+        {
+            Player::Remote(endpoint) => {
+                ggrs_info!(
+                    "Player {} disconnected at frame {}",
+                    player_handle,
+                    last_frame
+                );
+                endpoint.disconnect();
+                // mark the player as disconnected
+                self.local_connect_status[player_handle.0].disconnected = true;
+                self.local_connect_status[player_handle.0].last_frame = last_frame;
+                self.event_queue
+                    .push_back(GGRSEvent::DisconnectFrameDecided {
+                        player_handle,
+                        frame: last_frame,
+                    });
+
+                if self.sync_layer.current_frame() > last_frame + 1 {
+                    // remember to adjust simulation to account for the fact that the player disconnected a few frames ago,
+                    // resimulating with correct disconnect flags (to account for user having some AI kick in).
+                    if self.disconnect_frame == NULL_FRAME || self.disconnect_frame > last_frame + 1
+                    {
+                        self.disconnect_frame = last_frame + 1;
+                    }
+                }
+            }
+            Player::Spectator(endpoint) => {
+                endpoint.disconnect();
+            }
+            Player::Local => (),
+        }
+
+        // check if all remotes are synchronized now
+        self.check_initial_sync();
+    }
+
+    /// Adopts the given pause status if it is newer than the current one, notifies the user and forwards it to all remote players.
+    fn update_pause_status(&mut self, pause_status: PauseStatus) {
+        if pause_status <= self.pause_status {
+            return;
+        }
+
+        let frame = pause_status.pause_frame;
+        if pause_status.resumed {
+            ggrs_info!("Resuming from pause at frame {}", frame);
+            self.event_queue.push_back(GGRSEvent::Resumed { frame });
+        } else {
+            ggrs_info!("Pause scheduled at frame {}", frame);
+            self.event_queue
+                .push_back(GGRSEvent::PauseScheduled { frame });
+        }
+        self.pause_status = pause_status;
+
+        for endpoint in self
+            .players
+            .values_mut()
+            .filter_map(Player::remote_as_endpoint_mut)
+        {
+            endpoint.set_pause_status(pause_status);
+        }
+    }
+
+    /// Adopts the given change of the frame rate if it is newer than the current one, notifies the user and forwards it to all remote players and spectators.
+    fn update_fps_change(&mut self, fps_change: FpsChange) {
+        if fps_change <= self.fps_change {
+            return;
+        }
+
+        ggrs_info!(
+            "Change to {} FPS scheduled at frame {}",
+            fps_change.fps,
+            fps_change.frame
+        );
+        self.event_queue.push_back(GGRSEvent::FpsChangeScheduled {
+            frame: fps_change.frame,
+            fps: fps_change.scaled_fps(),
+        });
+        if fps_change.timescale != self.fps_change.timescale {
+            self.event_queue
+                .push_back(GGRSEvent::TimescaleChangeScheduled {
+                    frame: fps_change.frame,
+                    percent: fps_change.timescale,
+                });
+        }
+        self.fps_change = fps_change;
+        self.fps_change_applied = false;
+
+        for endpoint in self
             .players
-            .get_mut(&player_handle)
-            .expect("Invalid player handle")
+            .values_mut()
+            .filter_map(Player::as_endpoint_mut)
         {
-            Player::Remote(endpoint) => {
-                endpoint.disconnect();
-                // mark the player as disconnected
-                self.local_connect_status[player_handle].disconnected = true;
+            endpoint.set_fps_change(fps_change);
+        }
+    }
 
-                if self.sync_layer.current_frame() > last_frame {
-                    // remember to adjust simulation to account for the fact that the player disconnected a few frames ago,
-                    // resimulating with correct disconnect flags (to account for user having some AI kick in).
-                    self.disconnect_frame = last_frame + 1;
+    /// Returns `true` if the session has reached a barrier frame and still waits for the inputs of a connected remote player. Reports the progress and passes the barrier otherwise.
+    fn wait_at_barrier(&mut self) -> bool {
+        let barrier = match self.barrier_frames.iter().next() {
+            Some(&barrier) if self.sync_layer.current_frame() >= barrier => barrier,
+            _ => return false,
+        };
+
+        let mut total = 0;
+        let mut confirmed = 0;
+        for (handle, player) in &self.players {
+            let status = self.local_connect_status[handle.0];
+            if matches!(player, Player::Remote(_)) && !status.disconnected {
+                total += 1;
+                if status.last_frame >= barrier - 1 {
+                    confirmed += 1;
                 }
             }
-            Player::Spectator(endpoint) => {
-                endpoint.disconnect();
-            }
-            Player::Local => (),
         }
 
-        // check if all remotes are synchronized now
-        self.check_initial_sync();
+        if confirmed == total {
+            self.barrier_frames.remove(&barrier);
+            self.barrier_progress = None;
+            self.event_queue
+                .push_back(GGRSEvent::BarrierPassed { frame: barrier });
+            return false;
+        }
+        if self.barrier_progress != Some(confirmed) {
+            self.barrier_progress = Some(confirmed);
+            self.event_queue.push_back(GGRSEvent::BarrierProgress {
+                frame: barrier,
+                confirmed,
+                total,
+            });
+        }
+        true
+    }
+
+    /// Switches to the frame rate of the scheduled change once the session reaches its frame.
+    fn apply_fps_change(&mut self) {
+        if !self.fps_change_applied
+            && self.fps_change.fps != 0
+            && self.sync_layer.current_frame() >= self.fps_change.frame
+        {
+            self.fps_change_applied = true;
+            self.update_fps(self.fps_change.scaled_fps());
+        }
+    }
+
+    /// Returns the first frame that no peer can have advanced past yet. No peer can be more than the prediction window ahead of the last input we sent.
+    fn first_frame_no_peer_passed(&self) -> Frame {
+        let last_local_frame = self
+            .players
+            .iter()
+            .filter(|(_, player)| matches!(player, Player::Local))
+            .map(|(handle, _)| self.local_connect_status[handle.0].last_frame)
+            .max()
+            .unwrap_or(NULL_FRAME);
+        std::cmp::max(last_local_frame, self.sync_layer.current_frame())
+            + MAX_PREDICTION_FRAMES as i32
+            + 1
     }
 
-    /// Change the session state to `SessionState::Running` if all UDP endpoints are synchronized.
+    /// Change the session state to `SessionState::Running` (or `SessionState::WaitingRoom`) if all UDP endpoints are synchronized.
     fn check_initial_sync(&mut self) {
         // if we are not synchronizing, we don't need to do anything
         if self.state != SessionState::Synchronizing {
@@ -731,111 +2977,598 @@ impl P2PSession {
             }
         }
 
-        // everyone is synchronized, so we can change state and accept input
-        self.state = SessionState::Running;
+        // everyone is synchronized, so we can change state and accept input (or wait for the user to start the match)
+        self.state = if self.waiting_room {
+            SessionState::WaitingRoom
+        } else {
+            SessionState::Running
+        };
+    }
+
+    /// Digests the contributions of all players in the order of their handles, so all peers derive the same seed.
+    fn derive_shared_seed(&self) -> Option<u64> {
+        let mut contributions = Vec::new();
+        for handle in (0..self.num_players as usize).map(PlayerHandle) {
+            let seed = match self.players.get(&handle)? {
+                Player::Local => self.local_seed,
+                Player::Remote(endpoint) => endpoint.remote_seed()?,
+                Player::Spectator(_) => return None,
+            };
+            contributions.extend_from_slice(&seed.to_le_bytes());
+        }
+        Some(siphash(0, 0, &contributions))
+    }
+
+    /// Compares the checksums of all saved confirmed frames with the checksums of earlier simulations of the same frames.
+    fn check_determinism(&mut self) -> Result<(), GGRSError> {
+        // a disconnect changes the inputs of frames that have been confirmed before
+        if self.disconnect_frame != NULL_FRAME {
+            self.checksum_history.clear();
+            return Ok(());
+        }
+
+        let current_frame = self.sync_layer.current_frame();
+        let oldest_frame = std::cmp::max(current_frame - MAX_PREDICTION_FRAMES as i32, Frame(0));
+        self.checksum_history
+            .retain(|frame, _| *frame >= oldest_frame);
+
+        // the state of the frame after the last confirmed frame only depends on confirmed inputs as well
+        let newest_frame = std::cmp::min(self.min_confirmed_frame + 1, current_frame);
+        for frame in (oldest_frame.0..=newest_frame.0).map(Frame) {
+            let checksum = match self.sync_layer.saved_checksum(frame) {
+                Some(checksum) => checksum,
+                None => continue,
+            };
+            let original = *self.checksum_history.entry(frame).or_insert(checksum);
+            if original != checksum {
+                ggrs_warn!(
+                    "Checksum mismatch in frame {}: {} after resimulation, {} before",
+                    frame,
+                    checksum,
+                    original
+                );
+                return Err(GGRSError::MismatchedChecksum { frame });
+            }
+        }
+        Ok(())
+    }
+
+    /// Sends the checksums of all frames to compare that have been confirmed and saved since the last call to the remote players and compares them.
+    fn exchange_checksums(&mut self) {
+        // a disconnect changes the inputs of frames that have been confirmed before, so their states are resimulated first
+        if self.disconnect_frame != NULL_FRAME {
+            return;
+        }
+
+        // the state of the current frame is only saved during this call
+        let last_frame = std::cmp::min(
+            self.min_confirmed_frame,
+            self.sync_layer.current_frame() - 1,
+        );
+        while self.next_checksum_frame <= last_frame {
+            let frame = self.next_checksum_frame;
+            self.next_checksum_frame = Frame(frame.0 + i64::from(self.checksum_interval));
+            let checksum = match self.sync_layer.saved_checksum(frame) {
+                Some(checksum) => checksum,
+                None => continue,
+            };
+            for endpoint in self
+                .players
+                .values_mut()
+                .filter_map(Player::remote_as_endpoint_mut)
+            {
+                endpoint.send_state_checksum(frame, checksum);
+            }
+            self.state_checksums.entry(frame).or_default().local = Some(checksum);
+            self.compare_checksums(frame);
+        }
+        self.prune_checksums();
+    }
+
+    /// Adds the checksum a remote player sent for a frame and compares it.
+    fn add_remote_checksum(&mut self, player_handle: PlayerHandle, frame: Frame, checksum: u64) {
+        // only frames that are compared locally as well are accepted, so bogus frames of a peer can not push out the checksums of other frames
+        let interval = i64::from(self.checksum_interval);
+        if interval == 0
+            || frame.0 % interval != 0
+            || frame.0 >= self.next_checksum_frame.0 + INPUT_QUEUE_LENGTH as i64
+        {
+            return;
+        }
+        // the checksums of this frame have already been compared
+        if frame < self.next_checksum_frame && !self.state_checksums.contains_key(&frame) {
+            return;
+        }
+        self.state_checksums
+            .entry(frame)
+            .or_default()
+            .remote
+            .insert(player_handle, checksum);
+        self.compare_checksums(frame);
+        self.prune_checksums();
+    }
+
+    /// Compares the checksums of a frame once the checksums of the local session and of all connected remote players are known.
+    fn compare_checksums(&mut self, frame: Frame) {
+        let complete = match self.state_checksums.get(&frame) {
+            Some(checksums) => {
+                checksums.local.is_some()
+                    && self.players.iter().all(|(handle, player)| match player {
+                        Player::Remote(_) => {
+                            self.local_connect_status[handle.0].disconnected
+                                || checksums.remote.contains_key(handle)
+                        }
+                        Player::Local | Player::Spectator(_) => true,
+                    })
+            }
+            None => false,
+        };
+        if complete {
+            if let Some(checksums) = self.state_checksums.remove(&frame) {
+                self.handle_checksums(frame, checksums, true);
+            }
+        }
+    }
+
+    /// Gives up on the oldest frames if the checksums of some players do not arrive, e.g. because they have been lost, and compares the checksums that are known.
+    fn prune_checksums(&mut self) {
+        while self.state_checksums.len() > CHECKSUM_HISTORY_LENGTH {
+            if let Some((frame, checksums)) = self.state_checksums.pop_first() {
+                self.handle_checksums(frame, checksums, false);
+            }
+        }
+    }
+
+    /// Notifies the user of all remote players with a checksum different from the local one and reacts as set by the desync policy.
+    /// Players are only disconnected if the checksums of all connected players are known.
+    fn handle_checksums(&mut self, frame: Frame, checksums: FrameChecksums, complete: bool) {
+        let local_checksum = match checksums.local {
+            Some(checksum) => checksum,
+            None => return,
+        };
+        let deviating: Vec<(PlayerHandle, u64)> = checksums
+            .remote
+            .iter()
+            .filter(|(_, checksum)| **checksum != local_checksum)
+            .map(|(handle, checksum)| (*handle, *checksum))
+            .collect();
+        if deviating.is_empty() {
+            return;
+        }
+
+        for &(player_handle, remote_checksum) in &deviating {
+            ggrs_warn!(
+                "Desync in frame {}: player {} has checksum {}, the local checksum is {}",
+                frame,
+                player_handle,
+                remote_checksum,
+                local_checksum
+            );
+            self.event_queue.push_back(GGRSEvent::DesyncDetected {
+                player_handle,
+                frame,
+                local_checksum,
+                remote_checksum,
+            });
+        }
+
+        match self.desync_policy {
+            DesyncPolicy::Notify => (),
+            DesyncPolicy::Pause => {
+                if self.pause_status.resumed && self.request_pause().is_ok() {
+                    ggrs_info!("Pausing after a desync in frame {}", frame);
+                }
+            }
+            DesyncPolicy::DisconnectDeviating => {
+                // the local session and all players that agree with it need more than half of the votes
+                let num_checksums = checksums.remote.len() + 1;
+                let local_votes = num_checksums - deviating.len();
+                if complete && local_votes * 2 > num_checksums {
+                    for (player_handle, _) in deviating {
+                        if self.disconnect_player(player_handle).is_ok() {
+                            ggrs_info!(
+                                "Disconnected player {} after a desync in frame {}",
+                                player_handle,
+                                frame
+                            );
+                        }
+                    }
+                }
+            }
+        }
     }
 
     /// Roll back to `min_confirmed` frame and resimulate the game with most up-to-date input data.
+    /// With a maximum rollback depth, only part of the frames may be resimulated. The rest is deferred to the next frames.
     fn adjust_gamestate(
         &mut self,
         first_incorrect: Frame,
         min_confirmed: Frame,
         requests: &mut Vec<GGRSRequest>,
     ) {
-        let current_frame = self.sync_layer.current_frame();
-        // determine the frame to load
-        let frame_to_load = if self.sparse_saving {
-            // if sparse saving is turned on, we will rollback to the last saved state
-            self.sync_layer.last_saved_frame()
-        } else {
-            // otherwise, we will rollback to first_incorrect
-            first_incorrect
-        };
+        // the frame we would be at without deferred corrections
+        let target_frame = self.sync_layer.current_frame() + self.deferred_frames;
+        ggrs_span!("rollback", from = target_frame.0, to = first_incorrect.0);
+
+        // while catching up, frames the gamestate has not reached yet do not need a rollback
+        if first_incorrect != NULL_FRAME && first_incorrect < self.sync_layer.current_frame() {
+            // determine the frame to load
+            let frame_to_load = if self.sparse_saving {
+                // if sparse saving is turned on, we will rollback to the last saved state
+                self.sync_layer.last_saved_frame()
+            } else {
+                // otherwise, we will rollback to first_incorrect
+                first_incorrect
+            };
+
+            // we should always load a frame that is before or exactly the first incorrect frame
+            assert!(frame_to_load <= first_incorrect);
 
-        // we should always load a frame that is before or exactly the first incorrect frame
-        assert!(frame_to_load <= first_incorrect);
-        let count = current_frame - frame_to_load;
+            // request to load that frame
+            requests.push(self.sync_layer.load_frame(frame_to_load));
 
-        // request to load that frame
-        requests.push(self.sync_layer.load_frame(frame_to_load));
+            // we are now at the desired frame
+            assert_eq!(self.sync_layer.current_frame(), frame_to_load);
+            self.sync_layer.reset_prediction();
+        }
 
-        // we are now at the desired frame
-        assert_eq!(self.sync_layer.current_frame(), frame_to_load);
-        self.sync_layer.reset_prediction();
+        let mut count = target_frame - self.sync_layer.current_frame();
+        if self.max_rollback_depth > 0 {
+            count = std::cmp::min(count, self.max_rollback_depth as i64);
+        }
 
-        // step forward to the previous current state, but with updated inputs
+        // step forward towards the previous current state, but with updated inputs
+        ggrs_span!("resimulate", frames = count);
+        let from_frame = self.sync_layer.current_frame();
+        let mut inputs_per_frame = Vec::new();
+        let mut cells = Vec::new();
         for _ in 0..count {
             let inputs = self
                 .sync_layer
                 .synchronized_inputs(&self.local_connect_status);
 
             // advance the frame
+            let frame = self.sync_layer.current_frame();
             self.sync_layer.advance_frame();
-            requests.push(GGRSRequest::AdvanceFrame { inputs });
 
             // decide wether to request a state save
-            if self.sparse_saving {
+            let save = if self.sparse_saving {
                 // with sparse saving, we only save exactly the min_confirmed frame
-                if self.sync_layer.current_frame() == min_confirmed {
-                    requests.push(self.sync_layer.save_current_state());
-                }
+                self.sync_layer.current_frame() == min_confirmed
             } else {
                 // without sparse saving, we save every state except the very first one
-                requests.push(self.sync_layer.save_current_state());
+                true
+            };
+            let cell = if save {
+                Some(self.sync_layer.save_current_state_cell())
+            } else {
+                None
+            };
+
+            // with batched resimulation, all frames are handed out in a single request
+            if self.batched_resimulation {
+                inputs_per_frame.push(inputs);
+                cells.push(cell);
+            } else {
+                requests.push(GGRSRequest::AdvanceFrame { frame, inputs });
+                if let Some(cell) = cell {
+                    requests.push(GGRSRequest::SaveGameState {
+                        cell,
+                        frame: self.sync_layer.current_frame(),
+                    });
+                }
+            }
+        }
+        if !inputs_per_frame.is_empty() {
+            requests.push(GGRSRequest::Resimulate {
+                from_frame,
+                inputs_per_frame,
+                cells,
+            });
+        }
+
+        // after all this, we should have arrived at the same frame where we started, unless part of the correction has been deferred
+        self.deferred_frames = (target_frame - self.sync_layer.current_frame()) as i32;
+        if self.deferred_frames > 0 {
+            ggrs_debug!(
+                "Deferring the resimulation of {} frames",
+                self.deferred_frames
+            );
+            self.event_queue.push_back(GGRSEvent::CorrectionDeferred {
+                remaining_frames: self.deferred_frames as u32,
+            });
+        }
+    }
+
+    /// Advances a turn-based session. The local input of the turn is only added and sent once, then the turn waits for the inputs of all other players.
+    fn advance_turn(
+        &mut self,
+        local_player_handle: PlayerHandle,
+        local_input: &[u8],
+    ) -> Result<Vec<GGRSRequest>, GGRSError> {
+        let frame = self.sync_layer.current_frame();
+        if !self
+            .sync_layer
+            .is_local_input_added(local_player_handle, frame)
+        {
+            let mut game_input = GameInput::new(frame, self.input_size);
+            game_input.copy_input(local_input);
+            self.sync_layer
+                .add_local_input(local_player_handle, game_input)?;
+            self.local_connect_status[local_player_handle.0].last_frame = frame;
+            for endpoint in self
+                .players
+                .values_mut()
+                .filter_map(Player::remote_as_endpoint_mut)
+            {
+                endpoint.send_input(game_input, &self.local_connect_status);
             }
+            self.send_all_messages();
         }
-        // after all this, we should have arrived at the same frame where we started
-        assert_eq!(self.sync_layer.current_frame(), current_frame);
+
+        // the turn ends once the inputs of all connected players have arrived, even if the peers do not know about all of them yet
+        if let Some(min_confirmed) = self.min_confirmed_frame() {
+            self.min_confirmed_frame = min_confirmed;
+        }
+        let min_confirmed = self.min_confirmed_frame;
+        if self
+            .local_connect_status
+            .iter()
+            .any(|status| !status.disconnected && status.last_frame < frame)
+        {
+            return Err(GGRSError::WaitingForTurn);
+        }
+
+        self.send_confirmed_inputs_to_spectators(min_confirmed);
+        self.record_confirmed_inputs(min_confirmed);
+        self.sync_layer
+            .set_last_confirmed_frame(min_confirmed, false);
+        // nothing has been predicted, so disconnects never need a rollback
+        self.disconnect_frame = NULL_FRAME;
+
+        let inputs = self
+            .sync_layer
+            .synchronized_inputs(&self.local_connect_status);
+        let requests = vec![GGRSRequest::AdvanceFrame { frame, inputs }];
+        self.sync_layer.advance_frame();
+        Ok(requests)
     }
 
-    /// For each spectator, send all confirmed input up until the minimum confirmed frame.
+    /// Keeps all confirmed input up until the minimum confirmed frame for the spectators and sends it to them.
     fn send_confirmed_inputs_to_spectators(&mut self, min_confirmed_frame: Frame) {
-        if self.num_spectators() == 0 {
+        // nobody can ever need the inputs
+        let has_spectators = self
+            .players
+            .values()
+            .any(|player| matches!(player, Player::Spectator(_)));
+        if !has_spectators && !self.late_spectators {
             return;
         }
 
-        while self.next_spectator_frame <= min_confirmed_frame {
+        while Frame(self.spectator_inputs.len() as i64) <= min_confirmed_frame {
+            let frame = Frame(self.spectator_inputs.len() as i64);
             let inputs = self
                 .sync_layer
-                .confirmed_inputs(self.next_spectator_frame, &self.local_connect_status);
+                .confirmed_inputs(frame, &self.local_connect_status);
             assert_eq!(inputs.len(), self.num_players as usize);
             // construct a pseudo input containing input of all players for the spectators
-            let mut spectator_input = GameInput::new(
-                self.next_spectator_frame,
-                self.input_size * self.num_players as usize,
-            );
+            let mut spectator_input =
+                GameInput::new(frame, self.input_size * self.num_players as usize);
             for (i, input) in inputs.iter().enumerate() {
-                assert!(input.frame == NULL_FRAME || input.frame == self.next_spectator_frame);
+                assert!(input.frame == NULL_FRAME || input.frame == frame);
                 assert!(input.frame == NULL_FRAME || input.size == self.input_size);
                 let start = i * input.size;
                 let end = (i + 1) * input.size;
                 spectator_input.buffer[start..end].copy_from_slice(input.input());
             }
+            self.spectator_inputs.push(spectator_input);
+        }
 
-            // send it off
-            for endpoint in self
-                .players
-                .values_mut()
-                .filter_map(Player::spectator_as_endpoint_mut)
+        self.send_inputs_to_spectators();
+    }
+
+    /// Returns the number of frames of `spectator_inputs` that may be sent to spectators, which excludes the frames held back by the spectator delay.
+    fn spectator_frames_available(&self) -> usize {
+        self.spectator_inputs
+            .len()
+            .saturating_sub(self.spectator_delay as usize)
+    }
+
+    /// Sends the queued messages of all endpoints, within the bandwidth budget if there is one.
+    fn send_all_messages(&mut self) {
+        let budget = match &mut self.bandwidth_budget {
+            Some(budget) => budget,
+            None => {
+                for endpoint in self
+                    .players
+                    .values_mut()
+                    .filter_map(Player::as_endpoint_mut)
+                {
+                    endpoint.send_all_messages(&mut *self.socket);
+                }
+                return;
+            }
+        };
+
+        let mut messages = Vec::new();
+        for player in self.players.values_mut() {
+            let to_spectator = matches!(player, Player::Spectator(_));
+            if let Some(endpoint) = player.as_endpoint_mut() {
+                let addr = endpoint.peer_addr();
+                for msg in endpoint.take_messages() {
+                    messages.push((MessagePriority::of(&msg, to_spectator), addr, msg));
+                }
+            }
+        }
+        for (addr, msg) in budget.schedule(self.time_source.now(), messages) {
+            self.socket.send_to(&msg, addr);
+        }
+    }
+
+    /// Sends the input history in large chunks to every running spectator that is far behind, e.g. because it joined late.
+    /// The spectator receives its regular inputs again once it has acknowledged the chunks and caught up.
+    fn send_history_to_spectators(&mut self) {
+        let available = self.spectator_frames_available();
+        let merged_size = self.input_size * self.num_players as usize;
+        let chunk_frames = std::cmp::max(1, HISTORY_CHUNK_BYTES / merged_size.max(1));
+        for (handle, player) in self.players.iter_mut() {
+            let endpoint = match player {
+                Player::Spectator(endpoint)
+                    if endpoint.is_running()
+                        && !endpoint.is_sending_history()
+                        && endpoint.pending_output_len() == 0 =>
+                {
+                    endpoint
+                }
+                _ => continue,
+            };
+            // the frames of acknowledged chunks have not been counted as sent yet
+            let next_frame = self.spectator_frames.entry(*handle).or_insert(0);
+            let acked = (endpoint.last_acked_frame() + 1).0 as usize;
+            *next_frame = std::cmp::max(*next_frame, acked);
+            let behind = available.saturating_sub(*next_frame);
+            if behind > SPECTATOR_HISTORY_THRESHOLD {
+                let end = *next_frame + std::cmp::min(chunk_frames, behind);
+                endpoint.send_input_history(&self.spectator_inputs[*next_frame..end]);
+            }
+        }
+    }
+
+    /// Sends every running spectator the inputs it still needs, starting from the inputs each of them has received so far.
+    fn send_inputs_to_spectators(&mut self) {
+        self.send_history_to_spectators();
+        if self.spectator_batch_size > 0 {
+            self.send_batches_to_spectators();
+            return;
+        }
+
+        let available = self.spectator_frames_available();
+        for (handle, player) in self.players.iter_mut() {
+            let endpoint = match player {
+                Player::Spectator(endpoint)
+                    if endpoint.is_running() && !endpoint.is_sending_history() =>
+                {
+                    endpoint
+                }
+                _ => continue,
+            };
+            // only keep a limited amount of unacknowledged inputs in flight, the rest is sent once the spectator catches up
+            let next_frame = self.spectator_frames.entry(*handle).or_insert(0);
+            while *next_frame < available
+                && endpoint.pending_output_len() < self.spectator_send_window
             {
-                if endpoint.is_running() {
-                    endpoint.send_input(spectator_input, &self.local_connect_status);
+                endpoint.send_input(
+                    self.spectator_inputs[*next_frame],
+                    &self.local_connect_status,
+                );
+                *next_frame += 1;
+            }
+        }
+    }
+
+    /// Encodes all complete batches of spectator inputs and sends every running spectator the batches it still needs.
+    fn send_batches_to_spectators(&mut self) {
+        let batch_size = self.spectator_batch_size;
+        let available = self.spectator_frames_available();
+        while (self.spectator_batches.len() + 1) * batch_size <= available {
+            let index = self.spectator_batches.len();
+            let start = index * batch_size;
+            let reference = match index % self.spectator_keyframe_interval {
+                0 => Reference::Keyframe {
+                    input_size: self.input_size * self.num_players as usize,
+                },
+                _ => Reference::Input(&self.spectator_inputs[start - 1]),
+            };
+            self.spectator_batches.push(SpectatorBatch {
+                start_frame: Frame(start as i64),
+                reference_frame: match reference {
+                    Reference::Keyframe { .. } => None,
+                    Reference::Input(input) => Some(input.frame),
+                },
+                bytes: encode(
+                    reference,
+                    self.spectator_inputs[start..start + batch_size].iter(),
+                ),
+            });
+        }
+
+        for (handle, player) in self.players.iter_mut() {
+            let endpoint = match player {
+                Player::Spectator(endpoint)
+                    if endpoint.is_running() && !endpoint.is_sending_history() =>
+                {
+                    endpoint
+                }
+                _ => continue,
+            };
+            let next_frame = self.spectator_frames.entry(*handle).or_insert(0);
+            while endpoint.pending_output_len() + batch_size <= self.spectator_send_window {
+                // a spectator that is not at the start of a batch gets single inputs until it is
+                let batch_start = *next_frame / batch_size * batch_size;
+                if batch_start != *next_frame && *next_frame < available {
+                    endpoint.send_input(
+                        self.spectator_inputs[*next_frame],
+                        &self.local_connect_status,
+                    );
+                    *next_frame += 1;
+                    continue;
+                }
+                let batch = match self.spectator_batches.get(*next_frame / batch_size) {
+                    Some(batch) => batch,
+                    None => break,
+                };
+                for input in &self.spectator_inputs[*next_frame..*next_frame + batch_size] {
+                    endpoint.queue_input(*input);
                 }
+                endpoint.send_encoded_inputs(
+                    batch.start_frame,
+                    batch.reference_frame,
+                    &batch.bytes,
+                    &self.local_connect_status,
+                );
+                *next_frame += batch_size;
             }
+        }
+    }
+
+    /// Adds all inputs up until the minimum confirmed frame to the confirmed inputs for the user and to the input history.
+    fn record_confirmed_inputs(&mut self, min_confirmed_frame: Frame) {
+        if !self.record_confirmed_inputs && self.input_history_length == 0 {
+            // skip the frames, so enabling the recording later does not try to access discarded inputs
+            self.next_confirmed_frame =
+                std::cmp::max(self.next_confirmed_frame, min_confirmed_frame + 1);
+            return;
+        }
 
-            // onto the next frame
-            self.next_spectator_frame += 1;
+        while self.next_confirmed_frame <= min_confirmed_frame {
+            let inputs = self
+                .sync_layer
+                .confirmed_inputs(self.next_confirmed_frame, &self.local_connect_status);
+            if self.input_history_length > 0 {
+                if self.input_history.len() == self.input_history_length {
+                    self.input_history.pop_front();
+                }
+                self.input_history
+                    .push_back((self.next_confirmed_frame, inputs.clone()));
+            }
+            if self.record_confirmed_inputs {
+                self.confirmed_inputs
+                    .push_back((self.next_confirmed_frame, inputs));
+            }
+            self.next_confirmed_frame += 1;
         }
     }
 
     /// For each player, find out if they are still connected and what their minimum confirmed frame is.
-    /// Disconnects players if the remote clients have disconnected them already.
-    fn min_confirmed_frame(&mut self) -> Frame {
-        let mut total_min_confirmed = i32::MAX;
+    /// Disconnects players if the remote clients have disconnected them already. Returns `None` if no player is connected anymore.
+    fn min_confirmed_frame(&mut self) -> Option<Frame> {
+        let mut total_min_confirmed: Option<Frame> = None;
 
-        for handle in 0..self.num_players as usize {
+        for handle in (0..self.num_players as usize).map(PlayerHandle) {
             let mut queue_connected = true;
-            let mut queue_min_confirmed = i32::MAX;
+            let mut queue_min_confirmed = Frame(i64::MAX);
+            // only we can disconnect our local players, so remote clients claiming otherwise are ignored
+            let is_local = matches!(self.players.get(&handle), Some(Player::Local));
 
             // check all remote players for that player
             for endpoint in self.players.values().filter_map(Player::remote_as_endpoint) {
@@ -843,7 +3576,7 @@ impl P2PSession {
                     continue;
                 }
                 let con_status = endpoint.peer_connect_status(handle);
-                let connected = !con_status.disconnected;
+                let connected = !con_status.disconnected || is_local;
                 let min_confirmed = con_status.last_frame;
 
                 queue_connected = queue_connected && connected;
@@ -851,29 +3584,55 @@ impl P2PSession {
             }
 
             // check the local status for that player
-            let local_connected = !self.local_connect_status[handle].disconnected;
-            let local_min_confirmed = self.local_connect_status[handle].last_frame;
+            let local_connected = !self.local_connect_status[handle.0].disconnected;
+            let local_min_confirmed = self.local_connect_status[handle.0].last_frame;
 
             if local_connected {
                 queue_min_confirmed = std::cmp::min(queue_min_confirmed, local_min_confirmed);
             }
 
             if queue_connected {
-                total_min_confirmed = std::cmp::min(queue_min_confirmed, total_min_confirmed);
+                total_min_confirmed = Some(match total_min_confirmed {
+                    Some(total) => std::cmp::min(queue_min_confirmed, total),
+                    None => queue_min_confirmed,
+                });
             } else {
                 // check to see if the remote disconnect is further back than we have disconnected that player.
                 // If so, we need to re-adjust. This can happen when we e.g. detect our own disconnect at frame n
                 // and later receive a disconnect notification for frame n-1.
-                if local_connected || local_min_confirmed > queue_min_confirmed {
-                    self.disconnect_player_at_frame(handle as PlayerHandle, queue_min_confirmed);
+                // Frames before the confirmed frame are final, and we only have the inputs of the player up to the last one we received.
+                let mut disconnect_frame =
+                    std::cmp::max(queue_min_confirmed, self.min_confirmed_frame);
+                if local_connected {
+                    disconnect_frame = std::cmp::min(disconnect_frame, local_min_confirmed);
+                }
+                if local_connected || local_min_confirmed > disconnect_frame {
+                    self.disconnect_player_at_frame(handle, disconnect_frame);
+                }
+                // another peer disconnected a remote player we were still connected to
+                if local_connected && matches!(self.players.get(&handle), Some(Player::Remote(_))) {
+                    self.event_queue.push_back(GGRSEvent::Disconnected {
+                        player_handle: handle,
+                    });
                 }
             }
         }
 
-        assert!(total_min_confirmed < i32::MAX);
         total_min_confirmed
     }
 
+    /// Returns how many frames the session is estimated to be ahead of the connected remote player it is furthest ahead of.
+    fn frames_ahead(&self) -> i32 {
+        self.players
+            .iter()
+            .filter(|(handle, _)| !self.local_connect_status[handle.0].disconnected)
+            .filter_map(|(_, player)| player.remote_as_endpoint())
+            .filter(|endpoint| endpoint.is_running())
+            .map(UdpProtocol::frames_ahead)
+            .max()
+            .unwrap_or(0)
+    }
+
     /// Gather delay recommendations from each remote client and return the maximum.
     fn max_delay_recommendation(&self, require_idle_input: bool) -> u32 {
         let mut interval = 0;
@@ -904,6 +3663,7 @@ impl P2PSession {
             }
             // forward to user
             Event::NetworkInterrupted { disconnect_timeout } => {
+                ggrs_warn!("Connection to player {} interrupted", player_handle);
                 self.event_queue.push_back(GGRSEvent::NetworkInterrupted {
                     player_handle,
                     disconnect_timeout,
@@ -911,20 +3671,67 @@ impl P2PSession {
             }
             // forward to user
             Event::NetworkResumed => {
+                ggrs_info!("Connection to player {} resumed", player_handle);
                 self.event_queue
                     .push_back(GGRSEvent::NetworkResumed { player_handle });
             }
+            // forward to user
+            Event::FpsMismatch { remote_fps } => {
+                self.event_queue.push_back(GGRSEvent::FpsMismatch {
+                    player_handle,
+                    local_fps: self.fps,
+                    remote_fps,
+                });
+            }
+            // forward to user
+            Event::InvalidMessage => {
+                self.event_queue
+                    .push_back(GGRSEvent::InvalidMessage { player_handle });
+            }
+            // forward to user
+            Event::RateLimitExceeded => {
+                self.event_queue
+                    .push_back(GGRSEvent::RateLimitExceeded { player_handle });
+            }
+            // forward to user
+            Event::PendingInputsDropped { count } => {
+                self.event_queue.push_back(GGRSEvent::PendingInputsDropped {
+                    player_handle,
+                    count,
+                });
+            }
+            // compare the checksum with our own, checksums only come from remote players
+            Event::StateChecksum { frame, checksum } => {
+                if player_handle < self.num_players as usize
+                    && !self.local_connect_status[player_handle.0].disconnected
+                {
+                    self.add_remote_checksum(player_handle, frame, checksum);
+                }
+            }
             // check if all remotes are synced, then forward to user
             Event::Synchronized => {
                 self.check_initial_sync();
                 self.event_queue
                     .push_back(GGRSEvent::Synchronized { player_handle });
             }
+            // disconnect the player, so the session does not wait for it forever, then forward to user
+            Event::SyncFailed => {
+                if player_handle < self.num_players as usize {
+                    self.disconnect_player_at_frame(player_handle, NULL_FRAME);
+                } else if let Some(Player::Spectator(endpoint)) =
+                    self.players.get_mut(&player_handle)
+                {
+                    endpoint.disconnect();
+                }
+                self.check_initial_sync();
+                self.event_queue
+                    .push_back(GGRSEvent::SyncFailed { player_handle });
+            }
             // disconnect the player, then forward to user
             Event::Disconnected => {
                 // for remote players
-                let last_frame = if player_handle < self.num_players as PlayerHandle {
-                    self.local_connect_status[player_handle].last_frame
+                let last_frame = if player_handle < self.num_players as usize {
+                    self.local_connect_status[player_handle.0].last_frame
                 } else {
                     NULL_FRAME
                 };
@@ -935,34 +3742,113 @@ impl P2PSession {
             }
             // add the input and all associated information
             Event::Input(input) => {
-                // input only comes from remote players, not spectators
-                assert!(player_handle < self.num_players as PlayerHandle);
-                if !self.local_connect_status[player_handle].disconnected {
+                // input only comes from remote players, spectators do not send any inputs
+                if player_handle >= self.num_players as usize {
+                    return;
+                }
+                if !self.local_connect_status[player_handle.0].disconnected {
                     // check if the input comes in the correct sequence
-                    let current_remote_frame = self.local_connect_status[player_handle].last_frame;
-                    assert!(
-                        current_remote_frame == NULL_FRAME
-                            || current_remote_frame + 1 == input.frame
-                    );
+                    let current_remote_frame =
+                        self.local_connect_status[player_handle.0].last_frame;
+                    if current_remote_frame != NULL_FRAME && current_remote_frame + 1 != input.frame
+                    {
+                        ggrs_warn!(
+                            "Player {} sent input for frame {}, expected frame {}",
+                            player_handle,
+                            input.frame,
+                            current_remote_frame + 1
+                        );
+                        self.event_queue
+                            .push_back(GGRSEvent::InvalidMessage { player_handle });
+                        return;
+                    }
+                    // a peer that runs further ahead than the input queue can hold does not wait for us anymore, so we stop waiting for it as well
+                    if !self.sync_layer.has_room_for_input(player_handle) {
+                        ggrs_warn!(
+                            "Player {} is too far ahead, its input for frame {} does not fit into the input queue",
+                            player_handle,
+                            input.frame
+                        );
+                        self.disconnect_player_at_frame(player_handle, current_remote_frame);
+                        self.event_queue
+                            .push_back(GGRSEvent::Disconnected { player_handle });
+                        return;
+                    }
+                    // check the input, if the user wants to
+                    if let Some(InputValidator(validator)) = &self.input_validator {
+                        if !validator(player_handle, input.frame, input.input()) {
+                            ggrs_warn!(
+                                "Player {} sent an invalid input for frame {}",
+                                player_handle,
+                                input.frame
+                            );
+                            self.event_queue.push_back(GGRSEvent::InvalidInput {
+                                player_handle,
+                                frame: input.frame,
+                            });
+                            if self.disconnect_on_invalid_input {
+                                self.disconnect_player_at_frame(
+                                    player_handle,
+                                    current_remote_frame,
+                                );
+                                self.event_queue
+                                    .push_back(GGRSEvent::Disconnected { player_handle });
+                                return;
+                            }
+                        }
+                    }
                     // update our info
-                    self.local_connect_status[player_handle].last_frame = input.frame;
+                    self.local_connect_status[player_handle.0].last_frame = input.frame;
                     // add the remote input
                     self.sync_layer.add_remote_input(player_handle, input);
                 }
             }
         }
+    }
+}
 
-        // check event queue size and discard oldest events if too big
-        while self.event_queue.len() > MAX_EVENT_QUEUE_SIZE {
-            self.event_queue.pop_front();
+impl Session for P2PSession {
+    fn advance_frame(
+        &mut self,
+        local_inputs: &[(PlayerHandle, &[u8])],
+    ) -> Result<Vec<GGRSRequest>, GGRSError> {
+        match local_inputs {
+            [(handle, input)] => self.advance_frame(*handle, input),
+            _ => Err(GGRSError::InvalidRequest {
+                info: "A P2PSession takes the input of exactly one local player.".to_owned(),
+            }),
         }
     }
 
-    /// Return the number of spectators currently registered
-    fn num_spectators(&self) -> usize {
-        self.players
-            .values()
-            .filter_map(Player::spectator_as_endpoint)
-            .count()
+    fn poll_remote_clients(&mut self) {
+        self.poll_remote_clients();
+    }
+
+    fn events(&mut self) -> Vec<GGRSEvent> {
+        self.events().collect()
+    }
+
+    fn current_state(&self) -> SessionState {
+        self.current_state()
+    }
+
+    fn network_stats(&self, player_handle: PlayerHandle) -> Result<NetworkStats, GGRSError> {
+        self.network_stats(player_handle)
+    }
+
+    fn current_frame(&self) -> Frame {
+        self.current_frame()
+    }
+
+    fn confirmed_frame(&self) -> Frame {
+        self.confirmed_frame()
+    }
+
+    fn num_players(&self) -> u32 {
+        self.num_players()
+    }
+
+    fn input_size(&self) -> usize {
+        self.input_size()
     }
 }
@@ -1,17 +1,26 @@
 use std::{
     collections::{vec_deque::Drain, VecDeque},
-    net::{IpAddr, Ipv4Addr, SocketAddr},
+    net::SocketAddr,
+    sync::Arc,
+    time::Duration,
 };
 
 use crate::{
     frame_info::BLANK_INPUT,
     network::{
-        udp_msg::ConnectionStatus, udp_protocol::UdpProtocol, udp_socket::NonBlockingSocket,
+        connection_token::ConnectionToken,
+        udp_msg::{ConnectionStatus, FpsChange},
+        udp_protocol::{UdpProtocol, MAX_PAYLOAD},
+        udp_socket::{NonBlockingSocket, SocketStats},
     },
-    Frame, GGRSError, GGRSEvent, GGRSRequest, GameInput, NetworkStats, SessionState, NULL_FRAME,
+    time_source::{SharedTimeSource, TimeSource},
+    Frame, GGRSError, GGRSEvent, GGRSRequest, GameInput, NetworkStats, PlayerHandle, SessionState,
+    TimedEvent, NULL_FRAME,
 };
 
-use super::p2p_session::Event;
+use super::event_queue::{EventHandler, EventQueue};
+use super::p2p_session::{Event, DEFAULT_FPS};
+use super::session::{poll_until, Session};
 
 // The amount of inputs a spectator can buffer (a second worth of inputs)
 const SPECTATOR_BUFFER_SIZE: usize = 60;
@@ -21,8 +30,6 @@ const DEFAULT_MAX_FRAMES_BEHIND: u32 = 10;
 const NORMAL_SPEED: u32 = 1;
 // The amount of frames the spectator advances in a single step if too far behing
 const DEFAULT_CATCHUP_SPEED: u32 = 2;
-// The amount of events a spectator can buffer; should never be an issue if the user polls the events at every step
-const MAX_EVENT_QUEUE_SIZE: usize = 100;
 
 /// A `P2PSpectatorSession` provides a UDP protocol to connect to a remote host in a peer-to-peer fashion. The host will broadcast all confirmed inputs to this session.
 /// This session can be used to spectate a session without contributing to the game input.
@@ -32,47 +39,64 @@ pub struct P2PSpectatorSession {
     num_players: u32,
     input_size: usize,
     inputs: [GameInput; SPECTATOR_BUFFER_SIZE],
+    input_backlog: VecDeque<GameInput>,
     host_connect_status: Vec<ConnectionStatus>,
-    socket: NonBlockingSocket,
+    socket: Box<dyn NonBlockingSocket + Send>,
     host: UdpProtocol,
-    event_queue: VecDeque<GGRSEvent>,
+    event_queue: EventQueue,
     current_frame: Frame,
     last_recv_frame: Frame,
     max_frames_behind: u32,
     catchup_speed: u32,
+    jump_to_live_threshold: Option<u32>,
+    fps: u32,
+    fps_change: FpsChange,
+    fps_change_applied: bool,
+    reliable_messages: VecDeque<Vec<u8>>,
 }
 
 impl P2PSpectatorSession {
     pub(crate) fn new(
         num_players: u32,
         input_size: usize,
-        local_port: u16,
         host_addr: SocketAddr,
-    ) -> Result<Self, std::io::Error> {
-        // udp nonblocking socket creation
-        let addr = SocketAddr::new(IpAddr::V4(Ipv4Addr::new(0, 0, 0, 0)), local_port); //TODO: IpV6?
-        let socket = NonBlockingSocket::new(addr)?;
-
+        socket: Box<dyn NonBlockingSocket + Send>,
+    ) -> Self {
         // host connection status
         let mut host_connect_status = Vec::new();
         for _ in 0..num_players {
             host_connect_status.push(ConnectionStatus::default());
         }
 
-        Ok(Self {
+        // the host streams the input history to us in large chunks if we are far behind
+        let mut host = UdpProtocol::new(
+            PlayerHandle(0),
+            host_addr,
+            num_players,
+            input_size * num_players as usize,
+        );
+        host.set_accept_input_history(true);
+
+        Self {
             state: SessionState::Initializing,
             num_players,
             input_size,
             inputs: [BLANK_INPUT; SPECTATOR_BUFFER_SIZE],
+            input_backlog: VecDeque::new(),
             host_connect_status,
             socket,
-            host: UdpProtocol::new(0, host_addr, num_players, input_size * num_players as usize),
-            event_queue: VecDeque::new(),
+            host,
+            event_queue: EventQueue::new(),
             current_frame: NULL_FRAME,
             last_recv_frame: NULL_FRAME,
             max_frames_behind: DEFAULT_MAX_FRAMES_BEHIND,
             catchup_speed: DEFAULT_CATCHUP_SPEED,
-        })
+            jump_to_live_threshold: None,
+            fps: DEFAULT_FPS,
+            fps_change: FpsChange::default(),
+            fps_change_applied: false,
+            reliable_messages: VecDeque::new(),
+        }
     }
 
     /// Returns the current `SessionState` of a session.
@@ -80,49 +104,129 @@ impl P2PSpectatorSession {
         self.state
     }
 
-    /// Returns the number of frames behind the host
-    pub fn frames_behind_host(&self) -> u32 {
-        let diff = self.last_recv_frame - self.current_frame;
-        assert!(diff >= 0);
-        diff as u32
+    /// Returns the current address of the host.
+    pub const fn host_address(&self) -> SocketAddr {
+        self.host.peer_addr()
     }
 
-    /// Sets the amount of frames the spectator advances in a single `advance_frame()` call if it is too far behind the host.
-    /// If set to 1, the spectator will never catch up.
-    pub fn set_catchup_speed(&mut self, desired_catchup_speed: u32) -> Result<(), GGRSError> {
-        if desired_catchup_speed < 1 {
-            return Err(GGRSError::InvalidRequest {
-                info: "Catchup speed cannot be smaller than 1.".to_owned(),
-            });
-        }
-
-        if desired_catchup_speed >= self.max_frames_behind {
+    /// Runs the sync handshake with the host again, at the same or a new address, e.g. after a `GGRSEvent::Disconnected` event because the host
+    /// was unreachable for a while. The session keeps all inputs it received and waits in `SessionState::Synchronizing` until the host answers,
+    /// then continues with the inputs after the last received frame.
+    /// # Errors
+    /// - Returns `InvalidRequest` if the session has not been started yet.
+    pub fn reconnect(&mut self, host_addr: SocketAddr) -> Result<(), GGRSError> {
+        if self.state == SessionState::Initializing {
             return Err(GGRSError::InvalidRequest {
-                info: "Catchup speed cannot be larger or equal than the allowed maximum frames behind host"
+                info: "Session not started yet. You can only reconnect after starting the session."
                     .to_owned(),
             });
         }
 
-        self.catchup_speed = desired_catchup_speed;
+        ggrs_info!("Reconnecting to host at {}", host_addr);
+        self.state = SessionState::Synchronizing;
+        self.host.resynchronize(host_addr);
         Ok(())
     }
 
-    /// Sets the amount of frames behind the host before starting to catch up
+    /// Returns the frame of the current gamestate, which is the frame the next `AdvanceFrame` request advances from.
+    pub fn current_frame(&self) -> Frame {
+        self.current_frame + 1
+    }
+
+    /// Returns the last frame that has been advanced, or `NULL_FRAME` if there is none yet. Spectators only ever advance with confirmed inputs,
+    /// so this is always the frame before `current_frame()`.
+    pub const fn confirmed_frame(&self) -> Frame {
+        self.current_frame
+    }
+
+    /// Returns the number of frames behind the host
+    pub fn frames_behind_host(&self) -> u32 {
+        // before the first input arrives, the spectator is one frame ahead of the last received frame
+        std::cmp::max(self.last_recv_frame - self.current_frame, 0) as u32
+    }
+
+    /// Sets the amount of frames the spectator advances in a single `advance_frame()` call if it is too far behind the host.
+    /// If set to 1, the spectator will never catch up. Before the session has been started, the value is validated when calling `start_session()`.
+    /// # Errors
+    /// - Returns `InvalidRequest` if the session has already been started and the value would make the configuration invalid.
+    pub fn set_catchup_speed(&mut self, desired_catchup_speed: u32) -> Result<(), GGRSError> {
+        let previous = self.catchup_speed;
+        self.catchup_speed = desired_catchup_speed;
+        let result = self.validate_running_config();
+        if result.is_err() {
+            self.catchup_speed = previous;
+        }
+        result
+    }
+
+    /// Sets the amount of frames behind the host before starting to catch up. Before the session has been started, the value is validated when calling `start_session()`.
+    /// # Errors
+    /// - Returns `InvalidRequest` if the session has already been started and the value would make the configuration invalid.
     pub fn set_max_frames_behind(&mut self, desired_value: u32) -> Result<(), GGRSError> {
-        if desired_value < 1 {
-            return Err(GGRSError::InvalidRequest {
-                info: "Max frames behind cannot be smaller than 2.".to_owned(),
-            });
+        let previous = self.max_frames_behind;
+        self.max_frames_behind = desired_value;
+        let result = self.validate_running_config();
+        if result.is_err() {
+            self.max_frames_behind = previous;
+        }
+        result
+    }
+
+    /// Sets the amount of frames behind the host after which the spectator stops catching up gradually and instead fast-forwards
+    /// to the newest received frame within a single `advance_frame()` call, emitting a `GGRSEvent::SkippedFrames`. `None` disables jumping to live,
+    /// which is the default. The value is validated when calling `start_session()`.
+    /// After the session has been started, values that would make the configuration invalid are ignored.
+    pub fn set_jump_to_live_threshold(&mut self, threshold: Option<u32>) {
+        let previous = self.jump_to_live_threshold;
+        self.jump_to_live_threshold = threshold;
+        if self.state != SessionState::Initializing && !self.validate_config().is_empty() {
+            self.jump_to_live_threshold = previous;
         }
+    }
+
+    /// Sets the secret shared by all peers of the match. It has to be the same token the host has set with `P2PSession::set_connection_token()`,
+    /// otherwise the spectator can not synchronize with the host. The token has to be set before starting the session.
+    pub fn set_connection_token(&mut self, token: &[u8]) {
+        self.host
+            .set_connection_token(Some(ConnectionToken::new(token)));
+    }
 
-        if desired_value >= SPECTATOR_BUFFER_SIZE as u32 {
+    /// Sets the identifier of the match. It has to be the same session id the host has set with `P2PSession::set_session_id()`,
+    /// otherwise the spectator drops all messages of the host.
+    pub fn set_session_id(&mut self, session_id: u32) {
+        self.host.set_session_id(session_id);
+    }
+
+    /// Sets how often the spectator resends sync requests to the host while the host has not answered, and how many requests it resends
+    /// before it gives up with a `GGRSEvent::SyncFailed`. With `None`, the spectator keeps trying until the sync timeout passes, if there is one.
+    /// The default is a sync request every 200 milliseconds without a limit.
+    pub fn set_sync_retries(&mut self, interval: Duration, max_retries: Option<u32>) {
+        self.host.set_sync_retries(interval, max_retries);
+    }
+
+    /// Sets how long the spectator tries to synchronize with the host before it gives up with a `GGRSEvent::SyncFailed`. By default, there is no timeout.
+    pub fn set_sync_timeout(&mut self, timeout: Option<Duration>) {
+        self.host.set_sync_timeout(timeout);
+    }
+
+    /// Sets the clock the spectator reads the current time from, e.g. to detect disconnects and stamp events. By default, the spectator uses a `SystemTimeSource`.
+    /// See `P2PSession::set_time_source()`.
+    /// # Errors
+    /// - Returns `InvalidRequest` if the session has already been started.
+    pub fn set_time_source(
+        &mut self,
+        time_source: impl TimeSource + Send + Sync + 'static,
+    ) -> Result<(), GGRSError> {
+        if self.state != SessionState::Initializing {
             return Err(GGRSError::InvalidRequest {
-                info: "Max frames behind cannot be larger or equal than the Spectator buffer size (60)"
+                info: "Session already started. You can only set the time source before starting the session."
                     .to_owned(),
             });
         }
 
-        self.max_frames_behind = desired_value;
+        let time_source: SharedTimeSource = Arc::new(time_source);
+        self.event_queue.set_time_source(time_source.clone());
+        self.host.set_time_source(time_source);
         Ok(())
     }
 
@@ -136,14 +240,64 @@ impl P2PSpectatorSession {
         }
     }
 
-    /// Returns all events that happened since last queried for events. If the number of stored events exceeds `MAX_EVENT_QUEUE_SIZE`, the oldest events will be discarded.
-    pub fn events(&mut self) -> Drain<GGRSEvent> {
-        self.event_queue.drain(..)
+    /// Sends a message over the reliable channel to the host. See `P2PSession::send_reliable_message()`.
+    /// # Errors
+    /// - Returns `NotSynchronized` if the session is not synchronized with the host.
+    /// - Returns `InvalidRequest` if the message is larger than 467 bytes, the maximum payload of a single packet.
+    pub fn send_reliable_message(&mut self, msg: &[u8]) -> Result<(), GGRSError> {
+        if !self.host.is_running() {
+            return Err(GGRSError::NotSynchronized);
+        }
+        if msg.len() > MAX_PAYLOAD {
+            return Err(GGRSError::InvalidRequest {
+                info: format!("Reliable messages can be at most {} bytes.", MAX_PAYLOAD),
+            });
+        }
+
+        self.host.send_reliable(msg);
+        self.host.send_all_messages(&mut *self.socket);
+        Ok(())
+    }
+
+    /// Returns all messages the host sent over the reliable channel that have not been drained yet, in the order the host sent them.
+    pub fn reliable_messages(&mut self) -> Drain<'_, Vec<u8>> {
+        self.reliable_messages.drain(..)
+    }
+
+    /// Returns all events that happened since last queried for events. If the number of stored events exceeds the maximum set with `set_max_event_queue_size()`, the oldest events will be discarded.
+    pub fn events(
+        &mut self,
+    ) -> impl DoubleEndedIterator<Item = GGRSEvent> + ExactSizeIterator + '_ {
+        self.event_queue.drain().map(|timed| timed.event)
+    }
+
+    /// Sets how many events the session stores until you query them. Once the limit is reached, the oldest events are discarded, which you can detect
+    /// with `discarded_events()`. With `None`, the session stores all events, no matter how long you do not query them. The default is 100 events.
+    pub fn set_max_event_queue_size(&mut self, max_size: Option<usize>) {
+        self.event_queue.set_max_size(max_size);
+    }
+
+    /// Sets an `EventHandler` that receives all events of the session as they occur, starting with the events that have not been queried yet.
+    /// Events are not stored for `events()` anymore once a handler is set.
+    pub fn set_event_handler(&mut self, handler: impl EventHandler + 'static) {
+        self.event_queue.set_handler(Box::new(handler));
+    }
+
+    /// Returns the number of events that have been discarded since the session has been created, because they were not queried before the event queue was full.
+    pub const fn discarded_events(&self) -> usize {
+        self.event_queue.discarded()
+    }
+
+    /// Like `events()`, but every event comes with the frame of the session and the moment it occurred.
+    pub fn timed_events(&mut self) -> Drain<'_, TimedEvent> {
+        self.event_queue.drain()
     }
 
     /// A spectator can directly start the session. Then, the synchronization process will begin.
+    /// Before starting, the whole configuration of the session is validated.
     /// # Errors
     /// - Returns `InvalidRequest` if the session has already been started.
+    /// - Returns `InvalidConfiguration` listing all problems found if the session has been configured with invalid values.
     pub fn start_session(&mut self) -> Result<(), GGRSError> {
         // if we are not in the initialization state, we already started the session at some point
         if self.state != SessionState::Initializing {
@@ -152,6 +306,13 @@ impl P2PSpectatorSession {
             });
         }
 
+        // check the whole configuration and report all problems at once
+        let errors = self.validate_config();
+        if !errors.is_empty() {
+            return Err(GGRSError::InvalidConfiguration { errors });
+        }
+        self.host.set_fps(self.fps);
+
         // start the synchronisation
         self.state = SessionState::Synchronizing;
         self.host.synchronize();
@@ -166,6 +327,7 @@ impl P2PSpectatorSession {
     /// - Returns `NotSynchronized` if the session is not yet ready to accept input.
     /// In this case, you either need to start the session or wait for synchronization between clients.
     pub fn advance_frame(&mut self) -> Result<Vec<GGRSRequest>, GGRSError> {
+        ggrs_span!("advance_frame", frame = self.current_frame.0);
         // receive info from host, trigger events and send messages
         self.poll_remote_clients();
 
@@ -173,33 +335,71 @@ impl P2PSpectatorSession {
             return Err(GGRSError::NotSynchronized);
         }
 
+        // switch the frame rate at the frame the host and its peers agreed on
+        if !self.fps_change_applied
+            && self.fps_change.fps != 0
+            && self.current_frame() >= self.fps_change.frame
+        {
+            self.fps_change_applied = true;
+            self.fps = self.fps_change.scaled_fps();
+            self.host.set_fps(self.fps);
+        }
+
         let mut requests = Vec::new();
 
-        let frames_to_advance = if self.frames_behind_host() > self.max_frames_behind {
-            self.catchup_speed
-        } else {
-            NORMAL_SPEED
+        let frames_behind = self.frames_behind_host();
+        let frames_to_advance = match self.jump_to_live_threshold {
+            // we are so far behind that we skip straight to the newest frame
+            Some(threshold) if frames_behind > threshold => {
+                ggrs_info!(
+                    "Spectator is {} frames behind, jumping to frame {}",
+                    frames_behind,
+                    self.last_recv_frame
+                );
+                self.event_queue.push_back(GGRSEvent::SkippedFrames {
+                    from: self.current_frame,
+                    to: self.last_recv_frame,
+                });
+                frames_behind
+            }
+            _ if frames_behind > self.max_frames_behind => self.catchup_speed,
+            _ => NORMAL_SPEED,
         };
 
+        ggrs_trace!(
+            "Advancing {} frames from frame {}",
+            frames_to_advance,
+            self.current_frame
+        );
+
         for _ in 0..frames_to_advance {
             // get inputs for the next frame
             let frame_to_grab = self.current_frame + 1;
             let synced_inputs = self.inputs_at_frame(frame_to_grab)?;
 
             requests.push(GGRSRequest::AdvanceFrame {
+                frame: frame_to_grab,
                 inputs: synced_inputs,
             });
 
             // advance the frame, but only if grabbing the inputs succeeded
             self.current_frame += 1;
+            self.move_backlog_into_buffer();
         }
 
         Ok(requests)
     }
 
+    /// Polls the host like `poll_remote_clients()` and then keeps polling about every millisecond until `max_duration` has passed.
+    /// Use this while your game does not advance frames, e.g. while waiting for inputs of the host. With a duration of zero, this polls exactly once.
+    pub fn idle(&mut self, max_duration: Duration) {
+        poll_until(max_duration, || self.poll_remote_clients());
+    }
+
     /// Receive UDP packages, distribute them to corresponding UDP endpoints, handle all occurring events and send all outgoing UDP packages.
     /// Should be called periodically by your application to give GGRS a chance to do internal work like packet transmissions.
     pub fn poll_remote_clients(&mut self) {
+        self.event_queue.set_frame(self.current_frame);
         // Get all udp packets and distribute them to associated endpoints.
         // The endpoints will handle their packets, which will trigger both events and UPD replies.
         for (from, msg) in &self.socket.receive_all_messages() {
@@ -218,9 +418,39 @@ impl P2PSpectatorSession {
         for event in events.drain(..) {
             self.handle_event(event);
         }
+        self.reliable_messages
+            .extend(self.host.take_reliable_messages());
+
+        // adopt changes of the frame rate scheduled by the host
+        let fps_change = self.host.peer_fps_change();
+        if fps_change > self.fps_change {
+            self.event_queue.push_back(GGRSEvent::FpsChangeScheduled {
+                frame: fps_change.frame,
+                fps: fps_change.scaled_fps(),
+            });
+            if fps_change.timescale != self.fps_change.timescale {
+                self.event_queue
+                    .push_back(GGRSEvent::TimescaleChangeScheduled {
+                        frame: fps_change.frame,
+                        percent: fps_change.timescale,
+                    });
+            }
+            self.fps_change = fps_change;
+            self.fps_change_applied = false;
+        }
 
         // send out all pending UDP messages
-        self.host.send_all_messages(&self.socket);
+        self.host.send_all_messages(&mut *self.socket);
+
+        // report errors of the socket itself, which are not tied to the host
+        for error in self.socket.take_errors() {
+            self.event_queue.push_back(GGRSEvent::SocketError { error });
+        }
+    }
+
+    /// Returns statistics about the traffic on the socket of this session, if the socket keeps track of them.
+    pub fn socket_stats(&self) -> Option<SocketStats> {
+        self.socket.stats()
     }
 
     /// Returns the number of players this session was constructed with.
@@ -234,20 +464,93 @@ impl P2PSpectatorSession {
     }
 
     /// Sets the FPS this session is used with. This influences ping estimates.
+    /// Before the session has been started, the value is validated when calling `start_session()`.
+    /// # Errors
+    /// - Returns `InvalidRequest` if the session has already been started and `fps` is 0.
     pub fn set_fps(&mut self, fps: u32) -> Result<(), GGRSError> {
-        if fps == 0 {
-            return Err(GGRSError::InvalidRequest {
-                info: "FPS should be higher than 0.".to_owned(),
-            });
+        if self.state != SessionState::Initializing {
+            if fps == 0 {
+                return Err(GGRSError::InvalidRequest {
+                    info: "FPS should be higher than 0.".to_owned(),
+                });
+            }
+            self.host.set_fps(fps);
+        }
+        self.fps = fps;
+        Ok(())
+    }
+
+    /// Checks the configuration of a session that has already been started, which setters can not make invalid anymore.
+    fn validate_running_config(&self) -> Result<(), GGRSError> {
+        if self.state == SessionState::Initializing {
+            return Ok(());
+        }
+        let errors = self.validate_config();
+        if errors.is_empty() {
+            Ok(())
+        } else {
+            Err(GGRSError::InvalidRequest {
+                info: errors.join(" "),
+            })
         }
+    }
 
-        self.host.set_fps(fps);
+    /// Checks the configuration of the session and returns a description of every problem found.
+    fn validate_config(&self) -> Vec<String> {
+        let mut errors = Vec::new();
 
-        Ok(())
+        if self.fps == 0 {
+            errors.push("FPS should be higher than 0.".to_owned());
+        }
+
+        if self.catchup_speed < 1 {
+            errors.push("Catchup speed cannot be smaller than 1.".to_owned());
+        }
+
+        if self.catchup_speed >= self.max_frames_behind {
+            errors.push(
+                "Catchup speed cannot be larger or equal than the allowed maximum frames behind host."
+                    .to_owned(),
+            );
+        }
+
+        if self.max_frames_behind >= SPECTATOR_BUFFER_SIZE as u32 {
+            errors.push(format!(
+                "Max frames behind cannot be larger or equal than the Spectator buffer size ({}).",
+                SPECTATOR_BUFFER_SIZE
+            ));
+        }
+
+        if let Some(threshold) = self.jump_to_live_threshold {
+            if threshold <= self.max_frames_behind {
+                errors.push(
+                    "Jump to live threshold cannot be smaller or equal than the allowed maximum frames behind host."
+                        .to_owned(),
+                );
+            }
+            if threshold >= SPECTATOR_BUFFER_SIZE as u32 {
+                errors.push(format!(
+                    "Jump to live threshold cannot be larger or equal than the Spectator buffer size ({}).",
+                    SPECTATOR_BUFFER_SIZE
+                ));
+            }
+        }
+
+        errors
+    }
+
+    fn move_backlog_into_buffer(&mut self) {
+        while let Some(input) = self.input_backlog.front() {
+            if input.frame > self.current_frame + SPECTATOR_BUFFER_SIZE as i32 {
+                break;
+            }
+            self.inputs[input.frame.0 as usize % SPECTATOR_BUFFER_SIZE] = *input;
+            self.input_backlog.pop_front();
+        }
     }
 
     fn inputs_at_frame(&self, frame_to_grab: Frame) -> Result<Vec<GameInput>, GGRSError> {
-        let merged_input = self.inputs[frame_to_grab as usize % SPECTATOR_BUFFER_SIZE];
+        let merged_input = self.inputs[frame_to_grab.0 as usize % SPECTATOR_BUFFER_SIZE];
 
         // We haven't received the input from the host yet. Wait.
         if merged_input.frame < frame_to_grab {
@@ -256,6 +559,10 @@ impl P2PSpectatorSession {
 
         // The host is more than `SPECTATOR_BUFFER_SIZE` frames ahead of the spectator. The input we need is gone forever.
         if merged_input.frame > frame_to_grab {
+            ggrs_warn!(
+                "Input for frame {} has already been overwritten",
+                frame_to_grab
+            );
             return Err(GGRSError::SpectatorTooFarBehind);
         }
 
@@ -283,7 +590,7 @@ impl P2PSpectatorSession {
     }
 
     fn handle_event(&mut self, event: Event) {
-        let player_handle = 0;
+        let player_handle = PlayerHandle(0);
         match event {
             // forward to user
             Event::Synchronizing { total, count } => {
@@ -305,12 +612,44 @@ impl P2PSpectatorSession {
                 self.event_queue
                     .push_back(GGRSEvent::NetworkResumed { player_handle });
             }
+            // forward to user
+            Event::FpsMismatch { remote_fps } => {
+                self.event_queue.push_back(GGRSEvent::FpsMismatch {
+                    player_handle,
+                    local_fps: self.fps,
+                    remote_fps,
+                });
+            }
+            // forward to user
+            Event::InvalidMessage => {
+                self.event_queue
+                    .push_back(GGRSEvent::InvalidMessage { player_handle });
+            }
+            // forward to user
+            Event::RateLimitExceeded => {
+                self.event_queue
+                    .push_back(GGRSEvent::RateLimitExceeded { player_handle });
+            }
+            // forward to user
+            Event::PendingInputsDropped { count } => {
+                self.event_queue.push_back(GGRSEvent::PendingInputsDropped {
+                    player_handle,
+                    count,
+                });
+            }
             // synced with the host, then forward to user
             Event::Synchronized => {
                 self.state = SessionState::Running;
                 self.event_queue
                     .push_back(GGRSEvent::Synchronized { player_handle });
             }
+            // forward to user
+            Event::SyncFailed => {
+                self.event_queue
+                    .push_back(GGRSEvent::SyncFailed { player_handle });
+            }
+            // checksums are only exchanged between players
+            Event::StateChecksum { .. } => (),
             // disconnect the player, then forward to user
             Event::Disconnected => {
                 self.event_queue
@@ -318,24 +657,93 @@ impl P2PSpectatorSession {
             }
             // add the input and all associated information
             Event::Input(input) => {
-                // save the input
-                self.inputs[input.frame as usize % SPECTATOR_BUFFER_SIZE] = input;
-                assert!(input.frame > self.last_recv_frame);
+                // the host has to send every frame exactly once and in order
+                if input.frame <= self.last_recv_frame {
+                    ggrs_warn!(
+                        "Host sent input for frame {} after frame {}",
+                        input.frame,
+                        self.last_recv_frame
+                    );
+                    self.event_queue
+                        .push_back(GGRSEvent::InvalidMessage { player_handle });
+                    return;
+                }
+                // save the input. Inputs that do not fit into the buffer yet are kept until the spectator has advanced far enough,
+                // so a spectator catching up on a long match does not lose any inputs
+                if self.input_backlog.is_empty()
+                    && input.frame <= self.current_frame + SPECTATOR_BUFFER_SIZE as i32
+                {
+                    self.inputs[input.frame.0 as usize % SPECTATOR_BUFFER_SIZE] = input;
+                } else {
+                    self.input_backlog.push_back(input);
+                }
                 self.last_recv_frame = input.frame;
 
                 // update the frame advantage
                 self.host.update_local_frame_advantage(input.frame);
 
-                // update the host connection status
+                // update the host connection status and tell the user about players the host disconnected
                 for i in 0..self.num_players as usize {
-                    self.host_connect_status[i] = self.host.peer_connect_status(i);
+                    let player_handle = PlayerHandle(i);
+                    let status = self.host.peer_connect_status(player_handle);
+                    let known = self.host_connect_status[i];
+                    if status.disconnected
+                        && (!known.disconnected || status.last_frame != known.last_frame)
+                    {
+                        self.event_queue.push_back(GGRSEvent::PlayerDisconnected {
+                            player_handle,
+                            frame: status.last_frame,
+                        });
+                    }
+                    self.host_connect_status[i] = status;
                 }
             }
         }
+    }
+}
 
-        // check event queue size and discard oldest events if too big
-        while self.event_queue.len() > MAX_EVENT_QUEUE_SIZE {
-            self.event_queue.pop_front();
+impl Session for P2PSpectatorSession {
+    fn advance_frame(
+        &mut self,
+        local_inputs: &[(PlayerHandle, &[u8])],
+    ) -> Result<Vec<GGRSRequest>, GGRSError> {
+        if !local_inputs.is_empty() {
+            return Err(GGRSError::InvalidRequest {
+                info: "A P2PSpectatorSession has no local players.".to_owned(),
+            });
         }
+        self.advance_frame()
+    }
+
+    fn poll_remote_clients(&mut self) {
+        self.poll_remote_clients();
+    }
+
+    fn events(&mut self) -> Vec<GGRSEvent> {
+        self.events().collect()
+    }
+
+    fn current_state(&self) -> SessionState {
+        self.current_state()
+    }
+
+    fn network_stats(&self, _player_handle: PlayerHandle) -> Result<NetworkStats, GGRSError> {
+        self.network_stats()
+    }
+
+    fn current_frame(&self) -> Frame {
+        self.current_frame()
+    }
+
+    fn confirmed_frame(&self) -> Frame {
+        self.confirmed_frame()
+    }
+
+    fn num_players(&self) -> u32 {
+        self.num_players()
+    }
+
+    fn input_size(&self) -> usize {
+        self.input_size()
     }
 }
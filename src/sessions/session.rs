@@ -0,0 +1,98 @@
+use std::time::{Duration, Instant};
+
+use crate::{Frame, GGRSError, GGRSEvent, GGRSRequest, NetworkStats, PlayerHandle, SessionState};
+
+/// The time `idle()` sleeps between two polls.
+const IDLE_POLL_INTERVAL: Duration = Duration::from_millis(1);
+
+/// The functionality shared by `P2PSession`, `P2PSpectatorSession`, `SyncTestSession` and `OfflineSession`. Engine integrations can hold a `Box<dyn Session>`
+/// and run a P2P match, a spectated match or a sync test with the same code. Session-specific configuration is still done on the concrete types
+/// before boxing them.
+pub trait Session {
+    /// Advances the session by a single frame with the inputs of all local players, given as pairs of player handle and input.
+    /// A `P2PSession` takes the input of its local player, a `SyncTestSession` or `OfflineSession` takes the inputs of all players and a `P2PSpectatorSession` takes no inputs.
+    /// Returns an order-sensitive `Vec<GGRSRequest>` that you have to fulfill in the exact order they are provided.
+    /// # Errors
+    /// - Returns `InvalidRequest` if the inputs do not match the local players of the session.
+    /// - Returns all errors of the `advance_frame()` method of the concrete session.
+    fn advance_frame(
+        &mut self,
+        local_inputs: &[(PlayerHandle, &[u8])],
+    ) -> Result<Vec<GGRSRequest>, GGRSError>;
+
+    /// Receives and sends all packets and handles all occurring events. Does nothing for sessions without network.
+    fn poll_remote_clients(&mut self);
+
+    /// Polls the remote clients repeatedly until `max_duration` has passed, sleeping in between. See `P2PSession::idle()`.
+    fn idle(&mut self, max_duration: Duration) {
+        poll_until(max_duration, || self.poll_remote_clients());
+    }
+
+    /// Returns all events that happened since last queried for events.
+    fn events(&mut self) -> Vec<GGRSEvent>;
+
+    /// Returns the current `SessionState` of the session. Sessions without network are always `Running`.
+    fn current_state(&self) -> SessionState;
+
+    /// Returns statistics about the connection to the given player. Spectators are only connected to their host and ignore the handle.
+    /// # Errors
+    /// - Returns `InvalidRequest` if the session has no network.
+    /// - Returns the errors of the `network_stats()` method of the concrete session.
+    fn network_stats(&self, player_handle: PlayerHandle) -> Result<NetworkStats, GGRSError>;
+
+    /// Returns the frame of the current gamestate, which is the frame the next `AdvanceFrame` request advances from.
+    fn current_frame(&self) -> Frame;
+
+    /// Returns the last frame that has been advanced with confirmed inputs of all players, or `NULL_FRAME` if there is none yet.
+    fn confirmed_frame(&self) -> Frame;
+
+    /// Returns the number of players in the session.
+    fn num_players(&self) -> u32;
+
+    /// Returns the input size of a single player.
+    fn input_size(&self) -> usize;
+}
+
+/// Calls `poll` at least once and then about every millisecond, until `max_duration` has passed.
+pub(crate) fn poll_until(max_duration: Duration, mut poll: impl FnMut()) {
+    // browsers neither allow reading the clock through the standard library nor blocking the thread, so only poll once there
+    if cfg!(all(target_arch = "wasm32", target_os = "unknown")) {
+        poll();
+        return;
+    }
+
+    let deadline = Instant::now() + max_duration;
+    loop {
+        poll();
+        let now = Instant::now();
+        if now >= deadline {
+            return;
+        }
+        std::thread::sleep(std::cmp::min(IDLE_POLL_INTERVAL, deadline - now));
+    }
+}
+
+/// Orders the inputs of all players by player handle, for sessions where all players are local.
+pub(crate) fn inputs_of_all_players(
+    local_inputs: &[(PlayerHandle, &[u8])],
+    num_players: u32,
+) -> Result<Vec<Vec<u8>>, GGRSError> {
+    let mut all_inputs = vec![None; num_players as usize];
+    for (handle, input) in local_inputs {
+        match all_inputs.get_mut(handle.0) {
+            Some(slot @ None) => *slot = Some(input.to_vec()),
+            Some(Some(_)) => {
+                return Err(GGRSError::InvalidRequest {
+                    info: "Multiple inputs for the same player.".to_owned(),
+                })
+            }
+            None => return Err(GGRSError::InvalidHandle),
+        }
+    }
+    all_inputs
+        .into_iter()
+        .collect::<Option<Vec<_>>>()
+        .ok_or_else(|| GGRSError::InvalidRequest {
+            info: "The session takes the inputs of all players.".to_owned(),
+        })
+}
@@ -1,10 +1,21 @@
-use std::collections::HashMap;
+use alloc::borrow::ToOwned;
+use alloc::collections::BTreeMap;
+use alloc::sync::Arc;
+use alloc::vec::Vec;
 
 use crate::error::GGRSError;
 use crate::frame_info::GameInput;
+use crate::input_latency::InputLatency;
+use crate::input_predictor::InputPredictor;
+use crate::input_queue::InputQueueInfo;
 use crate::network::udp_msg::ConnectionStatus;
+#[cfg(feature = "std")]
+use crate::sessions::session::{inputs_of_all_players, Session};
 use crate::sync_layer::SyncLayer;
-use crate::{Frame, GGRSRequest, PlayerHandle};
+use crate::telemetry::SessionTelemetry;
+use crate::{Frame, GGRSRequest, PlayerHandle, MAX_PREDICTION_FRAMES};
+#[cfg(feature = "std")]
+use crate::{GGRSEvent, NetworkStats, SessionState};
 
 /// During a `SyncTestSession`, GGRS will simulate a rollback every frame and resimulate the last n states, where n is the given check distance.
 /// The resimulated checksums will be compared with the original checksums and report if there was a mismatch.
@@ -13,9 +24,13 @@ pub struct SyncTestSession {
     num_players: u32,
     input_size: usize,
     check_distance: u32,
+    /// The distance of the simulated rollbacks. After the check distance has been raised, it grows by one frame per frame,
+    /// since older inputs have already been discarded.
+    rollback_distance: u32,
     sync_layer: SyncLayer,
     dummy_connect_status: Vec<ConnectionStatus>,
-    checksum_history: HashMap<Frame, u64>,
+    checksum_history: BTreeMap<Frame, u64>,
+    input_latency: InputLatency,
 }
 
 impl SyncTestSession {
@@ -29,9 +44,11 @@ impl SyncTestSession {
             num_players,
             input_size,
             check_distance,
+            rollback_distance: check_distance,
             sync_layer: SyncLayer::new(num_players, input_size),
             dummy_connect_status,
-            checksum_history: HashMap::default(),
+            checksum_history: BTreeMap::default(),
+            input_latency: InputLatency::new(num_players as usize),
         }
     }
 
@@ -44,15 +61,32 @@ impl SyncTestSession {
     pub fn advance_frame(
         &mut self,
         all_inputs: &Vec<Vec<u8>>,
+    ) -> Result<Vec<GGRSRequest>, GGRSError> {
+        ggrs_span!("advance_frame", frame = self.sync_layer.current_frame().0);
+        // without the standard library, there is no clock to measure the time with
+        #[cfg(feature = "std")]
+        let start = std::time::Instant::now();
+        let result = self.advance_frame_untimed(all_inputs);
+        #[cfg(feature = "std")]
+        self.sync_layer.record_advance_frame(start.elapsed());
+        result
+    }
+
+    fn advance_frame_untimed(
+        &mut self,
+        all_inputs: &[Vec<u8>],
     ) -> Result<Vec<GGRSRequest>, GGRSError> {
         let mut requests = Vec::new();
 
         // if we advanced far enough into the game do comparisons and rollbacks
-        if self.check_distance > 0 && self.sync_layer.current_frame() > self.check_distance as i32 {
+        if self.rollback_distance > 0
+            && self.sync_layer.current_frame() > self.rollback_distance as i32
+        {
             // compare checksums of older frames to our checksum history (where only the first version of any checksum is recorded)
-            for i in 0..=self.check_distance as i32 {
+            for i in 0..=self.rollback_distance as i32 {
                 let frame_to_check = self.sync_layer.current_frame() - i;
                 if !self.checksums_consistent(frame_to_check) {
+                    ggrs_warn!("Checksum mismatch detected at frame {}", frame_to_check);
                     return Err(GGRSError::MismatchedChecksum {
                         frame: frame_to_check,
                     });
@@ -60,7 +94,7 @@ impl SyncTestSession {
             }
 
             // simulate rollbacks according to the check_distance
-            let frame_to = self.sync_layer.current_frame() - self.check_distance as i32;
+            let frame_to = self.sync_layer.current_frame() - self.rollback_distance as i32;
             self.adjust_gamestate(frame_to, &mut requests);
         }
 
@@ -70,10 +104,10 @@ impl SyncTestSession {
             //create an input struct for current frame
             let mut input: GameInput =
                 GameInput::new(self.sync_layer.current_frame(), self.input_size);
-            input.copy_input(&all_inputs[i]);
+            input.copy_input(&self.input_latency.delay(i, &all_inputs[i]));
 
             // send the input into the sync layer
-            self.sync_layer.add_local_input(i, input)?;
+            self.sync_layer.add_local_input(PlayerHandle(i), input)?;
         }
 
         // save the current frame in the syncronization layer
@@ -88,12 +122,20 @@ impl SyncTestSession {
         }
 
         // advance the frame
-        requests.push(GGRSRequest::AdvanceFrame { inputs });
+        requests.push(GGRSRequest::AdvanceFrame {
+            frame: self.sync_layer.current_frame(),
+            inputs,
+        });
         self.sync_layer.advance_frame();
 
+        // after raising the check distance, we keep one more frame of inputs every frame until we can roll back as far as requested
+        if self.rollback_distance < self.check_distance {
+            self.rollback_distance += 1;
+        }
+
         // since this is a sync test, we "cheat" by setting the last confirmed state to the (current state - check_distance), so the sync layer wont complain about missing
         // inputs from other players
-        let safe_frame = self.sync_layer.current_frame() - self.check_distance as i32;
+        let safe_frame = self.sync_layer.current_frame() - self.rollback_distance as i32;
 
         self.sync_layer.set_last_confirmed_frame(safe_frame, false);
 
@@ -105,6 +147,26 @@ impl SyncTestSession {
         Ok(requests)
     }
 
+    /// Sets the `InputPredictor` used during the simulated rollbacks. By default, GGRS predicts that players repeat their last input (`RepeatLastInput`).
+    pub fn set_input_predictor(&mut self, predictor: impl InputPredictor + Send + Sync + 'static) {
+        self.sync_layer.set_input_predictor(Arc::new(predictor));
+    }
+
+    /// Sets a function that computes the checksums of saved gamestates from their buffers. With a checksum function, you do not need to provide checksums
+    /// when saving your gamestate and checksums provided in `GameState` are ignored. By default, the checksums of `GameState::new()` are used,
+    /// which are computed with fletcher16 if you do not provide any.
+    pub fn set_checksum_function(&mut self, checksum_function: fn(&[u8]) -> u64) {
+        self.sync_layer.set_checksum_function(checksum_function);
+        self.checksum_history.clear();
+    }
+
+    /// Holds back the local inputs for `frames` frames before the session uses them, to simulate the input latency your players will experience,
+    /// e.g. to tune the frame delay against the amount of rollbacks. The latency adds to the frame delay, but is not part of the input queues:
+    /// held back inputs are simply not known yet, so the simulated rollbacks are unaffected. The default is 0.
+    pub fn set_simulated_latency(&mut self, frames: u32) {
+        self.input_latency.set_frames(frames);
+    }
+
     /// Change the amount of frames GGRS will delay the inputs for a player.
     /// # Errors
     /// Returns `InvalidHandle` if the provided player handle is higher than the number of players.
@@ -115,13 +177,55 @@ impl SyncTestSession {
         player_handle: PlayerHandle,
     ) -> Result<(), GGRSError> {
         // player handle is invalid
-        if player_handle > self.num_players as PlayerHandle {
+        if player_handle > self.num_players as usize {
             return Err(GGRSError::InvalidHandle);
         }
         self.sync_layer.set_frame_delay(player_handle, frame_delay);
         Ok(())
     }
 
+    /// Changes the number of frames simulated rollbacks go back, e.g. to narrow down the first frame that does not run deterministically.
+    /// A smaller check distance is used right away. A larger one is reached gradually, one frame per advanced frame, since the session only keeps
+    /// the inputs needed for the previous check distance.
+    /// # Errors
+    /// - Returns `InvalidRequest` if the check distance is higher than or equal to `MAX_PREDICTION_FRAMES`.
+    pub fn set_check_distance(&mut self, check_distance: u32) -> Result<(), GGRSError> {
+        if check_distance >= MAX_PREDICTION_FRAMES {
+            return Err(GGRSError::InvalidRequest {
+                info: "Check distance too big.".to_owned(),
+            });
+        }
+        self.check_distance = check_distance;
+        self.rollback_distance = core::cmp::min(self.rollback_distance, check_distance);
+        Ok(())
+    }
+
+    /// Returns the number of frames simulated rollbacks go back.
+    pub const fn check_distance(&self) -> u32 {
+        self.check_distance
+    }
+
+    /// Returns a `SessionTelemetry` struct that gives information about the rollbacks performed since the session was created.
+    pub const fn telemetry(&self) -> SessionTelemetry {
+        self.sync_layer.telemetry()
+    }
+
+    /// Returns an `InputQueueInfo` for every player, ordered by handle. It describes which frames have confirmed inputs.
+    pub fn input_queue_info(&self) -> Vec<InputQueueInfo> {
+        self.sync_layer.input_queue_info()
+    }
+
+    /// Returns the frame of the current gamestate, which is the frame the next `AdvanceFrame` request advances from.
+    pub const fn current_frame(&self) -> Frame {
+        self.sync_layer.current_frame()
+    }
+
+    /// Returns the last frame that has been advanced, or `NULL_FRAME` if there is none yet. All inputs of a sync test are given directly,
+    /// so this is always the frame before `current_frame()`.
+    pub fn confirmed_frame(&self) -> Frame {
+        self.sync_layer.current_frame() - 1
+    }
+
     /// Returns the number of players this session was constructed with.
     pub const fn num_players(&self) -> u32 {
         self.num_players
@@ -135,23 +239,18 @@ impl SyncTestSession {
     /// Updates the `checksum_history` and checks if the checksum is identical if it already has been recorded once
     fn checksums_consistent(&mut self, frame_to_check: Frame) -> bool {
         // remove entries older than the check_distance
-        let oldest_allowed_frame = self.sync_layer.current_frame() - self.check_distance as i32;
+        let oldest_allowed_frame = self.sync_layer.current_frame() - self.rollback_distance as i32;
         self.checksum_history
             .retain(|&k, _| k >= oldest_allowed_frame);
 
-        match self.sync_layer.saved_state_by_frame(frame_to_check) {
-            Some(latest_cell) => {
-                let latest_state = latest_cell.load();
-
-                match self.checksum_history.get(&latest_state.frame) {
-                    Some(cs) => *cs == latest_state.checksum,
-                    None => {
-                        self.checksum_history
-                            .insert(latest_state.frame, latest_state.checksum);
-                        true
-                    }
+        match self.sync_layer.saved_checksum(frame_to_check) {
+            Some(checksum) => match self.checksum_history.get(&frame_to_check) {
+                Some(cs) => *cs == checksum,
+                None => {
+                    self.checksum_history.insert(frame_to_check, checksum);
+                    true
                 }
-            }
+            },
             None => true,
         }
     }
@@ -159,6 +258,7 @@ impl SyncTestSession {
     fn adjust_gamestate(&mut self, frame_to: Frame, requests: &mut Vec<GGRSRequest>) {
         let start_frame = self.sync_layer.current_frame();
         let count = start_frame - frame_to;
+        ggrs_span!("rollback", from = start_frame.0, to = frame_to.0);
 
         // rollback to the first incorrect state
         requests.push(self.sync_layer.load_frame(frame_to));
@@ -171,9 +271,55 @@ impl SyncTestSession {
                 .sync_layer
                 .synchronized_inputs(&self.dummy_connect_status);
 
+            requests.push(GGRSRequest::AdvanceFrame {
+                frame: self.sync_layer.current_frame(),
+                inputs,
+            });
             self.sync_layer.advance_frame();
-            requests.push(GGRSRequest::AdvanceFrame { inputs });
         }
         assert_eq!(self.sync_layer.current_frame(), start_frame);
     }
 }
+
+#[cfg(feature = "std")]
+impl Session for SyncTestSession {
+    fn advance_frame(
+        &mut self,
+        local_inputs: &[(PlayerHandle, &[u8])],
+    ) -> Result<Vec<GGRSRequest>, GGRSError> {
+        let all_inputs = inputs_of_all_players(local_inputs, self.num_players)?;
+        self.advance_frame(&all_inputs)
+    }
+
+    fn poll_remote_clients(&mut self) {}
+
+    fn events(&mut self) -> Vec<GGRSEvent> {
+        Vec::new()
+    }
+
+    fn current_state(&self) -> SessionState {
+        SessionState::Running
+    }
+
+    fn network_stats(&self, _player_handle: PlayerHandle) -> Result<NetworkStats, GGRSError> {
+        Err(GGRSError::InvalidRequest {
+            info: "A SyncTestSession has no network.".to_owned(),
+        })
+    }
+
+    fn current_frame(&self) -> Frame {
+        self.current_frame()
+    }
+
+    fn confirmed_frame(&self) -> Frame {
+        self.confirmed_frame()
+    }
+
+    fn num_players(&self) -> u32 {
+        self.num_players()
+    }
+
+    fn input_size(&self) -> usize {
+        self.input_size()
+    }
+}
@@ -0,0 +1,134 @@
+use alloc::collections::vec_deque::Drain;
+use alloc::collections::VecDeque;
+
+use crate::{Frame, GGRSRequest, NULL_FRAME};
+
+/// A `SideEffectQueue` holds back effects of the simulation that should not happen twice or at all if the frame they originate from is rolled back,
+/// like playing sounds or rumbling the controller. Push effects with the frame of the `AdvanceFrame` request that caused them and show the requests
+/// of the session to `observe()` before fulfilling them. When the session rolls back, the queue drops the effects of all frames that will be simulated again.
+/// Once a frame is confirmed, `release()` hands out its effects, which will never be rolled back.
+///
+/// Released effects lag behind by the number of frames the session predicts. If your game should react immediately, play the `predicted()` effects
+/// and use the queue to find out which of them turned out to be wrong.
+#[derive(Debug, Clone)]
+pub struct SideEffectQueue<T> {
+    /// The effects that have not been released yet, ordered by frame.
+    effects: VecDeque<(Frame, T)>,
+    /// The last frame whose effects have been released. Effects pushed for this or earlier frames again are dropped, since they have been released already.
+    released_frame: Frame,
+}
+
+impl<T> Default for SideEffectQueue<T> {
+    fn default() -> Self {
+        Self {
+            effects: VecDeque::new(),
+            released_frame: NULL_FRAME,
+        }
+    }
+}
+
+impl<T> SideEffectQueue<T> {
+    /// Creates an empty `SideEffectQueue`.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Looks at the requests returned by `advance_frame()` and drops the effects of all frames that are rolled back. Call this before fulfilling the requests.
+    pub fn observe(&mut self, requests: &[GGRSRequest]) {
+        for request in requests {
+            if let GGRSRequest::LoadGameState { frame, .. } = request {
+                self.rollback(*frame);
+            }
+        }
+    }
+
+    /// Drops the effects of the given frame and all later frames, since the gamestate of `frame` has been loaded and those frames will be simulated again.
+    /// `observe()` calls this for you.
+    pub fn rollback(&mut self, frame: Frame) {
+        let index = self.effects.partition_point(|(f, _)| *f < frame);
+        self.effects.truncate(index);
+    }
+
+    /// Adds an effect that originates from advancing the given frame. Effects of frames that have been released already are dropped,
+    /// which happens when the session rolls back further than the confirmed frame, e.g. with sparse saving.
+    pub fn push(&mut self, frame: Frame, effect: T) {
+        if frame <= self.released_frame {
+            return;
+        }
+        let index = self.effects.partition_point(|(f, _)| *f <= frame);
+        self.effects.insert(index, (frame, effect));
+    }
+
+    /// Hands out the effects of all frames up to and including `confirmed_frame`, in the order of their frames. Pass the `confirmed_frame()` of your session.
+    pub fn release(&mut self, confirmed_frame: Frame) -> Drain<'_, (Frame, T)> {
+        if confirmed_frame > self.released_frame {
+            self.released_frame = confirmed_frame;
+        }
+        let index = self
+            .effects
+            .partition_point(|(f, _)| *f <= self.released_frame);
+        self.effects.drain(..index)
+    }
+
+    /// Returns the effects that have not been released yet, together with the frame they originate from. They might still be dropped by a rollback.
+    pub fn predicted(&self) -> impl Iterator<Item = (Frame, &T)> {
+        self.effects.iter().map(|(frame, effect)| (*frame, effect))
+    }
+
+    /// Returns the number of effects that have not been released yet.
+    pub fn len(&self) -> usize {
+        self.effects.len()
+    }
+
+    /// Returns true if all effects have been released or dropped.
+    pub fn is_empty(&self) -> bool {
+        self.effects.is_empty()
+    }
+}
+
+// #########
+// # TESTS #
+// #########
+
+#[cfg(test)]
+mod side_effects_tests {
+    use super::*;
+    use crate::GameStateCell;
+
+    #[test]
+    fn test_rollback_drops_effects() {
+        let mut queue = SideEffectQueue::new();
+        for frame in 0..5 {
+            queue.push(Frame(frame), frame);
+        }
+
+        let load = GGRSRequest::LoadGameState {
+            cell: GameStateCell::default(),
+            frame: Frame(2),
+        };
+        queue.observe(&[load]);
+        assert_eq!(queue.len(), 2);
+        queue.push(Frame(2), 20);
+        queue.push(Frame(3), 30);
+
+        let released: Vec<_> = queue.release(Frame(2)).collect();
+        assert_eq!(released, vec![(Frame(0), 0), (Frame(1), 1), (Frame(2), 20)]);
+        assert_eq!(queue.predicted().collect::<Vec<_>>(), vec![(Frame(3), &30)]);
+    }
+
+    #[test]
+    fn test_released_frames_are_not_repeated() {
+        let mut queue = SideEffectQueue::new();
+        queue.push(Frame(0), "jump");
+        assert_eq!(queue.release(Frame(0)).count(), 1);
+
+        // resimulating a released frame does not release its effects again
+        queue.rollback(Frame(0));
+        queue.push(Frame(0), "jump");
+        queue.push(Frame(1), "land");
+        assert_eq!(queue.len(), 1);
+        assert_eq!(queue.release(NULL_FRAME).count(), 0);
+        assert_eq!(queue.release(Frame(1)).count(), 1);
+        assert!(queue.is_empty());
+    }
+}
@@ -1,32 +1,126 @@
-use parking_lot::Mutex;
-use std::sync::Arc;
+use alloc::borrow::ToOwned;
+use alloc::collections::VecDeque;
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use core::cmp;
+use core::ops::DerefMut;
+#[cfg(feature = "std")]
+use core::time::Duration;
 
 use crate::error::GGRSError;
 use crate::frame_info::{GameInput, GameState, BLANK_INPUT};
-use crate::input_queue::InputQueue;
+#[cfg(feature = "std")]
+use crate::input_predictor::InputTolerance;
+use crate::input_predictor::{DisconnectedInput, InputBot, InputPredictor};
+use crate::input_queue::{InputQueue, InputQueueInfo};
 use crate::network::udp_msg::ConnectionStatus;
+use crate::telemetry::SessionTelemetry;
 use crate::{Frame, GGRSRequest, PlayerHandle, MAX_PREDICTION_FRAMES, NULL_FRAME};
 
 /// An `Arc<Mutex<GameState>>` that you can `save()`/`load()` a `GameState` to/from. These will be handed to the user as part of a `GGRSRequest`.
+/// Without the `std` feature, this is an `Rc<RefCell<GameState>>` instead, so the cell cannot be sent to other threads.
 #[derive(Debug)]
-pub struct GameStateCell(Arc<Mutex<GameState>>);
+pub struct GameStateCell(SharedCellData);
+
+#[cfg(feature = "std")]
+type SharedCellData = std::sync::Arc<parking_lot::Mutex<CellData>>;
+#[cfg(not(feature = "std"))]
+type SharedCellData = alloc::rc::Rc<core::cell::RefCell<CellData>>;
+
+/// The content of a `GameStateCell`.
+#[derive(Debug)]
+struct CellData {
+    state: GameState,
+    /// The frame of the gamestate that should be saved into the cell, or `NULL_FRAME` if any frame is fine.
+    expected_frame: Frame,
+    /// The size of the preallocated buffer, if the session has bounded memory. The buffer is kept when the cell is reset.
+    capacity: Option<usize>,
+}
+
+impl CellData {
+    fn check_frame(&self, frame: Frame) {
+        assert!(frame != NULL_FRAME);
+        assert!(
+            self.expected_frame == NULL_FRAME || self.expected_frame == frame,
+            "Trying to save the gamestate of frame {} into the cell for frame {}.",
+            frame,
+            self.expected_frame
+        );
+    }
+}
 
 impl GameStateCell {
-    pub(crate) fn reset(&self) {
-        *self.0.lock() = GameState {
-            frame: NULL_FRAME,
-            buffer: None,
-            checksum: 0,
-        }
+    /// Gives exclusive access to the content of the cell.
+    fn data(&self) -> impl DerefMut<Target = CellData> + '_ {
+        #[cfg(feature = "std")]
+        let data = self.0.lock();
+        #[cfg(not(feature = "std"))]
+        let data = self.0.borrow_mut();
+        data
+    }
+
+    /// Clears the cell, so it can receive the gamestate of the given frame.
+    pub(crate) fn reset(&self, expected_frame: Frame) {
+        let mut data = self.data();
+        let buffer = match data.capacity {
+            Some(_) => data.state.buffer.take().map(|mut buffer| {
+                buffer.clear();
+                buffer
+            }),
+            None => None,
+        };
+        data.state = GameState {
+            buffer,
+            ..GameState::default()
+        };
+        data.expected_frame = expected_frame;
+    }
+
+    /// Allocates a buffer of `capacity` bytes for the gamestates saved with `save_bytes()`, which larger gamestates are rejected for.
+    #[cfg(feature = "std")]
+    pub(crate) fn preallocate(&self, capacity: usize) {
+        let mut data = self.data();
+        data.capacity = Some(capacity);
+        data.state.buffer = Some(Vec::with_capacity(capacity));
+    }
+
+    /// Returns the frame of the gamestate in the cell, or `NULL_FRAME` if nothing has been saved yet.
+    pub(crate) fn frame(&self) -> Frame {
+        self.data().state.frame
     }
 
     /// Saves a `GameState` the user creates into the cell.
+    ///
+    /// # Panics
+    /// Will panic if the gamestate is not of the frame the cell has been handed out for, e.g. because the game and the session disagree on the current frame.
     pub fn save(&self, new_state: GameState) {
-        let mut state = self.0.lock();
-        assert!(new_state.frame != NULL_FRAME);
-        state.frame = new_state.frame;
-        state.checksum = new_state.checksum;
-        state.buffer = new_state.buffer;
+        let mut data = self.data();
+        data.check_frame(new_state.frame);
+        data.state = new_state;
+    }
+
+    /// Copies the serialized gamestate of the given frame into the buffer of the cell. Unlike `save()`, this does not allocate
+    /// if the buffer is large enough, which it always is for a session with bounded memory, see `P2PSession::set_bounded_memory()`.
+    ///
+    /// # Errors
+    /// Returns `InvalidRequest` if the session has bounded memory and the gamestate is larger than the preallocated buffer.
+    ///
+    /// # Panics
+    /// Will panic if the gamestate is not of the frame the cell has been handed out for, just like `save()`.
+    pub fn save_bytes(&self, frame: Frame, bytes: &[u8], checksum: u64) -> Result<(), GGRSError> {
+        let mut data = self.data();
+        data.check_frame(frame);
+        if matches!(data.capacity, Some(capacity) if bytes.len() > capacity) {
+            return Err(GGRSError::InvalidRequest {
+                info: "The gamestate does not fit into the preallocated buffer.".to_owned(),
+            });
+        }
+        data.state.frame = frame;
+        data.state.checksum = checksum;
+        let buffer = data.state.buffer.get_or_insert_with(Vec::new);
+        buffer.clear();
+        buffer.extend_from_slice(bytes);
+        Ok(())
     }
 
     /// Loads a `GameState` that the user previously saved into it.
@@ -34,18 +128,41 @@ impl GameStateCell {
     /// # Panics
     /// Will panic if the data has previously not been saved to.
     pub fn load(&self) -> GameState {
-        let state = self.0.lock();
-        if state.frame != NULL_FRAME {
-            state.clone()
+        let data = self.data();
+        if data.state.frame != NULL_FRAME {
+            data.state.clone()
         } else {
             panic!("Trying to load data that wasn't saved to.")
         }
     }
+
+    /// Copies the serialized gamestate in the cell into `buffer`, replacing its content. Unlike `load()`, this does not allocate if `buffer` is large enough.
+    ///
+    /// # Panics
+    /// Will panic if the data has previously not been saved to.
+    pub fn load_into(&self, buffer: &mut Vec<u8>) {
+        let data = self.data();
+        assert!(
+            data.state.frame != NULL_FRAME,
+            "Trying to load data that wasn't saved to."
+        );
+        buffer.clear();
+        if let Some(bytes) = &data.state.buffer {
+            buffer.extend_from_slice(bytes);
+        }
+    }
 }
 
 impl Default for GameStateCell {
     fn default() -> Self {
-        Self(Arc::new(Mutex::new(GameState::default())))
+        Self(SharedCellData::new(
+            CellData {
+                state: GameState::default(),
+                expected_frame: NULL_FRAME,
+                capacity: None,
+            }
+            .into(),
+        ))
     }
 }
 
@@ -73,15 +190,15 @@ impl Default for SavedStates {
 impl SavedStates {
     fn push(&mut self, frame: Frame) -> GameStateCell {
         assert!(frame >= 0);
-        let pos = frame as usize % self.states.len();
+        let pos = frame.0 as usize % self.states.len();
         let cell = self.states[pos].clone();
-        cell.reset();
+        cell.reset(frame);
         cell
     }
 
     fn peek(&mut self, frame: Frame) -> GameStateCell {
         assert!(frame >= 0);
-        let pos = frame as usize % self.states.len();
+        let pos = frame.0 as usize % self.states.len();
         let saved_cell = self.states[pos].clone();
         saved_cell
     }
@@ -89,7 +206,7 @@ impl SavedStates {
     fn by_frame(&self, frame: Frame) -> Option<GameStateCell> {
         self.states
             .iter()
-            .find(|saved| saved.0.lock().frame == frame)
+            .find(|saved| saved.frame() == frame)
             .cloned()
     }
 }
@@ -101,9 +218,20 @@ pub(crate) struct SyncLayer {
     saved_states: SavedStates,
     rolling_back: bool,
     last_confirmed_frame: Frame,
+    /// The number of frames before the last confirmed frame for which inputs are kept.
+    retained_frames: u32,
+    /// If set, checksums of saved states are computed from their buffers instead of using the checksums provided by the user.
+    checksum_function: Option<fn(&[u8]) -> u64>,
     last_saved_frame: Frame,
     current_frame: Frame,
     input_queues: Vec<InputQueue>,
+    /// If set, substitutes the inputs of disconnected players instead of giving them blank inputs.
+    disconnected_input: Option<Arc<dyn DisconnectedInput + Send + Sync>>,
+    /// If set, plays for disconnected players. Takes precedence over `disconnected_input`.
+    input_bot: Option<Arc<dyn InputBot + Send + Sync>>,
+    /// The inputs the gamestate has been advanced with in the most recent frames, which the bot sees for the frame after. Only kept with a bot.
+    simulated_inputs: VecDeque<(Frame, Vec<GameInput>)>,
+    telemetry: SessionTelemetry,
 }
 
 impl SyncLayer {
@@ -112,19 +240,25 @@ impl SyncLayer {
         // initialize input_queues
         let mut input_queues = Vec::new();
         for i in 0..num_players {
-            input_queues.push(InputQueue::new(i as PlayerHandle, input_size));
+            input_queues.push(InputQueue::new(PlayerHandle(i as usize), input_size));
         }
         Self {
             num_players,
             input_size,
             rolling_back: false,
             last_confirmed_frame: NULL_FRAME,
+            retained_frames: 0,
+            checksum_function: None,
             last_saved_frame: NULL_FRAME,
-            current_frame: 0,
+            current_frame: Frame(0),
             saved_states: SavedStates {
                 states: Default::default(),
             },
             input_queues,
+            disconnected_input: None,
+            input_bot: None,
+            simulated_inputs: VecDeque::new(),
+            telemetry: SessionTelemetry::new(),
         }
     }
 
@@ -132,22 +266,107 @@ impl SyncLayer {
         self.current_frame
     }
 
+    #[cfg(feature = "std")]
+    pub(crate) const fn last_confirmed_frame(&self) -> Frame {
+        self.last_confirmed_frame
+    }
+
+    /// Returns true if the input of the given player for the given frame has already been added, e.g. before restoring a session.
+    #[cfg(feature = "std")]
+    pub(crate) fn is_local_input_added(&self, player_handle: PlayerHandle, frame: Frame) -> bool {
+        let queue = &self.input_queues[player_handle.0];
+        queue.last_added_frame() != NULL_FRAME
+            && frame + queue.frame_delay() as i32 <= queue.last_added_frame()
+    }
+
+    pub(crate) fn input_queue_info(&self) -> Vec<InputQueueInfo> {
+        self.input_queues.iter().map(InputQueue::info).collect()
+    }
+
+    /// Returns the frame delay and all inputs from the given frame on for every player.
+    pub(crate) fn inputs_since(&self, frame: Frame) -> Vec<(u32, Vec<GameInput>)> {
+        self.input_queues
+            .iter()
+            .map(|queue| (queue.frame_delay(), queue.inputs_since(frame)))
+            .collect()
+    }
+
+    /// Resets the sync layer to the given frame with the given inputs. No state is saved, so the next state has to be saved before any rollback.
+    pub(crate) fn restore(
+        &mut self,
+        current_frame: Frame,
+        last_confirmed_frame: Frame,
+        inputs: &[(u32, Vec<GameInput>)],
+    ) {
+        assert_eq!(inputs.len(), self.num_players as usize);
+        self.current_frame = current_frame;
+        self.last_confirmed_frame = last_confirmed_frame;
+        self.last_saved_frame = NULL_FRAME;
+        self.rolling_back = false;
+        self.saved_states = SavedStates::default();
+        for (queue, (frame_delay, queue_inputs)) in self.input_queues.iter_mut().zip(inputs) {
+            queue.restore(queue_inputs, *frame_delay);
+        }
+    }
+
     pub(crate) fn advance_frame(&mut self) {
         self.current_frame += 1;
     }
 
     pub(crate) fn save_current_state(&mut self) -> GGRSRequest {
-        self.last_saved_frame = self.current_frame;
-        let cell = self.saved_states.push(self.current_frame);
         GGRSRequest::SaveGameState {
-            cell,
+            cell: self.save_current_state_cell(),
             frame: self.current_frame,
         }
     }
 
+    /// Preallocates a buffer of `capacity` bytes in every cell of the saved states.
+    #[cfg(feature = "std")]
+    pub(crate) fn preallocate_states(&mut self, capacity: usize) {
+        for cell in &self.saved_states.states {
+            cell.preallocate(capacity);
+        }
+    }
+
+    /// Like `save_current_state()`, but returns the cell the gamestate should be saved in instead of a request.
+    pub(crate) fn save_current_state_cell(&mut self) -> GameStateCell {
+        self.last_saved_frame = self.current_frame;
+        self.telemetry.record_save();
+        self.saved_states.push(self.current_frame)
+    }
+
     pub(crate) fn set_frame_delay(&mut self, player_handle: PlayerHandle, delay: u32) {
-        assert!(player_handle < self.num_players as PlayerHandle);
-        self.input_queues[player_handle as usize].set_frame_delay(delay);
+        assert!(player_handle < self.num_players as usize);
+        self.input_queues[player_handle.0].set_frame_delay(delay);
+    }
+
+    pub(crate) fn set_input_predictor(&mut self, predictor: Arc<dyn InputPredictor + Send + Sync>) {
+        for queue in self.input_queues.iter_mut() {
+            queue.set_input_predictor(predictor.clone());
+        }
+    }
+
+    #[cfg(feature = "std")]
+    pub(crate) fn set_disconnected_input(
+        &mut self,
+        disconnected_input: Arc<dyn DisconnectedInput + Send + Sync>,
+    ) {
+        self.disconnected_input = Some(disconnected_input);
+    }
+
+    #[cfg(feature = "std")]
+    pub(crate) fn set_input_bot(&mut self, input_bot: Arc<dyn InputBot + Send + Sync>) {
+        self.input_bot = Some(input_bot);
+    }
+
+    #[cfg(feature = "std")]
+    pub(crate) fn set_input_tolerance(
+        &mut self,
+        tolerance: Option<Arc<dyn InputTolerance + Send + Sync>>,
+    ) {
+        for queue in self.input_queues.iter_mut() {
+            queue.set_input_tolerance(tolerance.clone());
+        }
     }
 
     pub(crate) fn reset_prediction(&mut self) {
@@ -167,12 +386,22 @@ impl SyncLayer {
 
         // Reset the head of the state ring-buffer to point in advance of the current frame (as if we had just finished executing it).
         let cell = self.saved_states.peek(frame_to_load);
-        let loaded_frame = cell.0.lock().frame;
+        let loaded_frame = cell.frame();
         assert_eq!(loaded_frame, frame_to_load);
 
+        ggrs_debug!(
+            "Rolling back from frame {} to frame {}",
+            self.current_frame,
+            loaded_frame
+        );
+        self.telemetry
+            .record_rollback((self.current_frame - loaded_frame) as u32);
         self.current_frame = loaded_frame;
 
-        GGRSRequest::LoadGameState { cell }
+        GGRSRequest::LoadGameState {
+            cell,
+            frame: loaded_frame,
+        }
     }
 
     /// Adds local input to the corresponding input queue. Checks if the prediction threshold has been reached. Returns the frame number where the input is actually added to.
@@ -182,20 +411,45 @@ impl SyncLayer {
         player_handle: PlayerHandle,
         input: GameInput,
     ) -> Result<Frame, GGRSError> {
-        let frames_ahead = self.current_frame - self.last_confirmed_frame;
-        if frames_ahead >= MAX_PREDICTION_FRAMES as i32 {
+        Self::check_prediction_threshold(input.frame, self.last_confirmed_frame)?;
+        Ok(self.add_local_input_unchecked(player_handle, input))
+    }
+
+    /// Returns `PredictionThreshold` if local input for `frame` would predict too many frames beyond `confirmed_frame`.
+    pub(crate) fn check_prediction_threshold(
+        frame: Frame,
+        confirmed_frame: Frame,
+    ) -> Result<(), GGRSError> {
+        if frame - confirmed_frame >= MAX_PREDICTION_FRAMES as i64 {
+            ggrs_debug!("Prediction threshold reached at frame {}", frame);
             return Err(GGRSError::PredictionThreshold);
         }
+        Ok(())
+    }
 
-        // The input provided should match the current frame, we account for input delay later
-        assert_eq!(input.frame, self.current_frame);
-        Ok(self.input_queues[player_handle].add_input(input))
+    /// Like `add_local_input`, but without checking the prediction threshold, which the caller has checked before changing anything.
+    pub(crate) fn add_local_input_unchecked(
+        &mut self,
+        player_handle: PlayerHandle,
+        input: GameInput,
+    ) -> Frame {
+        // The input provided should match the current frame, we account for input delay later.
+        // While a session catches up on a deferred correction, the input is for a frame the gamestate has not reached yet.
+        assert!(input.frame >= self.current_frame);
+        self.input_queues[player_handle.0].add_input(input)
+    }
+
+    /// Returns `true` if the input queue of the player can take another remote input.
+    #[cfg(feature = "std")]
+    pub(crate) fn has_room_for_input(&self, player_handle: PlayerHandle) -> bool {
+        !self.input_queues[player_handle.0].is_full()
     }
 
     /// Adds remote input to the correspoinding input queue.
     /// Unlike `add_local_input`, this will not check for correct conditions, as remote inputs have already been checked on another device.
+    #[cfg(feature = "std")]
     pub(crate) fn add_remote_input(&mut self, player_handle: PlayerHandle, input: GameInput) {
-        self.input_queues[player_handle].add_input(input);
+        self.input_queues[player_handle.0].add_input(input);
     }
 
     /// Returns inputs for all players for the current frame of the sync layer. If there are none for a specific player, return predictions.
@@ -206,11 +460,29 @@ impl SyncLayer {
         let mut inputs = Vec::new();
         for (i, con_stat) in connect_status.iter().enumerate() {
             if con_stat.disconnected && con_stat.last_frame < self.current_frame {
-                inputs.push(BLANK_INPUT);
+                inputs.push(self.disconnected_input(
+                    i,
+                    con_stat.last_frame,
+                    self.current_frame,
+                    connect_status,
+                ));
             } else {
                 inputs.push(self.input_queues[i].input(self.current_frame));
             }
         }
+
+        // the bot sees these inputs in the next frame. After a rollback, the inputs of the resimulated frames are replaced
+        if self.input_bot.is_some() {
+            let frame = self.current_frame;
+            while matches!(self.simulated_inputs.back(), Some((simulated, _)) if *simulated >= frame)
+            {
+                self.simulated_inputs.pop_back();
+            }
+            self.simulated_inputs.push_back((frame, inputs.clone()));
+            while self.simulated_inputs.len() > MAX_PREDICTION_FRAMES as usize + 2 {
+                self.simulated_inputs.pop_front();
+            }
+        }
         inputs
     }
 
@@ -223,7 +495,7 @@ impl SyncLayer {
         let mut inputs = Vec::new();
         for (i, con_stat) in connect_status.iter().enumerate() {
             if con_stat.disconnected && con_stat.last_frame < frame {
-                inputs.push(BLANK_INPUT);
+                inputs.push(self.disconnected_input(i, con_stat.last_frame, frame, connect_status));
             } else {
                 inputs.push(self.input_queues[i].confirmed_input(frame));
             }
@@ -231,12 +503,62 @@ impl SyncLayer {
         inputs
     }
 
+    /// Returns the input of a player for a frame after their last confirmed frame `last_frame`, which is `BLANK_INPUT` unless a `DisconnectedInput`
+    /// or an `InputBot` is set. Substituted inputs keep `NULL_FRAME` as their frame, so the game can still tell that the player is disconnected.
+    fn disconnected_input(
+        &self,
+        player: usize,
+        last_frame: Frame,
+        frame: Frame,
+        connect_status: &[ConnectionStatus],
+    ) -> GameInput {
+        if self.disconnected_input.is_none() && self.input_bot.is_none() {
+            return BLANK_INPUT;
+        }
+        let mut input = GameInput::new(NULL_FRAME, self.input_size);
+        if let Some(last_input) = self.input_queues[player].stored_input(last_frame) {
+            input.copy_input(last_input.input());
+        }
+        if let Some(bot) = &self.input_bot {
+            let previous_inputs = self.previous_inputs(frame, connect_status);
+            bot.play(
+                frame,
+                PlayerHandle(player),
+                &previous_inputs,
+                &mut input.buffer[..self.input_size],
+            );
+        } else if let Some(substitute) = &self.disconnected_input {
+            let frames_disconnected = cmp::max(frame - last_frame, 1) as u32;
+            substitute.substitute(&mut input.buffer[..self.input_size], frames_disconnected);
+        }
+        input
+    }
+
+    /// Returns the inputs of all players in the frame before `frame`, as the bot sees them.
+    fn previous_inputs(&self, frame: Frame, connect_status: &[ConnectionStatus]) -> Vec<GameInput> {
+        let previous = frame - 1;
+        if previous < 0 {
+            return Vec::new();
+        }
+        // frames ahead of the gamestate have not been simulated yet, which happens for confirmed inputs with a frame delay.
+        // All inputs up to those frames are confirmed, so the bot sees the same inputs as it will when the gamestate gets there
+        if previous >= self.current_frame {
+            return self.confirmed_inputs(previous, connect_status);
+        }
+        self.simulated_inputs
+            .iter()
+            .rev()
+            .find(|(simulated, _)| *simulated == previous)
+            .map(|(_, inputs)| inputs.clone())
+            .unwrap_or_default()
+    }
+
     /// Sets the last confirmed frame to a given frame. By raising the last confirmed frame, we can discard all previous frames, as they are no longer necessary.
     pub(crate) fn set_last_confirmed_frame(&mut self, mut frame: Frame, sparse_saving: bool) {
         // dont set the last confirmed frame after the first incorrect frame before a rollback has happened
         let mut first_incorrect: Frame = NULL_FRAME;
         for handle in 0..self.num_players as usize {
-            first_incorrect = std::cmp::max(
+            first_incorrect = cmp::max(
                 first_incorrect,
                 self.input_queues[handle].first_incorrect_frame(),
             );
@@ -244,21 +566,29 @@ impl SyncLayer {
 
         // if sparse saving option is turned on, don't set the last confirmed frame after the last saved frame
         if sparse_saving {
-            frame = std::cmp::min(frame, self.last_saved_frame);
+            frame = cmp::min(frame, self.last_saved_frame);
         }
 
         // if we set the last confirmed frame beyond the first incorrect frame, we discard inputs that we need later for ajusting the gamestate.
         assert!(first_incorrect == NULL_FRAME || first_incorrect >= frame);
 
         self.last_confirmed_frame = frame;
-        if self.last_confirmed_frame > 0 {
+        let discard_frame = frame - 1 - self.retained_frames as i32;
+        if discard_frame >= 0 {
             for i in 0..self.num_players {
-                self.input_queues[i as usize].discard_confirmed_frames(frame - 1);
+                self.input_queues[i as usize].discard_confirmed_frames(discard_frame);
             }
         }
     }
 
+    /// Keeps the inputs of the given number of frames before the last confirmed frame, so the session can resimulate these frames.
+    #[cfg(feature = "std")]
+    pub(crate) fn set_retained_frames(&mut self, frames: u32) {
+        self.retained_frames = frames;
+    }
+
     /// Finds the earliest incorrect frame detected by the individual input queues
+    #[cfg(feature = "std")]
     pub(crate) fn check_simulation_consistency(&self, mut first_incorrect: Frame) -> Frame {
         for handle in 0..self.num_players as usize {
             let incorrect = self.input_queues[handle].first_incorrect_frame();
@@ -272,14 +602,42 @@ impl SyncLayer {
     }
 
     /// Returns a gamestate through given frame
+    #[cfg(feature = "std")]
     pub(crate) fn saved_state_by_frame(&self, frame: Frame) -> Option<GameStateCell> {
         self.saved_states.by_frame(frame)
     }
 
+    /// Returns the checksum of the saved state of a given frame, computed with the checksum function if one has been set.
+    pub(crate) fn saved_checksum(&self, frame: Frame) -> Option<u64> {
+        let cell = self.saved_states.by_frame(frame)?;
+        let data = cell.data();
+        let state = &data.state;
+        match (self.checksum_function, &state.buffer) {
+            (Some(checksum_function), Some(buffer)) => Some(checksum_function(buffer)),
+            _ => Some(state.checksum),
+        }
+    }
+
+    pub(crate) fn set_checksum_function(&mut self, checksum_function: fn(&[u8]) -> u64) {
+        self.checksum_function = Some(checksum_function);
+    }
+
     /// Returns the latest saved frame
+    #[cfg(feature = "std")]
     pub(crate) const fn last_saved_frame(&self) -> Frame {
         self.last_saved_frame
     }
+
+    /// Returns the rollback counters of this sync layer
+    pub(crate) const fn telemetry(&self) -> SessionTelemetry {
+        self.telemetry
+    }
+
+    /// Registers a call to `advance_frame()` of the session that took `duration`.
+    #[cfg(feature = "std")]
+    pub(crate) fn record_advance_frame(&mut self, duration: Duration) {
+        self.telemetry.record_advance_frame(duration);
+    }
 }
 
 // #########
@@ -290,16 +648,19 @@ impl SyncLayer {
 mod sync_layer_tests {
 
     use super::*;
+    use crate::input_predictor::{FixedInput, RepeatLastInput};
 
     #[test]
     #[should_panic]
     fn test_reach_prediction_threshold() {
         let mut sync_layer = SyncLayer::new(2, std::mem::size_of::<u32>());
         for i in 0..20 {
-            let serialized_input = bincode::serialize(&i).unwrap();
-            let mut game_input = GameInput::new(i, std::mem::size_of::<u32>());
+            let serialized_input = bincode::serialize(&(i as u32)).unwrap();
+            let mut game_input = GameInput::new(Frame(i), std::mem::size_of::<u32>());
             game_input.copy_input(&serialized_input);
-            sync_layer.add_local_input(0, game_input).unwrap(); // should crash at frame 7
+            sync_layer
+                .add_local_input(PlayerHandle(0), game_input)
+                .unwrap(); // should crash at frame 7
         }
     }
 
@@ -308,23 +669,23 @@ mod sync_layer_tests {
         let mut sync_layer = SyncLayer::new(2, std::mem::size_of::<u32>());
         let p1_delay = 2;
         let p2_delay = 0;
-        sync_layer.set_frame_delay(0, p1_delay);
-        sync_layer.set_frame_delay(1, p2_delay);
+        sync_layer.set_frame_delay(PlayerHandle(0), p1_delay);
+        sync_layer.set_frame_delay(PlayerHandle(1), p2_delay);
 
         let mut dummy_connect_status = Vec::new();
         dummy_connect_status.push(ConnectionStatus::default());
         dummy_connect_status.push(ConnectionStatus::default());
 
         for i in 0..20 {
-            let serialized_input = bincode::serialize(&i).unwrap();
-            let mut game_input = GameInput::new(i, std::mem::size_of::<u32>());
+            let serialized_input = bincode::serialize(&(i as u32)).unwrap();
+            let mut game_input = GameInput::new(Frame(i), std::mem::size_of::<u32>());
             game_input.copy_input(&serialized_input);
             // adding input as remote to avoid prediction threshold detection
-            sync_layer.add_remote_input(0, game_input);
-            sync_layer.add_remote_input(1, game_input);
+            sync_layer.add_remote_input(PlayerHandle(0), game_input);
+            sync_layer.add_remote_input(PlayerHandle(1), game_input);
             // update the dummy connect status
-            dummy_connect_status[0].last_frame = i;
-            dummy_connect_status[1].last_frame = i;
+            dummy_connect_status[0].last_frame = Frame(i);
+            dummy_connect_status[1].last_frame = Frame(i);
 
             if i >= 3 {
                 let sync_inputs = sync_layer.synchronized_inputs(&dummy_connect_status);
@@ -337,4 +698,118 @@ mod sync_layer_tests {
             sync_layer.advance_frame();
         }
     }
+
+    #[test]
+    fn test_disconnected_input() {
+        let mut sync_layer = SyncLayer::new(2, std::mem::size_of::<u32>());
+        let mut connect_status = vec![ConnectionStatus::default(); 2];
+        for i in 0..3 {
+            let serialized_input = bincode::serialize(&(i as u32 + 10)).unwrap();
+            let mut game_input = GameInput::new(Frame(i), std::mem::size_of::<u32>());
+            game_input.copy_input(&serialized_input);
+            sync_layer.add_remote_input(PlayerHandle(0), game_input);
+            sync_layer.add_remote_input(PlayerHandle(1), game_input);
+            sync_layer.advance_frame();
+        }
+        // the second player disconnected after frame 1
+        connect_status[1].disconnected = true;
+        connect_status[1].last_frame = Frame(1);
+
+        let inputs = sync_layer.confirmed_inputs(Frame(2), &connect_status);
+        assert_eq!(inputs[1], BLANK_INPUT);
+
+        sync_layer.set_disconnected_input(Arc::new(RepeatLastInput));
+        let inputs = sync_layer.confirmed_inputs(Frame(2), &connect_status);
+        assert_eq!(inputs[0].frame, Frame(2));
+        assert_eq!(inputs[1].frame, NULL_FRAME);
+        assert_eq!(bincode::deserialize::<u32>(inputs[1].input()).unwrap(), 11);
+
+        sync_layer.set_disconnected_input(Arc::new(FixedInput::new(vec![3, 0, 0, 0])));
+        let inputs = sync_layer.confirmed_inputs(Frame(2), &connect_status);
+        assert_eq!(bincode::deserialize::<u32>(inputs[1].input()).unwrap(), 3);
+    }
+
+    /// Plays the input of the first player in the frame before, plus one.
+    #[derive(Debug)]
+    struct FollowBot;
+
+    impl InputBot for FollowBot {
+        fn play(
+            &self,
+            _frame: Frame,
+            _player_handle: PlayerHandle,
+            previous_inputs: &[GameInput],
+            input: &mut [u8],
+        ) {
+            if let Some(previous) = previous_inputs.first() {
+                input[0] = previous.input()[0] + 1;
+            }
+        }
+    }
+
+    #[test]
+    fn test_input_bot() {
+        let mut sync_layer = SyncLayer::new(2, 1);
+        sync_layer.set_input_bot(Arc::new(FollowBot));
+        let mut connect_status = vec![ConnectionStatus::default(); 2];
+        connect_status[1].disconnected = true;
+        connect_status[1].last_frame = Frame(0);
+
+        for i in 0..5 {
+            let mut game_input = GameInput::new(Frame(i), 1);
+            game_input.copy_input(&[i as u8 * 10]);
+            sync_layer.add_remote_input(PlayerHandle(0), game_input);
+            if i == 0 {
+                sync_layer.add_remote_input(PlayerHandle(1), game_input);
+            }
+        }
+
+        // the bot plays along with the simulated frames
+        for i in 0..3 {
+            let inputs = sync_layer.synchronized_inputs(&connect_status);
+            if i > 0 {
+                assert_eq!(inputs[1].frame, NULL_FRAME);
+                assert_eq!(inputs[1].input(), &[(i as u8 - 1) * 10 + 1]);
+            }
+            sync_layer.advance_frame();
+        }
+
+        // the bot plays the same for confirmed frames, whether they have been simulated or not
+        for i in 1..5 {
+            let inputs = sync_layer.confirmed_inputs(Frame(i), &connect_status);
+            assert_eq!(inputs[1].input(), &[(i as u8 - 1) * 10 + 1]);
+        }
+    }
+
+    #[test]
+    #[should_panic]
+    fn test_save_wrong_frame() {
+        let mut sync_layer = SyncLayer::new(2, std::mem::size_of::<u32>());
+        sync_layer.advance_frame();
+        let cell = sync_layer.save_current_state_cell();
+        cell.save(GameState::new(Frame(0), None, None)); // should crash, the cell is for frame 1
+    }
+
+    #[test]
+    fn test_preallocated_states() {
+        let mut sync_layer = SyncLayer::new(2, std::mem::size_of::<u32>());
+        sync_layer.preallocate_states(4);
+
+        for frame in 0..20 {
+            let cell = sync_layer.save_current_state_cell();
+            assert!(cell.save_bytes(Frame(frame), &[1; 5], 0).is_err());
+            cell.save_bytes(Frame(frame), &(frame as u32).to_le_bytes(), 7)
+                .unwrap();
+            assert_eq!(cell.load().checksum, 7);
+            sync_layer.advance_frame();
+        }
+
+        // the buffers survive being reused for later frames
+        let cell = sync_layer.saved_state_by_frame(Frame(19)).unwrap();
+        let mut buffer = Vec::new();
+        cell.load_into(&mut buffer);
+        assert_eq!(buffer, 19u32.to_le_bytes());
+        cell.reset(Frame(20));
+        assert!(cell.0.lock().state.buffer.as_ref().unwrap().capacity() >= 4);
+    }
 }
@@ -0,0 +1,80 @@
+use core::time::Duration;
+
+/// The `SessionTelemetry` struct contains counters and timings about the rollback work a session has done since it was started.
+/// This can be useful to display in debug overlays or to tune the prediction window and input delay of your game.
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub struct SessionTelemetry {
+    /// The number of rollbacks the session performed.
+    pub rollbacks: usize,
+    /// The total number of frames that have been resimulated as part of rollbacks.
+    pub frames_resimulated: usize,
+    /// The number of `GGRSRequest::SaveGameState` requests handed to the user.
+    pub saves_requested: usize,
+    /// The largest number of frames resimulated during a single rollback.
+    pub max_rollback_depth: u32,
+    /// The number of UDP packets sent per second, averaged since the session was started.
+    pub packets_sent_per_second: f32,
+    /// The number of UDP packets received per second, averaged since the session was started.
+    pub packets_received_per_second: f32,
+    /// The total time spent inside `advance_frame()`, as read from the time source of the session. This is the work GGRS does itself, e.g. polling
+    /// and preparing rollbacks, not the time your game needs to fulfill the requests. A `P2PSession` reads its time source, a `SyncTestSession` the system clock,
+    /// which it can only do with the `std` feature. Always zero otherwise.
+    pub advance_frame_time: Duration,
+    /// The longest time a single call to `advance_frame()` took.
+    pub max_advance_frame_time: Duration,
+}
+
+impl SessionTelemetry {
+    /// Creates a new `SessionTelemetry` instance with default values.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Registers a rollback that resimulates `depth` frames.
+    pub(crate) fn record_rollback(&mut self, depth: u32) {
+        self.rollbacks += 1;
+        self.frames_resimulated += depth as usize;
+        self.max_rollback_depth = core::cmp::max(self.max_rollback_depth, depth);
+    }
+
+    /// Registers a call to `advance_frame()` that took `duration`.
+    #[cfg(feature = "std")]
+    pub(crate) fn record_advance_frame(&mut self, duration: Duration) {
+        self.advance_frame_time += duration;
+        self.max_advance_frame_time = core::cmp::max(self.max_advance_frame_time, duration);
+    }
+
+    /// Registers a single save request.
+    pub(crate) fn record_save(&mut self) {
+        self.saves_requested += 1;
+    }
+}
+
+// #########
+// # TESTS #
+// #########
+
+#[cfg(test)]
+mod telemetry_tests {
+    use super::*;
+
+    #[test]
+    fn test_record_rollbacks() {
+        let mut telemetry = SessionTelemetry::new();
+        telemetry.record_rollback(3);
+        telemetry.record_rollback(7);
+        telemetry.record_rollback(2);
+        assert_eq!(telemetry.rollbacks, 3);
+        assert_eq!(telemetry.frames_resimulated, 12);
+        assert_eq!(telemetry.max_rollback_depth, 7);
+    }
+
+    #[test]
+    fn test_record_advance_frame_times() {
+        let mut telemetry = SessionTelemetry::new();
+        telemetry.record_advance_frame(Duration::from_micros(300));
+        telemetry.record_advance_frame(Duration::from_micros(900));
+        assert_eq!(telemetry.advance_frame_time, Duration::from_micros(1200));
+        assert_eq!(telemetry.max_advance_frame_time, Duration::from_micros(900));
+    }
+}
@@ -0,0 +1,522 @@
+//! Utilities to test your game for determinism, available with the `test-utils` feature.
+//! A `SimulationHarness` runs a `P2PSession` for every player in a single process. The sessions talk over an `InMemoryNetwork` with simulated latencies,
+//! packet loss and disconnects, and read the time from the virtual clock of the network, so a test runs without real sockets and without waiting for real time to pass.
+
+use parking_lot::Mutex;
+use std::collections::{HashMap, HashSet};
+use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+use std::sync::Arc;
+use std::time::Duration;
+
+use crate::network::udp_msg::UdpMessage;
+use crate::network::udp_socket::NonBlockingSocket;
+use crate::{
+    Frame, GGRSError, GGRSRequest, GameInput, GameState, P2PSession, PlayerHandle, PlayerType,
+    SessionState, NULL_FRAME,
+};
+use crate::{ManualTimeSource, TimeSource};
+
+/// The port of the address of the first player. Every other player uses the next port.
+const BASE_PORT: u16 = 10000;
+/// The number of ticks the sessions have to synchronize before the harness gives up.
+const MAX_SYNC_TICKS: u32 = 1000;
+/// The virtual time that passes with every tick by default, a frame at 60 FPS.
+const DEFAULT_TICK_DURATION: Duration = Duration::from_micros(16_667);
+
+#[derive(Debug)]
+struct Packet {
+    deliver_at: u64,
+    from: SocketAddr,
+    to: SocketAddr,
+    msg: UdpMessage,
+}
+
+#[derive(Debug)]
+struct NetworkState {
+    tick: u64,
+    latencies: HashMap<SocketAddr, u32>,
+    in_flight: Vec<Packet>,
+    /// Packets sent from or to an address are lost while the tick is lower than the given one.
+    lossy_until: HashMap<SocketAddr, u64>,
+    /// Packets sent from or to these addresses are lost until they are connected again.
+    disconnected: HashSet<SocketAddr>,
+    tick_duration: Duration,
+    time_source: ManualTimeSource,
+}
+
+impl Default for NetworkState {
+    fn default() -> Self {
+        Self {
+            tick: 0,
+            latencies: HashMap::new(),
+            in_flight: Vec::new(),
+            lossy_until: HashMap::new(),
+            disconnected: HashSet::new(),
+            tick_duration: DEFAULT_TICK_DURATION,
+            time_source: ManualTimeSource::new(),
+        }
+    }
+}
+
+impl NetworkState {
+    fn is_lost(&self, addr: SocketAddr) -> bool {
+        self.disconnected.contains(&addr)
+            || matches!(self.lossy_until.get(&addr), Some(until) if self.tick < *until)
+    }
+}
+
+/// A simulated network connecting any number of `InMemorySocket`s. Time on the network advances in ticks: a packet sent from an address with a latency of `n`
+/// is delivered after `n` calls to `tick()`. Every tick also advances the virtual clock of the network, which you can set as the time source of the sessions
+/// on the network, so their timeouts expire after a fixed number of ticks. Cloning the network gives you another handle to the same network.
+#[derive(Debug, Clone, Default)]
+pub struct InMemoryNetwork {
+    state: Arc<Mutex<NetworkState>>,
+}
+
+impl InMemoryNetwork {
+    /// Creates a new network without any latency.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Creates a socket that sends and receives packets on this network with the given address.
+    pub fn socket(&self, addr: SocketAddr) -> InMemorySocket {
+        InMemorySocket {
+            addr,
+            network: self.clone(),
+        }
+    }
+
+    /// Sets the number of ticks it takes for packets sent from the given address to arrive.
+    pub fn set_latency(&self, addr: SocketAddr, ticks: u32) {
+        self.state.lock().latencies.insert(addr, ticks);
+    }
+
+    /// Loses all packets sent from or to the given address from now on, until `ticks` more ticks have passed. This simulates a burst of packet loss.
+    pub fn drop_packets(&self, addr: SocketAddr, ticks: u32) {
+        let mut state = self.state.lock();
+        let until = state.tick + ticks as u64;
+        state.lossy_until.insert(addr, until);
+    }
+
+    /// Disconnects the given address from the network, or connects it again. All packets sent from or to a disconnected address are lost.
+    pub fn set_disconnected(&self, addr: SocketAddr, disconnected: bool) {
+        let mut state = self.state.lock();
+        if disconnected {
+            state.disconnected.insert(addr);
+        } else {
+            state.disconnected.remove(&addr);
+        }
+    }
+
+    /// Sets the virtual time that passes with every tick. The default is the duration of a frame at 60 FPS.
+    pub fn set_tick_duration(&self, duration: Duration) {
+        self.state.lock().tick_duration = duration;
+    }
+
+    /// Returns the virtual clock of the network. Set it as the time source of your sessions with `set_time_source()`.
+    pub fn time_source(&self) -> ManualTimeSource {
+        self.state.lock().time_source.clone()
+    }
+
+    /// Returns the current virtual time of the network.
+    pub fn now(&self) -> Duration {
+        self.state.lock().time_source.now()
+    }
+
+    /// Advances the time on the network by a single tick.
+    pub fn tick(&self) {
+        let mut state = self.state.lock();
+        state.tick += 1;
+        state.time_source.advance(state.tick_duration);
+    }
+}
+
+/// A `NonBlockingSocket` on an `InMemoryNetwork`. Create it with `InMemoryNetwork::socket()`.
+#[derive(Debug)]
+pub struct InMemorySocket {
+    addr: SocketAddr,
+    network: InMemoryNetwork,
+}
+
+impl NonBlockingSocket for InMemorySocket {
+    fn send_to(&mut self, msg: &UdpMessage, addr: SocketAddr) {
+        let mut state = self.network.state.lock();
+        if state.is_lost(self.addr) || state.is_lost(addr) {
+            return;
+        }
+        let latency = state.latencies.get(&self.addr).copied().unwrap_or(0);
+        let deliver_at = state.tick + latency as u64;
+        state.in_flight.push(Packet {
+            deliver_at,
+            from: self.addr,
+            to: addr,
+            msg: msg.clone(),
+        });
+    }
+
+    fn receive_all_messages(&mut self) -> Vec<(SocketAddr, UdpMessage)> {
+        let mut state = self.network.state.lock();
+        let tick = state.tick;
+        let (arrived, in_flight): (Vec<Packet>, Vec<Packet>) = state
+            .in_flight
+            .drain(..)
+            .partition(|packet| packet.to == self.addr && packet.deliver_at <= tick);
+        state.in_flight = in_flight;
+        arrived
+            .into_iter()
+            .map(|packet| (packet.from, packet.msg))
+            .collect()
+    }
+}
+
+/// Your game, as driven by a `SimulationHarness`. Every player runs their own instance of the game.
+pub trait SimulationGame {
+    /// Returns the current gamestate of the given frame, including its checksum.
+    fn save(&self, frame: Frame) -> GameState;
+    /// Replaces the current gamestate with the given one.
+    fn load(&mut self, state: GameState);
+    /// Advances the gamestate by a single frame with the given inputs.
+    fn advance(&mut self, inputs: &[GameInput]);
+}
+
+#[derive(Debug)]
+struct Peer<G: SimulationGame> {
+    session: P2PSession,
+    game: G,
+    next_frame: Frame,
+    last_confirmed_frame: Frame,
+    checksums: HashMap<Frame, u64>,
+}
+
+impl<G: SimulationGame> Peer<G> {
+    fn handle_requests(&mut self, requests: Vec<GGRSRequest>) {
+        for request in requests {
+            match request {
+                GGRSRequest::SaveGameState { cell, frame } => {
+                    let state = self.game.save(frame);
+                    self.checksums.insert(frame, state.checksum);
+                    cell.save(state);
+                }
+                GGRSRequest::LoadGameState { cell, .. } => self.game.load(cell.load()),
+                GGRSRequest::AdvanceFrame { inputs, .. } => self.game.advance(&inputs),
+                GGRSRequest::Resimulate {
+                    from_frame,
+                    inputs_per_frame,
+                    cells,
+                } => {
+                    for (i, (inputs, cell)) in inputs_per_frame.iter().zip(cells).enumerate() {
+                        self.game.advance(inputs);
+                        if let Some(cell) = cell {
+                            let frame = from_frame + i as i32 + 1;
+                            let state = self.game.save(frame);
+                            self.checksums.insert(frame, state.checksum);
+                            cell.save(state);
+                        }
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// A `SimulationHarness` runs a `P2PSession` for every player over an `InMemoryNetwork`, drives all sessions with scripted inputs
+/// and checks that the confirmed gamestates of all players match. Use it to write end-to-end determinism tests for your game.
+#[derive(Debug)]
+pub struct SimulationHarness<G: SimulationGame> {
+    network: InMemoryNetwork,
+    peers: Vec<Peer<G>>,
+}
+
+impl<G: SimulationGame> SimulationHarness<G> {
+    /// Creates a session for every given game instance. The player handle of each player is the position of their game in `games`.
+    /// # Errors
+    /// - Returns `InvalidRequest` if the number of players or the input size are not allowed for a `P2PSession`.
+    pub fn new(input_size: usize, games: Vec<G>) -> Result<Self, GGRSError> {
+        let num_players = games.len() as u32;
+        let network = InMemoryNetwork::new();
+        let mut peers = Vec::new();
+
+        for (local_handle, game) in games.into_iter().enumerate() {
+            let local_handle = PlayerHandle(local_handle);
+            let socket = network.socket(Self::addr(local_handle));
+            let mut session =
+                crate::start_p2p_session_with_socket(num_players, input_size, socket)?;
+            session.set_time_source(network.time_source())?;
+            for handle in (0..num_players as usize).map(PlayerHandle) {
+                let player_type = if handle == local_handle {
+                    PlayerType::Local
+                } else {
+                    PlayerType::Remote(Self::addr(handle))
+                };
+                session.add_player(player_type, handle)?;
+            }
+            session.set_confirmed_inputs_enabled(true);
+            peers.push(Peer {
+                session,
+                game,
+                next_frame: Frame(0),
+                last_confirmed_frame: NULL_FRAME,
+                checksums: HashMap::new(),
+            });
+        }
+
+        Ok(Self { network, peers })
+    }
+
+    /// Sets the number of frames it takes for packets of the given player to arrive at the other players.
+    pub fn set_latency(&mut self, player_handle: PlayerHandle, frames: u32) {
+        self.network.set_latency(Self::addr(player_handle), frames);
+    }
+
+    /// Returns the network the sessions communicate over, for example to drop packets or disconnect a player during a scenario.
+    /// The address of a player is `127.0.0.1` with port `10000 + player_handle`.
+    pub fn network(&self) -> &InMemoryNetwork {
+        &self.network
+    }
+
+    /// Returns the session of the given player, for example to set an input delay before running the simulation.
+    pub fn session_mut(&mut self, player_handle: PlayerHandle) -> &mut P2PSession {
+        &mut self.peers[player_handle.0].session
+    }
+
+    /// Returns the game instance of the given player.
+    pub fn game(&self, player_handle: PlayerHandle) -> &G {
+        &self.peers[player_handle.0].game
+    }
+
+    /// Starts and synchronizes the sessions if necessary, then advances every player by the given amount of frames. Every frame, `inputs` is asked for the
+    /// input of each player. Afterwards, the checksums of all gamestates that are confirmed on every player are compared. Returns the number of compared frames.
+    /// # Errors
+    /// - Returns `MismatchedChecksum` with the first frame on which the gamestates of the players differ.
+    /// - Returns `NotSynchronized` if the sessions did not manage to synchronize.
+    /// - Returns any error the sessions return while starting or advancing.
+    pub fn run(
+        &mut self,
+        frames: u32,
+        mut inputs: impl FnMut(PlayerHandle, Frame) -> Vec<u8>,
+    ) -> Result<u32, GGRSError> {
+        self.synchronize()?;
+
+        let target_frame = self
+            .peers
+            .iter()
+            .map(|peer| peer.next_frame)
+            .max()
+            .unwrap_or(Frame(0))
+            + frames as i32;
+        while self.peers.iter().any(|peer| peer.next_frame < target_frame) {
+            self.network.tick();
+            for (handle, peer) in self.peers.iter_mut().enumerate() {
+                let handle = PlayerHandle(handle);
+                if peer.next_frame >= target_frame {
+                    peer.session.poll_remote_clients();
+                    continue;
+                }
+
+                let input = inputs(handle, peer.next_frame);
+                match peer.session.advance_frame(handle, &input) {
+                    Ok(requests) => {
+                        peer.handle_requests(requests);
+                        peer.next_frame += 1;
+                    }
+                    // the player is too far ahead and has to wait for the others
+                    Err(GGRSError::PredictionThreshold) => (),
+                    Err(e) => return Err(e),
+                }
+
+                if let Some((frame, _)) = peer.session.confirmed_inputs().next_back() {
+                    peer.last_confirmed_frame = frame;
+                }
+                peer.session.events().for_each(drop);
+            }
+        }
+
+        self.compare_checksums()
+    }
+
+    fn synchronize(&mut self) -> Result<(), GGRSError> {
+        for peer in &mut self.peers {
+            if peer.session.current_state() == SessionState::Initializing {
+                peer.session.start_session()?;
+            }
+        }
+
+        for _ in 0..MAX_SYNC_TICKS {
+            if self
+                .peers
+                .iter()
+                .all(|peer| peer.session.current_state() == SessionState::Running)
+            {
+                return Ok(());
+            }
+            self.network.tick();
+            for peer in &mut self.peers {
+                peer.session.poll_remote_clients();
+                peer.session.events().for_each(drop);
+            }
+        }
+        Err(GGRSError::NotSynchronized)
+    }
+
+    fn compare_checksums(&self) -> Result<u32, GGRSError> {
+        // the gamestate of a frame is final once the inputs of all frames before it are confirmed
+        let last_final_frame = match self
+            .peers
+            .iter()
+            .map(|peer| peer.last_confirmed_frame)
+            .min()
+        {
+            Some(frame) if frame != NULL_FRAME => frame + 1,
+            _ => return Ok(0),
+        };
+
+        let mut compared = 0;
+        for frame in (0..=last_final_frame.0).map(Frame) {
+            let checksums: Vec<u64> = self
+                .peers
+                .iter()
+                .filter_map(|peer| peer.checksums.get(&frame).copied())
+                .collect();
+            if checksums.len() < self.peers.len() {
+                continue;
+            }
+            if checksums.iter().any(|checksum| *checksum != checksums[0]) {
+                return Err(GGRSError::MismatchedChecksum { frame });
+            }
+            compared += 1;
+        }
+        Ok(compared)
+    }
+
+    fn addr(player_handle: PlayerHandle) -> SocketAddr {
+        SocketAddr::new(
+            IpAddr::V4(Ipv4Addr::LOCALHOST),
+            BASE_PORT + player_handle.0 as u16,
+        )
+    }
+}
+
+// #########
+// # TESTS #
+// #########
+
+#[cfg(test)]
+mod test_utils_tests {
+
+    use super::*;
+    use std::convert::TryInto;
+
+    #[derive(Debug)]
+    struct CounterGame {
+        frame: Frame,
+        value: u32,
+        /// Added on every frame. Games with different offsets desync.
+        offset: u32,
+    }
+
+    impl CounterGame {
+        fn new(offset: u32) -> Self {
+            Self {
+                frame: Frame(0),
+                value: 0,
+                offset,
+            }
+        }
+    }
+
+    impl SimulationGame for CounterGame {
+        fn save(&self, frame: Frame) -> GameState {
+            assert_eq!(self.frame, frame);
+            let buffer = [&self.frame.0.to_le_bytes()[..], &self.value.to_le_bytes()].concat();
+            GameState::new(frame, Some(buffer), Some(self.value as u64))
+        }
+
+        fn load(&mut self, state: GameState) {
+            let buffer = state.buffer.unwrap();
+            self.frame = Frame(i64::from_le_bytes(buffer[..8].try_into().unwrap()));
+            self.value = u32::from_le_bytes(buffer[8..12].try_into().unwrap());
+        }
+
+        fn advance(&mut self, inputs: &[GameInput]) {
+            for input in inputs {
+                if input.frame != NULL_FRAME {
+                    self.value = self
+                        .value
+                        .wrapping_mul(31)
+                        .wrapping_add(input.input()[0] as u32);
+                }
+            }
+            self.value = self.value.wrapping_add(self.offset);
+            self.frame += 1;
+        }
+    }
+
+    fn scripted_inputs(handle: PlayerHandle, frame: Frame) -> Vec<u8> {
+        vec![(frame.0 as u8).wrapping_mul(handle.0 as u8 + 1)]
+    }
+
+    #[test]
+    fn test_in_memory_latency() {
+        let network = InMemoryNetwork::new();
+        let addr1 = SocketAddr::new(IpAddr::V4(Ipv4Addr::LOCALHOST), 1);
+        let addr2 = SocketAddr::new(IpAddr::V4(Ipv4Addr::LOCALHOST), 2);
+        let mut socket1 = network.socket(addr1);
+        let mut socket2 = network.socket(addr2);
+        network.set_latency(addr1, 2);
+
+        let msg = UdpMessage::from_bytes(&[crate::WIRE_FORMAT_VERSION, 0, 0, 0, 0, 0, 0, 0, 0, 6])
+            .unwrap();
+        socket1.send_to(&msg, addr2);
+        assert!(socket2.receive_all_messages().is_empty());
+        network.tick();
+        assert!(socket2.receive_all_messages().is_empty());
+        network.tick();
+        assert_eq!(socket2.receive_all_messages(), vec![(addr1, msg)]);
+    }
+
+    #[test]
+    fn test_deterministic_game() {
+        let games = vec![CounterGame::new(1), CounterGame::new(1)];
+        let mut harness = SimulationHarness::new(1, games).unwrap();
+        harness.set_latency(PlayerHandle(1), 4);
+        let compared = harness.run(100, scripted_inputs).unwrap();
+        assert!(compared > 50);
+        assert_eq!(harness.game(PlayerHandle(0)).frame, 100);
+    }
+
+    #[test]
+    fn test_packet_loss_and_disconnects() {
+        let network = InMemoryNetwork::new();
+        let addr1 = SocketAddr::new(IpAddr::V4(Ipv4Addr::LOCALHOST), 1);
+        let addr2 = SocketAddr::new(IpAddr::V4(Ipv4Addr::LOCALHOST), 2);
+        let mut socket1 = network.socket(addr1);
+        let mut socket2 = network.socket(addr2);
+        let msg = UdpMessage::from_bytes(&[crate::WIRE_FORMAT_VERSION, 0, 0, 0, 0, 0, 0, 0, 0, 6])
+            .unwrap();
+
+        network.drop_packets(addr2, 1);
+        socket1.send_to(&msg, addr2);
+        network.tick();
+        socket1.send_to(&msg, addr2);
+        assert_eq!(socket2.receive_all_messages().len(), 1);
+
+        network.set_disconnected(addr1, true);
+        socket1.send_to(&msg, addr2);
+        assert!(socket2.receive_all_messages().is_empty());
+        network.set_disconnected(addr1, false);
+        socket1.send_to(&msg, addr2);
+        assert_eq!(socket2.receive_all_messages().len(), 1);
+        assert_eq!(network.now(), DEFAULT_TICK_DURATION);
+    }
+
+    #[test]
+    fn test_desync_is_detected() {
+        let games = vec![CounterGame::new(1), CounterGame::new(2)];
+        let mut harness = SimulationHarness::new(1, games).unwrap();
+        assert_eq!(
+            harness.run(20, scripted_inputs),
+            Err(GGRSError::MismatchedChecksum { frame: Frame(1) })
+        );
+    }
+}
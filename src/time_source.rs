@@ -0,0 +1,109 @@
+use parking_lot::Mutex;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+/// The clock that the networked sessions read the current time from, e.g. to resend messages, send keep alives, detect disconnects and stamp events.
+/// By default, sessions use a `SystemTimeSource`. Implement this trait to supply the clock of a platform without `std::time::Instant`,
+/// or set a `ManualTimeSource` to advance the time yourself, so tests run the same way every time without waiting.
+pub trait TimeSource: std::fmt::Debug {
+    /// Returns the time that has passed since a fixed point in the past, e.g. since the time source has been read for the first time. The time must never decrease.
+    fn now(&self) -> Duration;
+}
+
+/// A time source shared by a session, its endpoints and its event queue.
+pub(crate) type SharedTimeSource = Arc<dyn TimeSource + Send + Sync>;
+
+/// Reads the time from `std::time::Instant`, counting from the first time it is read. This is the default time source of all sessions.
+/// The clock is not read before the session needs it, so on platforms without `std::time::Instant`, you can replace it with your own time source
+/// right after creating the session.
+#[derive(Debug, Default)]
+pub struct SystemTimeSource {
+    start: Mutex<Option<Instant>>,
+}
+
+impl Clone for SystemTimeSource {
+    fn clone(&self) -> Self {
+        Self {
+            start: Mutex::new(*self.start.lock()),
+        }
+    }
+}
+
+impl SystemTimeSource {
+    /// Creates a `SystemTimeSource` starting at zero.
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+impl TimeSource for SystemTimeSource {
+    fn now(&self) -> Duration {
+        let mut start = self.start.lock();
+        match *start {
+            Some(start) => start.elapsed(),
+            None => {
+                *start = Some(Instant::now());
+                Duration::ZERO
+            }
+        }
+    }
+}
+
+/// A time source that only advances when you call `advance()`. All clones share the same time, so you can hand a clone to a session
+/// and control its time from your tests, e.g. to trigger a disconnect timeout without waiting for it.
+#[derive(Debug, Clone, Default)]
+pub struct ManualTimeSource {
+    now: Arc<Mutex<Duration>>,
+}
+
+impl ManualTimeSource {
+    /// Creates a `ManualTimeSource` starting at zero.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Advances the time of this time source and all its clones by `duration`.
+    pub fn advance(&self, duration: Duration) {
+        *self.now.lock() += duration;
+    }
+}
+
+impl TimeSource for ManualTimeSource {
+    fn now(&self) -> Duration {
+        *self.now.lock()
+    }
+}
+
+// #########
+// # TESTS #
+// #########
+
+#[cfg(test)]
+mod time_source_tests {
+    use super::*;
+
+    #[test]
+    fn test_manual_time_source_is_shared() {
+        let time_source = ManualTimeSource::new();
+        let clone = time_source.clone();
+        assert_eq!(clone.now(), Duration::ZERO);
+
+        time_source.advance(Duration::from_millis(250));
+        time_source.advance(Duration::from_millis(750));
+        assert_eq!(clone.now(), Duration::from_secs(1));
+    }
+
+    #[test]
+    fn test_time_sources_can_be_shared_between_threads() {
+        fn assert_send_sync<T: Send + Sync>() {}
+        assert_send_sync::<SystemTimeSource>();
+        assert_send_sync::<ManualTimeSource>();
+
+        let time_source = ManualTimeSource::new();
+        let clone = time_source.clone();
+        std::thread::spawn(move || clone.advance(Duration::from_millis(16)))
+            .join()
+            .unwrap();
+        assert_eq!(time_source.now(), Duration::from_millis(16));
+    }
+}
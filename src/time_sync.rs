@@ -1,24 +1,31 @@
 use crate::frame_info::BLANK_INPUT;
 use crate::GameInput;
 
-const FRAME_WINDOW_SIZE: usize = 30;
+/// The default number of frames over which frame advantages are averaged.
+pub(crate) const DEFAULT_FRAME_WINDOW_SIZE: usize = 30;
+/// The default minimum rift in frames for which a wait is recommended.
+pub(crate) const DEFAULT_MIN_FRAME_ADVANTAGE: u32 = 3;
+/// The default maximum amount of frames a single wait recommendation asks for.
+pub(crate) const DEFAULT_MAX_FRAME_ADVANTAGE: u32 = 10;
 const MIN_UNIQUE_FRAMES: usize = 10;
-const MIN_FRAME_ADVANTAGE: i32 = 3;
-const MAX_FRAME_ADVANTAGE: i32 = 10;
 
 #[derive(Debug)]
 pub(crate) struct TimeSync {
-    local: [i32; FRAME_WINDOW_SIZE],
-    remote: [i32; FRAME_WINDOW_SIZE],
+    local: Vec<f32>,
+    remote: Vec<f32>,
     last_inputs: [GameInput; MIN_UNIQUE_FRAMES],
+    min_frame_advantage: u32,
+    max_frame_advantage: u32,
 }
 
 impl Default for TimeSync {
     fn default() -> Self {
         Self {
-            local: [0; FRAME_WINDOW_SIZE],
-            remote: [0; FRAME_WINDOW_SIZE],
+            local: vec![0.0; DEFAULT_FRAME_WINDOW_SIZE],
+            remote: vec![0.0; DEFAULT_FRAME_WINDOW_SIZE],
             last_inputs: [BLANK_INPUT; MIN_UNIQUE_FRAMES],
+            min_frame_advantage: DEFAULT_MIN_FRAME_ADVANTAGE,
+            max_frame_advantage: DEFAULT_MAX_FRAME_ADVANTAGE,
         }
     }
 }
@@ -28,18 +35,43 @@ impl TimeSync {
         Self::default()
     }
 
-    pub(crate) fn advance_frame(&mut self, input: GameInput, local_adv: i32, remote_adv: i32) {
-        self.last_inputs[input.frame as usize % self.last_inputs.len()] = input;
-        self.local[input.frame as usize % self.local.len()] = local_adv;
-        self.remote[input.frame as usize % self.remote.len()] = remote_adv;
+    /// Sets the number of frames over which frame advantages are averaged. Changing the window size discards the collected frame advantages.
+    pub(crate) fn set_window_size(&mut self, window_size: usize) {
+        assert!(window_size > 0);
+        if window_size != self.local.len() {
+            self.local = vec![0.0; window_size];
+            self.remote = vec![0.0; window_size];
+        }
+    }
+
+    /// Sets the smallest rift that leads to a recommendation and the largest recommendation given.
+    pub(crate) fn set_frame_advantage_limits(&mut self, min: u32, max: u32) {
+        assert!(min <= max);
+        self.min_frame_advantage = min;
+        self.max_frame_advantage = max;
+    }
+
+    /// Registers the frame advantages at the frame of the given input. The advantages are measured in frames, including fractions of frames.
+    pub(crate) fn advance_frame(&mut self, input: GameInput, local_adv: f32, remote_adv: f32) {
+        self.last_inputs[input.frame.0 as usize % self.last_inputs.len()] = input;
+        let local_len = self.local.len();
+        self.local[input.frame.0 as usize % local_len] = local_adv;
+        let remote_len = self.remote.len();
+        self.remote[input.frame.0 as usize % remote_len] = remote_adv;
+    }
+
+    /// Returns the average local and remote frame advantages over the window.
+    pub(crate) fn average_frame_advantage(&self) -> (f32, f32) {
+        let local_sum: f32 = self.local.iter().sum();
+        let local_avg = local_sum / self.local.len() as f32;
+        let remote_sum: f32 = self.remote.iter().sum();
+        let remote_avg = remote_sum / self.remote.len() as f32;
+        (local_avg, remote_avg)
     }
 
     pub(crate) fn recommend_frame_delay(&self, require_idle_input: bool) -> u32 {
         // average local and remote frame advantages
-        let local_sum: i32 = self.local.iter().sum();
-        let local_avg = local_sum as f32 / self.local.len() as f32;
-        let remote_sum: i32 = self.remote.iter().sum();
-        let remote_avg = remote_sum as f32 / self.remote.len() as f32;
+        let (local_avg, remote_avg) = self.average_frame_advantage();
 
         // if we have the advantage, we are behind and don't need to wait.
         if local_avg >= remote_avg {
@@ -50,7 +82,7 @@ impl TimeSync {
         let sleep_frames = (((remote_avg - local_avg) / 2.0_f32) + 0.5) as i32;
 
         // only wait if the discrepancy is big enough
-        if sleep_frames < MIN_FRAME_ADVANTAGE {
+        if sleep_frames < self.min_frame_advantage as i32 {
             return 0;
         }
 
@@ -67,7 +99,7 @@ impl TimeSync {
         }
 
         // never recommend beyond maximum wait
-        std::cmp::min(sleep_frames, MAX_FRAME_ADVANTAGE) as u32
+        std::cmp::min(sleep_frames as u32, self.max_frame_advantage)
     }
 }
 
@@ -79,6 +111,7 @@ impl TimeSync {
 mod sync_layer_tests {
 
     use super::*;
+    use crate::Frame;
 
     #[test]
     fn test_advance_frame_no_advantage() {
@@ -87,9 +120,9 @@ mod sync_layer_tests {
         let mut time_sync = TimeSync::default();
 
         for i in 0..60 {
-            let input = GameInput::new(i, input_size);
-            let local_adv = 0;
-            let remote_adv = 0;
+            let input = GameInput::new(Frame(i), input_size);
+            let local_adv = 0.0;
+            let remote_adv = 0.0;
             time_sync.advance_frame(input, local_adv, remote_adv)
         }
 
@@ -103,9 +136,9 @@ mod sync_layer_tests {
         let mut time_sync = TimeSync::default();
 
         for i in 0..60 {
-            let input = GameInput::new(i, input_size);
-            let local_adv = 5;
-            let remote_adv = -5;
+            let input = GameInput::new(Frame(i), input_size);
+            let local_adv = 5.0;
+            let remote_adv = -5.0;
             time_sync.advance_frame(input, local_adv, remote_adv)
         }
 
@@ -119,9 +152,9 @@ mod sync_layer_tests {
         let mut time_sync = TimeSync::default();
 
         for i in 0..60 {
-            let input = GameInput::new(i, input_size);
-            let local_adv = -1;
-            let remote_adv = 1;
+            let input = GameInput::new(Frame(i), input_size);
+            let local_adv = -1.0;
+            let remote_adv = 1.0;
             time_sync.advance_frame(input, local_adv, remote_adv)
         }
 
@@ -135,9 +168,9 @@ mod sync_layer_tests {
         let mut time_sync = TimeSync::default();
 
         for i in 0..60 {
-            let input = GameInput::new(i, input_size);
-            let local_adv = -4;
-            let remote_adv = 4;
+            let input = GameInput::new(Frame(i), input_size);
+            let local_adv = -4.0;
+            let remote_adv = 4.0;
             time_sync.advance_frame(input, local_adv, remote_adv)
         }
 
@@ -151,9 +184,9 @@ mod sync_layer_tests {
         let mut time_sync = TimeSync::default();
 
         for i in 0..60 {
-            let input = GameInput::new(i, input_size);
-            let local_adv = -40;
-            let remote_adv = 40;
+            let input = GameInput::new(Frame(i), input_size);
+            let local_adv = -40.0;
+            let remote_adv = 40.0;
             time_sync.advance_frame(input, local_adv, remote_adv)
         }
 
@@ -167,15 +200,71 @@ mod sync_layer_tests {
         let mut time_sync = TimeSync::default();
 
         for i in 0..60 {
-            let mut input = GameInput::new(i, input_size);
+            let mut input = GameInput::new(Frame(i), input_size);
             let mut bytes = [0u8; 4];
             bytes[0] = i as u8;
             input.copy_input(&bytes);
-            let local_adv = -4;
-            let remote_adv = 4;
+            let local_adv = -4.0;
+            let remote_adv = 4.0;
             time_sync.advance_frame(input, local_adv, remote_adv)
         }
 
         assert_eq!(time_sync.recommend_frame_delay(require_idle), 0);
     }
+
+    #[test]
+    fn test_custom_frame_advantage_limits() {
+        let input_size = std::mem::size_of::<u32>();
+        let require_idle = false;
+        let mut time_sync = TimeSync::default();
+        time_sync.set_frame_advantage_limits(1, 5);
+
+        for i in 0..60 {
+            let input = GameInput::new(Frame(i), input_size);
+            time_sync.advance_frame(input, -1.0, 1.0);
+        }
+        assert_eq!(time_sync.recommend_frame_delay(require_idle), 1);
+
+        for i in 60..120 {
+            let input = GameInput::new(Frame(i), input_size);
+            time_sync.advance_frame(input, -40.0, 40.0);
+        }
+        assert_eq!(time_sync.recommend_frame_delay(require_idle), 5);
+    }
+
+    #[test]
+    fn test_sub_frame_advantage() {
+        let input_size = std::mem::size_of::<u32>();
+        let require_idle = false;
+        let mut time_sync = TimeSync::default();
+        time_sync.set_frame_advantage_limits(1, 5);
+
+        // fractions of frames add up instead of being cut off
+        for i in 0..60 {
+            let input = GameInput::new(Frame(i), input_size);
+            time_sync.advance_frame(input, -1.6, 1.6);
+        }
+        let (local_avg, remote_avg) = time_sync.average_frame_advantage();
+        assert!((local_avg + 1.6).abs() < 0.001 && (remote_avg - 1.6).abs() < 0.001);
+        assert_eq!(time_sync.recommend_frame_delay(require_idle), 2);
+    }
+
+    #[test]
+    fn test_custom_window_size() {
+        let input_size = std::mem::size_of::<u32>();
+        let require_idle = false;
+        let mut time_sync = TimeSync::default();
+        time_sync.set_window_size(5);
+
+        for i in 0..60 {
+            let input = GameInput::new(Frame(i), input_size);
+            time_sync.advance_frame(input, -40.0, 40.0);
+        }
+        // with a short window, a few frames without a rift are enough to stop recommending waits
+        for i in 60..65 {
+            let input = GameInput::new(Frame(i), input_size);
+            time_sync.advance_frame(input, 0.0, 0.0);
+        }
+        assert_eq!(time_sync.recommend_frame_delay(require_idle), 0);
+    }
 }
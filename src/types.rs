@@ -0,0 +1,180 @@
+use core::fmt;
+use core::ops::{Add, AddAssign, Sub, SubAssign};
+use serde::{Deserialize, Serialize};
+
+/// A frame number. Frames of a match are counted from 0 upwards; `Frame::NULL` stands for no frame / an invalid frame.
+/// Adding or subtracting a number of frames gives another frame, and subtracting two frames gives the number of frames between them.
+/// Use `get()` to handle the null frame explicitly instead of doing arithmetic with it.
+/// Frame numbers are 64 bits wide, so even sessions that run for years at high frame rates never run out of frames.
+#[derive(
+    Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Hash, Default, Serialize, Deserialize,
+)]
+pub struct Frame(pub i64);
+
+impl Frame {
+    /// Represents no frame / an invalid frame.
+    pub const NULL: Self = Self(-1);
+
+    /// Creates the frame with the given number.
+    pub const fn new(frame: i64) -> Self {
+        Self(frame)
+    }
+
+    /// Returns true if this is `Frame::NULL`.
+    pub const fn is_null(self) -> bool {
+        self.0 == Self::NULL.0
+    }
+
+    /// Returns the frame number, or `None` for `Frame::NULL`.
+    pub const fn get(self) -> Option<i64> {
+        if self.is_null() {
+            None
+        } else {
+            Some(self.0)
+        }
+    }
+}
+
+impl From<i32> for Frame {
+    fn from(frame: i32) -> Self {
+        Self(frame.into())
+    }
+}
+
+impl From<i64> for Frame {
+    fn from(frame: i64) -> Self {
+        Self(frame)
+    }
+}
+
+impl From<Frame> for i64 {
+    fn from(frame: Frame) -> Self {
+        frame.0
+    }
+}
+
+/// `None` converts to `Frame::NULL`.
+impl From<Option<i64>> for Frame {
+    fn from(frame: Option<i64>) -> Self {
+        frame.map_or(Self::NULL, Self)
+    }
+}
+
+impl fmt::Display for Frame {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.0.fmt(f)
+    }
+}
+
+impl Add<i32> for Frame {
+    type Output = Self;
+
+    fn add(self, frames: i32) -> Self {
+        Self(self.0 + i64::from(frames))
+    }
+}
+
+impl Sub<i32> for Frame {
+    type Output = Self;
+
+    fn sub(self, frames: i32) -> Self {
+        Self(self.0 - i64::from(frames))
+    }
+}
+
+/// The number of frames between two frames.
+impl Sub for Frame {
+    type Output = i64;
+
+    fn sub(self, other: Self) -> i64 {
+        self.0 - other.0
+    }
+}
+
+impl AddAssign<i32> for Frame {
+    fn add_assign(&mut self, frames: i32) {
+        self.0 += i64::from(frames);
+    }
+}
+
+impl SubAssign<i32> for Frame {
+    fn sub_assign(&mut self, frames: i32) {
+        self.0 -= i64::from(frames);
+    }
+}
+
+impl PartialEq<i32> for Frame {
+    fn eq(&self, other: &i32) -> bool {
+        self.0 == i64::from(*other)
+    }
+}
+
+impl PartialEq<Frame> for i32 {
+    fn eq(&self, other: &Frame) -> bool {
+        i64::from(*self) == other.0
+    }
+}
+
+impl PartialOrd<i32> for Frame {
+    fn partial_cmp(&self, other: &i32) -> Option<core::cmp::Ordering> {
+        self.0.partial_cmp(&i64::from(*other))
+    }
+}
+
+/// Identifies a player of a session. Player handles are counted from 0 upwards; spectators get handles from 1000 upwards.
+#[derive(
+    Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Hash, Default, Serialize, Deserialize,
+)]
+pub struct PlayerHandle(pub usize);
+
+impl From<usize> for PlayerHandle {
+    fn from(handle: usize) -> Self {
+        Self(handle)
+    }
+}
+
+impl From<PlayerHandle> for usize {
+    fn from(handle: PlayerHandle) -> Self {
+        handle.0
+    }
+}
+
+impl fmt::Display for PlayerHandle {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.0.fmt(f)
+    }
+}
+
+impl PartialEq<usize> for PlayerHandle {
+    fn eq(&self, other: &usize) -> bool {
+        self.0 == *other
+    }
+}
+
+impl PartialOrd<usize> for PlayerHandle {
+    fn partial_cmp(&self, other: &usize) -> Option<core::cmp::Ordering> {
+        self.0.partial_cmp(other)
+    }
+}
+
+// #########
+// # TESTS #
+// #########
+
+#[cfg(test)]
+mod types_tests {
+    use super::*;
+
+    #[test]
+    fn test_frame_arithmetic() {
+        let frame = Frame::new(5);
+        assert_eq!(frame + 3, Frame(8));
+        assert_eq!(frame - Frame(2), 3);
+        assert!(frame > 4);
+        assert_eq!(frame.get(), Some(5));
+        assert_eq!(Frame::NULL.get(), None);
+        assert_eq!(Frame::from(None), Frame::NULL);
+        assert_eq!(i64::from(frame), 5);
+        assert_eq!(Frame::from(i64::MAX - 1) - Frame(i64::MAX - 3), 2);
+    }
+}
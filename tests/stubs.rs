@@ -1,7 +1,7 @@
 use bincode;
 use serde::{Deserialize, Serialize};
 
-use ggrs::{Frame, GGRSRequest, GameInput, GameState, GameStateCell};
+use ggrs::{Frame, GGRSRequest, GameInput, GameState, GameStateCell, NULL_FRAME};
 
 pub const INPUT_SIZE: usize = std::mem::size_of::<u32>();
 
@@ -21,9 +21,27 @@ impl GameStub {
     pub fn handle_requests(&mut self, requests: Vec<GGRSRequest>) {
         for request in requests {
             match request {
-                GGRSRequest::LoadGameState { cell } => self.load_game_state(cell),
+                GGRSRequest::LoadGameState { cell, frame } => {
+                    self.load_game_state(cell);
+                    assert_eq!(self.gs.frame, frame);
+                }
                 GGRSRequest::SaveGameState { cell, frame } => self.save_game_state(cell, frame),
-                GGRSRequest::AdvanceFrame { inputs } => self.advance_frame(inputs),
+                GGRSRequest::AdvanceFrame { frame, inputs } => {
+                    assert_eq!(self.gs.frame, frame);
+                    self.advance_frame(inputs);
+                }
+                GGRSRequest::Resimulate {
+                    from_frame,
+                    inputs_per_frame,
+                    cells,
+                } => {
+                    for (i, (inputs, cell)) in inputs_per_frame.into_iter().zip(cells).enumerate() {
+                        self.advance_frame(inputs);
+                        if let Some(cell) = cell {
+                            self.save_game_state(cell, from_frame + i as i32 + 1);
+                        }
+                    }
+                }
             }
         }
     }
@@ -53,8 +71,15 @@ pub struct GameStateStub {
 
 impl GameStateStub {
     fn advance_frame(&mut self, inputs: Vec<GameInput>) {
-        let p0_inputs: u32 = bincode::deserialize(inputs[0].input()).unwrap();
-        let p1_inputs: u32 = bincode::deserialize(inputs[1].input()).unwrap();
+        // disconnected players are identified by NULL_FRAME and have no input
+        let p0_inputs: u32 = match inputs[0].frame {
+            NULL_FRAME => 0,
+            _ => bincode::deserialize(inputs[0].input()).unwrap(),
+        };
+        let p1_inputs: u32 = match inputs[1].frame {
+            NULL_FRAME => 0,
+            _ => bincode::deserialize(inputs[1].input()).unwrap(),
+        };
 
         if (p0_inputs + p1_inputs) % 2 == 0 {
             self.state += 2;
@@ -0,0 +1,65 @@
+#![cfg(feature = "hole-punching")]
+
+mod stubs;
+
+use ggrs::{HolePuncher, PlayerHandle, RendezvousServer, SessionState};
+use serial_test::serial;
+use std::net::SocketAddr;
+use std::time::Duration;
+
+#[test]
+#[serial]
+fn test_hole_punching() {
+    let mut server = RendezvousServer::bind_to_port(9000).unwrap();
+    let server_addr: SocketAddr = "127.0.0.1:9000".parse().unwrap();
+
+    let punchers: Vec<_> = [(7777, 0), (8888, 1)]
+        .iter()
+        .map(|&(port, handle)| {
+            let mut puncher =
+                HolePuncher::bind_to_port(port, server_addr, 42, PlayerHandle(handle), 2).unwrap();
+            puncher.set_timeout(Duration::from_secs(5));
+            puncher.set_resend_interval(Duration::from_millis(10));
+            std::thread::spawn(move || puncher.punch())
+        })
+        .collect();
+
+    while punchers.iter().any(|puncher| !puncher.is_finished()) {
+        server.poll().unwrap();
+        std::thread::sleep(Duration::from_millis(1));
+    }
+    let holes: Vec<_> = punchers
+        .into_iter()
+        .map(|puncher| puncher.join().unwrap().unwrap())
+        .collect();
+
+    assert_eq!(holes[0].peers[&PlayerHandle(1)].port(), 8888);
+    assert_eq!(holes[1].peers[&PlayerHandle(0)].port(), 7777);
+
+    let mut sessions: Vec<_> = holes
+        .into_iter()
+        .map(|holes| holes.start_p2p_session(stubs::INPUT_SIZE).unwrap())
+        .collect();
+    for sess in sessions.iter_mut() {
+        assert!(sess.start_session().is_ok());
+    }
+    for _ in 0..50 {
+        for sess in sessions.iter_mut() {
+            sess.poll_remote_clients();
+        }
+    }
+    for sess in sessions.iter() {
+        assert_eq!(sess.current_state(), SessionState::Running);
+    }
+}
+
+#[test]
+#[serial]
+fn test_hole_punching_timeout() {
+    // nobody answers on this port
+    let mut puncher =
+        HolePuncher::bind_to_port(7777, "127.0.0.1:9001", 42, PlayerHandle(0), 2).unwrap();
+    puncher.set_timeout(Duration::from_millis(50));
+    let err = puncher.punch().unwrap_err();
+    assert_eq!(err.kind(), std::io::ErrorKind::TimedOut);
+}
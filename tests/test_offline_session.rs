@@ -0,0 +1,146 @@
+use ggrs::{Frame, GGRSError, GGRSRequest, PlayerHandle, Session, SessionState};
+
+mod stubs;
+
+#[test]
+fn test_create_session() {
+    assert!(ggrs::start_offline_session(2, stubs::INPUT_SIZE).is_ok());
+    assert!(ggrs::start_offline_session(ggrs::MAX_PLAYERS + 1, stubs::INPUT_SIZE).is_err());
+    assert!(ggrs::start_offline_session(2, ggrs::MAX_INPUT_BYTES + 1).is_err());
+}
+
+#[test]
+fn test_advance_frame() {
+    let mut stub = stubs::GameStub::new();
+    let mut sess = ggrs::start_offline_session(2, stubs::INPUT_SIZE).unwrap();
+
+    // offline sessions never run into the prediction threshold
+    for i in 0..200u32 {
+        let inputs = vec![bincode::serialize(&i).unwrap(); 2];
+        let requests = sess.advance_frame(&inputs).unwrap();
+        assert_eq!(requests.len(), 1);
+        stub.handle_requests(requests);
+        assert_eq!(stub.gs.frame, i as i32 + 1);
+        assert_eq!(sess.current_frame(), stub.gs.frame);
+        assert_eq!(sess.confirmed_frame(), stub.gs.frame - 1);
+    }
+
+    let input = bincode::serialize(&0u32).unwrap();
+    assert!(sess.advance_frame(&[input]).is_err());
+}
+
+#[test]
+fn test_frame_delay() {
+    let mut sess = ggrs::start_offline_session(2, stubs::INPUT_SIZE).unwrap();
+    sess.set_frame_delay(2, PlayerHandle(1)).unwrap();
+    assert_eq!(
+        sess.set_frame_delay(2, PlayerHandle(2)),
+        Err(GGRSError::InvalidHandle)
+    );
+
+    for i in 0..10u32 {
+        let inputs = vec![bincode::serialize(&(i + 1)).unwrap(); 2];
+        let requests = sess.advance_frame(&inputs).unwrap();
+        if let GGRSRequest::AdvanceFrame { inputs, .. } = &requests[0] {
+            let p0: u32 = bincode::deserialize(inputs[0].input()).unwrap();
+            let p1: u32 = bincode::deserialize(inputs[1].input()).unwrap();
+            assert_eq!(p0, i + 1);
+            // the delayed player starts with empty inputs
+            assert_eq!(p1, i.saturating_sub(1));
+        } else {
+            panic!("expected an AdvanceFrame request");
+        }
+    }
+}
+
+#[test]
+fn test_simulated_latency() {
+    let mut sess = ggrs::start_offline_session(2, stubs::INPUT_SIZE).unwrap();
+    sess.set_simulated_latency(3);
+    sess.set_frame_delay(1, PlayerHandle(1)).unwrap();
+
+    for i in 0..10u32 {
+        let inputs = vec![bincode::serialize(&(i + 1)).unwrap(); 2];
+        let requests = sess.advance_frame(&inputs).unwrap();
+        if let GGRSRequest::AdvanceFrame { inputs, .. } = &requests[0] {
+            let p0: u32 = bincode::deserialize(inputs[0].input()).unwrap();
+            let p1: u32 = bincode::deserialize(inputs[1].input()).unwrap();
+            // the latency adds to the frame delay
+            assert_eq!(p0, i.saturating_sub(2));
+            assert_eq!(p1, i.saturating_sub(3));
+        } else {
+            panic!("expected an AdvanceFrame request");
+        }
+    }
+}
+
+#[test]
+fn test_offline_session_as_session() {
+    let mut stub = stubs::GameStub::new();
+    let mut sess: Box<dyn Session> =
+        Box::new(ggrs::start_offline_session(2, stubs::INPUT_SIZE).unwrap());
+
+    for i in 0..20u32 {
+        let input = bincode::serialize(&i).unwrap();
+        let requests = sess
+            .advance_frame(&[(PlayerHandle(1), &input), (PlayerHandle(0), &input)])
+            .unwrap();
+        stub.handle_requests(requests);
+    }
+    assert_eq!(sess.current_frame(), 20);
+    assert_eq!(sess.current_state(), SessionState::Running);
+    assert!(sess.events().is_empty());
+    assert!(sess.network_stats(PlayerHandle(0)).is_err());
+
+    let input = bincode::serialize(&0u32).unwrap();
+    assert!(sess.advance_frame(&[(PlayerHandle(0), &input)]).is_err());
+    assert_eq!(
+        sess.advance_frame(&[(PlayerHandle(0), &input), (PlayerHandle(2), &input)])
+            .unwrap_err(),
+        GGRSError::InvalidHandle
+    );
+}
+
+#[test]
+fn test_rewind() {
+    let mut stub = stubs::GameStub::new();
+    let mut sess = ggrs::start_offline_session(2, stubs::INPUT_SIZE).unwrap();
+    sess.set_frame_delay(2, PlayerHandle(1)).unwrap();
+    let input = |i: u32| vec![bincode::serialize(&i).unwrap(); 2];
+
+    // without rewind frames, nothing is saved
+    stub.handle_requests(sess.advance_frame(&input(0)).unwrap());
+    assert!(sess.rewindable_frames().is_empty());
+
+    sess.set_rewind_frames(5);
+    let mut states = Vec::new();
+    for i in 1..10u32 {
+        states.push(stub.gs.state);
+        let requests = sess.advance_frame(&input(i)).unwrap();
+        assert_eq!(requests.len(), 2);
+        stub.handle_requests(requests);
+    }
+    assert_eq!(sess.rewindable_frames(), vec![5, 6, 7, 8, 9]);
+    assert!(sess.rewind(Frame(4)).is_err());
+    assert!(sess.rewind(Frame(10)).is_err());
+
+    // rewinding and replaying the same inputs leads to the same gamestate
+    let state_at_10 = stub.gs.state;
+    stub.handle_requests(sess.rewind(Frame(6)).unwrap());
+    assert_eq!(sess.current_frame(), 6);
+    assert_eq!(stub.gs.frame, 6);
+    assert_eq!(stub.gs.state, states[5]);
+    assert_eq!(sess.rewindable_frames(), vec![5, 6]);
+    for i in 6..10u32 {
+        stub.handle_requests(sess.advance_frame(&input(i)).unwrap());
+    }
+    assert_eq!(stub.gs.state, state_at_10);
+
+    // replaying with different inputs leads somewhere else
+    stub.handle_requests(sess.rewind(Frame(5)).unwrap());
+    for _ in 5..10u32 {
+        stub.handle_requests(sess.advance_frame(&input(1)).unwrap());
+    }
+    assert_eq!(stub.gs.frame, 10);
+    assert_ne!(stub.gs.state, state_at_10);
+}
@@ -0,0 +1,154 @@
+use ggrs::{
+    GGRSError, GGRSEvent, GGRSRequest, ManualTimeSource, PlayerHandle, PlayerType, SessionState,
+};
+use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+use std::time::Duration;
+
+use serial_test::serial;
+
+mod stubs;
+
+#[test]
+#[serial]
+fn test_create_session() {
+    let host_addr = SocketAddr::new(IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)), 7777);
+    assert!(ggrs::start_p2p_relay_session(1, stubs::INPUT_SIZE, 8888, host_addr).is_ok());
+}
+
+#[test]
+#[serial]
+fn test_start_session() {
+    let host_addr = SocketAddr::new(IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)), 7777);
+    let spec_addr = SocketAddr::new(IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)), 9999);
+    let mut relay_sess =
+        ggrs::start_p2p_relay_session(1, stubs::INPUT_SIZE, 8888, host_addr).unwrap();
+    assert_eq!(relay_sess.add_spectator(spec_addr), 1000);
+    assert!(relay_sess.start_session().is_ok());
+    assert!(relay_sess.start_session().is_err());
+    assert_eq!(relay_sess.current_state(), SessionState::Synchronizing);
+    assert!(matches!(
+        relay_sess.set_fps(0),
+        Err(GGRSError::InvalidRequest { .. })
+    ));
+    assert!(relay_sess.set_fps(30).is_ok());
+    assert_eq!(relay_sess.num_spectators(), 1);
+}
+
+#[test]
+#[serial]
+fn test_relay_inputs_to_spectator() {
+    let host_addr = SocketAddr::new(IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)), 7777);
+    let relay_addr = SocketAddr::new(IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)), 8888);
+    let spec_addr = SocketAddr::new(IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)), 9999);
+
+    let mut host_sess = ggrs::start_p2p_session(1, stubs::INPUT_SIZE, 7777).unwrap();
+    let mut relay_sess =
+        ggrs::start_p2p_relay_session(1, stubs::INPUT_SIZE, 8888, host_addr).unwrap();
+    let mut spec_sess =
+        ggrs::start_p2p_spectator_session(1, stubs::INPUT_SIZE, 9999, relay_addr).unwrap();
+
+    host_sess
+        .add_player(PlayerType::Local, PlayerHandle(0))
+        .unwrap();
+    host_sess
+        .add_player(PlayerType::Spectator(relay_addr), PlayerHandle(1))
+        .unwrap();
+    relay_sess.add_spectator(spec_addr);
+
+    host_sess.start_session().unwrap();
+    relay_sess.start_session().unwrap();
+    spec_sess.start_session().unwrap();
+
+    for _ in 0..10 {
+        host_sess.poll_remote_clients();
+        relay_sess.poll_remote_clients();
+        spec_sess.poll_remote_clients();
+    }
+
+    assert_eq!(host_sess.current_state(), SessionState::Running);
+    assert_eq!(relay_sess.current_state(), SessionState::Running);
+    assert_eq!(spec_sess.current_state(), SessionState::Running);
+
+    let mut received_frames = Vec::new();
+    for i in 0..50u32 {
+        let serialized_input = bincode::serialize(&i).unwrap();
+        // with a single local player, the host never rolls back, so we can skip the requests
+        host_sess
+            .advance_frame(PlayerHandle(0), &serialized_input)
+            .unwrap();
+
+        relay_sess.poll_remote_clients();
+
+        if let Ok(requests) = spec_sess.advance_frame() {
+            for request in requests {
+                if let GGRSRequest::AdvanceFrame { inputs, .. } = request {
+                    let input: u32 = bincode::deserialize(inputs[0].input()).unwrap();
+                    assert_eq!(input, inputs[0].frame.0 as u32);
+                    received_frames.push(inputs[0].frame);
+                }
+            }
+        }
+    }
+
+    assert!(relay_sess.last_received_frame() > 0);
+    assert!(!received_frames.is_empty());
+    for (i, frame) in received_frames.iter().enumerate() {
+        assert_eq!(*frame, i as i32);
+    }
+}
+
+#[test]
+#[serial]
+fn test_drop_disconnected_spectator() {
+    let host_addr = SocketAddr::new(IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)), 7777);
+    let relay_addr = SocketAddr::new(IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)), 8888);
+    let spec_addr = SocketAddr::new(IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)), 9999);
+
+    let mut host_sess = ggrs::start_p2p_session(1, stubs::INPUT_SIZE, 7777).unwrap();
+    let mut relay_sess =
+        ggrs::start_p2p_relay_session(1, stubs::INPUT_SIZE, 8888, host_addr).unwrap();
+    let mut spec_sess =
+        ggrs::start_p2p_spectator_session(1, stubs::INPUT_SIZE, 9999, relay_addr).unwrap();
+    let time = ManualTimeSource::new();
+    relay_sess.set_time_source(time.clone()).unwrap();
+
+    host_sess
+        .add_player(PlayerType::Local, PlayerHandle(0))
+        .unwrap();
+    host_sess
+        .add_player(PlayerType::Spectator(relay_addr), PlayerHandle(1))
+        .unwrap();
+    let spec_handle = relay_sess.add_spectator(spec_addr);
+
+    host_sess.start_session().unwrap();
+    relay_sess.start_session().unwrap();
+    spec_sess.start_session().unwrap();
+
+    for _ in 0..10 {
+        host_sess.poll_remote_clients();
+        relay_sess.poll_remote_clients();
+        spec_sess.poll_remote_clients();
+    }
+    assert_eq!(spec_sess.current_state(), SessionState::Running);
+    assert_eq!(relay_sess.num_spectators(), 1);
+
+    // the spectator goes away without a word, so the relay disconnects it after the timeout
+    drop(spec_sess);
+    time.advance(Duration::from_secs(3));
+    host_sess.poll_remote_clients();
+    relay_sess.poll_remote_clients();
+    assert!(relay_sess.events().any(|event| event
+        == GGRSEvent::Disconnected {
+            player_handle: spec_handle
+        }));
+    assert_eq!(relay_sess.num_spectators(), 0);
+
+    // once the endpoint has shut down, the relay forgets the spectator
+    time.advance(Duration::from_secs(6));
+    host_sess.poll_remote_clients();
+    relay_sess.poll_remote_clients();
+    assert!(matches!(
+        relay_sess.spectator_network_stats(spec_handle),
+        Err(GGRSError::InvalidRequest { .. })
+    ));
+}
@@ -1,5 +1,12 @@
-use ggrs::{PlayerType, SessionState};
+use ggrs::{
+    DesyncPolicy, Frame, GGRSError, GGRSEvent, GGRSRequest, ManualTimeSource, NonBlockingSocket,
+    PendingOutputPolicy, PlayerHandle, PlayerType, SessionState, UdpMessage, UdpNonBlockingSocket,
+};
+use std::collections::HashMap;
 use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
 
 use serial_test::serial;
 
@@ -16,14 +23,85 @@ fn test_create_session() {
 fn test_add_player() {
     let mut sess = ggrs::start_p2p_session(2, stubs::INPUT_SIZE, 7777).unwrap();
     let addr = SocketAddr::new(IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)), 8080);
-    assert!(sess.add_player(PlayerType::Local, 0).is_ok());
-    assert!(sess.add_player(PlayerType::Remote(addr), 1).is_ok());
-    assert!(sess.add_player(PlayerType::Remote(addr), 1).is_err()); // handle already registered
-    assert!(sess.add_player(PlayerType::Remote(addr), 2).is_err()); // invalid handle
-    assert!(sess.add_player(PlayerType::Spectator(addr), 2).is_ok());
-    assert!(sess.add_player(PlayerType::Spectator(addr), 2).is_err()); // specatator handle already registered
+    assert!(sess.add_player(PlayerType::Local, PlayerHandle(0)).is_ok());
+    assert!(sess
+        .add_player(PlayerType::Remote(addr), PlayerHandle(1))
+        .is_ok());
+    assert!(sess
+        .add_player(PlayerType::Remote(addr), PlayerHandle(1))
+        .is_err()); // handle already registered
+    assert!(sess
+        .add_player(PlayerType::Remote(addr), PlayerHandle(2))
+        .is_err()); // invalid handle
+    assert!(sess
+        .add_player(PlayerType::Spectator(addr), PlayerHandle(2))
+        .is_ok());
+    assert!(sess
+        .add_player(PlayerType::Spectator(addr), PlayerHandle(2))
+        .is_err()); // specatator handle already registered
     assert!(sess.start_session().is_ok());
-    assert!(sess.add_player(ggrs::PlayerType::Remote(addr), 1).is_err()); // cannot add player after starting
+    assert!(sess
+        .add_player(ggrs::PlayerType::Remote(addr), PlayerHandle(1))
+        .is_err()); // cannot add player after starting
+}
+
+#[test]
+#[serial]
+fn test_players() {
+    let mut sess = ggrs::start_p2p_session(3, stubs::INPUT_SIZE, 7777).unwrap();
+    let addr1 = SocketAddr::new(IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)), 8080);
+    let addr2 = SocketAddr::new(IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)), 8081);
+    let spectator_addr = SocketAddr::new(IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)), 8082);
+    assert!(sess
+        .add_player(PlayerType::Spectator(spectator_addr), PlayerHandle(0))
+        .is_ok());
+    assert!(sess
+        .add_player(PlayerType::Remote(addr2), PlayerHandle(2))
+        .is_ok());
+    assert!(sess.add_player(PlayerType::Local, PlayerHandle(0)).is_ok());
+    assert!(sess
+        .add_player(PlayerType::Remote(addr1), PlayerHandle(1))
+        .is_ok());
+
+    // the players are ordered by handle, spectators get handles from 1000 upwards
+    let players: Vec<_> = sess.players().collect();
+    assert_eq!(
+        players,
+        vec![
+            (PlayerHandle(0), PlayerType::Local),
+            (PlayerHandle(1), PlayerType::Remote(addr1)),
+            (PlayerHandle(2), PlayerType::Remote(addr2)),
+            (PlayerHandle(1000), PlayerType::Spectator(spectator_addr)),
+        ]
+    );
+    assert_eq!(sess.address_for_handle(PlayerHandle(2)), Some(addr2));
+    assert_eq!(
+        sess.address_for_handle(PlayerHandle(1000)),
+        Some(spectator_addr)
+    );
+    assert_eq!(sess.address_for_handle(PlayerHandle(0)), None);
+    assert_eq!(sess.address_for_handle(PlayerHandle(5)), None);
+    assert_eq!(sess.handle_for_address(addr1), Some(PlayerHandle(1)));
+    assert_eq!(
+        sess.handle_for_address(spectator_addr),
+        Some(PlayerHandle(1000))
+    );
+    let unknown_addr = SocketAddr::new(IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)), 9999);
+    assert_eq!(sess.handle_for_address(unknown_addr), None);
+}
+
+#[test]
+fn test_resolve_player_address() {
+    let addr = SocketAddr::new(IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)), 8080);
+    assert_eq!(
+        PlayerType::remote("127.0.0.1:8080").unwrap(),
+        PlayerType::Remote(addr)
+    );
+    assert_eq!(
+        PlayerType::spectator(("127.0.0.1", 8080)).unwrap(),
+        PlayerType::Spectator(addr)
+    );
+    assert!(PlayerType::remote("not an address").is_err());
 }
 
 #[test]
@@ -31,11 +109,88 @@ fn test_add_player() {
 fn test_start_session() {
     let mut sess = ggrs::start_p2p_session(2, stubs::INPUT_SIZE, 7777).unwrap();
     let addr = SocketAddr::new(IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)), 8080);
-    assert!(sess.add_player(ggrs::PlayerType::Local, 0).is_ok());
+    assert!(sess
+        .add_player(ggrs::PlayerType::Local, PlayerHandle(0))
+        .is_ok());
     assert!(sess.start_session().is_err()); // not enough players
-    assert!(sess.add_player(ggrs::PlayerType::Remote(addr), 1).is_ok());
+    assert!(sess
+        .add_player(ggrs::PlayerType::Remote(addr), PlayerHandle(1))
+        .is_ok());
+    assert!(sess
+        .set_disconnected_input(ggrs::FixedInput::new(vec![0; stubs::INPUT_SIZE]))
+        .is_ok());
+    assert!(sess
+        .set_input_tolerance(ggrs::ByteTolerance::new(vec![2, 0, 0, 0]))
+        .is_ok());
+    assert!(sess.clear_input_tolerance().is_ok());
     assert!(sess.start_session().is_ok()); // works
     assert!(sess.start_session().is_err()); // cannot start twice
+    assert!(sess.set_disconnected_input(ggrs::RepeatLastInput).is_err()); // cannot change after starting
+    assert!(sess.clear_input_tolerance().is_err());
+}
+
+#[test]
+#[serial]
+fn test_start_session_reports_all_errors() {
+    let mut sess = ggrs::start_p2p_session(3, stubs::INPUT_SIZE, 7777).unwrap();
+    assert!(sess.add_player(PlayerType::Local, PlayerHandle(0)).is_ok());
+    assert!(sess.set_fps(0).is_ok()); // only validated when starting
+    match sess.start_session() {
+        Err(GGRSError::InvalidConfiguration { errors }) => assert_eq!(errors.len(), 3), // fps and two missing players
+        _ => panic!("expected an invalid configuration"),
+    }
+    assert!(sess.current_state() == SessionState::Initializing);
+}
+
+#[test]
+#[serial]
+fn test_set_fps_after_start() {
+    let mut sess = ggrs::start_p2p_session(1, stubs::INPUT_SIZE, 7777).unwrap();
+    assert!(sess.add_player(PlayerType::Local, PlayerHandle(0)).is_ok());
+    assert!(sess.start_session().is_ok());
+    assert!(matches!(
+        sess.set_fps(0),
+        Err(GGRSError::InvalidRequest { .. })
+    ));
+    assert!(sess.set_fps(30).is_ok());
+}
+
+#[test]
+#[serial]
+fn test_time_sync_configuration() {
+    let mut sess = ggrs::start_p2p_session(1, stubs::INPUT_SIZE, 7777).unwrap();
+    assert!(sess.add_player(PlayerType::Local, PlayerHandle(0)).is_ok());
+    sess.set_time_sync_window(0);
+    sess.set_wait_recommendation_limits(5, 2);
+    match sess.start_session() {
+        Err(GGRSError::InvalidConfiguration { errors }) => assert_eq!(errors.len(), 2),
+        _ => panic!("expected an invalid configuration"),
+    }
+
+    sess.set_time_sync_window(10);
+    sess.set_wait_recommendation_limits(1, 4);
+    assert!(sess.start_session().is_ok());
+}
+
+#[test]
+#[serial]
+fn test_sessions_can_be_moved_to_another_thread() {
+    fn assert_send<T: Send>() {}
+    assert_send::<ggrs::P2PSpectatorSession>();
+    assert_send::<ggrs::P2PRelaySession>();
+    assert_send::<ggrs::SyncTestSession>();
+    assert_send::<ggrs::OfflineSession>();
+
+    let mut sess = ggrs::start_p2p_session(1, stubs::INPUT_SIZE, 7777).unwrap();
+    sess.set_event_handler(|_| {});
+    let state = std::thread::spawn(move || {
+        sess.add_player(PlayerType::Local, PlayerHandle(0)).unwrap();
+        sess.start_session().unwrap();
+        sess.current_state()
+    })
+    .join()
+    .unwrap();
+    assert_eq!(state, SessionState::Running);
 }
 
 #[test]
@@ -43,12 +198,206 @@ fn test_start_session() {
 fn test_disconnect_player() {
     let mut sess = ggrs::start_p2p_session(2, stubs::INPUT_SIZE, 7777).unwrap();
     let addr = SocketAddr::new(IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)), 8080);
-    assert!(sess.add_player(ggrs::PlayerType::Local, 0).is_ok());
-    assert!(sess.add_player(ggrs::PlayerType::Remote(addr), 1).is_ok());
+    assert!(sess
+        .add_player(ggrs::PlayerType::Local, PlayerHandle(0))
+        .is_ok());
+    assert!(sess
+        .add_player(ggrs::PlayerType::Remote(addr), PlayerHandle(1))
+        .is_ok());
+    assert!(sess.start_session().is_ok());
+    assert!(sess.disconnect_player(PlayerHandle(0)).is_err()); // for now, local players cannot be disconnected
+    assert!(sess.disconnect_player(PlayerHandle(1)).is_ok());
+    assert!(sess.disconnect_player(PlayerHandle(1)).is_err()); // already disconnected
+}
+
+#[test]
+#[serial]
+fn test_malformed_message() {
+    let mut sess = ggrs::start_p2p_session(2, stubs::INPUT_SIZE, 7777).unwrap();
+    let addr = SocketAddr::new(IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)), 8888);
+    assert!(sess
+        .add_player(ggrs::PlayerType::Local, PlayerHandle(0))
+        .is_ok());
+    assert!(sess
+        .add_player(ggrs::PlayerType::Remote(addr), PlayerHandle(1))
+        .is_ok());
+    assert!(sess.start_session().is_ok());
+
+    // a well-formed input message that claims the session has no players
+    let mut bytes = vec![ggrs::WIRE_FORMAT_VERSION, 0, 0, 0, 0, 0, 0, 0, 0, 2, 0];
+    bytes.extend_from_slice(&[0; 8]);
+    bytes.extend_from_slice(&100u16.to_le_bytes()); // timescale
+    bytes.extend_from_slice(&[0; 5]);
+    let socket = std::net::UdpSocket::bind(addr).unwrap();
+    socket.send_to(&bytes, "127.0.0.1:7777").unwrap();
+    std::thread::sleep(std::time::Duration::from_millis(10));
+
+    sess.poll_remote_clients();
+    assert!(sess.events().any(|event| event
+        == GGRSEvent::InvalidMessage {
+            player_handle: PlayerHandle(1)
+        }));
+}
+
+#[test]
+#[serial]
+fn test_invalid_fps_change() {
+    let mut sess = ggrs::start_p2p_session(2, stubs::INPUT_SIZE, 7777).unwrap();
+    let addr = SocketAddr::new(IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)), 8888);
+    sess.add_player(PlayerType::Local, PlayerHandle(0)).unwrap();
+    sess.add_player(PlayerType::Remote(addr), PlayerHandle(1))
+        .unwrap();
+    sess.start_session().unwrap();
+    let socket = std::net::UdpSocket::bind(addr).unwrap();
+
+    // an input message without inputs that changes the frame rate at the given frame, which is encoded as a zigzag varint
+    let fps_change = |zigzag_frame: u8, fps: u32| {
+        let mut bytes = vec![ggrs::WIRE_FORMAT_VERSION, 0, 0, 0, 0, 0, 0, 0, 0, 2];
+        bytes.extend_from_slice(&[2, 0, 1, 0, 1, 0, 1, 1, zigzag_frame]);
+        bytes.extend_from_slice(&fps.to_le_bytes());
+        bytes.extend_from_slice(&100u16.to_le_bytes());
+        bytes.extend_from_slice(&[0, 1, 1]);
+        bytes.extend_from_slice(&0x4000u16.to_le_bytes()); // an empty keyframe
+        bytes
+    };
+
+    // no rate, an absurd rate and no frame, followed by a valid change at frame 5
+    for (bytes, invalid) in [
+        (fps_change(10, 0), true),
+        (fps_change(10, 1_000_000), true),
+        (fps_change(1, 60), true),
+        (fps_change(10, 30), false),
+    ] {
+        socket.send_to(&bytes, "127.0.0.1:7777").unwrap();
+        std::thread::sleep(std::time::Duration::from_millis(10));
+        sess.poll_remote_clients();
+        let reported = sess.events().any(|event| {
+            event
+                == GGRSEvent::InvalidMessage {
+                    player_handle: PlayerHandle(1),
+                }
+        });
+        assert_eq!(reported, invalid);
+    }
+}
+
+#[test]
+#[serial]
+fn test_rate_limit() {
+    let mut sess = ggrs::start_p2p_session(2, stubs::INPUT_SIZE, 7777).unwrap();
+    let addr = SocketAddr::new(IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)), 8888);
+    assert!(sess
+        .add_player(ggrs::PlayerType::Local, PlayerHandle(0))
+        .is_ok());
+    assert!(sess
+        .add_player(ggrs::PlayerType::Remote(addr), PlayerHandle(1))
+        .is_ok());
+    sess.set_rate_limit(5, 0);
     assert!(sess.start_session().is_ok());
-    assert!(sess.disconnect_player(0).is_err()); // for now, local players cannot be disconnected
-    assert!(sess.disconnect_player(1).is_ok());
-    assert!(sess.disconnect_player(1).is_err()); // already disconnected
+
+    // flood the session with keep alive messages
+    let keep_alive = [ggrs::WIRE_FORMAT_VERSION, 0, 0, 0, 0, 0, 0, 0, 0, 6];
+    let socket = std::net::UdpSocket::bind(addr).unwrap();
+    for _ in 0..20 {
+        socket.send_to(&keep_alive, "127.0.0.1:7777").unwrap();
+    }
+    std::thread::sleep(std::time::Duration::from_millis(10));
+
+    sess.poll_remote_clients();
+    let rate_limit_events = sess
+        .events()
+        .filter(|event| {
+            *event
+                == GGRSEvent::RateLimitExceeded {
+                    player_handle: PlayerHandle(1),
+                }
+        })
+        .count();
+    assert_eq!(rate_limit_events, 1);
+}
+
+#[test]
+#[serial]
+fn test_bandwidth_limit() {
+    let mut stub1 = stubs::GameStub::new();
+    let mut stub2 = stubs::GameStub::new();
+    let mut sess1 = ggrs::start_p2p_session(2, stubs::INPUT_SIZE, 7777).unwrap();
+    let mut sess2 = ggrs::start_p2p_session(2, stubs::INPUT_SIZE, 8888).unwrap();
+    let addr1 = SocketAddr::new(IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)), 7777);
+    let addr2 = SocketAddr::new(IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)), 8888);
+    let bytes_per_second = 6000;
+
+    sess1.set_bandwidth_limit(bytes_per_second);
+    assert!(sess1.add_player(PlayerType::Local, PlayerHandle(0)).is_ok());
+    assert!(sess1
+        .add_player(PlayerType::Remote(addr2), PlayerHandle(1))
+        .is_ok());
+    assert!(sess1.start_session().is_ok());
+    assert!(sess2.add_player(PlayerType::Local, PlayerHandle(1)).is_ok());
+    assert!(sess2
+        .add_player(PlayerType::Remote(addr1), PlayerHandle(0))
+        .is_ok());
+    assert!(sess2.start_session().is_ok());
+    let start = std::time::Instant::now();
+
+    for _ in 0..10 {
+        sess1.poll_remote_clients();
+        sess2.poll_remote_clients();
+    }
+    assert!(sess1.current_state() == SessionState::Running);
+
+    for i in 0..20 {
+        let input = bincode::serialize(&(i as u32)).unwrap();
+        // flood the session with extra polls, which would queue more messages than the budget allows
+        for _ in 0..5 {
+            sess1.poll_remote_clients();
+        }
+        stub1.handle_requests(sess1.advance_frame(PlayerHandle(0), &input).unwrap());
+        stub2.handle_requests(sess2.advance_frame(PlayerHandle(1), &input).unwrap());
+        std::thread::sleep(std::time::Duration::from_millis(10));
+    }
+
+    // the initial burst and everything accumulated since
+    let stats = sess1.socket_stats().unwrap();
+    let sent = stats.bytes_sent + 28 * stats.packets_sent;
+    let allowed = 1500 + start.elapsed().as_millis() as u64 * bytes_per_second as u64 / 1000;
+    assert!(sent <= allowed);
+    assert_eq!(stub1.gs.frame, 20);
+}
+
+#[test]
+#[serial]
+fn test_shared_seed() {
+    let mut sess1 = ggrs::start_p2p_session(2, stubs::INPUT_SIZE, 7777).unwrap();
+    let mut sess2 = ggrs::start_p2p_session(2, stubs::INPUT_SIZE, 8888).unwrap();
+    let addr1 = SocketAddr::new(IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)), 7777);
+    let addr2 = SocketAddr::new(IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)), 8888);
+
+    sess1
+        .add_player(PlayerType::Local, PlayerHandle(0))
+        .unwrap();
+    sess1
+        .add_player(PlayerType::Remote(addr2), PlayerHandle(1))
+        .unwrap();
+    sess1.start_session().unwrap();
+    sess2
+        .add_player(PlayerType::Local, PlayerHandle(1))
+        .unwrap();
+    sess2
+        .add_player(PlayerType::Remote(addr1), PlayerHandle(0))
+        .unwrap();
+    sess2.start_session().unwrap();
+    assert_eq!(sess1.shared_seed(), None);
+
+    for _ in 0..10 {
+        sess1.poll_remote_clients();
+        sess2.poll_remote_clients();
+    }
+
+    // both peers derive the same seed from their contributions
+    assert!(sess1.current_state() == SessionState::Running);
+    assert!(sess1.shared_seed().is_some());
+    assert_eq!(sess1.shared_seed(), sess2.shared_seed());
 }
 
 #[test]
@@ -62,12 +411,20 @@ fn test_synchronize_p2p_sessions() {
     assert!(sess1.current_state() == SessionState::Initializing);
     assert!(sess2.current_state() == SessionState::Initializing);
 
-    assert!(sess1.add_player(ggrs::PlayerType::Local, 0).is_ok());
-    assert!(sess1.add_player(ggrs::PlayerType::Remote(addr2), 1).is_ok());
+    assert!(sess1
+        .add_player(ggrs::PlayerType::Local, PlayerHandle(0))
+        .is_ok());
+    assert!(sess1
+        .add_player(ggrs::PlayerType::Remote(addr2), PlayerHandle(1))
+        .is_ok());
     assert!(sess1.start_session().is_ok());
 
-    assert!(sess2.add_player(ggrs::PlayerType::Local, 1).is_ok());
-    assert!(sess2.add_player(ggrs::PlayerType::Remote(addr1), 0).is_ok());
+    assert!(sess2
+        .add_player(ggrs::PlayerType::Local, PlayerHandle(1))
+        .is_ok());
+    assert!(sess2
+        .add_player(ggrs::PlayerType::Remote(addr1), PlayerHandle(0))
+        .is_ok());
     assert!(sess2.start_session().is_ok());
 
     assert!(sess1.current_state() == SessionState::Synchronizing);
@@ -80,6 +437,122 @@ fn test_synchronize_p2p_sessions() {
 
     assert!(sess1.current_state() == SessionState::Running);
     assert!(sess2.current_state() == SessionState::Running);
+
+    let stats = sess1.socket_stats().unwrap();
+    assert!(stats.packets_sent > 0 && stats.packets_received > 0);
+    assert_eq!(stats.send_errors + stats.receive_errors, 0);
+    assert!(!sess1
+        .events()
+        .any(|event| matches!(event, GGRSEvent::SocketError { .. })));
+
+    // only remote players have a frame advantage
+    let advantages = sess1.frame_advantages();
+    assert_eq!(advantages.len(), 1);
+    assert_eq!(advantages[0].player_handle, 1);
+    assert_eq!(advantages[0].recommended_wait, 0);
+}
+
+#[test]
+#[serial]
+fn test_connection_token() {
+    let addr1 = SocketAddr::new(IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)), 7777);
+    let addr2 = SocketAddr::new(IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)), 8888);
+
+    for (token1, token2, synchronized) in [(b"open", b"open", true), (b"open", b"shut", false)] {
+        let mut sess1 = ggrs::start_p2p_session(2, stubs::INPUT_SIZE, 7777).unwrap();
+        let mut sess2 = ggrs::start_p2p_session(2, stubs::INPUT_SIZE, 8888).unwrap();
+        sess1.set_connection_token(token1);
+        sess2.set_connection_token(token2);
+
+        assert!(sess1
+            .add_player(ggrs::PlayerType::Local, PlayerHandle(0))
+            .is_ok());
+        assert!(sess1
+            .add_player(ggrs::PlayerType::Remote(addr2), PlayerHandle(1))
+            .is_ok());
+        assert!(sess1.start_session().is_ok());
+        assert!(sess2
+            .add_player(ggrs::PlayerType::Local, PlayerHandle(1))
+            .is_ok());
+        assert!(sess2
+            .add_player(ggrs::PlayerType::Remote(addr1), PlayerHandle(0))
+            .is_ok());
+        assert!(sess2.start_session().is_ok());
+
+        for _ in 0..10 {
+            sess1.poll_remote_clients();
+            sess2.poll_remote_clients();
+        }
+
+        assert_eq!(sess1.current_state() == SessionState::Running, synchronized);
+        assert_eq!(sess2.current_state() == SessionState::Running, synchronized);
+    }
+}
+
+#[test]
+#[serial]
+fn test_network_profile() {
+    let addr = SocketAddr::new(IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)), 8888);
+    let mut sess = ggrs::start_p2p_session(2, stubs::INPUT_SIZE, 7777).unwrap();
+
+    // the frame delay is applied to the local player, whether it is added before or after setting the profile
+    sess.set_network_profile(ggrs::NetworkProfile::Mobile);
+    assert!(sess.add_player(PlayerType::Local, PlayerHandle(0)).is_ok());
+    assert!(sess
+        .add_player(PlayerType::Remote(addr), PlayerHandle(1))
+        .is_ok());
+    assert_eq!(sess.input_queue_info()[0].frame_delay, 3);
+    sess.set_network_profile(ggrs::NetworkProfile::Lan);
+    assert_eq!(sess.input_queue_info()[0].frame_delay, 0);
+    assert_eq!(sess.input_queue_info()[1].frame_delay, 0);
+
+    // all profiles are valid configurations
+    for profile in [
+        ggrs::NetworkProfile::Lan,
+        ggrs::NetworkProfile::Broadband,
+        ggrs::NetworkProfile::Mobile,
+    ] {
+        sess.set_network_profile(profile);
+    }
+    assert!(sess.start_session().is_ok());
+}
+
+#[test]
+#[serial]
+fn test_session_id() {
+    let addr1 = SocketAddr::new(IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)), 7777);
+    let addr2 = SocketAddr::new(IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)), 8888);
+
+    // messages of another match are dropped, e.g. from a peer that is still in the previous match
+    for (session_id1, session_id2, synchronized) in [(3, 3, true), (3, 4, false)] {
+        let mut sess1 = ggrs::start_p2p_session(2, stubs::INPUT_SIZE, 7777).unwrap();
+        let mut sess2 = ggrs::start_p2p_session(2, stubs::INPUT_SIZE, 8888).unwrap();
+        sess1.set_session_id(session_id1);
+
+        assert!(sess1
+            .add_player(ggrs::PlayerType::Local, PlayerHandle(0))
+            .is_ok());
+        assert!(sess1
+            .add_player(ggrs::PlayerType::Remote(addr2), PlayerHandle(1))
+            .is_ok());
+        assert!(sess1.start_session().is_ok());
+        assert!(sess2
+            .add_player(ggrs::PlayerType::Local, PlayerHandle(1))
+            .is_ok());
+        assert!(sess2
+            .add_player(ggrs::PlayerType::Remote(addr1), PlayerHandle(0))
+            .is_ok());
+        sess2.set_session_id(session_id2);
+        assert!(sess2.start_session().is_ok());
+
+        for _ in 0..10 {
+            sess1.poll_remote_clients();
+            sess2.poll_remote_clients();
+        }
+
+        assert_eq!(sess1.current_state() == SessionState::Running, synchronized);
+        assert_eq!(sess2.current_state() == SessionState::Running, synchronized);
+    }
 }
 
 #[test]
@@ -95,12 +568,20 @@ fn test_advance_frame_p2p_sessions() {
     assert!(sess1.current_state() == SessionState::Initializing);
     assert!(sess2.current_state() == SessionState::Initializing);
 
-    assert!(sess1.add_player(ggrs::PlayerType::Local, 0).is_ok());
-    assert!(sess1.add_player(ggrs::PlayerType::Remote(addr2), 1).is_ok());
+    assert!(sess1
+        .add_player(ggrs::PlayerType::Local, PlayerHandle(0))
+        .is_ok());
+    assert!(sess1
+        .add_player(ggrs::PlayerType::Remote(addr2), PlayerHandle(1))
+        .is_ok());
     assert!(sess1.start_session().is_ok());
 
-    assert!(sess2.add_player(ggrs::PlayerType::Local, 1).is_ok());
-    assert!(sess2.add_player(ggrs::PlayerType::Remote(addr1), 0).is_ok());
+    assert!(sess2
+        .add_player(ggrs::PlayerType::Local, PlayerHandle(1))
+        .is_ok());
+    assert!(sess2
+        .add_player(ggrs::PlayerType::Remote(addr1), PlayerHandle(0))
+        .is_ok());
     assert!(sess2.start_session().is_ok());
 
     assert!(sess1.current_state() == SessionState::Synchronizing);
@@ -114,6 +595,9 @@ fn test_advance_frame_p2p_sessions() {
     assert!(sess1.current_state() == SessionState::Running);
     assert!(sess2.current_state() == SessionState::Running);
 
+    assert!(sess1.input_stats().is_none());
+    sess1.set_input_analysis(true);
+
     let reps = 10;
     for i in 0..reps {
         let input: u32 = i;
@@ -122,13 +606,2619 @@ fn test_advance_frame_p2p_sessions() {
         sess1.poll_remote_clients();
         sess2.poll_remote_clients();
 
-        let requests1 = sess1.advance_frame(0, &serialized_input).unwrap();
+        let requests1 = sess1
+            .advance_frame(PlayerHandle(0), &serialized_input)
+            .unwrap();
         stub1.handle_requests(requests1);
-        let requests2 = sess2.advance_frame(1, &serialized_input).unwrap();
+        let requests2 = sess2
+            .advance_frame(PlayerHandle(1), &serialized_input)
+            .unwrap();
         stub2.handle_requests(requests2);
 
         // gamestate evolves
         assert_eq!(stub1.gs.frame, i as i32 + 1);
         assert_eq!(stub2.gs.frame, i as i32 + 1);
     }
+
+    // the local inputs are all confirmed, the remote inputs are confirmed up to what has arrived
+    let info = sess1.input_queue_info();
+    assert_eq!(info.len(), 2);
+    assert_eq!(info[0].last_confirmed_frame, reps as i32 - 1);
+    assert!(info[0].is_confirmed(Frame(reps as i64 - 1)));
+    assert!(info[1].last_confirmed_frame < reps as i32);
+
+    // the confirmed frame trails the current frame
+    assert_eq!(sess1.current_frame(), reps as i32);
+    assert!(sess1.confirmed_frame() < sess1.current_frame());
+    assert!(sess1.confirmed_frame() <= info[1].last_confirmed_frame);
+
+    // only the lowest byte of the counting input changes
+    let stats = sess1.input_stats().unwrap();
+    assert_eq!(stats.inputs_analyzed, reps as usize);
+    assert_eq!(stats.byte_changes[0], reps as usize - 1);
+    assert_eq!(stats.most_changed_bytes()[0], 0);
+    assert!(stats.byte_changes[1..].iter().all(|&changes| changes == 0));
+    assert!(stats.packets_sent >= reps as usize);
+    assert!(stats.average_encoded_size > 0.0);
+}
+
+#[test]
+#[serial]
+fn test_confirmed_inputs_p2p_sessions() {
+    let mut stub1 = stubs::GameStub::new();
+    let mut stub2 = stubs::GameStub::new();
+    let mut sess1 = ggrs::start_p2p_session(2, stubs::INPUT_SIZE, 7777).unwrap();
+    let mut sess2 = ggrs::start_p2p_session(2, stubs::INPUT_SIZE, 8888).unwrap();
+    let addr1 = SocketAddr::new(IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)), 7777);
+    let addr2 = SocketAddr::new(IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)), 8888);
+
+    sess1.set_confirmed_inputs_enabled(true);
+    assert!(sess1
+        .add_player(ggrs::PlayerType::Local, PlayerHandle(0))
+        .is_ok());
+    assert!(sess1
+        .add_player(ggrs::PlayerType::Remote(addr2), PlayerHandle(1))
+        .is_ok());
+    assert!(sess1.start_session().is_ok());
+
+    sess2.set_input_history_length(5);
+    assert!(sess2
+        .add_player(ggrs::PlayerType::Local, PlayerHandle(1))
+        .is_ok());
+    assert!(sess2
+        .add_player(ggrs::PlayerType::Remote(addr1), PlayerHandle(0))
+        .is_ok());
+    assert!(sess2.start_session().is_ok());
+
+    for _ in 0..10 {
+        sess1.poll_remote_clients();
+        sess2.poll_remote_clients();
+    }
+
+    let mut confirmed_frames = Vec::new();
+    for i in 0..20 {
+        let input: u32 = i;
+        let serialized_input = bincode::serialize(&input).unwrap();
+
+        sess1.poll_remote_clients();
+        sess2.poll_remote_clients();
+
+        let requests1 = sess1
+            .advance_frame(PlayerHandle(0), &serialized_input)
+            .unwrap();
+        stub1.handle_requests(requests1);
+        let requests2 = sess2
+            .advance_frame(PlayerHandle(1), &serialized_input)
+            .unwrap();
+        stub2.handle_requests(requests2);
+
+        for (frame, inputs) in sess1.confirmed_inputs() {
+            assert_eq!(inputs.len(), 2);
+            let p0_input: u32 = bincode::deserialize(inputs[0].input()).unwrap();
+            assert_eq!(p0_input, frame.0 as u32);
+            confirmed_frames.push(frame);
+        }
+    }
+
+    // every confirmed frame is reported exactly once and in order
+    assert!(!confirmed_frames.is_empty());
+    for (i, frame) in confirmed_frames.iter().enumerate() {
+        assert_eq!(*frame, i as i32);
+    }
+
+    // the input history keeps the most recent confirmed frames
+    let history: Vec<Frame> = sess2.input_history().map(|(frame, _)| *frame).collect();
+    assert_eq!(history.len(), 5);
+    assert_eq!(*history.last().unwrap(), sess2.confirmed_frame());
+    for (frame, inputs) in sess2.input_history() {
+        let p1_input: u32 = bincode::deserialize(inputs[1].input()).unwrap();
+        assert_eq!(p1_input, frame.0 as u32);
+    }
+    assert_eq!(sess1.input_history().len(), 0);
+}
+
+#[test]
+#[serial]
+fn test_invalid_input_disconnects_player() {
+    let mut stub1 = stubs::GameStub::new();
+    let mut stub2 = stubs::GameStub::new();
+    let mut sess1 = ggrs::start_p2p_session(2, stubs::INPUT_SIZE, 7777).unwrap();
+    let mut sess2 = ggrs::start_p2p_session(2, stubs::INPUT_SIZE, 8888).unwrap();
+    let addr1 = SocketAddr::new(IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)), 7777);
+    let addr2 = SocketAddr::new(IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)), 8888);
+
+    // inputs larger than 5 are considered cheating
+    sess1.set_input_validator(|_, _, input| bincode::deserialize::<u32>(input).unwrap() <= 5);
+    sess1.set_disconnect_on_invalid_input(true);
+    assert!(sess1
+        .add_player(ggrs::PlayerType::Local, PlayerHandle(0))
+        .is_ok());
+    assert!(sess1
+        .add_player(ggrs::PlayerType::Remote(addr2), PlayerHandle(1))
+        .is_ok());
+    assert!(sess1.start_session().is_ok());
+
+    assert!(sess2
+        .add_player(ggrs::PlayerType::Local, PlayerHandle(1))
+        .is_ok());
+    assert!(sess2
+        .add_player(ggrs::PlayerType::Remote(addr1), PlayerHandle(0))
+        .is_ok());
+    assert!(sess2.start_session().is_ok());
+
+    for _ in 0..10 {
+        sess1.poll_remote_clients();
+        sess2.poll_remote_clients();
+    }
+
+    for i in 0..20 {
+        let input: u32 = i;
+        let serialized_input = bincode::serialize(&input).unwrap();
+
+        sess1.poll_remote_clients();
+        sess2.poll_remote_clients();
+
+        if let Ok(requests1) = sess1.advance_frame(PlayerHandle(0), &serialized_input) {
+            stub1.handle_requests(requests1);
+        }
+        if let Ok(requests2) = sess2.advance_frame(PlayerHandle(1), &serialized_input) {
+            stub2.handle_requests(requests2);
+        }
+    }
+
+    let events: Vec<GGRSEvent> = sess1.events().collect();
+    assert!(events.contains(&GGRSEvent::InvalidInput {
+        player_handle: PlayerHandle(1),
+        frame: Frame(6)
+    }));
+    assert!(events.contains(&GGRSEvent::Disconnected {
+        player_handle: PlayerHandle(1)
+    }));
+}
+
+#[test]
+#[serial]
+fn test_override_disconnect_frame() {
+    let mut stub1 = stubs::GameStub::new();
+    let mut stub2 = stubs::GameStub::new();
+    let mut sess1 = ggrs::start_p2p_session(2, stubs::INPUT_SIZE, 7777).unwrap();
+    let mut sess2 = ggrs::start_p2p_session(2, stubs::INPUT_SIZE, 8888).unwrap();
+    let addr1 = SocketAddr::new(IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)), 7777);
+    let addr2 = SocketAddr::new(IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)), 8888);
+
+    assert!(sess1
+        .add_player(ggrs::PlayerType::Local, PlayerHandle(0))
+        .is_ok());
+    assert!(sess1
+        .add_player(ggrs::PlayerType::Remote(addr2), PlayerHandle(1))
+        .is_ok());
+    assert!(sess1.start_session().is_ok());
+
+    assert!(sess2
+        .add_player(ggrs::PlayerType::Local, PlayerHandle(1))
+        .is_ok());
+    assert!(sess2
+        .add_player(ggrs::PlayerType::Remote(addr1), PlayerHandle(0))
+        .is_ok());
+    assert!(sess2.start_session().is_ok());
+
+    for _ in 0..10 {
+        sess1.poll_remote_clients();
+        sess2.poll_remote_clients();
+    }
+
+    for i in 0..10 {
+        let input: u32 = i;
+        let serialized_input = bincode::serialize(&input).unwrap();
+
+        sess1.poll_remote_clients();
+        sess2.poll_remote_clients();
+
+        let requests1 = sess1
+            .advance_frame(PlayerHandle(0), &serialized_input)
+            .unwrap();
+        stub1.handle_requests(requests1);
+        let requests2 = sess2
+            .advance_frame(PlayerHandle(1), &serialized_input)
+            .unwrap();
+        stub2.handle_requests(requests2);
+    }
+
+    // the remote player runs a few frames ahead, so the local session received inputs it has not confirmed yet
+    for i in 10..13 {
+        let input: u32 = i;
+        let serialized_input = bincode::serialize(&input).unwrap();
+        sess2.poll_remote_clients();
+        let requests2 = sess2
+            .advance_frame(PlayerHandle(1), &serialized_input)
+            .unwrap();
+        stub2.handle_requests(requests2);
+    }
+    for _ in 0..10 {
+        sess1.poll_remote_clients();
+        sess2.poll_remote_clients();
+    }
+
+    // the player has to be disconnected first
+    assert!(sess1
+        .override_disconnect_frame(PlayerHandle(1), Frame(5))
+        .is_err());
+    assert!(sess1
+        .override_disconnect_frame(PlayerHandle(0), Frame(5))
+        .is_err());
+
+    sess1.events().for_each(drop);
+    sess1.disconnect_player(PlayerHandle(1)).unwrap();
+    let decided: Vec<GGRSEvent> = sess1
+        .events()
+        .filter(|event| matches!(event, GGRSEvent::DisconnectFrameDecided { .. }))
+        .collect();
+    assert_eq!(decided.len(), 1);
+    let decided_frame = match decided[0] {
+        GGRSEvent::DisconnectFrameDecided {
+            player_handle: PlayerHandle(1),
+            frame,
+        } => frame,
+        _ => panic!("expected a disconnect frame decision for player 1"),
+    };
+
+    // the disconnect frame can only be moved back
+    assert!(sess1
+        .override_disconnect_frame(PlayerHandle(1), decided_frame + 1)
+        .is_err());
+    assert!(sess1
+        .override_disconnect_frame(PlayerHandle(1), decided_frame - 1)
+        .is_ok());
+    assert_eq!(
+        sess1.events().next_back(),
+        Some(GGRSEvent::DisconnectFrameDecided {
+            player_handle: PlayerHandle(1),
+            frame: decided_frame - 1
+        })
+    );
+
+    // the inputs of the player are considered disconnected from the overridden frame on
+    for i in 10..14 {
+        let input: u32 = i;
+        let serialized_input = bincode::serialize(&input).unwrap();
+        for request in sess1
+            .advance_frame(PlayerHandle(0), &serialized_input)
+            .unwrap()
+        {
+            if let ggrs::GGRSRequest::AdvanceFrame { inputs, .. } = &request {
+                if inputs[0].frame >= decided_frame {
+                    assert_eq!(inputs[1].frame, ggrs::NULL_FRAME);
+                }
+            }
+            stub1.handle_requests(vec![request]);
+        }
+    }
+}
+
+#[test]
+#[serial]
+fn test_event_handler() {
+    let mut sess1 = ggrs::start_p2p_session(2, stubs::INPUT_SIZE, 7777).unwrap();
+    let mut sess2 = ggrs::start_p2p_session(2, stubs::INPUT_SIZE, 8888).unwrap();
+    let addr1 = SocketAddr::new(IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)), 7777);
+    let addr2 = SocketAddr::new(IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)), 8888);
+
+    let handled = Arc::new(Mutex::new(Vec::new()));
+    let handled_clone = handled.clone();
+    sess1.set_event_handler(move |timed: ggrs::TimedEvent| {
+        handled_clone.lock().unwrap().push(timed.event)
+    });
+
+    assert!(sess1.add_player(PlayerType::Local, PlayerHandle(0)).is_ok());
+    assert!(sess1
+        .add_player(PlayerType::Remote(addr2), PlayerHandle(1))
+        .is_ok());
+    assert!(sess1.start_session().is_ok());
+    assert!(sess2.add_player(PlayerType::Local, PlayerHandle(1)).is_ok());
+    assert!(sess2
+        .add_player(PlayerType::Remote(addr1), PlayerHandle(0))
+        .is_ok());
+    assert!(sess2.start_session().is_ok());
+
+    for _ in 0..10 {
+        sess1.poll_remote_clients();
+        sess2.poll_remote_clients();
+    }
+
+    // the handler got the events while polling, so there is nothing left to query
+    assert!(handled.lock().unwrap().contains(&GGRSEvent::Synchronized {
+        player_handle: PlayerHandle(1)
+    }));
+    assert_eq!(sess1.events().len(), 0);
+    assert!(sess2.events().len() > 0);
+}
+
+#[test]
+#[serial]
+fn test_sync_failed() {
+    // nobody answers on the address of the remote players
+    let addr = SocketAddr::new(IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)), 9999);
+
+    // the first session gives up after three resent sync requests
+    let mut sess1 = ggrs::start_p2p_session(2, stubs::INPUT_SIZE, 7777).unwrap();
+    sess1.set_sync_retries(Duration::from_millis(5), Some(3));
+    assert!(sess1.add_player(PlayerType::Local, PlayerHandle(0)).is_ok());
+    assert!(sess1
+        .add_player(PlayerType::Remote(addr), PlayerHandle(1))
+        .is_ok());
+    assert!(sess1.start_session().is_ok());
+
+    // the second session gives up after a timeout
+    let mut sess2 = ggrs::start_p2p_session(2, stubs::INPUT_SIZE, 8888).unwrap();
+    sess2.set_sync_timeout(Some(Duration::from_millis(50)));
+    assert!(sess2.add_player(PlayerType::Local, PlayerHandle(0)).is_ok());
+    assert!(sess2
+        .add_player(PlayerType::Remote(addr), PlayerHandle(1))
+        .is_ok());
+    assert!(sess2.start_session().is_ok());
+
+    sess1.poll_remote_clients();
+    sess2.poll_remote_clients();
+    assert!(!sess1.events().any(|event| event
+        == GGRSEvent::SyncFailed {
+            player_handle: PlayerHandle(1)
+        }));
+
+    let mut failed1 = false;
+    let mut failed2 = false;
+    for _ in 0..20 {
+        std::thread::sleep(Duration::from_millis(10));
+        sess1.poll_remote_clients();
+        sess2.poll_remote_clients();
+        failed1 |= sess1.events().any(|event| {
+            event
+                == GGRSEvent::SyncFailed {
+                    player_handle: PlayerHandle(1),
+                }
+        });
+        failed2 |= sess2.events().any(|event| {
+            event
+                == GGRSEvent::SyncFailed {
+                    player_handle: PlayerHandle(1),
+                }
+        });
+    }
+
+    // the sessions start without the player that failed to synchronize
+    assert!(failed1 && failed2);
+    assert!(sess1.current_state() == SessionState::Running);
+    assert!(sess2.current_state() == SessionState::Running);
+    let serialized_input = bincode::serialize(&0u32).unwrap();
+    assert!(sess1
+        .advance_frame(PlayerHandle(0), &serialized_input)
+        .is_ok());
+}
+
+#[test]
+#[serial]
+fn test_disconnect_player_propagates() {
+    let mut stub1 = stubs::GameStub::new();
+    let mut stub2 = stubs::GameStub::new();
+    let mut sess1 = ggrs::start_p2p_session(2, stubs::INPUT_SIZE, 7777).unwrap();
+    let mut sess2 = ggrs::start_p2p_session(2, stubs::INPUT_SIZE, 8888).unwrap();
+    let addr1 = SocketAddr::new(IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)), 7777);
+    let addr2 = SocketAddr::new(IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)), 8888);
+
+    assert!(sess1
+        .add_player(ggrs::PlayerType::Local, PlayerHandle(0))
+        .is_ok());
+    assert!(sess1
+        .add_player(ggrs::PlayerType::Remote(addr2), PlayerHandle(1))
+        .is_ok());
+    assert!(sess1.start_session().is_ok());
+
+    assert!(sess2
+        .add_player(ggrs::PlayerType::Local, PlayerHandle(1))
+        .is_ok());
+    assert!(sess2
+        .add_player(ggrs::PlayerType::Remote(addr1), PlayerHandle(0))
+        .is_ok());
+    assert!(sess2.start_session().is_ok());
+
+    for _ in 0..10 {
+        sess1.poll_remote_clients();
+        sess2.poll_remote_clients();
+    }
+
+    for i in 0..5 {
+        let input: u32 = i;
+        let serialized_input = bincode::serialize(&input).unwrap();
+        let requests1 = sess1
+            .advance_frame(PlayerHandle(0), &serialized_input)
+            .unwrap();
+        stub1.handle_requests(requests1);
+        let requests2 = sess2
+            .advance_frame(PlayerHandle(1), &serialized_input)
+            .unwrap();
+        stub2.handle_requests(requests2);
+    }
+
+    sess1.events().for_each(drop);
+    sess2.events().for_each(drop);
+
+    // kick the remote player
+    sess1.disconnect_player(PlayerHandle(1)).unwrap();
+    assert!(sess1.events().any(|event| event
+        == GGRSEvent::Disconnected {
+            player_handle: PlayerHandle(1)
+        }));
+
+    // the remote session is notified right away instead of timing out
+    for _ in 0..10 {
+        sess1.poll_remote_clients();
+        sess2.poll_remote_clients();
+    }
+    assert!(sess2.events().any(|event| event
+        == GGRSEvent::Disconnected {
+            player_handle: PlayerHandle(0)
+        }));
+}
+
+#[test]
+#[serial]
+fn test_synchronized_pause() {
+    let mut stub1 = stubs::GameStub::new();
+    let mut stub2 = stubs::GameStub::new();
+    let mut sess1 = ggrs::start_p2p_session(2, stubs::INPUT_SIZE, 7777).unwrap();
+    let mut sess2 = ggrs::start_p2p_session(2, stubs::INPUT_SIZE, 8888).unwrap();
+    let addr1 = SocketAddr::new(IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)), 7777);
+    let addr2 = SocketAddr::new(IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)), 8888);
+
+    assert!(sess1
+        .add_player(ggrs::PlayerType::Local, PlayerHandle(0))
+        .is_ok());
+    assert!(sess1
+        .add_player(ggrs::PlayerType::Remote(addr2), PlayerHandle(1))
+        .is_ok());
+    assert!(sess1.start_session().is_ok());
+
+    assert!(sess2
+        .add_player(ggrs::PlayerType::Local, PlayerHandle(1))
+        .is_ok());
+    assert!(sess2
+        .add_player(ggrs::PlayerType::Remote(addr1), PlayerHandle(0))
+        .is_ok());
+    assert!(sess2.start_session().is_ok());
+
+    assert!(sess1.request_pause().is_err()); // not running yet
+    for _ in 0..10 {
+        sess1.poll_remote_clients();
+        sess2.poll_remote_clients();
+    }
+
+    let pause_frame = sess1.request_pause().unwrap();
+    assert!(sess1.request_pause().is_err()); // already requested
+
+    // both sessions stop at the same frame
+    let mut frames = [0, 0];
+    for i in 0..40 {
+        let input: u32 = i;
+        let serialized_input = bincode::serialize(&input).unwrap();
+        match sess1.advance_frame(PlayerHandle(0), &serialized_input) {
+            Ok(requests) => {
+                stub1.handle_requests(requests);
+                frames[0] += 1;
+            }
+            Err(e) if e.is_retryable() => (),
+            Err(e) => panic!("{}", e),
+        }
+        match sess2.advance_frame(PlayerHandle(1), &serialized_input) {
+            Ok(requests) => {
+                stub2.handle_requests(requests);
+                frames[1] += 1;
+            }
+            Err(e) if e.is_retryable() => (),
+            Err(e) => panic!("{}", e),
+        }
+    }
+    assert!(sess1.is_paused());
+    assert!(sess2.is_paused());
+    assert_eq!(frames, [pause_frame, pause_frame]);
+    assert!(sess2
+        .events()
+        .any(|event| event == GGRSEvent::PauseScheduled { frame: pause_frame }));
+
+    // the remote player resumes, both sessions continue
+    sess2.request_resume().unwrap();
+    assert!(sess2.request_resume().is_err());
+    for _ in 0..10 {
+        sess1.poll_remote_clients();
+        sess2.poll_remote_clients();
+    }
+    assert!(!sess1.is_paused());
+    assert!(sess1
+        .events()
+        .any(|event| event == GGRSEvent::Resumed { frame: pause_frame }));
+    let serialized_input = bincode::serialize(&0u32).unwrap();
+    assert!(sess1
+        .advance_frame(PlayerHandle(0), &serialized_input)
+        .is_ok());
+}
+
+#[test]
+#[serial]
+fn test_barrier_frames() {
+    let mut stub1 = stubs::GameStub::new();
+    let mut stub2 = stubs::GameStub::new();
+    let mut sess1 = ggrs::start_p2p_session(2, stubs::INPUT_SIZE, 7777).unwrap();
+    let mut sess2 = ggrs::start_p2p_session(2, stubs::INPUT_SIZE, 8888).unwrap();
+    let addr1 = SocketAddr::new(IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)), 7777);
+    let addr2 = SocketAddr::new(IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)), 8888);
+
+    sess1
+        .add_player(ggrs::PlayerType::Local, PlayerHandle(0))
+        .unwrap();
+    sess1
+        .add_player(ggrs::PlayerType::Remote(addr2), PlayerHandle(1))
+        .unwrap();
+    sess2
+        .add_player(ggrs::PlayerType::Local, PlayerHandle(1))
+        .unwrap();
+    sess2
+        .add_player(ggrs::PlayerType::Remote(addr1), PlayerHandle(0))
+        .unwrap();
+    // both peers register the end of the round
+    let barrier = Frame(6);
+    sess1.add_barrier_frame(barrier).unwrap();
+    sess2.add_barrier_frame(barrier).unwrap();
+    sess1.start_session().unwrap();
+    sess2.start_session().unwrap();
+    for _ in 0..10 {
+        sess1.poll_remote_clients();
+        sess2.poll_remote_clients();
+    }
+
+    // the first session runs ahead and waits at the barrier for the inputs of the second session
+    let serialized_input = bincode::serialize(&0u32).unwrap();
+    for _ in 0..10 {
+        match sess1.advance_frame(PlayerHandle(0), &serialized_input) {
+            Ok(requests) => stub1.handle_requests(requests),
+            Err(GGRSError::WaitingAtBarrier) => (),
+            Err(e) => panic!("{}", e),
+        }
+    }
+    assert_eq!(sess1.current_frame(), barrier);
+    assert!(sess1.events().any(|event| event
+        == GGRSEvent::BarrierProgress {
+            frame: barrier,
+            confirmed: 0,
+            total: 1,
+        }));
+
+    // the second session already has all inputs up to the barrier, so it passes right away and lets the first session pass as well
+    for _ in 0..10 {
+        match sess2.advance_frame(PlayerHandle(1), &serialized_input) {
+            Ok(requests) => stub2.handle_requests(requests),
+            Err(e) if e.is_retryable() => (),
+            Err(e) => panic!("{}", e),
+        }
+        sess1.poll_remote_clients();
+    }
+    assert!(sess2.current_frame() > barrier);
+    assert!(sess2
+        .events()
+        .any(|event| event == GGRSEvent::BarrierPassed { frame: barrier }));
+    assert!(sess1
+        .advance_frame(PlayerHandle(0), &serialized_input)
+        .map(|requests| stub1.handle_requests(requests))
+        .is_ok());
+    assert!(sess1
+        .events()
+        .any(|event| event == GGRSEvent::BarrierPassed { frame: barrier }));
+    assert!(sess1.add_barrier_frame(Frame(3)).is_err());
+}
+
+#[test]
+#[serial]
+fn test_synchronized_fps_change() {
+    let mut stub1 = stubs::GameStub::new();
+    let mut stub2 = stubs::GameStub::new();
+    let mut sess1 = ggrs::start_p2p_session(2, stubs::INPUT_SIZE, 7777).unwrap();
+    let mut sess2 = ggrs::start_p2p_session(2, stubs::INPUT_SIZE, 8888).unwrap();
+    let addr1 = SocketAddr::new(IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)), 7777);
+    let addr2 = SocketAddr::new(IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)), 8888);
+
+    assert!(sess1
+        .add_player(ggrs::PlayerType::Local, PlayerHandle(0))
+        .is_ok());
+    assert!(sess1
+        .add_player(ggrs::PlayerType::Remote(addr2), PlayerHandle(1))
+        .is_ok());
+    assert!(sess1.start_session().is_ok());
+
+    assert!(sess2
+        .add_player(ggrs::PlayerType::Local, PlayerHandle(1))
+        .is_ok());
+    assert!(sess2
+        .add_player(ggrs::PlayerType::Remote(addr1), PlayerHandle(0))
+        .is_ok());
+    assert!(sess2.start_session().is_ok());
+
+    assert!(sess1.request_fps_change(30).is_err()); // not running yet
+    for _ in 0..10 {
+        sess1.poll_remote_clients();
+        sess2.poll_remote_clients();
+    }
+    assert!(sess1.request_fps_change(0).is_err());
+    let change_frame = sess1.request_fps_change(30).unwrap();
+
+    // both sessions learn about the change and keep running
+    for i in 0..40 {
+        let input: u32 = i;
+        let serialized_input = bincode::serialize(&input).unwrap();
+        match sess1.advance_frame(PlayerHandle(0), &serialized_input) {
+            Ok(requests) => stub1.handle_requests(requests),
+            Err(e) if e.is_retryable() => (),
+            Err(e) => panic!("{}", e),
+        }
+        match sess2.advance_frame(PlayerHandle(1), &serialized_input) {
+            Ok(requests) => stub2.handle_requests(requests),
+            Err(e) if e.is_retryable() => (),
+            Err(e) => panic!("{}", e),
+        }
+    }
+    let scheduled = GGRSEvent::FpsChangeScheduled {
+        frame: change_frame,
+        fps: 30,
+    };
+    assert!(sess1.events().any(|event| event == scheduled));
+    assert!(sess2.events().any(|event| event == scheduled));
+    assert!(sess1.current_frame() > change_frame);
+    assert!(sess2.current_frame() > change_frame);
+}
+
+#[test]
+#[serial]
+fn test_timescale_change() {
+    let mut stub1 = stubs::GameStub::new();
+    let mut stub2 = stubs::GameStub::new();
+    let mut sess1 = ggrs::start_p2p_session(2, stubs::INPUT_SIZE, 7777).unwrap();
+    let mut sess2 = ggrs::start_p2p_session(2, stubs::INPUT_SIZE, 8888).unwrap();
+    let addr1 = SocketAddr::new(IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)), 7777);
+    let addr2 = SocketAddr::new(IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)), 8888);
+
+    sess1
+        .add_player(ggrs::PlayerType::Local, PlayerHandle(0))
+        .unwrap();
+    sess1
+        .add_player(ggrs::PlayerType::Remote(addr2), PlayerHandle(1))
+        .unwrap();
+    sess1.start_session().unwrap();
+    sess2
+        .add_player(ggrs::PlayerType::Local, PlayerHandle(1))
+        .unwrap();
+    sess2
+        .add_player(ggrs::PlayerType::Remote(addr1), PlayerHandle(0))
+        .unwrap();
+    sess2.start_session().unwrap();
+
+    assert!(sess1.request_timescale_change(50).is_err()); // not running yet
+    for _ in 0..10 {
+        sess1.poll_remote_clients();
+        sess2.poll_remote_clients();
+    }
+    assert!(sess1.request_timescale_change(0).is_err());
+    let slow_frame = sess1.request_timescale_change(50).unwrap();
+
+    let mut advance = |sess1: &mut ggrs::P2PSession, sess2: &mut ggrs::P2PSession| {
+        for i in 0..40 {
+            let serialized_input = bincode::serialize(&(i as u32)).unwrap();
+            match sess1.advance_frame(PlayerHandle(0), &serialized_input) {
+                Ok(requests) => stub1.handle_requests(requests),
+                Err(e) if e.is_retryable() => (),
+                Err(e) => panic!("{}", e),
+            }
+            match sess2.advance_frame(PlayerHandle(1), &serialized_input) {
+                Ok(requests) => stub2.handle_requests(requests),
+                Err(e) if e.is_retryable() => (),
+                Err(e) => panic!("{}", e),
+            }
+        }
+    };
+
+    // both sessions slow down to half of the regular 60 FPS at the same frame
+    advance(&mut sess1, &mut sess2);
+    let expected = vec![
+        GGRSEvent::FpsChangeScheduled {
+            frame: slow_frame,
+            fps: 30,
+        },
+        GGRSEvent::TimescaleChangeScheduled {
+            frame: slow_frame,
+            percent: 50,
+        },
+    ];
+    let changes = |sess: &mut ggrs::P2PSession| -> Vec<GGRSEvent> {
+        sess.events()
+            .filter(|event| {
+                matches!(
+                    event,
+                    GGRSEvent::FpsChangeScheduled { .. }
+                        | GGRSEvent::TimescaleChangeScheduled { .. }
+                )
+            })
+            .collect()
+    };
+    assert_eq!(changes(&mut sess1), expected);
+    assert_eq!(changes(&mut sess2), expected);
+
+    // the other peer returns to the regular speed
+    let regular_frame = sess2.request_timescale_change(100).unwrap();
+    assert!(regular_frame > slow_frame);
+    advance(&mut sess1, &mut sess2);
+    assert!(sess1.events().any(|event| event
+        == GGRSEvent::FpsChangeScheduled {
+            frame: regular_frame,
+            fps: 60,
+        }));
+}
+
+/// Handles the requests like the game stub, but additionally records every saved state.
+fn handle_and_record(
+    stub: &mut stubs::GameStub,
+    requests: Vec<ggrs::GGRSRequest>,
+    saved: &mut HashMap<ggrs::Frame, (i32, i32)>,
+) {
+    for request in requests {
+        let saved_frame = match &request {
+            ggrs::GGRSRequest::SaveGameState { frame, .. } => Some(*frame),
+            _ => None,
+        };
+        stub.handle_requests(vec![request]);
+        if let Some(frame) = saved_frame {
+            saved.insert(frame, (stub.gs.frame, stub.gs.state));
+        }
+    }
+}
+
+#[test]
+#[serial]
+fn test_restore_session() {
+    let addr1 = SocketAddr::new(IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)), 7777);
+    let addr2 = SocketAddr::new(IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)), 8888);
+    let mut stub1 = stubs::GameStub::new();
+    let mut stub2 = stubs::GameStub::new();
+    let mut saved1 = HashMap::new();
+    let mut saved2 = HashMap::new();
+
+    let mut sess1 = ggrs::start_p2p_session(2, stubs::INPUT_SIZE, 7777).unwrap();
+    sess1
+        .add_player(PlayerType::Local, PlayerHandle(0))
+        .unwrap();
+    sess1
+        .add_player(PlayerType::Remote(addr2), PlayerHandle(1))
+        .unwrap();
+    sess1.start_session().unwrap();
+    let mut sess2 = ggrs::start_p2p_session(2, stubs::INPUT_SIZE, 8888).unwrap();
+    sess2
+        .add_player(PlayerType::Local, PlayerHandle(1))
+        .unwrap();
+    sess2
+        .add_player(PlayerType::Remote(addr1), PlayerHandle(0))
+        .unwrap();
+    sess2.start_session().unwrap();
+
+    // a session that is not running cannot be saved
+    assert_eq!(sess1.save_session(), Err(GGRSError::NotSynchronized));
+
+    for _ in 0..10 {
+        sess1.poll_remote_clients();
+        sess2.poll_remote_clients();
+    }
+
+    for i in 0..20u32 {
+        let serialized_input = bincode::serialize(&i).unwrap();
+        let requests1 = sess1
+            .advance_frame(PlayerHandle(0), &serialized_input)
+            .unwrap();
+        handle_and_record(&mut stub1, requests1, &mut saved1);
+        let requests2 = sess2
+            .advance_frame(PlayerHandle(1), &serialized_input)
+            .unwrap();
+        handle_and_record(&mut stub2, requests2, &mut saved2);
+    }
+
+    // the first client crashes and restarts with the persisted snapshot and gamestate
+    let snapshot = sess1.save_session().unwrap();
+    let snapshot: ggrs::SessionSnapshot =
+        bincode::deserialize(&bincode::serialize(&snapshot).unwrap()).unwrap();
+    drop(sess1);
+
+    let mut sess1 = ggrs::start_p2p_session(2, stubs::INPUT_SIZE, 7777).unwrap();
+    sess1
+        .add_player(PlayerType::Local, PlayerHandle(0))
+        .unwrap();
+    assert!(sess1.restore_session(snapshot.clone()).is_err());
+    drop(sess1);
+    let mut sess1 = ggrs::start_p2p_session(2, stubs::INPUT_SIZE, 7777).unwrap();
+    // a snapshot that has been corrupted on disk is rejected, and the session can still be restored afterwards.
+    // In bincode, the frame of the snapshot is at byte 12, the last confirmed frame at byte 20 and the first input at byte 48.
+    let bytes = bincode::serialize(&snapshot).unwrap();
+    for (offset, frame) in [(12, -5), (20, 1000), (48, snapshot.frame().0 + 1)].iter() {
+        let mut corrupted = bytes.clone();
+        corrupted[*offset..*offset + 8].copy_from_slice(&frame.to_le_bytes());
+        let corrupted: ggrs::SessionSnapshot = bincode::deserialize(&corrupted).unwrap();
+        assert!(matches!(
+            sess1.restore_session(corrupted),
+            Err(GGRSError::InvalidRequest { .. })
+        ));
+    }
+    sess1.restore_session(snapshot.clone()).unwrap();
+    assert_eq!(sess1.current_state(), SessionState::Running);
+
+    let (frame, state) = saved1[&snapshot.frame()];
+    stub1.gs.frame = frame;
+    stub1.gs.state = state;
+
+    // both clients continue the match without synchronizing again
+    let target_frame = 40;
+    for _ in 0..200 {
+        sess1.poll_remote_clients();
+        sess2.poll_remote_clients();
+        if stub1.gs.frame < target_frame {
+            let serialized_input = bincode::serialize(&(stub1.gs.frame as u32)).unwrap();
+            if let Ok(requests) = sess1.advance_frame(PlayerHandle(0), &serialized_input) {
+                handle_and_record(&mut stub1, requests, &mut saved1);
+            }
+        }
+        if stub2.gs.frame < target_frame {
+            let serialized_input = bincode::serialize(&(stub2.gs.frame as u32)).unwrap();
+            if let Ok(requests) = sess2.advance_frame(PlayerHandle(1), &serialized_input) {
+                handle_and_record(&mut stub2, requests, &mut saved2);
+            }
+        }
+    }
+
+    assert_eq!(stub1.gs.frame, target_frame);
+    assert_eq!(stub2.gs.frame, target_frame);
+    assert!(!sess2
+        .events()
+        .any(|event| matches!(event, GGRSEvent::Disconnected { .. })));
+    // confirmed frames are identical on both clients
+    assert_eq!(saved1[&Frame(30)], saved2[&Frame(30)]);
+}
+
+/// A socket that drops the next input messages it sends, as many as `drop_inputs` says.
+#[derive(Debug)]
+struct InputDroppingSocket {
+    socket: UdpNonBlockingSocket,
+    drop_inputs: Arc<AtomicUsize>,
+}
+
+impl NonBlockingSocket for InputDroppingSocket {
+    fn send_to(&mut self, msg: &UdpMessage, addr: SocketAddr) {
+        // the type of the message follows the 9 bytes of the header in the wire format, and 2 is an input message
+        if self.drop_inputs.load(Ordering::Relaxed) > 0 && msg.to_bytes()[9] == 2 {
+            self.drop_inputs.fetch_sub(1, Ordering::Relaxed);
+            return;
+        }
+        self.socket.send_to(msg, addr);
+    }
+
+    fn receive_all_messages(&mut self) -> Vec<(SocketAddr, UdpMessage)> {
+        self.socket.receive_all_messages()
+    }
+}
+
+fn advance_both_to(
+    sessions: [&mut ggrs::P2PSession; 2],
+    stubs: [&mut stubs::GameStub; 2],
+    saved: [&mut HashMap<Frame, (i32, i32)>; 2],
+    target_frame: i32,
+) {
+    let [sess1, sess2] = sessions;
+    let [stub1, stub2] = stubs;
+    let [saved1, saved2] = saved;
+    for _ in 0..400 {
+        sess1.poll_remote_clients();
+        sess2.poll_remote_clients();
+        if stub1.gs.frame < target_frame {
+            let serialized_input = bincode::serialize(&(stub1.gs.frame as u32 % 3)).unwrap();
+            if let Ok(requests) = sess1.advance_frame(PlayerHandle(0), &serialized_input) {
+                handle_and_record(stub1, requests, saved1);
+            }
+        }
+        if stub2.gs.frame < target_frame {
+            let serialized_input = bincode::serialize(&(stub2.gs.frame as u32 % 4)).unwrap();
+            if let Ok(requests) = sess2.advance_frame(PlayerHandle(1), &serialized_input) {
+                handle_and_record(stub2, requests, saved2);
+            }
+        }
+        std::thread::sleep(Duration::from_millis(1));
+    }
+}
+
+#[test]
+#[serial]
+fn test_lost_first_inputs() {
+    let addr1 = SocketAddr::new(IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)), 7777);
+    let addr2 = SocketAddr::new(IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)), 8888);
+    let mut stub1 = stubs::GameStub::new();
+    let mut stub2 = stubs::GameStub::new();
+    let mut saved1 = HashMap::new();
+    let mut saved2 = HashMap::new();
+
+    // the first input messages of both clients get lost, including the keyframes nothing has been acknowledged for yet
+    let drop_inputs1 = Arc::new(AtomicUsize::new(3));
+    let socket1 = InputDroppingSocket {
+        socket: UdpNonBlockingSocket::bind_to_port(7777).unwrap(),
+        drop_inputs: drop_inputs1.clone(),
+    };
+    let drop_inputs2 = Arc::new(AtomicUsize::new(3));
+    let socket2 = InputDroppingSocket {
+        socket: UdpNonBlockingSocket::bind_to_port(8888).unwrap(),
+        drop_inputs: drop_inputs2.clone(),
+    };
+    let mut sess1 = ggrs::start_p2p_session_with_socket(2, stubs::INPUT_SIZE, socket1).unwrap();
+    sess1
+        .add_player(PlayerType::Local, PlayerHandle(0))
+        .unwrap();
+    sess1
+        .add_player(PlayerType::Remote(addr2), PlayerHandle(1))
+        .unwrap();
+    sess1.start_session().unwrap();
+    let mut sess2 = ggrs::start_p2p_session_with_socket(2, stubs::INPUT_SIZE, socket2).unwrap();
+    sess2
+        .add_player(PlayerType::Local, PlayerHandle(1))
+        .unwrap();
+    sess2
+        .add_player(PlayerType::Remote(addr1), PlayerHandle(0))
+        .unwrap();
+    sess2.start_session().unwrap();
+
+    advance_both_to(
+        [&mut sess1, &mut sess2],
+        [&mut stub1, &mut stub2],
+        [&mut saved1, &mut saved2],
+        20,
+    );
+    assert_eq!(
+        drop_inputs1.load(Ordering::Relaxed) + drop_inputs2.load(Ordering::Relaxed),
+        0
+    );
+    assert_eq!(saved1[&Frame(10)], saved2[&Frame(10)]);
+
+    // the first client restarts from a snapshot, and its first inputs after the restart get lost as well
+    let snapshot = sess1.save_session().unwrap();
+    drop(sess1);
+    drop_inputs1.store(3, Ordering::Relaxed);
+    let socket1 = InputDroppingSocket {
+        socket: UdpNonBlockingSocket::bind_to_port(7777).unwrap(),
+        drop_inputs: drop_inputs1.clone(),
+    };
+    let mut sess1 = ggrs::start_p2p_session_with_socket(2, stubs::INPUT_SIZE, socket1).unwrap();
+    sess1.restore_session(snapshot.clone()).unwrap();
+    let (frame, state) = saved1[&snapshot.frame()];
+    stub1.gs.frame = frame;
+    stub1.gs.state = state;
+
+    advance_both_to(
+        [&mut sess1, &mut sess2],
+        [&mut stub1, &mut stub2],
+        [&mut saved1, &mut saved2],
+        40,
+    );
+    assert_eq!(drop_inputs1.load(Ordering::Relaxed), 0);
+    assert_eq!(stub1.gs.frame, 40);
+    assert_eq!(stub2.gs.frame, 40);
+    assert!(!sess2.events().any(|event| matches!(
+        event,
+        GGRSEvent::Disconnected { .. } | GGRSEvent::InvalidMessage { .. }
+    )));
+    assert_eq!(saved1[&Frame(30)], saved2[&Frame(30)]);
+}
+
+#[test]
+#[serial]
+fn test_bounded_memory() {
+    let addr1 = SocketAddr::new(IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)), 7777);
+    let addr2 = SocketAddr::new(IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)), 8888);
+    let spectator_addr = SocketAddr::new(IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)), 9999);
+    let mut sess1 = ggrs::start_p2p_session(2, stubs::INPUT_SIZE, 7777).unwrap();
+    let mut sess2 = ggrs::start_p2p_session(2, stubs::INPUT_SIZE, 8888).unwrap();
+    assert!(sess1.set_bounded_memory(0).is_err());
+    sess1.set_bounded_memory(8).unwrap();
+    sess1
+        .add_player(PlayerType::Local, PlayerHandle(0))
+        .unwrap();
+    sess1
+        .add_player(PlayerType::Remote(addr2), PlayerHandle(1))
+        .unwrap();
+    assert!(sess1.add_spectator(spectator_addr).is_err());
+    sess1.set_max_event_queue_size(None);
+    assert!(matches!(
+        sess1.start_session(),
+        Err(GGRSError::InvalidConfiguration { .. })
+    ));
+    sess1.set_max_event_queue_size(Some(16));
+    sess1.start_session().unwrap();
+    assert!(sess1.set_bounded_memory(8).is_err());
+    sess2
+        .add_player(PlayerType::Local, PlayerHandle(1))
+        .unwrap();
+    sess2
+        .add_player(PlayerType::Remote(addr1), PlayerHandle(0))
+        .unwrap();
+    sess2.start_session().unwrap();
+
+    // the gamestates of the first session are copied into the preallocated buffers
+    let mut stub1 = stubs::GameStub::new();
+    let mut stub2 = stubs::GameStub::new();
+    let mut saved1 = HashMap::new();
+    let mut saved2 = HashMap::new();
+    let mut buffer = Vec::with_capacity(8);
+    for _ in 0..200 {
+        sess1.poll_remote_clients();
+        sess2.poll_remote_clients();
+        if stub1.gs.frame < 30 {
+            let serialized_input = bincode::serialize(&(stub1.gs.frame as u32)).unwrap();
+            if let Ok(requests) = sess1.advance_frame(PlayerHandle(0), &serialized_input) {
+                for request in requests {
+                    match request {
+                        GGRSRequest::SaveGameState { cell, frame } => {
+                            let state = bincode::serialize(&stub1.gs).unwrap();
+                            assert!(cell.save_bytes(frame, &[0; 9], 0).is_err());
+                            cell.save_bytes(frame, &state, 0).unwrap();
+                            saved1.insert(frame, (stub1.gs.frame, stub1.gs.state));
+                        }
+                        GGRSRequest::LoadGameState { cell, .. } => {
+                            cell.load_into(&mut buffer);
+                            stub1.gs = bincode::deserialize(&buffer).unwrap();
+                        }
+                        request => stub1.handle_requests(vec![request]),
+                    }
+                }
+            }
+        }
+        if stub2.gs.frame < 30 {
+            let serialized_input = bincode::serialize(&(stub2.gs.frame as u32)).unwrap();
+            if let Ok(requests) = sess2.advance_frame(PlayerHandle(1), &serialized_input) {
+                handle_and_record(&mut stub2, requests, &mut saved2);
+            }
+        }
+    }
+    assert_eq!(stub1.gs.frame, 30);
+    assert_eq!(stub2.gs.frame, 30);
+    assert_eq!(saved1[&Frame(20)], saved2[&Frame(20)]);
+}
+
+#[test]
+#[serial]
+fn test_waiting_room() {
+    let addr1 = SocketAddr::new(IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)), 7777);
+    let addr2 = SocketAddr::new(IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)), 8888);
+    let mut stub1 = stubs::GameStub::new();
+
+    let mut sess1 = ggrs::start_p2p_session(2, stubs::INPUT_SIZE, 7777).unwrap();
+    sess1
+        .add_player(PlayerType::Local, PlayerHandle(0))
+        .unwrap();
+    sess1
+        .add_player(PlayerType::Remote(addr2), PlayerHandle(1))
+        .unwrap();
+    sess1.set_waiting_room_enabled(true).unwrap();
+    sess1.start_session().unwrap();
+    assert!(sess1.set_waiting_room_enabled(false).is_err());
+    assert_eq!(sess1.start_match(), Err(GGRSError::NotSynchronized));
+
+    let mut sess2 = ggrs::start_p2p_session(2, stubs::INPUT_SIZE, 8888).unwrap();
+    sess2
+        .add_player(PlayerType::Local, PlayerHandle(1))
+        .unwrap();
+    sess2
+        .add_player(PlayerType::Remote(addr1), PlayerHandle(0))
+        .unwrap();
+    sess2.start_session().unwrap();
+
+    for _ in 0..10 {
+        sess1.poll_remote_clients();
+        sess2.poll_remote_clients();
+    }
+
+    // synchronized, but frames cannot be advanced until the match is started
+    assert_eq!(sess1.current_state(), SessionState::WaitingRoom);
+    assert_eq!(sess2.current_state(), SessionState::Running);
+    let serialized_input = bincode::serialize(&0u32).unwrap();
+    assert!(matches!(
+        sess1.advance_frame(PlayerHandle(0), &serialized_input),
+        Err(GGRSError::NotSynchronized)
+    ));
+
+    sess1.start_match().unwrap();
+    assert_eq!(sess1.current_state(), SessionState::Running);
+    assert!(sess1.start_match().is_err());
+    let requests = sess1
+        .advance_frame(PlayerHandle(0), &serialized_input)
+        .unwrap();
+    stub1.handle_requests(requests);
+    assert_eq!(stub1.gs.frame, 1);
+}
+
+#[test]
+#[serial]
+fn test_input_send_interval() {
+    let addr1 = SocketAddr::new(IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)), 7777);
+    let addr2 = SocketAddr::new(IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)), 8888);
+    let mut stub1 = stubs::GameStub::new();
+    let mut stub2 = stubs::GameStub::new();
+
+    let mut sess1 = ggrs::start_p2p_session(2, stubs::INPUT_SIZE, 7777).unwrap();
+    sess1
+        .add_player(PlayerType::Local, PlayerHandle(0))
+        .unwrap();
+    sess1
+        .add_player(PlayerType::Remote(addr2), PlayerHandle(1))
+        .unwrap();
+    sess1.set_input_send_interval(0).unwrap();
+    assert!(sess1.start_session().is_err());
+    sess1.set_input_send_interval(3).unwrap();
+    sess1.start_session().unwrap();
+    // after starting, invalid intervals are rejected
+    assert!(matches!(
+        sess1.set_input_send_interval(0),
+        Err(GGRSError::InvalidRequest { .. })
+    ));
+
+    let mut sess2 = ggrs::start_p2p_session(2, stubs::INPUT_SIZE, 8888).unwrap();
+    sess2
+        .add_player(PlayerType::Local, PlayerHandle(1))
+        .unwrap();
+    sess2
+        .add_player(PlayerType::Remote(addr1), PlayerHandle(0))
+        .unwrap();
+    sess2.set_input_send_interval(2).unwrap();
+    sess2.start_session().unwrap();
+
+    for _ in 0..10 {
+        sess1.poll_remote_clients();
+        sess2.poll_remote_clients();
+    }
+
+    // coalesced inputs still arrive, so the sessions never get stuck at the prediction threshold
+    for i in 0..30u32 {
+        let serialized_input = bincode::serialize(&i).unwrap();
+        let requests1 = sess1
+            .advance_frame(PlayerHandle(0), &serialized_input)
+            .unwrap();
+        stub1.handle_requests(requests1);
+        let requests2 = sess2
+            .advance_frame(PlayerHandle(1), &serialized_input)
+            .unwrap();
+        stub2.handle_requests(requests2);
+    }
+    assert_eq!(stub1.gs.frame, 30);
+    assert_eq!(stub2.gs.frame, 30);
+}
+
+#[test]
+#[serial]
+fn test_sparse_inputs() {
+    let addr1 = SocketAddr::new(IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)), 7777);
+    let addr2 = SocketAddr::new(IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)), 8888);
+    let mut stub1 = stubs::GameStub::new();
+    let mut stub2 = stubs::GameStub::new();
+
+    let mut sess1 = ggrs::start_p2p_session(2, stubs::INPUT_SIZE, 7777).unwrap();
+    sess1
+        .add_player(PlayerType::Local, PlayerHandle(0))
+        .unwrap();
+    sess1
+        .add_player(PlayerType::Remote(addr2), PlayerHandle(1))
+        .unwrap();
+    sess1.set_sparse_input_interval(ggrs::MAX_PREDICTION_FRAMES + 1);
+    assert!(sess1.start_session().is_err());
+    sess1.set_sparse_input_interval(4);
+    sess1.start_session().unwrap();
+
+    let mut sess2 = ggrs::start_p2p_session(2, stubs::INPUT_SIZE, 8888).unwrap();
+    sess2
+        .add_player(PlayerType::Local, PlayerHandle(1))
+        .unwrap();
+    sess2
+        .add_player(PlayerType::Remote(addr1), PlayerHandle(0))
+        .unwrap();
+    sess2.set_sparse_input_interval(4);
+    sess2.start_session().unwrap();
+
+    for _ in 0..10 {
+        sess1.poll_remote_clients();
+        sess2.poll_remote_clients();
+    }
+    let packets_before = sess1.network_stats(PlayerHandle(1)).unwrap().packets_sent;
+
+    // the players change their inputs only once, so most frames are not sent on their own
+    for i in 0..40u32 {
+        let serialized_input = bincode::serialize(&(i / 20)).unwrap();
+        let requests1 = sess1
+            .advance_frame(PlayerHandle(0), &serialized_input)
+            .unwrap();
+        stub1.handle_requests(requests1);
+        let requests2 = sess2
+            .advance_frame(PlayerHandle(1), &serialized_input)
+            .unwrap();
+        stub2.handle_requests(requests2);
+    }
+    assert_eq!(stub1.gs.frame, 40);
+    assert_eq!(stub2.gs.frame, 40);
+    let packets_sent = sess1.network_stats(PlayerHandle(1)).unwrap().packets_sent - packets_before;
+    // without sparse inputs, there would be an input message and an acknowledgement for every frame
+    assert!(packets_sent < 40, "sent {} packets", packets_sent);
+
+    // the second session received every input exactly as it was given
+    for _ in 0..10 {
+        sess1.poll_remote_clients();
+        sess2.poll_remote_clients();
+    }
+    assert!(sess2.confirmed_frame() >= Frame(30));
+    assert_eq!(stub1.gs.state, stub2.gs.state);
+}
+
+#[test]
+#[serial]
+fn test_turn_based() {
+    let addr1 = SocketAddr::new(IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)), 7777);
+    let addr2 = SocketAddr::new(IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)), 8888);
+    let mut stub1 = stubs::GameStub::new();
+    let mut stub2 = stubs::GameStub::new();
+
+    let mut sess1 = ggrs::start_p2p_session(2, stubs::INPUT_SIZE, 7777).unwrap();
+    sess1
+        .add_player(PlayerType::Local, PlayerHandle(0))
+        .unwrap();
+    sess1
+        .add_player(PlayerType::Remote(addr2), PlayerHandle(1))
+        .unwrap();
+    sess1.set_turn_based(true).unwrap();
+    sess1.set_frame_delay(2, PlayerHandle(0)).unwrap();
+    assert!(sess1.start_session().is_err());
+    sess1.set_frame_delay(0, PlayerHandle(0)).unwrap();
+    sess1.start_session().unwrap();
+    assert!(sess1.set_turn_based(false).is_err());
+
+    let mut sess2 = ggrs::start_p2p_session(2, stubs::INPUT_SIZE, 8888).unwrap();
+    sess2
+        .add_player(PlayerType::Local, PlayerHandle(1))
+        .unwrap();
+    sess2
+        .add_player(PlayerType::Remote(addr1), PlayerHandle(0))
+        .unwrap();
+    sess2.set_turn_based(true).unwrap();
+    sess2.start_session().unwrap();
+
+    for _ in 0..10 {
+        sess1.poll_remote_clients();
+        sess2.poll_remote_clients();
+    }
+
+    for turn in 0..5u32 {
+        // the first player submits the turn, but has to wait for the second player
+        let input1 = bincode::serialize(&turn).unwrap();
+        for _ in 0..3 {
+            assert_eq!(
+                sess1.advance_frame(PlayerHandle(0), &input1).unwrap_err(),
+                GGRSError::WaitingForTurn
+            );
+            std::thread::sleep(Duration::from_millis(1));
+        }
+
+        // the second player ends the turn once the input of the first player has arrived
+        let input2 = bincode::serialize(&(turn * 2)).unwrap();
+        let requests2 = loop {
+            match sess2.advance_frame(PlayerHandle(1), &input2) {
+                Ok(requests) => break requests,
+                Err(e) => assert_eq!(e, GGRSError::WaitingForTurn),
+            }
+        };
+        assert_eq!(requests2.len(), 1);
+        stub2.handle_requests(requests2);
+
+        // the input given while waiting does not replace the submitted turn
+        let ignored = bincode::serialize(&100u32).unwrap();
+        let requests1 = loop {
+            match sess1.advance_frame(PlayerHandle(0), &ignored) {
+                Ok(requests) => break requests,
+                Err(e) => assert_eq!(e, GGRSError::WaitingForTurn),
+            }
+        };
+        match &requests1[..] {
+            [GGRSRequest::AdvanceFrame { frame, inputs }] => {
+                assert_eq!(*frame, Frame(turn as i64));
+                assert_eq!(inputs[0].input(), &input1[..]);
+                assert_eq!(inputs[1].input(), &input2[..]);
+            }
+            _ => panic!("expected a single advance frame request"),
+        }
+        stub1.handle_requests(requests1);
+    }
+
+    assert_eq!(stub1.gs.frame, 5);
+    assert_eq!(stub1.gs.state, stub2.gs.state);
+    assert!(sess1
+        .events()
+        .all(|event| !matches!(event, GGRSEvent::WaitRecommendation { .. })));
+}
+
+#[test]
+#[serial]
+fn test_reliable_messages() {
+    let addr1 = SocketAddr::new(IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)), 7777);
+    let addr2 = SocketAddr::new(IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)), 8888);
+    let mut sess1 = ggrs::start_p2p_session(2, stubs::INPUT_SIZE, 7777).unwrap();
+    sess1
+        .add_player(PlayerType::Local, PlayerHandle(0))
+        .unwrap();
+    sess1
+        .add_player(PlayerType::Remote(addr2), PlayerHandle(1))
+        .unwrap();
+    sess1.start_session().unwrap();
+    assert_eq!(
+        sess1.send_reliable_message(PlayerHandle(1), b"too early"),
+        Err(GGRSError::NotSynchronized)
+    );
+
+    let mut sess2 = ggrs::start_p2p_session(2, stubs::INPUT_SIZE, 8888).unwrap();
+    sess2
+        .add_player(PlayerType::Local, PlayerHandle(1))
+        .unwrap();
+    sess2
+        .add_player(PlayerType::Remote(addr1), PlayerHandle(0))
+        .unwrap();
+    sess2.start_session().unwrap();
+
+    for _ in 0..10 {
+        sess1.poll_remote_clients();
+        sess2.poll_remote_clients();
+    }
+
+    assert_eq!(
+        sess1.send_reliable_message(PlayerHandle(0), b"to myself"),
+        Err(GGRSError::InvalidHandle)
+    );
+    assert!(sess1
+        .send_reliable_message(PlayerHandle(1), &[0; 468])
+        .is_err());
+    for msg in [&b"first"[..], b"second", b"third"].iter() {
+        sess1.send_reliable_message(PlayerHandle(1), msg).unwrap();
+    }
+    sess2
+        .send_reliable_message(PlayerHandle(0), b"reply")
+        .unwrap();
+
+    for _ in 0..10 {
+        sess1.poll_remote_clients();
+        sess2.poll_remote_clients();
+    }
+
+    // every message arrives exactly once and in order
+    assert_eq!(
+        sess2.reliable_messages().collect::<Vec<_>>(),
+        vec![
+            (PlayerHandle(0), b"first".to_vec()),
+            (PlayerHandle(0), b"second".to_vec()),
+            (PlayerHandle(0), b"third".to_vec()),
+        ]
+    );
+    assert_eq!(
+        sess1.reliable_messages().collect::<Vec<_>>(),
+        vec![(PlayerHandle(1), b"reply".to_vec())]
+    );
+    assert_eq!(sess2.reliable_messages().count(), 0);
+}
+
+#[test]
+#[serial]
+fn test_fps_mismatch() {
+    let addr1 = SocketAddr::new(IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)), 7777);
+    let addr2 = SocketAddr::new(IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)), 8888);
+
+    let mut sess1 = ggrs::start_p2p_session(2, stubs::INPUT_SIZE, 7777).unwrap();
+    sess1
+        .add_player(PlayerType::Local, PlayerHandle(0))
+        .unwrap();
+    sess1
+        .add_player(PlayerType::Remote(addr2), PlayerHandle(1))
+        .unwrap();
+    sess1.start_session().unwrap();
+
+    let mut sess2 = ggrs::start_p2p_session(2, stubs::INPUT_SIZE, 8888).unwrap();
+    sess2
+        .add_player(PlayerType::Local, PlayerHandle(1))
+        .unwrap();
+    sess2
+        .add_player(PlayerType::Remote(addr1), PlayerHandle(0))
+        .unwrap();
+    sess2.set_fps(30).unwrap();
+    sess2.start_session().unwrap();
+
+    for _ in 0..10 {
+        sess1.poll_remote_clients();
+        sess2.poll_remote_clients();
+    }
+
+    // the sessions refuse to synchronize and report the mismatch once
+    assert_eq!(sess1.current_state(), SessionState::Synchronizing);
+    assert_eq!(sess2.current_state(), SessionState::Synchronizing);
+    let mismatches: Vec<GGRSEvent> = sess1
+        .events()
+        .filter(|event| matches!(event, GGRSEvent::FpsMismatch { .. }))
+        .collect();
+    assert_eq!(
+        mismatches,
+        vec![GGRSEvent::FpsMismatch {
+            player_handle: PlayerHandle(1),
+            local_fps: 60,
+            remote_fps: 30,
+        }]
+    );
+    assert_eq!(sess1.network_stats(PlayerHandle(1)).unwrap().remote_fps, 30);
+}
+
+/// A socket that can be moved to another port while the session is using it, like a socket behind a NAT that rebinds.
+#[derive(Debug, Clone)]
+struct MovableSocket(Arc<Mutex<UdpNonBlockingSocket>>);
+
+impl NonBlockingSocket for MovableSocket {
+    fn send_to(&mut self, msg: &UdpMessage, addr: SocketAddr) {
+        self.0.lock().unwrap().send_to(msg, addr);
+    }
+
+    fn receive_all_messages(&mut self) -> Vec<(SocketAddr, UdpMessage)> {
+        self.0.lock().unwrap().receive_all_messages()
+    }
+}
+
+/// A socket that drops every fourth message it sends while `lossy` is set.
+#[derive(Debug)]
+struct LossySocket {
+    socket: UdpNonBlockingSocket,
+    lossy: Arc<AtomicBool>,
+    until_drop: usize,
+}
+
+impl NonBlockingSocket for LossySocket {
+    fn send_to(&mut self, msg: &UdpMessage, addr: SocketAddr) {
+        self.until_drop -= 1;
+        if self.until_drop == 0 {
+            self.until_drop = 4;
+            if self.lossy.load(Ordering::Relaxed) {
+                return;
+            }
+        }
+        self.socket.send_to(msg, addr);
+    }
+
+    fn receive_all_messages(&mut self) -> Vec<(SocketAddr, UdpMessage)> {
+        self.socket.receive_all_messages()
+    }
+}
+
+#[test]
+#[serial]
+fn test_packet_loss_stats() {
+    let lossy = Arc::new(AtomicBool::new(false));
+    let socket1 = LossySocket {
+        socket: UdpNonBlockingSocket::bind_to_port(7777).unwrap(),
+        lossy: lossy.clone(),
+        until_drop: 4,
+    };
+    let mut sess1 = ggrs::start_p2p_session_with_socket(2, stubs::INPUT_SIZE, socket1).unwrap();
+    let mut sess2 = ggrs::start_p2p_session(2, stubs::INPUT_SIZE, 8888).unwrap();
+    let addr1 = SocketAddr::new(IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)), 7777);
+    let addr2 = SocketAddr::new(IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)), 8888);
+
+    assert!(sess1.add_player(PlayerType::Local, PlayerHandle(0)).is_ok());
+    assert!(sess1
+        .add_player(PlayerType::Remote(addr2), PlayerHandle(1))
+        .is_ok());
+    assert!(sess1.start_session().is_ok());
+    assert!(sess2.add_player(PlayerType::Local, PlayerHandle(1)).is_ok());
+    assert!(sess2
+        .add_player(PlayerType::Remote(addr1), PlayerHandle(0))
+        .is_ok());
+    assert!(sess2.start_session().is_ok());
+
+    for _ in 0..10 {
+        sess1.poll_remote_clients();
+        sess2.poll_remote_clients();
+    }
+    assert!(sess1.current_state() == SessionState::Running);
+    assert!(sess2.current_state() == SessionState::Running);
+
+    lossy.store(true, Ordering::Relaxed);
+    let serialized_input = bincode::serialize(&0u32).unwrap();
+    for _ in 0..40 {
+        let _ = sess1.advance_frame(PlayerHandle(0), &serialized_input);
+        let _ = sess2.advance_frame(PlayerHandle(1), &serialized_input);
+        std::thread::sleep(Duration::from_millis(1));
+    }
+    sess1.poll_remote_clients();
+    sess2.poll_remote_clients();
+
+    // the second session notices the gaps in the messages of the first session
+    let stats2 = sess2.network_stats(PlayerHandle(0)).unwrap();
+    assert!(stats2.packets_received > 0);
+    assert!(stats2.packet_loss > 0.1 && stats2.packet_loss < 0.4);
+    let stats1 = sess1.network_stats(PlayerHandle(1)).unwrap();
+    assert!(stats1.packets_sent > 0);
+    assert_eq!(stats1.packet_loss, 0.0);
+    assert_eq!(stats1.packets_out_of_order, 0);
+}
+
+/// A socket that sends every message twice, swaps every fifth message with the next one and sends one message again much later while `faulty` is set.
+#[derive(Debug)]
+struct FaultySocket {
+    socket: UdpNonBlockingSocket,
+    faulty: Arc<AtomicBool>,
+    sent: usize,
+    until_hold_back: usize,
+    held_back: Option<(UdpMessage, SocketAddr)>,
+    replay: Option<(UdpMessage, SocketAddr)>,
+}
+
+impl NonBlockingSocket for FaultySocket {
+    fn send_to(&mut self, msg: &UdpMessage, addr: SocketAddr) {
+        if !self.faulty.load(Ordering::Relaxed) {
+            self.socket.send_to(msg, addr);
+            return;
+        }
+
+        self.sent += 1;
+        if self.sent == 1 {
+            self.replay = Some((msg.clone(), addr));
+        } else if self.sent == 100 {
+            if let Some((replay, replay_addr)) = self.replay.take() {
+                self.socket.send_to(&replay, replay_addr);
+            }
+        }
+        self.until_hold_back -= 1;
+        if self.until_hold_back == 0 {
+            self.until_hold_back = 5;
+            self.held_back = Some((msg.clone(), addr));
+            return;
+        }
+        self.socket.send_to(msg, addr);
+        self.socket.send_to(msg, addr);
+        if let Some((held_back, held_back_addr)) = self.held_back.take() {
+            self.socket.send_to(&held_back, held_back_addr);
+        }
+    }
+
+    fn receive_all_messages(&mut self) -> Vec<(SocketAddr, UdpMessage)> {
+        self.socket.receive_all_messages()
+    }
+}
+
+#[test]
+#[serial]
+fn test_duplicated_and_stale_packets() {
+    let mut stub1 = stubs::GameStub::new();
+    let mut stub2 = stubs::GameStub::new();
+    let faulty = Arc::new(AtomicBool::new(false));
+    let socket1 = FaultySocket {
+        socket: UdpNonBlockingSocket::bind_to_port(7777).unwrap(),
+        faulty: faulty.clone(),
+        sent: 0,
+        until_hold_back: 5,
+        held_back: None,
+        replay: None,
+    };
+    let mut sess1 = ggrs::start_p2p_session_with_socket(2, stubs::INPUT_SIZE, socket1).unwrap();
+    let mut sess2 = ggrs::start_p2p_session(2, stubs::INPUT_SIZE, 8888).unwrap();
+    let addr1 = SocketAddr::new(IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)), 7777);
+    let addr2 = SocketAddr::new(IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)), 8888);
+
+    assert!(sess1.add_player(PlayerType::Local, PlayerHandle(0)).is_ok());
+    assert!(sess1
+        .add_player(PlayerType::Remote(addr2), PlayerHandle(1))
+        .is_ok());
+    assert!(sess1.start_session().is_ok());
+    assert!(sess2.add_player(PlayerType::Local, PlayerHandle(1)).is_ok());
+    assert!(sess2
+        .add_player(PlayerType::Remote(addr1), PlayerHandle(0))
+        .is_ok());
+    assert!(sess2.start_session().is_ok());
+
+    for _ in 0..10 {
+        sess1.poll_remote_clients();
+        sess2.poll_remote_clients();
+    }
+    assert!(sess1.current_state() == SessionState::Running);
+    assert!(sess2.current_state() == SessionState::Running);
+
+    faulty.store(true, Ordering::Relaxed);
+    let mut saved1 = HashMap::new();
+    let mut saved2 = HashMap::new();
+    let target_frame = 120;
+    for _ in 0..600 {
+        sess1.poll_remote_clients();
+        sess2.poll_remote_clients();
+        if stub1.gs.frame < target_frame {
+            let serialized_input = bincode::serialize(&(stub1.gs.frame as u32 % 7)).unwrap();
+            if let Ok(requests) = sess1.advance_frame(PlayerHandle(0), &serialized_input) {
+                handle_and_record(&mut stub1, requests, &mut saved1);
+            }
+        }
+        if stub2.gs.frame < target_frame {
+            let serialized_input = bincode::serialize(&(stub2.gs.frame as u32 % 5)).unwrap();
+            if let Ok(requests) = sess2.advance_frame(PlayerHandle(1), &serialized_input) {
+                handle_and_record(&mut stub2, requests, &mut saved2);
+            }
+        }
+        std::thread::sleep(Duration::from_millis(1));
+    }
+
+    // the second session drops the duplicates and the replayed message of the first session
+    let stats2 = sess2.network_stats(PlayerHandle(0)).unwrap();
+    assert!(stats2.packets_duplicated > 0);
+    assert!(stats2.packets_stale > 0);
+    assert!(stats2.packets_out_of_order > 0);
+    assert_eq!(stats2.packet_loss, 0.0);
+    let stats1 = sess1.network_stats(PlayerHandle(1)).unwrap();
+    assert_eq!(stats1.packets_duplicated, 0);
+
+    // the match went on and the confirmed frames are identical on both clients
+    assert_eq!(stub1.gs.frame, target_frame);
+    assert_eq!(stub2.gs.frame, target_frame);
+    assert_eq!(saved1[&Frame(100)], saved2[&Frame(100)]);
+}
+
+#[test]
+#[serial]
+fn test_address_migration() {
+    let mut stub1 = stubs::GameStub::new();
+    let mut stub2 = stubs::GameStub::new();
+    let socket2 = MovableSocket(Arc::new(Mutex::new(
+        UdpNonBlockingSocket::bind_to_port(8888).unwrap(),
+    )));
+    let mut sess1 = ggrs::start_p2p_session(2, stubs::INPUT_SIZE, 7777).unwrap();
+    let mut sess2 =
+        ggrs::start_p2p_session_with_socket(2, stubs::INPUT_SIZE, socket2.clone()).unwrap();
+    let addr1 = SocketAddr::new(IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)), 7777);
+    let addr2 = SocketAddr::new(IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)), 8888);
+    let new_addr2 = SocketAddr::new(IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)), 9999);
+
+    sess1.set_address_migration_enabled(true);
+    assert!(sess1.add_player(PlayerType::Local, PlayerHandle(0)).is_ok());
+    assert!(sess1
+        .add_player(PlayerType::Remote(addr2), PlayerHandle(1))
+        .is_ok());
+    assert!(sess1.start_session().is_ok());
+    assert!(sess2.add_player(PlayerType::Local, PlayerHandle(1)).is_ok());
+    assert!(sess2
+        .add_player(PlayerType::Remote(addr1), PlayerHandle(0))
+        .is_ok());
+    assert!(sess2.start_session().is_ok());
+
+    for _ in 0..10 {
+        sess1.poll_remote_clients();
+        sess2.poll_remote_clients();
+    }
+    assert!(sess1.current_state() == SessionState::Running);
+    assert!(sess2.current_state() == SessionState::Running);
+
+    let mut migrated = false;
+    for i in 0..20 {
+        // the NAT of the second player rebinds
+        if i == 5 {
+            *socket2.0.lock().unwrap() = UdpNonBlockingSocket::bind_to_port(9999).unwrap();
+        }
+
+        let input = bincode::serialize(&(i as u32)).unwrap();
+        stub1.handle_requests(sess1.advance_frame(PlayerHandle(0), &input).unwrap());
+        stub2.handle_requests(sess2.advance_frame(PlayerHandle(1), &input).unwrap());
+        std::thread::sleep(std::time::Duration::from_millis(5));
+
+        migrated |= sess1.events().any(|event| {
+            event
+                == GGRSEvent::PeerAddressChanged {
+                    player_handle: PlayerHandle(1),
+                    addr: new_addr2,
+                }
+        });
+    }
+
+    assert!(migrated);
+    // inputs keep arriving from the new address
+    assert!(sess1.network_stats(PlayerHandle(1)).unwrap().send_queue_len < 8);
+}
+
+#[test]
+#[serial]
+fn test_determinism_check() {
+    let mut stub1 = stubs::GameStub::new();
+    let mut stub2 = stubs::GameStub::new();
+    let mut sess1 = ggrs::start_p2p_session(2, stubs::INPUT_SIZE, 7777).unwrap();
+    let mut sess2 = ggrs::start_p2p_session(2, stubs::INPUT_SIZE, 8888).unwrap();
+    let addr1 = SocketAddr::new(IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)), 7777);
+    let addr2 = SocketAddr::new(IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)), 8888);
+
+    // the distance has to stay below the prediction window
+    sess1.set_determinism_check_distance(ggrs::MAX_PREDICTION_FRAMES);
+    assert!(sess1.add_player(PlayerType::Local, PlayerHandle(0)).is_ok());
+    assert!(sess1
+        .add_player(PlayerType::Remote(addr2), PlayerHandle(1))
+        .is_ok());
+    assert!(sess1.start_session().is_err());
+    sess1.set_determinism_check_distance(4);
+    assert!(sess1.start_session().is_ok());
+
+    sess2.set_determinism_check_distance(2);
+    assert!(sess2.add_player(PlayerType::Local, PlayerHandle(1)).is_ok());
+    assert!(sess2
+        .add_player(PlayerType::Remote(addr1), PlayerHandle(0))
+        .is_ok());
+    assert!(sess2.start_session().is_ok());
+
+    for _ in 0..10 {
+        sess1.poll_remote_clients();
+        sess2.poll_remote_clients();
+    }
+    assert!(sess1.current_state() == SessionState::Running);
+    assert!(sess2.current_state() == SessionState::Running);
+
+    // the stub is deterministic, so the resimulated checksums always match up
+    let reps = 30;
+    for i in 0..reps {
+        let input = bincode::serialize(&(i as u32)).unwrap();
+        stub1.handle_requests(sess1.advance_frame(PlayerHandle(0), &input).unwrap());
+        stub2.handle_requests(sess2.advance_frame(PlayerHandle(1), &input).unwrap());
+        assert_eq!(stub1.gs.frame, i as i32 + 1);
+        assert_eq!(stub2.gs.frame, i as i32 + 1);
+    }
+    assert!(sess1.telemetry().rollbacks >= reps - 4);
+
+    // corrupting the gamestate on every load makes resimulations differ from the original simulation
+    let mut mismatch = None;
+    for i in reps..reps + 20 {
+        let input = bincode::serialize(&(i as u32)).unwrap();
+        match sess1.advance_frame(PlayerHandle(0), &input) {
+            Ok(requests) => {
+                for request in requests {
+                    let load = matches!(request, GGRSRequest::LoadGameState { .. });
+                    stub1.handle_requests(vec![request]);
+                    if load {
+                        stub1.gs.state += 1;
+                    }
+                }
+            }
+            Err(e) => {
+                mismatch = Some(e);
+                break;
+            }
+        }
+        stub2.handle_requests(sess2.advance_frame(PlayerHandle(1), &input).unwrap());
+    }
+    assert!(matches!(
+        mismatch,
+        Some(GGRSError::MismatchedChecksum { .. })
+    ));
+}
+
+#[test]
+#[serial]
+fn test_desync_detection() {
+    let mut stub1 = stubs::GameStub::new();
+    let mut stub2 = stubs::GameStub::new();
+    let mut sess1 = ggrs::start_p2p_session(2, stubs::INPUT_SIZE, 7777).unwrap();
+    let mut sess2 = ggrs::start_p2p_session(2, stubs::INPUT_SIZE, 8888).unwrap();
+    let addr1 = SocketAddr::new(IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)), 7777);
+    let addr2 = SocketAddr::new(IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)), 8888);
+
+    assert!(sess1.add_player(PlayerType::Local, PlayerHandle(0)).is_ok());
+    assert!(sess1
+        .add_player(PlayerType::Remote(addr2), PlayerHandle(1))
+        .is_ok());
+    assert!(sess1.start_session().is_ok());
+    assert!(sess2.add_player(PlayerType::Local, PlayerHandle(1)).is_ok());
+    assert!(sess2
+        .add_player(PlayerType::Remote(addr1), PlayerHandle(0))
+        .is_ok());
+    assert!(sess2.start_session().is_ok());
+    for sess in [&mut sess1, &mut sess2].iter_mut() {
+        sess.set_checksum_interval(10);
+        sess.set_desync_policy(DesyncPolicy::Pause);
+    }
+
+    for _ in 0..10 {
+        sess1.poll_remote_clients();
+        sess2.poll_remote_clients();
+    }
+
+    // the second gamestate diverges from frame 30 on
+    for i in 0..80u32 {
+        let input = bincode::serialize(&i).unwrap();
+        if let Ok(requests) = sess1.advance_frame(PlayerHandle(0), &input) {
+            stub1.handle_requests(requests);
+        }
+        if let Ok(requests) = sess2.advance_frame(PlayerHandle(1), &input) {
+            stub2.handle_requests(requests);
+            if stub2.gs.frame >= 30 {
+                stub2.gs.state += 1;
+            }
+        }
+    }
+
+    let desyncs: Vec<GGRSEvent> = sess1
+        .events()
+        .filter(|event| matches!(event, GGRSEvent::DesyncDetected { .. }))
+        .collect();
+    assert!(!desyncs.is_empty());
+    for event in desyncs {
+        match event {
+            GGRSEvent::DesyncDetected {
+                player_handle,
+                frame,
+                local_checksum,
+                remote_checksum,
+            } => {
+                assert_eq!(player_handle, PlayerHandle(1));
+                assert!(frame >= 30);
+                assert_eq!(frame.0 % 10, 0);
+                assert_ne!(local_checksum, remote_checksum);
+            }
+            _ => unreachable!(),
+        }
+    }
+
+    // both sessions pause, so the desync does not spread any further
+    assert!(sess1.is_paused());
+    assert!(sess2.is_paused());
+}
+
+#[test]
+#[serial]
+fn test_desync_disconnects_deviating_player() {
+    let addrs: Vec<SocketAddr> = [7777, 8888, 9999]
+        .iter()
+        .map(|port| SocketAddr::new(IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)), *port))
+        .collect();
+    let mut stubs = [
+        stubs::GameStub::new(),
+        stubs::GameStub::new(),
+        stubs::GameStub::new(),
+    ];
+    let mut sessions = Vec::new();
+    for (local, addr) in addrs.iter().enumerate() {
+        let mut sess = ggrs::start_p2p_session(3, stubs::INPUT_SIZE, addr.port()).unwrap();
+        for (handle, remote_addr) in addrs.iter().enumerate() {
+            let player_type = if handle == local {
+                PlayerType::Local
+            } else {
+                PlayerType::Remote(*remote_addr)
+            };
+            assert!(sess.add_player(player_type, PlayerHandle(handle)).is_ok());
+        }
+        sess.set_checksum_interval(5);
+        sess.set_desync_policy(DesyncPolicy::DisconnectDeviating);
+        assert!(sess.start_session().is_ok());
+        sessions.push(sess);
+    }
+
+    for _ in 0..10 {
+        for sess in sessions.iter_mut() {
+            sess.poll_remote_clients();
+        }
+    }
+
+    // the gamestate of the third player diverges, the other two players outvote it
+    for i in 0..80u32 {
+        let input = bincode::serialize(&i).unwrap();
+        for (handle, (sess, stub)) in sessions.iter_mut().zip(stubs.iter_mut()).enumerate() {
+            if let Ok(requests) = sess.advance_frame(PlayerHandle(handle), &input) {
+                stub.handle_requests(requests);
+                if handle == 2 && stub.gs.frame >= 20 {
+                    stub.gs.state += 1;
+                }
+            }
+        }
+    }
+
+    for sess in sessions.iter_mut().take(2) {
+        let events: Vec<GGRSEvent> = sess.events().collect();
+        assert!(events.contains(&GGRSEvent::Disconnected {
+            player_handle: PlayerHandle(2)
+        }));
+        assert!(!events.iter().any(|event| matches!(
+            event,
+            GGRSEvent::DesyncDetected { player_handle, .. } if *player_handle != PlayerHandle(2)
+        )));
+    }
+}
+
+/// A socket that sends forged checksums for frames that are never compared along with every checksum the session sends.
+/// Since the forged messages need sequence numbers of their own, it renumbers all messages it sends.
+#[derive(Debug)]
+struct ChecksumForgingSocket {
+    socket: UdpNonBlockingSocket,
+    next_sequence_number: u16,
+}
+
+impl ChecksumForgingSocket {
+    /// Sends the message in the wire format with the next sequence number, which follows the version, the magic and the session id.
+    fn send_bytes(&mut self, mut bytes: Vec<u8>, addr: SocketAddr) {
+        bytes[7..9].copy_from_slice(&self.next_sequence_number.to_le_bytes());
+        self.next_sequence_number = self.next_sequence_number.wrapping_add(1);
+        self.socket
+            .send_to(&UdpMessage::from_bytes(&bytes).unwrap(), addr);
+    }
+
+    /// Encodes a state checksum message with the header of `msg` for the frame, which is encoded as a zigzag varint.
+    fn forge(msg: &[u8], frame: i64) -> Vec<u8> {
+        let mut bytes = msg[..10].to_vec();
+        let mut value = (frame << 1) as u64;
+        while value >= 0x80 {
+            bytes.push(value as u8 | 0x80);
+            value >>= 7;
+        }
+        bytes.push(value as u8);
+        bytes.extend_from_slice(&u64::MAX.to_le_bytes());
+        bytes
+    }
+}
+
+impl NonBlockingSocket for ChecksumForgingSocket {
+    fn send_to(&mut self, msg: &UdpMessage, addr: SocketAddr) {
+        // the type of the message follows the 9 bytes of the header in the wire format, and 10 is a state checksum message
+        let bytes = msg.to_bytes();
+        if bytes[9] == 10 {
+            for k in 0..20 {
+                // frames that are not a multiple of the interval, and frames far ahead of the match
+                self.send_bytes(Self::forge(&bytes, 10 * k + 1), addr);
+                self.send_bytes(Self::forge(&bytes, 100_000 + 10 * k), addr);
+            }
+        }
+        self.send_bytes(bytes, addr);
+    }
+
+    fn receive_all_messages(&mut self) -> Vec<(SocketAddr, UdpMessage)> {
+        self.socket.receive_all_messages()
+    }
+}
+
+#[test]
+#[serial]
+fn test_forged_checksums_do_not_hide_desyncs() {
+    let mut stub1 = stubs::GameStub::new();
+    let mut stub2 = stubs::GameStub::new();
+    let mut sess1 = ggrs::start_p2p_session(2, stubs::INPUT_SIZE, 7777).unwrap();
+    let socket = ChecksumForgingSocket {
+        socket: UdpNonBlockingSocket::bind_to_port(8888).unwrap(),
+        next_sequence_number: 0,
+    };
+    let mut sess2 = ggrs::start_p2p_session_with_socket(2, stubs::INPUT_SIZE, socket).unwrap();
+    let addr1 = SocketAddr::new(IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)), 7777);
+    let addr2 = SocketAddr::new(IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)), 8888);
+
+    sess1
+        .add_player(PlayerType::Local, PlayerHandle(0))
+        .unwrap();
+    sess1
+        .add_player(PlayerType::Remote(addr2), PlayerHandle(1))
+        .unwrap();
+    sess1.start_session().unwrap();
+    sess2
+        .add_player(PlayerType::Local, PlayerHandle(1))
+        .unwrap();
+    sess2
+        .add_player(PlayerType::Remote(addr1), PlayerHandle(0))
+        .unwrap();
+    sess2.start_session().unwrap();
+    for sess in [&mut sess1, &mut sess2].iter_mut() {
+        sess.set_checksum_interval(10);
+    }
+
+    for _ in 0..10 {
+        sess1.poll_remote_clients();
+        sess2.poll_remote_clients();
+    }
+
+    // the second gamestate diverges from frame 30 on, while the second player floods the first with checksums of other frames
+    for i in 0..80u32 {
+        let input = bincode::serialize(&i).unwrap();
+        if let Ok(requests) = sess1.advance_frame(PlayerHandle(0), &input) {
+            stub1.handle_requests(requests);
+        }
+        if let Ok(requests) = sess2.advance_frame(PlayerHandle(1), &input) {
+            stub2.handle_requests(requests);
+            if stub2.gs.frame >= 30 {
+                stub2.gs.state += 1;
+            }
+        }
+    }
+
+    let desync_frames: Vec<Frame> = sess1
+        .events()
+        .filter_map(|event| match event {
+            GGRSEvent::DesyncDetected { frame, .. } => Some(frame),
+            _ => None,
+        })
+        .collect();
+    assert!(!desync_frames.is_empty());
+    assert!(desync_frames
+        .iter()
+        .all(|frame| *frame >= 30 && frame.0 % 10 == 0 && *frame < 100));
+}
+
+#[test]
+#[serial]
+fn test_max_rollback_depth() {
+    let mut stub1 = stubs::GameStub::new();
+    let mut stub2 = stubs::GameStub::new();
+    let mut sess1 = ggrs::start_p2p_session(2, stubs::INPUT_SIZE, 7777).unwrap();
+    let mut sess2 = ggrs::start_p2p_session(2, stubs::INPUT_SIZE, 8888).unwrap();
+    let addr1 = SocketAddr::new(IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)), 7777);
+    let addr2 = SocketAddr::new(IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)), 8888);
+
+    // the depth can not be combined with sparse saving
+    sess1.set_max_rollback_depth(2);
+    assert!(sess1.set_sparse_saving(true).is_ok());
+    assert!(sess1.add_player(PlayerType::Local, PlayerHandle(0)).is_ok());
+    assert!(sess1
+        .add_player(PlayerType::Remote(addr2), PlayerHandle(1))
+        .is_ok());
+    assert!(sess1.start_session().is_err());
+    assert!(sess1.set_sparse_saving(false).is_ok());
+    assert!(sess1.start_session().is_ok());
+    assert!(sess2.add_player(PlayerType::Local, PlayerHandle(1)).is_ok());
+    assert!(sess2
+        .add_player(PlayerType::Remote(addr1), PlayerHandle(0))
+        .is_ok());
+    assert!(sess2.start_session().is_ok());
+
+    for _ in 0..10 {
+        sess1.poll_remote_clients();
+        sess2.poll_remote_clients();
+    }
+    assert!(sess1.current_state() == SessionState::Running);
+    assert!(sess2.current_state() == SessionState::Running);
+    sess1.events().for_each(drop);
+
+    // the first session predicts 6 frames of the second player wrongly
+    let input1 = bincode::serialize(&0u32).unwrap();
+    let input2 = bincode::serialize(&1u32).unwrap();
+    for _ in 0..6 {
+        stub1.handle_requests(sess1.advance_frame(PlayerHandle(0), &input1).unwrap());
+    }
+    for _ in 0..6 {
+        sess2.poll_remote_clients();
+        stub2.handle_requests(sess2.advance_frame(PlayerHandle(1), &input2).unwrap());
+    }
+
+    // the correction is spread over the next frames, resimulating two frames at a time
+    let mut frames = Vec::new();
+    for _ in 0..3 {
+        sess1.poll_remote_clients();
+        stub1.handle_requests(sess1.advance_frame(PlayerHandle(0), &input1).unwrap());
+        frames.push(stub1.gs.frame);
+    }
+    assert_eq!(frames, vec![3, 6, 9]);
+    assert_eq!(sess1.current_frame(), 9);
+    let deferred: Vec<GGRSEvent> = sess1
+        .events()
+        .filter(|event| matches!(event, GGRSEvent::CorrectionDeferred { .. }))
+        .collect();
+    assert_eq!(
+        deferred,
+        vec![
+            GGRSEvent::CorrectionDeferred {
+                remaining_frames: 4
+            },
+            GGRSEvent::CorrectionDeferred {
+                remaining_frames: 2
+            }
+        ]
+    );
+}
+
+#[test]
+#[serial]
+fn test_batched_resimulation() {
+    let mut stub1 = stubs::GameStub::new();
+    let mut stub2 = stubs::GameStub::new();
+    let mut sess1 = ggrs::start_p2p_session(2, stubs::INPUT_SIZE, 7777).unwrap();
+    let mut sess2 = ggrs::start_p2p_session(2, stubs::INPUT_SIZE, 8888).unwrap();
+    let addr1 = SocketAddr::new(IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)), 7777);
+    let addr2 = SocketAddr::new(IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)), 8888);
+
+    sess1.set_batched_resimulation(true);
+    assert!(sess1.add_player(PlayerType::Local, PlayerHandle(0)).is_ok());
+    assert!(sess1
+        .add_player(PlayerType::Remote(addr2), PlayerHandle(1))
+        .is_ok());
+    assert!(sess1.start_session().is_ok());
+    assert!(sess2.add_player(PlayerType::Local, PlayerHandle(1)).is_ok());
+    assert!(sess2
+        .add_player(PlayerType::Remote(addr1), PlayerHandle(0))
+        .is_ok());
+    assert!(sess2.start_session().is_ok());
+
+    for _ in 0..10 {
+        sess1.poll_remote_clients();
+        sess2.poll_remote_clients();
+    }
+    assert!(sess1.current_state() == SessionState::Running);
+    assert!(sess2.current_state() == SessionState::Running);
+
+    // the first session predicts 4 frames of the second player wrongly
+    let input1 = bincode::serialize(&0u32).unwrap();
+    let input2 = bincode::serialize(&1u32).unwrap();
+    for _ in 0..4 {
+        stub1.handle_requests(sess1.advance_frame(PlayerHandle(0), &input1).unwrap());
+    }
+    for _ in 0..4 {
+        sess2.poll_remote_clients();
+        stub2.handle_requests(sess2.advance_frame(PlayerHandle(1), &input2).unwrap());
+    }
+
+    // the rollback resimulates all frames with a single request
+    sess1.poll_remote_clients();
+    let requests = sess1.advance_frame(PlayerHandle(0), &input1).unwrap();
+    assert_eq!(requests.len(), 4);
+    assert!(matches!(requests[0], GGRSRequest::LoadGameState { .. }));
+    match &requests[1] {
+        GGRSRequest::Resimulate {
+            from_frame,
+            inputs_per_frame,
+            cells,
+        } => {
+            assert_eq!(*from_frame, 0);
+            assert_eq!(inputs_per_frame.len(), 4);
+            assert!(cells.iter().all(Option::is_some));
+            for (frame, inputs) in inputs_per_frame.iter().enumerate() {
+                assert_eq!(inputs[1].frame, Frame(frame as i64));
+                assert_eq!(inputs[1].input(), &input2[..]);
+            }
+        }
+        _ => panic!("expected a batched resimulation"),
+    }
+    assert!(matches!(requests[2], GGRSRequest::SaveGameState { .. }));
+    assert!(matches!(requests[3], GGRSRequest::AdvanceFrame { .. }));
+    stub1.handle_requests(requests);
+    assert_eq!(stub1.gs.frame, 5);
+}
+
+#[test]
+#[serial]
+fn test_max_frames_ahead() {
+    let mut stub1 = stubs::GameStub::new();
+    let mut stub2 = stubs::GameStub::new();
+    let mut sess1 = ggrs::start_p2p_session(2, stubs::INPUT_SIZE, 7777).unwrap();
+    let mut sess2 = ggrs::start_p2p_session(2, stubs::INPUT_SIZE, 8888).unwrap();
+    let addr1 = SocketAddr::new(IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)), 7777);
+    let addr2 = SocketAddr::new(IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)), 8888);
+
+    sess1.set_max_frames_ahead(3);
+    assert!(sess1.add_player(PlayerType::Local, PlayerHandle(0)).is_ok());
+    assert!(sess1
+        .add_player(PlayerType::Remote(addr2), PlayerHandle(1))
+        .is_ok());
+    assert!(sess1.start_session().is_ok());
+    assert!(sess2.add_player(PlayerType::Local, PlayerHandle(1)).is_ok());
+    assert!(sess2
+        .add_player(PlayerType::Remote(addr1), PlayerHandle(0))
+        .is_ok());
+    assert!(sess2.start_session().is_ok());
+
+    for _ in 0..10 {
+        sess1.poll_remote_clients();
+        sess2.poll_remote_clients();
+    }
+    assert!(sess1.current_state() == SessionState::Running);
+    assert!(sess2.current_state() == SessionState::Running);
+
+    // the second session only advances a single frame, so the first one runs ahead until it hits the limit
+    let input = bincode::serialize(&0u32).unwrap();
+    stub2.handle_requests(sess2.advance_frame(PlayerHandle(1), &input).unwrap());
+    let mut result = Ok(());
+    for _ in 0..ggrs::MAX_PREDICTION_FRAMES {
+        sess2.poll_remote_clients();
+        match sess1.advance_frame(PlayerHandle(0), &input) {
+            Ok(requests) => stub1.handle_requests(requests),
+            Err(e) => {
+                result = Err(e);
+                break;
+            }
+        }
+    }
+    assert_eq!(result, Err(GGRSError::TooFarAhead));
+    assert!(stub1.gs.frame > 3 && stub1.gs.frame < ggrs::MAX_PREDICTION_FRAMES as i32);
+
+    // once the second session catches up, the first one advances again
+    for _ in 0..stub1.gs.frame {
+        sess1.poll_remote_clients();
+        stub2.handle_requests(sess2.advance_frame(PlayerHandle(1), &input).unwrap());
+    }
+    sess1.poll_remote_clients();
+    assert!(sess1.advance_frame(PlayerHandle(0), &input).is_ok());
+}
+
+#[test]
+#[serial]
+fn test_misprediction_event() {
+    let mut stub1 = stubs::GameStub::new();
+    let mut stub2 = stubs::GameStub::new();
+    let mut sess1 = ggrs::start_p2p_session(2, stubs::INPUT_SIZE, 7777).unwrap();
+    let mut sess2 = ggrs::start_p2p_session(2, stubs::INPUT_SIZE, 8888).unwrap();
+    let addr1 = SocketAddr::new(IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)), 7777);
+    let addr2 = SocketAddr::new(IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)), 8888);
+
+    assert!(sess1.add_player(PlayerType::Local, PlayerHandle(0)).is_ok());
+    assert!(sess1
+        .add_player(PlayerType::Remote(addr2), PlayerHandle(1))
+        .is_ok());
+    assert!(sess1.start_session().is_ok());
+    assert!(sess2.add_player(PlayerType::Local, PlayerHandle(1)).is_ok());
+    assert!(sess2
+        .add_player(PlayerType::Remote(addr1), PlayerHandle(0))
+        .is_ok());
+    assert!(sess2.start_session().is_ok());
+    for _ in 0..10 {
+        sess1.poll_remote_clients();
+        sess2.poll_remote_clients();
+    }
+
+    // the first session predicts blank inputs for the first frames, but the second player presses something from frame 2 on
+    let blank = bincode::serialize(&0u32).unwrap();
+    let pressed = bincode::serialize(&5u32).unwrap();
+    for _ in 0..4 {
+        stub1.handle_requests(sess1.advance_frame(PlayerHandle(0), &blank).unwrap());
+    }
+    for i in 0..4 {
+        let input = if i < 2 { &blank } else { &pressed };
+        stub2.handle_requests(sess2.advance_frame(PlayerHandle(1), input).unwrap());
+    }
+    sess1.events().for_each(drop);
+
+    sess1.poll_remote_clients();
+    stub1.handle_requests(sess1.advance_frame(PlayerHandle(0), &blank).unwrap());
+    assert!(sess1.events().any(|event| event
+        == GGRSEvent::Misprediction {
+            first_incorrect_frame: Frame(2),
+        }));
+}
+
+#[test]
+#[serial]
+fn test_pending_output_limit() {
+    let mut stub1 = stubs::GameStub::new();
+    let mut sess1 = ggrs::start_p2p_session(2, stubs::INPUT_SIZE, 7777).unwrap();
+    let mut sess2 = ggrs::start_p2p_session(2, stubs::INPUT_SIZE, 8888).unwrap();
+    let addr1 = SocketAddr::new(IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)), 7777);
+    let addr2 = SocketAddr::new(IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)), 8888);
+
+    assert!(sess1
+        .set_pending_output_limit(0, PendingOutputPolicy::BlockAdvance)
+        .is_err());
+    assert!(sess1
+        .set_pending_output_limit(129, PendingOutputPolicy::BlockAdvance)
+        .is_err());
+    sess1
+        .set_pending_output_limit(4, PendingOutputPolicy::BlockAdvance)
+        .unwrap();
+    assert!(sess1.add_player(PlayerType::Local, PlayerHandle(0)).is_ok());
+    assert!(sess1
+        .add_player(PlayerType::Remote(addr2), PlayerHandle(1))
+        .is_ok());
+    assert!(sess1.start_session().is_ok());
+    assert!(sess2.add_player(PlayerType::Local, PlayerHandle(1)).is_ok());
+    assert!(sess2
+        .add_player(PlayerType::Remote(addr1), PlayerHandle(0))
+        .is_ok());
+    assert!(sess2.start_session().is_ok());
+    assert!(sess1
+        .set_pending_output_limit(8, PendingOutputPolicy::Disconnect)
+        .is_err());
+
+    for _ in 0..10 {
+        sess1.poll_remote_clients();
+        sess2.poll_remote_clients();
+    }
+    assert!(sess1.current_state() == SessionState::Running);
+
+    // the second session does not acknowledge anything, so the first one stops well before the prediction threshold
+    let input = bincode::serialize(&0u32).unwrap();
+    for _ in 0..ggrs::MAX_PREDICTION_FRAMES {
+        match sess1.advance_frame(PlayerHandle(0), &input) {
+            Ok(requests) => stub1.handle_requests(requests),
+            Err(e) => assert_eq!(e, GGRSError::PredictionThreshold),
+        }
+    }
+    assert_eq!(sess1.current_frame(), Frame(4));
+
+    // once the inputs are acknowledged, the first session advances again
+    for _ in 0..10 {
+        sess2.poll_remote_clients();
+        sess1.poll_remote_clients();
+    }
+    assert!(sess1.advance_frame(PlayerHandle(0), &input).is_ok());
+}
+
+#[test]
+#[serial]
+fn test_pending_output_drop_oldest() {
+    let mut stub1 = stubs::GameStub::new();
+    let mut sess1 = ggrs::start_p2p_session(2, stubs::INPUT_SIZE, 7777).unwrap();
+    let mut sess2 = ggrs::start_p2p_session(2, stubs::INPUT_SIZE, 8888).unwrap();
+    let addr1 = SocketAddr::new(IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)), 7777);
+    let addr2 = SocketAddr::new(IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)), 8888);
+
+    sess1
+        .set_pending_output_limit(4, PendingOutputPolicy::DropOldest)
+        .unwrap();
+    assert!(sess1.add_player(PlayerType::Local, PlayerHandle(0)).is_ok());
+    assert!(sess1
+        .add_player(PlayerType::Remote(addr2), PlayerHandle(1))
+        .is_ok());
+    assert!(sess1.start_session().is_ok());
+    assert!(sess2.add_player(PlayerType::Local, PlayerHandle(1)).is_ok());
+    assert!(sess2
+        .add_player(PlayerType::Remote(addr1), PlayerHandle(0))
+        .is_ok());
+    assert!(sess2.start_session().is_ok());
+
+    for _ in 0..10 {
+        sess1.poll_remote_clients();
+        sess2.poll_remote_clients();
+    }
+    sess1.events().for_each(drop);
+
+    // without acknowledgements, the first session drops the oldest pending input with every new input
+    let input = bincode::serialize(&0u32).unwrap();
+    for _ in 0..6 {
+        stub1.handle_requests(sess1.advance_frame(PlayerHandle(0), &input).unwrap());
+    }
+    sess1.poll_remote_clients();
+    let dropped: Vec<GGRSEvent> = sess1
+        .events()
+        .filter(|event| matches!(event, GGRSEvent::PendingInputsDropped { .. }))
+        .collect();
+    assert_eq!(
+        dropped,
+        vec![
+            GGRSEvent::PendingInputsDropped {
+                player_handle: PlayerHandle(1),
+                count: 1,
+            };
+            2
+        ]
+    );
+}
+
+#[test]
+#[serial]
+fn test_timed_events() {
+    let mut sess1 = ggrs::start_p2p_session(2, stubs::INPUT_SIZE, 7777).unwrap();
+    let mut sess2 = ggrs::start_p2p_session(2, stubs::INPUT_SIZE, 8888).unwrap();
+    let addr1 = SocketAddr::new(IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)), 7777);
+    let addr2 = SocketAddr::new(IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)), 8888);
+
+    let time = ManualTimeSource::new();
+    time.advance(Duration::from_secs(5));
+    assert!(sess1.set_time_source(time.clone()).is_ok());
+    assert!(sess1.add_player(PlayerType::Local, PlayerHandle(0)).is_ok());
+    assert!(sess1
+        .add_player(PlayerType::Remote(addr2), PlayerHandle(1))
+        .is_ok());
+    assert!(sess1.start_session().is_ok());
+    assert!(sess2.add_player(PlayerType::Local, PlayerHandle(1)).is_ok());
+    assert!(sess2
+        .add_player(PlayerType::Remote(addr1), PlayerHandle(0))
+        .is_ok());
+    assert!(sess2.start_session().is_ok());
+
+    for _ in 0..10 {
+        sess1.poll_remote_clients();
+        sess2.poll_remote_clients();
+    }
+
+    // the session synchronizes before running the first frame
+    let synchronized = sess1
+        .timed_events()
+        .find(|timed| {
+            timed.event
+                == GGRSEvent::Synchronized {
+                    player_handle: PlayerHandle(1),
+                }
+        })
+        .unwrap();
+    assert_eq!(synchronized.frame, 0);
+    assert_eq!(synchronized.timestamp, Duration::from_secs(5));
+    assert!(sess1.set_time_source(time).is_err());
+}
+
+#[test]
+#[serial]
+fn test_disconnect_timeout_with_manual_time() {
+    let mut sess1 = ggrs::start_p2p_session(2, stubs::INPUT_SIZE, 7777).unwrap();
+    let mut sess2 = ggrs::start_p2p_session(2, stubs::INPUT_SIZE, 8888).unwrap();
+    let addr1 = SocketAddr::new(IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)), 7777);
+    let addr2 = SocketAddr::new(IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)), 8888);
+
+    let time = ManualTimeSource::new();
+    sess1.set_time_source(time.clone()).unwrap();
+    sess2.set_time_source(time.clone()).unwrap();
+    sess1.set_disconnect_timeout(Duration::from_secs(2));
+    sess1.set_disconnect_notify_delay(Duration::from_millis(500));
+    assert!(sess1.add_player(PlayerType::Local, PlayerHandle(0)).is_ok());
+    assert!(sess1
+        .add_player(PlayerType::Remote(addr2), PlayerHandle(1))
+        .is_ok());
+    assert!(sess1.start_session().is_ok());
+    assert!(sess2.add_player(PlayerType::Local, PlayerHandle(1)).is_ok());
+    assert!(sess2
+        .add_player(PlayerType::Remote(addr1), PlayerHandle(0))
+        .is_ok());
+    assert!(sess2.start_session().is_ok());
+
+    for _ in 0..10 {
+        sess1.poll_remote_clients();
+        sess2.poll_remote_clients();
+    }
+    assert!(sess1.current_state() == SessionState::Running);
+    sess1.events().for_each(drop);
+
+    // the remote player stops answering, but the session only notices once time passes
+    for _ in 0..10 {
+        sess1.poll_remote_clients();
+    }
+    assert_eq!(sess1.events().count(), 0);
+
+    time.advance(Duration::from_secs(1));
+    sess1.poll_remote_clients();
+    assert!(sess1.events().any(|event| matches!(
+        event,
+        GGRSEvent::NetworkInterrupted {
+            player_handle: PlayerHandle(1),
+            ..
+        }
+    )));
+
+    time.advance(Duration::from_millis(1001));
+    sess1.poll_remote_clients();
+    assert!(sess1.events().any(|event| event
+        == GGRSEvent::Disconnected {
+            player_handle: PlayerHandle(1)
+        }));
+}
+
+#[test]
+#[serial]
+fn test_idle_synchronizes() {
+    let mut sess1 = ggrs::start_p2p_session(2, stubs::INPUT_SIZE, 7777).unwrap();
+    let mut sess2 = ggrs::start_p2p_session(2, stubs::INPUT_SIZE, 8888).unwrap();
+    let addr1 = SocketAddr::new(IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)), 7777);
+    let addr2 = SocketAddr::new(IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)), 8888);
+
+    assert!(sess1.add_player(PlayerType::Local, PlayerHandle(0)).is_ok());
+    assert!(sess1
+        .add_player(PlayerType::Remote(addr2), PlayerHandle(1))
+        .is_ok());
+    assert!(sess1.start_session().is_ok());
+    assert!(sess2.add_player(PlayerType::Local, PlayerHandle(1)).is_ok());
+    assert!(sess2
+        .add_player(PlayerType::Remote(addr1), PlayerHandle(0))
+        .is_ok());
+    assert!(sess2.start_session().is_ok());
+
+    // idling waits for at least the given duration
+    let start = Instant::now();
+    for _ in 0..10 {
+        sess1.idle(Duration::from_millis(1));
+        sess2.idle(Duration::ZERO);
+    }
+    assert!(start.elapsed() >= Duration::from_millis(10));
+
+    assert!(sess1.current_state() == SessionState::Running);
+    assert!(sess2.current_state() == SessionState::Running);
+}
+
+#[test]
+#[serial]
+fn test_prediction_threshold_rejects_input() {
+    for sparse_saving in [false, true] {
+        let mut stub1 = stubs::GameStub::new();
+        let mut stub2 = stubs::GameStub::new();
+        let mut sess1 = ggrs::start_p2p_session(2, stubs::INPUT_SIZE, 7777).unwrap();
+        let mut sess2 = ggrs::start_p2p_session(2, stubs::INPUT_SIZE, 8888).unwrap();
+        let addr1 = SocketAddr::new(IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)), 7777);
+        let addr2 = SocketAddr::new(IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)), 8888);
+
+        sess1.set_sparse_saving(sparse_saving).unwrap();
+        sess1.set_confirmed_inputs_enabled(true);
+        assert!(sess1.add_player(PlayerType::Local, PlayerHandle(0)).is_ok());
+        assert!(sess1
+            .add_player(PlayerType::Remote(addr2), PlayerHandle(1))
+            .is_ok());
+        assert!(sess1.start_session().is_ok());
+        assert!(sess2.add_player(PlayerType::Local, PlayerHandle(1)).is_ok());
+        assert!(sess2
+            .add_player(PlayerType::Remote(addr1), PlayerHandle(0))
+            .is_ok());
+        assert!(sess2.start_session().is_ok());
+
+        for _ in 0..50 {
+            sess1.poll_remote_clients();
+            sess2.poll_remote_clients();
+        }
+        assert!(sess1.current_state() == SessionState::Running);
+        assert!(sess2.current_state() == SessionState::Running);
+
+        // the second session does not advance, so the first one runs into the prediction threshold
+        let input = |value: u32| bincode::serialize(&value).unwrap();
+        let mut next_value = 0;
+        while let Ok(requests) = sess1.advance_frame(PlayerHandle(0), &input(next_value)) {
+            stub1.handle_requests(requests);
+            next_value += 1;
+        }
+        for _ in 0..3 {
+            let result = sess1.advance_frame(PlayerHandle(0), &input(1000));
+            assert!(matches!(result, Err(GGRSError::PredictionThreshold)));
+            assert_eq!(sess1.current_frame(), Frame(next_value as i64));
+        }
+
+        // inputs that differ from the predictions force a rollback once the first session advances again
+        for value in 0..next_value {
+            let requests = sess2
+                .advance_frame(PlayerHandle(1), &input(value + 1))
+                .unwrap();
+            stub2.handle_requests(requests);
+        }
+        for value in next_value..next_value + 4 {
+            sess1.poll_remote_clients();
+            let requests = sess1.advance_frame(PlayerHandle(0), &input(value)).unwrap();
+            stub1.handle_requests(requests);
+            sess2.poll_remote_clients();
+            let requests = sess2
+                .advance_frame(PlayerHandle(1), &input(value + 1))
+                .unwrap();
+            stub2.handle_requests(requests);
+        }
+
+        // the rejected inputs are gone, the retried inputs follow the inputs before without a gap
+        let confirmed: Vec<_> = sess1.confirmed_inputs().collect();
+        assert!(confirmed.len() > next_value as usize);
+        for (i, (frame, inputs)) in confirmed.iter().enumerate() {
+            assert_eq!(*frame, Frame(i as i64));
+            assert_eq!(inputs[0].input(), &input(i as u32)[..]);
+        }
+    }
+}
+
+/// A clock that advances by a millisecond every time it is read.
+#[derive(Debug, Default)]
+struct SteppingTimeSource {
+    now: Mutex<Duration>,
+}
+
+impl ggrs::TimeSource for SteppingTimeSource {
+    fn now(&self) -> Duration {
+        let mut now = self.now.lock().unwrap();
+        *now += Duration::from_millis(1);
+        *now
+    }
+}
+
+#[test]
+#[serial]
+fn test_telemetry_measures_advance_frame() {
+    let mut sess = ggrs::start_p2p_session(1, stubs::INPUT_SIZE, 7777).unwrap();
+    assert!(sess.set_time_source(SteppingTimeSource::default()).is_ok());
+    assert!(sess.add_player(PlayerType::Local, PlayerHandle(0)).is_ok());
+    assert!(sess.start_session().is_ok());
+    assert_eq!(sess.telemetry().advance_frame_time, Duration::ZERO);
+
+    let input = bincode::serialize(&0u32).unwrap();
+    // without remote players, there are no rollbacks, so the requests can be ignored
+    for _ in 0..3 {
+        assert!(sess.advance_frame(PlayerHandle(0), &input).is_ok());
+    }
+
+    let telemetry = sess.telemetry();
+    assert!(telemetry.max_advance_frame_time > Duration::ZERO);
+    assert!(telemetry.advance_frame_time >= telemetry.max_advance_frame_time);
 }
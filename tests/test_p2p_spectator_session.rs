@@ -1,5 +1,6 @@
-use ggrs::{PlayerType, SessionState};
+use ggrs::{Frame, GGRSError, GGRSEvent, PlayerHandle, PlayerType, SessionState};
 use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+use std::time::Duration;
 
 use serial_test::serial;
 
@@ -20,6 +21,46 @@ fn test_start_session() {
         ggrs::start_p2p_spectator_session(1, stubs::INPUT_SIZE, 9999, host_addr).unwrap();
     assert!(spec_sess.start_session().is_ok());
     assert!(spec_sess.current_state() == SessionState::Synchronizing);
+    // no input has been received yet
+    assert_eq!(spec_sess.frames_behind_host(), 0);
+}
+
+#[test]
+#[serial]
+fn test_start_session_with_invalid_config() {
+    let host_addr = SocketAddr::new(IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)), 7777);
+    let mut spec_sess =
+        ggrs::start_p2p_spectator_session(1, stubs::INPUT_SIZE, 9999, host_addr).unwrap();
+    // before the session has been started, the configuration is only validated as a whole
+    assert!(spec_sess.set_fps(0).is_ok());
+    assert!(spec_sess.set_catchup_speed(20).is_ok()); // larger than max frames behind
+    match spec_sess.start_session() {
+        Err(GGRSError::InvalidConfiguration { errors }) => assert_eq!(errors.len(), 2),
+        _ => panic!("expected an invalid configuration"),
+    }
+    assert!(spec_sess.current_state() == SessionState::Initializing);
+
+    // the order of setters does not matter
+    spec_sess.set_fps(60).unwrap();
+    spec_sess.set_catchup_speed(20).unwrap();
+    spec_sess.set_max_frames_behind(30).unwrap();
+    assert!(spec_sess.start_session().is_ok());
+
+    // after starting, invalid values are rejected and the previous ones are kept
+    assert!(matches!(
+        spec_sess.set_fps(0),
+        Err(GGRSError::InvalidRequest { .. })
+    ));
+    assert!(matches!(
+        spec_sess.set_catchup_speed(30),
+        Err(GGRSError::InvalidRequest { .. })
+    ));
+    assert!(matches!(
+        spec_sess.set_max_frames_behind(10),
+        Err(GGRSError::InvalidRequest { .. })
+    ));
+    assert!(spec_sess.set_catchup_speed(10).is_ok());
+    assert!(spec_sess.set_max_frames_behind(15).is_ok());
 }
 
 #[test]
@@ -32,9 +73,11 @@ fn test_synchronize_with_host() {
     let mut spec_sess =
         ggrs::start_p2p_spectator_session(1, stubs::INPUT_SIZE, 8888, host_addr).unwrap();
 
-    host_sess.add_player(PlayerType::Local, 0).unwrap();
     host_sess
-        .add_player(PlayerType::Spectator(spec_addr), 2)
+        .add_player(PlayerType::Local, PlayerHandle(0))
+        .unwrap();
+    host_sess
+        .add_player(PlayerType::Spectator(spec_addr), PlayerHandle(2))
         .unwrap();
 
     host_sess.start_session().unwrap();
@@ -51,3 +94,511 @@ fn test_synchronize_with_host() {
     assert_eq!(spec_sess.current_state(), SessionState::Running);
     assert_eq!(host_sess.current_state(), SessionState::Running);
 }
+
+#[test]
+#[serial]
+fn test_reconnect_to_host() {
+    let host_addr = SocketAddr::new(IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)), 7777);
+    let spec_addr = SocketAddr::new(IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)), 8888);
+    // nobody answers on the address the spectator tries first
+    let wrong_addr = SocketAddr::new(IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)), 9999);
+
+    let mut host_sess = ggrs::start_p2p_session(1, stubs::INPUT_SIZE, 7777).unwrap();
+    let mut spec_sess =
+        ggrs::start_p2p_spectator_session(1, stubs::INPUT_SIZE, 8888, wrong_addr).unwrap();
+    assert_eq!(spec_sess.host_address(), wrong_addr);
+    assert!(spec_sess.reconnect(host_addr).is_err()); // cannot reconnect before starting
+
+    host_sess
+        .add_player(PlayerType::Local, PlayerHandle(0))
+        .unwrap();
+    host_sess
+        .add_player(PlayerType::Spectator(spec_addr), PlayerHandle(2))
+        .unwrap();
+    // the host retries quickly, since the spectator ignores its first sync requests
+    host_sess.set_sync_retries(Duration::from_millis(1), None);
+    host_sess.start_session().unwrap();
+    spec_sess.start_session().unwrap();
+
+    for _ in 0..10 {
+        spec_sess.poll_remote_clients();
+        host_sess.poll_remote_clients();
+    }
+    assert_eq!(spec_sess.current_state(), SessionState::Synchronizing);
+
+    // the spectator finds the host at the right address
+    spec_sess.reconnect(host_addr).unwrap();
+    assert_eq!(spec_sess.host_address(), host_addr);
+    for _ in 0..20 {
+        std::thread::sleep(Duration::from_millis(2));
+        spec_sess.poll_remote_clients();
+        host_sess.poll_remote_clients();
+    }
+    assert_eq!(spec_sess.current_state(), SessionState::Running);
+    assert_eq!(host_sess.current_state(), SessionState::Running);
+
+    let advance = |host_sess: &mut ggrs::P2PSession, spec_sess: &mut ggrs::P2PSpectatorSession| {
+        for i in 0..10u32 {
+            let serialized_input = bincode::serialize(&i).unwrap();
+            host_sess
+                .advance_frame(PlayerHandle(0), &serialized_input)
+                .unwrap();
+            for _ in 0..5 {
+                spec_sess.poll_remote_clients();
+                host_sess.poll_remote_clients();
+            }
+            let _ = spec_sess.advance_frame();
+        }
+    };
+    advance(&mut host_sess, &mut spec_sess);
+    let frame = spec_sess.current_frame();
+    assert!(frame > 0);
+
+    // reconnecting to the same host continues with the next frame
+    spec_sess.reconnect(host_addr).unwrap();
+    assert_eq!(spec_sess.current_state(), SessionState::Synchronizing);
+    advance(&mut host_sess, &mut spec_sess);
+    assert_eq!(spec_sess.current_state(), SessionState::Running);
+    assert!(spec_sess.current_frame() > frame);
+    assert!(!spec_sess
+        .events()
+        .any(|event| matches!(event, GGRSEvent::InvalidMessage { .. })));
+}
+
+#[test]
+#[serial]
+fn test_jump_to_live() {
+    let host_addr = SocketAddr::new(IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)), 7777);
+    let spec_addr = SocketAddr::new(IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)), 8888);
+
+    let mut host_sess = ggrs::start_p2p_session(1, stubs::INPUT_SIZE, 7777).unwrap();
+    let mut spec_sess =
+        ggrs::start_p2p_spectator_session(1, stubs::INPUT_SIZE, 8888, host_addr).unwrap();
+
+    host_sess
+        .add_player(PlayerType::Local, PlayerHandle(0))
+        .unwrap();
+    host_sess
+        .add_player(PlayerType::Spectator(spec_addr), PlayerHandle(2))
+        .unwrap();
+
+    spec_sess.set_jump_to_live_threshold(Some(5)); // not larger than max frames behind
+    assert!(spec_sess.start_session().is_err());
+    spec_sess.set_jump_to_live_threshold(Some(20));
+
+    host_sess.start_session().unwrap();
+    spec_sess.start_session().unwrap();
+
+    for _ in 0..10 {
+        spec_sess.poll_remote_clients();
+        host_sess.poll_remote_clients();
+    }
+    assert_eq!(spec_sess.current_state(), SessionState::Running);
+
+    // the host advances while the spectator only receives inputs
+    for i in 0..30u32 {
+        let serialized_input = bincode::serialize(&i).unwrap();
+        host_sess
+            .advance_frame(PlayerHandle(0), &serialized_input)
+            .unwrap();
+        spec_sess.poll_remote_clients();
+    }
+    for _ in 0..10 {
+        host_sess.poll_remote_clients();
+        spec_sess.poll_remote_clients();
+    }
+    assert!(spec_sess.frames_behind_host() > 20);
+    spec_sess.events().for_each(drop);
+
+    assert_eq!(spec_sess.current_frame(), 0);
+    let requests = spec_sess.advance_frame().unwrap();
+    assert_eq!(spec_sess.frames_behind_host(), 0);
+    assert!(spec_sess.current_frame() <= host_sess.current_frame());
+    assert_eq!(spec_sess.confirmed_frame(), spec_sess.current_frame() - 1);
+    let skipped: Vec<GGRSEvent> = spec_sess
+        .events()
+        .filter(|event| matches!(event, GGRSEvent::SkippedFrames { .. }))
+        .collect();
+    assert_eq!(
+        skipped,
+        vec![GGRSEvent::SkippedFrames {
+            from: Frame(-1),
+            to: Frame(requests.len() as i64 - 1),
+        }]
+    );
+}
+
+#[test]
+#[serial]
+fn test_late_spectators_disabled() {
+    let spec_addr = SocketAddr::new(IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)), 8888);
+
+    let mut host_sess = ggrs::start_p2p_session(1, stubs::INPUT_SIZE, 7777).unwrap();
+    host_sess
+        .add_player(PlayerType::Local, PlayerHandle(0))
+        .unwrap();
+    host_sess.start_session().unwrap();
+
+    // late spectators have to be allowed before the start
+    assert!(host_sess.set_late_spectators_enabled(true).is_err());
+    assert!(host_sess.add_spectator(spec_addr).is_err());
+    assert!(host_sess
+        .add_player(PlayerType::Spectator(spec_addr), PlayerHandle(2))
+        .is_err());
+    assert_eq!(host_sess.num_spectators(), 0);
+}
+
+#[test]
+#[serial]
+fn test_add_spectator_after_start() {
+    let host_addr = SocketAddr::new(IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)), 7777);
+    let spec_addr = SocketAddr::new(IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)), 8888);
+
+    let mut host_sess = ggrs::start_p2p_session(1, stubs::INPUT_SIZE, 7777).unwrap();
+    host_sess.set_late_spectators_enabled(true).unwrap();
+    host_sess
+        .add_player(PlayerType::Local, PlayerHandle(0))
+        .unwrap();
+    host_sess.start_session().unwrap();
+    host_sess.poll_remote_clients();
+    assert_eq!(host_sess.current_state(), SessionState::Running);
+
+    // only spectators can be added after the start
+    assert!(host_sess
+        .add_player(PlayerType::Local, PlayerHandle(0))
+        .is_err());
+
+    // the host plays for a while before the spectator joins
+    for i in 0..100u32 {
+        let serialized_input = bincode::serialize(&i).unwrap();
+        host_sess
+            .advance_frame(PlayerHandle(0), &serialized_input)
+            .unwrap();
+    }
+
+    host_sess
+        .add_player(PlayerType::Spectator(spec_addr), PlayerHandle(2))
+        .unwrap();
+    let mut spec_sess =
+        ggrs::start_p2p_spectator_session(1, stubs::INPUT_SIZE, 8888, host_addr).unwrap();
+    spec_sess.start_session().unwrap();
+
+    for _ in 0..10 {
+        spec_sess.poll_remote_clients();
+        host_sess.poll_remote_clients();
+    }
+    assert_eq!(spec_sess.current_state(), SessionState::Running);
+    assert_eq!(host_sess.num_spectators(), 1);
+    let stats = host_sess.spectator_stats();
+    assert_eq!(stats.len(), 1);
+    assert_eq!(stats[0].player_handle, 1002);
+    assert!(stats[0].frames_behind > 0);
+
+    // the spectator catches up on all frames the host has confirmed so far
+    let mut frames = 0;
+    for _ in 0..200 {
+        host_sess.poll_remote_clients();
+        if let Ok(requests) = spec_sess.advance_frame() {
+            frames += requests.len();
+        }
+        if frames == 99 {
+            break;
+        }
+        // give lost or dropped packets a chance to be resent
+        std::thread::sleep(std::time::Duration::from_millis(5));
+    }
+    assert_eq!(frames, 99);
+    host_sess.poll_remote_clients();
+    assert_eq!(host_sess.spectator_stats()[0].frames_behind, 0);
+}
+
+#[test]
+#[serial]
+fn test_late_join_input_history() {
+    let host_addr = SocketAddr::new(IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)), 7777);
+    let spec_addr = SocketAddr::new(IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)), 8888);
+
+    let mut host_sess = ggrs::start_p2p_session(1, stubs::INPUT_SIZE, 7777).unwrap();
+    host_sess.set_late_spectators_enabled(true).unwrap();
+    host_sess
+        .add_player(PlayerType::Local, PlayerHandle(0))
+        .unwrap();
+    host_sess.start_session().unwrap();
+    host_sess.poll_remote_clients();
+
+    // the host plays for much longer than the spectator could catch up on with single inputs
+    for i in 0..1000u32 {
+        let serialized_input = bincode::serialize(&i).unwrap();
+        host_sess
+            .advance_frame(PlayerHandle(0), &serialized_input)
+            .unwrap();
+    }
+
+    host_sess
+        .add_player(PlayerType::Spectator(spec_addr), PlayerHandle(2))
+        .unwrap();
+    let mut spec_sess =
+        ggrs::start_p2p_spectator_session(1, stubs::INPUT_SIZE, 8888, host_addr).unwrap();
+    spec_sess.start_session().unwrap();
+    for _ in 0..10 {
+        spec_sess.poll_remote_clients();
+        host_sess.poll_remote_clients();
+    }
+    assert_eq!(spec_sess.current_state(), SessionState::Running);
+
+    // the whole history arrives in a single chunk
+    for _ in 0..5 {
+        host_sess.poll_remote_clients();
+        spec_sess.poll_remote_clients();
+        if host_sess.spectator_stats()[0].frames_behind == 0 {
+            break;
+        }
+        std::thread::sleep(std::time::Duration::from_millis(5));
+    }
+    assert_eq!(host_sess.spectator_stats()[0].frames_behind, 0);
+
+    // the spectator simulates all frames without waiting for the host
+    let mut frames = 0;
+    while let Ok(requests) = spec_sess.advance_frame() {
+        frames += requests.len();
+    }
+    assert_eq!(frames, 999);
+}
+
+#[test]
+#[serial]
+fn test_add_and_remove_spectator() {
+    let host_addr = SocketAddr::new(IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)), 7777);
+    let spec_addr = SocketAddr::new(IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)), 8888);
+
+    let mut host_sess = ggrs::start_p2p_session(1, stubs::INPUT_SIZE, 7777).unwrap();
+    host_sess.set_late_spectators_enabled(true).unwrap();
+    host_sess
+        .add_player(PlayerType::Local, PlayerHandle(0))
+        .unwrap();
+    host_sess.start_session().unwrap();
+
+    // handles are picked automatically and addresses can only be added once
+    assert_eq!(host_sess.add_spectator(spec_addr), Ok(PlayerHandle(1000)));
+    assert!(host_sess.add_spectator(spec_addr).is_err());
+
+    let mut spec_sess =
+        ggrs::start_p2p_spectator_session(1, stubs::INPUT_SIZE, 8888, host_addr).unwrap();
+    spec_sess.start_session().unwrap();
+    for _ in 0..10 {
+        spec_sess.poll_remote_clients();
+        host_sess.poll_remote_clients();
+    }
+    assert_eq!(spec_sess.current_state(), SessionState::Running);
+    spec_sess.events().for_each(drop);
+
+    // the host can monitor the connection to the spectator
+    let stats = host_sess.network_stats_for_spectator(spec_addr).unwrap();
+    assert_eq!(stats.packet_loss, 0.0);
+    assert!(host_sess.network_stats_for_spectator(host_addr).is_err());
+
+    // the removed spectator is notified and the handle becomes free again
+    host_sess.remove_spectator(spec_addr).unwrap();
+    assert!(host_sess.remove_spectator(spec_addr).is_err());
+    assert!(host_sess.network_stats_for_spectator(spec_addr).is_err());
+    spec_sess.poll_remote_clients();
+    assert!(spec_sess.events().any(|event| event
+        == GGRSEvent::Disconnected {
+            player_handle: PlayerHandle(0)
+        }));
+    assert_eq!(host_sess.add_spectator(spec_addr), Ok(PlayerHandle(1000)));
+}
+
+#[test]
+#[serial]
+fn test_spectator_batching() {
+    let host_addr = SocketAddr::new(IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)), 7777);
+    let spec_addrs = [
+        SocketAddr::new(IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)), 8888),
+        SocketAddr::new(IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)), 9999),
+    ];
+
+    let mut host_sess = ggrs::start_p2p_session(1, stubs::INPUT_SIZE, 7777).unwrap();
+    host_sess
+        .add_player(PlayerType::Local, PlayerHandle(0))
+        .unwrap();
+    for (i, addr) in spec_addrs.iter().enumerate() {
+        host_sess
+            .add_player(PlayerType::Spectator(*addr), PlayerHandle(1 + i))
+            .unwrap();
+    }
+    host_sess.set_spectator_batching(4, 2).unwrap();
+    host_sess.start_session().unwrap();
+    assert!(host_sess.set_spectator_batching(0, 0).is_err());
+
+    let mut spec_sessions = Vec::new();
+    for port in &[8888, 9999] {
+        let mut spec_sess =
+            ggrs::start_p2p_spectator_session(1, stubs::INPUT_SIZE, *port, host_addr).unwrap();
+        spec_sess.start_session().unwrap();
+        spec_sessions.push(spec_sess);
+    }
+    for _ in 0..10 {
+        host_sess.poll_remote_clients();
+        for spec_sess in &mut spec_sessions {
+            spec_sess.poll_remote_clients();
+        }
+    }
+
+    for i in 0..22u32 {
+        let serialized_input = bincode::serialize(&i).unwrap();
+        host_sess
+            .advance_frame(PlayerHandle(0), &serialized_input)
+            .unwrap();
+    }
+
+    // both spectators receive the complete batches with the correct inputs, the last frames wait for the next batch
+    for spec_sess in &mut spec_sessions {
+        let mut inputs = Vec::new();
+        for _ in 0..100 {
+            host_sess.poll_remote_clients();
+            if let Ok(requests) = spec_sess.advance_frame() {
+                for request in requests {
+                    if let ggrs::GGRSRequest::AdvanceFrame {
+                        inputs: frame_inputs,
+                        ..
+                    } = request
+                    {
+                        inputs.push(frame_inputs[0]);
+                    }
+                }
+            }
+            if inputs.len() == 20 {
+                break;
+            }
+            std::thread::sleep(std::time::Duration::from_millis(5));
+        }
+        assert_eq!(inputs.len(), 20);
+        for (frame, input) in inputs.iter().enumerate() {
+            let expected = bincode::serialize(&(frame as u32)).unwrap();
+            assert_eq!(input.input(), &expected[..]);
+        }
+    }
+}
+
+#[test]
+#[serial]
+fn test_player_disconnected() {
+    let host_addr = SocketAddr::new(IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)), 7777);
+    let spec_addr = SocketAddr::new(IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)), 8888);
+    let peer_addr = SocketAddr::new(IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)), 9999);
+
+    let mut host_sess = ggrs::start_p2p_session(2, stubs::INPUT_SIZE, 7777).unwrap();
+    host_sess
+        .add_player(PlayerType::Local, PlayerHandle(0))
+        .unwrap();
+    host_sess
+        .add_player(PlayerType::Remote(peer_addr), PlayerHandle(1))
+        .unwrap();
+    host_sess
+        .add_player(PlayerType::Spectator(spec_addr), PlayerHandle(2))
+        .unwrap();
+    let mut peer_sess = ggrs::start_p2p_session(2, stubs::INPUT_SIZE, 9999).unwrap();
+    peer_sess
+        .add_player(PlayerType::Remote(host_addr), PlayerHandle(0))
+        .unwrap();
+    peer_sess
+        .add_player(PlayerType::Local, PlayerHandle(1))
+        .unwrap();
+    let mut spec_sess =
+        ggrs::start_p2p_spectator_session(2, stubs::INPUT_SIZE, 8888, host_addr).unwrap();
+
+    host_sess.start_session().unwrap();
+    peer_sess.start_session().unwrap();
+    spec_sess.start_session().unwrap();
+    for _ in 0..10 {
+        host_sess.poll_remote_clients();
+        peer_sess.poll_remote_clients();
+        spec_sess.poll_remote_clients();
+    }
+    assert_eq!(spec_sess.current_state(), SessionState::Running);
+
+    let input = bincode::serialize(&0u32).unwrap();
+    for _ in 0..5 {
+        host_sess.advance_frame(PlayerHandle(0), &input).unwrap();
+        peer_sess.advance_frame(PlayerHandle(1), &input).unwrap();
+        host_sess.poll_remote_clients();
+        peer_sess.poll_remote_clients();
+    }
+
+    // the host drops the peer and keeps playing on its own
+    host_sess.disconnect_player(PlayerHandle(1)).unwrap();
+    for _ in 0..5 {
+        host_sess.advance_frame(PlayerHandle(0), &input).unwrap();
+        host_sess.poll_remote_clients();
+        spec_sess.poll_remote_clients();
+    }
+
+    let disconnects: Vec<GGRSEvent> = spec_sess
+        .events()
+        .filter(|event| matches!(event, GGRSEvent::PlayerDisconnected { .. }))
+        .collect();
+    assert_eq!(disconnects.len(), 1);
+    if let GGRSEvent::PlayerDisconnected {
+        player_handle,
+        frame,
+    } = disconnects[0]
+    {
+        assert_eq!(player_handle, PlayerHandle(1));
+        assert!(frame < host_sess.current_frame());
+    }
+}
+
+#[test]
+#[serial]
+fn test_spectator_delay() {
+    let host_addr = SocketAddr::new(IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)), 7777);
+    let spec_addr = SocketAddr::new(IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)), 8888);
+
+    let mut host_sess = ggrs::start_p2p_session(1, stubs::INPUT_SIZE, 7777).unwrap();
+    let mut spec_sess =
+        ggrs::start_p2p_spectator_session(1, stubs::INPUT_SIZE, 8888, host_addr).unwrap();
+
+    host_sess
+        .add_player(PlayerType::Local, PlayerHandle(0))
+        .unwrap();
+    host_sess
+        .add_player(PlayerType::Spectator(spec_addr), PlayerHandle(2))
+        .unwrap();
+    host_sess.set_spectator_delay(10).unwrap();
+
+    host_sess.start_session().unwrap();
+    spec_sess.start_session().unwrap();
+    assert!(host_sess.set_spectator_delay(0).is_err());
+
+    for _ in 0..10 {
+        spec_sess.poll_remote_clients();
+        host_sess.poll_remote_clients();
+    }
+    assert_eq!(spec_sess.current_state(), SessionState::Running);
+
+    for i in 0..20u32 {
+        let serialized_input = bincode::serialize(&i).unwrap();
+        host_sess
+            .advance_frame(PlayerHandle(0), &serialized_input)
+            .unwrap();
+        host_sess.poll_remote_clients();
+        spec_sess.poll_remote_clients();
+    }
+    for _ in 0..10 {
+        host_sess.poll_remote_clients();
+        spec_sess.poll_remote_clients();
+    }
+
+    // the spectator only gets the inputs of the frames that are at least 10 frames old
+    let mut advanced_frames = 0;
+    while let Ok(requests) = spec_sess.advance_frame() {
+        advanced_frames += requests.len();
+    }
+    let confirmed_frames = (host_sess.confirmed_frame().0 + 1) as usize;
+    assert_eq!(advanced_frames, confirmed_frames - 10);
+    assert!(host_sess
+        .spectator_stats()
+        .iter()
+        .all(|stats| stats.frames_behind == 0));
+}
@@ -0,0 +1,731 @@
+#![cfg(feature = "test-utils")]
+
+//! End-to-end scenarios that run several sessions on an `InMemoryNetwork` with virtual time. Every scenario scripts
+//! what happens on the network and checks that all peers and spectators agree on every confirmed gamestate.
+
+use std::collections::HashMap;
+use std::convert::TryInto;
+use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+use ggrs::{
+    CaptureSocket, Frame, GGRSError, GGRSEvent, GGRSRequest, GameInput, GameState, InMemoryNetwork,
+    InMemorySocket, NonBlockingSocket, P2PSession, P2PSpectatorSession, PlayerHandle, PlayerType,
+    ReplaySocket, SessionState, UdpMessage, NULL_FRAME,
+};
+
+const INPUT_SIZE: usize = std::mem::size_of::<u32>();
+const MAX_TICKS: u32 = 2000;
+
+/// A game that folds all inputs into a single value and remembers the value of every frame it has simulated.
+#[derive(Debug, Default)]
+struct ScenarioGame {
+    frame: Frame,
+    value: u64,
+    history: HashMap<Frame, u64>,
+}
+
+impl ScenarioGame {
+    fn handle_requests(&mut self, requests: Vec<GGRSRequest>) {
+        for request in requests {
+            match request {
+                GGRSRequest::SaveGameState { cell, frame } => {
+                    assert_eq!(self.frame, frame);
+                    cell.save(self.save());
+                }
+                GGRSRequest::LoadGameState { cell, frame } => {
+                    let buffer = cell.load().buffer.expect("no gamestate buffer");
+                    self.load(frame, u64::from_le_bytes(buffer[..].try_into().unwrap()));
+                }
+                GGRSRequest::AdvanceFrame { frame, inputs } => {
+                    assert_eq!(self.frame, frame);
+                    self.advance(&inputs);
+                }
+                GGRSRequest::Resimulate {
+                    inputs_per_frame,
+                    cells,
+                    ..
+                } => {
+                    for (inputs, cell) in inputs_per_frame.iter().zip(cells) {
+                        self.advance(inputs);
+                        if let Some(cell) = cell {
+                            cell.save(self.save());
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    fn save(&self) -> GameState {
+        GameState::new(self.frame, Some(self.value.to_le_bytes().to_vec()), None)
+    }
+
+    fn load(&mut self, frame: Frame, value: u64) {
+        self.frame = frame;
+        self.value = value;
+    }
+
+    fn advance(&mut self, inputs: &[GameInput]) {
+        for input in inputs {
+            // disconnected players have no input
+            let input = match input.frame {
+                NULL_FRAME => 0,
+                _ => u32::from_le_bytes(input.input().try_into().unwrap()),
+            };
+            self.value = self.value.wrapping_mul(31).wrapping_add(input as u64 + 1);
+        }
+        self.frame += 1;
+        self.history.insert(self.frame, self.value);
+    }
+}
+
+struct ScenarioPlayer {
+    session: P2PSession,
+    game: ScenarioGame,
+    started: bool,
+    /// The number of ticks the player does not run for, like during a lag spike.
+    stalled_ticks: u32,
+    /// The number of frames `advance_frame()` returned no requests for.
+    skipped_frames: u32,
+    events: Vec<GGRSEvent>,
+}
+
+struct ScenarioSpectator {
+    session: P2PSpectatorSession,
+    game: ScenarioGame,
+}
+
+/// Runs a `P2PSession` for every player and a `P2PSpectatorSession` for every spectator on the same network and virtual clock.
+/// Player 0 hosts all spectators.
+struct Scenario {
+    network: InMemoryNetwork,
+    num_players: u32,
+    players: Vec<ScenarioPlayer>,
+    spectators: Vec<ScenarioSpectator>,
+    /// If true, the inputs of the players never change. Sessions only recommend to wait while the inputs are idle.
+    idle_inputs: bool,
+}
+
+impl Scenario {
+    fn new(num_players: u32) -> Self {
+        let network = InMemoryNetwork::new();
+        let players = (0..num_players as usize)
+            .map(|handle| ScenarioPlayer {
+                session: create_session(&network, num_players, PlayerHandle(handle)),
+                game: ScenarioGame::default(),
+                started: false,
+                stalled_ticks: 0,
+                skipped_frames: 0,
+                events: Vec::new(),
+            })
+            .collect();
+        Self {
+            network,
+            num_players,
+            players,
+            spectators: Vec::new(),
+            idle_inputs: false,
+        }
+    }
+
+    fn player_addr(handle: usize) -> SocketAddr {
+        SocketAddr::new(IpAddr::V4(Ipv4Addr::LOCALHOST), 10000 + handle as u16)
+    }
+
+    fn spectator_addr(index: usize) -> SocketAddr {
+        SocketAddr::new(IpAddr::V4(Ipv4Addr::LOCALHOST), 11000 + index as u16)
+    }
+
+    fn start(&mut self, handle: usize) {
+        self.players[handle].session.start_session().unwrap();
+        self.players[handle].started = true;
+    }
+
+    fn start_all(&mut self) {
+        for handle in 0..self.players.len() {
+            self.start(handle);
+        }
+    }
+
+    /// Adds a spectator to the host, which works before and after the match has started.
+    fn add_spectator(&mut self) {
+        let addr = Self::spectator_addr(self.spectators.len());
+        let mut session = ggrs::start_p2p_spectator_session_with_socket(
+            self.num_players,
+            INPUT_SIZE,
+            Self::player_addr(0),
+            self.network.socket(addr),
+        )
+        .unwrap();
+        session.set_time_source(self.network.time_source()).unwrap();
+        session.start_session().unwrap();
+        self.players[0].session.add_spectator(addr).unwrap();
+        self.spectators.push(ScenarioSpectator {
+            session,
+            game: ScenarioGame::default(),
+        });
+    }
+
+    /// Replaces the session of a player with a new one in a new process, which resumes from a snapshot of the old session
+    /// and the gamestate of the snapshot frame.
+    fn restart(&mut self, handle: usize) {
+        let player = &mut self.players[handle];
+        let snapshot = player.session.save_session().unwrap();
+        let frame = snapshot.frame();
+        let value = if frame == 0 {
+            0
+        } else {
+            player.game.history[&frame]
+        };
+
+        let mut session = ggrs::start_p2p_session_with_socket(
+            self.num_players,
+            INPUT_SIZE,
+            self.network.socket(Self::player_addr(handle)),
+        )
+        .unwrap();
+        session.set_time_source(self.network.time_source()).unwrap();
+        session.set_confirmed_inputs_enabled(true);
+        session.restore_session(snapshot).unwrap();
+        player.session = session;
+        player.game.load(frame, value);
+    }
+
+    fn input(handle: usize, frame: Frame, idle: bool) -> Vec<u8> {
+        let input = if idle {
+            handle as u32
+        } else {
+            (frame.0 as u32).wrapping_mul(7) ^ (handle as u32 * 13)
+        };
+        input.to_le_bytes().to_vec()
+    }
+
+    /// Advances the network by a single tick and every started session by up to a single frame.
+    fn tick(&mut self) {
+        self.network.tick();
+        let idle = self.idle_inputs;
+        for (handle, player) in self.players.iter_mut().enumerate() {
+            if !player.started {
+                continue;
+            }
+            if player.stalled_ticks > 0 {
+                player.stalled_ticks -= 1;
+                continue;
+            }
+            if player.session.current_state() == SessionState::Running {
+                let input = Self::input(handle, player.session.current_frame(), idle);
+                match player.session.advance_frame(PlayerHandle(handle), &input) {
+                    Ok(requests) if requests.is_empty() => player.skipped_frames += 1,
+                    Ok(requests) => player.game.handle_requests(requests),
+                    // the player is too far ahead and has to wait for the others
+                    Err(GGRSError::PredictionThreshold) => (),
+                    Err(e) => panic!("player {} failed to advance: {}", handle, e),
+                }
+            } else {
+                player.session.poll_remote_clients();
+            }
+            player.events.extend(player.session.events());
+        }
+
+        for spectator in &mut self.spectators {
+            match spectator.session.advance_frame() {
+                Ok(requests) => spectator.game.handle_requests(requests),
+                Err(GGRSError::NotSynchronized) | Err(GGRSError::PredictionThreshold) => (),
+                Err(e) => panic!("spectator failed to advance: {}", e),
+            }
+            spectator.session.events().for_each(drop);
+        }
+    }
+
+    fn run(&mut self, ticks: u32) {
+        for _ in 0..ticks {
+            self.tick();
+        }
+    }
+
+    /// Ticks until all started players are running.
+    fn synchronize(&mut self) {
+        for _ in 0..MAX_TICKS {
+            if self
+                .players
+                .iter()
+                .filter(|player| player.started)
+                .all(|player| player.session.current_state() == SessionState::Running)
+            {
+                return;
+            }
+            self.tick();
+        }
+        panic!("the players did not synchronize");
+    }
+
+    fn take_events(&mut self, handle: usize) -> Vec<GGRSEvent> {
+        std::mem::take(&mut self.players[handle].events)
+    }
+
+    /// Asserts that all players and spectators agree on every gamestate that is confirmed on all of them and returns the number of compared frames.
+    fn assert_confirmed_states_match(&self) -> usize {
+        // the gamestate after a frame is final once the inputs of that frame are confirmed
+        let last_final_frame = self
+            .players
+            .iter()
+            .map(|player| player.session.confirmed_frame() + 1)
+            .min()
+            .unwrap();
+
+        let mut compared = 0;
+        for frame in (1..=last_final_frame.0).map(Frame) {
+            let mut values = self
+                .players
+                .iter()
+                .map(|player| &player.game)
+                .chain(self.spectators.iter().map(|spectator| &spectator.game))
+                .filter_map(|game| game.history.get(&frame));
+            let first = match values.next() {
+                Some(value) => value,
+                None => continue,
+            };
+            for value in values {
+                assert_eq!(value, first, "the gamestates of frame {} differ", frame);
+            }
+            compared += 1;
+        }
+        compared
+    }
+}
+
+/// A socket that corrupts some of the messages it receives by changing one to three bytes of their encoding, like a faulty link or a malicious peer would.
+/// Messages that do not decode anymore are dropped. The corruption is pseudo-random with a fixed seed, so every run corrupts the same bytes.
+/// Messages are only corrupted while the shared flag is set.
+#[derive(Debug)]
+struct CorruptingSocket {
+    socket: InMemorySocket,
+    enabled: Arc<AtomicBool>,
+    state: u64,
+}
+
+impl CorruptingSocket {
+    fn new(socket: InMemorySocket, enabled: Arc<AtomicBool>, seed: u64) -> Self {
+        Self {
+            socket,
+            enabled,
+            state: seed.wrapping_mul(0x9E37_79B9_7F4A_7C15) | 1,
+        }
+    }
+
+    /// Returns the next number of a xorshift sequence.
+    fn next(&mut self) -> u64 {
+        self.state ^= self.state << 13;
+        self.state ^= self.state >> 7;
+        self.state ^= self.state << 17;
+        self.state
+    }
+
+    fn corrupt(&mut self, msg: UdpMessage) -> Option<UdpMessage> {
+        // most messages arrive intact, so the sessions still make progress
+        if !self.enabled.load(Ordering::Relaxed) || self.next() & 7 != 0 {
+            return Some(msg);
+        }
+        let mut bytes = msg.to_bytes();
+        for _ in 0..=self.next() % 3 {
+            let pos = (self.next() % bytes.len() as u64) as usize;
+            bytes[pos] = self.next() as u8;
+        }
+        UdpMessage::from_bytes(&bytes)
+    }
+}
+
+impl NonBlockingSocket for CorruptingSocket {
+    fn send_to(&mut self, msg: &UdpMessage, addr: SocketAddr) {
+        self.socket.send_to(msg, addr);
+    }
+
+    fn receive_all_messages(&mut self) -> Vec<(SocketAddr, UdpMessage)> {
+        let received = self.socket.receive_all_messages();
+        received
+            .into_iter()
+            .filter_map(|(addr, msg)| self.corrupt(msg).map(|msg| (addr, msg)))
+            .collect()
+    }
+}
+
+fn create_session(network: &InMemoryNetwork, num_players: u32, local: PlayerHandle) -> P2PSession {
+    let socket = network.socket(Scenario::player_addr(local.0));
+    let mut session = ggrs::start_p2p_session_with_socket(num_players, INPUT_SIZE, socket).unwrap();
+    session.set_time_source(network.time_source()).unwrap();
+    session.set_confirmed_inputs_enabled(true);
+    // spectators can join the host at any time
+    session.set_late_spectators_enabled(true).unwrap();
+    for handle in (0..num_players as usize).map(PlayerHandle) {
+        let player_type = if handle == local {
+            PlayerType::Local
+        } else {
+            PlayerType::Remote(Scenario::player_addr(handle.0))
+        };
+        session.add_player(player_type, handle).unwrap();
+    }
+    session
+}
+
+#[test]
+fn test_peer_joins_late() {
+    let mut scenario = Scenario::new(3);
+    scenario.start(0);
+    scenario.start(1);
+
+    // the others wait for the last player, who starts their session a few seconds later
+    scenario.run(300);
+    assert_eq!(
+        scenario.players[0].session.current_state(),
+        SessionState::Synchronizing
+    );
+    scenario.start(2);
+    scenario.synchronize();
+    scenario.run(300);
+
+    assert!(scenario.players[2].session.confirmed_frame() > Frame(200));
+    assert!(scenario.assert_confirmed_states_match() > 200);
+}
+
+#[test]
+fn test_packet_loss_bursts() {
+    let mut scenario = Scenario::new(2);
+    scenario.network.set_latency(Scenario::player_addr(0), 2);
+    scenario.network.set_latency(Scenario::player_addr(1), 3);
+    scenario.start_all();
+    scenario.synchronize();
+
+    // short bursts of loss in both directions force predictions, resends and rollbacks
+    for burst in 0..5 {
+        scenario
+            .network
+            .drop_packets(Scenario::player_addr(burst % 2), 5 + burst as u32);
+        scenario.run(60);
+    }
+    scenario.run(60);
+
+    assert!(scenario.players[0].session.confirmed_frame() > Frame(200));
+    assert!(scenario.assert_confirmed_states_match() > 200);
+}
+
+#[test]
+fn test_network_partition_and_resume() {
+    let mut scenario = Scenario::new(2);
+    for player in &mut scenario.players {
+        player
+            .session
+            .set_disconnect_notify_delay(Duration::from_millis(500));
+        player
+            .session
+            .set_disconnect_timeout(Duration::from_secs(5));
+    }
+    scenario.start_all();
+    scenario.synchronize();
+    scenario.run(60);
+    scenario.take_events(0);
+
+    // player 1 loses the connection for about a second, shorter than the disconnect timeout
+    scenario
+        .network
+        .set_disconnected(Scenario::player_addr(1), true);
+    scenario.run(60);
+    assert!(scenario
+        .take_events(0)
+        .iter()
+        .any(|event| matches!(event, GGRSEvent::NetworkInterrupted { .. })));
+
+    scenario
+        .network
+        .set_disconnected(Scenario::player_addr(1), false);
+    scenario.run(120);
+    let events = scenario.take_events(0);
+    assert!(events
+        .iter()
+        .any(|event| matches!(event, GGRSEvent::NetworkResumed { .. })));
+    assert!(!events
+        .iter()
+        .any(|event| matches!(event, GGRSEvent::Disconnected { .. })));
+
+    assert!(scenario.players[0].session.confirmed_frame() > Frame(150));
+    assert!(scenario.assert_confirmed_states_match() > 150);
+}
+
+#[test]
+fn test_disconnect_timeout() {
+    let mut scenario = Scenario::new(2);
+    for player in &mut scenario.players {
+        player
+            .session
+            .set_disconnect_timeout(Duration::from_secs(1));
+    }
+    scenario.start_all();
+    scenario.synchronize();
+    scenario.run(60);
+
+    // player 1 never comes back, so player 0 continues without them
+    scenario
+        .network
+        .set_disconnected(Scenario::player_addr(1), true);
+    scenario.run(120);
+    assert!(scenario.take_events(0).contains(&GGRSEvent::Disconnected {
+        player_handle: PlayerHandle(1)
+    }));
+
+    let confirmed_frame = scenario.players[0].session.confirmed_frame();
+    scenario.run(60);
+    assert!(scenario.players[0].session.confirmed_frame() > confirmed_frame + 50);
+}
+
+#[test]
+fn test_disconnect_and_rejoin_after_restart() {
+    let mut scenario = Scenario::new(2);
+    for player in &mut scenario.players {
+        player
+            .session
+            .set_disconnect_timeout(Duration::from_secs(5));
+    }
+    scenario.start_all();
+    scenario.synchronize();
+    scenario.run(120);
+
+    // player 1 crashes, is offline for half a second and resumes from their last snapshot
+    scenario
+        .network
+        .set_disconnected(Scenario::player_addr(1), true);
+    scenario.restart(1);
+    scenario.run(30);
+    scenario
+        .network
+        .set_disconnected(Scenario::player_addr(1), false);
+    scenario.run(180);
+
+    assert!(!scenario
+        .take_events(0)
+        .iter()
+        .any(|event| matches!(event, GGRSEvent::Disconnected { .. })));
+    assert!(scenario.players[1].session.confirmed_frame() > Frame(250));
+    assert!(scenario.assert_confirmed_states_match() > 250);
+}
+
+#[test]
+fn test_spectator_catches_up() {
+    let mut scenario = Scenario::new(2);
+    scenario.start_all();
+    scenario.synchronize();
+    scenario.run(300);
+
+    // the spectator joins a running match and has to catch up on all frames since the start
+    scenario.add_spectator();
+    scenario.run(600);
+
+    let spectator = &scenario.spectators[0];
+    assert!(spectator.session.frames_behind_host() < 10);
+    assert!(spectator.game.frame > Frame(800));
+    assert!(scenario.assert_confirmed_states_match() > 800);
+}
+
+#[test]
+fn test_auto_wait_after_lag_spike() {
+    for auto_wait in [false, true] {
+        let mut scenario = Scenario::new(2);
+        scenario.idle_inputs = true;
+        scenario.players[0].session.set_auto_wait(auto_wait);
+        scenario.start_all();
+        scenario.synchronize();
+        scenario.run(60);
+
+        // player 1 freezes for a moment, so player 0 is ahead afterwards and should wait for them
+        scenario.players[1].stalled_ticks = 30;
+        scenario.run(180);
+
+        let recommended = scenario
+            .take_events(0)
+            .iter()
+            .any(|event| matches!(event, GGRSEvent::WaitRecommendation { .. }));
+        assert_eq!(recommended, !auto_wait);
+        assert_eq!(scenario.players[0].skipped_frames > 0, auto_wait);
+        assert!(scenario.assert_confirmed_states_match() > 150);
+    }
+}
+
+#[test]
+fn test_corrupted_messages_do_not_panic() {
+    for seed in 0..50 {
+        let network = InMemoryNetwork::new();
+        let corrupting = Arc::new(AtomicBool::new(false));
+        let relay_addr = SocketAddr::new(IpAddr::V4(Ipv4Addr::LOCALHOST), 12000);
+        let spectator_addr = Scenario::spectator_addr(0);
+
+        let mut players: Vec<(P2PSession, ScenarioGame)> = (0..2)
+            .map(|handle| {
+                let socket = CorruptingSocket::new(
+                    network.socket(Scenario::player_addr(handle)),
+                    corrupting.clone(),
+                    seed * 4 + handle as u64,
+                );
+                let mut session =
+                    ggrs::start_p2p_session_with_socket(2, INPUT_SIZE, socket).unwrap();
+                session.set_time_source(network.time_source()).unwrap();
+                session.set_late_spectators_enabled(true).unwrap();
+                for other in 0..2 {
+                    let player_type = if other == handle {
+                        PlayerType::Local
+                    } else {
+                        PlayerType::Remote(Scenario::player_addr(other))
+                    };
+                    session
+                        .add_player(player_type, PlayerHandle(other))
+                        .unwrap();
+                }
+                session.start_session().unwrap();
+                (session, ScenarioGame::default())
+            })
+            .collect();
+        players[0].0.add_spectator(relay_addr).unwrap();
+
+        // the host sends its inputs to a relay, which forwards them to the spectator
+        let mut relay = ggrs::start_p2p_relay_session_with_socket(
+            2,
+            INPUT_SIZE,
+            Scenario::player_addr(0),
+            CorruptingSocket::new(network.socket(relay_addr), corrupting.clone(), seed * 4 + 2),
+        )
+        .unwrap();
+        relay.set_time_source(network.time_source()).unwrap();
+        relay.add_spectator(spectator_addr);
+        relay.start_session().unwrap();
+        let mut spectator = ggrs::start_p2p_spectator_session_with_socket(
+            2,
+            INPUT_SIZE,
+            relay_addr,
+            CorruptingSocket::new(
+                network.socket(spectator_addr),
+                corrupting.clone(),
+                seed * 4 + 3,
+            ),
+        )
+        .unwrap();
+        spectator.set_time_source(network.time_source()).unwrap();
+        spectator.start_session().unwrap();
+        let mut spectator_game = ScenarioGame::default();
+
+        // once everyone is running, any error is fine, as long as no session panics
+        for _ in 0..600 {
+            network.tick();
+            if players
+                .iter()
+                .all(|(session, _)| session.current_state() == SessionState::Running)
+                && spectator.current_state() == SessionState::Running
+            {
+                corrupting.store(true, Ordering::Relaxed);
+            }
+            for (handle, (session, game)) in players.iter_mut().enumerate() {
+                if session.current_state() == SessionState::Running {
+                    let input = Scenario::input(handle, session.current_frame(), false);
+                    if let Ok(requests) = session.advance_frame(PlayerHandle(handle), &input) {
+                        game.handle_requests(requests);
+                    }
+                } else {
+                    session.poll_remote_clients();
+                }
+                session.events().for_each(drop);
+            }
+            relay.poll_remote_clients();
+            relay.events().for_each(drop);
+            if let Ok(requests) = spectator.advance_frame() {
+                spectator_game.handle_requests(requests);
+            }
+            spectator.events().for_each(drop);
+        }
+        // the messages were only corrupted once all sessions had synchronized
+        assert!(corrupting.load(Ordering::Relaxed));
+    }
+}
+
+#[test]
+fn test_replay_capture() {
+    let path = std::env::temp_dir().join("ggrs_test_replay_capture.cap");
+    let network = InMemoryNetwork::new();
+    let add_players = |session: &mut P2PSession| {
+        session.set_confirmed_inputs_enabled(true);
+        for handle in 0..2 {
+            let player_type = if handle == 0 {
+                PlayerType::Local
+            } else {
+                PlayerType::Remote(Scenario::player_addr(handle))
+            };
+            session
+                .add_player(player_type, PlayerHandle(handle))
+                .unwrap();
+        }
+    };
+
+    // player 0 captures a short match with player 1
+    let socket = CaptureSocket::new(network.socket(Scenario::player_addr(0)), &path).unwrap();
+    let mut captured = ggrs::start_p2p_session_with_socket(2, INPUT_SIZE, socket).unwrap();
+    captured.set_time_source(network.time_source()).unwrap();
+    add_players(&mut captured);
+    captured.start_session().unwrap();
+    let mut peer = create_session(&network, 2, PlayerHandle(1));
+    peer.start_session().unwrap();
+
+    let mut games = [ScenarioGame::default(), ScenarioGame::default()];
+    let mut captured_inputs = Vec::new();
+    for _ in 0..300 {
+        // the capture records real time, which the replay waits for before handing out the messages
+        std::thread::sleep(Duration::from_millis(2));
+        network.tick();
+        for (handle, game) in games.iter_mut().enumerate() {
+            let session = if handle == 0 {
+                &mut captured
+            } else {
+                &mut peer
+            };
+            if session.current_state() == SessionState::Running {
+                let input = Scenario::input(handle, session.current_frame(), false);
+                match session.advance_frame(PlayerHandle(handle), &input) {
+                    Ok(requests) => game.handle_requests(requests),
+                    Err(GGRSError::PredictionThreshold) => (),
+                    Err(e) => panic!("player {} failed to advance: {}", handle, e),
+                }
+            } else {
+                session.poll_remote_clients();
+            }
+        }
+        captured_inputs.extend(captured.confirmed_inputs());
+    }
+    // dropping the session flushes the capture
+    drop(captured);
+    assert!(captured_inputs.len() > 200);
+
+    // a new session of player 0 gets the same inputs from the capture
+    let mut replay =
+        ggrs::start_p2p_session_with_socket(2, INPUT_SIZE, ReplaySocket::from_file(&path).unwrap())
+            .unwrap();
+    add_players(&mut replay);
+    replay.start_session().unwrap();
+    let mut game = ScenarioGame::default();
+    let mut replayed_inputs = Vec::new();
+    let start = std::time::Instant::now();
+    while replayed_inputs.len() < captured_inputs.len() && start.elapsed() < Duration::from_secs(5)
+    {
+        if replay.current_state() == SessionState::Running {
+            let input = Scenario::input(0, replay.current_frame(), false);
+            match replay.advance_frame(PlayerHandle(0), &input) {
+                Ok(requests) => game.handle_requests(requests),
+                Err(GGRSError::PredictionThreshold) => (),
+                Err(e) => panic!("the replay failed to advance: {}", e),
+            }
+        } else {
+            replay.poll_remote_clients();
+            std::thread::sleep(Duration::from_millis(1));
+        }
+        replayed_inputs.extend(replay.confirmed_inputs());
+    }
+    assert_eq!(replayed_inputs, captured_inputs);
+
+    std::fs::remove_file(path).unwrap();
+}
@@ -1,4 +1,7 @@
 use bincode;
+use ggrs::{GGRSError, GGRSRequest, PlayerHandle, Session, SessionState, SideEffectQueue};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::Duration;
 
 mod stubs;
 
@@ -21,6 +24,8 @@ fn test_advance_frame_with_rollbacks() {
         let requests = sess.advance_frame(&serialized_input).unwrap();
         stub.handle_requests(requests);
         assert_eq!(stub.gs.frame, i as i32 + 1); // frame should have advanced
+        assert_eq!(sess.current_frame(), stub.gs.frame);
+        assert_eq!(sess.confirmed_frame(), stub.gs.frame - 1);
     }
 }
 
@@ -30,7 +35,7 @@ fn test_advance_frames_with_delayed_input() {
     let check_distance = 7;
     let mut stub = stubs::GameStub::new();
     let mut sess = ggrs::start_synctest_session(2, stubs::INPUT_SIZE, check_distance).unwrap();
-    assert!(sess.set_frame_delay(2, handle).is_ok());
+    assert!(sess.set_frame_delay(2, PlayerHandle(handle)).is_ok());
 
     for i in 0..200 {
         let input: u32 = i;
@@ -42,3 +47,149 @@ fn test_advance_frames_with_delayed_input() {
         assert_eq!(stub.gs.frame, i as i32 + 1); // frame should have advanced
     }
 }
+
+#[test]
+fn test_telemetry_counts_rollbacks() {
+    let check_distance = 7;
+    let mut stub = stubs::GameStub::new();
+    let mut sess = ggrs::start_synctest_session(2, stubs::INPUT_SIZE, check_distance).unwrap();
+
+    for i in 0..20 {
+        let input: u32 = i;
+        let serialized_input = vec![
+            bincode::serialize(&input).unwrap(),
+            bincode::serialize(&input).unwrap(),
+        ];
+        let requests = sess.advance_frame(&serialized_input).unwrap();
+        stub.handle_requests(requests);
+    }
+
+    let telemetry = sess.telemetry();
+    assert!(telemetry.rollbacks > 0);
+    assert_eq!(telemetry.max_rollback_depth, check_distance);
+    assert_eq!(
+        telemetry.frames_resimulated,
+        telemetry.rollbacks * check_distance as usize
+    );
+    // every call is timed with the system clock
+    assert!(telemetry.max_advance_frame_time > Duration::from_secs(0));
+    assert!(telemetry.max_advance_frame_time <= telemetry.advance_frame_time);
+}
+
+#[test]
+fn test_advance_frame_as_dyn_session() {
+    let mut stub = stubs::GameStub::new();
+    let mut sess: Box<dyn Session> =
+        Box::new(ggrs::start_synctest_session(2, stubs::INPUT_SIZE, 2).unwrap());
+
+    for i in 0..20u32 {
+        let input = bincode::serialize(&i).unwrap();
+        // the order of the players does not matter
+        let requests = sess
+            .advance_frame(&[(PlayerHandle(1), &input), (PlayerHandle(0), &input)])
+            .unwrap();
+        stub.handle_requests(requests);
+    }
+    assert_eq!(sess.current_frame(), 20);
+    assert_eq!(sess.current_state(), SessionState::Running);
+    assert!(sess.events().is_empty());
+    assert!(sess.network_stats(PlayerHandle(0)).is_err());
+
+    // every player needs an input
+    let input = bincode::serialize(&0u32).unwrap();
+    assert!(sess.advance_frame(&[(PlayerHandle(0), &input)]).is_err());
+    assert!(sess
+        .advance_frame(&[(PlayerHandle(0), &input), (PlayerHandle(0), &input)])
+        .is_err());
+    assert_eq!(
+        sess.advance_frame(&[(PlayerHandle(0), &input), (PlayerHandle(2), &input)])
+            .unwrap_err(),
+        GGRSError::InvalidHandle
+    );
+}
+
+#[test]
+fn test_change_check_distance() {
+    let mut stub = stubs::GameStub::new();
+    let mut sess = ggrs::start_synctest_session(2, stubs::INPUT_SIZE, 2).unwrap();
+    assert!(sess
+        .set_check_distance(ggrs::MAX_PREDICTION_FRAMES)
+        .is_err());
+
+    for i in 0..60u32 {
+        // raise and lower the check distance while running
+        match i {
+            10 => sess.set_check_distance(7).unwrap(),
+            30 => sess.set_check_distance(1).unwrap(),
+            45 => sess.set_check_distance(5).unwrap(),
+            _ => (),
+        }
+        let input = bincode::serialize(&i).unwrap();
+        let requests = sess.advance_frame(&vec![input.clone(), input]).unwrap();
+        stub.handle_requests(requests);
+        assert_eq!(stub.gs.frame, i as i32 + 1);
+        if i == 25 {
+            // the raised check distance has been reached
+            assert_eq!(sess.telemetry().max_rollback_depth, 7);
+        }
+    }
+    assert_eq!(sess.check_distance(), 5);
+}
+
+#[test]
+fn test_checksum_function() {
+    static CALLS: AtomicU64 = AtomicU64::new(0);
+    fn counting_checksum(_: &[u8]) -> u64 {
+        CALLS.fetch_add(1, Ordering::SeqCst)
+    }
+
+    let mut stub = stubs::GameStub::new();
+    let mut sess = ggrs::start_synctest_session(2, stubs::INPUT_SIZE, 2).unwrap();
+    sess.set_checksum_function(counting_checksum);
+
+    // a checksum that differs on each call makes every resimulation look nondeterministic
+    let mut result = Ok(());
+    for i in 0..10u32 {
+        let serialized_input = vec![
+            bincode::serialize(&i).unwrap(),
+            bincode::serialize(&i).unwrap(),
+        ];
+        match sess.advance_frame(&serialized_input) {
+            Ok(requests) => stub.handle_requests(requests),
+            Err(e) => {
+                result = Err(e);
+                break;
+            }
+        }
+    }
+    assert!(CALLS.load(Ordering::SeqCst) > 0);
+    assert!(matches!(result, Err(GGRSError::MismatchedChecksum { .. })));
+}
+
+#[test]
+fn test_side_effects_are_released_once() {
+    let mut stub = stubs::GameStub::new();
+    let mut sess = ggrs::start_synctest_session(2, stubs::INPUT_SIZE, 7).unwrap();
+    let mut effects = SideEffectQueue::new();
+    let mut released = Vec::new();
+
+    for i in 0..50u32 {
+        let input = bincode::serialize(&i).unwrap();
+        let requests = sess.advance_frame(&vec![input.clone(), input]).unwrap();
+        // every frame is rolled back and simulated again, but each effect is released only once
+        effects.observe(&requests);
+        for request in &requests {
+            if let GGRSRequest::AdvanceFrame { frame, .. } = request {
+                effects.push(*frame, *frame);
+            }
+        }
+        stub.handle_requests(requests);
+        released.extend(
+            effects
+                .release(sess.confirmed_frame())
+                .map(|(_, effect)| effect),
+        );
+    }
+    assert_eq!(released, (0..50).map(ggrs::Frame).collect::<Vec<_>>());
+    assert!(effects.is_empty());
+}
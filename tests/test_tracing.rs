@@ -0,0 +1,63 @@
+#![cfg(feature = "tracing")]
+
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+
+use tracing::span::{Attributes, Id, Record};
+use tracing::{Event, Metadata, Subscriber};
+
+mod stubs;
+
+/// A subscriber that remembers the names of all spans that have been created.
+#[derive(Default)]
+struct SpanRecorder {
+    next_id: AtomicU64,
+    spans: Arc<Mutex<Vec<&'static str>>>,
+}
+
+impl Subscriber for SpanRecorder {
+    fn enabled(&self, _metadata: &Metadata<'_>) -> bool {
+        true
+    }
+
+    fn new_span(&self, span: &Attributes<'_>) -> Id {
+        self.spans.lock().unwrap().push(span.metadata().name());
+        Id::from_u64(self.next_id.fetch_add(1, Ordering::Relaxed) + 1)
+    }
+
+    fn record(&self, _span: &Id, _values: &Record<'_>) {}
+
+    fn record_follows_from(&self, _span: &Id, _follows: &Id) {}
+
+    fn event(&self, _event: &Event<'_>) {}
+
+    fn enter(&self, _span: &Id) {}
+
+    fn exit(&self, _span: &Id) {}
+}
+
+#[test]
+fn test_spans_around_frames_and_rollbacks() {
+    let recorder = SpanRecorder::default();
+    let spans = recorder.spans.clone();
+
+    tracing::subscriber::with_default(recorder, || {
+        let mut stub = stubs::GameStub::new();
+        let mut sess = ggrs::start_synctest_session(2, stubs::INPUT_SIZE, 2).unwrap();
+        for i in 0..10u32 {
+            let input = bincode::serialize(&i).unwrap();
+            let requests = sess.advance_frame(&vec![input.clone(), input]).unwrap();
+            stub.handle_requests(requests);
+        }
+    });
+
+    let spans = spans.lock().unwrap();
+    assert_eq!(
+        spans
+            .iter()
+            .filter(|name| **name == "advance_frame")
+            .count(),
+        10
+    );
+    assert!(spans.contains(&"rollback"));
+}